@@ -0,0 +1,27 @@
+//
+// Copyright 2025 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+package org.signal.libsignal.net;
+
+import java.time.Duration;
+
+/**
+ * Indicates that the server rejected the connection or a request because it is down for
+ * maintenance.
+ */
+public class ServerMaintenanceException extends ChatServiceException {
+  /** How long the server asked clients to wait before reconnecting, or null if it didn't say. */
+  public final Duration retryAfter;
+
+  /**
+   * Called from native code.
+   *
+   * <p>A negative {@code retryAfterSeconds} means the server didn't provide a retry hint.
+   */
+  public ServerMaintenanceException(String message, long retryAfterSeconds) {
+    super(message);
+    this.retryAfter = retryAfterSeconds < 0 ? null : Duration.ofSeconds(retryAfterSeconds);
+  }
+}
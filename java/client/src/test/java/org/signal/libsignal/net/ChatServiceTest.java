@@ -11,6 +11,7 @@ import java.io.IOException;
 import java.nio.charset.StandardCharsets;
 import java.time.Duration;
 import java.util.Map;
+import java.util.concurrent.CancellationException;
 import java.util.concurrent.CountDownLatch;
 import java.util.concurrent.ExecutionException;
 import java.util.concurrent.TimeUnit;
@@ -71,9 +72,18 @@ public class ChatServiceTest {
     assertChatConnectErrorIs("Timeout", ChatServiceException.class);
     assertChatConnectErrorIs("AllAttemptsFailed", ChatServiceException.class);
     assertChatConnectErrorIs("InvalidConnectionConfiguration", ChatServiceException.class);
+    assertChatConnectErrorIs("Cancelled", CancellationException.class);
     RetryLaterException retryLater =
         assertChatConnectErrorIs("RetryAfter42Seconds", RetryLaterException.class);
     assertEquals(retryLater.duration, Duration.ofSeconds(42));
+
+    ServerMaintenanceException maintenance =
+        assertChatConnectErrorIs("ServerMaintenance", ServerMaintenanceException.class);
+    assertNull(maintenance.retryAfter);
+    maintenance =
+        assertChatConnectErrorIs(
+            "ServerMaintenanceRetryAfter42Seconds", ServerMaintenanceException.class);
+    assertEquals(maintenance.retryAfter, Duration.ofSeconds(42));
   }
 
   @Test
@@ -86,6 +96,14 @@ public class ChatServiceTest {
     assertChatSendErrorIs("RequestHasInvalidHeader", ChatServiceException.class);
     assertChatSendErrorIs("ConnectionInvalidated", ConnectionInvalidatedException.class);
     assertChatSendErrorIs("ConnectedElsewhere", ConnectedElsewhereException.class);
+
+    ServerMaintenanceException maintenance =
+        assertChatSendErrorIs("ServerMaintenance", ServerMaintenanceException.class);
+    assertNull(maintenance.retryAfter);
+    maintenance =
+        assertChatSendErrorIs(
+            "ServerMaintenanceRetryAfter42Seconds", ServerMaintenanceException.class);
+    assertEquals(maintenance.retryAfter, Duration.ofSeconds(42));
   }
 
   private static <E extends Throwable> E assertChatConnectErrorIs(
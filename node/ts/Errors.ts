@@ -52,6 +52,8 @@ export enum ErrorCode {
   DeviceDelinked,
   ConnectionInvalidated,
   ConnectedElsewhere,
+  ServerMaintenance,
+  RequestOutcomeUnknown,
 
   BackupValidation,
 
@@ -262,6 +264,15 @@ export type ConnectedElsewhereError = LibSignalErrorBase & {
   code: ErrorCode.ConnectedElsewhere;
 };
 
+export type ServerMaintenanceError = LibSignalErrorBase & {
+  code: ErrorCode.ServerMaintenance;
+  readonly retryAfterSecs?: number;
+};
+
+export type RequestOutcomeUnknownError = LibSignalErrorBase & {
+  code: ErrorCode.RequestOutcomeUnknown;
+};
+
 export type SvrDataMissingError = LibSignalErrorBase & {
   code: ErrorCode.SvrDataMissing;
 };
@@ -321,6 +332,8 @@ export type LibSignalError =
   | DeviceDelinkedError
   | ConnectionInvalidatedError
   | ConnectedElsewhereError
+  | ServerMaintenanceError
+  | RequestOutcomeUnknownError
   | RateLimitedError
   | BackupValidationError
   | CancellationError;
@@ -269,21 +269,25 @@ export class AuthenticatedChatConnection implements ChatConnection {
    * @param asyncContext the async runtime to use
    * @param listener the listener to send events to
    * @param alerts alerts to send immediately upon connect
+   * @param incomingRequests serialized server requests to deliver, in order,
+   * immediately upon connect
    * @returns an {@link AuthenticatedChatConnection} and handle for the remote
    * end of the fake connection.
    */
   public static fakeConnect(
     asyncContext: TokioAsyncContext,
     listener: ChatServiceListener,
-    alerts?: ReadonlyArray<string>
+    alerts?: ReadonlyArray<string>,
+    incomingRequests?: ReadonlyArray<Buffer>
   ): [AuthenticatedChatConnection, Wrapper<Native.FakeChatRemoteEnd>] {
     const nativeChatListener = makeNativeChatListener(asyncContext, listener);
 
     const fakeChat = newNativeHandle(
-      Native.TESTING_FakeChatConnection_Create(
+      Native.TESTING_FakeChatConnection_CreateWithIncoming(
         asyncContext,
         new WeakListenerWrapper(nativeChatListener),
-        alerts?.join('\n') ?? ''
+        alerts?.join('\n') ?? '',
+        incomingRequests?.slice() ?? []
       )
     );
 
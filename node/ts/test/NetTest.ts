@@ -60,6 +60,7 @@ describe('chat service api', () => {
       ['Timeout', ErrorCode.IoError],
       ['AllAttemptsFailed', ErrorCode.IoError],
       ['InvalidConnectionConfiguration', ErrorCode.IoError],
+      ['Cancelled', ErrorCode.Cancelled],
       [
         'RetryAfter42Seconds',
         {
@@ -67,6 +68,14 @@ describe('chat service api', () => {
           retryAfterSecs: 42,
         },
       ],
+      ['ServerMaintenance', ErrorCode.ServerMaintenance],
+      [
+        'ServerMaintenanceRetryAfter42Seconds',
+        {
+          code: ErrorCode.ServerMaintenance,
+          retryAfterSecs: 42,
+        },
+      ],
     ];
     cases.forEach((testCase) => {
       const [name, expectation] = testCase;
@@ -89,6 +98,14 @@ describe('chat service api', () => {
       ['RequestHasInvalidHeader', ErrorCode.IoError],
       ['ConnectionInvalidated', ErrorCode.ConnectionInvalidated],
       ['ConnectedElsewhere', ErrorCode.ConnectedElsewhere],
+      ['ServerMaintenance', ErrorCode.ServerMaintenance],
+      [
+        'ServerMaintenanceRetryAfter42Seconds',
+        {
+          code: ErrorCode.ServerMaintenance,
+          retryAfterSecs: 42,
+        },
+      ],
     ];
     cases.forEach((testCase) => {
       const [name, expectation] = testCase;
@@ -604,6 +621,45 @@ describe('chat service api', () => {
       });
     });
 
+    it('delivers pre-queued requests in order on connect', async () => {
+      const completable = new CompletablePromise();
+      const callsReceived: string[] = [];
+      const recordCall = (name: string) => {
+        callsReceived.push(name);
+        if (callsReceived.length == 3) {
+          completable.complete();
+        }
+      };
+      const listener: ChatServiceListener = {
+        onIncomingMessage(
+          _envelope: Buffer,
+          timestamp: number,
+          _ack: ChatServerMessageAck
+        ): void {
+          recordCall(`_incoming_message ${timestamp}`);
+        },
+        onQueueEmpty(): void {
+          recordCall('_queue_empty');
+        },
+        onReceivedAlerts(_alerts: string[]): void {},
+        onConnectionInterrupted(_cause: object | null): void {},
+      };
+      const tokio = new TokioAsyncContext(Native.TokioAsyncContext_new());
+      const [_chat, _fakeRemote] = AuthenticatedChatConnection.fakeConnect(
+        tokio,
+        listener,
+        [],
+        [INCOMING_MESSAGE_1, INCOMING_MESSAGE_2, EMPTY_QUEUE]
+      );
+
+      await completable.done();
+      expect(callsReceived).to.eql([
+        '_incoming_message 1000',
+        '_incoming_message 2000',
+        '_queue_empty',
+      ]);
+    });
+
     it('listener gets null cause for intentional disconnect', async () => {
       const completable = new CompletablePromise();
       const connectionInterruptedReasons: (object | null)[] = [];
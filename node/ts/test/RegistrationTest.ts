@@ -60,6 +60,10 @@ describe('Registration types', () => {
       },
     ];
     const timeoutCase: [string, ErrorCode] = ['Timeout', ErrorCode.IoError];
+    const outcomeUnknownCase: [string, ErrorCode] = [
+      'OutcomeUnknown',
+      ErrorCode.RequestOutcomeUnknown,
+    ];
     const cases: Array<{
       operationName: string;
       convertFn: (_: string) => void;
@@ -73,6 +77,7 @@ describe('Registration types', () => {
           retryLaterCase,
           unknownCase,
           timeoutCase,
+          outcomeUnknownCase,
         ],
       },
       {
@@ -83,6 +88,7 @@ describe('Registration types', () => {
           ['SessionNotFound', ErrorCode.Generic],
           unknownCase,
           timeoutCase,
+          outcomeUnknownCase,
         ],
       },
       {
@@ -93,6 +99,7 @@ describe('Registration types', () => {
           retryLaterCase,
           unknownCase,
           timeoutCase,
+          outcomeUnknownCase,
         ],
       },
       {
@@ -108,6 +115,7 @@ describe('Registration types', () => {
           retryLaterCase,
           unknownCase,
           timeoutCase,
+          outcomeUnknownCase,
         ],
       },
       {
@@ -118,9 +126,11 @@ describe('Registration types', () => {
           ['InvalidSessionId', ErrorCode.Generic],
           ['SessionNotFound', ErrorCode.Generic],
           ['NotReadyForVerification', ErrorCode.Generic],
+          ['IncorrectCode', ErrorCode.Generic],
           retryLaterCase,
           unknownCase,
           timeoutCase,
+          outcomeUnknownCase,
         ],
       },
     ];
@@ -9,7 +9,7 @@ use prost::Message;
 
 use crate::constants::ENCLAVE_ID_CDSI_STAGING_AND_PROD;
 use crate::dcap;
-use crate::enclave::{Handshake, HandshakeType, Result};
+use crate::enclave::{Handshake, HandshakeRng, HandshakeType, Result};
 use crate::proto::cds2;
 use crate::util::SmallMap;
 
@@ -24,10 +24,15 @@ const ACCEPTABLE_SW_ADVISORIES: &SmallMap<&[u8], &'static [&'static str], 1> = &
 /// is not contained in `ACCEPTABLE_SW_ADVISORIES`, this will be used
 const DEFAULT_SW_ADVISORIES: &[&str] = &[];
 
+/// Starts a handshake with the CDSI enclave that sent `attestation_msg`.
+///
+/// `handshake_rng` replaces the operating system's RNG for the handshake's
+/// ephemeral key; see [`HandshakeRng`] for why that's only for tests.
 pub fn new_handshake(
     mrenclave: &[u8],
     attestation_msg: &[u8],
     current_time: std::time::SystemTime,
+    handshake_rng: Option<HandshakeRng>,
 ) -> Result<Handshake> {
     // Deserialize attestation handshake start.
     let handshake_start = cds2::ClientHandshakeStart::decode(attestation_msg)?;
@@ -40,6 +45,7 @@ pub fn new_handshake(
             .unwrap_or(&DEFAULT_SW_ADVISORIES),
         current_time,
         HandshakeType::PreQuantum,
+        handshake_rng,
     )?
     .skip_raft_validation())
 }
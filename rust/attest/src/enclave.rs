@@ -7,6 +7,7 @@ use std::collections::HashMap;
 
 use displaydoc::Display;
 use prost::Message;
+use snow::resolvers::CryptoResolver;
 
 use crate::client_connection::ClientConnection;
 use crate::svr2::RaftConfig;
@@ -100,28 +101,34 @@ impl Handshake {
         })
     }
 
-    pub(crate) fn with_claims(claims: Claims, typ: HandshakeType) -> Result<UnvalidatedHandshake> {
+    pub(crate) fn with_claims(
+        claims: Claims,
+        typ: HandshakeType,
+        handshake_rng: Option<HandshakeRng>,
+    ) -> Result<UnvalidatedHandshake> {
         let pattern = match typ {
             HandshakeType::PreQuantum => client_connection::NOISE_PATTERN,
             HandshakeType::PostQuantum => client_connection::NOISE_PATTERN_HFS,
         };
-        let mut handshake = snow::Builder::with_resolver(
-            pattern.parse().expect("valid"),
-            Box::new(snow_resolver::Resolver),
-        )
-        .remote_public_key(&claims.public_key)
-        .build_initiator()
-        .map_err(|_| {
-            // The only thing that can go wrong is that claims.public_key is invalid, which isn't a
-            // fault in the Noise handshake. Produce a data error instead to indicate this (and for
-            // simpler exception logic in the apps).
-            //
-            // In practice the current version of Noise does not even check this up front, so we
-            // can't test this. But a future version could and the previous reasoning stands.
-            Error::AttestationDataError {
-                reason: "invalid public key".to_string(),
-            }
-        })?;
+        let resolver = match handshake_rng {
+            Some(HandshakeRng(resolver)) => resolver,
+            None => Box::new(snow_resolver::Resolver),
+        };
+        let mut handshake = snow::Builder::with_resolver(pattern.parse().expect("valid"), resolver)
+            .remote_public_key(&claims.public_key)
+            .build_initiator()
+            .map_err(|_| {
+                // The only thing that can go wrong is that claims.public_key is invalid,
+                // which isn't a fault in the Noise handshake. Produce a data error instead
+                // to indicate this (and for simpler exception logic in the apps).
+                //
+                // In practice the current version of Noise does not even check this up
+                // front, so we can't test this. But a future version could and the
+                // previous reasoning stands.
+                Error::AttestationDataError {
+                    reason: "invalid public key".to_string(),
+                }
+            })?;
         let mut initial_request = vec![0u8; client_connection::NOISE_HANDSHAKE_OVERHEAD];
         // We send an empty message, but the round-trip to the server and back is still required
         // in order to complete the noise handshake. If we needed some initial payload we could
@@ -138,6 +145,31 @@ impl Handshake {
     }
 }
 
+/// A caller-provided source of randomness for the ephemeral key of an attested
+/// handshake, used instead of the operating system's RNG.
+///
+/// # Security
+///
+/// **The security of the resulting connection depends entirely on this RNG.**
+/// A predictable or reused RNG lets anyone who can observe the connection
+/// recover the session keys, which is why one can only be created with the
+/// `test-util` feature.
+///
+/// Post-quantum handshakes still draw their KEM encapsulation randomness
+/// from the operating system, so they are not fully deterministic even with
+/// a seeded RNG.
+pub struct HandshakeRng(Box<dyn CryptoResolver + Send>);
+
+impl HandshakeRng {
+    /// Wraps `rng` for use by a single handshake.
+    #[cfg(any(test, feature = "test-util"))]
+    pub fn new(
+        rng: impl rand_core::RngCore + rand_core::CryptoRng + Send + Sync + 'static,
+    ) -> Self {
+        Self(Box::new(snow_resolver::ResolverWithRng::new(rng)))
+    }
+}
+
 pub(crate) struct UnvalidatedHandshake(Handshake);
 
 impl UnvalidatedHandshake {
@@ -13,7 +13,9 @@
 use std::time::Duration;
 
 use crate::dcap::{self, MREnclave};
-use crate::enclave::{Claims, Error, Handshake, HandshakeType, Result, UnvalidatedHandshake};
+use crate::enclave::{
+    Claims, Error, Handshake, HandshakeRng, HandshakeType, Result, UnvalidatedHandshake,
+};
 
 const INVALID_EVIDENCE: &str = "Evidence does not fit expected format";
 const INVALID_ENDORSEMENT: &str = "Endorsement does not fit expected format";
@@ -31,6 +33,7 @@ impl Handshake {
         acceptable_sw_advisories: &[&str],
         current_time: std::time::SystemTime,
         handshake_type: HandshakeType,
+        handshake_rng: Option<HandshakeRng>,
     ) -> Result<UnvalidatedHandshake> {
         if evidence.is_empty() {
             return Err(Error::AttestationDataError {
@@ -59,7 +62,11 @@ impl Handshake {
             current_time + SKEW_ADJUSTMENT,
         )?;
 
-        Self::with_claims(Claims::from_custom_claims(claims)?, handshake_type)
+        Self::with_claims(
+            Claims::from_custom_claims(claims)?,
+            handshake_type,
+            handshake_rng,
+        )
     }
 }
 
@@ -93,6 +100,14 @@ pub mod testutil {
     }
 
     pub fn handshake_from_tests_data() -> Result<Handshake> {
+        handshake_from_tests_data_with_rng(None)
+    }
+
+    /// Like [`handshake_from_tests_data`], but generating the ephemeral key
+    /// with `handshake_rng` if provided.
+    pub fn handshake_from_tests_data_with_rng(
+        handshake_rng: Option<HandshakeRng>,
+    ) -> Result<Handshake> {
         // Read test data files, de-hex-stringing as necessary.
         let mrenclave_bytes = mrenclave_bytes();
         let current_time = SystemTime::UNIX_EPOCH + Duration::from_millis(1655857680000);
@@ -103,6 +118,7 @@ pub mod testutil {
             &[],
             current_time,
             HandshakeType::PreQuantum,
+            handshake_rng,
         )?
         .skip_raft_validation())
     }
@@ -127,6 +143,7 @@ mod tests {
                 &[],
                 time,
                 HandshakeType::PreQuantum,
+                None,
             );
             assert_eq!(result.is_ok(), expect_success);
         };
@@ -196,6 +213,43 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_handshake_with_seeded_rng() -> Result<()> {
+        use rand_core::SeedableRng as _;
+
+        let seeded_handshake = |seed| {
+            testutil::handshake_from_tests_data_with_rng(Some(HandshakeRng::new(
+                rand_chacha::ChaCha20Rng::seed_from_u64(seed),
+            )))
+        };
+
+        let first = seeded_handshake(1)?;
+        let same_seed = seeded_handshake(1)?;
+        let other_seed = seeded_handshake(2)?;
+        assert_eq!(first.initial_request(), same_seed.initial_request());
+        assert_ne!(first.initial_request(), other_seed.initial_request());
+
+        // The handshake still completes against the enclave.
+        let private_key = testutil::private_key();
+        let mut server_hs = snow::Builder::new(client_connection::NOISE_PATTERN.parse()?)
+            .local_private_key(&private_key)
+            .build_responder()?;
+        let read_size = server_hs.read_message(first.initial_request(), &mut [])?;
+        assert_eq!(read_size, 0);
+        let mut message = vec![0u8; 48];
+        let write_size = server_hs.write_message(&[], &mut message)?;
+        assert_eq!(write_size, 48);
+
+        let mut conn = first.complete(&message)?;
+        let mut server_transport = server_hs.into_transport_mode()?;
+        let cli_svr_message = conn.send(&[1, 2, 3])?;
+        let mut cli_svr_payload = vec![0u8; 3];
+        server_transport.read_message(&cli_svr_message, &mut cli_svr_payload)?;
+        assert_eq!([1, 2, 3], cli_svr_payload.as_slice());
+
+        Ok(())
+    }
+
     #[test]
     fn test_mismatched_keys() -> Result<()> {
         // Spin up a handshake for the server-side.
@@ -298,6 +298,20 @@ impl Kem for Kyber1024 {
 
 pub struct Resolver;
 
+/// A [`Resolver`] that provides a caller-supplied RNG instead of the OS's.
+///
+/// The RNG can only be resolved once; that's enough for building a single
+/// handshake.
+#[cfg(any(test, feature = "test-util"))]
+pub(crate) struct ResolverWithRng(std::cell::Cell<Option<Box<dyn Random>>>);
+
+#[cfg(any(test, feature = "test-util"))]
+impl ResolverWithRng {
+    pub(crate) fn new(rng: impl RngCore + CryptoRng + Send + Sync + 'static) -> Self {
+        Self(std::cell::Cell::new(Some(Box::new(Rng(rng)))))
+    }
+}
+
 impl CryptoResolver for Resolver {
     fn resolve_rng(&self) -> Option<Box<dyn Random>> {
         Some(Box::new(Rng(rand_core::OsRng)))
@@ -331,3 +345,26 @@ impl CryptoResolver for Resolver {
         }
     }
 }
+
+#[cfg(any(test, feature = "test-util"))]
+impl CryptoResolver for ResolverWithRng {
+    fn resolve_rng(&self) -> Option<Box<dyn Random>> {
+        self.0.take()
+    }
+
+    fn resolve_dh(&self, choice: &DHChoice) -> Option<Box<dyn Dh>> {
+        Resolver.resolve_dh(choice)
+    }
+
+    fn resolve_hash(&self, choice: &HashChoice) -> Option<Box<dyn Hash>> {
+        Resolver.resolve_hash(choice)
+    }
+
+    fn resolve_cipher(&self, choice: &CipherChoice) -> Option<Box<dyn Cipher>> {
+        Resolver.resolve_cipher(choice)
+    }
+
+    fn resolve_kem(&self, choice: &KemChoice) -> Option<Box<dyn Kem>> {
+        Resolver.resolve_kem(choice)
+    }
+}
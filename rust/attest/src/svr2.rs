@@ -8,7 +8,7 @@ use prost::Message;
 use crate::constants::{
     ACCEPTABLE_SW_ADVISORIES, DEFAULT_SW_ADVISORIES, EXPECTED_RAFT_CONFIG_SVR2,
 };
-use crate::enclave::{Error, Handshake, HandshakeType, Result};
+use crate::enclave::{Error, Handshake, HandshakeRng, HandshakeType, Result};
 use crate::proto::svr;
 
 /// A RaftConfig that can be checked against the attested remote config
@@ -57,15 +57,21 @@ pub fn new_handshake_with_raft_config_lookup(
         current_time,
         expected_raft_config,
         handshake_type,
+        None,
     )
 }
 
+/// Starts a handshake with the SVR2 enclave that sent `attestation_msg`.
+///
+/// `handshake_rng` replaces the operating system's RNG for the handshake's
+/// ephemeral key; see [`HandshakeRng`] for why that's only for tests.
 pub fn new_handshake(
     mrenclave: &[u8],
     attestation_msg: &[u8],
     current_time: std::time::SystemTime,
     expected_raft_config: &'static RaftConfig,
     handshake_type: HandshakeType,
+    handshake_rng: Option<HandshakeRng>,
 ) -> Result<Handshake> {
     new_handshake_with_constants(
         mrenclave,
@@ -76,6 +82,7 @@ pub fn new_handshake(
             .unwrap_or(&DEFAULT_SW_ADVISORIES),
         expected_raft_config,
         handshake_type,
+        handshake_rng,
     )
 }
 
@@ -86,6 +93,7 @@ fn new_handshake_with_constants(
     acceptable_sw_advisories: &[&str],
     expected_raft_config: &RaftConfig,
     handshake_type: HandshakeType,
+    handshake_rng: Option<HandshakeRng>,
 ) -> Result<Handshake> {
     // Deserialize attestation handshake start.
     let handshake_start = svr::ClientHandshakeStart::decode(attestation_msg)?;
@@ -96,6 +104,7 @@ fn new_handshake_with_constants(
         acceptable_sw_advisories,
         current_time,
         handshake_type,
+        handshake_rng,
     )?
     .validate(expected_raft_config)?;
 
@@ -128,6 +137,7 @@ mod tests {
                 group_id: 3565209795906488720,
             },
             HandshakeType::PreQuantum,
+            None,
         )
         .unwrap();
     }
@@ -151,6 +161,7 @@ mod tests {
                 group_id: 0, // wrong
             },
             HandshakeType::PreQuantum,
+            None,
         )
         .is_err());
     }
@@ -24,6 +24,7 @@ fn new_client(
         mrenclave,
         attestation_msg,
         current_time,
+        None,
     )?)
 }
 
@@ -58,6 +58,33 @@ fn TESTING_FakeChatConnection_Create(
     }
 }
 
+#[bridge_fn]
+fn TESTING_FakeChatConnection_CreateWithIncoming(
+    tokio: &TokioAsyncContext,
+    listener: Box<dyn ChatListener>,
+    alerts_joined_by_newlines: String,
+    requests: Vec<&[u8]>,
+) -> FakeChatConnection {
+    let alerts = alerts_joined_by_newlines.split_terminator('\n');
+    let (chat, remote) = libsignal_bridge_types::net::chat::FakeChatConnection::new(
+        tokio.handle(),
+        listener,
+        alerts,
+    );
+    // Queue the requests before handing out the connection. The fake transport
+    // delivers them in order ahead of anything the test sends afterwards, and
+    // the fake connection's idle timeouts are far longer than any test runs.
+    for request in requests {
+        remote
+            .send_request(prost::Message::decode(request).expect("invalid Request proto"))
+            .expect("chat task finished");
+    }
+    FakeChatConnection {
+        chat: Some(chat).into(),
+        remote_end: Some(remote).into(),
+    }
+}
+
 #[bridge_fn]
 fn TESTING_FakeChatConnection_TakeAuthenticatedChat(
     chat: &FakeChatConnection,
@@ -223,6 +250,13 @@ make_error_testing_enum! {
         AllAttemptsFailed => AllAttemptsFailed,
         InvalidConnectionConfiguration => InvalidConnectionConfiguration,
         RetryLater => RetryAfter42Seconds,
+        NoServerResponse => NoServerResponse,
+        ServerMaintenance => ServerMaintenance,
+        UnstableNetwork => UnstableNetwork,
+        UnsupportedSubprotocol => UnsupportedSubprotocol,
+        Cancelled => Cancelled,
+        ;
+        ServerMaintenanceRetryAfter42Seconds,
     }
 }
 
@@ -247,6 +281,20 @@ fn TESTING_ChatConnectErrorConvert(
         TestingChatConnectError::RetryAfter42Seconds => ConnectError::RetryLater(RetryLater {
             retry_after_seconds: 42,
         }),
+        TestingChatConnectError::NoServerResponse => ConnectError::NoServerResponse,
+        TestingChatConnectError::UnstableNetwork => ConnectError::UnstableNetwork,
+        TestingChatConnectError::UnsupportedSubprotocol => ConnectError::UnsupportedSubprotocol,
+        TestingChatConnectError::Cancelled => ConnectError::Cancelled,
+        TestingChatConnectError::ServerMaintenance => {
+            ConnectError::ServerMaintenance { retry_after: None }
+        }
+        TestingChatConnectError::ServerMaintenanceRetryAfter42Seconds => {
+            ConnectError::ServerMaintenance {
+                retry_after: Some(RetryLater {
+                    retry_after_seconds: 42,
+                }),
+            }
+        }
     })
 }
 
@@ -259,6 +307,9 @@ make_error_testing_enum! {
         WebSocket => WebSocketConnectionReset,
         IncomingDataInvalid => IncomingDataInvalid,
         RequestHasInvalidHeader => RequestHasInvalidHeader,
+        ServerMaintenance => ServerMaintenance,
+        ;
+        ServerMaintenanceRetryAfter42Seconds,
     }
 }
 
@@ -279,5 +330,15 @@ fn TESTING_ChatSendErrorConvert(
         }
         TestingChatSendError::IncomingDataInvalid => SendError::IncomingDataInvalid,
         TestingChatSendError::RequestHasInvalidHeader => SendError::RequestHasInvalidHeader,
+        TestingChatSendError::ServerMaintenance => {
+            SendError::ServerMaintenance { retry_after: None }
+        }
+        TestingChatSendError::ServerMaintenanceRetryAfter42Seconds => {
+            SendError::ServerMaintenance {
+                retry_after: Some(RetryLater {
+                    retry_after_seconds: 42,
+                }),
+            }
+        }
     })
 }
@@ -38,6 +38,7 @@ impl<TestE> TestingRequestError<TestE> {
         match inner {
             RequestError::Timeout => RequestError::Timeout,
             RequestError::RequestWasNotValid => RequestError::RequestWasNotValid,
+            RequestError::OutcomeUnknown => RequestError::OutcomeUnknown,
             RequestError::Unknown(message) => RequestError::Unknown(message),
             RequestError::Other(e) => RequestError::Other(f(e)),
         }
@@ -148,6 +149,7 @@ make_error_testing_enum!(
     enum TestingRequestVerificationCodeError for RequestVerificationCodeError {
         InvalidSessionId => InvalidSessionId,
         SessionNotFound => SessionNotFound,
+        AlreadyVerified => AlreadyVerified,
         NotReadyForVerification => NotReadyForVerification,
         SendFailed => SendFailed,
         CodeNotDeliverable => CodeNotDeliverable,
@@ -174,6 +176,9 @@ fn TESTING_RegistrationService_RequestVerificationCodeErrorConvert(
             TestingRequestVerificationCodeError::SessionNotFound => {
                 RequestVerificationCodeError::SessionNotFound
             }
+            TestingRequestVerificationCodeError::AlreadyVerified => {
+                RequestVerificationCodeError::AlreadyVerified
+            }
             TestingRequestVerificationCodeError::NotReadyForVerification => {
                 RequestVerificationCodeError::NotReadyForVerification
             }
@@ -196,7 +201,9 @@ make_error_testing_enum!(
     enum TestingSubmitVerificationError for SubmitVerificationError {
         InvalidSessionId => InvalidSessionId,
         SessionNotFound => SessionNotFound,
+        AlreadyVerified => AlreadyVerified,
         NotReadyForVerification => NotReadyForVerification,
+        IncorrectCode => IncorrectCode,
         RetryLater => RetryAfter42Seconds,
     }
 );
@@ -219,9 +226,17 @@ fn TESTING_RegistrationService_SubmitVerificationErrorConvert(
             TestingSubmitVerificationError::SessionNotFound => {
                 SubmitVerificationError::SessionNotFound
             }
+            TestingSubmitVerificationError::AlreadyVerified => {
+                SubmitVerificationError::AlreadyVerified
+            }
             TestingSubmitVerificationError::NotReadyForVerification => {
                 SubmitVerificationError::NotReadyForVerification
             }
+            TestingSubmitVerificationError::IncorrectCode => {
+                SubmitVerificationError::IncorrectCode {
+                    remaining_attempts: Some(2),
+                }
+            }
             TestingSubmitVerificationError::RetryAfter42Seconds => {
                 SubmitVerificationError::RetryLater(RETRY_AFTER_42_SECONDS)
             }
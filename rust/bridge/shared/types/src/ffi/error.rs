@@ -102,6 +102,7 @@ pub enum SignalErrorCode {
     DeviceDeregistered = 171,
     ConnectionInvalidated = 172,
     ConnectedElsewhere = 173,
+    ServerMaintenance = 174,
 
     BackupValidation = 180,
 }
@@ -492,29 +493,56 @@ impl FfiError for libsignal_net::chat::ConnectError {
             Self::Timeout => "Connect timed out".to_owned(),
             Self::AppExpired => "App expired".to_owned(),
             Self::DeviceDeregistered => "Device deregistered or delinked".to_owned(),
+            Self::NoServerResponse => "Connected, but the server did not respond".to_owned(),
+            Self::UnstableNetwork => "Network changed too often to connect".to_owned(),
+            Self::Throttled => "Too many recent connects; throttled".to_owned(),
+            Self::UnsupportedSubprotocol => {
+                "Server selected an unsupported websocket subprotocol".to_owned()
+            }
+            Self::Cancelled => "Connect was cancelled".to_owned(),
             Self::RetryLater(RetryLater {
                 retry_after_seconds,
             }) => format!("Rate limited; try again after {retry_after_seconds}s"),
+            Self::ServerMaintenance { retry_after: None } => {
+                "Server is down for maintenance".to_owned()
+            }
+            Self::ServerMaintenance {
+                retry_after:
+                    Some(RetryLater {
+                        retry_after_seconds,
+                    }),
+            } => format!("Server is down for maintenance; try again after {retry_after_seconds}s"),
         }
     }
 
     fn code(&self) -> SignalErrorCode {
         match self {
             Self::WebSocket(_) => SignalErrorCode::WebSocket,
-            Self::AllAttemptsFailed { .. } | Self::InvalidConnectionConfiguration => {
-                SignalErrorCode::ConnectionFailed
-            }
+            Self::AllAttemptsFailed { .. }
+            | Self::InvalidConnectionConfiguration
+            | Self::NoServerResponse
+            | Self::UnstableNetwork
+            | Self::Throttled
+            | Self::UnsupportedSubprotocol => SignalErrorCode::ConnectionFailed,
             Self::Timeout => SignalErrorCode::ConnectionTimedOut,
             Self::AppExpired => SignalErrorCode::AppExpired,
             Self::DeviceDeregistered => SignalErrorCode::DeviceDeregistered,
             Self::RetryLater { .. } => SignalErrorCode::RateLimited,
+            Self::ServerMaintenance { .. } => SignalErrorCode::ServerMaintenance,
+            Self::Cancelled => SignalErrorCode::Cancelled,
         }
     }
     fn provide_retry_after_seconds(&self) -> Result<u32, WrongErrorKind> {
         match self {
             Self::RetryLater(RetryLater {
                 retry_after_seconds,
-            }) => Ok(*retry_after_seconds),
+            })
+            | Self::ServerMaintenance {
+                retry_after:
+                    Some(RetryLater {
+                        retry_after_seconds,
+                    }),
+            } => Ok(*retry_after_seconds),
             _ => Err(WrongErrorKind),
         }
     }
@@ -532,6 +560,15 @@ impl FfiError for libsignal_net::chat::SendError {
             Self::Disconnected => "Chat service disconnected".to_owned(),
             Self::ConnectionInvalidated => "Connection invalidated".to_owned(),
             Self::ConnectedElsewhere => "Connected elsewhere".to_owned(),
+            Self::ServerMaintenance { retry_after: None } => {
+                "Server is down for maintenance".to_owned()
+            }
+            Self::ServerMaintenance {
+                retry_after:
+                    Some(RetryLater {
+                        retry_after_seconds,
+                    }),
+            } => format!("Server is down for maintenance; try again after {retry_after_seconds}s"),
         }
     }
 
@@ -544,10 +581,19 @@ impl FfiError for libsignal_net::chat::SendError {
             Self::Disconnected => SignalErrorCode::ChatServiceInactive,
             Self::ConnectionInvalidated => SignalErrorCode::ConnectionInvalidated,
             Self::ConnectedElsewhere => SignalErrorCode::ConnectedElsewhere,
+            Self::ServerMaintenance { .. } => SignalErrorCode::ServerMaintenance,
         }
     }
     fn provide_retry_after_seconds(&self) -> Result<u32, WrongErrorKind> {
-        Err(WrongErrorKind)
+        match self {
+            Self::ServerMaintenance {
+                retry_after:
+                    Some(RetryLater {
+                        retry_after_seconds,
+                    }),
+            } => Ok(*retry_after_seconds),
+            _ => Err(WrongErrorKind),
+        }
     }
 }
 
@@ -574,9 +574,17 @@ impl JniError for ChatConnectError {
             ChatConnectError::DeviceDeregistered => {
                 ClassName("org.signal.libsignal.net.DeviceDeregisteredException")
             }
+            ChatConnectError::Cancelled => ClassName("java.util.concurrent.CancellationException"),
+            ChatConnectError::ServerMaintenance { retry_after } => {
+                return server_maintenance_exception(env, self.to_string(), retry_after);
+            }
             ChatConnectError::WebSocket(_)
             | ChatConnectError::Timeout
             | ChatConnectError::AllAttemptsFailed
+            | ChatConnectError::NoServerResponse
+            | ChatConnectError::UnstableNetwork
+            | ChatConnectError::Throttled
+            | ChatConnectError::UnsupportedSubprotocol
             | ChatConnectError::InvalidConnectionConfiguration => {
                 ClassName("org.signal.libsignal.net.ChatServiceException")
             }
@@ -585,32 +593,62 @@ impl JniError for ChatConnectError {
     }
 }
 
-impl MessageOnlyExceptionJniError for ChatSendError {
-    fn exception_class(&self) -> ClassName<'static> {
-        match self {
-            ChatSendError::Disconnected => {
-                ClassName("org.signal.libsignal.net.ChatServiceInactiveException")
-            }
-            ChatSendError::ConnectionInvalidated => {
-                ClassName("org.signal.libsignal.net.ConnectionInvalidatedException")
-            }
-            ChatSendError::ConnectedElsewhere => {
-                ClassName("org.signal.libsignal.net.ConnectedElsewhereException")
-            }
-            ChatSendError::WebSocket(_)
-            | ChatSendError::IncomingDataInvalid
-            | ChatSendError::RequestHasInvalidHeader
-            | ChatSendError::RequestTimedOut => {
-                ClassName("org.signal.libsignal.net.ChatServiceException")
-            }
+impl JniError for ChatSendError {
+    fn to_throwable<'a>(&self, env: &mut JNIEnv<'a>) -> Result<JThrowable<'a>, BridgeLayerError> {
+        if let ChatSendError::ServerMaintenance { retry_after } = *self {
+            return server_maintenance_exception(env, self.to_string(), retry_after);
+        }
+        make_single_message_throwable(env, &self.to_string(), chat_send_error_class(self))
+    }
+}
+
+fn chat_send_error_class(error: &ChatSendError) -> ClassName<'static> {
+    match error {
+        ChatSendError::Disconnected => {
+            ClassName("org.signal.libsignal.net.ChatServiceInactiveException")
+        }
+        ChatSendError::ConnectionInvalidated => {
+            ClassName("org.signal.libsignal.net.ConnectionInvalidatedException")
+        }
+        ChatSendError::ConnectedElsewhere => {
+            ClassName("org.signal.libsignal.net.ConnectedElsewhereException")
+        }
+        // ChatSendError throws ServerMaintenanceException itself; this is only
+        // reached for a KeyTransNetError, which can only carry a message.
+        ChatSendError::ServerMaintenance { .. }
+        | ChatSendError::WebSocket(_)
+        | ChatSendError::IncomingDataInvalid
+        | ChatSendError::RequestHasInvalidHeader
+        | ChatSendError::RequestTimedOut => {
+            ClassName("org.signal.libsignal.net.ChatServiceException")
         }
     }
 }
 
+/// Creates a `ServerMaintenanceException`, with a negative retry delay if the
+/// server didn't say when to come back.
+fn server_maintenance_exception<'a>(
+    env: &mut JNIEnv<'a>,
+    message: String,
+    retry_after: Option<RetryLater>,
+) -> Result<JThrowable<'a>, BridgeLayerError> {
+    let retry_after_seconds: i64 = retry_after.map_or(-1, |r| r.retry_after_seconds.into());
+    let message = to_java_string(env, message)?;
+    new_instance(
+        env,
+        ClassName("org.signal.libsignal.net.ServerMaintenanceException"),
+        jni_args!((
+            message => java.lang.String,
+            retry_after_seconds => long,
+        ) -> void),
+    )
+    .map(Into::into)
+}
+
 impl MessageOnlyExceptionJniError for KeyTransNetError {
     fn exception_class(&self) -> ClassName<'static> {
         match &self {
-            KeyTransNetError::ChatSendError(send_error) => send_error.exception_class(),
+            KeyTransNetError::ChatSendError(send_error) => chat_send_error_class(send_error),
             KeyTransNetError::RequestFailed(_)
             | KeyTransNetError::VerificationFailed(_)
             | KeyTransNetError::InvalidResponse(_)
@@ -149,7 +149,7 @@ impl ConnectionManager {
             connect: ConnectState::new_with_transport_connector(
                 SUGGESTED_CONNECT_CONFIG,
                 PreconnectingFactory::new(
-                    DefaultConnectorFactory,
+                    DefaultConnectorFactory::from_config(&SUGGESTED_CONNECT_CONFIG),
                     SUGGESTED_TLS_PRECONNECT_LIFETIME,
                 ),
             ),
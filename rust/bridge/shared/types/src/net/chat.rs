@@ -19,8 +19,8 @@ use libsignal_net::chat::fake::FakeChatRemote;
 use libsignal_net::chat::server_requests::DisconnectCause;
 use libsignal_net::chat::ws2::ListenerEvent;
 use libsignal_net::chat::{
-    self, ChatConnection, ConnectError, ConnectionInfo, DebugInfo as ChatServiceDebugInfo, Request,
-    Response as ChatResponse, SendError,
+    self, ChatConnection, ConnectError, ConnectionInfo, DebugInfo as ChatServiceDebugInfo,
+    PreconnectPolicy, Request, Response as ChatResponse, SendError,
 };
 use libsignal_net::connect_state::ConnectionResources;
 use libsignal_net::infra::route::{
@@ -130,7 +130,7 @@ impl AuthenticatedChatConnection {
 
         log::info!("preconnecting chat");
         connection_resources
-            .preconnect_and_save(route_provider, "preconnect".into())
+            .preconnect_and_save(route_provider, "preconnect".into(), None)
             .await?;
         Ok(())
     }
@@ -322,8 +322,10 @@ async fn establish_chat_connection(
             local_idle_timeout,
             remote_idle_timeout: remote_idle_disconnect_timeout,
             initial_request_id: 0,
+            subprotocols: &[],
         },
         auth,
+        PreconnectPolicy::UseIfAvailable,
         auth_type,
     )
     .inspect(|r| match r {
@@ -418,12 +418,21 @@ impl SignalNodeError for libsignal_net::chat::ConnectError {
         let name = match self {
             Self::AppExpired => "AppExpired",
             Self::DeviceDeregistered => "DeviceDelinked",
+            Self::Cancelled => "Cancelled",
             Self::RetryLater(retry_later) => {
                 return retry_later.into_throwable(cx, module, operation_name)
             }
+            Self::ServerMaintenance { retry_after } => {
+                let message = self.to_string();
+                return server_maintenance_error(cx, module, &message, operation_name, retry_after);
+            }
             Self::WebSocket(_)
             | Self::Timeout
             | Self::AllAttemptsFailed
+            | Self::NoServerResponse
+            | Self::UnstableNetwork
+            | Self::Throttled
+            | Self::UnsupportedSubprotocol
             | Self::InvalidConnectionConfiguration =>
             // TODO: Distinguish retryable errors from proper failures?
             {
@@ -442,6 +451,33 @@ impl SignalNodeError for libsignal_net::chat::ConnectError {
     }
 }
 
+/// Creates a `ServerMaintenance` error, with `retryAfterSecs` set if the
+/// server said when to come back.
+fn server_maintenance_error<'a, C: Context<'a>>(
+    cx: &mut C,
+    module: Handle<'a, JsObject>,
+    message: &str,
+    operation_name: &str,
+    retry_after: Option<libsignal_net::infra::errors::RetryLater>,
+) -> Handle<'a, JsError> {
+    let properties = move |cx: &mut C| {
+        let props = cx.empty_object();
+        if let Some(retry_after) = retry_after {
+            let retry_after = retry_after.retry_after_seconds.convert_into(cx)?;
+            props.set(cx, "retryAfterSecs", retry_after)?;
+        }
+        Ok(props.upcast())
+    };
+    new_js_error(
+        cx,
+        module,
+        Some("ServerMaintenance"),
+        message,
+        operation_name,
+        properties,
+    )
+}
+
 impl SignalNodeError for libsignal_net::chat::SendError {
     fn into_throwable<'a, C: Context<'a>>(
         self,
@@ -453,6 +489,10 @@ impl SignalNodeError for libsignal_net::chat::SendError {
             Self::Disconnected => Some("ChatServiceInactive"),
             Self::ConnectionInvalidated => Some("ConnectionInvalidated"),
             Self::ConnectedElsewhere => Some("ConnectedElsewhere"),
+            Self::ServerMaintenance { retry_after } => {
+                let message = self.to_string();
+                return server_maintenance_error(cx, module, &message, operation_name, retry_after);
+            }
             Self::WebSocket(_)
             | Self::IncomingDataInvalid
             | Self::RequestHasInvalidHeader
@@ -590,6 +630,16 @@ mod registration {
                         no_extra_properties,
                     )
                 }
+                e @ RequestError::OutcomeUnknown => {
+                    return new_js_error(
+                        cx,
+                        module,
+                        Some("RequestOutcomeUnknown"),
+                        &e.to_string(),
+                        operation_name,
+                        no_extra_properties,
+                    )
+                }
             };
             SignalNodeError::into_throwable(inner, cx, module, operation_name)
         }
@@ -601,9 +651,11 @@ mod registration {
         RequestInvalid,
         RetryLater(RetryLater),
         RequestRejected,
+        AlreadyVerified,
         NotReadyForVerification,
         VerificationSendFailed,
         VerificationNotDeliverable(VerificationCodeNotDeliverable),
+        IncorrectVerificationCode,
     }
 
     impl SignalNodeError for BridgedErrorVariant {
@@ -623,6 +675,7 @@ mod registration {
                 BridgedErrorVariant::InvalidSessionId => "the session ID was invalid",
                 BridgedErrorVariant::RequestInvalid => "the request did not pass server validation",
                 BridgedErrorVariant::RequestRejected => "the information provided was rejected",
+                BridgedErrorVariant::AlreadyVerified => "the session is already verified",
                 BridgedErrorVariant::NotReadyForVerification => {
                     "the session is not ready for verification"
                 }
@@ -632,6 +685,9 @@ mod registration {
                 BridgedErrorVariant::VerificationNotDeliverable(_not_deliverable) => {
                     "the verification code could not be delivered"
                 }
+                BridgedErrorVariant::IncorrectVerificationCode => {
+                    "the verification code was incorrect"
+                }
             };
             new_js_error(
                 cx,
@@ -676,6 +732,7 @@ mod registration {
             match value {
                 RequestVerificationCodeError::InvalidSessionId => Self::InvalidSessionId,
                 RequestVerificationCodeError::SessionNotFound => Self::SessionNotFound,
+                RequestVerificationCodeError::AlreadyVerified => Self::AlreadyVerified,
                 RequestVerificationCodeError::NotReadyForVerification => {
                     Self::NotReadyForVerification
                 }
@@ -695,7 +752,11 @@ mod registration {
             match value {
                 SubmitVerificationError::InvalidSessionId => Self::InvalidSessionId,
                 SubmitVerificationError::SessionNotFound => Self::SessionNotFound,
+                SubmitVerificationError::AlreadyVerified => Self::AlreadyVerified,
                 SubmitVerificationError::NotReadyForVerification => Self::NotReadyForVerification,
+                SubmitVerificationError::IncorrectCode {
+                    remaining_attempts: _,
+                } => Self::IncorrectVerificationCode,
                 SubmitVerificationError::RetryLater(retry_later) => Self::RetryLater(retry_later),
             }
         }
@@ -68,6 +68,10 @@ async fn main() {
     };
 
     let cdsi_env = libsignal_net::env::PROD.cdsi;
+    cdsi_env
+        .domain_config
+        .validate()
+        .expect("valid CDSI domain config");
     let network_change_event = ObservableEvent::default();
     let resolver = DnsResolver::new();
 
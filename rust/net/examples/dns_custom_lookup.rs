@@ -71,7 +71,8 @@ async fn main() {
                     fragment: TlsRouteFragment {
                         root_certs: RootCertificates::Native,
                         sni: Host::Domain(host.clone()),
-                        alpn: Some(Alpn::Http2),
+                        alpn: Alpn::Http2.into(),
+                        ech_config_list: None,
                     },
                     inner: TcpRoute {
                         address: HOST_IP,
@@ -54,7 +54,8 @@ async fn main() {
             fragment: TlsRouteFragment {
                 root_certs: RootCertificates::Native,
                 sni: Host::Domain(host),
-                alpn: Some(Alpn::Http2),
+                alpn: Alpn::Http2.into(),
+                ech_config_list: None,
             },
             inner: TcpRoute {
                 address,
@@ -94,7 +94,8 @@ async fn main() {
             fragment: TlsRouteFragment {
                 root_certs,
                 sni: proxy_host.clone(),
-                alpn: Some(Alpn::Http1_1),
+                alpn: Alpn::Http1_1.into(),
+                ech_config_list: None,
             },
         }),
         scheme => panic!("unsupported protocol {scheme}"),
@@ -17,7 +17,9 @@ use libsignal_net::infra::certs::RootCertificates;
 use libsignal_net::infra::dns::DnsResolver;
 use libsignal_net::infra::host::Host;
 use libsignal_net::infra::tcp_ssl::proxy::socks::{Protocol, SocksConnector};
-use libsignal_net::infra::{Alpn, StreamAndInfo, TransportConnectionParams, TransportConnector};
+use libsignal_net::infra::{
+    Alpn, AlpnList, StreamAndInfo, TransportConnectionParams, TransportConnector,
+};
 use libsignal_net_infra::errors::TransportConnectError;
 use libsignal_net_infra::route::{
     ConnectorExt as _, ProxyTarget, SocksRoute, TcpRoute, TlsRoute, TlsRouteFragment,
@@ -105,7 +107,8 @@ async fn main() {
             fragment: TlsRouteFragment {
                 root_certs,
                 sni: Host::Domain(host_name),
-                alpn: None,
+                alpn: AlpnList::default(),
+                ech_config_list: None,
             },
             inner: SocksRoute {
                 proxy: TcpRoute {
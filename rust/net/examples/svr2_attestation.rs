@@ -71,11 +71,12 @@ impl<E: NewHandshake + 'static> NewHandshake for LoggingNewHandshake<E> {
     fn new_handshake(
         params: &EndpointParams<Self>,
         attestation_message: &[u8],
+        handshake_rng: Option<enclave::HandshakeRng>,
     ) -> enclave::Result<Handshake> {
         std::io::stdout()
             .write_all(attestation_message)
             .expect("can write to stdout");
-        E::new_handshake(&cast_params(params), attestation_message)
+        E::new_handshake(&cast_params(params), attestation_message, handshake_rng)
     }
 }
 
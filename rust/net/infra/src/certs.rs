@@ -4,9 +4,13 @@
 //
 
 use std::borrow::Cow;
+use std::sync::OnceLock;
 
 use boring_signal::error::ErrorStack;
-use boring_signal::ssl::{SslAlert, SslConnectorBuilder, SslVerifyError, SslVerifyMode};
+use boring_signal::ex_data::Index;
+use boring_signal::ssl::{
+    Ssl, SslAlert, SslConnectorBuilder, SslRef, SslVerifyError, SslVerifyMode,
+};
 use boring_signal::x509::store::X509StoreBuilder;
 use boring_signal::x509::X509;
 use rustls::client::danger::ServerCertVerifier;
@@ -66,6 +70,29 @@ impl RootCertificates {
     }
 }
 
+/// Why a server certificate was rejected, as far as error reporting cares.
+///
+/// BoringSSL only records that a [custom verify
+/// callback](boring::ssl::SslContextBuilder::set_custom_verify_callback)
+/// failed, so the platform verifier saves this on the connection instead.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum CertificateRejection {
+    Expired,
+    NotYetValid,
+    Other,
+}
+
+fn platform_verifier_rejection_index() -> Index<Ssl, CertificateRejection> {
+    static INDEX: OnceLock<Index<Ssl, CertificateRejection>> = OnceLock::new();
+    *INDEX.get_or_init(|| Ssl::new_ex_index().expect("can allocate an ex_data index"))
+}
+
+/// Returns why the platform verifier rejected `ssl`'s peer certificate, if it
+/// did.
+pub(crate) fn platform_verifier_rejection(ssl: &SslRef) -> Option<CertificateRejection> {
+    ssl.ex_data(platform_verifier_rejection_index()).copied()
+}
+
 /// Configures [rustls_platform_verifier] as a BoringSSL [custom verify
 /// callback](boring::ssl::SslContextBuilder::set_custom_verify_callback).
 fn set_up_platform_verifier(
@@ -104,63 +131,74 @@ fn set_up_platform_verifier(
         // We don't do our own OCSP. Either the platform will do its own checks, or it won't.
         let ocsp_responses = [];
 
-        verifier
-            .verify_server_cert(
-                &end_entity,
-                &intermediates,
-                &host_as_server_name,
-                &ocsp_responses,
-                rustls::pki_types::UnixTime::now(),
-            )
-            .map_err(|e| {
-                // The most important thing is to reject the certificate. Mapping the errors over
-                // only affects what message gets reported in logs. Which isn't *unimportant*, but
-                // isn't critical for correctness either.
-                //
-                // From RFC 5246:
-                // - bad_certificate: A certificate was corrupt, contained signatures that did not
-                //   verify correctly, etc.
-                // - certificate_expired: A certificate has expired or is not currently valid.
-                // - certificate_unknown: Some other (unspecified) issue arose in processing the
-                //   certificate, rendering it unacceptable.
-                // - certificate_revoked: A certificate was revoked by its signer.
-                // - unknown_ca: A valid certificate chain or partial chain was received, but the
-                //   certificate was not accepted because the CA certificate could not be located or
-                //   couldn't be matched with a known, trusted CA.
-                // - internal_error: An internal error unrelated to the peer or the correctness of
-                //   the protocol (such as a memory allocation failure) makes it impossible to
-                //   continue.
-                log::debug!(
-                    "TLS certificate for {} failed verification: {e}",
-                    host_as_server_name.to_str()
-                );
-                SslVerifyError::Invalid(match e {
-                    rustls::Error::InvalidCertificate(e) => match e {
-                        rustls::CertificateError::BadEncoding => SslAlert::BAD_CERTIFICATE,
-                        rustls::CertificateError::Expired => SslAlert::CERTIFICATE_EXPIRED,
-                        rustls::CertificateError::NotValidYet => SslAlert::CERTIFICATE_UNKNOWN,
-                        rustls::CertificateError::Revoked => SslAlert::CERTIFICATE_REVOKED,
-                        rustls::CertificateError::UnhandledCriticalExtension => {
-                            SslAlert::CERTIFICATE_UNKNOWN
-                        }
-                        rustls::CertificateError::UnknownIssuer => SslAlert::UNKNOWN_CA,
-                        rustls::CertificateError::UnknownRevocationStatus => {
-                            SslAlert::CERTIFICATE_UNKNOWN
-                        }
-                        rustls::CertificateError::BadSignature => SslAlert::BAD_CERTIFICATE,
-                        rustls::CertificateError::NotValidForName => SslAlert::CERTIFICATE_UNKNOWN,
-                        rustls::CertificateError::InvalidPurpose => SslAlert::CERTIFICATE_UNKNOWN,
-                        rustls::CertificateError::ApplicationVerificationFailure => {
-                            SslAlert::INTERNAL_ERROR
-                        }
-                        rustls::CertificateError::Other(_) => SslAlert::CERTIFICATE_UNKNOWN,
-
-                        // CertificateError is marked non_exhaustive, so we also have to have an explicit fallback:
-                        _ => SslAlert::CERTIFICATE_UNKNOWN,
-                    },
-                    _ => SslAlert::BAD_CERTIFICATE,
-                })
-            })?;
+        let result = verifier.verify_server_cert(
+            &end_entity,
+            &intermediates,
+            &host_as_server_name,
+            &ocsp_responses,
+            rustls::pki_types::UnixTime::now(),
+        );
+        if let Err(e) = &result {
+            let rejection = match e {
+                rustls::Error::InvalidCertificate(rustls::CertificateError::Expired) => {
+                    CertificateRejection::Expired
+                }
+                rustls::Error::InvalidCertificate(rustls::CertificateError::NotValidYet) => {
+                    CertificateRejection::NotYetValid
+                }
+                _ => CertificateRejection::Other,
+            };
+            ssl.set_ex_data(platform_verifier_rejection_index(), rejection);
+        }
+        result.map_err(|e| {
+            // The most important thing is to reject the certificate. Mapping the errors over
+            // only affects what message gets reported in logs. Which isn't *unimportant*, but
+            // isn't critical for correctness either.
+            //
+            // From RFC 5246:
+            // - bad_certificate: A certificate was corrupt, contained signatures that did not
+            //   verify correctly, etc.
+            // - certificate_expired: A certificate has expired or is not currently valid.
+            // - certificate_unknown: Some other (unspecified) issue arose in processing the
+            //   certificate, rendering it unacceptable.
+            // - certificate_revoked: A certificate was revoked by its signer.
+            // - unknown_ca: A valid certificate chain or partial chain was received, but the
+            //   certificate was not accepted because the CA certificate could not be located or
+            //   couldn't be matched with a known, trusted CA.
+            // - internal_error: An internal error unrelated to the peer or the correctness of
+            //   the protocol (such as a memory allocation failure) makes it impossible to
+            //   continue.
+            log::debug!(
+                "TLS certificate for {} failed verification: {e}",
+                host_as_server_name.to_str()
+            );
+            SslVerifyError::Invalid(match e {
+                rustls::Error::InvalidCertificate(e) => match e {
+                    rustls::CertificateError::BadEncoding => SslAlert::BAD_CERTIFICATE,
+                    rustls::CertificateError::Expired => SslAlert::CERTIFICATE_EXPIRED,
+                    rustls::CertificateError::NotValidYet => SslAlert::CERTIFICATE_UNKNOWN,
+                    rustls::CertificateError::Revoked => SslAlert::CERTIFICATE_REVOKED,
+                    rustls::CertificateError::UnhandledCriticalExtension => {
+                        SslAlert::CERTIFICATE_UNKNOWN
+                    }
+                    rustls::CertificateError::UnknownIssuer => SslAlert::UNKNOWN_CA,
+                    rustls::CertificateError::UnknownRevocationStatus => {
+                        SslAlert::CERTIFICATE_UNKNOWN
+                    }
+                    rustls::CertificateError::BadSignature => SslAlert::BAD_CERTIFICATE,
+                    rustls::CertificateError::NotValidForName => SslAlert::CERTIFICATE_UNKNOWN,
+                    rustls::CertificateError::InvalidPurpose => SslAlert::CERTIFICATE_UNKNOWN,
+                    rustls::CertificateError::ApplicationVerificationFailure => {
+                        SslAlert::INTERNAL_ERROR
+                    }
+                    rustls::CertificateError::Other(_) => SslAlert::CERTIFICATE_UNKNOWN,
+
+                    // CertificateError is marked non_exhaustive, so we also have to have an explicit fallback:
+                    _ => SslAlert::CERTIFICATE_UNKNOWN,
+                },
+                _ => SslAlert::BAD_CERTIFICATE,
+            })
+        })?;
 
         Ok(())
     });
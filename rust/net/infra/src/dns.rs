@@ -9,7 +9,6 @@ use std::str::FromStr as _;
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
-use futures_util::{FutureExt as _, StreamExt as _};
 use oneshot_broadcast::Sender;
 use tokio::time::Instant;
 
@@ -19,7 +18,7 @@ use crate::dns::dns_errors::Error;
 use crate::dns::dns_lookup::{DnsLookup, DnsLookupRequest, StaticDnsMap, SystemDnsLookup};
 use crate::dns::dns_transport_doh::{DohTransportConnectorFactory, CLOUDFLARE_IPS};
 use crate::dns::dns_types::ResourceType;
-use crate::dns::dns_utils::log_safe_domain;
+pub use crate::dns::dns_utils::log_safe_domain;
 use crate::dns::lookup_result::LookupResult;
 use crate::host::Host;
 use crate::route::{
@@ -45,6 +44,11 @@ pub type Result<T> = std::result::Result<T, Error>;
 struct DnsResolverState {
     /// Controls if lookup results will contain IPv6 entries.
     ipv6_enabled: bool,
+    /// Overrides the order in which lookup options are attempted, as indexes
+    /// into [`DnsResolver::lookup_options`].
+    ///
+    /// `None` means the options are tried in the order they were provided.
+    strategy_order: Option<Vec<usize>>,
     in_flight_lookups: HashMap<String, Receiver<Result<LookupResult>>>,
 }
 
@@ -52,6 +56,7 @@ impl std::fmt::Debug for DnsResolverState {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("DnsResolverState")
             .field("ipv6_enabled", &self.ipv6_enabled)
+            .field("strategy_order", &self.strategy_order)
             .field("in_flight_lookups", &self.in_flight_lookups.keys())
             .finish()
     }
@@ -61,6 +66,7 @@ impl Default for DnsResolverState {
     fn default() -> Self {
         Self {
             ipv6_enabled: true,
+            strategy_order: None,
             in_flight_lookups: Default::default(),
         }
     }
@@ -95,7 +101,8 @@ pub fn build_custom_resolver_cloudflare_doh(
                 fragment: TlsRouteFragment {
                     sni: host,
                     root_certs: RootCertificates::Native,
-                    alpn: Some(Alpn::Http2),
+                    alpn: Alpn::Http2.into(),
+                    ech_config_list: None,
                 },
                 inner: TcpRoute {
                     address: ip_addr,
@@ -128,6 +135,29 @@ impl DnsResolver {
         Self::new_with_static_fallback(HashMap::new())
     }
 
+    /// Creates a DNS resolver that consults `backup` only when `primary` fails.
+    ///
+    /// Each strategy is paired with the timeout after which it's given up on.
+    /// The backup lookup is never started while the primary one is still
+    /// running, and isn't started at all if the primary succeeds. This matters
+    /// for privacy when the backup is something like a DNS-over-HTTPS
+    /// provider: the hostnames being looked up are only revealed to a third
+    /// party when the system resolver couldn't answer.
+    pub fn new_sequential_fallback(
+        primary: (Box<dyn DnsLookup>, Duration),
+        backup: (Box<dyn DnsLookup>, Duration),
+    ) -> Self {
+        let lookup_options = [primary, backup].map(|(lookup, timeout_after)| LookupOption {
+            lookup,
+            timeout_after,
+        });
+
+        DnsResolver {
+            lookup_options: lookup_options.into(),
+            state: Default::default(),
+        }
+    }
+
     /// Creates a DNS resolver that will only use a provided static map
     /// to resolve DNS lookups
     #[cfg_attr(feature = "test-util", visibility::make(pub))]
@@ -175,7 +205,32 @@ impl DnsResolver {
         }
     }
 
+    /// Changes the order in which the resolver's strategies are attempted.
+    ///
+    /// `order` lists each strategy by its position in the list the resolver
+    /// was created with, most preferred first. Lookups that are already in
+    /// flight pick their next strategy from the new order, skipping any they
+    /// have already tried. The original order is restored on the next
+    /// [network change](Self::on_network_change).
+    ///
+    /// # Panics
+    ///
+    /// Panics if `order` isn't a permutation of the strategy positions.
+    pub fn reorder_strategies(&self, order: &[usize]) {
+        let mut sorted = order.to_vec();
+        sorted.sort_unstable();
+        assert!(
+            sorted.iter().copied().eq(0..self.lookup_options.len()),
+            "strategy order {order:?} must list each of the {} strategies exactly once",
+            self.lookup_options.len()
+        );
+
+        let mut guard = self.state.lock().expect("not poisoned");
+        guard.strategy_order = Some(order.to_vec());
+    }
+
     pub fn on_network_change(&self, now: Instant) {
+        self.state.lock().expect("not poisoned").strategy_order = None;
         for option in &self.lookup_options[..] {
             option.lookup.on_network_change(now);
         }
@@ -237,13 +292,28 @@ impl DnsResolver {
                 ipv6_enabled,
             };
 
-            let successful_lookups = futures_util::stream::iter(lookup_options.iter())
-                .filter_map(|lookup_option| lookup_option.attempt(request.clone()).map(Result::ok));
-            let mut perform_lookups = std::pin::pin!(successful_lookups);
+            // Options are attempted strictly one after another, so a later
+            // option never sees the request unless every earlier one failed.
+            // The order is checked again before each attempt in case it was
+            // changed while the previous one was running.
+            let mut attempted = vec![false; lookup_options.len()];
+            let next_option = |attempted: &[bool]| {
+                let guard = state.lock().expect("not poisoned");
+                match &guard.strategy_order {
+                    Some(order) => order.iter().copied().find(|&i| !attempted[i]),
+                    None => attempted.iter().position(|&done| !done),
+                }
+            };
+            let mut found = None;
+            while let Some(index) = next_option(&attempted) {
+                attempted[index] = true;
+                if let Ok(res) = lookup_options[index].attempt(request.clone()).await {
+                    found = Some(res);
+                    break;
+                }
+            }
 
-            let result = perform_lookups
-                .next()
-                .await
+            let result = found
                 .ok_or(Error::LookupFailed)
                 .and_then(|res| match ipv6_enabled {
                     true => Ok(res),
@@ -509,6 +579,82 @@ mod test {
         );
     }
 
+    #[tokio::test(start_paused = true)]
+    async fn test_sequential_fallback_not_consulted_on_success() {
+        let primary_lookup = TestLookup::with_custom_response(Duration::from_secs(1), IPV6);
+        let backup_lookup = TestLookup::with_custom_response(Duration::ZERO, IPV4);
+        let backup_log = backup_lookup.clone();
+
+        let resolver = DnsResolver::new_sequential_fallback(
+            (primary_lookup, ATTEMPT_TIMEOUT * 2),
+            (backup_lookup, ATTEMPT_TIMEOUT),
+        );
+
+        let result = resolver.lookup_ip(CUSTOM_DOMAIN).await.expect("success");
+        assert_eq!(result.ipv6, vec![IPV6]);
+        assert_empty!(backup_log.logged_requests());
+
+        let result = resolver.lookup_ip(IPV4_ONLY_DOMAIN).await.expect("success");
+        assert_eq!(result.ipv4, vec![IPV4]);
+        assert_empty!(backup_log.logged_requests());
+
+        let result = resolver.lookup_ip(FALLBACK_ONLY_DOMAIN).await;
+        assert_matches!(result, Err(Error::LookupFailed));
+        assert_eq!(backup_log.logged_requests().len(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_reordered_strategies_consulted_in_new_order() {
+        let system_lookup = TestLookup::with_custom_response(Duration::ZERO, IPV4);
+        let doh_lookup = TestLookup::with_custom_response(Duration::ZERO, IPV6);
+        let system_log = system_lookup.clone();
+        let doh_log = doh_lookup.clone();
+
+        let resolver = DnsResolver::new_custom(vec![
+            (system_lookup, ATTEMPT_TIMEOUT),
+            (doh_lookup, ATTEMPT_TIMEOUT),
+        ]);
+        resolver.reorder_strategies(&[1, 0]);
+
+        let result = resolver.lookup_ip(CUSTOM_DOMAIN).await.expect("success");
+        assert_eq!(result.ipv6, vec![IPV6]);
+        assert_empty!(system_log.logged_requests());
+        assert_eq!(doh_log.logged_requests().len(), 1);
+
+        // A network change goes back to the original order.
+        resolver.on_network_change(Instant::now());
+        let result = resolver.lookup_ip(CUSTOM_DOMAIN).await.expect("success");
+        assert_eq!(result.ipv4, vec![IPV4]);
+        assert_eq!(system_log.logged_requests().len(), 1);
+        assert_eq!(doh_log.logged_requests().len(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_in_flight_lookup_uses_new_order_for_next_attempt() {
+        let failing_lookup = TestLookup::standard_responses(ATTEMPT_TIMEOUT / 2);
+        let second_lookup = TestLookup::with_custom_response(Duration::ZERO, IPV4);
+        let third_lookup = TestLookup::with_custom_response(Duration::ZERO, IPV6);
+        let second_log = second_lookup.clone();
+
+        let resolver = DnsResolver::new_custom(vec![
+            (failing_lookup, ATTEMPT_TIMEOUT),
+            (second_lookup, ATTEMPT_TIMEOUT),
+            (third_lookup, ATTEMPT_TIMEOUT),
+        ]);
+
+        let resolver_clone = resolver.clone();
+        let lookup = tokio::spawn(async move { resolver_clone.lookup_ip(CUSTOM_DOMAIN).await });
+
+        // Reorder while the first strategy is still running; it shouldn't be
+        // retried, but the next attempt should follow the new order.
+        tokio::time::sleep(ATTEMPT_TIMEOUT / 4).await;
+        resolver.reorder_strategies(&[2, 0, 1]);
+
+        let result = lookup.await.expect("joined").expect("success");
+        assert_eq!(result.ipv6, vec![IPV6]);
+        assert_empty!(second_log.logged_requests());
+    }
+
     #[tokio::test]
     async fn test_dns_lookup_ipv6_disabled() {
         let static_dns_map =
@@ -22,7 +22,10 @@ use crate::dns::dns_lookup::DnsLookupRequest;
 use crate::dns::dns_types::Expiring;
 use crate::dns::dns_utils::log_safe_domain;
 use crate::dns::lookup_result::LookupResult;
-use crate::route::{ConnectionOutcomeParams, ConnectionOutcomes, ConnectorFactory, ResolvedRoute};
+use crate::route::{
+    AgedOutSuccessOrdering, ConnectionOutcomeParams, ConnectionOutcomes, ConnectorFactory,
+    ResolvedRoute,
+};
 use crate::timeouts::{DNS_CALL_BACKGROUND_TIMEOUT, DNS_RESOLUTION_DELAY};
 use crate::utils::future::results_within_interval;
 use crate::{dns, DnsSource};
@@ -84,7 +87,10 @@ const DNS_CONNECTION_COOLDOWN_CONFIG: ConnectionOutcomeParams = ConnectionOutcom
     cooldown_growth_factor: 10.0,
     max_count: 5,
     max_delay: Duration::from_secs(30),
+    max_entries: None,
     count_growth_factor: 10.0,
+    aged_out_successes: AgedOutSuccessOrdering::Unranked,
+    jitter_fraction: 0.0,
 };
 
 /// A resolver that combines the logic of retrieving results of the DNS queries
@@ -179,7 +185,10 @@ where
         let transport = result.map_err(|e| match e {
             crate::route::ConnectError::NoResolvedRoutes => dns::DnsError::TransportRestricted,
             crate::route::ConnectError::AllAttemptsFailed
-            | crate::route::ConnectError::FatalConnect(_) => dns::DnsError::TransportFailure,
+            | crate::route::ConnectError::FatalConnect(_)
+            | crate::route::ConnectError::UnstableNetwork
+            | crate::route::ConnectError::Cancelled
+            | crate::route::ConnectError::Throttled => dns::DnsError::TransportFailure,
         })?;
 
         let (ipv4_res_rx, ipv6_res_rx) = self.send_dns_queries(transport, request);
@@ -542,6 +551,76 @@ pub(crate) mod test {
         }
     }
 
+    /// A response for [`TestDnsTransportWithScriptedResponses`] to send, along with how long after
+    /// the queries are sent it should arrive.
+    #[derive(Clone, Debug)]
+    pub(crate) enum ScriptedResponse {
+        Ipv4(Duration, &'static [Ipv4Addr]),
+        Ipv6(Duration, &'static [Ipv6Addr]),
+    }
+
+    /// A transport that answers every query with the same records, in exactly the order given.
+    ///
+    /// Unlike [`TestDnsTransportWithResponses`], the responses don't have to be sent by hand,
+    /// which makes it easy to control how A and AAAA records are mixed and how far apart they
+    /// arrive. Responses are sent in sequence, so one scheduled earlier than the response before
+    /// it will arrive right after that one instead.
+    #[derive(Clone, Debug)]
+    pub(crate) struct TestDnsTransportWithScriptedResponses(Arc<[ScriptedResponse]>);
+
+    impl TestDnsTransportWithScriptedResponses {
+        pub(crate) fn custom_dns_resolver(
+            responses: impl IntoIterator<Item = ScriptedResponse>,
+        ) -> CustomDnsResolver<IpAddr, MakeConnectorByCloning<Self>> {
+            CustomDnsResolver::new(
+                vec![DNS_SERVER_IP],
+                MakeConnectorByCloning(Self(responses.into_iter().collect())),
+            )
+        }
+    }
+
+    impl Connector<IpAddr, ()> for TestDnsTransportWithScriptedResponses {
+        type Connection = Self;
+        type Error = std::convert::Infallible;
+
+        fn connect_over(
+            &self,
+            _over: (),
+            _route: IpAddr,
+            _log_tag: Arc<str>,
+        ) -> impl Future<Output = Result<Self::Connection, Self::Error>> + Send {
+            std::future::ready(Ok(self.clone()))
+        }
+    }
+
+    impl DnsTransport for TestDnsTransportWithScriptedResponses {
+        const SOURCE: DnsSource = DnsSource::Test;
+
+        fn send_queries(
+            self,
+            _request: DnsLookupRequest,
+        ) -> impl Future<
+            Output = dns::Result<impl Stream<Item = dns::Result<DnsQueryResult>> + Send + 'static>,
+        > + Send {
+            let start = Instant::now();
+            let responses = self.0.iter().cloned().collect::<Vec<_>>();
+            std::future::ready(Ok(futures_util::stream::iter(responses).then(
+                move |response| async move {
+                    match response {
+                        ScriptedResponse::Ipv4(delay, data) => {
+                            tokio::time::sleep_until(start + delay).await;
+                            ok_query_result_ipv4(NORMAL_TTL, data)
+                        }
+                        ScriptedResponse::Ipv6(delay, data) => {
+                            tokio::time::sleep_until(start + delay).await;
+                            ok_query_result_ipv6(NORMAL_TTL, data)
+                        }
+                    }
+                },
+            )))
+        }
+    }
+
     type TestDnsTransportWithOneResponse = TestDnsTransportWithResponses<1>;
     type TestDnsTransportWithTwoResponses = TestDnsTransportWithResponses<2>;
     type TestDnsTransportWithThreeResponses = TestDnsTransportWithResponses<3>;
@@ -644,6 +723,42 @@ pub(crate) mod test {
         assert_lookup_result_content_equal(&result_2.unwrap(), &[], IP_V6_LIST_1);
     }
 
+    #[test_case(
+        [
+            ScriptedResponse::Ipv6(Duration::ZERO, IP_V6_LIST_1),
+            ScriptedResponse::Ipv4(DNS_RESOLUTION_DELAY / 2, IP_V4_LIST_1),
+        ];
+        "AAAA then A"
+    )]
+    #[test_case(
+        [
+            ScriptedResponse::Ipv4(Duration::ZERO, IP_V4_LIST_1),
+            ScriptedResponse::Ipv6(DNS_RESOLUTION_DELAY / 2, IP_V6_LIST_1),
+        ];
+        "A then AAAA"
+    )]
+    #[test_case(
+        [
+            ScriptedResponse::Ipv4(Duration::ZERO, IP_V4_LIST_1),
+            ScriptedResponse::Ipv6(Duration::ZERO, IP_V6_LIST_1),
+        ];
+        "A and AAAA together"
+    )]
+    #[tokio::test(start_paused = true)]
+    async fn ipv6_preferred_regardless_of_arrival_order(responses: [ScriptedResponse; 2]) {
+        let resolver = TestDnsTransportWithScriptedResponses::custom_dns_resolver(responses);
+        let result = resolver.resolve(test_request()).await.expect("resolved");
+        assert_eq!(
+            result.iter().collect::<Vec<_>>(),
+            [
+                IpAddr::V6(IP_V6_LIST_1[0]),
+                IpAddr::V4(IP_V4_LIST_1[0]),
+                IpAddr::V6(IP_V6_LIST_1[1]),
+                IpAddr::V4(IP_V4_LIST_1[1]),
+            ]
+        );
+    }
+
     #[tokio::test(start_paused = true)]
     async fn works_correctly_if_transport_only_returns_one_response() {
         let resolver = TestDnsTransportWithOneResponse::custom_dns_resolver(|_, _, txs| {
@@ -58,7 +58,7 @@ impl Default for DohTransportConnector {
         Self {
             transport_connector: VariableTlsTimeoutConnector::new(
                 ThrottlingConnector::new(crate::tcp_ssl::StatelessTls, 1),
-                crate::tcp_ssl::StatelessTcp,
+                crate::tcp_ssl::StatelessTcp::default(),
                 MIN_TLS_HANDSHAKE_TIMEOUT,
             ),
         }
@@ -5,7 +5,9 @@
 
 const SIGNAL_DOMAIN_SUFFIX: &str = ".signal.org";
 
-pub(crate) fn log_safe_domain(domain: &str) -> &str {
+/// Returns `domain` if it's safe to log (a Signal domain or localhost), or a
+/// placeholder otherwise.
+pub fn log_safe_domain(domain: &str) -> &str {
     match domain {
         "localhost" => domain,
         d if d.ends_with(SIGNAL_DOMAIN_SUFFIX) => d,
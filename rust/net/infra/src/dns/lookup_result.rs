@@ -62,7 +62,7 @@ impl LookupResult {
         self.into_iter()
     }
 
-    pub(crate) fn source(&self) -> DnsSource {
+    pub fn source(&self) -> DnsSource {
         self.source
     }
 
@@ -4,11 +4,17 @@
 //
 
 use std::fmt::Display;
-use std::time::Duration;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
+use boring_signal::asn1::{Asn1Time, Asn1TimeRef, TimeDiff};
+use boring_signal::ssl::SslRef;
+use boring_signal::x509::X509VerifyError;
 use http::{HeaderName, HeaderValue};
 use tokio_boring_signal::HandshakeError;
 
+use crate::certs::CertificateRejection;
+use crate::route::ConnectionOutcomeParams;
 use crate::{certs, AsHttpHeader};
 
 pub trait LogSafeDisplay: Display {}
@@ -38,6 +44,20 @@ pub enum TransportConnectError {
     CertError,
     /// Failed to establish SSL connection: {0}
     SslFailedHandshake(FailedHandshakeReason),
+    /// Server certificate has expired; {0}
+    CertificateExpired(CertificateValidity),
+    /// Server certificate is not yet valid; {0}
+    CertificateNotYetValid(CertificateValidity),
+    /// Server certificate is not trusted
+    CertificateUntrusted,
+    /// Server rejected Encrypted Client Hello
+    EchRejected {
+        /// The ECHConfigList the server sent to use instead, if any.
+        ///
+        /// If this is `None`, the server has securely disabled ECH and the
+        /// connection should be retried without it.
+        retry_configs: Option<Arc<[u8]>>,
+    },
     /// Proxy handshake failed
     ProxyProtocol,
     /// Abort due to local error
@@ -74,6 +94,84 @@ impl FailedHandshakeReason {
     };
 }
 
+/// The validity window of a certificate that was rejected.
+///
+/// An "expired" or "not yet valid" certificate is often a sign that the local
+/// clock is wrong, so this is reported to let callers suggest a fix.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct CertificateValidity {
+    pub not_before: SystemTime,
+    pub not_after: SystemTime,
+}
+
+impl Display for CertificateValidity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let Self {
+            not_before,
+            not_after,
+        } = self;
+        write!(
+            f,
+            "valid from {} until {}",
+            httpdate::fmt_http_date(*not_before),
+            httpdate::fmt_http_date(*not_after)
+        )
+    }
+}
+
+impl LogSafeDisplay for CertificateValidity {}
+
+impl CertificateValidity {
+    fn of_peer_certificate(ssl: &SslRef) -> Option<Self> {
+        let certificate = ssl.peer_certificate()?;
+        Some(Self {
+            not_before: asn1_to_system_time(certificate.not_before())?,
+            not_after: asn1_to_system_time(certificate.not_after())?,
+        })
+    }
+}
+
+fn asn1_to_system_time(time: &Asn1TimeRef) -> Option<SystemTime> {
+    let TimeDiff { days, secs } = Asn1Time::from_unix(0).ok()?.diff(time).ok()?;
+    let since_epoch = i64::from(days) * 24 * 60 * 60 + i64::from(secs);
+    match u64::try_from(since_epoch) {
+        Ok(since_epoch) => UNIX_EPOCH.checked_add(Duration::from_secs(since_epoch)),
+        Err(_) => UNIX_EPOCH.checked_sub(Duration::from_secs(since_epoch.unsigned_abs())),
+    }
+}
+
+impl TransportConnectError {
+    /// Checks whether a failed handshake was caused by the server's certificate
+    /// being rejected.
+    ///
+    /// Certificate verification can go through either BoringSSL or the
+    /// platform verifier (see [`certs::RootCertificates`]). BoringSSL reports
+    /// the X509 verification error directly; for the platform verifier, the
+    /// reason it gave is saved on the connection (see
+    /// [`certs::platform_verifier_rejection`]). Only a failure that is actually
+    /// due to the validity window is reported as expired or not yet valid.
+    fn from_certificate_verification(ssl: &SslRef) -> Option<Self> {
+        let failure = match ssl.verify_result() {
+            Ok(()) => return None,
+            Err(e) if e == X509VerifyError::CERT_HAS_EXPIRED => CertificateRejection::Expired,
+            Err(e) if e == X509VerifyError::CERT_NOT_YET_VALID => CertificateRejection::NotYetValid,
+            Err(e) if e == X509VerifyError::APPLICATION_VERIFICATION => {
+                certs::platform_verifier_rejection(ssl).unwrap_or(CertificateRejection::Other)
+            }
+            Err(_) => CertificateRejection::Other,
+        };
+        let validity = CertificateValidity::of_peer_certificate(ssl);
+        Some(match (failure, validity) {
+            (CertificateRejection::Expired, Some(validity)) => Self::CertificateExpired(validity),
+            (CertificateRejection::NotYetValid, Some(validity)) => {
+                Self::CertificateNotYetValid(validity)
+            }
+            (CertificateRejection::Expired | CertificateRejection::NotYetValid, None)
+            | (CertificateRejection::Other, _) => Self::CertificateUntrusted,
+        })
+    }
+}
+
 /// Error type for TLS handshake timeouts
 #[derive(Debug, Clone, Copy, thiserror::Error)]
 #[error("TLS handshake timed out")]
@@ -112,6 +210,59 @@ impl RetryLater {
     pub fn duration(&self) -> Duration {
         Duration::from_secs(self.retry_after_seconds.into())
     }
+
+    /// Projects when the next `count` retries could be made if each one is
+    /// throttled in turn.
+    ///
+    /// The first retry can happen after the server's requested delay. Each
+    /// later retry waits at least that long again, or longer if `params`
+    /// calls for more backoff after that many consecutive failures. This is
+    /// only an estimate for display; the server may well ask for a different
+    /// delay next time.
+    pub fn projected_schedule(&self, params: &ConnectionOutcomeParams, count: u8) -> RetrySchedule {
+        let retry_after = self.duration();
+        let mut offset = Duration::ZERO;
+        RetrySchedule(
+            (0..count)
+                .map(|previous_retries| {
+                    let wait = params
+                        .compute_delay(Duration::ZERO, previous_retries)
+                        .max(retry_after);
+                    offset = offset.saturating_add(wait);
+                    offset
+                })
+                .collect(),
+        )
+    }
+}
+
+/// Retry times projected from a [`RetryLater`].
+///
+/// See [`RetryLater::projected_schedule`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RetrySchedule(Vec<Duration>);
+
+impl RetrySchedule {
+    /// The time of each retry, measured from when the [`RetryLater`] was
+    /// received.
+    pub fn offsets(&self) -> &[Duration] {
+        &self.0
+    }
+}
+
+/// Formats as, e.g., "earliest retry in 10s, then in 40s".
+impl Display for RetrySchedule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut offsets = self.0.iter();
+        let Some(first) = offsets.next() else {
+            return Ok(());
+        };
+        write!(f, "earliest retry in {first:?}")?;
+        for offset in offsets {
+            write!(f, ", then in {offset:?}")?;
+        }
+        Ok(())
+    }
 }
 
 impl AsHttpHeader for RetryLater {
@@ -136,6 +287,21 @@ impl From<certs::Error> for TransportConnectError {
 
 impl<S> From<HandshakeError<S>> for TransportConnectError {
     fn from(error: HandshakeError<S>) -> Self {
+        if let Some(certificate_error) = error.ssl().and_then(Self::from_certificate_verification) {
+            log::debug!("handshake error: {error}");
+            return certificate_error;
+        }
+        // BoringSSL only overrides the name to verify when the server rejected
+        // the ECH offer and the handshake continued with the public name.
+        if let Some(ssl) = error
+            .ssl()
+            .filter(|ssl| ssl.get_ech_name_override().is_some())
+        {
+            log::debug!("handshake error: {error}");
+            return Self::EchRejected {
+                retry_configs: ssl.get_ech_retry_configs().map(Arc::from),
+            };
+        }
         Self::SslFailedHandshake(FailedHandshakeReason::from(error))
     }
 }
@@ -149,6 +315,10 @@ impl From<TransportConnectError> for std::io::Error {
             TransportConnectError::SslFailedHandshake(_)
             | TransportConnectError::SslError(_)
             | TransportConnectError::CertError
+            | TransportConnectError::CertificateExpired(_)
+            | TransportConnectError::CertificateNotYetValid(_)
+            | TransportConnectError::CertificateUntrusted
+            | TransportConnectError::EchRejected { .. }
             | TransportConnectError::ProxyProtocol => ErrorKind::InvalidData,
             TransportConnectError::DnsError => ErrorKind::NotFound,
             TransportConnectError::ClientAbort => ErrorKind::ConnectionAborted,
@@ -162,3 +332,84 @@ impl From<TlsHandshakeTimeout> for TransportConnectError {
         Self::SslFailedHandshake(FailedHandshakeReason::TIMED_OUT)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use test_case::test_case;
+
+    use super::*;
+    use crate::route::AgedOutSuccessOrdering;
+
+    /// Backoff that goes straight to `max_delay` after the first failure, so
+    /// that the expected values are exact.
+    const ONE_STEP_PARAMS: ConnectionOutcomeParams = ConnectionOutcomeParams {
+        age_cutoff: Duration::from_secs(60),
+        cooldown_growth_factor: 1.5,
+        count_growth_factor: 10.0,
+        max_count: 1,
+        max_delay: Duration::from_secs(30),
+        max_entries: None,
+        aged_out_successes: AgedOutSuccessOrdering::Unranked,
+        jitter_fraction: 0.0,
+    };
+
+    #[test_case(0, &[0, 30, 60]; "no server delay")]
+    #[test_case(10, &[10, 40, 70]; "backoff longer than server delay")]
+    #[test_case(60, &[60, 120, 180]; "server delay longer than backoff")]
+    fn projected_schedule(retry_after_seconds: u32, expected_seconds: &[u64]) {
+        let schedule = RetryLater {
+            retry_after_seconds,
+        }
+        .projected_schedule(&ONE_STEP_PARAMS, 3);
+        assert_eq!(
+            schedule.offsets(),
+            expected_seconds
+                .iter()
+                .copied()
+                .map(Duration::from_secs)
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn projected_schedule_grows_with_backoff() {
+        let params = ConnectionOutcomeParams {
+            max_count: 5,
+            ..ONE_STEP_PARAMS
+        };
+        let schedule = RetryLater {
+            retry_after_seconds: 1,
+        }
+        .projected_schedule(&params, 7);
+
+        let waits = std::iter::once(Duration::ZERO)
+            .chain(schedule.offsets().iter().copied())
+            .collect::<Vec<_>>()
+            .windows(2)
+            .map(|pair| pair[1] - pair[0])
+            .collect::<Vec<_>>();
+        assert_eq!(waits[0], Duration::from_secs(1));
+        assert!(waits.windows(2).all(|pair| pair[0] <= pair[1]), "{waits:?}");
+        assert_eq!(waits[5..], [params.max_delay; 2]);
+    }
+
+    #[test]
+    fn retry_schedule_display() {
+        let schedule = RetryLater {
+            retry_after_seconds: 10,
+        }
+        .projected_schedule(&ONE_STEP_PARAMS, 3);
+        assert_eq!(
+            schedule.to_string(),
+            "earliest retry in 10s, then in 40s, then in 70s"
+        );
+        assert_eq!(
+            RetryLater {
+                retry_after_seconds: 10
+            }
+            .projected_schedule(&ONE_STEP_PARAMS, 0)
+            .to_string(),
+            ""
+        );
+    }
+}
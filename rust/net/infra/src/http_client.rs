@@ -208,10 +208,11 @@ mod test {
     use super::*;
     use crate::host::Host;
     use crate::route::{
-        ConnectError, ConnectionOutcomeParams, ConnectionOutcomes, TcpRoute, ThrottlingConnector,
-        TlsRoute, TlsRouteFragment,
+        AgedOutSuccessOrdering, ConnectError, ConnectionOutcomeParams, ConnectionOutcomes,
+        TcpRoute, ThrottlingConnector, TlsRoute, TlsRouteFragment,
     };
     use crate::tcp_ssl::testutil::{SERVER_CERTIFICATE, SERVER_HOSTNAME};
+    use crate::AlpnList;
 
     const FAKE_RESPONSE: &str = "RESPONSE";
     const FAKE_RESPONSE_HEADER: (HeaderName, HeaderValue) = (
@@ -266,6 +267,9 @@ mod test {
             count_growth_factor: 10.0,
             max_count: MAX_COUNT,
             max_delay: MAX_DELAY,
+            max_entries: None,
+            aged_out_successes: AgedOutSuccessOrdering::Unranked,
+            jitter_fraction: 0.0,
         })
         .into()
     }
@@ -282,7 +286,7 @@ mod test {
         let outcome_record_snapshot = outcome_record.read().await.clone();
         let tls_connector = crate::route::ComposedConnector::new(
             ThrottlingConnector::new(crate::tcp_ssl::StatelessTls, 1),
-            crate::tcp_ssl::StatelessTcp,
+            crate::tcp_ssl::StatelessTcp::default(),
         );
         let connector = Http2Connector {
             inner: tls_connector,
@@ -315,9 +319,11 @@ mod test {
             .apply_outcome_updates(updates.outcomes, updates.finished_at);
 
         result.map_err(|e| match e {
-            ConnectError::AllAttemptsFailed | ConnectError::NoResolvedRoutes => {
-                HttpError::SslHandshakeFailed
-            }
+            ConnectError::AllAttemptsFailed
+            | ConnectError::NoResolvedRoutes
+            | ConnectError::UnstableNetwork
+            | ConnectError::Cancelled
+            | ConnectError::Throttled => HttpError::SslHandshakeFailed,
             ConnectError::FatalConnect(e) => e,
         })
     }
@@ -346,7 +352,8 @@ mod test {
                         root_certs: crate::certs::RootCertificates::FromDer(Cow::Borrowed(
                             SERVER_CERTIFICATE.cert.der(),
                         )),
-                        alpn: None,
+                        alpn: AlpnList::default(),
+                        ech_config_list: None,
                     },
                     inner: TcpRoute {
                         address: Ipv6Addr::LOCALHOST.into(),
@@ -422,7 +429,8 @@ mod test {
                         root_certs: crate::certs::RootCertificates::FromDer(Cow::Borrowed(
                             SERVER_CERTIFICATE.cert.der(),
                         )),
-                        alpn: None,
+                        alpn: AlpnList::default(),
+                        ech_config_list: None,
                     },
                     inner: TcpRoute {
                         address: Ipv6Addr::LOCALHOST.into(),
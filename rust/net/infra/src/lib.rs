@@ -196,6 +196,9 @@ pub struct TransportInfo {
 
     /// The local port number for the connection.
     pub local_port: u16,
+
+    /// The protocol selected by the server via TLS ALPN, if any.
+    pub negotiated_alpn: Option<Alpn>,
 }
 
 /// An established connection.
@@ -226,7 +229,9 @@ pub enum DnsSource {
 }
 
 /// Type of the route used for the connection.
-#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, strum::Display, strum::IntoStaticStr)]
+#[derive(
+    Copy, Clone, Debug, Eq, PartialEq, Hash, strum::Display, strum::EnumString, strum::IntoStaticStr,
+)]
 #[strum(serialize_all = "lowercase")]
 pub enum RouteType {
     /// Direct connection to the service.
@@ -338,6 +343,16 @@ pub enum Alpn {
     Http2,
 }
 
+impl Alpn {
+    /// Parses a protocol name as reported after a TLS handshake, without the
+    /// length prefix.
+    pub fn from_protocol_name(name: &[u8]) -> Option<Self> {
+        [Self::Http1_1, Self::Http2]
+            .into_iter()
+            .find(|alpn| &alpn.as_ref()[1..] == name)
+    }
+}
+
 impl AsRef<[u8]> for Alpn {
     fn as_ref(&self) -> &[u8] {
         match self {
@@ -347,6 +362,48 @@ impl AsRef<[u8]> for Alpn {
     }
 }
 
+/// An ordered list of ALPN protocols to offer, most preferred first.
+///
+/// The server picks which of these to use; the result is reported as
+/// [`TransportInfo::negotiated_alpn`]. An empty list disables ALPN.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub struct AlpnList(Vec<Alpn>);
+
+impl AlpnList {
+    pub fn new(protocols: impl IntoIterator<Item = Alpn>) -> Self {
+        Self(protocols.into_iter().collect())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Alpn> + '_ {
+        self.0.iter().copied()
+    }
+
+    /// The concatenated length-delimited wire form of the list.
+    pub fn to_wire_format(&self) -> Vec<u8> {
+        self.0
+            .iter()
+            .flat_map(|alpn| alpn.as_ref())
+            .copied()
+            .collect()
+    }
+}
+
+impl From<Alpn> for AlpnList {
+    fn from(value: Alpn) -> Self {
+        Self(vec![value])
+    }
+}
+
+impl From<Option<Alpn>> for AlpnList {
+    fn from(value: Option<Alpn>) -> Self {
+        Self(value.into_iter().collect())
+    }
+}
+
 pub struct EndpointConnection<C> {
     pub manager: C,
     pub config: WebSocketConfig,
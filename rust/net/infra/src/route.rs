@@ -3,6 +3,8 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 //
 
+use std::collections::BTreeMap;
+use std::future::Future;
 use std::hash::Hash;
 use std::net::IpAddr;
 use std::ops::ControlFlow;
@@ -207,6 +209,13 @@ pub enum ConnectError<E> {
     AllAttemptsFailed,
     /// An attempt to connect failed fatally.
     FatalConnect(E),
+    /// The network changed too many times during recent attempts, so no new
+    /// attempt was made.
+    UnstableNetwork,
+    /// The caller cancelled the connect before it finished.
+    Cancelled,
+    /// Too many connects were started recently, so no new attempt was made.
+    Throttled,
 }
 
 /// Recorded success and failure information from [`connect()`].
@@ -223,6 +232,32 @@ pub struct OutcomeUpdates<R> {
     pub finished_at: Instant,
 }
 
+/// Observes the progress of [`connect()`].
+pub trait ConnectObserver<R> {
+    /// Called when a connection attempt that was in progress is cancelled
+    /// without finishing.
+    fn on_route_abandoned(&self, route: &R, reason: RouteAbandonReason);
+}
+
+/// Why an in-progress connection attempt was abandoned.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RouteAbandonReason {
+    /// An attempt on a different route succeeded first.
+    OtherRouteSucceeded,
+    /// A different attempt failed fatally, ending the whole connect.
+    ///
+    /// This includes connects that were aborted because the network interface
+    /// changed.
+    FatalError,
+    /// The [`connect()`] future was dropped, e.g. because it timed out, or the
+    /// connect was cancelled (see [`connect_until_cancelled`]).
+    Cancelled,
+}
+
+impl<R> ConnectObserver<R> for () {
+    fn on_route_abandoned(&self, _route: &R, _reason: RouteAbandonReason) {}
+}
+
 /// Attempt to connect to routes from the given [`RouteProvider`].
 ///
 /// Generates the sequence of routes from the given `RouteProvider` and then
@@ -253,6 +288,86 @@ pub async fn connect<R, UR, C, Inner, FatalError>(
     Result<C::Connection, ConnectError<FatalError>>,
     OutcomeUpdates<R>,
 )
+where
+    Inner: Clone,
+    C: Connector<R, Inner>,
+    UR: ResolveHostnames<Resolved = R> + Clone + 'static,
+    R: Clone + ResolvedRoute,
+{
+    connect_with_observer(
+        route_resolver,
+        delay_policy,
+        ordered_routes,
+        resolver,
+        connector,
+        inner,
+        log_tag,
+        on_error,
+        &(),
+    )
+    .await
+}
+
+/// Like [`connect`] but reports events to the provided [`ConnectObserver`].
+#[allow(clippy::too_many_arguments)]
+pub async fn connect_with_observer<R, UR, C, Inner, FatalError>(
+    route_resolver: &RouteResolver,
+    delay_policy: impl RouteDelayPolicy<R>,
+    ordered_routes: impl Iterator<Item = UR>,
+    resolver: &impl Resolver,
+    connector: C,
+    inner: Inner,
+    log_tag: Arc<str>,
+    on_error: impl FnMut(C::Error) -> ControlFlow<FatalError>,
+    observer: &impl ConnectObserver<R>,
+) -> (
+    Result<C::Connection, ConnectError<FatalError>>,
+    OutcomeUpdates<R>,
+)
+where
+    Inner: Clone,
+    C: Connector<R, Inner>,
+    UR: ResolveHostnames<Resolved = R> + Clone + 'static,
+    R: Clone + ResolvedRoute,
+{
+    connect_until_cancelled(
+        route_resolver,
+        delay_policy,
+        ordered_routes,
+        resolver,
+        connector,
+        inner,
+        log_tag,
+        on_error,
+        observer,
+        std::future::pending(),
+    )
+    .await
+}
+
+/// Like [`connect_with_observer`] but stops early when `cancelled` resolves.
+///
+/// Unlike dropping the future, this still produces [`OutcomeUpdates`] for the
+/// attempts that finished before the cancellation, with
+/// [`ConnectError::Cancelled`] as the result. Attempts still in progress are
+/// abandoned with [`RouteAbandonReason::Cancelled`] and don't get an outcome,
+/// since they neither succeeded nor failed.
+#[allow(clippy::too_many_arguments)]
+pub async fn connect_until_cancelled<R, UR, C, Inner, FatalError>(
+    route_resolver: &RouteResolver,
+    delay_policy: impl RouteDelayPolicy<R>,
+    ordered_routes: impl Iterator<Item = UR>,
+    resolver: &impl Resolver,
+    connector: C,
+    inner: Inner,
+    log_tag: Arc<str>,
+    on_error: impl FnMut(C::Error) -> ControlFlow<FatalError>,
+    observer: &impl ConnectObserver<R>,
+    cancelled: impl Future<Output = ()>,
+) -> (
+    Result<C::Connection, ConnectError<FatalError>>,
+    OutcomeUpdates<R>,
+)
 where
     Inner: Clone,
     C: Connector<R, Inner>,
@@ -268,6 +383,8 @@ where
         inner,
         log_tag,
         on_error,
+        observer,
+        cancelled,
     )
     .await
 }
@@ -299,10 +416,13 @@ where
         inner,
         log_tag,
         on_error,
+        &(),
+        std::future::pending(),
     )
     .await
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn connect_inner<R, C, Inner, FatalError>(
     resolver_stream: impl FusedStream<Item = (ResolvedRoutes<R>, ResolveMeta)>,
     delay_policy: impl RouteDelayPolicy<R>,
@@ -310,6 +430,8 @@ async fn connect_inner<R, C, Inner, FatalError>(
     inner: Inner,
     log_tag: Arc<str>,
     mut on_error: impl FnMut(C::Error) -> ControlFlow<FatalError>,
+    observer: &impl ConnectObserver<R>,
+    cancelled: impl Future<Output = ()>,
 ) -> (
     Result<C::Connection, ConnectError<FatalError>>,
     OutcomeUpdates<R>,
@@ -328,6 +450,7 @@ where
 
     let mut sleep_until_start_next_connection = tokio::time::sleep(Duration::ZERO);
     let mut sleep_until_start_next_connection = std::pin::pin!(sleep_until_start_next_connection);
+    let mut cancelled = std::pin::pin!(cancelled);
 
     // Every N seconds, log about what we've tried and still have yet to try.
     let mut log_for_slow_connections = tokio::time::interval(Duration::from_secs(3));
@@ -343,6 +466,11 @@ where
     let mut connects_started = 0;
     let mut connects_in_progress = FuturesUnordered::new();
     let mut outcomes = Vec::new();
+    let mut abandoned_routes = ReportAbandonedOnDrop {
+        observer,
+        in_progress: BTreeMap::new(),
+        reason: RouteAbandonReason::Cancelled,
+    };
 
     #[derive(Debug)]
     enum Event<C, R> {
@@ -350,6 +478,7 @@ where
         ConnectionAttemptFinished(C),
         NextRouteAvailable(R),
         LogStatus,
+        Cancelled,
     }
 
     let outcome = loop {
@@ -385,6 +514,7 @@ where
             event = SomeOrPending::from(poll_or_wait) => event,
             c = SomeOrPending::from(next_connect_in_progress) => Event::ConnectionAttemptFinished(c),
             _ = log_for_slow_connections.tick() => Event::LogStatus,
+            () = cancelled.as_mut() => Event::Cancelled,
         };
 
         match event {
@@ -394,13 +524,18 @@ where
 
             Event::NextRouteAvailable(Some(route)) => {
                 let log_tag_for_connect = format!("{log_tag} {connects_started}").into();
+                let attempt_index = connects_started;
                 connects_started += 1;
-                connects_in_progress.push(async {
+                abandoned_routes
+                    .in_progress
+                    .insert(attempt_index, route.clone());
+                let (connector, inner) = (&connector, &inner);
+                connects_in_progress.push(async move {
                     let started = Instant::now();
                     let result = connector
                         .connect_over(inner.clone(), route.clone(), log_tag_for_connect)
                         .await;
-                    (route, result, started)
+                    (attempt_index, route, result, started)
                 });
                 poll_schedule_for_next = false;
                 most_recent_connection_start = Instant::now();
@@ -414,12 +549,14 @@ where
                 schedule.set(None);
                 poll_schedule_for_next = false;
             }
-            Event::ConnectionAttemptFinished((route, result, started)) => {
+            Event::ConnectionAttemptFinished((attempt_index, route, result, started)) => {
+                abandoned_routes.in_progress.remove(&attempt_index);
                 let make_outcome = |result| (route, AttemptOutcome { started, result });
                 match result.map_err(&mut on_error) {
                     Ok(connection) => {
                         // We've got a successful connection!
                         outcomes.push(make_outcome(Ok(())));
+                        abandoned_routes.reason = RouteAbandonReason::OtherRouteSucceeded;
                         break Ok(connection);
                     }
                     Err(ControlFlow::Continue(())) => {
@@ -431,6 +568,7 @@ where
                         // service-level error. It doesn't necessarily mean
                         // the route is bad, so don't record the
                         // unsuccessful attempt.
+                        abandoned_routes.reason = RouteAbandonReason::FatalError;
                         break Err(ConnectError::FatalConnect(fatal_err));
                     }
                 }
@@ -440,6 +578,15 @@ where
                     most_recent_connection_start + pull_next_route_delay(&connects_in_progress),
                 );
             }
+            Event::Cancelled => {
+                log::info!(
+                    "[{log_tag}] cancelled with {} connection(s) in progress",
+                    connects_in_progress.len()
+                );
+                // Attempts still in progress are reported as abandoned with
+                // the default reason, Cancelled.
+                break Err(ConnectError::Cancelled);
+            }
             Event::LogStatus => {
                 log::info!(
                     "[{log_tag}] {} connection(s) in progress after {:.2?}, {}",
@@ -454,6 +601,11 @@ where
             }
         }
     };
+
+    // Drop any lookups that are still in flight now instead of letting them
+    // linger until the caller is done with the result.
+    schedule.set(None);
+
     (
         outcome,
         OutcomeUpdates {
@@ -470,6 +622,30 @@ impl<E: std::fmt::Display> std::fmt::Display for ConnectError<E> {
             ConnectError::NoResolvedRoutes => f.write_str("no resolved routes"),
             ConnectError::AllAttemptsFailed => f.write_str("all connect attempts failed"),
             ConnectError::FatalConnect(e) => write!(f, "fatal connect error: {e}"),
+            ConnectError::UnstableNetwork => f.write_str("network changed too often to connect"),
+            ConnectError::Cancelled => f.write_str("connect was cancelled"),
+            ConnectError::Throttled => f.write_str("too many recent connects; throttled"),
+        }
+    }
+}
+
+/// Reports routes whose connection attempts are still in progress when dropped.
+struct ReportAbandonedOnDrop<'o, R, O: ConnectObserver<R>> {
+    observer: &'o O,
+    /// In-progress routes, keyed by the order in which their attempts started.
+    in_progress: BTreeMap<usize, R>,
+    reason: RouteAbandonReason,
+}
+
+impl<R, O: ConnectObserver<R>> Drop for ReportAbandonedOnDrop<'_, R, O> {
+    fn drop(&mut self) {
+        let Self {
+            observer,
+            in_progress,
+            reason,
+        } = self;
+        for route in in_progress.values() {
+            observer.on_route_abandoned(route, *reason);
         }
     }
 }
@@ -509,6 +685,7 @@ pub mod testutils {
     use std::convert::Infallible;
     use std::future::Future;
     use std::net::IpAddr;
+    use std::num::NonZeroU16;
 
     use rand::rngs::mock::StepRng;
     use rand::Rng as _;
@@ -536,6 +713,9 @@ pub mod testutils {
         fn immediate_target(&self) -> &IpAddr {
             self.0.immediate_target()
         }
+        fn immediate_port(&self) -> Option<NonZeroU16> {
+            self.0.immediate_port()
+        }
     }
 
     impl<R: Clone> RouteProvider for Vec<R> {
@@ -626,7 +806,7 @@ mod test {
     use crate::route::testutils::{FakeConnectError, FakeContext, FakeRoute};
     use crate::route::{SocksProxy, TlsProxy};
     use crate::tcp_ssl::proxy::socks;
-    use crate::{Alpn, DnsSource};
+    use crate::{Alpn, AlpnList, DnsSource};
 
     static WS_ENDPOINT: LazyLock<PathAndQuery> =
         LazyLock::new(|| PathAndQuery::from_static("/ws-path"));
@@ -660,6 +840,7 @@ mod test {
                 inner: TlsRouteProvider {
                     sni: Host::Domain("sni-name".into()),
                     certs: ROOT_CERTS.clone(),
+                    ech_config_list: None,
                     inner: DirectTcpRouteProvider {
                         dns_hostname: "target-host".into(),
                         port: TARGET_PORT,
@@ -687,7 +868,8 @@ mod test {
                         fragment: TlsRouteFragment {
                             root_certs: ROOT_CERTS.clone(),
                             sni: Host::Domain("sni-name".into()),
-                            alpn: Some(Alpn::Http1_1),
+                            alpn: Alpn::Http1_1.into(),
+                            ech_config_list: None,
                         },
                         inner: TcpRoute {
                             address: UnresolvedHost("target-host".into()),
@@ -712,7 +894,8 @@ mod test {
                         fragment: TlsRouteFragment {
                             root_certs: PROXY_ROOT_CERTS,
                             sni: Host::Domain("front-sni1".into()),
-                            alpn: Some(Alpn::Http2),
+                            alpn: Alpn::Http2.into(),
+                            ech_config_list: None,
                         },
                         inner: TcpRoute {
                             address: UnresolvedHost("front-sni1".into()),
@@ -737,7 +920,8 @@ mod test {
                         fragment: TlsRouteFragment {
                             root_certs: PROXY_ROOT_CERTS,
                             sni: Host::Domain("front-sni2".into()),
-                            alpn: Some(Alpn::Http2),
+                            alpn: Alpn::Http2.into(),
+                            ech_config_list: None,
                         },
                         inner: TcpRoute {
                             address: UnresolvedHost("front-sni2".into()),
@@ -760,6 +944,7 @@ mod test {
         let direct_provider = TlsRouteProvider {
             sni: Host::Domain("direct-sni".into()),
             certs: ROOT_CERTS.clone(),
+            ech_config_list: None,
             inner: DirectTcpRouteProvider {
                 dns_hostname: "direct-target".into(),
                 port: TARGET_PORT,
@@ -784,7 +969,8 @@ mod test {
                 fragment: TlsRouteFragment {
                     root_certs: ROOT_CERTS.clone(),
                     sni: Host::Domain("direct-sni".into()),
-                    alpn: None,
+                    alpn: AlpnList::default(),
+                    ech_config_list: None,
                 },
                 inner: ConnectionProxyRoute::Tls {
                     proxy: TlsRoute {
@@ -795,7 +981,8 @@ mod test {
                         fragment: TlsRouteFragment {
                             root_certs: PROXY_CERTS.clone(),
                             sni: Host::Domain("tls-proxy".into()),
-                            alpn: None,
+                            alpn: AlpnList::default(),
+                            ech_config_list: None,
                         },
                     },
                 },
@@ -814,6 +1001,7 @@ mod test {
         let direct_provider = TlsRouteProvider {
             sni: Host::Domain("direct-sni".into()),
             certs: ROOT_CERTS.clone(),
+            ech_config_list: None,
             inner: DirectTcpRouteProvider {
                 dns_hostname: "direct-target".into(),
                 port: TARGET_PORT,
@@ -837,7 +1025,8 @@ mod test {
             fragment: TlsRouteFragment {
                 root_certs: ROOT_CERTS.clone(),
                 sni: Host::Domain("direct-sni".into()),
-                alpn: None,
+                alpn: AlpnList::default(),
+                ech_config_list: None,
             },
             inner: ConnectionProxyRoute::Socks(SocksRoute {
                 proxy: TcpRoute {
@@ -1087,6 +1276,206 @@ mod test {
         );
     }
 
+    #[tokio::test(start_paused = true)]
+    async fn connect_cancels_outstanding_lookups_on_success() {
+        const FAST_HOST: (&str, Ipv6Addr) = ("fast", ip_addr!(v6, "3fff::1"));
+        const SLOW_HOST: &str = "slow";
+
+        let (connector, mut connection_responders) = FakeConnector::<FakeRoute<IpAddr>>::new();
+        let (resolver, mut resolution_responders) = FakeResolver::new();
+
+        let connect_task = tokio::spawn(async move {
+            connect(
+                &RouteResolver::default(),
+                NoDelay,
+                [FAST_HOST.0, SLOW_HOST]
+                    .into_iter()
+                    .map(|h| FakeRoute(UnresolvedHost::from(Arc::from(h)))),
+                &resolver,
+                connector,
+                (),
+                "test".into(),
+                |_err: FakeConnectError| ControlFlow::<Infallible>::Continue(()),
+            )
+            .await
+        });
+
+        let fast_lookup = resolution_responders.next().await.unwrap();
+        assert_eq!(fast_lookup.hostname(), FAST_HOST.0);
+        let slow_lookup = resolution_responders.next().await.unwrap();
+        assert_eq!(slow_lookup.hostname(), SLOW_HOST);
+
+        fast_lookup.respond(Ok(LookupResult::new(
+            DnsSource::Test,
+            vec![],
+            vec![FAST_HOST.1],
+        )));
+        let connection = connection_responders.next().await.unwrap();
+        assert_eq!(connection.route(), &FakeRoute(IpAddr::V6(FAST_HOST.1)));
+        connection.respond(Ok(()));
+
+        // The connect finishes without the slow lookup ever getting a response,
+        // and the lookup gets dropped along the way.
+        let (result, _updates) = connect_task.await.expect("did not panic");
+        assert_eq!(
+            result,
+            Ok(FakeConnection(FakeRoute(IpAddr::V6(FAST_HOST.1))))
+        );
+        assert!(slow_lookup.is_cancelled());
+    }
+
+    #[derive(Default)]
+    struct RecordingObserver<R>(std::sync::Mutex<Vec<(R, RouteAbandonReason)>>);
+
+    impl<R: Clone> ConnectObserver<R> for RecordingObserver<R> {
+        fn on_route_abandoned(&self, route: &R, reason: RouteAbandonReason) {
+            self.0
+                .lock()
+                .expect("not poisoned")
+                .push((route.clone(), reason));
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn connect_reports_abandoned_routes() {
+        const HOSTNAMES: &[(&str, Ipv6Addr)] = &[
+            ("A", ip_addr!(v6, "3fff::1")),
+            ("B", ip_addr!(v6, "3fff::2")),
+            ("C", ip_addr!(v6, "3fff::3")),
+        ];
+
+        let (connector, mut connection_responders) = FakeConnector::<FakeRoute<IpAddr>>::new();
+        let (resolver, mut resolution_responders) = FakeResolver::new();
+        let observer = RecordingObserver::default();
+
+        let _resolve_task = tokio::spawn(async move {
+            for (_host, addr) in HOSTNAMES {
+                let responder = resolution_responders.next().await.unwrap();
+                responder.respond(Ok(LookupResult::new(
+                    crate::DnsSource::Test,
+                    vec![],
+                    vec![*addr],
+                )));
+            }
+        });
+        let _connect_task = tokio::spawn(async move {
+            // Hold on to the first attempts without responding so they're still
+            // in progress when the last one succeeds.
+            let mut slow_attempts = Vec::new();
+            while let Some(responder) = connection_responders.next().await {
+                if responder.route().0 == IpAddr::V6(HOSTNAMES[2].1) {
+                    responder.respond(Ok(()));
+                } else {
+                    slow_attempts.push(responder);
+                }
+            }
+        });
+
+        let (result, _updates) = connect_with_observer(
+            &RouteResolver::default(),
+            NoDelay,
+            HOSTNAMES
+                .iter()
+                .map(|(h, _addr)| FakeRoute(UnresolvedHost::from(Arc::from(*h)))),
+            &resolver,
+            connector,
+            (),
+            "test".into(),
+            |_err: FakeConnectError| ControlFlow::<Infallible>::Continue(()),
+            &observer,
+        )
+        .await;
+
+        assert_eq!(
+            result,
+            Ok(FakeConnection(FakeRoute(IpAddr::V6(HOSTNAMES[2].1))))
+        );
+        assert_eq!(
+            observer.0.into_inner().expect("not poisoned"),
+            HOSTNAMES[..2]
+                .iter()
+                .map(|(_, ip)| (
+                    FakeRoute(IpAddr::V6(*ip)),
+                    RouteAbandonReason::OtherRouteSucceeded
+                ))
+                .collect_vec()
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn connect_until_cancelled_keeps_finished_outcomes() {
+        const HOSTNAMES: &[(&str, Ipv6Addr)] = &[
+            ("A", ip_addr!(v6, "3fff::1")),
+            ("B", ip_addr!(v6, "3fff::2")),
+        ];
+
+        let (connector, mut connection_responders) = FakeConnector::<FakeRoute<IpAddr>>::new();
+        let (resolver, mut resolution_responders) = FakeResolver::new();
+        let observer = RecordingObserver::default();
+        let (cancel_tx, cancel_rx) = tokio::sync::oneshot::channel();
+
+        let _resolve_task = tokio::spawn(async move {
+            for (_host, addr) in HOSTNAMES {
+                let responder = resolution_responders.next().await.unwrap();
+                responder.respond(Ok(LookupResult::new(
+                    crate::DnsSource::Test,
+                    vec![],
+                    vec![*addr],
+                )));
+            }
+        });
+        let _connect_task = tokio::spawn(async move {
+            // The first attempt fails, and the second is cancelled while it's
+            // still in progress.
+            let failing = connection_responders.next().await.unwrap();
+            assert_eq!(failing.route(), &FakeRoute(IpAddr::V6(HOSTNAMES[0].1)));
+            failing.respond(Err(FakeConnectError));
+
+            let in_progress = connection_responders.next().await.unwrap();
+            assert_eq!(in_progress.route(), &FakeRoute(IpAddr::V6(HOSTNAMES[1].1)));
+            cancel_tx.send(()).unwrap();
+            in_progress
+        });
+
+        let (result, updates) = connect_until_cancelled(
+            &RouteResolver::default(),
+            NoDelay,
+            HOSTNAMES
+                .iter()
+                .map(|(h, _addr)| FakeRoute(UnresolvedHost::from(Arc::from(*h)))),
+            &resolver,
+            connector,
+            (),
+            "test".into(),
+            |_err: FakeConnectError| ControlFlow::<Infallible>::Continue(()),
+            &observer,
+            async {
+                cancel_rx.await.expect("sent");
+            },
+        )
+        .await;
+
+        assert_eq!(result, Err(ConnectError::Cancelled));
+        assert_eq!(
+            updates
+                .outcomes
+                .into_iter()
+                .map(|(route, outcome)| (route, outcome.result))
+                .collect_vec(),
+            [(
+                FakeRoute(IpAddr::V6(HOSTNAMES[0].1)),
+                Err(UnsuccessfulOutcome)
+            )]
+        );
+        assert_eq!(
+            observer.0.into_inner().expect("not poisoned"),
+            [(
+                FakeRoute(IpAddr::V6(HOSTNAMES[1].1)),
+                RouteAbandonReason::Cancelled
+            )]
+        );
+    }
+
     #[tokio::test(start_paused = true)]
     async fn connect_interleaves_resolved_routes() {
         const HOSTNAMES: &[(&str, &[Ipv6Addr])] = &[
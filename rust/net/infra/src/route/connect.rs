@@ -23,18 +23,30 @@ pub use composed::*;
 mod direct_or_proxy;
 pub use direct_or_proxy::*;
 
+mod ech_retry;
+pub use ech_retry::*;
+
 mod interface_monitor;
 pub use interface_monitor::*;
 
 mod logging;
 pub use logging::*;
 
+mod phase_timing;
+pub use phase_timing::*;
+
 mod preconnect;
 pub use preconnect::*;
 
+mod sni;
+pub use sni::*;
+
 mod throttle;
 pub use throttle::*;
 
+mod timeout;
+pub use timeout::*;
+
 mod variable_timeout;
 pub use variable_timeout::*;
 
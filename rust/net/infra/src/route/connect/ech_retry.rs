@@ -0,0 +1,200 @@
+//
+// Copyright 2025 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+use std::future::Future;
+use std::sync::Arc;
+
+use crate::errors::TransportConnectError;
+use crate::route::{Connector, TlsRoute};
+
+/// A [`Connector`] for [`TlsRoute`]s that recovers once from the server
+/// rejecting Encrypted Client Hello.
+///
+/// When a handshake that offered ECH fails with
+/// [`TransportConnectError::EchRejected`], the connection is attempted again
+/// over the same route with the server's retry configs, as described in the
+/// [ECH spec][spec]. If the server didn't send any, it has securely disabled
+/// ECH, and the retry sends the SNI in plaintext. Only one retry is made; if
+/// that is rejected too, the error is returned as is.
+///
+/// [spec]: https://datatracker.ietf.org/doc/html/draft-ietf-tls-esni#section-6.1.6
+#[derive(Debug, Default)]
+pub struct EchRetryConnector<C> {
+    inner: C,
+}
+
+impl<C> EchRetryConnector<C> {
+    pub fn new(inner: C) -> Self {
+        Self { inner }
+    }
+
+    /// Consumes the connector and returns the wrapped one.
+    pub fn into_inner(self) -> C {
+        self.inner
+    }
+}
+
+impl<C, T, Inner> Connector<TlsRoute<T>, Inner> for EchRetryConnector<C>
+where
+    C: Connector<TlsRoute<T>, Inner, Error = TransportConnectError> + Sync,
+    T: Clone + Send,
+    Inner: Clone + Send,
+{
+    type Connection = C::Connection;
+
+    type Error = TransportConnectError;
+
+    fn connect_over(
+        &self,
+        over: Inner,
+        route: TlsRoute<T>,
+        log_tag: Arc<str>,
+    ) -> impl Future<Output = Result<Self::Connection, Self::Error>> + Send {
+        let Self { inner } = self;
+        async move {
+            let offered_ech = route.fragment.ech_config_list.is_some();
+            let mut retry_route = route.clone();
+            let result = inner
+                .connect_over(over.clone(), route, log_tag.clone())
+                .await;
+
+            let retry_configs = match result {
+                Err(TransportConnectError::EchRejected { retry_configs }) if offered_ech => {
+                    retry_configs
+                }
+                result => return result,
+            };
+
+            if retry_configs.is_some() {
+                log::info!("[{log_tag}] ECH was rejected; retrying with the server's configs");
+            } else {
+                log::info!("[{log_tag}] ECH was disabled by the server; retrying without it");
+            }
+            retry_route.fragment.ech_config_list = retry_configs;
+            inner.connect_over(over, retry_route, log_tag).await
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Mutex;
+
+    use assert_matches::assert_matches;
+    use futures_util::FutureExt as _;
+
+    use super::*;
+    use crate::certs::RootCertificates;
+    use crate::host::Host;
+    use crate::route::testutils::ConnectFn;
+    use crate::route::TlsRouteFragment;
+
+    const ORIGINAL_CONFIGS: &[u8] = b"original configs";
+    const RETRY_CONFIGS: &[u8] = b"retry configs";
+
+    fn route(ech_config_list: Option<&[u8]>) -> TlsRoute<()> {
+        TlsRoute {
+            fragment: TlsRouteFragment {
+                root_certs: RootCertificates::Native,
+                sni: Host::Domain("chat.signal.org".into()),
+                alpn: Default::default(),
+                ech_config_list: ech_config_list.map(Arc::from),
+            },
+            inner: (),
+        }
+    }
+
+    /// Connects over `route` through a fake connector that answers each
+    /// attempt with `respond`, returning the result and the ECH config list of
+    /// each attempt.
+    fn connect_recording_attempts(
+        route: TlsRoute<()>,
+        respond: impl Fn(&TlsRoute<()>) -> Result<(), TransportConnectError> + Sync,
+    ) -> (Result<(), TransportConnectError>, Vec<Option<Arc<[u8]>>>) {
+        let attempts = Mutex::new(Vec::new());
+        let connector = EchRetryConnector::new(ConnectFn(|(), route: TlsRoute<()>, _log_tag| {
+            attempts
+                .lock()
+                .expect("not poisoned")
+                .push(route.fragment.ech_config_list.clone());
+            std::future::ready(respond(&route))
+        }));
+
+        let result = connector
+            .connect_over((), route, "test".into())
+            .now_or_never()
+            .expect("completes immediately");
+        (result, attempts.into_inner().expect("not poisoned"))
+    }
+
+    #[test]
+    fn retries_with_server_configs() {
+        let (result, attempts) =
+            connect_recording_attempts(route(Some(ORIGINAL_CONFIGS)), |route| {
+                match route.fragment.ech_config_list.as_deref() {
+                    Some(ORIGINAL_CONFIGS) => Err(TransportConnectError::EchRejected {
+                        retry_configs: Some(RETRY_CONFIGS.into()),
+                    }),
+                    _ => Ok(()),
+                }
+            });
+
+        assert_matches!(result, Ok(()));
+        assert_eq!(
+            attempts,
+            [Some(ORIGINAL_CONFIGS.into()), Some(RETRY_CONFIGS.into())]
+        );
+    }
+
+    #[test]
+    fn retries_without_ech_if_server_disabled_it() {
+        let (result, attempts) =
+            connect_recording_attempts(route(Some(ORIGINAL_CONFIGS)), |route| {
+                match route.fragment.ech_config_list {
+                    Some(_) => Err(TransportConnectError::EchRejected {
+                        retry_configs: None,
+                    }),
+                    None => Ok(()),
+                }
+            });
+
+        assert_matches!(result, Ok(()));
+        assert_eq!(attempts, [Some(ORIGINAL_CONFIGS.into()), None]);
+    }
+
+    #[test]
+    fn retries_only_once() {
+        let (result, attempts) = connect_recording_attempts(route(Some(ORIGINAL_CONFIGS)), |_| {
+            Err(TransportConnectError::EchRejected {
+                retry_configs: Some(RETRY_CONFIGS.into()),
+            })
+        });
+
+        assert_matches!(result, Err(TransportConnectError::EchRejected { .. }));
+        assert_eq!(attempts.len(), 2);
+    }
+
+    #[test]
+    fn does_not_retry_other_errors() {
+        let (result, attempts) = connect_recording_attempts(route(Some(ORIGINAL_CONFIGS)), |_| {
+            Err(TransportConnectError::TcpConnectionFailed)
+        });
+
+        assert_matches!(result, Err(TransportConnectError::TcpConnectionFailed));
+        assert_eq!(attempts.len(), 1);
+    }
+
+    #[test]
+    fn does_not_retry_if_ech_was_not_offered() {
+        let (result, attempts) = connect_recording_attempts(route(None), |_| {
+            Err(TransportConnectError::EchRejected {
+                retry_configs: Some(RETRY_CONFIGS.into()),
+            })
+        });
+
+        assert_matches!(result, Err(TransportConnectError::EchRejected { .. }));
+        assert_eq!(attempts.len(), 1);
+    }
+}
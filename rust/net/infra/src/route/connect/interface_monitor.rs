@@ -5,6 +5,7 @@
 
 use std::future::Future;
 use std::net::IpAddr;
+use std::sync::Arc;
 use std::time::Duration;
 
 use futures_util::TryFutureExt as _;
@@ -72,6 +73,25 @@ impl<Inner> InterfaceMonitor<Inner> {
     }
 }
 
+impl<Inner, F> InterfaceMonitor<Inner, F> {
+    /// Like [`InterfaceMonitor::new`], but uses `get_current_interface` to detect route changes.
+    pub fn new_with_interface_source(
+        inner: Inner,
+        get_current_interface: F,
+        network_change_event: tokio::sync::watch::Receiver<()>,
+        network_change_poll_interval: Duration,
+        post_change_grace_period: Duration,
+    ) -> Self {
+        Self {
+            inner,
+            get_current_interface,
+            network_change_event,
+            network_change_poll_interval,
+            post_change_grace_period,
+        }
+    }
+}
+
 impl<R, Over, Inner, F> Connector<R, Over> for InterfaceMonitor<Inner, F>
 where
     R: Send + ResolvedRoute,
@@ -170,6 +190,37 @@ impl GetCurrentInterface for DefaultGetCurrentInterface {
     }
 }
 
+/// Where [`InterfaceMonitor`] gets the local IP used to reach a target.
+///
+/// By default this asks the OS (see [`DefaultGetCurrentInterface`]), but tests and callers that
+/// know more about the device's network setup can provide the answer explicitly instead.
+#[derive(Clone, Default)]
+pub enum LocalIpSource {
+    #[default]
+    Os,
+    Explicit(Arc<dyn Fn(IpAddr) -> IpAddr + Send + Sync>),
+}
+
+impl std::fmt::Debug for LocalIpSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Os => f.write_str("Os"),
+            Self::Explicit(_) => f.write_str("Explicit"),
+        }
+    }
+}
+
+impl GetCurrentInterface for LocalIpSource {
+    type Representation = IpAddr;
+
+    async fn get_interface_for(&self, target: IpAddr) -> Self::Representation {
+        match self {
+            Self::Os => DefaultGetCurrentInterface.get_interface_for(target).await,
+            Self::Explicit(get_local_ip) => get_local_ip(target),
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use std::convert::Infallible;
@@ -0,0 +1,199 @@
+//
+// Copyright 2025 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+use std::cell::Cell;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use crate::route::Connector;
+
+/// A part of a connection attempt whose duration can be measured with a
+/// [`PhaseTimingConnector`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ConnectPhase {
+    /// Establishing a TCP connection, directly or through a proxy.
+    Tcp,
+    /// The TLS handshake with the server.
+    Tls,
+    /// Upgrading an established connection to a websocket.
+    WebSocketUpgrade,
+}
+
+/// How long the phases of a single connection attempt took.
+///
+/// Produced by [`RecordPhasesConnector`]. Phases that weren't measured are
+/// reported as zero.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct PhaseDurations {
+    pub tcp: Duration,
+    pub tls: Duration,
+    pub websocket_upgrade: Duration,
+    /// Whether the attempt used a connection saved by a
+    /// [`PreconnectingFactory`](crate::route::PreconnectingFactory) instead
+    /// of making a new one.
+    pub preconnected: bool,
+}
+
+tokio::task_local! {
+    /// The durations for the connection attempt currently being polled.
+    static CURRENT_ATTEMPT: Cell<PhaseDurations>;
+}
+
+impl PhaseDurations {
+    /// Updates the durations for the attempt being polled, if it's being
+    /// recorded by a [`RecordPhasesConnector`].
+    pub(crate) fn update_current(update: impl FnOnce(&mut Self)) {
+        let _not_recording = CURRENT_ATTEMPT.try_with(|current| {
+            let mut durations = current.get();
+            update(&mut durations);
+            current.set(durations);
+        });
+    }
+}
+
+/// A [`Connector`] that reports the duration of each successful connect to
+/// the enclosing [`RecordPhasesConnector`], if there is one.
+///
+/// Without an enclosing `RecordPhasesConnector` this just delegates to the
+/// inner connector.
+#[derive(Debug)]
+pub struct PhaseTimingConnector<C> {
+    inner: C,
+    phase: ConnectPhase,
+}
+
+impl<C> PhaseTimingConnector<C> {
+    pub fn new(inner: C, phase: ConnectPhase) -> Self {
+        Self { inner, phase }
+    }
+
+    pub fn into_inner(self) -> C {
+        self.inner
+    }
+}
+
+impl<C, R, Inner> Connector<R, Inner> for PhaseTimingConnector<C>
+where
+    C: Connector<R, Inner> + Sync,
+    R: Send,
+    Inner: Send,
+{
+    type Connection = C::Connection;
+    type Error = C::Error;
+
+    async fn connect_over(
+        &self,
+        over: Inner,
+        route: R,
+        log_tag: Arc<str>,
+    ) -> Result<Self::Connection, Self::Error> {
+        let Self { inner, phase } = self;
+        let start = Instant::now();
+        let connection = inner.connect_over(over, route, log_tag).await?;
+        let elapsed = start.elapsed();
+        PhaseDurations::update_current(|durations| {
+            let phase_duration = match phase {
+                ConnectPhase::Tcp => &mut durations.tcp,
+                ConnectPhase::Tls => &mut durations.tls,
+                ConnectPhase::WebSocketUpgrade => &mut durations.websocket_upgrade,
+            };
+            *phase_duration += elapsed;
+        });
+        Ok(connection)
+    }
+}
+
+/// A [`Connector`] that collects the [`PhaseDurations`] reported while its
+/// inner connector runs, and produces them along with the connection.
+///
+/// Each call to [`Connector::connect_over`] is recorded separately, even if
+/// several are in progress at once on the same task.
+#[derive(Debug)]
+pub struct RecordPhasesConnector<C>(pub C);
+
+impl<C, R, Inner> Connector<R, Inner> for RecordPhasesConnector<C>
+where
+    C: Connector<R, Inner, Connection: Send> + Sync,
+    R: Send,
+    Inner: Send,
+{
+    type Connection = (C::Connection, PhaseDurations);
+    type Error = C::Error;
+
+    async fn connect_over(
+        &self,
+        over: Inner,
+        route: R,
+        log_tag: Arc<str>,
+    ) -> Result<Self::Connection, Self::Error> {
+        let Self(inner) = self;
+        CURRENT_ATTEMPT
+            .scope(Cell::default(), async {
+                let connection = inner.connect_over(over, route, log_tag).await?;
+                Ok((connection, CURRENT_ATTEMPT.with(Cell::get)))
+            })
+            .await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use assert_matches::assert_matches;
+
+    use super::*;
+    use crate::route::testutils::{ConnectFn, DummyDelayConnector};
+
+    #[tokio::test(start_paused = true)]
+    async fn records_each_attempt_separately() {
+        const TCP_DELAY: Duration = Duration::from_millis(100);
+        const TLS_DELAY: Duration = Duration::from_millis(200);
+
+        // Connects over TCP and then TLS, taking the given time for each.
+        let connector = RecordPhasesConnector(ConnectFn(
+            |(), (tcp_delay, tls_delay): (Duration, Duration), log_tag: Arc<str>| async move {
+                let tcp = PhaseTimingConnector::new(
+                    DummyDelayConnector { delay: tcp_delay },
+                    ConnectPhase::Tcp,
+                )
+                .connect_over((), (), log_tag.clone())
+                .await?;
+                PhaseTimingConnector::new(
+                    DummyDelayConnector { delay: tls_delay },
+                    ConnectPhase::Tls,
+                )
+                .connect_over(tcp, (), log_tag)
+                .await
+            },
+        ));
+
+        let (slow, fast) = tokio::join!(
+            connector.connect_over((), (TCP_DELAY * 3, TLS_DELAY), "slow".into()),
+            connector.connect_over((), (TCP_DELAY, TLS_DELAY), "fast".into()),
+        );
+
+        let (_connection, slow) = assert_matches!(slow, Ok(connection) => connection);
+        let (_connection, fast) = assert_matches!(fast, Ok(connection) => connection);
+        assert_eq!(
+            slow,
+            PhaseDurations {
+                tcp: TCP_DELAY * 3,
+                tls: TLS_DELAY,
+                websocket_upgrade: Duration::ZERO,
+                preconnected: false,
+            }
+        );
+        assert_eq!(
+            fast,
+            PhaseDurations {
+                tcp: TCP_DELAY,
+                tls: TLS_DELAY,
+                websocket_upgrade: Duration::ZERO,
+                preconnected: false,
+            }
+        );
+    }
+}
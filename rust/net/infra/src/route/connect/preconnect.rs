@@ -8,7 +8,7 @@ use std::time::Duration;
 
 use tokio::time::Instant;
 
-use super::{Connector, ConnectorFactory};
+use super::{Connector, ConnectorFactory, PhaseDurations};
 
 /// A [`ConnectorFactory`] wrapper that can be directed to save and restore a single existing
 /// connection.
@@ -23,6 +23,9 @@ use super::{Connector, ConnectorFactory};
 /// share the same saved connection state. A successful connect over a [`UsePreconnect`] route will
 /// clear the saved connection whether or not it was used, so as not to hold onto resources
 /// unnecessarily.
+///
+/// A saved connection that goes unused for longer than the factory's timeout is dropped, and
+/// anyone watching [`Self::subscribe_to_expiration`] is notified so they can preconnect again.
 pub struct PreconnectingFactory<R, F: ConnectorFactory<R>> {
     inner_factory: F,
     shared: Arc<SharedState<R, F::Connection>>,
@@ -62,12 +65,17 @@ impl<R, F: ConnectorFactory<R>> PreconnectingFactory<R, F> {
             shared: SharedState {
                 timeout,
                 saved: Default::default(),
+                expired: tokio::sync::watch::Sender::new(()),
             }
             .into(),
         }
     }
 
-    pub fn save_preconnected(&self, route: R, connection: F::Connection, established: Instant) {
+    pub fn save_preconnected(&self, route: R, connection: F::Connection, established: Instant)
+    where
+        R: Send + 'static,
+        F::Connection: Send + 'static,
+    {
         let mut saved_guard = self.shared.saved.lock().expect("not poisoned");
         if saved_guard
             .as_ref()
@@ -80,6 +88,40 @@ impl<R, F: ConnectorFactory<R>> PreconnectingFactory<R, F> {
             route,
             established,
         });
+        drop(saved_guard);
+
+        // Only hold a weak reference, so that dropping the factory doesn't have to wait for the
+        // timer to go off.
+        let shared = Arc::downgrade(&self.shared);
+        let expiration = established + self.shared.timeout;
+        tokio::spawn(async move {
+            tokio::time::sleep_until(expiration).await;
+            let Some(shared) = shared.upgrade() else {
+                return;
+            };
+            let mut saved_guard = shared.saved.lock().expect("not poisoned");
+            // If the connection was used or replaced in the meantime, there's nothing to do.
+            // (A replacement with the same `established` time expires now too, so it's fine to
+            // drop it.)
+            if saved_guard
+                .as_ref()
+                .is_some_and(|saved| saved.established == established)
+            {
+                log::debug!("saved preconnection expired");
+                *saved_guard = None;
+                drop(saved_guard);
+                shared.expired.send_replace(());
+            }
+        });
+    }
+
+    /// Returns a receiver that is notified whenever a saved connection expires without being
+    /// used.
+    ///
+    /// Connections that are used, or cleared by a successful connect over a [`UsePreconnect`]
+    /// route, don't produce a notification.
+    pub fn subscribe_to_expiration(&self) -> tokio::sync::watch::Receiver<()> {
+        self.shared.expired.subscribe()
     }
 }
 
@@ -96,6 +138,7 @@ pub struct PreconnectingConnector<R, C: Connector<R, ()>> {
 struct SharedState<R, C> {
     timeout: Duration,
     saved: std::sync::Mutex<Option<SavedConnection<R, C>>>,
+    expired: tokio::sync::watch::Sender<()>,
 }
 
 /// A saved connection for [`PreconnectingConnector`].
@@ -129,6 +172,7 @@ where
                     log::debug!("[{log_tag}] expiring old preconnection");
                 } else if saved.route == route.inner {
                     log::info!("[{log_tag}] using preconnection");
+                    PhaseDurations::update_current(|durations| durations.preconnected = true);
                     return Ok(saved.connection);
                 } else {
                     // We have a saved connection, but it's for a different route. Assuming we try
@@ -174,7 +218,7 @@ mod test {
 
     use super::*;
     use crate::route::testutils::ConnectFn;
-    use crate::route::ConnectorExt;
+    use crate::route::{ConnectorExt, RecordPhasesConnector};
 
     const TIMEOUT: Duration = Duration::from_secs(1);
 
@@ -245,6 +289,24 @@ mod test {
         );
     }
 
+    #[tokio::test(start_paused = true)]
+    async fn used_preconnection_is_recorded() {
+        let number_of_times_called = AtomicU8::new(0);
+        let factory = test_factory(&number_of_times_called);
+
+        factory.save_preconnected(1, 10, Instant::now());
+        let connector = RecordPhasesConnector(ConnectorFactory::<UsePreconnect<_>>::make(&factory));
+        let (connection, durations) =
+            assert_matches!(connector.connect(pre(1), "1".into()).await, Ok(c) => c);
+        assert_eq!(connection, 10);
+        assert!(durations.preconnected);
+
+        let (connection, durations) =
+            assert_matches!(connector.connect(pre(1), "1 again".into()).await, Ok(c) => c);
+        assert_eq!(connection, 1);
+        assert!(!durations.preconnected);
+    }
+
     #[tokio::test(start_paused = true)]
     async fn respects_should_field() {
         let number_of_times_called = AtomicU8::new(0);
@@ -326,4 +388,51 @@ mod test {
         assert_matches!(connector.connect(pre(1), "1".into()).await, Ok(10));
         assert_eq!(number_of_times_called.load(atomic::Ordering::SeqCst), 1);
     }
+
+    #[tokio::test(start_paused = true)]
+    async fn expiration_is_notified() {
+        let number_of_times_called = AtomicU8::new(0);
+        let factory = test_factory(&number_of_times_called);
+        let expired = factory.subscribe_to_expiration();
+
+        factory.save_preconnected(1, 10, Instant::now());
+        tokio::time::sleep(TIMEOUT / 2).await;
+        assert!(!expired.has_changed().expect("not closed"));
+
+        tokio::time::sleep(TIMEOUT).await;
+        assert!(expired.has_changed().expect("not closed"));
+        assert!(factory.shared.saved.lock().expect("not poisoned").is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn used_preconnection_does_not_notify() {
+        let number_of_times_called = AtomicU8::new(0);
+        let factory = test_factory(&number_of_times_called);
+        let expired = factory.subscribe_to_expiration();
+
+        factory.save_preconnected(1, 10, Instant::now());
+        let connector = ConnectorFactory::<UsePreconnect<_>>::make(&factory);
+        assert_matches!(connector.connect(pre(1), "1".into()).await, Ok(10));
+
+        tokio::time::sleep(TIMEOUT * 2).await;
+        assert!(!expired.has_changed().expect("not closed"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn replaced_preconnection_notifies_once() {
+        let number_of_times_called = AtomicU8::new(0);
+        let factory = test_factory(&number_of_times_called);
+        let expired = factory.subscribe_to_expiration();
+
+        factory.save_preconnected(1, 10, Instant::now());
+        tokio::time::sleep(TIMEOUT / 2).await;
+        factory.save_preconnected(2, 20, Instant::now());
+
+        // The first connection's timer goes off, but it's already been replaced.
+        tokio::time::sleep(TIMEOUT / 2).await;
+        assert!(!expired.has_changed().expect("not closed"));
+
+        tokio::time::sleep(TIMEOUT).await;
+        assert!(expired.has_changed().expect("not closed"));
+    }
 }
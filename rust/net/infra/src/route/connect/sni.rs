@@ -0,0 +1,70 @@
+//
+// Copyright 2025 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+use std::fmt::Debug;
+use std::future::Future;
+use std::sync::Arc;
+
+use crate::host::Host;
+use crate::route::{Connector, TlsRouteFragment};
+
+/// Computes the SNI to send for a TLS connection from the one on its route.
+pub type DeriveSni = Arc<dyn Fn(&Host<Arc<str>>) -> Host<Arc<str>> + Send + Sync>;
+
+/// A [`Connector`] for [`TlsRouteFragment`]s that replaces each fragment's SNI
+/// right before handing it to the inner connector.
+///
+/// This allows the server name to be chosen at connect time (for example, from
+/// a set of fronts that rotates) instead of being fixed when the routes are
+/// generated. Everything the inner connector does with the SNI uses the
+/// derived name, including certificate verification and, if the fragment has
+/// an ECH config list, the name hidden in the encrypted ClientHello.
+pub struct SniMappingConnector<C> {
+    inner: C,
+    derive_sni: DeriveSni,
+}
+
+impl<C> SniMappingConnector<C> {
+    pub fn new(
+        inner: C,
+        derive_sni: impl Fn(&Host<Arc<str>>) -> Host<Arc<str>> + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            inner,
+            derive_sni: Arc::new(derive_sni),
+        }
+    }
+}
+
+impl<C: Debug> Debug for SniMappingConnector<C> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let Self {
+            inner,
+            derive_sni: _,
+        } = self;
+        f.debug_struct("SniMappingConnector")
+            .field("inner", inner)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<C, Inner> Connector<TlsRouteFragment, Inner> for SniMappingConnector<C>
+where
+    C: Connector<TlsRouteFragment, Inner>,
+{
+    type Connection = C::Connection;
+    type Error = C::Error;
+
+    fn connect_over(
+        &self,
+        over: Inner,
+        mut fragment: TlsRouteFragment,
+        log_tag: Arc<str>,
+    ) -> impl Future<Output = Result<Self::Connection, Self::Error>> + Send {
+        let Self { inner, derive_sni } = self;
+        fragment.sni = derive_sni(&fragment.sni);
+        inner.connect_over(over, fragment, log_tag)
+    }
+}
@@ -3,16 +3,21 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 //
 
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
 use std::future::Future;
+use std::net::IpAddr;
+use std::num::NonZeroUsize;
 use std::pin::Pin;
 use std::sync::Arc;
 
 use futures_util::{Sink, Stream};
 use pin_project::pin_project;
 use tokio::io::{AsyncRead, AsyncWrite};
-use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::sync::{oneshot, OwnedSemaphorePermit, Semaphore};
 
-use crate::route::connect::Connector;
+use crate::route::connect::{Connector, ConnectorFactory};
+use crate::route::ResolvedRoute;
 use crate::{Connection, TransportInfo};
 
 /// [`Connector`] wrapper that limits the number of concurrent connection
@@ -25,7 +30,16 @@ use crate::{Connection, TransportInfo};
 /// time. See the [`ThrottledConnection`] docs for more details.
 pub struct ThrottlingConnector<C> {
     inner: C,
-    permits: Arc<Semaphore>,
+    permits: PermitSource,
+}
+
+/// Where a [`ThrottlingConnector`] gets its permits from.
+enum PermitSource {
+    Owned(Arc<Semaphore>),
+    Shared {
+        pool: SharedConnectPermits,
+        priority: ConnectPriority,
+    },
 }
 
 impl<C> ThrottlingConnector<C> {
@@ -34,7 +48,26 @@ impl<C> ThrottlingConnector<C> {
     pub fn new(connector: C, permits: usize) -> Self {
         Self {
             inner: connector,
-            permits: Semaphore::new(permits).into(),
+            permits: PermitSource::Owned(Semaphore::new(permits).into()),
+        }
+    }
+
+    /// Wrap an inner [`Connector`] so that it draws permits from a pool shared
+    /// with other connectors.
+    ///
+    /// When the pool is exhausted, waiting connectors are given permits in
+    /// order of `priority`, highest first. See [`SharedConnectPermits`].
+    pub fn new_shared(
+        connector: C,
+        pool: &SharedConnectPermits,
+        priority: ConnectPriority,
+    ) -> Self {
+        Self {
+            inner: connector,
+            permits: PermitSource::Shared {
+                pool: pool.clone(),
+                priority,
+            },
         }
     }
 
@@ -52,15 +85,139 @@ impl<C> ThrottlingConnector<C> {
     }
 }
 
-/// Pairs a connection `S` with a [`OwnedSemaphorePermit`].
+/// The relative importance of connection attempts drawing from the same
+/// [`SharedConnectPermits`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ConnectPriority {
+    /// Work the user isn't waiting on, like a backup or a prefetch.
+    Background,
+    Normal,
+    /// Connections the user is actively waiting on, like chat.
+    UserFacing,
+}
+
+/// A pool of connection permits that can be shared by several
+/// [`ThrottlingConnector`]s.
+///
+/// Unlike the first-come-first-served permits of a connector made with
+/// [`ThrottlingConnector::new`], a permit returned to the pool goes to the
+/// highest-[`ConnectPriority`] waiter, and only then to the one that has been
+/// waiting longest. A steady stream of high-priority attempts can therefore
+/// hold off lower-priority ones indefinitely.
+///
+/// Cloning a `SharedConnectPermits` produces a handle to the same pool.
+#[derive(Clone, Debug)]
+pub struct SharedConnectPermits {
+    state: Arc<std::sync::Mutex<PoolState>>,
+}
+
+#[derive(Debug, Default)]
+struct PoolState {
+    available: usize,
+    /// Ordered so that the first entry is the next to be given a permit.
+    waiting: BTreeMap<(Reverse<ConnectPriority>, u64), oneshot::Sender<()>>,
+    next_waiter_id: u64,
+}
+
+/// A permit taken from a [`SharedConnectPermits`], returned on drop.
+#[derive(Debug)]
+struct PoolPermit(SharedConnectPermits);
+
+impl SharedConnectPermits {
+    pub fn new(permits: usize) -> Self {
+        Self {
+            state: Arc::new(
+                PoolState {
+                    available: permits,
+                    ..Default::default()
+                }
+                .into(),
+            ),
+        }
+    }
+
+    async fn acquire(&self, priority: ConnectPriority) -> PoolPermit {
+        let receiver = {
+            let mut state = self.state.lock().expect("not poisoned");
+            if state.available > 0 && state.waiting.is_empty() {
+                state.available -= 1;
+                return PoolPermit(self.clone());
+            }
+            let (sender, receiver) = oneshot::channel();
+            let id = state.next_waiter_id;
+            state.next_waiter_id += 1;
+            state.waiting.insert((Reverse(priority), id), sender);
+            receiver
+        };
+
+        // If this future is dropped after a permit was handed over but before
+        // it was received, the permit needs to go back to the pool.
+        struct Waiter<'a> {
+            pool: &'a SharedConnectPermits,
+            receiver: oneshot::Receiver<()>,
+            granted: bool,
+        }
+        impl Drop for Waiter<'_> {
+            fn drop(&mut self) {
+                if self.granted {
+                    return;
+                }
+                self.receiver.close();
+                if self.receiver.try_recv().is_ok() {
+                    self.pool.release();
+                }
+            }
+        }
+
+        let mut waiter = Waiter {
+            pool: self,
+            receiver,
+            granted: false,
+        };
+        (&mut waiter.receiver)
+            .await
+            .expect("waiters are only removed to be given a permit");
+        waiter.granted = true;
+        PoolPermit(self.clone())
+    }
+
+    fn release(&self) {
+        let mut state = self.state.lock().expect("not poisoned");
+        while let Some((_, waiter)) = state.waiting.pop_first() {
+            // Skip over waiters that gave up.
+            if waiter.send(()).is_ok() {
+                return;
+            }
+        }
+        state.available += 1;
+    }
+}
+
+impl Drop for PoolPermit {
+    fn drop(&mut self) {
+        self.0.release()
+    }
+}
+
+/// A permit held by a [`ThrottledConnection`].
+#[derive(Debug)]
+enum Permit {
+    // The fields are only held to be dropped.
+    #[allow(dead_code)]
+    Semaphore(OwnedSemaphorePermit),
+    #[allow(dead_code)]
+    Pool(PoolPermit),
+}
+
+/// Pairs a connection `S` with a permit.
 ///
-/// The semaphore permit comes from the [`ThrottlingConnector`] that produced
-/// this connection, and can only be released by dropping this connection.
-/// Dropping a `ThrottlingConnector` unblocks one in-progress call to
+/// The permit comes from the [`ThrottlingConnector`] that produced this
+/// connection, and can only be released by dropping this connection. Dropping
+/// a `ThrottledConnection` unblocks one in-progress call to
 /// [`Connector::connect_over`].
 #[derive(Debug)]
 #[pin_project]
-pub struct ThrottledConnection<S>(#[pin] S, OwnedSemaphorePermit);
+pub struct ThrottledConnection<S>(#[pin] S, Permit);
 
 impl<S> ThrottledConnection<S> {
     /// Returns the inner connection, **discarding the semaphore permit.**
@@ -87,16 +244,153 @@ where
     ) -> impl Future<Output = Result<Self::Connection, Self::Error>> + Send {
         let Self { inner, permits } = self;
         async move {
-            let permit = Arc::clone(permits)
-                .acquire_owned()
-                .await
-                .expect("semaphore not closed");
+            let permit = match permits {
+                PermitSource::Owned(semaphore) => Permit::Semaphore(
+                    Arc::clone(semaphore)
+                        .acquire_owned()
+                        .await
+                        .expect("semaphore not closed"),
+                ),
+                PermitSource::Shared { pool, priority } => {
+                    Permit::Pool(pool.acquire(*priority).await)
+                }
+            };
             let connection = inner.connect_over(over, route, log_tag).await?;
             Ok(ThrottledConnection(connection, permit))
         }
     }
 }
 
+/// Limits the number of connections to each host that can be open at once.
+///
+/// Hosts are identified by the IP address a route connects to directly (see
+/// [`ResolvedRoute::immediate_target`]), so all routes through the same proxy
+/// count against the proxy. A slot is taken when a connection attempt starts
+/// and released when the attempt fails or the resulting connection is
+/// dropped.
+///
+/// Cloning a `PerHostConnectionLimit` produces a handle to the same set of
+/// counts, so it can be shared by several connector factories.
+#[derive(Clone, Debug)]
+pub struct PerHostConnectionLimit {
+    limit: NonZeroUsize,
+    hosts: Arc<std::sync::Mutex<HashMap<IpAddr, Arc<Semaphore>>>>,
+}
+
+impl PerHostConnectionLimit {
+    pub fn new(limit: NonZeroUsize) -> Self {
+        Self {
+            limit,
+            hosts: Default::default(),
+        }
+    }
+
+    /// Returns the number of connections and connection attempts currently
+    /// open for each host that has any.
+    pub fn open_counts(&self) -> HashMap<IpAddr, usize> {
+        let Self { limit, hosts } = self;
+        hosts
+            .lock()
+            .expect("not poisoned")
+            .iter()
+            .filter_map(|(host, permits)| {
+                let open = limit.get() - permits.available_permits();
+                (open != 0).then_some((*host, open))
+            })
+            .collect()
+    }
+
+    fn permits_for(&self, host: IpAddr) -> Arc<Semaphore> {
+        let Self { limit, hosts } = self;
+        let mut hosts = hosts.lock().expect("not poisoned");
+        // Forget hosts that nothing is connected to so the map doesn't grow
+        // without bound.
+        hosts.retain(|_, permits| {
+            Arc::strong_count(permits) > 1 || permits.available_permits() != limit.get()
+        });
+        Arc::clone(
+            hosts
+                .entry(host)
+                .or_insert_with(|| Semaphore::new(limit.get()).into()),
+        )
+    }
+}
+
+/// [`ConnectorFactory`] wrapper whose connectors respect a
+/// [`PerHostConnectionLimit`].
+///
+/// A connection attempt to a host that's already at its limit waits for one of
+/// the existing connections to that host to close. Wrapping the factory given
+/// to `ConnectState` applies the limit to every connection it makes.
+pub struct PerHostLimitingFactory<F> {
+    inner_factory: F,
+    limit: PerHostConnectionLimit,
+}
+
+/// The [`Connector`] produced by [`PerHostLimitingFactory`].
+pub struct PerHostLimitingConnector<C> {
+    inner: C,
+    limit: PerHostConnectionLimit,
+}
+
+impl<F> PerHostLimitingFactory<F> {
+    pub fn new(inner_factory: F, limit: PerHostConnectionLimit) -> Self {
+        Self {
+            inner_factory,
+            limit,
+        }
+    }
+
+    pub fn limit(&self) -> &PerHostConnectionLimit {
+        &self.limit
+    }
+}
+
+impl<R, F> ConnectorFactory<R> for PerHostLimitingFactory<F>
+where
+    F: ConnectorFactory<R>,
+    PerHostLimitingConnector<F::Connector>: Connector<R, ()>,
+{
+    type Connector = PerHostLimitingConnector<F::Connector>;
+    type Connection = <Self::Connector as Connector<R, ()>>::Connection;
+
+    fn make(&self) -> Self::Connector {
+        PerHostLimitingConnector {
+            inner: self.inner_factory.make(),
+            limit: self.limit.clone(),
+        }
+    }
+}
+
+impl<C, R, Inner> Connector<R, Inner> for PerHostLimitingConnector<C>
+where
+    R: ResolvedRoute + Send,
+    Inner: Send,
+    C: Connector<R, Inner> + Sync,
+{
+    type Connection = ThrottledConnection<C::Connection>;
+
+    type Error = C::Error;
+
+    fn connect_over(
+        &self,
+        over: Inner,
+        route: R,
+        log_tag: Arc<str>,
+    ) -> impl Future<Output = Result<Self::Connection, Self::Error>> + Send {
+        let Self { inner, limit } = self;
+        let permits = limit.permits_for(*route.immediate_target());
+        async move {
+            if permits.available_permits() == 0 {
+                log::info!("[{log_tag}] waiting for another connection to the same host to close");
+            }
+            let permit = permits.acquire_owned().await.expect("semaphore not closed");
+            let connection = inner.connect_over(over, route, log_tag).await?;
+            Ok(ThrottledConnection(connection, Permit::Semaphore(permit)))
+        }
+    }
+}
+
 impl<S> AsRef<S> for ThrottledConnection<S> {
     fn as_ref(&self) -> &S {
         &self.0
@@ -200,3 +494,123 @@ impl<S: AsyncWrite> AsyncWrite for ThrottledConnection<S> {
         self.as_pin_mut().poll_shutdown(cx)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::net::Ipv4Addr;
+
+    use assert_matches::assert_matches;
+    use futures_util::FutureExt as _;
+    use nonzero_ext::nonzero;
+
+    use super::*;
+    use crate::route::testutils::ConnectFn;
+    use crate::route::ConnectorExt as _;
+
+    const HOST_A: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+    const HOST_B: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2));
+
+    #[tokio::test]
+    async fn per_host_limit_blocks_second_connect_to_same_host() {
+        let limit = PerHostConnectionLimit::new(nonzero!(1usize));
+        let factory = PerHostLimitingFactory::new(
+            ConnectFn(|(), route: IpAddr, _log_tag| {
+                std::future::ready(Ok::<_, std::convert::Infallible>(route))
+            }),
+            limit.clone(),
+        );
+
+        let first = factory
+            .make()
+            .connect(HOST_A, "first".into())
+            .await
+            .expect("no other connections");
+        assert_eq!(limit.open_counts(), HashMap::from([(HOST_A, 1)]));
+
+        let connector = factory.make();
+        let mut second = std::pin::pin!(connector.connect(HOST_A, "second".into()));
+        assert_matches!((&mut second).now_or_never(), None);
+
+        // A different host isn't affected.
+        let other = factory
+            .make()
+            .connect(HOST_B, "other".into())
+            .await
+            .expect("different host");
+        assert_eq!(
+            limit.open_counts(),
+            HashMap::from([(HOST_A, 1), (HOST_B, 1)])
+        );
+
+        drop(first);
+        let second = second.await.expect("first connection closed");
+        assert_eq!(second.as_ref(), &HOST_A);
+        drop(other);
+        assert_eq!(limit.open_counts(), HashMap::from([(HOST_A, 1)]));
+    }
+
+    #[tokio::test]
+    async fn shared_permits_go_to_higher_priority_first() {
+        let pool = SharedConnectPermits::new(1);
+        let make_connector = |priority| {
+            ThrottlingConnector::new_shared(
+                ConnectFn(|(), route: &'static str, _log_tag| {
+                    std::future::ready(Ok::<_, std::convert::Infallible>(route))
+                }),
+                &pool,
+                priority,
+            )
+        };
+        let chat = make_connector(ConnectPriority::UserFacing);
+        let svr = make_connector(ConnectPriority::Background);
+
+        let first = svr
+            .connect("first", "first".into())
+            .await
+            .expect("permit available");
+
+        // The low-priority connect starts waiting first...
+        let mut low = std::pin::pin!(svr.connect("low", "low".into()));
+        assert_matches!((&mut low).now_or_never(), None);
+        let mut high = std::pin::pin!(chat.connect("high", "high".into()));
+        assert_matches!((&mut high).now_or_never(), None);
+
+        // ...but the high-priority one gets the permit.
+        drop(first);
+        assert_matches!((&mut low).now_or_never(), None);
+        let high = (&mut high).now_or_never().expect("ready").expect("success");
+        assert_eq!(high.as_ref(), &"high");
+
+        drop(high);
+        let low = low.now_or_never().expect("ready").expect("success");
+        assert_eq!(low.as_ref(), &"low");
+    }
+
+    #[tokio::test]
+    async fn shared_permits_skip_abandoned_waiters() {
+        let pool = SharedConnectPermits::new(1);
+        let connector = ThrottlingConnector::new_shared(
+            ConnectFn(|(), route: &'static str, _log_tag| {
+                std::future::ready(Ok::<_, std::convert::Infallible>(route))
+            }),
+            &pool,
+            ConnectPriority::Normal,
+        );
+
+        let first = connector
+            .connect("first", "first".into())
+            .await
+            .expect("permit available");
+        let mut abandoned = Box::pin(connector.connect("abandoned", "abandoned".into()));
+        assert_matches!((&mut abandoned).now_or_never(), None);
+        drop(abandoned);
+
+        drop(first);
+        let next = connector
+            .connect("next", "next".into())
+            .now_or_never()
+            .expect("permit returned to the pool")
+            .expect("success");
+        assert_eq!(next.as_ref(), &"next");
+    }
+}
@@ -0,0 +1,204 @@
+//
+// Copyright 2025 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::route::Connector;
+
+/// A [`Connector`] that fails a connection attempt if it takes longer than a
+/// fixed amount of time.
+///
+/// This is meant for bounding a single phase of a connection (like the TCP
+/// connect or the TLS handshake) independently of the overall timeout, so a
+/// stall in that phase fails the route quickly. When the limit is reached, the
+/// error produced by `on_timeout` is returned in place of the inner
+/// connector's result. A `timeout` of `None` disables the limit.
+#[derive(Debug)]
+pub struct TimeoutConnector<Inner, Error> {
+    inner_connector: Inner,
+    timeout: Option<Duration>,
+    label: &'static str,
+    on_timeout: fn() -> Error,
+}
+
+impl<I, E> TimeoutConnector<I, E> {
+    pub fn new(
+        inner: I,
+        timeout: Option<Duration>,
+        label: &'static str,
+        on_timeout: fn() -> E,
+    ) -> Self {
+        Self {
+            inner_connector: inner,
+            timeout,
+            label,
+            on_timeout,
+        }
+    }
+
+    pub fn into_inner(self) -> I {
+        self.inner_connector
+    }
+}
+
+impl<I, R, Inner, E> Connector<R, Inner> for TimeoutConnector<I, E>
+where
+    I: Connector<R, Inner, Error = E> + Sync,
+    R: Send,
+    Inner: Send,
+    E: Send,
+{
+    type Connection = I::Connection;
+    type Error = E;
+
+    async fn connect_over(
+        &self,
+        over: Inner,
+        route: R,
+        log_tag: Arc<str>,
+    ) -> Result<Self::Connection, Self::Error> {
+        let Self {
+            inner_connector,
+            timeout,
+            label,
+            on_timeout,
+        } = self;
+
+        let connect = inner_connector.connect_over(over, route, log_tag.clone());
+        let Some(timeout) = *timeout else {
+            return connect.await;
+        };
+
+        match tokio::time::timeout(timeout, connect).await {
+            Ok(result) => result,
+            Err(_elapsed) => {
+                log::info!("[{log_tag}] {label} timed out after {timeout:?}");
+                Err(on_timeout())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_matches::assert_matches;
+    use tokio::time::Instant;
+
+    use super::*;
+    use crate::errors::{FailedHandshakeReason, TlsHandshakeTimeout, TransportConnectError};
+    use crate::route::connect::testutils::DummyDelayConnector;
+    use crate::route::VariableTlsTimeoutConnector;
+
+    const TEST_TRANSPORT: () = ();
+    const TEST_ROUTE: () = ();
+    fn test_log_tag() -> Arc<str> {
+        Arc::from("test")
+    }
+
+    /// Long enough to count as a stall for any of these tests.
+    const STALL: Duration = Duration::from_secs(1000);
+
+    #[tokio::test(start_paused = true)]
+    async fn no_timeout_waits_for_inner() {
+        let connector =
+            TimeoutConnector::new(DummyDelayConnector { delay: STALL }, None, "test", || {
+                TransportConnectError::TcpConnectionFailed
+            });
+
+        let start = Instant::now();
+        let result = connector
+            .connect_over(TEST_TRANSPORT, TEST_ROUTE, test_log_tag())
+            .await;
+        assert_matches!(result, Ok(_));
+        assert_eq!(start.elapsed(), STALL);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn fast_inner_is_unaffected() {
+        let connector = TimeoutConnector::new(
+            DummyDelayConnector {
+                delay: Duration::from_millis(50),
+            },
+            Some(Duration::from_millis(100)),
+            "test",
+            || TransportConnectError::TcpConnectionFailed,
+        );
+
+        let result = connector
+            .connect_over(TEST_TRANSPORT, TEST_ROUTE, test_log_tag())
+            .await;
+        assert_matches!(result, Ok(_));
+    }
+
+    /// Builds the same shape of connector as the default transport stack, with
+    /// a phase timeout on each layer.
+    fn tcp_then_tls(
+        tcp_delay: Duration,
+        tls_delay: Duration,
+    ) -> VariableTlsTimeoutConnector<
+        TimeoutConnector<DummyDelayConnector, TransportConnectError>,
+        TimeoutConnector<DummyDelayConnector, TransportConnectError>,
+        TransportConnectError,
+    > {
+        VariableTlsTimeoutConnector::new(
+            TimeoutConnector::new(
+                DummyDelayConnector { delay: tls_delay },
+                Some(Duration::from_secs(2)),
+                "TLS",
+                || TlsHandshakeTimeout.into(),
+            ),
+            TimeoutConnector::new(
+                DummyDelayConnector { delay: tcp_delay },
+                Some(Duration::from_secs(1)),
+                "TCP",
+                || TransportConnectError::TcpConnectionFailed,
+            ),
+            // Much longer than the TLS phase timeout, so that's the one that fires.
+            Duration::from_secs(30),
+        )
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn tcp_stall_fails_at_tcp_timeout() {
+        let connector = tcp_then_tls(STALL, Duration::ZERO);
+
+        let start = Instant::now();
+        let result = connector
+            .connect_inner_then_outer_with_timeout(
+                TEST_TRANSPORT,
+                TEST_ROUTE,
+                TEST_ROUTE,
+                test_log_tag(),
+            )
+            .await;
+        assert_matches!(result, Err(TransportConnectError::TcpConnectionFailed));
+        assert_eq!(start.elapsed(), Duration::from_secs(1));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn tls_stall_fails_at_tls_timeout() {
+        let connector = tcp_then_tls(Duration::from_millis(10), STALL);
+
+        let start = Instant::now();
+        let result = connector
+            .connect_inner_then_outer_with_timeout(
+                TEST_TRANSPORT,
+                TEST_ROUTE,
+                TEST_ROUTE,
+                test_log_tag(),
+            )
+            .await;
+        let reason = assert_matches!(
+            result,
+            Err(TransportConnectError::SslFailedHandshake(reason)) => reason
+        );
+        assert_eq!(reason, FailedHandshakeReason::TIMED_OUT);
+        assert_eq!(
+            start.elapsed(),
+            Duration::from_millis(10) + Duration::from_secs(2)
+        );
+    }
+}
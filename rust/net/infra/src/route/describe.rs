@@ -18,8 +18,10 @@ use crate::route::{
     ConnectionProxyKind, ConnectionProxyRoute, Connector, DirectOrProxyRoute,
     HttpProxyRouteFragment, HttpsProxyRoute, HttpsTlsRoute, ProxyTarget, ResolveHostnames,
     ResolvedRoute, SocksRoute, TcpRoute, TlsRoute, TransportRoute, UnresolvedHost,
-    UnresolvedTransportRoute, UnresolvedWebsocketServiceRoute, UsesTransport, DEFAULT_HTTPS_PORT,
+    UnresolvedTransportRoute, UnresolvedWebsocketServiceRoute, UsePreconnect, UsesTransport,
+    DEFAULT_HTTPS_PORT,
 };
+use crate::RouteType;
 
 /// A type that is not itself loggable but can produce a [`LogSafeDisplay`]
 /// value.
@@ -87,6 +89,9 @@ impl<R: ResolvedRoute, D> ResolvedRoute for WithLoggableDescription<R, D> {
     fn immediate_target(&self) -> &IpAddr {
         self.route.immediate_target()
     }
+    fn immediate_port(&self) -> Option<NonZeroU16> {
+        self.route.immediate_port()
+    }
 }
 
 impl<R: Clone + Send, Inner, C: Connector<R, Inner>, D: Send>
@@ -134,6 +139,40 @@ impl std::fmt::Display for UnresolvedRouteDescription {
 }
 
 impl UnresolvedRouteDescription {
+    /// The name of the domain front the route goes through, if any.
+    pub fn front(&self) -> Option<&'static str> {
+        self.front
+    }
+
+    /// The host that the route ultimately connects to.
+    pub fn target_host(&self) -> &Host<Arc<str>> {
+        &self.target.0
+    }
+
+    /// The kind of route this describes.
+    ///
+    /// A domain front takes precedence over any proxy the front is reached
+    /// through. Returns `None` for routes that don't correspond to a
+    /// [`RouteType`], like those through an HTTPS proxy.
+    pub fn route_type(&self) -> Option<RouteType> {
+        let Self {
+            front,
+            proxy,
+            target: _,
+        } = self;
+        if let Some(front) = front {
+            return [RouteType::ProxyF, RouteType::ProxyG]
+                .into_iter()
+                .find(|&route_type| <&'static str>::from(route_type) == *front);
+        }
+        match proxy {
+            None => Some(RouteType::Direct),
+            Some(ConnectionProxyKind::Tls | ConnectionProxyKind::Tcp) => Some(RouteType::TlsProxy),
+            Some(ConnectionProxyKind::Socks) => Some(RouteType::SocksProxy),
+            Some(ConnectionProxyKind::Https) => None,
+        }
+    }
+
     pub fn fake() -> Self {
         Self {
             front: None,
@@ -160,48 +199,71 @@ impl<Transport: UsesTransport<UnresolvedTransportRoute>> DescribeForLog
                     inner: transport,
                 },
         } = self;
-        let TlsRoute {
-            fragment: tls_fragment,
-            inner: direct_or_proxy,
-        } = transport.transport_part();
-
-        let target = match direct_or_proxy {
-            DirectOrProxyRoute::Direct(TcpRoute { address, port }) => {
-                (Host::Domain(address.clone().into()), *port)
-            }
-            DirectOrProxyRoute::Proxy(proxy) => match proxy {
-                ConnectionProxyRoute::Tls { proxy: _ } | ConnectionProxyRoute::Tcp { proxy: _ } => {
-                    // The host is implicit; the proxy will look for the TLS SNI and resolve that.
-                    (tls_fragment.sni.clone(), DEFAULT_HTTPS_PORT)
-                }
-                ConnectionProxyRoute::Socks(SocksRoute {
-                    target_addr,
-                    target_port,
-                    ..
-                }) => (target_addr.as_informational_host(), *target_port),
-                ConnectionProxyRoute::Https(HttpsProxyRoute {
-                    fragment:
-                        HttpProxyRouteFragment {
-                            target_host,
-                            target_port,
-                            ..
-                        },
-                    inner: _,
-                }) => (target_host.as_informational_host(), *target_port),
-            },
-        };
-
-        let proxy = match &direct_or_proxy {
-            DirectOrProxyRoute::Direct(_) => None,
-            DirectOrProxyRoute::Proxy(proxy) => Some(ConnectionProxyKind::from(proxy)),
-        };
-        let front = http_fragment.front_name;
-
-        UnresolvedRouteDescription {
-            front,
-            proxy,
-            target,
+        describe_transport(transport.transport_part(), http_fragment.front_name)
+    }
+}
+
+/// Describes a transport-level route on its own, without a domain front.
+impl DescribeForLog for UnresolvedTransportRoute {
+    type Description = UnresolvedRouteDescription;
+
+    fn describe_for_log(&self) -> Self::Description {
+        describe_transport(self, None)
+    }
+}
+
+impl<R: DescribeForLog> DescribeForLog for UsePreconnect<R> {
+    type Description = R::Description;
+
+    fn describe_for_log(&self) -> Self::Description {
+        self.inner.describe_for_log()
+    }
+}
+
+fn describe_transport(
+    transport: &UnresolvedTransportRoute,
+    front: Option<&'static str>,
+) -> UnresolvedRouteDescription {
+    let TlsRoute {
+        fragment: tls_fragment,
+        inner: direct_or_proxy,
+    } = transport;
+
+    let target = match direct_or_proxy {
+        DirectOrProxyRoute::Direct(TcpRoute { address, port }) => {
+            (Host::Domain(address.clone().into()), *port)
         }
+        DirectOrProxyRoute::Proxy(proxy) => match proxy {
+            ConnectionProxyRoute::Tls { proxy: _ } | ConnectionProxyRoute::Tcp { proxy: _ } => {
+                // The host is implicit; the proxy will look for the TLS SNI and resolve that.
+                (tls_fragment.sni.clone(), DEFAULT_HTTPS_PORT)
+            }
+            ConnectionProxyRoute::Socks(SocksRoute {
+                target_addr,
+                target_port,
+                ..
+            }) => (target_addr.as_informational_host(), *target_port),
+            ConnectionProxyRoute::Https(HttpsProxyRoute {
+                fragment:
+                    HttpProxyRouteFragment {
+                        target_host,
+                        target_port,
+                        ..
+                    },
+                inner: _,
+            }) => (target_host.as_informational_host(), *target_port),
+        },
+    };
+
+    let proxy = match &direct_or_proxy {
+        DirectOrProxyRoute::Direct(_) => None,
+        DirectOrProxyRoute::Proxy(proxy) => Some(ConnectionProxyKind::from(proxy)),
+    };
+
+    UnresolvedRouteDescription {
+        front,
+        proxy,
+        target,
     }
 }
 
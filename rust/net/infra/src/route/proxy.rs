@@ -17,7 +17,7 @@ use crate::route::{
     TlsRouteFragment, UnresolvedHost,
 };
 use crate::tcp_ssl::proxy::socks;
-use crate::Alpn;
+use crate::{Alpn, AlpnList};
 
 pub const SIGNAL_TLS_PROXY_SCHEME: &str = "org.signal.tls";
 
@@ -398,7 +398,8 @@ impl AsReplacer for TlsProxy {
         let tls_fragment = TlsRouteFragment {
             root_certs: proxy_certs.clone(),
             sni: proxy_host.clone(),
-            alpn: None,
+            alpn: AlpnList::default(),
+            ech_config_list: None,
         };
 
         let tcp = TcpRoute {
@@ -476,7 +477,8 @@ impl AsReplacer for HttpProxy {
                 fragment: TlsRouteFragment {
                     root_certs: proxy_certs.clone(),
                     sni: proxy_host.clone(),
-                    alpn: Some(Alpn::Http1_1),
+                    alpn: Alpn::Http1_1.into(),
+                    ech_config_list: None,
                 },
             }),
             None => Either::Right(proxy_tcp_route),
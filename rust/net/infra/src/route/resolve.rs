@@ -6,7 +6,9 @@
 use std::fmt::Debug;
 use std::future::Future;
 use std::net::IpAddr;
+use std::num::NonZeroU16;
 use std::sync::Arc;
+use std::time::Duration;
 
 use either::Either;
 use futures_util::stream::FuturesUnordered;
@@ -49,6 +51,12 @@ pub trait ResolvedRoute {
     /// For a route that communicates through a proxy, this will be the address
     /// of the proxy. Otherwise it will be the IP address of the target.
     fn immediate_target(&self) -> &IpAddr;
+
+    /// The port on [`Self::immediate_target`] that this route connects to, if
+    /// the route includes one.
+    fn immediate_port(&self) -> Option<NonZeroU16> {
+        None
+    }
 }
 
 /// Asynchronous resolver for individual names.
@@ -71,6 +79,44 @@ impl Resolver for DnsResolver {
     }
 }
 
+impl<R: Resolver + ?Sized> Resolver for &R {
+    fn lookup_ip(
+        &self,
+        hostname: &str,
+    ) -> impl Future<Output = Result<LookupResult, DnsError>> + Send {
+        R::lookup_ip(self, hostname)
+    }
+}
+
+/// A [`Resolver`] that gives up on lookups that take longer than a fixed
+/// amount of time, failing them with [`DnsError::Timeout`].
+///
+/// A `timeout` of `None` passes lookups through unchanged.
+#[derive(Debug)]
+pub struct TimeoutResolver<R> {
+    inner: R,
+    timeout: Option<Duration>,
+}
+
+impl<R> TimeoutResolver<R> {
+    pub fn new(inner: R, timeout: Option<Duration>) -> Self {
+        Self { inner, timeout }
+    }
+}
+
+impl<R: Resolver + Sync> Resolver for TimeoutResolver<R> {
+    async fn lookup_ip(&self, hostname: &str) -> Result<LookupResult, DnsError> {
+        let Self { inner, timeout } = self;
+        let lookup = inner.lookup_ip(hostname);
+        let Some(timeout) = *timeout else {
+            return lookup.await;
+        };
+        tokio::time::timeout(timeout, lookup)
+            .await
+            .unwrap_or(Err(DnsError::Timeout))
+    }
+}
+
 /// The output of [`resolve_route`] on successful resolution.
 ///
 /// The actual type isn't important, but writing it out lets the compiler infer
@@ -368,6 +414,9 @@ macro_rules! impl_resolved_route {
             fn immediate_target(&self) -> &IpAddr {
                 self.$delegate_field.immediate_target()
             }
+            fn immediate_port(&self) -> Option<NonZeroU16> {
+                self.$delegate_field.immediate_port()
+            }
         }
     };
 }
@@ -378,13 +427,29 @@ impl ResolvedRoute for IpAddr {
     }
 }
 
-impl_resolved_route!(TcpRoute, address);
 impl_resolved_route!(TlsRoute, inner);
 impl_resolved_route!(HttpsTlsRoute, inner);
 impl_resolved_route!(HttpsProxyRoute, inner);
 impl_resolved_route!(WebSocketRoute, inner);
 impl_resolved_route!(UsePreconnect, inner);
-impl_resolved_route!(UdpRoute, address);
+
+impl<A: ResolvedRoute> ResolvedRoute for TcpRoute<A> {
+    fn immediate_target(&self) -> &IpAddr {
+        self.address.immediate_target()
+    }
+    fn immediate_port(&self) -> Option<NonZeroU16> {
+        Some(self.port)
+    }
+}
+
+impl<A: ResolvedRoute> ResolvedRoute for UdpRoute<A> {
+    fn immediate_target(&self) -> &IpAddr {
+        self.address.immediate_target()
+    }
+    fn immediate_port(&self) -> Option<NonZeroU16> {
+        Some(self.port)
+    }
+}
 
 impl<D: ResolvedRoute, P: ResolvedRoute> ResolvedRoute for DirectOrProxyRoute<D, P> {
     fn immediate_target(&self) -> &IpAddr {
@@ -393,6 +458,12 @@ impl<D: ResolvedRoute, P: ResolvedRoute> ResolvedRoute for DirectOrProxyRoute<D,
             DirectOrProxyRoute::Proxy(p) => p.immediate_target(),
         }
     }
+    fn immediate_port(&self) -> Option<NonZeroU16> {
+        match self {
+            DirectOrProxyRoute::Direct(d) => d.immediate_port(),
+            DirectOrProxyRoute::Proxy(p) => p.immediate_port(),
+        }
+    }
 }
 
 impl<A: ResolvedRoute> ResolvedRoute for ConnectionProxyRoute<A> {
@@ -404,6 +475,14 @@ impl<A: ResolvedRoute> ResolvedRoute for ConnectionProxyRoute<A> {
             ConnectionProxyRoute::Https(proxy) => proxy.immediate_target(),
         }
     }
+    fn immediate_port(&self) -> Option<NonZeroU16> {
+        match self {
+            ConnectionProxyRoute::Tls { proxy } => proxy.immediate_port(),
+            ConnectionProxyRoute::Tcp { proxy } => proxy.immediate_port(),
+            ConnectionProxyRoute::Socks(proxy) => proxy.immediate_port(),
+            ConnectionProxyRoute::Https(proxy) => proxy.immediate_port(),
+        }
+    }
 }
 
 impl<A: ResolvedRoute> ResolvedRoute for SocksRoute<A> {
@@ -416,6 +495,9 @@ impl<A: ResolvedRoute> ResolvedRoute for SocksRoute<A> {
         } = self;
         proxy.immediate_target()
     }
+    fn immediate_port(&self) -> Option<NonZeroU16> {
+        self.proxy.immediate_port()
+    }
 }
 
 impl<L: ResolvedRoute, R: ResolvedRoute> ResolvedRoute for Either<L, R> {
@@ -427,6 +509,11 @@ impl<L: ResolvedRoute, R: ResolvedRoute> ResolvedRoute for Either<L, R> {
             )
             .into_inner()
     }
+    fn immediate_port(&self) -> Option<NonZeroU16> {
+        self.as_ref()
+            .map_either(ResolvedRoute::immediate_port, ResolvedRoute::immediate_port)
+            .into_inner()
+    }
 }
 
 #[cfg(any(test, feature = "test-util"))]
@@ -485,6 +572,10 @@ pub mod testutils {
         pub fn respond(self, result: Result<LookupResult, DnsError>) {
             let _ignore_error = self.result_sender.send(result);
         }
+        /// Returns `true` if the lookup was dropped before getting a response.
+        pub fn is_cancelled(&self) -> bool {
+            self.result_sender.is_closed()
+        }
     }
 }
 
@@ -507,7 +598,7 @@ mod test {
         UnresolvedHttpsServiceRoute,
     };
     use crate::tcp_ssl::proxy::socks;
-    use crate::DnsSource;
+    use crate::{AlpnList, DnsSource};
 
     const PROXY_PORT: NonZeroU16 = nonzero!(444u16);
     const TARGET_PORT: NonZeroU16 = nonzero!(888u16);
@@ -555,6 +646,26 @@ mod test {
         assert_matches!(resolve.await, Err((_, DnsError::NoData)));
     }
 
+    #[tokio::test(start_paused = true)]
+    async fn timeout_resolver_gives_up_on_stalled_lookup() {
+        let (resolver, mut responders) = FakeResolver::new();
+        let resolver = TimeoutResolver::new(resolver, Some(Duration::from_secs(2)));
+
+        let start = tokio::time::Instant::now();
+        let resolve = resolve_route(&resolver, vec![UnresolvedHost("hostname".into())]);
+        pin_mut!(resolve);
+
+        let responder = tokio::select! {
+            _ = resolve.as_mut() => unreachable!("nothing has responded yet"),
+            responder = responders.next() => responder.expect("incoming request"),
+        };
+
+        // Never respond; the lookup should time out on its own.
+        assert_matches!(resolve.await, Err((_, DnsError::Timeout)));
+        assert_eq!(start.elapsed(), Duration::from_secs(2));
+        assert!(responder.is_cancelled());
+    }
+
     #[tokio::test]
     async fn runs_resolutions_in_parallel() {
         let (resolver, mut responders) = FakeResolver::new();
@@ -684,7 +795,8 @@ mod test {
         let tls_fragment = TlsRouteFragment {
             root_certs: RootCertificates::Native,
             sni: Host::Domain("target-domain".into()),
-            alpn: None,
+            alpn: AlpnList::default(),
+            ech_config_list: None,
         };
 
         fn socks_route<A>(proxy: A, target: A) -> ConnectionProxyRoute<A> {
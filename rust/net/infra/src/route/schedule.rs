@@ -3,23 +3,27 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 //
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt::{Debug, Display};
 use std::future::Future;
 use std::hash::Hash;
+use std::num::NonZeroUsize;
 use std::pin::Pin;
 use std::sync::Arc;
 
 use derive_where::derive_where;
 use futures_util::stream::{FusedStream, FuturesUnordered};
 use futures_util::{Stream, StreamExt};
+use itertools::Itertools as _;
 use pin_project::pin_project;
 use rangemap::RangeSet;
 use tokio::time::{Duration, Instant};
 
 use crate::dns::dns_utils::log_safe_domain;
 use crate::dns::DnsError;
-use crate::route::{ResolveHostnames, ResolvedRoute, Resolver, TransportRoute, UsesTransport};
+use crate::route::{
+    ResolveHostnames, ResolvedRoute, Resolver, RouteProviderContext, TransportRoute, UsesTransport,
+};
 use crate::utils::binary_heap::{MinKeyValueQueue, Queue};
 use crate::utils::future::SomeOrPending;
 
@@ -30,6 +34,15 @@ use crate::utils::future::SomeOrPending;
 #[derive(Clone)]
 pub struct RouteResolver {
     pub allow_ipv6: bool,
+    /// Whether to drop resolved routes whose immediate target (IP address and
+    /// port) is the same as an earlier route's.
+    ///
+    /// Several routes can lead to the same endpoint, e.g. when domain fronts
+    /// share infrastructure, and trying each of them uses up time that could
+    /// be spent on other endpoints. When enabled, only the first route to
+    /// each target that comes out of resolution is kept. Leave this off if
+    /// the differences between such routes (like the SNI or HTTP host) matter.
+    pub dedupe_targets: bool,
 }
 
 /// A policy object that decides how much to delay a route.
@@ -78,6 +91,20 @@ pub struct ConnectionOutcomes<R> {
     recent_failures: HashMap<R, (Instant, u8)>,
 }
 
+/// A route's recent failures as recorded by [`ConnectionOutcomes`], with the
+/// time expressed relative to a reference point so it can outlive the process.
+///
+/// Produced by [`ConnectionOutcomes::to_serializable`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SerializableOutcome<R> {
+    pub route: R,
+    /// How long before the reference time the most recent failed attempt
+    /// started.
+    pub age: Duration,
+    /// How many times in a row connecting over the route has failed.
+    pub consecutive_failures: u8,
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct ConnectionOutcomeParams {
     pub age_cutoff: Duration,
@@ -85,11 +112,48 @@ pub struct ConnectionOutcomeParams {
     pub count_growth_factor: f32,
     pub max_count: u8,
     pub max_delay: Duration,
+    /// The most routes to keep failures for, or `None` for no limit.
+    ///
+    /// Once there are more, entries that have aged out are dropped first, then
+    /// the ones whose most recent failure is the oldest.
+    pub max_entries: Option<NonZeroUsize>,
+    /// How routes without an outstanding failure compare to routes that are
+    /// still cooling down.
+    pub aged_out_successes: AgedOutSuccessOrdering,
+    /// How much to randomize delays by, as a fraction of the delay in either
+    /// direction.
+    ///
+    /// Spreads out reconnects from clients that failed at the same time. Only
+    /// applied by [`Self::apply_jitter`]; `0.0` keeps delays deterministic.
+    pub jitter_fraction: f64,
+}
+
+/// Ordering policy for routes whose last outcome was a success that has since
+/// aged out of [`ConnectionOutcomes`].
+///
+/// Once a success is older than [`ConnectionOutcomeParams::age_cutoff`], the
+/// route is indistinguishable from one that's never been tried. Both get no
+/// delay, but a route that failed recently can still be attempted before them
+/// if its cooldown is shorter than the happy-eyeballs stagger between routes.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AgedOutSuccessOrdering {
+    /// Treat the route like any other route with no history.
+    Unranked,
+    /// Prefer the route over any route that's still cooling down, by delaying
+    /// the latter by an extra margin on top of its computed cooldown.
+    ///
+    /// This reflects "it worked before, it probably still does". Routes with
+    /// no history at all benefit from the margin too, since the two can't be
+    /// told apart.
+    PreferOverCoolingDown(Duration),
 }
 
 impl Default for RouteResolver {
     fn default() -> Self {
-        Self { allow_ipv6: true }
+        Self {
+            allow_ipv6: true,
+            dedupe_targets: false,
+        }
     }
 }
 
@@ -110,7 +174,10 @@ impl RouteResolver {
     where
         R: ResolveHostnames<Resolved: ResolvedRoute> + Clone + 'static,
     {
-        let Self { allow_ipv6 } = self;
+        let Self {
+            allow_ipv6,
+            dedupe_targets,
+        } = self;
 
         let resolved = eagerly_resolve_each(ordered_routes, resolver).filter_map(
             |(resolution_result, meta)| {
@@ -127,13 +194,20 @@ impl RouteResolver {
             },
         );
 
+        let mut seen_targets = HashSet::new();
+
         // Prune routes that connect directly to IPv6 addresses if necessary.
-        resolved.map(|(mut routes, meta)| {
+        resolved.map(move |(mut routes, meta)| {
             if !*allow_ipv6 {
                 routes
                     .routes
                     .retain(|route| route.immediate_target().is_ipv4())
             }
+            if *dedupe_targets {
+                routes.routes.retain(|route| {
+                    seen_targets.insert((*route.immediate_target(), route.immediate_port()))
+                });
+            }
             (routes, meta)
         })
     }
@@ -313,9 +387,16 @@ impl<R: Hash + Eq + Clone> ConnectionOutcomes<R> {
             count_growth_factor: 0.0,
             max_count: 0,
             max_delay: Duration::ZERO,
+            max_entries: None,
+            aged_out_successes: AgedOutSuccessOrdering::Unranked,
+            jitter_fraction: 0.0,
         })
     }
 
+    pub fn params(&self) -> &ConnectionOutcomeParams {
+        &self.params
+    }
+
     /// Update the internal state with the results of completed connection attempts.
     pub fn apply_outcome_updates(
         &mut self,
@@ -329,10 +410,9 @@ impl<R: Hash + Eq + Clone> ConnectionOutcomes<R> {
             recent_failures,
         } = self;
 
-        // Age out any old entries.
-        recent_failures.retain(|_route, (last_time, _failure_count)| {
-            now.saturating_duration_since(*last_time) < params.age_cutoff
-        });
+        // Age out any old entries. This happens before adding new ones so that
+        // expired entries are always the first to go.
+        remove_aged_out_failures(params, recent_failures, now);
 
         for (route, outcome) in updates {
             let AttemptOutcome { started, result } = outcome;
@@ -353,6 +433,140 @@ impl<R: Hash + Eq + Clone> ConnectionOutcomes<R> {
                 },
             }
         }
+
+        let excess = params
+            .max_entries
+            .map_or(0, |max| recent_failures.len().saturating_sub(max.get()));
+        if excess != 0 {
+            let mut least_recent = recent_failures
+                .iter()
+                .map(|(route, (when, _count))| (*when, route.clone()))
+                .collect_vec();
+            least_recent.sort_unstable_by_key(|(when, _route)| *when);
+            for (_when, route) in least_recent.into_iter().take(excess) {
+                recent_failures.remove(&route);
+            }
+        }
+    }
+
+    /// Computes the smallest delay among the recorded routes that match the
+    /// predicate.
+    ///
+    /// Returns `None` if there are no recent failures for any matching route.
+    pub fn min_delay_matching(
+        &self,
+        mut predicate: impl FnMut(&R) -> bool,
+        now: Instant,
+    ) -> Option<Duration> {
+        let Self {
+            params,
+            recent_failures,
+        } = self;
+        recent_failures
+            .iter()
+            .filter(|(route, _)| predicate(route))
+            .map(|(_route, (when, count))| {
+                params.cooldown_delay(now.saturating_duration_since(*when), *count)
+            })
+            .min()
+    }
+
+    /// Removes the outcomes that are older than
+    /// [`ConnectionOutcomeParams::age_cutoff`] as of `now`.
+    ///
+    /// Returns how many were removed. Aged-out outcomes are ignored anyway,
+    /// and are removed whenever new outcomes are applied; this only makes
+    /// that happen sooner.
+    pub fn remove_aged_out(&mut self, now: Instant) -> usize {
+        let Self {
+            params,
+            recent_failures,
+        } = self;
+        remove_aged_out_failures(params, recent_failures, now)
+    }
+
+    /// Returns the recorded failures that haven't aged out as of `reference`,
+    /// with their times relative to it.
+    pub fn to_serializable(&self, reference: Instant) -> Vec<SerializableOutcome<R>> {
+        let Self {
+            params,
+            recent_failures,
+        } = self;
+        recent_failures
+            .iter()
+            .filter_map(|(route, (when, count))| {
+                let age = reference.saturating_duration_since(*when);
+                (age < params.age_cutoff).then(|| SerializableOutcome {
+                    route: route.clone(),
+                    age,
+                    consecutive_failures: *count,
+                })
+            })
+            .collect()
+    }
+
+    /// Creates a record from outcomes produced by [`Self::to_serializable`].
+    ///
+    /// See [`Self::extend_from_serializable`].
+    pub fn from_serializable(
+        params: ConnectionOutcomeParams,
+        outcomes: impl IntoIterator<Item = SerializableOutcome<R>>,
+        reference: Instant,
+    ) -> Self {
+        let mut this = Self::new(params);
+        let _added = this.extend_from_serializable(outcomes, reference);
+        this
+    }
+
+    /// Adds outcomes produced by [`Self::to_serializable`], with their times
+    /// taken relative to `reference`.
+    ///
+    /// Outcomes that are older than [`ConnectionOutcomeParams::age_cutoff`]
+    /// are dropped, as are outcomes for routes that already have a recorded
+    /// failure, since that's more current. Failure counts are capped at
+    /// [`ConnectionOutcomeParams::max_count`].
+    ///
+    /// Returns how many of the outcomes are still recorded afterwards, which
+    /// excludes any pruned to stay within
+    /// [`ConnectionOutcomeParams::max_entries`].
+    pub fn extend_from_serializable(
+        &mut self,
+        outcomes: impl IntoIterator<Item = SerializableOutcome<R>>,
+        reference: Instant,
+    ) -> usize {
+        use std::collections::hash_map::Entry;
+
+        let Self {
+            params,
+            recent_failures,
+        } = self;
+
+        let mut added = Vec::new();
+        for SerializableOutcome {
+            route,
+            age,
+            consecutive_failures,
+        } in outcomes
+        {
+            if age >= params.age_cutoff || consecutive_failures == 0 {
+                continue;
+            }
+            let Some(when) = reference.checked_sub(age) else {
+                continue;
+            };
+            if let Entry::Vacant(entry) = recent_failures.entry(route.clone()) {
+                entry.insert((when, consecutive_failures.min(params.max_count)));
+                added.push(route);
+            }
+        }
+
+        // Reuse the usual pruning to respect max_entries.
+        self.apply_outcome_updates([], reference);
+
+        added
+            .iter()
+            .filter(|route| self.recent_failures.contains_key(route))
+            .count()
     }
 
     /// Clear any outcomes from before the cutoff.
@@ -364,6 +578,18 @@ impl<R: Hash + Eq + Clone> ConnectionOutcomes<R> {
     }
 }
 
+fn remove_aged_out_failures<R>(
+    params: &ConnectionOutcomeParams,
+    recent_failures: &mut HashMap<R, (Instant, u8)>,
+    now: Instant,
+) -> usize {
+    let before = recent_failures.len();
+    recent_failures.retain(|_route, (last_time, _failure_count)| {
+        now.saturating_duration_since(*last_time) < params.age_cutoff
+    });
+    before - recent_failures.len()
+}
+
 impl<P: RouteDelayPolicy<R>, R> RouteDelayPolicy<R> for &P {
     fn compute_delay(&self, route: &R, now: Instant) -> Duration {
         P::compute_delay(self, route, now)
@@ -388,11 +614,44 @@ impl<R: Hash + Eq> RouteDelayPolicy<R> for ConnectionOutcomes<R> {
             return Duration::ZERO;
         };
 
-        params.compute_delay(now.saturating_duration_since(*when), *count)
+        params.cooldown_delay(now.saturating_duration_since(*when), *count)
     }
 }
 
 impl ConnectionOutcomeParams {
+    /// Like [`Self::compute_delay`], but also applies the
+    /// [`AgedOutSuccessOrdering`] margin to routes that are still cooling down.
+    fn cooldown_delay(
+        &self,
+        since_last_failure: Duration,
+        consecutive_failure_count: u8,
+    ) -> Duration {
+        let delay = self.compute_delay(since_last_failure, consecutive_failure_count);
+        match self.aged_out_successes {
+            AgedOutSuccessOrdering::PreferOverCoolingDown(margin) if delay != Duration::ZERO => {
+                delay + margin
+            }
+            AgedOutSuccessOrdering::Unranked | AgedOutSuccessOrdering::PreferOverCoolingDown(_) => {
+                delay
+            }
+        }
+    }
+
+    /// Randomizes `delay` by up to [`Self::jitter_fraction`] of itself in
+    /// either direction, without exceeding [`Self::max_delay`].
+    pub fn apply_jitter(&self, delay: Duration, context: &impl RouteProviderContext) -> Duration {
+        let fraction = self.jitter_fraction.clamp(0.0, 1.0);
+        if fraction.is_nan() || fraction == 0.0 || delay.is_zero() {
+            return delay;
+        }
+
+        // Uniform in [-1, 1].
+        let offset = 2.0 * (context.random_usize() as f64 / usize::MAX as f64) - 1.0;
+        delay
+            .mul_f64((1.0 + offset * fraction).max(0.0))
+            .min(self.max_delay)
+    }
+
     /// Compute the delay given the time since the last failure and count of
     /// repeated failures.
     ///
@@ -409,6 +668,9 @@ impl ConnectionOutcomeParams {
             count_growth_factor,
             max_count,
             max_delay,
+            max_entries: _,
+            aged_out_successes: _,
+            jitter_fraction: _,
         } = *self;
 
         // Exponential backoff: as the count grows, the delay should be longer.
@@ -712,13 +974,15 @@ mod test {
     use const_str::ip_addr;
     use futures_util::FutureExt as _;
     use itertools::Itertools as _;
+    use nonzero_ext::nonzero;
     use proptest::proptest;
+    use test_case::test_case;
     use tokio::sync::mpsc;
     use tokio_stream::wrappers::UnboundedReceiverStream;
 
     use super::*;
     use crate::dns::lookup_result::LookupResult;
-    use crate::route::testutils::FakeRoute;
+    use crate::route::testutils::{FakeContext, FakeRoute};
     use crate::route::{NoDelay, UnresolvedHost};
     use crate::DnsSource;
 
@@ -737,7 +1001,10 @@ mod test {
 
     #[tokio::test(start_paused = true)]
     async fn single_resolved_route_e2e() {
-        let resolver = RouteResolver { allow_ipv6: true };
+        let resolver = RouteResolver {
+            allow_ipv6: true,
+            dedupe_targets: false,
+        };
         let name_resolver = HashMap::from([(
             "domain-name",
             LookupResult {
@@ -771,7 +1038,10 @@ mod test {
 
     #[tokio::test(start_paused = true)]
     async fn multiple_resolved_routes_e2e() {
-        let resolver = RouteResolver { allow_ipv6: true };
+        let resolver = RouteResolver {
+            allow_ipv6: true,
+            dedupe_targets: false,
+        };
 
         let name_resolver = HashMap::from([
             (
@@ -848,6 +1118,9 @@ mod test {
                 count_growth_factor,
                 max_count: COUNT_CUTOFF,
                 max_delay: MAX_DELAY,
+                max_entries: None,
+                aged_out_successes: AgedOutSuccessOrdering::Unranked,
+                jitter_fraction: 0.0,
             };
 
             // Lots of failures, the last one recent.
@@ -900,6 +1173,9 @@ mod test {
             count_growth_factor: 10.0,
             max_count: MAX_COUNT,
             max_delay: MAX_DELAY,
+            max_entries: None,
+            aged_out_successes: AgedOutSuccessOrdering::Unranked,
+            jitter_fraction: 0.0,
         });
 
         const ROUTE: &str = "route";
@@ -941,6 +1217,9 @@ mod test {
             count_growth_factor: 10.0,
             max_count: MAX_COUNT,
             max_delay: MAX_DELAY,
+            max_entries: None,
+            aged_out_successes: AgedOutSuccessOrdering::Unranked,
+            jitter_fraction: 0.0,
         });
 
         const ROUTE: &str = "route";
@@ -989,6 +1268,9 @@ mod test {
             count_growth_factor: 10.0,
             max_count: MAX_COUNT,
             max_delay: MAX_DELAY,
+            max_entries: None,
+            aged_out_successes: AgedOutSuccessOrdering::Unranked,
+            jitter_fraction: 0.0,
         });
 
         const ROUTE: &str = "route";
@@ -1008,6 +1290,285 @@ mod test {
         );
     }
 
+    #[test]
+    fn connection_outcomes_min_delay_matching() {
+        let mut outcomes = ConnectionOutcomes::new(ConnectionOutcomeParams {
+            age_cutoff: Duration::from_secs(1000),
+            cooldown_growth_factor: 2.0,
+            count_growth_factor: 10.0,
+            max_count: 5,
+            max_delay: Duration::from_secs(100),
+            max_entries: None,
+            aged_out_successes: AgedOutSuccessOrdering::Unranked,
+            jitter_fraction: 0.0,
+        });
+
+        let start = Instant::now();
+        for _ in 0..3 {
+            outcomes.record_outcome("a1", start, Duration::ZERO, Err(UnsuccessfulOutcome));
+        }
+        outcomes.record_outcome("a2", start, Duration::ZERO, Err(UnsuccessfulOutcome));
+
+        let delay_for = |prefix: &str| {
+            outcomes.min_delay_matching(|route: &&str| route.starts_with(prefix), start)
+        };
+        assert_eq!(delay_for("a"), Some(outcomes.compute_delay(&"a2", start)));
+        assert!(delay_for("a") < Some(outcomes.compute_delay(&"a1", start)));
+        assert_eq!(delay_for("b"), None);
+    }
+
+    #[test]
+    fn connection_outcomes_max_entries() {
+        const AGE_CUTOFF: Duration = Duration::from_secs(1000);
+        let mut outcomes = ConnectionOutcomes::new(ConnectionOutcomeParams {
+            age_cutoff: AGE_CUTOFF,
+            cooldown_growth_factor: 2.0,
+            count_growth_factor: 10.0,
+            max_count: 5,
+            max_delay: Duration::from_secs(100),
+            max_entries: Some(nonzero!(3usize)),
+            aged_out_successes: AgedOutSuccessOrdering::Unranked,
+            jitter_fraction: 0.0,
+        });
+
+        let start = Instant::now();
+        // A failure that will have aged out by the time the others are recorded.
+        outcomes.record_outcome("expired", start, Duration::ZERO, Err(UnsuccessfulOutcome));
+
+        const ROUTES: [&str; 10] = ["r0", "r1", "r2", "r3", "r4", "r5", "r6", "r7", "r8", "r9"];
+        let later = start + AGE_CUTOFF;
+        for (i, route) in (0..).zip(ROUTES) {
+            outcomes.record_outcome(
+                route,
+                later + Duration::from_secs(i),
+                Duration::ZERO,
+                Err(UnsuccessfulOutcome),
+            );
+            assert!(outcomes.recent_failures.len() <= 3);
+        }
+        // Failing again on an older route makes it recent.
+        outcomes.record_outcome(
+            "r7",
+            later + Duration::from_secs(10),
+            Duration::ZERO,
+            Err(UnsuccessfulOutcome),
+        );
+
+        assert_eq!(
+            outcomes
+                .recent_failures
+                .iter()
+                .map(|(route, (_when, count))| (*route, *count))
+                .sorted()
+                .collect_vec(),
+            [("r7", 2), ("r8", 1), ("r9", 1)]
+        );
+    }
+
+    #[test]
+    fn connection_outcomes_max_entries_prefers_expired() {
+        const AGE_CUTOFF: Duration = Duration::from_secs(1000);
+        let mut outcomes = ConnectionOutcomes::new(ConnectionOutcomeParams {
+            age_cutoff: AGE_CUTOFF,
+            cooldown_growth_factor: 2.0,
+            count_growth_factor: 10.0,
+            max_count: 5,
+            max_delay: Duration::from_secs(100),
+            max_entries: Some(nonzero!(2usize)),
+            aged_out_successes: AgedOutSuccessOrdering::Unranked,
+            jitter_fraction: 0.0,
+        });
+
+        let start = Instant::now();
+        outcomes.record_outcome("old", start, Duration::ZERO, Err(UnsuccessfulOutcome));
+        outcomes.record_outcome(
+            "recent",
+            start + AGE_CUTOFF / 2,
+            Duration::ZERO,
+            Err(UnsuccessfulOutcome),
+        );
+        // By now "old" has aged out, so it's dropped rather than "recent".
+        outcomes.record_outcome(
+            "new",
+            start + AGE_CUTOFF,
+            Duration::ZERO,
+            Err(UnsuccessfulOutcome),
+        );
+
+        assert_eq!(
+            outcomes
+                .recent_failures
+                .keys()
+                .copied()
+                .sorted()
+                .collect_vec(),
+            ["new", "recent"]
+        );
+    }
+
+    #[test]
+    fn connection_outcome_params_jitter_stays_in_bounds() {
+        const MAX_DELAY: Duration = Duration::from_secs(10);
+        let params = ConnectionOutcomeParams {
+            age_cutoff: Duration::from_secs(1000),
+            cooldown_growth_factor: 2.0,
+            count_growth_factor: 10.0,
+            max_count: 5,
+            max_delay: MAX_DELAY,
+            max_entries: None,
+            aged_out_successes: AgedOutSuccessOrdering::Unranked,
+            jitter_fraction: 0.5,
+        };
+        let context = FakeContext::new();
+
+        let delay = Duration::from_secs(4);
+        let jittered = (0..100)
+            .map(|_| params.apply_jitter(delay, &context))
+            .collect_vec();
+        assert!(
+            jittered
+                .iter()
+                .all(|d| (delay / 2..=delay * 3 / 2).contains(d)),
+            "{jittered:?}"
+        );
+        assert!(jittered.iter().any(|d| *d != jittered[0]), "{jittered:?}");
+
+        for _ in 0..100 {
+            assert!(params.apply_jitter(MAX_DELAY, &context) <= MAX_DELAY);
+        }
+        assert_eq!(
+            params.apply_jitter(Duration::ZERO, &context),
+            Duration::ZERO
+        );
+
+        let deterministic = ConnectionOutcomeParams {
+            jitter_fraction: 0.0,
+            ..params
+        };
+        assert_eq!(deterministic.apply_jitter(delay, &context), delay);
+    }
+
+    #[test]
+    fn connection_outcomes_serializable_round_trip() {
+        const AGE_CUTOFF: Duration = Duration::from_secs(1000);
+        let params = ConnectionOutcomeParams {
+            age_cutoff: AGE_CUTOFF,
+            cooldown_growth_factor: 2.0,
+            count_growth_factor: 10.0,
+            max_count: 5,
+            max_delay: Duration::from_secs(100),
+            max_entries: None,
+            aged_out_successes: AgedOutSuccessOrdering::Unranked,
+            jitter_fraction: 0.0,
+        };
+        let mut outcomes = ConnectionOutcomes::new(params.clone());
+
+        let start = Instant::now();
+        outcomes.record_outcome("old", start, Duration::ZERO, Err(UnsuccessfulOutcome));
+        let later = start + AGE_CUTOFF / 2;
+        for _ in 0..3 {
+            outcomes.record_outcome("recent", later, Duration::ZERO, Err(UnsuccessfulOutcome));
+        }
+
+        let now = later + Duration::from_secs(10);
+        let serialized = outcomes.to_serializable(now);
+        assert_eq!(
+            serialized
+                .iter()
+                .map(|outcome| (outcome.route, outcome.age, outcome.consecutive_failures))
+                .sorted()
+                .collect_vec(),
+            [
+                ("old", AGE_CUTOFF / 2 + Duration::from_secs(10), 1),
+                ("recent", Duration::from_secs(10), 3)
+            ]
+        );
+
+        // Restored relative to a different reference point, the delays are
+        // the same.
+        let restored_now = now + AGE_CUTOFF;
+        let restored =
+            ConnectionOutcomes::from_serializable(params.clone(), serialized.clone(), restored_now);
+        for route in ["old", "recent"] {
+            assert_eq!(
+                restored.compute_delay(&route, restored_now),
+                outcomes.compute_delay(&route, now),
+                "{route}"
+            );
+        }
+
+        // Outcomes that are too old by the time they're restored are dropped.
+        let aged = ConnectionOutcomes::from_serializable(
+            params,
+            serialized.into_iter().map(|outcome| SerializableOutcome {
+                age: outcome.age + AGE_CUTOFF / 2,
+                ..outcome
+            }),
+            restored_now,
+        );
+        assert_eq!(
+            aged.recent_failures.keys().copied().collect_vec(),
+            ["recent"]
+        );
+    }
+
+    #[test_case(
+        AgedOutSuccessOrdering::Unranked,
+        [ip_addr!("192.0.2.1"), ip_addr!("192.0.2.2")];
+        "unranked"
+    )]
+    #[test_case(
+        AgedOutSuccessOrdering::PreferOverCoolingDown(Duration::from_secs(1)),
+        [ip_addr!("192.0.2.2"), ip_addr!("192.0.2.1")];
+        "prefer aged-out success"
+    )]
+    #[tokio::test(start_paused = true)]
+    async fn schedule_orders_aged_out_success_against_cooling_down_failure(
+        aged_out_successes: AgedOutSuccessOrdering,
+        expected_order: [IpAddr; 2],
+    ) {
+        const AGE_CUTOFF: Duration = Duration::from_secs(1000);
+        const FAILED: FakeRoute<IpAddr> = FakeRoute(ip_addr!("192.0.2.1"));
+        const SUCCEEDED: FakeRoute<IpAddr> = FakeRoute(ip_addr!("192.0.2.2"));
+
+        let mut outcomes = ConnectionOutcomes::new(ConnectionOutcomeParams {
+            age_cutoff: AGE_CUTOFF,
+            cooldown_growth_factor: 2.0,
+            count_growth_factor: 10.0,
+            max_count: 1,
+            // Shorter than the happy-eyeballs stagger, so without a preference
+            // the failed route still goes first.
+            max_delay: HAPPY_EYEBALLS_DELAY / 2,
+            max_entries: None,
+            aged_out_successes,
+            jitter_fraction: 0.0,
+        });
+
+        let start = Instant::now();
+        outcomes.record_outcome(SUCCEEDED, start, Duration::ZERO, Ok(()));
+        tokio::time::advance(AGE_CUTOFF).await;
+        outcomes.record_outcome(
+            FAILED,
+            Instant::now(),
+            Duration::ZERO,
+            Err(UnsuccessfulOutcome),
+        );
+
+        let resolver_stream = futures_util::stream::iter([(
+            ResolvedRoutes {
+                routes: vec![FAILED, SUCCEEDED],
+            },
+            ResolveMeta {
+                original_group_index: 0,
+            },
+        )]);
+        let schedule = Schedule::new(resolver_stream.fuse(), &outcomes, Duration::from_secs(1));
+        let schedule = std::pin::pin!(schedule);
+        let order: Vec<_> = schedule.as_stream().collect().await;
+
+        assert_eq!(order, expected_order.map(FakeRoute));
+    }
+
     #[tokio::test(start_paused = true)]
     async fn min_kvq_stream_debounce() {
         use std::task::Poll;
@@ -8,13 +8,22 @@ use std::sync::Arc;
 use crate::certs::RootCertificates;
 use crate::host::Host;
 use crate::route::{ReplaceFragment, RouteProvider, RouteProviderContext, SimpleRoute};
-use crate::Alpn;
+use crate::{Alpn, AlpnList};
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct TlsRouteFragment {
     pub root_certs: RootCertificates,
     pub sni: Host<Arc<str>>,
-    pub alpn: Option<Alpn>,
+    pub alpn: AlpnList,
+    /// The server's published ECHConfigList, if Encrypted Client Hello should
+    /// be used to hide `sni`.
+    ///
+    /// The list is in the TLS wire format, as found in the `ech` parameter of
+    /// a DNS HTTPS record. If it can't be used, the handshake falls back to
+    /// sending `sni` in plaintext. If the server rejects it, the handshake
+    /// fails; [`EchRetryConnector`](crate::route::EchRetryConnector) retries
+    /// with the server's replacement configs.
+    pub ech_config_list: Option<Arc<[u8]>>,
 }
 
 pub type TlsRoute<T> = SimpleRoute<TlsRouteFragment, T>;
@@ -23,19 +32,40 @@ pub type TlsRoute<T> = SimpleRoute<TlsRouteFragment, T>;
 pub struct TlsRouteProvider<P> {
     pub(crate) sni: Host<Arc<str>>,
     pub(crate) certs: RootCertificates,
+    pub(crate) ech_config_list: Option<Arc<[u8]>>,
     pub(crate) inner: P,
 }
 
 impl<T> TlsRouteProvider<T> {
     pub fn new(certs: RootCertificates, sni: Host<Arc<str>>, inner: T) -> Self {
-        Self { sni, certs, inner }
+        Self {
+            sni,
+            certs,
+            ech_config_list: None,
+            inner,
+        }
+    }
+
+    /// Offers Encrypted Client Hello on the produced routes.
+    ///
+    /// See [`TlsRouteFragment::ech_config_list`].
+    pub fn with_ech_config_list(self, ech_config_list: Arc<[u8]>) -> Self {
+        Self {
+            ech_config_list: Some(ech_config_list),
+            ..self
+        }
     }
 }
 
 /// Sets the [`Alpn`] value for a route or route fragment.
 pub(crate) trait SetAlpn {
     /// Sets the `Alpn` for `self`.
-    fn set_alpn(&mut self, alpn: Alpn);
+    fn set_alpn(&mut self, alpn: Alpn) {
+        self.set_alpn_list(alpn.into())
+    }
+
+    /// Sets the protocols to offer for `self`, most preferred first.
+    fn set_alpn_list(&mut self, alpn: AlpnList);
 }
 
 impl<P: RouteProvider> RouteProvider for TlsRouteProvider<P> {
@@ -45,13 +75,19 @@ impl<P: RouteProvider> RouteProvider for TlsRouteProvider<P> {
         &'s self,
         context: &impl RouteProviderContext,
     ) -> impl Iterator<Item = Self::Route> + 's {
-        let Self { sni, certs, inner } = self;
+        let Self {
+            sni,
+            certs,
+            ech_config_list,
+            inner,
+        } = self;
 
         inner.routes(context).map(|route| TlsRoute {
             fragment: TlsRouteFragment {
                 root_certs: certs.clone(),
                 sni: sni.clone(),
-                alpn: None,
+                alpn: AlpnList::default(),
+                ech_config_list: ech_config_list.clone(),
             },
             inner: route,
         })
@@ -71,14 +107,14 @@ impl<R: ReplaceFragment<S>, S> ReplaceFragment<S> for TlsRoute<R> {
 }
 
 impl<T> SetAlpn for TlsRoute<T> {
-    fn set_alpn(&mut self, alpn: Alpn) {
-        self.fragment.set_alpn(alpn)
+    fn set_alpn_list(&mut self, alpn: AlpnList) {
+        self.fragment.set_alpn_list(alpn)
     }
 }
 
 impl SetAlpn for TlsRouteFragment {
-    fn set_alpn(&mut self, alpn: Alpn) {
-        self.alpn = Some(alpn);
+    fn set_alpn_list(&mut self, alpn: AlpnList) {
+        self.alpn = alpn;
     }
 }
 
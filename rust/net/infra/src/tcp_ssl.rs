@@ -4,7 +4,7 @@
 //
 
 use std::future::Future;
-use std::net::IpAddr;
+use std::net::{IpAddr, SocketAddr};
 use std::num::NonZeroU16;
 use std::sync::Arc;
 use std::time::Duration;
@@ -25,13 +25,13 @@ use crate::route::{
     TlsRouteFragment,
 };
 use crate::tcp_ssl::proxy::tls::TlsProxyConnector;
-use crate::timeouts::TCP_CONNECTION_ATTEMPT_DELAY;
+use crate::timeouts::{TCP_CONNECTION_ATTEMPT_DELAY, TCP_FAST_OPEN_FALLBACK_TIMEOUT};
 #[cfg(feature = "dev-util")]
 #[allow(unused_imports)]
 use crate::utils::development_only_enable_nss_standard_debug_interop;
 use crate::utils::first_ok;
 use crate::{
-    Alpn, AsyncDuplexStream, Connection, RouteType, ServiceConnectionInfo, StreamAndInfo,
+    Alpn, AlpnList, AsyncDuplexStream, Connection, RouteType, ServiceConnectionInfo, StreamAndInfo,
     TransportConnectionParams, TransportConnector,
 };
 
@@ -106,7 +106,22 @@ pub struct DirectConnector {
 
 /// Stateless [`Connector`] for [`TcpRoute`]s.
 #[derive(Debug, Default)]
-pub struct StatelessTcp;
+pub struct StatelessTcp {
+    /// Whether to try connecting with TCP Fast Open first.
+    ///
+    /// If the Fast Open attempt fails or doesn't complete within
+    /// [`TCP_FAST_OPEN_FALLBACK_TIMEOUT`], a regular connection is made
+    /// instead, well within the per-route timeout. Fast Open is currently only
+    /// supported on Linux; elsewhere this always falls back immediately.
+    pub tcp_fast_open: bool,
+    /// A DSCP value to mark outgoing packets with, for networks that
+    /// prioritize traffic based on it.
+    ///
+    /// Only the low six bits are used. Networks are free to ignore or rewrite
+    /// the marking, and if it can't be set on the socket the connection is
+    /// made without it. Currently only supported on Linux.
+    pub dscp: Option<u8>,
+}
 
 /// Stateless [`Connector`] for [`TlsRouteFragment`]s.
 #[derive(Debug, Default)]
@@ -157,15 +172,159 @@ impl Connector<TcpRoute<IpAddr>, ()> for StatelessTcp {
         &self,
         (): (),
         route: TcpRoute<IpAddr>,
-        _log_tag: Arc<str>,
+        log_tag: Arc<str>,
     ) -> impl Future<Output = Result<Self::Connection, Self::Error>> {
+        let Self {
+            tcp_fast_open,
+            dscp,
+        } = *self;
         let TcpRoute { address, port } = route;
+        let address = SocketAddr::new(address, port.get());
 
-        TcpStream::connect((address, port.get()))
+        async move {
+            if tcp_fast_open {
+                connect_with_fast_open_fallback(
+                    address,
+                    dscp,
+                    connect_fast_open(address, dscp, &log_tag),
+                    &log_tag,
+                )
+                .await
+            } else {
+                connect_with_dscp(address, dscp, &log_tag).await
+            }
             .map_err(|_e| TransportConnectError::TcpConnectionFailed)
+        }
+    }
+}
+
+/// Makes a TCP connection, trying `fast_open_attempt` first.
+///
+/// The attempt is abandoned after [`TCP_FAST_OPEN_FALLBACK_TIMEOUT`] so that a
+/// network that drops Fast Open SYNs doesn't use up the whole per-route timeout.
+async fn connect_with_fast_open_fallback(
+    address: SocketAddr,
+    dscp: Option<u8>,
+    fast_open_attempt: impl Future<Output = std::io::Result<TcpStream>>,
+    log_tag: &str,
+) -> std::io::Result<TcpStream> {
+    match tokio::time::timeout(TCP_FAST_OPEN_FALLBACK_TIMEOUT, fast_open_attempt).await {
+        Ok(Ok(stream)) => return Ok(stream),
+        Ok(Err(e)) => log::info!(
+            "[{log_tag}] TCP Fast Open connect failed ({kind}); falling back",
+            kind = e.kind()
+        ),
+        Err(_elapsed) => {
+            log::info!("[{log_tag}] TCP Fast Open connect timed out; falling back")
+        }
+    }
+    connect_with_dscp(address, dscp, log_tag).await
+}
+
+/// Makes a TCP connection, marking the socket with `dscp` if provided.
+async fn connect_with_dscp(
+    address: SocketAddr,
+    dscp: Option<u8>,
+    log_tag: &str,
+) -> std::io::Result<TcpStream> {
+    let Some(dscp) = dscp else {
+        return TcpStream::connect(address).await;
+    };
+    let socket = new_tcp_socket(address)?;
+    set_dscp(&socket, address, dscp, log_tag);
+    socket.connect(address).await
+}
+
+fn new_tcp_socket(address: SocketAddr) -> std::io::Result<tokio::net::TcpSocket> {
+    match address {
+        SocketAddr::V4(_) => tokio::net::TcpSocket::new_v4(),
+        SocketAddr::V6(_) => tokio::net::TcpSocket::new_v6(),
     }
 }
 
+/// Sets an integer-valued socket option.
+#[cfg(target_os = "linux")]
+fn set_socket_option(
+    socket: &tokio::net::TcpSocket,
+    level: libc::c_int,
+    name: libc::c_int,
+    value: libc::c_int,
+) -> std::io::Result<()> {
+    use std::os::fd::AsRawFd as _;
+
+    let option_len = libc::socklen_t::try_from(std::mem::size_of_val(&value))
+        .expect("c_int size fits in socklen_t");
+    // SAFETY: the file descriptor is owned by `socket` and stays open for the
+    // duration of the call, and the option value points to a live c_int whose
+    // size is passed along with it.
+    let result = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            level,
+            name,
+            std::ptr::from_ref(&value).cast(),
+            option_len,
+        )
+    };
+    if result != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Marks packets sent on `socket` with `dscp`, logging instead of failing if
+/// that isn't possible.
+#[cfg(target_os = "linux")]
+fn set_dscp(socket: &tokio::net::TcpSocket, address: SocketAddr, dscp: u8, log_tag: &str) {
+    // The DSCP is the upper six bits of the IPv4 TOS or IPv6 traffic class.
+    let traffic_class = u32::from(dscp & 0x3f) << 2;
+    let socket = socket2::SockRef::from(socket);
+    let result = match address {
+        SocketAddr::V4(_) => socket.set_tos(traffic_class),
+        SocketAddr::V6(_) => socket.set_tclass_v6(traffic_class),
+    };
+    if let Err(e) = result {
+        log::info!(
+            "[{log_tag}] failed to set DSCP ({kind}); connecting without it",
+            kind = e.kind()
+        );
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_dscp(_socket: &tokio::net::TcpSocket, _address: SocketAddr, _dscp: u8, log_tag: &str) {
+    log::info!("[{log_tag}] DSCP marking is not supported on this platform; connecting without it");
+}
+
+/// Connects with `TCP_FASTOPEN_CONNECT` set on the socket.
+///
+/// If the kernel has a Fast Open cookie cached for the server, the SYN is
+/// deferred until the first write so it can carry data; the kernel itself
+/// stops sending data on SYNs for that destination if they go unanswered.
+#[cfg(target_os = "linux")]
+async fn connect_fast_open(
+    address: SocketAddr,
+    dscp: Option<u8>,
+    log_tag: &str,
+) -> std::io::Result<TcpStream> {
+    let socket = new_tcp_socket(address)?;
+    if let Some(dscp) = dscp {
+        set_dscp(&socket, address, dscp, log_tag);
+    }
+    set_socket_option(&socket, libc::IPPROTO_TCP, libc::TCP_FASTOPEN_CONNECT, 1)?;
+
+    socket.connect(address).await
+}
+
+#[cfg(not(target_os = "linux"))]
+async fn connect_fast_open(
+    _address: SocketAddr,
+    _dscp: Option<u8>,
+    _log_tag: &str,
+) -> std::io::Result<TcpStream> {
+    Err(std::io::ErrorKind::Unsupported.into())
+}
+
 impl<Inner> Connector<TlsRouteFragment, Inner> for StatelessTls
 where
     Inner: AsyncDuplexStream,
@@ -178,16 +337,22 @@ where
         &self,
         inner: Inner,
         fragment: TlsRouteFragment,
-        _log_tag: Arc<str>,
+        log_tag: Arc<str>,
     ) -> impl Future<Output = Result<Self::Connection, Self::Error>> + Send {
         let TlsRouteFragment {
             root_certs,
             sni,
             alpn,
+            ech_config_list,
         } = fragment;
         let host = sni;
 
-        let ssl_config = ssl_config(&root_certs, host.as_deref(), alpn);
+        let ssl_config = ssl_config(&root_certs, host.as_deref(), alpn).map(|mut config| {
+            if let Some(ech_config_list) = ech_config_list {
+                offer_ech(&mut config, host.as_deref(), &ech_config_list, &log_tag);
+            }
+            config
+        });
 
         async move {
             let domain = match &host {
@@ -205,19 +370,23 @@ where
 
 impl<S: Connection> Connection for SslStream<S> {
     fn transport_info(&self) -> crate::TransportInfo {
-        self.get_ref().transport_info()
+        let mut info = self.get_ref().transport_info();
+        if let Some(alpn) = self.ssl().selected_alpn_protocol() {
+            info.negotiated_alpn = Alpn::from_protocol_name(alpn);
+        }
+        info
     }
 }
 
 fn ssl_config(
     certs: &RootCertificates,
     host: Host<&str>,
-    alpn: Option<Alpn>,
+    alpn: AlpnList,
 ) -> Result<ConnectConfiguration, TransportConnectError> {
     let mut ssl = SslConnector::builder(SslMethod::tls_client())?;
     certs.apply_to_connector(&mut ssl, host)?;
-    if let Some(alpn) = alpn {
-        ssl.set_alpn_protos(alpn.as_ref())?;
+    if !alpn.is_empty() {
+        ssl.set_alpn_protos(&alpn.to_wire_format())?;
     }
 
     // This is just the default Boring TLS supported signature scheme list
@@ -243,6 +412,33 @@ fn ssl_config(
     Ok(ssl.build().configure()?)
 }
 
+/// Configures `ssl` to hide `host` using Encrypted Client Hello.
+///
+/// This is best-effort: if `ech_config_list` can't be used, the handshake goes
+/// ahead with `host` as the plaintext SNI instead of failing the connection.
+/// A server that is sent ECH but declines it will still cause the handshake
+/// to fail with [`TransportConnectError::EchRejected`]; see
+/// [`EchRetryConnector`](crate::route::EchRetryConnector) for recovering from
+/// that.
+fn offer_ech(
+    ssl: &mut ConnectConfiguration,
+    host: Host<&str>,
+    ech_config_list: &[u8],
+    log_tag: &str,
+) {
+    if let Host::Ip(_) = host {
+        // ECH can only hide a server name; there's no way to send an IP address.
+        log::debug!("[{log_tag}] not offering ECH for an IP address SNI");
+        return;
+    }
+    match ssl.set_ech_config_list(ech_config_list) {
+        Ok(()) => log::debug!("[{log_tag}] offering ECH"),
+        Err(_e) => {
+            log::info!("[{log_tag}] ECH config list is unusable; sending SNI in plaintext")
+        }
+    }
+}
+
 async fn connect_tls<S: AsyncDuplexStream>(
     transport: S,
     connection_params: &TransportConnectionParams,
@@ -252,7 +448,8 @@ async fn connect_tls<S: AsyncDuplexStream>(
     let route = TlsRouteFragment {
         root_certs: connection_params.certs.clone(),
         sni: Host::Domain(Arc::clone(&connection_params.sni)),
-        alpn: Some(alpn),
+        alpn: alpn.into(),
+        ech_config_list: None,
     };
 
     StatelessTls.connect_over(transport, route, log_tag).await
@@ -297,7 +494,7 @@ async fn connect_tcp(
     // First, for each resolved IP address, constructing a future
     // that incorporates the delay based on its position in the list.
     // This way we can start all futures at once and simply wait for the first one to complete successfully.
-    let connector = StatelessTcp;
+    let connector = StatelessTcp::default();
     let staggered_futures = dns_lookup.into_iter().enumerate().map(|(idx, ip)| {
         let delay = TCP_CONNECTION_ATTEMPT_DELAY * idx.try_into().unwrap();
         let connector = &connector;
@@ -460,6 +657,7 @@ mod test {
     use super::testutil::*;
     use super::*;
     use crate::dns::lookup_result::LookupResult;
+    use crate::errors::CertificateValidity;
     use crate::host::Host;
 
     #[test_case(true; "resolved hostname")]
@@ -529,4 +727,396 @@ mod test {
             }
         }
     }
+
+    fn tls_acceptor_builder(
+        certificate: &rcgen::Certificate,
+        key_pair: &rcgen::KeyPair,
+    ) -> boring_signal::ssl::SslAcceptorBuilder {
+        let mut builder =
+            boring_signal::ssl::SslAcceptor::mozilla_intermediate_v5(SslMethod::tls_server())
+                .expect("can build");
+        builder
+            .set_certificate(
+                &boring_signal::x509::X509::from_der(certificate.der()).expect("valid"),
+            )
+            .expect("can set certificate");
+        builder
+            .set_private_key(
+                &boring_signal::pkey::PKey::private_key_from_der(key_pair.serialized_der())
+                    .expect("valid"),
+            )
+            .expect("can set key");
+        builder
+    }
+
+    /// Connects to a localhost server that accepts a single TLS connection.
+    ///
+    /// The server's handshake result is ignored.
+    async fn connect_tls_to_localhost(
+        acceptor: boring_signal::ssl::SslAcceptor,
+        trusted_certificate: &rcgen::Certificate,
+        alpn: AlpnList,
+    ) -> Result<SslStream<TcpStream>, TransportConnectError> {
+        use crate::route::{ConnectorExt as _, TlsRoute};
+
+        let listener = tokio::net::TcpListener::bind((Ipv6Addr::LOCALHOST, 0))
+            .await
+            .expect("can bind");
+        let addr = listener.local_addr().expect("bound");
+
+        let route = TlsRoute {
+            fragment: TlsRouteFragment {
+                root_certs: RootCertificates::FromDer(Cow::Owned(
+                    trusted_certificate.der().to_vec(),
+                )),
+                sni: Host::Domain(SERVER_HOSTNAME.into()),
+                alpn,
+                ech_config_list: None,
+            },
+            inner: TcpRoute {
+                address: addr.ip(),
+                port: addr.port().try_into().expect("bound port"),
+            },
+        };
+
+        let connector = crate::route::ComposedConnector::<_, _, TransportConnectError>::new(
+            StatelessTls,
+            StatelessTcp::default(),
+        );
+        let (client, _server) = tokio::join!(connector.connect(route, "test".into()), async {
+            let (tcp, _) = listener.accept().await.expect("can accept");
+            tokio_boring_signal::accept(&acceptor, tcp).await
+        });
+        client
+    }
+
+    #[tokio::test]
+    async fn negotiates_alpn_preferred_by_server() {
+        use boring_signal::ssl::{select_next_proto, AlpnError};
+
+        let mut acceptor =
+            tls_acceptor_builder(&SERVER_CERTIFICATE.cert, &SERVER_CERTIFICATE.key_pair);
+        // The server prefers HTTP/2, even though it's the client's second choice.
+        acceptor.set_alpn_select_callback(|_ssl, client_protos| {
+            select_next_proto(Alpn::Http2.as_ref(), client_protos).ok_or(AlpnError::NOACK)
+        });
+
+        let client = connect_tls_to_localhost(
+            acceptor.build(),
+            &SERVER_CERTIFICATE.cert,
+            AlpnList::new([Alpn::Http1_1, Alpn::Http2]),
+        )
+        .await
+        .expect("can connect");
+
+        assert_eq!(client.transport_info().negotiated_alpn, Some(Alpn::Http2));
+    }
+
+    /// Starts a TLS connection to a localhost listener and returns the
+    /// extensions of the ClientHello it sends, as `(type, data)` pairs.
+    async fn client_hello_extensions(
+        tls_connector: impl Connector<TlsRouteFragment, TcpStream, Error = TransportConnectError> + Sync,
+        fragment: TlsRouteFragment,
+    ) -> Vec<(u16, Vec<u8>)> {
+        use tokio::io::AsyncReadExt as _;
+
+        use crate::route::{ConnectorExt as _, TlsRoute};
+
+        let listener = tokio::net::TcpListener::bind((Ipv6Addr::LOCALHOST, 0))
+            .await
+            .expect("can bind");
+        let addr = listener.local_addr().expect("bound");
+
+        let route = TlsRoute {
+            fragment,
+            inner: TcpRoute {
+                address: addr.ip(),
+                port: addr.port().try_into().expect("bound port"),
+            },
+        };
+
+        let connector = crate::route::ComposedConnector::<_, _, TransportConnectError>::new(
+            tls_connector,
+            StatelessTcp::default(),
+        );
+        let (_client, record) = tokio::join!(connector.connect(route, "test".into()), async {
+            let (mut tcp, _) = listener.accept().await.expect("can accept");
+            let mut header = [0; 5];
+            tcp.read_exact(&mut header).await.expect("can read");
+            assert_eq!(header[0], 0x16, "not a handshake record");
+            let mut record = vec![0; u16::from_be_bytes([header[3], header[4]]).into()];
+            tcp.read_exact(&mut record).await.expect("can read");
+            // Dropping the connection here fails the client's handshake.
+            record
+        });
+
+        fn take<'a>(bytes: &mut &'a [u8], len: usize) -> &'a [u8] {
+            let all: &'a [u8] = *bytes;
+            let (taken, rest) = all.split_at(len);
+            *bytes = rest;
+            taken
+        }
+        fn take_u16(bytes: &mut &[u8]) -> u16 {
+            let taken = take(bytes, 2);
+            u16::from_be_bytes([taken[0], taken[1]])
+        }
+
+        let mut hello = &record[..];
+        // handshake type, length, legacy_version, random
+        assert_eq!(take(&mut hello, 1), [1], "not a ClientHello");
+        take(&mut hello, 3 + 2 + 32);
+        let session_id_len = take(&mut hello, 1)[0].into();
+        take(&mut hello, session_id_len);
+        let cipher_suites_len = take_u16(&mut hello).into();
+        take(&mut hello, cipher_suites_len);
+        let compression_methods_len = take(&mut hello, 1)[0].into();
+        take(&mut hello, compression_methods_len);
+
+        let extensions_len = take_u16(&mut hello).into();
+        let mut extensions = take(&mut hello, extensions_len);
+        let mut result = vec![];
+        while !extensions.is_empty() {
+            let extension_type = take_u16(&mut extensions);
+            let len = take_u16(&mut extensions).into();
+            result.push((extension_type, take(&mut extensions, len).to_vec()));
+        }
+        result
+    }
+
+    /// Returns the host name from a ClientHello `server_name` extension.
+    fn server_name(extensions: &[(u16, Vec<u8>)]) -> Option<&str> {
+        const SERVER_NAME: u16 = 0;
+        let (_, data) = extensions.iter().find(|(t, _)| *t == SERVER_NAME)?;
+        // list length (2), name type (1), name length (2)
+        Some(std::str::from_utf8(&data[5..]).expect("valid UTF-8"))
+    }
+
+    const ENCRYPTED_CLIENT_HELLO: u16 = 0xfe0d;
+    const ECH_PUBLIC_NAME: &str = "public.example";
+
+    /// Builds an ECHConfigList with a single X25519/HKDF-SHA256/AES-128-GCM
+    /// config.
+    fn fake_ech_config_list() -> Vec<u8> {
+        let mut contents = vec![
+            0x01, // config_id
+            0x00, 0x20, // kem_id: DHKEM(X25519, HKDF-SHA256)
+            0x00, 0x20, // public_key length
+        ];
+        contents.extend([0x42; 32]);
+        contents.extend([
+            0x00, 0x04, // cipher_suites length
+            0x00, 0x01, // kdf_id: HKDF-SHA256
+            0x00, 0x01, // aead_id: AES-128-GCM
+            0x00, // maximum_name_length
+        ]);
+        contents.push(ECH_PUBLIC_NAME.len().try_into().expect("short"));
+        contents.extend(ECH_PUBLIC_NAME.as_bytes());
+        contents.extend([0x00, 0x00]); // extensions
+
+        let mut config = ENCRYPTED_CLIENT_HELLO.to_be_bytes().to_vec();
+        config.extend(u16::try_from(contents.len()).expect("short").to_be_bytes());
+        config.extend(contents);
+
+        let mut list = u16::try_from(config.len())
+            .expect("short")
+            .to_be_bytes()
+            .to_vec();
+        list.extend(config);
+        list
+    }
+
+    #[test_case(None, None; "not configured")]
+    #[test_case(Some(fake_ech_config_list()), Some(ECH_PUBLIC_NAME); "configured")]
+    #[test_case(Some(b"not a config list".to_vec()), None; "unusable config")]
+    #[tokio::test]
+    async fn offers_ech_when_configured(
+        ech_config_list: Option<Vec<u8>>,
+        expected_public_name: Option<&str>,
+    ) {
+        let extensions = client_hello_extensions(
+            StatelessTls,
+            TlsRouteFragment {
+                root_certs: RootCertificates::Native,
+                sni: Host::Domain(SERVER_HOSTNAME.into()),
+                alpn: AlpnList::default(),
+                ech_config_list: ech_config_list.map(Into::into),
+            },
+        )
+        .await;
+
+        let offered_ech = extensions.iter().any(|(t, _)| *t == ENCRYPTED_CLIENT_HELLO);
+        assert_eq!(offered_ech, expected_public_name.is_some());
+        // With ECH the real name is only in the encrypted inner ClientHello.
+        assert_eq!(
+            server_name(&extensions),
+            Some(expected_public_name.unwrap_or(SERVER_HOSTNAME))
+        );
+    }
+
+    #[test_case(None, "rotated-3.example"; "plaintext")]
+    #[test_case(Some(fake_ech_config_list()), ECH_PUBLIC_NAME; "with ECH")]
+    #[tokio::test]
+    async fn sni_mapping_connector_sends_derived_sni(
+        ech_config_list: Option<Vec<u8>>,
+        expected_server_name: &str,
+    ) {
+        let connector = crate::route::SniMappingConnector::new(StatelessTls, |host| {
+            assert_eq!(host, &Host::Domain(SERVER_HOSTNAME.into()));
+            Host::Domain("rotated-3.example".into())
+        });
+
+        let extensions = client_hello_extensions(
+            connector,
+            TlsRouteFragment {
+                root_certs: RootCertificates::Native,
+                sni: Host::Domain(SERVER_HOSTNAME.into()),
+                alpn: AlpnList::default(),
+                ech_config_list: ech_config_list.clone().map(Into::into),
+            },
+        )
+        .await;
+
+        let offered_ech = extensions.iter().any(|(t, _)| *t == ENCRYPTED_CLIENT_HELLO);
+        assert_eq!(offered_ech, ech_config_list.is_some());
+        assert_eq!(server_name(&extensions), Some(expected_server_name));
+    }
+
+    #[tokio::test]
+    async fn expired_certificate_is_reported_with_validity() {
+        let key_pair = rcgen::KeyPair::generate().expect("can generate");
+        let mut params =
+            rcgen::CertificateParams::new([SERVER_HOSTNAME.to_owned()]).expect("valid");
+        params.not_before = rcgen::date_time_ymd(2000, 1, 1);
+        params.not_after = rcgen::date_time_ymd(2001, 1, 1);
+        let certificate = params.self_signed(&key_pair).expect("can sign");
+
+        let acceptor = tls_acceptor_builder(&certificate, &key_pair).build();
+        let result = connect_tls_to_localhost(acceptor, &certificate, AlpnList::default()).await;
+
+        let validity = assert_matches!(
+            result.map(|_| ()),
+            Err(TransportConnectError::CertificateExpired(validity)) => validity
+        );
+        assert_eq!(
+            validity,
+            CertificateValidity {
+                not_before: httpdate::parse_http_date("Sat, 01 Jan 2000 00:00:00 GMT").unwrap(),
+                not_after: httpdate::parse_http_date("Mon, 01 Jan 2001 00:00:00 GMT").unwrap(),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn expired_untrusted_certificate_is_reported_as_untrusted() {
+        let key_pair = rcgen::KeyPair::generate().expect("can generate");
+        let mut params =
+            rcgen::CertificateParams::new([SERVER_HOSTNAME.to_owned()]).expect("valid");
+        params.not_before = rcgen::date_time_ymd(2000, 1, 1);
+        params.not_after = rcgen::date_time_ymd(2001, 1, 1);
+        let certificate = params.self_signed(&key_pair).expect("can sign");
+
+        let acceptor = tls_acceptor_builder(&certificate, &key_pair).build();
+        // The certificate is expired, but it isn't trusted in the first place,
+        // so the local clock isn't to blame.
+        let result =
+            connect_tls_to_localhost(acceptor, &SERVER_CERTIFICATE.cert, AlpnList::default()).await;
+
+        assert_matches!(
+            result.map(|_| ()),
+            Err(TransportConnectError::CertificateUntrusted)
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn connect_with_tcp_fast_open() {
+        use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+
+        use crate::route::ConnectorExt as _;
+
+        let listener = tokio::net::TcpListener::bind((Ipv6Addr::LOCALHOST, 0))
+            .await
+            .expect("can bind");
+        let addr = listener.local_addr().expect("bound");
+
+        let connector = StatelessTcp {
+            tcp_fast_open: true,
+            dscp: None,
+        };
+        let route = TcpRoute {
+            address: addr.ip(),
+            port: addr.port().try_into().expect("bound port"),
+        };
+        let (client, server) = tokio::join!(connector.connect(route, "test".into()), async {
+            listener.accept().await.expect("can accept").0
+        });
+        let mut client = client.expect("can connect");
+        let mut server = server;
+
+        // With TCP_FASTOPEN_CONNECT the connect returns before the handshake,
+        // and the SYN might not go out until this write, so make sure the
+        // data actually arrives.
+        client.write_all(b"hello").await.expect("can write");
+        let mut received = [0; 5];
+        server.read_exact(&mut received).await.expect("can read");
+        assert_eq!(&received, b"hello");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test_case(false; "plain")]
+    #[test_case(true; "with Fast Open")]
+    #[tokio::test]
+    async fn connect_with_dscp_marking(tcp_fast_open: bool) {
+        use crate::route::ConnectorExt as _;
+
+        const EXPEDITED_FORWARDING: u8 = 46;
+
+        let listener = tokio::net::TcpListener::bind((Ipv6Addr::LOCALHOST, 0))
+            .await
+            .expect("can bind");
+        let addr = listener.local_addr().expect("bound");
+
+        let connector = StatelessTcp {
+            tcp_fast_open,
+            dscp: Some(EXPEDITED_FORWARDING),
+        };
+        let route = TcpRoute {
+            address: addr.ip(),
+            port: addr.port().try_into().expect("bound port"),
+        };
+        let (client, _server) = tokio::join!(connector.connect(route, "test".into()), async {
+            listener.accept().await.expect("can accept").0
+        });
+        let client = client.expect("can connect");
+
+        let traffic_class = socket2::SockRef::from(&client)
+            .tclass_v6()
+            .expect("can get traffic class");
+        assert_eq!(traffic_class, u32::from(EXPEDITED_FORWARDING) << 2);
+    }
+
+    #[tokio::test]
+    async fn tcp_fast_open_falls_back_if_blackholed() {
+        let listener = tokio::net::TcpListener::bind((Ipv6Addr::LOCALHOST, 0))
+            .await
+            .expect("can bind");
+        let addr = listener.local_addr().expect("bound");
+        let _server_handle = tokio::spawn(async move {
+            let _accepted = listener.accept().await;
+        });
+
+        // Simulate a network that drops Fast Open SYNs by never finishing the
+        // Fast Open attempt.
+        let start = tokio::time::Instant::now();
+        let _stream = connect_with_fast_open_fallback(addr, None, std::future::pending(), "test")
+            .await
+            .expect("can connect");
+
+        let elapsed = start.elapsed();
+        assert!(elapsed >= TCP_FAST_OPEN_FALLBACK_TIMEOUT, "{elapsed:?}");
+        assert!(
+            elapsed < crate::timeouts::ONE_ROUTE_CONNECTION_TIMEOUT,
+            "{elapsed:?}"
+        );
+    }
 }
@@ -48,7 +48,7 @@ impl Connector<ConnectionProxyRoute<IpAddr>, ()> for StatelessProxied {
                 } = proxy;
 
                 let tcp = LoggingConnector::new(
-                    super::StatelessTcp,
+                    super::StatelessTcp::default(),
                     LONG_TCP_HANDSHAKE_THRESHOLD,
                     "Proxy-TCP",
                 )
@@ -65,7 +65,7 @@ impl Connector<ConnectionProxyRoute<IpAddr>, ()> for StatelessProxied {
             }
             ConnectionProxyRoute::Tcp { proxy } => {
                 let connector = LoggingConnector::new(
-                    super::StatelessTcp,
+                    super::StatelessTcp::default(),
                     LONG_TCP_HANDSHAKE_THRESHOLD,
                     "Proxy-TCP",
                 );
@@ -113,6 +113,7 @@ impl Connection for TcpStream {
         crate::TransportInfo {
             ip_version: IpType::from(&local_addr.ip()),
             local_port: local_addr.port(),
+            negotiated_alpn: None,
         }
     }
 }
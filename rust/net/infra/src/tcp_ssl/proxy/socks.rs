@@ -204,7 +204,7 @@ impl Connector<SocksRoute<IpAddr>, ()> for super::StatelessProxied {
                 }
             };
 
-            let stream = super::super::StatelessTcp
+            let stream = super::super::StatelessTcp::default()
                 .connect(proxy, log_tag.clone())
                 .await?;
             log::info!("[{log_tag}] performing proxy handshake");
@@ -59,10 +59,48 @@ pub const MULTI_ROUTE_CONNECTION_TIMEOUT: Duration = Duration::from_secs(180);
 /// before it starts.
 pub const TCP_CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(200);
 
+/// How long a TCP Fast Open connection attempt is given before falling back to
+/// a regular TCP connect.
+///
+/// Some middleboxes silently drop SYNs carrying the Fast Open option, so this
+/// needs to be well under the per-route timeout for the fallback to be useful.
+pub const TCP_FAST_OPEN_FALLBACK_TIMEOUT: Duration = Duration::from_millis(750);
+
 /// Minimum timeout duration for TLS handshake. May be greater depending on length of
 /// the TCP handshake.
 pub const MIN_TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(3);
 
+/// Limits on the individual phases of a connection attempt over one route.
+///
+/// Each limit is applied on its own, in addition to the per-route timeout, so
+/// that a stall in one phase fails the route as soon as that phase's limit is
+/// reached. `None` leaves a phase bounded only by the other timeouts.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct PhaseTimeouts {
+    /// Time allowed for resolving a hostname.
+    pub dns: Option<Duration>,
+    /// Time allowed for establishing the transport, including any proxy
+    /// handshake, before TLS starts.
+    pub tcp_connect: Option<Duration>,
+    /// Time allowed for the TLS handshake.
+    ///
+    /// This caps the variable TLS timeout derived from the TCP round trip;
+    /// it never extends it.
+    pub tls_handshake: Option<Duration>,
+    /// Time allowed for the websocket upgrade request and response.
+    pub websocket_upgrade: Option<Duration>,
+}
+
+impl PhaseTimeouts {
+    /// No per-phase limits; every phase is bounded only by the other timeouts.
+    pub const NONE: Self = Self {
+        dns: None,
+        tcp_connect: None,
+        tls_handshake: None,
+        websocket_upgrade: None,
+    };
+}
+
 /// A sequence of timeout values to be used as cooldown intervals between attempts
 /// when a connection to a given route is consecutively failing to establish
 pub const CONNECTION_ROUTE_COOLDOWN_INTERVALS: [Duration; 8] = [
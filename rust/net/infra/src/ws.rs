@@ -176,6 +176,35 @@ impl WithoutResponseHeaders {
     }
 }
 
+/// The headers [`Stateless`] sends in the request to upgrade a connection to a
+/// websocket.
+///
+/// These are the route's own `headers`, followed by the ones the upgrade
+/// itself requires. `key` is the `Sec-WebSocket-Key`, which should be freshly
+/// generated for each request.
+pub fn websocket_upgrade_headers(
+    headers: http::HeaderMap,
+    host_header: &str,
+    key: http::HeaderValue,
+) -> Result<http::HeaderMap, http::header::InvalidHeaderValue> {
+    let mut headers = headers;
+    headers.append(http::header::HOST, host_header.try_into()?);
+    headers.append(
+        http::header::CONNECTION,
+        http::HeaderValue::from_static("Upgrade"),
+    );
+    headers.append(
+        http::header::UPGRADE,
+        http::HeaderValue::from_static("websocket"),
+    );
+    headers.append(
+        http::header::SEC_WEBSOCKET_VERSION,
+        http::HeaderValue::from_static("13"),
+    );
+    headers.append(http::header::SEC_WEBSOCKET_KEY, key);
+    Ok(headers)
+}
+
 #[derive(Debug)]
 pub struct StreamWithResponseHeaders<Inner> {
     pub stream: Inner,
@@ -229,21 +258,13 @@ where
                 .scheme("wss")
                 .build()?;
 
+            let key = http::HeaderValue::try_from(generate_key())
+                .expect("base64 is a valid header value");
             let mut builder = http::Request::builder();
-            *builder.headers_mut().expect("no headers, so not invalid") = headers;
-
-            let request = builder
-                .header(http::header::HOST, &*host_header)
-                .uri(uri)
-                .method(http::Method::GET)
-                .header(http::header::CONNECTION, "Upgrade")
-                .header(http::header::UPGRADE, "websocket")
-                .header(http::header::SEC_WEBSOCKET_VERSION, "13")
-                .header(
-                    http::header::SEC_WEBSOCKET_KEY,
-                    tungstenite::handshake::client::generate_key(),
-                )
-                .body(())?;
+            *builder.headers_mut().expect("no headers, so not invalid") =
+                websocket_upgrade_headers(headers, &host_header, key).map_err(http::Error::from)?;
+
+            let request = builder.uri(uri).method(http::Method::GET).body(())?;
 
             let (stream, response) =
                 tokio_tungstenite::client_async_with_config(request, inner, Some(ws_config))
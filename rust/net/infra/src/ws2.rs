@@ -104,6 +104,28 @@ pub struct Connection<S, R> {
 
     /// A tag to include in log lines, to disambiguate multiple websockets.
     log_tag: Arc<str>,
+
+    /// The close frame to send when the outgoing stream ends.
+    close_frame: CloseFrameSlot,
+}
+
+/// Holds the close frame a [`Connection`] sends once its outgoing stream ends.
+///
+/// This is shared between the connection and whoever ends the stream, so the
+/// frame can be chosen at the time of disconnecting. If nothing was set, the
+/// connection is closed without a status code.
+#[derive(Clone, Debug, Default)]
+pub struct CloseFrameSlot(Arc<std::sync::Mutex<Option<CloseFrame<'static>>>>);
+
+impl CloseFrameSlot {
+    /// Replaces the frame to send, if one was already set.
+    pub fn set(&self, frame: CloseFrame<'static>) {
+        *self.0.lock().expect("not poisoned") = Some(frame);
+    }
+
+    fn take(&self) -> Option<CloseFrame<'static>> {
+        self.0.lock().expect("not poisoned").take()
+    }
 }
 
 /// Fatal error that causes a connection to be closed.
@@ -216,6 +238,16 @@ where
             last_sent_to_server: None,
             last_sent_ping_to_server: None,
             log_tag,
+            close_frame: CloseFrameSlot::default(),
+        }
+    }
+
+    /// Uses the frame in `close_frame`, if one has been set, to close the
+    /// websocket when the outgoing stream ends.
+    pub fn with_close_frame(self, close_frame: CloseFrameSlot) -> Self {
+        Self {
+            close_frame,
+            ..self
         }
     }
 
@@ -259,6 +291,7 @@ where
             last_sent_ping_to_server,
             last_heard_from_server,
             log_tag,
+            close_frame,
         } = self.project();
 
         // For the first call this function, assume we just heard from & sent to
@@ -357,7 +390,7 @@ where
             Event::ClientDisconnect => {
                 // The client has been closed, so there aren't any more messages
                 // coming in. Tell the server we're done.
-                let result = stream.send(Message::Close(None)).await;
+                let result = stream.send(Message::Close(close_frame.take())).await;
                 Outcome::Finished(match result {
                     Ok(()) => Ok(FinishReason::LocalDisconnect),
                     Err(e) => Err({
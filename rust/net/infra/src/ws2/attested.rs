@@ -26,6 +26,17 @@ pub struct AttestedConnection {
     client_connection: ClientConnection,
 }
 
+/// The attestation evidence an enclave presented, and whether it was accepted.
+///
+/// Produced by [`AttestedConnection::check_attestation`].
+#[derive(Debug)]
+pub struct AttestationCheck {
+    /// The attestation message, exactly as the enclave sent it.
+    pub evidence: Vec<u8>,
+    /// The outcome of validating `evidence`.
+    pub result: attest::enclave::Result<()>,
+}
+
 #[derive(Debug, thiserror::Error, displaydoc::Display)]
 pub enum AttestedProtocolError {
     /// failed to decode frame as protobuf
@@ -86,6 +97,36 @@ impl AttestedConnection {
         })
     }
 
+    /// Reads the attestation message from `ws` and validates it with
+    /// `new_handshake`, then closes the websocket.
+    ///
+    /// This is the first step of [`Self::connect`] on its own: the handshake
+    /// produced by `new_handshake` is never sent, so no session is
+    /// established. A rejected attestation is reported in the returned
+    /// [`AttestationCheck`]; errors are only returned if the attestation
+    /// message couldn't be received at all.
+    pub async fn check_attestation<WS>(
+        ws: WS,
+        ws_config: crate::ws2::Config,
+        log_tag: Arc<str>,
+        new_handshake: impl FnOnce(&[u8]) -> attest::enclave::Result<attest::enclave::Handshake>,
+    ) -> Result<AttestationCheck, AttestedConnectionError>
+    where
+        WS: WebSocketStreamLike + Send + 'static,
+    {
+        let mut ws_client = WsClient::new(ws, ws_config, log_tag);
+
+        let evidence = ws_client.read().await?.next_or_else(|close| {
+            AttestedConnectionError::Protocol(AttestedProtocolError::UnexpectedClose(close.into()))
+        })?;
+        let result = new_handshake(&evidence).map(|_handshake| ());
+
+        // Hanging up on the client's task makes it close the websocket.
+        drop(ws_client);
+
+        Ok(AttestationCheck { evidence, result })
+    }
+
     /// Read the next message from the stream, blocking until there is one.
     ///
     /// Waits for the next event from the server, then returns
@@ -627,10 +668,11 @@ mod test {
     use std::time::Duration;
 
     use assert_matches::assert_matches;
+    use test_case::test_case;
     use tokio_tungstenite::WebSocketStream;
 
     use super::*;
-    use crate::ws::testutil::fake_websocket;
+    use crate::ws::testutil::{fake_websocket, websocket_test_client};
     use crate::ws2::attested::testutil::{
         run_attested_server, AttestedServerOutput, FAKE_ATTESTATION,
     };
@@ -710,6 +752,46 @@ mod test {
         );
     }
 
+    #[test_case(true; "valid")]
+    #[test_case(false; "invalid")]
+    #[tokio::test]
+    async fn check_attestation_without_connecting(accept: bool) {
+        let (server, client) = fake_websocket().await;
+        let server = tokio::task::spawn(async move {
+            let mut websocket = websocket_test_client(server);
+            websocket
+                .send(Vec::from(FAKE_ATTESTATION).into())
+                .await
+                .unwrap();
+            // The client should hang up instead of starting the handshake.
+            websocket.receive().await
+        });
+
+        let check = AttestedConnection::check_attestation(
+            client,
+            FAKE_WS_CONFIG,
+            "test".into(),
+            |attestation| {
+                assert_eq!(attestation, FAKE_ATTESTATION);
+                if accept {
+                    attest::sgx_session::testutil::handshake_from_tests_data()
+                } else {
+                    Err(attest::enclave::Error::AttestationDataError {
+                        reason: "invalid".to_string(),
+                    })
+                }
+            },
+        )
+        .await
+        .expect("received attestation");
+
+        let AttestationCheck { evidence, result } = check;
+        assert_eq!(evidence, FAKE_ATTESTATION);
+        assert_eq!(result.is_ok(), accept, "{result:?}");
+
+        assert_matches!(server.await.unwrap(), Ok(NextOrClose::Close(_)));
+    }
+
     #[tokio::test]
     async fn attested_connection_invalid_decode() {
         // Start the server with a known private key (K of NK).
@@ -292,6 +292,9 @@ impl From<crate::enclave::Error> for LookupError {
                     }
                     Self::WebSocket(WebSocketServiceError::Http(response))
                 }
+                WebSocketServiceConnectError::InvalidConfirmationHeader { response } => {
+                    Self::WebSocket(WebSocketServiceError::Http(response))
+                }
                 WebSocketServiceConnectError::Connect(e, _) => match e {
                     WebSocketConnectError::Timeout => Self::ConnectionTimedOut,
                     WebSocketConnectError::Transport(e) => Self::ConnectTransport(e),
@@ -300,8 +303,10 @@ impl From<crate::enclave::Error> for LookupError {
             },
             Error::AttestationError(err) => Self::AttestationError(err),
             Error::WebSocket(err) => Self::WebSocket(err),
-            Error::Protocol(error) => Self::EnclaveProtocol(error),
-            Error::ConnectionTimedOut => Self::ConnectionTimedOut,
+            Error::Protocol(error) | Error::HandshakeRejected(error) => {
+                Self::EnclaveProtocol(error)
+            }
+            Error::ConnectionTimedOut | Error::Unreachable => Self::ConnectionTimedOut,
         }
     }
 }
@@ -345,6 +350,7 @@ impl CdsiConnection {
                 ),
                 "cdsi".into(),
                 params,
+                None,
             )
             .await?;
         Ok(Self(connection))
@@ -3,12 +3,16 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 //
 
+use std::collections::VecDeque;
 use std::fmt::{Debug, Display};
+use std::pin::Pin;
 use std::sync::Arc;
-use std::time::Duration;
+use std::task::{Context, Poll};
+use std::time::{Duration, SystemTime};
 
 use ::http::uri::PathAndQuery;
 use ::http::{HeaderMap, HeaderName, HeaderValue, StatusCode};
+use futures_util::{Sink, SinkExt as _, Stream, StreamExt as _};
 use libsignal_net_infra::connection_manager::MultiRouteConnectionManager;
 use libsignal_net_infra::route::{
     Connector, HttpsTlsRoute, RouteProvider, RouteProviderExt, ThrottlingConnector, TransportRoute,
@@ -19,13 +23,17 @@ use libsignal_net_infra::timeouts::ONE_ROUTE_CONNECTION_TIMEOUT;
 use libsignal_net_infra::utils::ObservableEvent;
 use libsignal_net_infra::ws::StreamWithResponseHeaders;
 use libsignal_net_infra::{
-    make_ws_config, AsHttpHeader, Connection, EndpointConnection, IpType, TransportInfo,
+    extract_retry_later, make_ws_config, AsHttpHeader, AsyncDuplexStream, Connection,
+    EndpointConnection, IpType, TransportInfo,
 };
 use tokio_tungstenite::WebSocketStream;
+use tungstenite::protocol::frame::coding::CloseCode;
+use tungstenite::protocol::CloseFrame;
 
 use crate::auth::Auth;
 use crate::connect_state::{
-    ConnectionResources, DefaultTransportConnector, RouteInfo, WebSocketTransportConnectorFactory,
+    lock_connect_state, ConnectState, ConnectionResources, DefaultTransportConnector, RouteInfo,
+    WebSocketTransportConnectorFactory,
 };
 use crate::env::{add_user_agent_header, ConnectionConfig, UserAgent};
 use crate::proto;
@@ -162,8 +170,16 @@ pub struct ConnectionInfo {
 pub struct ChatConnection {
     inner: self::ws2::Chat,
     connection_info: ConnectionInfo,
+    server_time: Option<SystemTime>,
+    subprotocol: Option<Arc<str>>,
+    request_middleware: Option<RequestMiddleware>,
 }
 
+/// A hook that can inspect and modify each request sent on a [`ChatConnection`].
+///
+/// See [`ChatConnection::with_request_middleware`].
+pub type RequestMiddleware = Box<dyn Fn(&mut Request) + Send + Sync>;
+
 type ChatTransportConnection =
     <DefaultTransportConnector as Connector<TransportRoute, ()>>::Connection;
 
@@ -177,6 +193,8 @@ pub struct PendingChatConnection<T = ChatTransportConnection> {
     ws_config: ws2::Config,
     route_info: RouteInfo,
     log_tag: Arc<str>,
+    /// Messages from the server read while checking that it responds.
+    early_messages: VecDeque<tungstenite::Message>,
 }
 
 #[cfg_attr(test, derive(Clone))]
@@ -187,13 +205,33 @@ pub struct AuthenticatedChatHeaders {
 
 pub type ChatServiceRoute = UnresolvedWebsocketServiceRoute;
 
+/// Whether a chat connect may use a connection saved by
+/// [`ConnectionResources::preconnect_and_save`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PreconnectPolicy {
+    /// Use a saved connection if one is available.
+    ///
+    /// Only authenticated connections ever do.
+    #[default]
+    UseIfAvailable,
+    /// Make a fresh connection, leaving any saved one in place for a later
+    /// attempt.
+    Skip,
+}
+
 impl ChatConnection {
+    /// Starts connecting to the chat server over the provided routes.
+    ///
+    /// Authenticated connections normally use a connection saved by
+    /// [`ConnectionResources::preconnect_and_save`] if one is available; see
+    /// [`PreconnectPolicy`].
     pub async fn start_connect_with<TC>(
         connection_resources: ConnectionResources<'_, TC>,
         http_route_provider: impl RouteProvider<Route = UnresolvedHttpsServiceRoute>,
         user_agent: &UserAgent,
         ws_config: self::ws2::Config,
         auth: Option<AuthenticatedChatHeaders>,
+        preconnect: PreconnectPolicy,
         log_tag: &str,
     ) -> Result<PendingChatConnection, ConnectError>
     where
@@ -208,6 +246,7 @@ impl ChatConnection {
             user_agent,
             ws_config,
             auth,
+            preconnect,
             log_tag,
         )
         .await
@@ -220,12 +259,17 @@ impl ChatConnection {
         user_agent: &UserAgent,
         ws_config: self::ws2::Config,
         auth: Option<AuthenticatedChatHeaders>,
+        preconnect: PreconnectPolicy,
         log_tag: &str,
     ) -> Result<PendingChatConnection<TC::Connection>, ConnectError>
     where
         TC: WebSocketTransportConnectorFactory<UsePreconnect<TransportRoute>>,
     {
-        let should_preconnect = auth.is_some();
+        let should_preconnect = auth.is_some() && preconnect == PreconnectPolicy::UseIfAvailable;
+        let offered_subprotocols = (!ws_config.subprotocols.is_empty())
+            .then(|| HeaderValue::from_str(&ws_config.subprotocols.join(", ")))
+            .transpose()
+            .map_err(|_| ConnectError::InvalidConnectionConfiguration)?;
         let headers = auth
             .into_iter()
             .flat_map(
@@ -234,7 +278,10 @@ impl ChatConnection {
                      receive_stories,
                  }| [auth.as_header(), receive_stories.as_header()],
             )
-            .chain([user_agent.as_header()]);
+            .chain([user_agent.as_header()])
+            .chain(
+                offered_subprotocols.map(|value| (::http::header::SEC_WEBSOCKET_PROTOCOL, value)),
+            );
         let ws_fragment = WebSocketRouteFragment {
             ws_config: Default::default(),
             endpoint: PathAndQuery::from_static(crate::env::constants::WEB_SOCKET_PATH),
@@ -253,7 +300,8 @@ impl ChatConnection {
         });
 
         let log_tag: Arc<str> = log_tag.into();
-        let (connection, route_info) = connection_resources
+        let confirmation_header_name = connection_resources.confirmation_header_name.clone();
+        let (connection, mut route_info) = connection_resources
             .connect_ws(
                 ws_routes,
                 // If we create multiple authenticated chat websocket connections at
@@ -264,6 +312,7 @@ impl ChatConnection {
                 // at a time.
                 ThrottlingConnector::new(crate::infra::ws::Stateless, 1),
                 log_tag.clone(),
+                None,
             )
             .await?;
 
@@ -274,18 +323,25 @@ impl ChatConnection {
             response_headers,
         } = connection.into_inner();
 
+        if let Some(name) = &confirmation_header_name {
+            route_info.set_confirmation_header(
+                crate::ws::valid_confirmation_header(&response_headers, name).map(Arc::from),
+            );
+        }
+
         Ok(PendingChatConnection {
             connection: stream,
             connect_response_headers: response_headers,
             route_info,
             ws_config,
             log_tag,
+            early_messages: VecDeque::new(),
         })
     }
 
-    pub fn finish_connect(
+    pub fn finish_connect<T: AsyncDuplexStream + Connection + 'static>(
         tokio_runtime: tokio::runtime::Handle,
-        pending: PendingChatConnection,
+        pending: PendingChatConnection<T>,
         listener: ws2::EventListener,
     ) -> Self {
         let PendingChatConnection {
@@ -294,15 +350,22 @@ impl ChatConnection {
             ws_config,
             route_info,
             log_tag,
+            early_messages,
         } = pending;
         Self {
             connection_info: ConnectionInfo {
                 route_info,
                 transport_info: connection.transport_info(),
             },
+            server_time: server_time_from_headers(&connect_response_headers),
+            subprotocol: subprotocol_from_headers(&connect_response_headers).map(Arc::from),
+            request_middleware: None,
             inner: ws2::Chat::new(
                 tokio_runtime,
-                connection,
+                WithEarlyMessages {
+                    early: early_messages,
+                    inner: connection,
+                },
                 connect_response_headers,
                 ws_config,
                 log_tag,
@@ -311,20 +374,217 @@ impl ChatConnection {
         }
     }
 
-    pub async fn send(&self, msg: Request, timeout: Duration) -> Result<Response, SendError> {
+    /// Installs a hook that is run on each request before it's sent.
+    ///
+    /// The middleware sees the request as passed to [`Self::send`], and can
+    /// change its method, path, headers, or body. The request ID and websocket
+    /// framing are added afterwards, so they're out of its reach. Only one
+    /// middleware can be installed; a later one replaces an earlier one.
+    pub fn with_request_middleware(mut self, middleware: RequestMiddleware) -> Self {
+        self.request_middleware = Some(middleware);
+        self
+    }
+
+    /// Sends `msg` and waits up to `timeout` for the server's response.
+    ///
+    /// Error statuses are returned as ordinary responses, except for a 503 with
+    /// a Retry-After header, which means the server is down for maintenance and
+    /// is reported as [`SendError::ServerMaintenance`].
+    pub async fn send(&self, mut msg: Request, timeout: Duration) -> Result<Response, SendError> {
+        if let Some(middleware) = &self.request_middleware {
+            middleware(&mut msg);
+        }
         let send_result = tokio::time::timeout(timeout, self.inner.send(msg))
             .await
             .map_err(|_elapsed| SendError::RequestTimedOut)?;
-        Ok(send_result?)
+        let response = send_result?;
+        if crate::ws::is_server_maintenance(response.status, &response.headers) {
+            return Err(SendError::ServerMaintenance {
+                retry_after: extract_retry_later(&response.headers),
+            });
+        }
+        Ok(response)
     }
 
     pub async fn disconnect(&self) {
         self.inner.disconnect().await
     }
 
+    /// Like [`Self::disconnect`], but sends the server a close frame with
+    /// `code` and `reason` instead of one without a status code.
+    ///
+    /// This lets callers distinguish, for example, going away
+    /// ([`CloseCode::Away`]) from a normal closure.
+    pub async fn disconnect_with(&self, code: CloseCode, reason: &str) {
+        self.inner
+            .disconnect_with(CloseFrame {
+                code,
+                reason: reason.to_owned().into(),
+            })
+            .await
+    }
+
     pub fn connection_info(&self) -> &ConnectionInfo {
         &self.connection_info
     }
+
+    /// Subscribes to changes in the state of the connection.
+    ///
+    /// See [`ws2::Chat::state_stream`].
+    pub fn state_stream(&self) -> tokio::sync::watch::Receiver<ws2::ConnectionState> {
+        self.inner.state_stream()
+    }
+
+    /// The time reported by the server in the `Date` header of its response to
+    /// the websocket upgrade request.
+    ///
+    /// Comparing this against the local clock gives an estimate of clock skew.
+    /// Returns `None` if the header was missing or couldn't be parsed.
+    pub fn server_time(&self) -> Option<SystemTime> {
+        self.server_time
+    }
+
+    /// The websocket subprotocol the server selected from
+    /// [`ws2::Config::subprotocols`].
+    ///
+    /// This is `None` if no subprotocols were offered; otherwise the connect
+    /// would have failed without one.
+    pub fn subprotocol(&self) -> Option<&str> {
+        self.subprotocol.as_deref()
+    }
+}
+
+impl<T> PendingChatConnection<T> {
+    /// The time reported by the server when the connection was established.
+    ///
+    /// See [`ChatConnection::server_time`].
+    pub fn server_time(&self) -> Option<SystemTime> {
+        server_time_from_headers(&self.connect_response_headers)
+    }
+
+    /// The websocket subprotocol the server selected.
+    ///
+    /// See [`ChatConnection::subprotocol`].
+    pub fn subprotocol(&self) -> Option<&str> {
+        subprotocol_from_headers(&self.connect_response_headers)
+    }
+}
+
+impl<T: AsyncDuplexStream> PendingChatConnection<T> {
+    /// Checks that data sent by the server makes it back to the client.
+    ///
+    /// Some middleboxes let the websocket upgrade through but drop everything
+    /// the server sends afterwards, which would otherwise only show up later
+    /// as requests that never get responses. This sends a websocket ping and
+    /// waits up to `timeout` for the pong. Any other message from the server
+    /// counts as a response too, and is kept for the [`ChatConnection`] to
+    /// handle once the connection is finished.
+    ///
+    /// This costs a round trip, so it's up to the caller whether to check. If
+    /// the check fails, the route is recorded as failed in `connect_state`,
+    /// the same as if the connect itself had failed.
+    pub async fn check_server_responds<TC>(
+        &mut self,
+        connect_state: &std::sync::Mutex<ConnectState<TC>>,
+        timeout: Duration,
+    ) -> Result<(), ConnectError> {
+        let Self {
+            connection,
+            connect_response_headers: _,
+            ws_config: _,
+            route_info,
+            log_tag,
+            early_messages,
+        } = self;
+
+        let wait_for_response = async {
+            connection.send(tungstenite::Message::Ping(vec![])).await?;
+            match connection.next().await {
+                Some(Ok(tungstenite::Message::Pong(_))) => Ok(()),
+                Some(Ok(message)) => {
+                    early_messages.push_back(message);
+                    Ok(())
+                }
+                Some(Err(e)) => Err(e),
+                None => Err(tungstenite::Error::ConnectionClosed),
+            }
+        };
+
+        let error = match tokio::time::timeout(timeout, wait_for_response).await {
+            Ok(Ok(())) => return Ok(()),
+            Ok(Err(e)) => ConnectError::WebSocket(e.into()),
+            Err(_elapsed) => {
+                log::warn!("[{log_tag}] no response from the server after {timeout:?}");
+                ConnectError::NoServerResponse
+            }
+        };
+        lock_connect_state(connect_state)
+            .record_failed_connection(route_info, tokio::time::Instant::now());
+        Err(error)
+    }
+}
+
+/// A websocket stream with messages that were already read from it put back
+/// in front.
+#[pin_project::pin_project]
+struct WithEarlyMessages<S> {
+    early: VecDeque<tungstenite::Message>,
+    #[pin]
+    inner: S,
+}
+
+impl<S: Stream<Item = Result<tungstenite::Message, tungstenite::Error>>> Stream
+    for WithEarlyMessages<S>
+{
+    type Item = S::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.project();
+        if let Some(message) = this.early.pop_front() {
+            return Poll::Ready(Some(Ok(message)));
+        }
+        this.inner.poll_next(cx)
+    }
+}
+
+impl<S: Sink<tungstenite::Message>> Sink<tungstenite::Message> for WithEarlyMessages<S> {
+    type Error = S::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.project().inner.poll_ready(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: tungstenite::Message) -> Result<(), Self::Error> {
+        self.project().inner.start_send(item)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.project().inner.poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.project().inner.poll_close(cx)
+    }
+}
+
+/// Parses the `Date` header from an HTTP response.
+///
+/// All three formats permitted by RFC 7231 (IMF-fixdate, RFC 850, and asctime)
+/// are accepted.
+fn server_time_from_headers(headers: &HeaderMap) -> Option<SystemTime> {
+    let date = headers.get(::http::header::DATE)?.to_str().ok()?;
+    httpdate::parse_http_date(date.trim()).ok()
+}
+
+/// Reads the subprotocol selected in a websocket upgrade response.
+///
+/// By the time a connection is established, the websocket handshake has
+/// already checked that this is one of the offered subprotocols.
+fn subprotocol_from_headers(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(::http::header::SEC_WEBSOCKET_PROTOCOL)?
+        .to_str()
+        .ok()
 }
 
 impl PendingChatConnection {
@@ -352,6 +612,7 @@ impl Display for ConnectionInfo {
                 TransportInfo {
                     local_port,
                     ip_version,
+                    negotiated_alpn: _,
                 },
             route_info,
         } = self;
@@ -393,12 +654,13 @@ pub mod test_support {
 
         let connect = ConnectState::new_with_transport_connector(
             SUGGESTED_CONNECT_CONFIG,
-            PreconnectingFactory::new(DefaultConnectorFactory, Duration::ZERO),
+            PreconnectingFactory::new(DefaultConnectorFactory::new(), Duration::ZERO),
         );
         let user_agent = UserAgent::with_libsignal_version("test_simple_chat_connection");
 
         let ws_config = ws2::Config {
             initial_request_id: 0,
+            subprotocols: &[],
             local_idle_timeout: Duration::from_secs(60),
             remote_idle_timeout: Duration::from_secs(60),
         };
@@ -420,6 +682,7 @@ pub mod test_support {
             &user_agent,
             ws_config,
             None,
+            PreconnectPolicy::UseIfAvailable,
             "test",
         )
         .await?;
@@ -578,6 +841,169 @@ pub(crate) mod test {
         assert_matches!(response, Err(ResponseProtoInvalidError));
     }
 
+    #[test_case(None => None; "without status code")]
+    #[test_case(
+        Some((CloseCode::Away, "backgrounded")) => Some((CloseCode::Away, "backgrounded".to_owned()));
+        "going away"
+    )]
+    #[tokio::test(start_paused = true)]
+    async fn disconnect_sends_close_frame(
+        close: Option<(CloseCode, &str)>,
+    ) -> Option<(CloseCode, String)> {
+        let (chat, remote) =
+            ChatConnection::new_fake(tokio::runtime::Handle::current(), Box::new(|_| {}), []);
+
+        match close {
+            Some((code, reason)) => chat.disconnect_with(code, reason).await,
+            None => chat.disconnect().await,
+        }
+
+        let frame = remote.receive_close().await.expect("only a close frame");
+        frame.map(|CloseFrame { code, reason }| (code, reason.into_owned()))
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn request_middleware_modifies_outgoing_requests() {
+        let (chat, remote) =
+            ChatConnection::new_fake(tokio::runtime::Handle::current(), Box::new(|_| {}), []);
+        let chat = chat.with_request_middleware(Box::new(|request| {
+            request.headers.insert(
+                HeaderName::from_static("x-experiment"),
+                HeaderValue::from_static("treatment"),
+            );
+            request.path = PathAndQuery::from_static("/v2/rewritten");
+        }));
+
+        let send = chat.send(
+            Request {
+                method: http::Method::GET,
+                body: None,
+                headers: HeaderMap::new(),
+                path: PathAndQuery::from_static("/v1/original"),
+            },
+            Duration::from_secs(10),
+        );
+
+        let respond = async {
+            let request = remote
+                .receive_request()
+                .await
+                .expect("still connected")
+                .expect("request received");
+            assert_eq!(request.path(), "/v2/rewritten");
+            assert_eq!(request.headers, ["x-experiment: treatment"]);
+            remote
+                .send_response(ResponseProto {
+                    id: request.id,
+                    status: Some(200),
+                    message: Some("OK".to_owned()),
+                    headers: vec![],
+                    body: None,
+                })
+                .expect("still connected");
+        };
+
+        let (response, ()) = tokio::join!(send, respond);
+        assert_eq!(response.expect("succeeded").status, StatusCode::OK);
+    }
+
+    #[test_case(&[] => matches Ok(StatusCode::SERVICE_UNAVAILABLE); "unavailable")]
+    #[test_case(
+        &["retry-after: 600"]
+        => matches Err(SendError::ServerMaintenance {
+            retry_after: Some(RetryLater { retry_after_seconds: 600 }),
+        });
+        "maintenance"
+    )]
+    #[tokio::test(start_paused = true)]
+    async fn maintenance_response_is_reported_as_error(
+        headers: &[&str],
+    ) -> Result<StatusCode, SendError> {
+        let (chat, remote) =
+            ChatConnection::new_fake(tokio::runtime::Handle::current(), Box::new(|_| {}), []);
+
+        let send = chat.send(
+            Request {
+                method: http::Method::GET,
+                body: None,
+                headers: HeaderMap::new(),
+                path: PathAndQuery::from_static("/v1/keepalive"),
+            },
+            Duration::from_secs(10),
+        );
+
+        let respond = async {
+            let request = remote
+                .receive_request()
+                .await
+                .expect("still connected")
+                .expect("request received");
+            remote
+                .send_response(ResponseProto {
+                    id: request.id,
+                    status: Some(503),
+                    message: Some("Service Unavailable".to_owned()),
+                    headers: headers.iter().map(|h| h.to_string()).collect(),
+                    body: None,
+                })
+                .expect("still connected");
+        };
+
+        let (response, ()) = tokio::join!(send, respond);
+        response.map(|response| response.status)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn responses_are_matched_to_requests_by_id() {
+        let (chat, remote) =
+            ChatConnection::new_fake(tokio::runtime::Handle::current(), Box::new(|_| {}), []);
+
+        let send = |path| {
+            chat.send(
+                Request {
+                    method: http::Method::GET,
+                    body: None,
+                    headers: HeaderMap::new(),
+                    path: PathAndQuery::from_static(path),
+                },
+                Duration::from_secs(10),
+            )
+        };
+
+        let respond = async {
+            let requests = remote
+                .receive_requests(2)
+                .await
+                .expect("valid requests")
+                .expect("still connected");
+            // Answer in reverse order, echoing each request's path.
+            for request in requests.iter().rev() {
+                remote
+                    .send_response_to(
+                        request.id(),
+                        ResponseProto {
+                            id: None,
+                            status: Some(200),
+                            message: Some("OK".to_owned()),
+                            headers: vec![],
+                            body: Some(request.path().as_bytes().to_vec()),
+                        },
+                    )
+                    .expect("still connected");
+            }
+        };
+
+        let (first, second, ()) = tokio::join!(send("/first"), send("/second"), respond);
+        assert_eq!(
+            first.expect("succeeded").body.as_deref(),
+            Some(b"/first".as_slice())
+        );
+        assert_eq!(
+            second.expect("succeeded").body.as_deref(),
+            Some(b"/second".as_slice())
+        );
+    }
+
     fn encode_response(response: http::Response<impl AsRef<[u8]>>) -> Vec<u8> {
         let mut result = vec![];
         assert_eq!(
@@ -607,9 +1033,15 @@ pub(crate) mod test {
     #[test_case(403, &[] => matches ConnectError::AllAttemptsFailed)]
     #[test_case(403, &[(CONFIRMATION_HEADER, "1")] => matches ConnectError::DeviceDeregistered)]
     #[test_case(499, &[(CONFIRMATION_HEADER, "1")] => matches ConnectError::AppExpired)]
+    #[test_case(426, &[(CONFIRMATION_HEADER, "1")] => matches ConnectError::AppExpired)]
+    #[test_case(426, &[] => matches ConnectError::AllAttemptsFailed)]
     #[test_case(429, &[(CONFIRMATION_HEADER, "1"), ("retry-after", "20")] => matches ConnectError::RetryLater(RetryLater { retry_after_seconds: 20 }))]
     #[test_case(500, &[(CONFIRMATION_HEADER, "1"), ("retry-after", "20")] => matches ConnectError::RetryLater(RetryLater { retry_after_seconds: 20 }))]
     #[test_case(429, &[("retry-after", "20")] => matches ConnectError::AllAttemptsFailed)]
+    #[test_case(503, &[(CONFIRMATION_HEADER, "1")] => matches ConnectError::AllAttemptsFailed)]
+    #[test_case(503, &[(CONFIRMATION_HEADER, "1"), ("retry-after", "Fri, 16 Oct 2026 18:00:00 GMT")] => matches ConnectError::ServerMaintenance { retry_after: None })]
+    #[test_case(503, &[(CONFIRMATION_HEADER, "1"), ("retry-after", "600")] => matches ConnectError::ServerMaintenance { retry_after: Some(RetryLater { retry_after_seconds: 600 }) })]
+    #[test_case(503, &[] => matches ConnectError::AllAttemptsFailed)]
     #[test_log::test(tokio::test(start_paused = true))]
     async fn html_status_tests(
         status: u16,
@@ -638,6 +1070,19 @@ pub(crate) mod test {
                 .expect("can read");
         });
 
+        let err = start_connect_over(client)
+            .await
+            .expect_err("should fail to connect");
+
+        server_task.await.expect("clean exit");
+
+        err
+    }
+
+    /// Starts a chat connection over `client` to a single fake route.
+    async fn start_connect_over(
+        client: tokio::io::DuplexStream,
+    ) -> Result<PendingChatConnection<tokio::io::DuplexStream>, ConnectError> {
         let client = std::sync::Mutex::new(Some(client));
         let connect_state = ConnectState::new_with_transport_connector(
             SUGGESTED_CONNECT_CONFIG,
@@ -659,7 +1104,7 @@ pub(crate) mod test {
             confirmation_header_name: Some(HeaderName::from_static(CONFIRMATION_HEADER)),
         };
 
-        let err = ChatConnection::start_connect_with_transport(
+        ChatConnection::start_connect_with_transport(
             connection_resources,
             vec![HttpsTlsRoute {
                 fragment: HttpRouteFragment {
@@ -671,7 +1116,8 @@ pub(crate) mod test {
                     fragment: TlsRouteFragment {
                         root_certs: RootCertificates::Native,
                         sni: Host::Domain(CHAT_DOMAIN.into()),
-                        alpn: Some(Alpn::Http1_1),
+                        alpn: Alpn::Http1_1.into(),
+                        ech_config_list: None,
                     },
                     inner: DirectOrProxyRoute::Direct(TcpRoute {
                         address: UnresolvedHost(CHAT_DOMAIN.into()),
@@ -685,16 +1131,93 @@ pub(crate) mod test {
                 local_idle_timeout: Duration::ZERO,
                 remote_idle_timeout: Duration::ZERO,
                 initial_request_id: 0,
+                subprotocols: &[],
             },
             None,
+            PreconnectPolicy::UseIfAvailable,
             "fake chat",
         )
         .await
-        .expect_err("should fail to connect");
+    }
 
-        server_task.await.expect("clean exit");
+    const SERVER_DATE: &str = "Sun, 06 Nov 1994 08:49:37 GMT";
 
-        err
+    #[test_log::test(tokio::test(start_paused = true))]
+    async fn server_time_from_upgrade_response() {
+        let (client, server) = tokio::io::duplex(1024);
+
+        let server_task = tokio::spawn(async move {
+            tokio_tungstenite::accept_hdr_async(
+                server,
+                |_request: &http::Request<()>, mut response: http::Response<()>| {
+                    response
+                        .headers_mut()
+                        .insert(http::header::DATE, HeaderValue::from_static(SERVER_DATE));
+                    Ok::<_, tungstenite::handshake::server::ErrorResponse>(response)
+                },
+            )
+            .await
+            .expect("can accept")
+        });
+
+        let pending = start_connect_over(client).await.expect("can connect");
+        let _server_ws = server_task.await.expect("clean exit");
+
+        assert_eq!(
+            pending.server_time(),
+            Some(SystemTime::UNIX_EPOCH + Duration::from_secs(784111777))
+        );
+    }
+
+    #[test_case(&["1700000000000"] => Some("1700000000000".to_owned()); "valid")]
+    #[test_case(&["us-east"] => None; "not a timestamp")]
+    #[test_case(&[] => None; "missing")]
+    #[test_log::test(tokio::test(start_paused = true))]
+    async fn confirmation_header_from_upgrade_response(
+        values: &'static [&'static str],
+    ) -> Option<String> {
+        let (client, server) = tokio::io::duplex(1024);
+
+        let server_task = tokio::spawn(async move {
+            tokio_tungstenite::accept_hdr_async(
+                server,
+                |_request: &http::Request<()>, mut response: http::Response<()>| {
+                    for value in values {
+                        response.headers_mut().append(
+                            HeaderName::from_static(CONFIRMATION_HEADER),
+                            HeaderValue::from_static(value),
+                        );
+                    }
+                    Ok::<_, tungstenite::handshake::server::ErrorResponse>(response)
+                },
+            )
+            .await
+            .expect("can accept")
+        });
+
+        let pending = start_connect_over(client).await.expect("can connect");
+        let _server_ws = server_task.await.expect("clean exit");
+
+        pending.route_info.confirmation_header().map(str::to_owned)
+    }
+
+    #[test_case(SERVER_DATE; "IMF-fixdate")]
+    #[test_case("Sunday, 06-Nov-94 08:49:37 GMT"; "RFC 850")]
+    #[test_case("Sun Nov  6 08:49:37 1994"; "asctime")]
+    fn server_time_accepts_rfc7231_formats(date: &'static str) {
+        let headers = HeaderMap::from_iter([(http::header::DATE, HeaderValue::from_static(date))]);
+        assert_eq!(
+            server_time_from_headers(&headers),
+            Some(SystemTime::UNIX_EPOCH + Duration::from_secs(784111777))
+        );
+    }
+
+    #[test]
+    fn server_time_missing_or_invalid() {
+        assert_eq!(server_time_from_headers(&HeaderMap::new()), None);
+        let headers =
+            HeaderMap::from_iter([(http::header::DATE, HeaderValue::from_static("yesterday"))]);
+        assert_eq!(server_time_from_headers(&headers), None);
     }
 
     #[test_log::test(tokio::test(start_paused = true))]
@@ -732,7 +1255,8 @@ pub(crate) mod test {
                 fragment: TlsRouteFragment {
                     root_certs: RootCertificates::Native,
                     sni: Host::Domain(CHAT_DOMAIN.into()),
-                    alpn: Some(Alpn::Http1_1),
+                    alpn: Alpn::Http1_1.into(),
+                    ech_config_list: None,
                 },
                 inner: DirectOrProxyRoute::Direct(TcpRoute {
                     address: UnresolvedHost(CHAT_DOMAIN.into()),
@@ -757,6 +1281,7 @@ pub(crate) mod test {
                     .map(|route| route.inner)
                     .collect_vec(),
                 "preconnect".into(),
+                None,
             )
             .await
             .expect("success");
@@ -781,8 +1306,10 @@ pub(crate) mod test {
                 local_idle_timeout: Duration::ZERO,
                 remote_idle_timeout: Duration::ZERO,
                 initial_request_id: 0,
+                subprotocols: &[],
             },
             Some(auth_headers.clone()),
+            PreconnectPolicy::UseIfAvailable,
             "fake chat",
         )
         .await
@@ -801,8 +1328,10 @@ pub(crate) mod test {
                 local_idle_timeout: Duration::ZERO,
                 remote_idle_timeout: Duration::ZERO,
                 initial_request_id: 0,
+                subprotocols: &[],
             },
             Some(auth_headers),
+            PreconnectPolicy::UseIfAvailable,
             "fake chat",
         )
         .await
@@ -811,4 +1340,120 @@ pub(crate) mod test {
         assert_matches!(err, ConnectError::AllAttemptsFailed);
         assert_eq!(number_of_times_called.load(atomic::Ordering::SeqCst), 4);
     }
+
+    #[test_log::test(tokio::test(start_paused = true))]
+    async fn skipping_preconnect_keeps_saved_connection() {
+        let number_of_times_called = AtomicU8::new(0);
+
+        let inner_connector = ConnectFn(|_inner, _route, _log_tag| {
+            // This acts like a successful TLS connection to a server that immediately closes
+            // the connection before sending anything.
+            let (client, _server) = tokio::io::duplex(1024);
+            number_of_times_called.fetch_add(1, atomic::Ordering::SeqCst);
+            std::future::ready(Ok::<_, TransportConnectError>(client))
+        });
+        let transport_connector =
+            PreconnectingFactory::new(inner_connector, Duration::from_secs(1));
+
+        let connect_state = ConnectState::new_with_transport_connector(
+            SUGGESTED_CONNECT_CONFIG,
+            transport_connector,
+        );
+
+        let dns_resolver = DnsResolver::new_from_static_map(HashMap::from_iter([(
+            CHAT_DOMAIN,
+            LookupResult::localhost(),
+        )]));
+
+        const CHAT_DOMAIN: &str = "test.signal.org";
+        let routes = vec![HttpsTlsRoute {
+            fragment: HttpRouteFragment {
+                host_header: CHAT_DOMAIN.into(),
+                path_prefix: "".into(),
+                front_name: None,
+            },
+            inner: TlsRoute {
+                fragment: TlsRouteFragment {
+                    root_certs: RootCertificates::Native,
+                    sni: Host::Domain(CHAT_DOMAIN.into()),
+                    alpn: Alpn::Http1_1.into(),
+                    ech_config_list: None,
+                },
+                inner: DirectOrProxyRoute::Direct(TcpRoute {
+                    address: UnresolvedHost(CHAT_DOMAIN.into()),
+                    port: DEFAULT_HTTPS_PORT,
+                }),
+            },
+        }];
+
+        let network_change_event = ObservableEvent::new();
+        let make_connection_resources = || ConnectionResources {
+            connect_state: &connect_state,
+            dns_resolver: &dns_resolver,
+            network_change_event: &network_change_event,
+            confirmation_header_name: Some(HeaderName::from_static(CONFIRMATION_HEADER)),
+        };
+
+        make_connection_resources()
+            .preconnect_and_save(
+                routes
+                    .iter()
+                    .cloned()
+                    .map(|route| route.inner)
+                    .collect_vec(),
+                "preconnect".into(),
+                None,
+            )
+            .await
+            .expect("success");
+
+        assert_eq!(number_of_times_called.load(atomic::Ordering::SeqCst), 1);
+
+        // ChatConnection only uses the preconnect for auth connections
+        let auth_headers = AuthenticatedChatHeaders {
+            auth: Auth {
+                username: "user".into(),
+                password: "****".into(),
+            },
+            receive_stories: ReceiveStories(true),
+        };
+
+        let user_agent = UserAgent::with_libsignal_version("test");
+        let connect = |preconnect| {
+            ChatConnection::start_connect_with_transport(
+                make_connection_resources(),
+                routes.clone(),
+                &user_agent,
+                ws2::Config {
+                    // We shouldn't get to timing out anyway.
+                    local_idle_timeout: Duration::ZERO,
+                    remote_idle_timeout: Duration::ZERO,
+                    initial_request_id: 0,
+                    subprotocols: &[],
+                },
+                Some(auth_headers.clone()),
+                preconnect,
+                "fake chat",
+            )
+        };
+
+        let err = connect(PreconnectPolicy::Skip)
+            .await
+            .expect_err("should fail to connect");
+        assert_matches!(err, ConnectError::AllAttemptsFailed);
+        // The saved connection was ignored, so the route's IPv6 and IPv4
+        // addresses were both connected fresh.
+        assert_eq!(number_of_times_called.load(atomic::Ordering::SeqCst), 3);
+
+        // Forget about the failures so the next attempt starts from the same route.
+        lock_connect_state(&connect_state).network_changed(tokio::time::Instant::now());
+
+        let err = connect(PreconnectPolicy::UseIfAvailable)
+            .await
+            .expect_err("should fail to connect");
+        assert_matches!(err, ConnectError::AllAttemptsFailed);
+        // The saved connection is still available and gets used in place of
+        // the first address; only the IPv4 follow-up is connected fresh.
+        assert_eq!(number_of_times_called.load(atomic::Ordering::SeqCst), 4);
+    }
 }
@@ -8,7 +8,9 @@ use libsignal_net_infra::extract_retry_later;
 use libsignal_net_infra::route::ConnectError as RouteConnectError;
 use libsignal_net_infra::timeouts::TimeoutOr;
 use libsignal_net_infra::ws::{WebSocketConnectError, WebSocketServiceError};
+use tungstenite::error::ProtocolError;
 
+use crate::connect_state::PreconnectError;
 use crate::ws::WebSocketServiceConnectError;
 
 /// Error that can occur when sending a request to the Chat service.
@@ -28,6 +30,11 @@ pub enum SendError {
     IncomingDataInvalid,
     /// request object must contain only ASCII text as header names and values.
     RequestHasInvalidHeader,
+    /// the server is down for maintenance
+    ServerMaintenance {
+        /// How long the server asked us to wait, if it said.
+        retry_after: Option<RetryLater>,
+    },
 }
 impl LogSafeDisplay for SendError where WebSocketServiceError: LogSafeDisplay {}
 
@@ -48,6 +55,21 @@ pub enum ConnectError {
     AppExpired,
     /// device was deregistered
     DeviceDeregistered,
+    /// connected, but the server did not respond
+    NoServerResponse,
+    /// the server is down for maintenance
+    ServerMaintenance {
+        /// How long the server asked us to wait, if it said.
+        retry_after: Option<RetryLater>,
+    },
+    /// the network changed too often to connect
+    UnstableNetwork,
+    /// too many connects were started recently
+    Throttled,
+    /// the server didn't select a supported websocket subprotocol
+    UnsupportedSubprotocol,
+    /// the connect attempt was cancelled
+    Cancelled,
 }
 impl LogSafeDisplay for ConnectError {}
 
@@ -61,6 +83,9 @@ impl<T: Into<ConnectError>> From<TimeoutOr<RouteConnectError<T>>> for ConnectErr
                 ConnectError::AllAttemptsFailed
             }
             TimeoutOr::Other(RouteConnectError::FatalConnect(err)) => err.into(),
+            TimeoutOr::Other(RouteConnectError::UnstableNetwork) => ConnectError::UnstableNetwork,
+            TimeoutOr::Other(RouteConnectError::Throttled) => ConnectError::Throttled,
+            TimeoutOr::Other(RouteConnectError::Cancelled) => ConnectError::Cancelled,
             TimeoutOr::Timeout {
                 attempt_duration: _,
             } => ConnectError::Timeout,
@@ -68,20 +93,55 @@ impl<T: Into<ConnectError>> From<TimeoutOr<RouteConnectError<T>>> for ConnectErr
     }
 }
 
+impl From<PreconnectError> for ConnectError {
+    fn from(e: PreconnectError) -> Self {
+        match e {
+            PreconnectError::Timeout {
+                attempt_duration: _,
+            } => ConnectError::Timeout,
+            PreconnectError::NoResolvedRoutes => ConnectError::InvalidConnectionConfiguration,
+            PreconnectError::AllAttemptsFailed { last_error: _ } => ConnectError::AllAttemptsFailed,
+            PreconnectError::Fatal(e) => e.into(),
+            PreconnectError::UnstableNetwork => ConnectError::UnstableNetwork,
+            PreconnectError::Throttled => ConnectError::Throttled,
+            PreconnectError::Cancelled => ConnectError::Cancelled,
+        }
+    }
+}
+
 impl From<WebSocketServiceConnectError> for ConnectError {
     fn from(e: WebSocketServiceConnectError) -> Self {
         match e {
+            WebSocketServiceConnectError::Connect(
+                WebSocketConnectError::WebSocketError(tungstenite::Error::Protocol(
+                    ProtocolError::SecWebSocketSubProtocolError(_),
+                )),
+                _,
+            ) => Self::UnsupportedSubprotocol,
             WebSocketServiceConnectError::Connect(e, _) => Self::WebSocket(e),
+            WebSocketServiceConnectError::InvalidConfirmationHeader { response } => {
+                Self::WebSocket(WebSocketConnectError::WebSocketError(
+                    tungstenite::Error::Http(response),
+                ))
+            }
             WebSocketServiceConnectError::RejectedByServer {
                 response,
                 received_at: _,
             } => {
-                // Retry-After takes precedence over everything else.
-                if let Some(retry_after) = extract_retry_later(response.headers()) {
+                let retry_after = extract_retry_later(response.headers());
+                // A maintenance window is reported as such even with a
+                // Retry-After, so the app can tell the user what's going on.
+                if crate::ws::is_server_maintenance(response.status(), response.headers()) {
+                    return Self::ServerMaintenance { retry_after };
+                }
+                // Otherwise, Retry-After takes precedence over everything else.
+                if let Some(retry_after) = retry_after {
                     return Self::RetryLater(retry_after);
                 }
                 match response.status().as_u16() {
-                    499 => Self::AppExpired,
+                    // 499 is Signal-specific; 426 Upgrade Required is the
+                    // standard way of saying the same thing.
+                    499 | 426 => Self::AppExpired,
                     403 => {
                         // Technically this only applies to identified sockets,
                         // but unidentified sockets should never produce a 403 anyway.
@@ -107,3 +167,24 @@ impl From<TransportConnectError> for ConnectError {
         Self::WebSocket(WebSocketConnectError::Transport(e))
     }
 }
+
+#[cfg(test)]
+mod test {
+    use assert_matches::assert_matches;
+
+    use super::*;
+
+    #[test]
+    fn cancellation_is_preserved() {
+        assert_matches!(
+            ConnectError::from(TimeoutOr::Other(
+                RouteConnectError::<WebSocketServiceConnectError>::Cancelled
+            )),
+            ConnectError::Cancelled
+        );
+        assert_matches!(
+            ConnectError::from(PreconnectError::Cancelled),
+            ConnectError::Cancelled
+        );
+    }
+}
@@ -64,6 +64,7 @@ impl ChatConnection {
             transport_info: TransportInfo {
                 ip_version: IpType::V4,
                 local_port: 0,
+                negotiated_alpn: None,
             },
         };
         let log_tag = "fake chat".into();
@@ -71,6 +72,7 @@ impl ChatConnection {
             local_idle_timeout: Duration::from_secs(86400),
             remote_idle_timeout: Duration::from_secs(86400),
             initial_request_id: 0,
+            subprotocols: &[],
         };
         let headers = http::HeaderMap::from_iter(alerts.into_iter().map(|alert| {
             (
@@ -89,6 +91,9 @@ impl ChatConnection {
                 listener,
             ),
             connection_info,
+            server_time: None,
+            subprotocol: None,
+            request_middleware: None,
         };
         (chat, remote)
     }
@@ -137,6 +142,54 @@ impl FakeChatRemote {
         }
     }
 
+    /// Waits for the next `count` requests from the client.
+    ///
+    /// Together with [`Self::send_response_to`], this lets a test answer
+    /// requests in a different order than they were received in, as a
+    /// misbehaving server or proxy might. Returns `Ok(None)` if the client
+    /// disconnects before sending that many.
+    pub async fn receive_requests(
+        &self,
+        count: usize,
+    ) -> Result<Option<Vec<RequestProto>>, ReceiveRequestError> {
+        let mut requests = Vec::with_capacity(count);
+        while requests.len() < count {
+            let Some(request) = self.receive_request().await? else {
+                return Ok(None);
+            };
+            requests.push(request);
+        }
+        Ok(Some(requests))
+    }
+
+    /// Sends `response` to the client as the answer to the request with ID
+    /// `request_id`, replacing any ID it already had.
+    pub fn send_response_to(
+        &self,
+        request_id: u64,
+        response: ResponseProto,
+    ) -> Result<(), Disconnected> {
+        self.send_response(ResponseProto {
+            id: Some(request_id),
+            ..response
+        })
+    }
+
+    /// Waits for the client to close the connection.
+    ///
+    /// Returns the close frame the client sent, if it had one. Fails if the
+    /// client sends anything other than a close frame first.
+    pub async fn receive_close(
+        &self,
+    ) -> Result<Option<tungstenite::protocol::CloseFrame<'static>>, ReceiveRequestError> {
+        log::debug!("waiting for close");
+        match self.rx.lock().await.recv().await {
+            None => Ok(None),
+            Some(tungstenite::Message::Close(frame)) => Ok(frame),
+            Some(_) => Err(ReceiveRequestError::InvalidWebsocketMessageType),
+        }
+    }
+
     /// Send a close frame to the client.
     pub fn send_close(&self, code: Option<u16>) -> Result<(), Disconnected> {
         self.tx
@@ -20,18 +20,19 @@ pub use libsignal_net_infra::ws2::FinishReason;
 use libsignal_net_infra::ws2::Outcome;
 use pin_project::pin_project;
 use prost::Message as _;
-use tokio::sync::{mpsc, oneshot, Mutex as TokioMutex};
+use tokio::sync::{mpsc, oneshot, watch, Mutex as TokioMutex};
 use tokio::task::JoinHandle;
 use tokio::time::Duration;
 use tokio_stream::wrappers::{ReceiverStream, UnboundedReceiverStream};
 use tungstenite::protocol::frame::coding::CloseCode;
+use tungstenite::protocol::CloseFrame;
 
 use crate::chat::{ChatMessageType, MessageProto, Request, RequestProto, Response, ResponseProto};
 use crate::env::{
     ALERT_HEADER_NAME, CONNECTED_ELSEWHERE_CLOSE_CODE, CONNECTION_INVALIDATED_CLOSE_CODE,
 };
 use crate::infra::ws::TextOrBinary;
-use crate::infra::ws2::{MessageEvent, NextEventError, TungsteniteSendError};
+use crate::infra::ws2::{CloseFrameSlot, MessageEvent, NextEventError, TungsteniteSendError};
 
 /// Chat service avilable via a connected websocket.
 ///
@@ -49,6 +50,10 @@ pub struct Chat {
     /// points. If it were a regular [`Mutex`] the futures produced by methods
     /// on `Chat` would not be `Send`.
     state: TokioMutex<TaskState>,
+    /// Updated by the backing task when the connection ends.
+    connection_state: watch::Receiver<ConnectionState>,
+    /// Read by the backing task when it closes the websocket.
+    close_frame: CloseFrameSlot,
 }
 
 /// Instantiation-time configuration for a [`Chat`] instance.
@@ -70,6 +75,15 @@ pub struct Config {
 
     /// The value to use as the ID for the first outgoing request.
     pub initial_request_id: u64,
+
+    /// Websocket subprotocols to offer in the upgrade request, most preferred
+    /// first.
+    ///
+    /// If this is non-empty, the server has to select one of them; a response
+    /// that selects none, or one that wasn't offered, fails the connect with
+    /// [`ConnectError::UnsupportedSubprotocol`](crate::chat::ConnectError::UnsupportedSubprotocol).
+    /// Only used when connecting.
+    pub subprotocols: &'static [&'static str],
 }
 
 #[derive(Debug)]
@@ -94,6 +108,28 @@ pub enum ListenerEvent {
     Finished(Result<FinishReason, FinishError>),
 }
 
+/// The state of a [`Chat`], as published by [`Chat::state_stream`].
+///
+/// A `Chat` is created already connected, so it only ever moves from
+/// `Connected` to `Disconnected`. Connecting and reconnecting happen before a
+/// `Chat` exists and are up to whoever is establishing connections.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ConnectionState {
+    Connected,
+    Disconnected(DisconnectReason),
+}
+
+/// Why a [`Chat`] moved to [`ConnectionState::Disconnected`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DisconnectReason {
+    /// The local end disconnected first.
+    LocalDisconnect,
+    /// The remote end disconnected first.
+    RemoteDisconnect,
+    /// The connection ended because of an error.
+    Error,
+}
+
 /// Error that can occur during a [`Chat::send`] operation.
 #[derive(Debug)]
 #[cfg_attr(test, derive(PartialEq))]
@@ -169,6 +205,7 @@ impl Chat {
             initial_request_id,
             local_idle_timeout,
             remote_idle_timeout,
+            subprotocols: _,
         } = config;
 
         Self::report_alerts(connect_response_headers, &mut listener);
@@ -211,7 +248,11 @@ impl Chat {
     /// If the request can't be sent or the response isn't received, this
     /// returns an error.
     pub async fn send(&self, request: Request) -> Result<Response, SendError> {
-        let Self { state } = self;
+        let Self {
+            state,
+            connection_state: _,
+            close_frame: _,
+        } = self;
 
         let Request {
             method,
@@ -266,6 +307,23 @@ impl Chat {
         *guard = new_state
     }
 
+    /// Like [`Self::disconnect`], but closes the websocket with `close_frame`
+    /// instead of a frame with no status code.
+    ///
+    /// If the connection is already closing, the frame might not be sent.
+    pub async fn disconnect_with(&self, close_frame: CloseFrame<'static>) {
+        self.close_frame.set(close_frame);
+        self.disconnect().await
+    }
+
+    /// Returns a receiver for the state of the connection.
+    ///
+    /// Only the most recent state is retained, so a slow consumer never holds
+    /// up the connection but might not observe every transition.
+    pub fn state_stream(&self) -> watch::Receiver<ConnectionState> {
+        self.connection_state.clone()
+    }
+
     /// Returns `true` if the websocket is known to be connected.
     ///
     /// If this returns `false`, the websocket is either disconnected or in the
@@ -335,8 +393,10 @@ impl Chat {
             (message, OutgoingMeta::ResponseToIncoming)
         });
 
+        let close_frame = CloseFrameSlot::default();
         let inner_connection = into_inner_connection.into_inner_connection(
             tokio_stream::StreamExt::merge(request_rx, response_rx),
+            close_frame.clone(),
             log_tag.clone(),
         );
 
@@ -345,11 +405,14 @@ impl Chat {
             requests_in_flight,
         };
 
+        let (connection_state_tx, connection_state) = watch::channel(ConnectionState::Connected);
+
         let task = tokio_runtime.spawn(spawned_task_body(
             connection,
             log_tag,
             listener,
             response_tx.downgrade(),
+            connection_state_tx,
         ));
         let state = TaskState::MaybeStillRunning {
             request_tx,
@@ -359,6 +422,8 @@ impl Chat {
 
         Self {
             state: TokioMutex::new(state),
+            connection_state,
+            close_frame,
         }
     }
 }
@@ -618,6 +683,7 @@ async fn spawned_task_body<I: InnerConnection>(
     log_tag: Arc<str>,
     listener: EventListener,
     weak_response_tx: mpsc::WeakUnboundedSender<OutgoingResponse>,
+    connection_state: watch::Sender<ConnectionState>,
 ) -> Result<FinishReason, TaskErrorState> {
     pin_mut!(connection);
     let tokio_rt = tokio::runtime::Handle::current();
@@ -627,6 +693,7 @@ async fn spawned_task_body<I: InnerConnection>(
     // disconnection.
     let mut listener_state = scopeguard::guard_on_unwind(listener_state, |mut listener_state| {
         log::error!("[{log_tag}] chat handler task exited abnormally");
+        connection_state.send_replace(ConnectionState::Disconnected(DisconnectReason::Error));
         listener_state.send_event_blocking(ListenerEvent::Finished(Err(FinishError::Unknown)));
     });
     let result = loop {
@@ -655,6 +722,12 @@ async fn spawned_task_body<I: InnerConnection>(
     }
     let task_result = result.as_ref().map_err(Into::into).copied();
 
+    connection_state.send_replace(ConnectionState::Disconnected(match &result {
+        Ok(FinishReason::LocalDisconnect) => DisconnectReason::LocalDisconnect,
+        Ok(FinishReason::RemoteDisconnect) => DisconnectReason::RemoteDisconnect,
+        Err(_) => DisconnectReason::Error,
+    }));
+
     // The loop is finishing. Make sure to tell the listener after disarming the
     // scope guard.
     let mut listener = scopeguard::ScopeGuard::into_inner(listener_state);
@@ -827,9 +900,13 @@ impl InFlightRequests {
 /// [`InnerConnection`].
 trait IntoInnerConnection {
     /// Turn `self` and an outgoing stream into an `InnerConnection` impl.
+    ///
+    /// The connection should close with the frame in `close_frame`, if any,
+    /// once the outgoing stream ends.
     fn into_inner_connection<R>(
         self,
         outgoing_stream: R,
+        close_frame: CloseFrameSlot,
         log_tag: Arc<str>,
     ) -> impl InnerConnection + Send + 'static
     where
@@ -843,6 +920,7 @@ where
     fn into_inner_connection<R>(
         self,
         outgoing_stream: R,
+        close_frame: CloseFrameSlot,
         log_tag: Arc<str>,
     ) -> impl InnerConnection + Send + 'static
     where
@@ -850,6 +928,7 @@ where
     {
         let (stream, config) = self;
         crate::infra::ws2::Connection::new(stream, outgoing_stream, config, log_tag)
+            .with_close_frame(close_frame)
     }
 }
 
@@ -1365,6 +1444,7 @@ mod test {
             fn into_inner_connection<R>(
                 self,
                 outgoing_stream: R,
+                _close_frame: CloseFrameSlot,
                 _log_tag: Arc<str>,
             ) -> impl InnerConnection + Send + 'static
             where
@@ -1858,6 +1938,29 @@ mod test {
         );
     }
 
+    #[test_case(FinishReason::LocalDisconnect => DisconnectReason::LocalDisconnect)]
+    #[test_case(FinishReason::RemoteDisconnect => DisconnectReason::RemoteDisconnect)]
+    #[test_log::test(tokio::test(start_paused = true))]
+    async fn state_stream_reports_connected_then_disconnected(
+        finish_reason: FinishReason,
+    ) -> DisconnectReason {
+        let (chat, (_inner_events, inner_responses)) = fake::new_chat(Box::new(|_| ()));
+
+        let mut states = chat.state_stream();
+        assert_eq!(*states.borrow_and_update(), ConnectionState::Connected);
+
+        inner_responses
+            .send(Outcome::Finished(Ok(finish_reason)).into())
+            .expect("can send");
+
+        states.changed().await.expect("state was updated");
+        let state = *states.borrow_and_update();
+        // The task has exited, so there won't be any further transitions.
+        assert_matches!(states.changed().await, Err(_));
+
+        assert_matches!(state, ConnectionState::Disconnected(reason) => reason)
+    }
+
     #[test_log::test(tokio::test(start_paused = true))]
     async fn is_not_connected_after_remote_close() {
         let (received_close_tx, mut received_close_rx) = mpsc::unbounded_channel();
@@ -3,54 +3,79 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 //
 
+use std::collections::{HashMap, VecDeque};
 use std::default::Default;
 use std::fmt::Debug;
 use std::future::Future;
+use std::net::SocketAddr;
+use std::num::{NonZeroU32, NonZeroUsize};
 use std::ops::ControlFlow;
 use std::sync::Arc;
 use std::time::Duration;
 
+use either::Either;
 use futures_util::TryFutureExt as _;
 use http::HeaderName;
 use itertools::Itertools as _;
 use libsignal_net_infra::connection_manager::{ErrorClass, ErrorClassifier as _};
-use libsignal_net_infra::dns::DnsResolver;
-use libsignal_net_infra::errors::{LogSafeDisplay, TransportConnectError};
+use libsignal_net_infra::dns::lookup_result::LookupResult;
+use libsignal_net_infra::dns::{DnsError, DnsResolver};
+use libsignal_net_infra::errors::{
+    FailedHandshakeReason, LogSafeDisplay, TlsHandshakeTimeout, TransportConnectError,
+};
 use libsignal_net_infra::route::{
-    ComposedConnector, ConnectError, ConnectionOutcomeParams, ConnectionOutcomes, Connector,
-    ConnectorFactory, DelayBasedOnTransport, DescribeForLog, DescribedRouteConnector,
-    DirectOrProxy, HttpRouteFragment, InterfaceChangedOr, InterfaceMonitor, LoggingConnector,
-    ResolveHostnames, ResolveWithSavedDescription, ResolvedRoute, RouteProvider,
-    RouteProviderContext, RouteProviderExt as _, RouteResolver, ThrottlingConnector,
-    TransportRoute, UnresolvedRouteDescription, UnresolvedTransportRoute,
-    UnresolvedWebsocketServiceRoute, UsePreconnect, UsesTransport, VariableTlsTimeoutConnector,
-    WebSocketRouteFragment, WebSocketServiceRoute,
+    AgedOutSuccessOrdering, AttemptOutcome, ComposedConnector, ConnectError, ConnectObserver,
+    ConnectPhase, ConnectPriority, ConnectionOutcomeParams, ConnectionOutcomes,
+    ConnectionProxyRoute, Connector, ConnectorFactory, DelayBasedOnTransport, DescribeForLog,
+    DescribedRouteConnector, DirectOrProxy, DirectOrProxyRoute, EchRetryConnector,
+    HttpRouteFragment, HttpsProxyRoute, HttpsTlsRoute, InterfaceChangedOr, InterfaceMonitor,
+    LocalIpSource, LoggingConnector, PhaseDurations, PhaseTimingConnector, RecordPhasesConnector,
+    ResolveHostnames, ResolveWithSavedDescription, ResolvedRoute, RouteAbandonReason,
+    RouteDelayPolicy, RouteProvider, RouteProviderContext, RouteProviderExt as _, RouteResolver,
+    SharedConnectPermits, SocksRoute, TcpRoute, ThrottlingConnector, TimeoutConnector,
+    TimeoutResolver, TlsRoute, TransportRoute, UnresolvedHost, UnresolvedRouteDescription,
+    UnresolvedTransportRoute, UnresolvedWebsocketServiceRoute, UnsuccessfulOutcome, UsePreconnect,
+    UsesTransport, VariableTlsTimeoutConnector, WebSocketRoute, WebSocketRouteFragment,
+    WebSocketServiceRoute, WithLoggableDescription,
 };
 use libsignal_net_infra::tcp_ssl::{LONG_TCP_HANDSHAKE_THRESHOLD, LONG_TLS_HANDSHAKE_THRESHOLD};
 use libsignal_net_infra::timeouts::{
-    TimeoutOr, MIN_TLS_HANDSHAKE_TIMEOUT, NETWORK_INTERFACE_POLL_INTERVAL,
+    PhaseTimeouts, TimeoutOr, MIN_TLS_HANDSHAKE_TIMEOUT, NETWORK_INTERFACE_POLL_INTERVAL,
     ONE_ROUTE_CONNECTION_TIMEOUT, POST_ROUTE_CHANGE_CONNECTION_TIMEOUT,
 };
 use libsignal_net_infra::utils::ObservableEvent;
-use libsignal_net_infra::ws::{WebSocketConnectError, WebSocketStreamLike};
-use libsignal_net_infra::ws2::attested::AttestedConnection;
-use libsignal_net_infra::{AsHttpHeader as _, AsyncDuplexStream};
+use libsignal_net_infra::ws::{
+    websocket_upgrade_headers, WebSocketConnectError, WebSocketStreamLike,
+};
+use libsignal_net_infra::ws2::attested::{AttestationCheck, AttestedConnection};
+use libsignal_net_infra::{AsHttpHeader as _, AsyncDuplexStream, DnsSource, IpType, RouteType};
 use rand::Rng;
 use rand_core::OsRng;
 use static_assertions::assert_eq_size_val;
 use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
 
 use crate::auth::Auth;
 use crate::enclave::{EndpointParams, NewHandshake};
 use crate::ws::WebSocketServiceConnectError;
 
+mod persisted_outcomes;
+pub use persisted_outcomes::ImportOutcomesError;
+
+mod spans;
+
 /// Suggested values for [`ConnectionOutcomeParams`].
 pub const SUGGESTED_CONNECT_PARAMS: ConnectionOutcomeParams = ConnectionOutcomeParams {
     age_cutoff: Duration::from_secs(5 * 60),
     cooldown_growth_factor: 10.0,
     max_count: 5,
     max_delay: Duration::from_secs(30),
+    // Far more than a single network should ever need, but bounded in case the
+    // device sees lots of networks within the age cutoff.
+    max_entries: Some(nonzero_ext::nonzero!(1000usize)),
     count_growth_factor: 10.0,
+    aged_out_successes: AgedOutSuccessOrdering::Unranked,
+    jitter_fraction: 0.0,
 };
 
 /// Suggested values for [`Config`].
@@ -59,6 +84,13 @@ pub const SUGGESTED_CONNECT_CONFIG: Config = Config {
     connect_timeout: ONE_ROUTE_CONNECTION_TIMEOUT,
     network_interface_poll_interval: NETWORK_INTERFACE_POLL_INTERVAL,
     post_route_change_connect_timeout: POST_ROUTE_CHANGE_CONNECTION_TIMEOUT,
+    max_fronting_domains: None,
+    preconnect_timeout: None,
+    phase_timeouts: PhaseTimeouts::NONE,
+    unstable_network: None,
+    max_concurrent_attestations: None,
+    tcp_fast_open: false,
+    dscp: None,
 };
 
 /// Suggested lifetime for a [`PreconnectingConnector`] that handles up to a TLS handshake.
@@ -99,22 +131,84 @@ pub struct ConnectState<ConnectorFactory = DefaultConnectorFactory> {
     network_interface_poll_interval: Duration,
     /// The amount of time allowed for a connection attempt after a network change.
     post_route_change_connect_timeout: Duration,
+    /// The maximum number of distinct domain fronts to try per connection attempt.
+    max_fronting_domains: Option<NonZeroUsize>,
+    /// Replaces `connect_timeout` for preconnects, if set.
+    preconnect_timeout: Option<Duration>,
     /// Transport-level connector used for all connections.
     make_transport_connector: ConnectorFactory,
     /// Record of connection outcomes.
     attempts_record: ConnectionOutcomes<TransportRoute>,
     /// [`RouteProviderContext`] passed to route providers.
     route_provider_context: RouteProviderContextImpl,
+    /// Notified about the progress of websocket connection attempts.
+    connect_observer: Option<Arc<dyn ConnectObserver<RouteInfo> + Send + Sync>>,
+    /// Notified as websocket connection attempts reach each [`ConnectStage`].
+    connect_progress: Option<Arc<dyn Fn(ConnectStage) + Send + Sync>>,
+    /// Used to detect when the preferred network route changes mid-connect.
+    local_ip_source: LocalIpSource,
+    /// Rate limit on websocket connects, possibly shared with other
+    /// `ConnectState`s.
+    global_throttle: Option<GlobalConnectThrottle>,
+    /// Whether to include [`RouteInfo::resolved_target`] for successful
+    /// connects.
+    report_resolved_target: bool,
+    /// Whether to include [`RouteInfo::connect_timing`] for successful
+    /// connects.
+    report_connect_timing: bool,
+    /// Limits on the DNS and websocket phases of each route's attempt.
+    phase_timeouts: PhaseTimeouts,
+    /// Routes of this type are tried before any others, if there are any.
+    preferred_route_hint: Option<RouteType>,
+    /// When to stop connecting because network changes keep aborting attempts.
+    unstable_network: Option<UnstableNetworkParams>,
+    /// When recent connects were aborted by a network change, oldest first.
+    interface_change_aborts: VecDeque<Instant>,
+    /// Successful websocket connects since the last network change.
+    connectivity_stats: ConnectivityStats,
+    /// Limits how many enclave attestations can be in progress at once.
+    attestation_permits: Option<Arc<tokio::sync::Semaphore>>,
+    /// Used in place of `attempts_record` to decide when to try each route.
+    route_delay_override: Option<RouteDelayOverride>,
+    /// How routes are reordered before they're tried.
+    route_ordering: RouteOrdering,
+    /// Measured throughput of connections over each route.
+    route_bandwidth: RouteBandwidth,
+    /// Sent a [`ConnectAttemptEvent`] for each route tried by a websocket
+    /// connect.
+    connect_attempt_events: Option<tokio::sync::mpsc::Sender<ConnectAttemptEvent>>,
 }
 
-pub type DefaultTransportConnector = VariableTlsTimeoutConnector<
-    ThrottlingConnector<LoggingConnector<crate::infra::tcp_ssl::StatelessTls>>,
-    crate::infra::route::DirectOrProxy<
-        LoggingConnector<crate::infra::tcp_ssl::StatelessTcp>,
-        crate::infra::tcp_ssl::proxy::StatelessProxied,
+/// Decides how long to hold back a route, given the record of recent
+/// connection outcomes and the current time.
+///
+/// See [`ConnectState::set_route_delay_override`].
+pub type RouteDelayOverride = Arc<
+    dyn Fn(&TransportRoute, &ConnectionOutcomes<TransportRoute>, Instant) -> Duration + Send + Sync,
+>;
+
+pub type DefaultTransportConnector = EchRetryConnector<
+    VariableTlsTimeoutConnector<
+        ThrottlingConnector<
+            PhaseTimingConnector<
+                TimeoutConnector<
+                    LoggingConnector<crate::infra::tcp_ssl::StatelessTls>,
+                    TransportConnectError,
+                >,
+            >,
+        >,
+        PhaseTimingConnector<
+            TimeoutConnector<
+                crate::infra::route::DirectOrProxy<
+                    LoggingConnector<crate::infra::tcp_ssl::StatelessTcp>,
+                    crate::infra::tcp_ssl::proxy::StatelessProxied,
+                    TransportConnectError,
+                >,
+                TransportConnectError,
+            >,
+        >,
         TransportConnectError,
     >,
-    TransportConnectError,
 >;
 
 #[derive(Clone, Debug, PartialEq)]
@@ -123,6 +217,267 @@ pub struct Config {
     pub connect_timeout: Duration,
     pub network_interface_poll_interval: Duration,
     pub post_route_change_connect_timeout: Duration,
+    /// If set, limits how many distinct domain fronts are tried when
+    /// connecting.
+    ///
+    /// The fronts with the fewest recent failures are kept. This bounds how
+    /// much of the connect timeout can be spent on fronting when the network
+    /// blocks all of them. Direct routes are not affected.
+    pub max_fronting_domains: Option<NonZeroUsize>,
+    /// If set, used instead of `connect_timeout` by
+    /// [`ConnectionResources::preconnect_and_save`].
+    ///
+    /// Preconnects happen in the background before they're needed, so they
+    /// can give up sooner than a connect the user is waiting on.
+    pub preconnect_timeout: Option<Duration>,
+    /// Limits on the individual phases of each route's connection attempt.
+    ///
+    /// The DNS and websocket limits are always applied. The TCP and TLS
+    /// limits are applied by [`DefaultConnectorFactory`]; a `ConnectState`
+    /// made with a custom transport connector is responsible for its own.
+    pub phase_timeouts: PhaseTimeouts,
+    /// If set, stops connecting for a while once too many recent connects
+    /// have been aborted by network changes. Off by default.
+    ///
+    /// See [`UnstableNetworkParams`].
+    pub unstable_network: Option<UnstableNetworkParams>,
+    /// If set, limits how many attested connections can be performing their
+    /// enclave handshake at the same time.
+    ///
+    /// Verifying an attestation is much more expensive than a TLS handshake,
+    /// so this is separate from the transport-level limit on concurrent TLS
+    /// handshakes. Connects wait for a permit before they start connecting,
+    /// so no websocket sits idle meanwhile; a connect that doesn't get one
+    /// within `connect_timeout` fails as timed out.
+    pub max_concurrent_attestations: Option<NonZeroUsize>,
+    /// Whether to use TCP Fast Open for direct connections.
+    ///
+    /// Like the TCP and TLS phase timeouts, this is applied by
+    /// [`DefaultConnectorFactory`]. See [`StatelessTcp::tcp_fast_open`].
+    ///
+    /// [`StatelessTcp::tcp_fast_open`]: crate::infra::tcp_ssl::StatelessTcp::tcp_fast_open
+    pub tcp_fast_open: bool,
+    /// A DSCP value to mark packets on direct connections with.
+    ///
+    /// Also applied by [`DefaultConnectorFactory`]. See [`StatelessTcp::dscp`].
+    ///
+    /// [`StatelessTcp::dscp`]: crate::infra::tcp_ssl::StatelessTcp::dscp
+    pub dscp: Option<u8>,
+}
+
+impl Config {
+    /// Starts building a `Config` from [`SUGGESTED_CONNECT_CONFIG`].
+    ///
+    /// Only the fields that differ from the suggested values need to be set,
+    /// so fields added later pick up their suggested values automatically.
+    pub fn builder() -> ConfigBuilder {
+        ConfigBuilder {
+            config: SUGGESTED_CONNECT_CONFIG,
+        }
+    }
+}
+
+/// Builds a [`Config`]; see [`Config::builder`].
+///
+/// Each setter overrides the corresponding [`Config`] field.
+#[derive(Clone, Debug)]
+#[must_use]
+pub struct ConfigBuilder {
+    config: Config,
+}
+
+impl ConfigBuilder {
+    pub fn connect_params(mut self, connect_params: ConnectionOutcomeParams) -> Self {
+        self.config.connect_params = connect_params;
+        self
+    }
+
+    pub fn connect_timeout(mut self, connect_timeout: Duration) -> Self {
+        self.config.connect_timeout = connect_timeout;
+        self
+    }
+
+    pub fn network_interface_poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.config.network_interface_poll_interval = poll_interval;
+        self
+    }
+
+    pub fn post_route_change_connect_timeout(mut self, connect_timeout: Duration) -> Self {
+        self.config.post_route_change_connect_timeout = connect_timeout;
+        self
+    }
+
+    pub fn max_fronting_domains(mut self, max_fronting_domains: Option<NonZeroUsize>) -> Self {
+        self.config.max_fronting_domains = max_fronting_domains;
+        self
+    }
+
+    pub fn preconnect_timeout(mut self, preconnect_timeout: Option<Duration>) -> Self {
+        self.config.preconnect_timeout = preconnect_timeout;
+        self
+    }
+
+    pub fn phase_timeouts(mut self, phase_timeouts: PhaseTimeouts) -> Self {
+        self.config.phase_timeouts = phase_timeouts;
+        self
+    }
+
+    pub fn unstable_network(mut self, unstable_network: Option<UnstableNetworkParams>) -> Self {
+        self.config.unstable_network = unstable_network;
+        self
+    }
+
+    pub fn max_concurrent_attestations(
+        mut self,
+        max_concurrent_attestations: Option<NonZeroUsize>,
+    ) -> Self {
+        self.config.max_concurrent_attestations = max_concurrent_attestations;
+        self
+    }
+
+    pub fn tcp_fast_open(mut self, tcp_fast_open: bool) -> Self {
+        self.config.tcp_fast_open = tcp_fast_open;
+        self
+    }
+
+    pub fn dscp(mut self, dscp: Option<u8>) -> Self {
+        self.config.dscp = dscp;
+        self
+    }
+
+    pub fn build(self) -> Config {
+        self.config
+    }
+}
+
+/// How far a websocket connection attempt has gotten, for showing progress.
+///
+/// Stages are ordered from first to last. See
+/// [`ConnectState::set_connect_progress`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ConnectStage {
+    /// Looking up the addresses of the servers to connect to.
+    Resolving,
+    /// Establishing a transport connection to a server (or proxy).
+    ///
+    /// This covers both the TCP connection and the TLS handshake. The
+    /// transport connector is opaque to `ConnectState`, so the two aren't
+    /// reported separately.
+    ConnectingTransport,
+    /// Upgrading an established transport connection to a websocket.
+    Upgrading,
+    /// The websocket connection was established.
+    Connected,
+}
+
+/// A single route tried by a websocket connect.
+///
+/// See [`ConnectState::set_connect_attempt_events`].
+#[derive(Debug)]
+pub struct ConnectAttemptEvent {
+    pub route: UnresolvedRouteDescription,
+    /// When the attempt started, or for a skipped route, when the connect
+    /// started.
+    pub started: Instant,
+    pub outcome: ConnectAttemptOutcome,
+}
+
+#[derive(Debug)]
+pub enum ConnectAttemptOutcome {
+    Succeeded,
+    Failed(ErrorClass),
+    TimedOut,
+    /// The route wasn't tried because it was still cooling down from an
+    /// earlier failure when the connect finished.
+    SkippedDueToCooldown,
+}
+
+/// How [`ConnectState`] reorders the routes it's given before trying them.
+///
+/// See [`ConnectState::set_route_ordering`].
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum RouteOrdering {
+    /// Keep the order routes are provided in.
+    #[default]
+    AsProvided,
+    /// Try routes with a higher recorded bandwidth first.
+    ///
+    /// Routes without a recorded bandwidth go after those with one.
+    HighestBandwidthFirst,
+}
+
+/// Smoothed throughput estimates, in bytes per second, for the routes passed
+/// to [`ConnectState::record_bandwidth`].
+#[derive(Clone, Debug, Default)]
+struct RouteBandwidth(Vec<(UnresolvedRouteDescription, f64)>);
+
+impl RouteBandwidth {
+    /// How much a new measurement counts for in a route's estimate.
+    const NEW_MEASUREMENT_WEIGHT: f64 = 0.5;
+
+    fn record(&mut self, route: &UnresolvedRouteDescription, bytes_per_second: f64) {
+        let Self(estimates) = self;
+        match estimates.iter_mut().find(|(r, _)| r == route) {
+            Some((_, estimate)) => {
+                *estimate += (bytes_per_second - *estimate) * Self::NEW_MEASUREMENT_WEIGHT
+            }
+            None => estimates.push((route.clone(), bytes_per_second)),
+        }
+    }
+
+    fn estimate(&self, route: &UnresolvedRouteDescription) -> Option<f64> {
+        let Self(estimates) = self;
+        estimates
+            .iter()
+            .find_map(|(r, estimate)| (r == route).then_some(*estimate))
+    }
+}
+
+/// Successful websocket connects, counted by the kind of route they used.
+///
+/// A network where connects only ever succeed through domain fronting is
+/// likely to be blocking Signal. See [`ConnectState::connectivity_stats`].
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ConnectivityStats {
+    /// Connects that didn't use a domain front or a proxy.
+    pub direct_successes: u32,
+    /// Connects through a domain front, whether or not they also used a proxy.
+    pub fronted_successes: u32,
+    /// Connects through a proxy but not a domain front.
+    pub proxied_successes: u32,
+}
+
+impl ConnectivityStats {
+    fn record_success(&mut self, route: &UnresolvedRouteDescription) {
+        let Self {
+            direct_successes,
+            fronted_successes,
+            proxied_successes,
+        } = self;
+        let count = if route.front().is_some() {
+            fronted_successes
+        } else if route.route_type() == Some(RouteType::Direct) {
+            direct_successes
+        } else {
+            proxied_successes
+        };
+        *count = count.saturating_add(1);
+    }
+}
+
+/// Threshold for [`Config::unstable_network`].
+///
+/// A connect that is abandoned because the network changed out from under it
+/// normally fails with [`TransportConnectError::ClientAbort`], and the app is
+/// free to try again right away. On a network that flaps constantly, that can
+/// turn into a loop of connects that never get anywhere. Once `max_aborts`
+/// connects have been aborted within `window`, connects instead fail with
+/// [`ConnectError::UnstableNetwork`] without trying any routes, until the
+/// oldest of those aborts is more than `window` ago.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct UnstableNetworkParams {
+    pub max_aborts: NonZeroUsize,
+    pub window: Duration,
 }
 
 pub struct ConnectionResources<'a, TC> {
@@ -132,7 +487,21 @@ pub struct ConnectionResources<'a, TC> {
     pub confirmation_header_name: Option<HeaderName>,
 }
 
-pub struct DefaultConnectorFactory;
+#[derive(Clone, Debug, Default)]
+pub struct DefaultConnectorFactory {
+    /// Only the TCP and TLS limits are used here.
+    pub phase_timeouts: PhaseTimeouts,
+    /// See [`Config::tcp_fast_open`].
+    pub tcp_fast_open: bool,
+    /// See [`Config::dscp`].
+    pub dscp: Option<u8>,
+    /// If set, TLS handshakes draw from this pool, at this priority, instead
+    /// of each connector allowing one at a time.
+    ///
+    /// See [`ConnectState::set_shared_tls_permits`].
+    pub shared_tls_permits: Option<(SharedConnectPermits, ConnectPriority)>,
+}
+
 impl<R> ConnectorFactory<R> for DefaultConnectorFactory
 where
     DefaultTransportConnector: Connector<R, ()>,
@@ -141,26 +510,103 @@ where
     type Connection = <DefaultTransportConnector as Connector<R, ()>>::Connection;
 
     fn make(&self) -> Self::Connector {
-        let throttle_tls_connections = ThrottlingConnector::new(
-            LoggingConnector::new(Default::default(), LONG_TLS_HANDSHAKE_THRESHOLD, "TLS"),
-            1,
+        let Self {
+            phase_timeouts,
+            tcp_fast_open,
+            dscp,
+            shared_tls_permits: _,
+        } = self;
+        let tls_connector = PhaseTimingConnector::new(
+            TimeoutConnector::new(
+                LoggingConnector::new(Default::default(), LONG_TLS_HANDSHAKE_THRESHOLD, "TLS"),
+                phase_timeouts.tls_handshake,
+                "TLS handshake",
+                || TlsHandshakeTimeout.into(),
+            ),
+            ConnectPhase::Tls,
         );
-        let proxy_or_direct_connector = DirectOrProxy::new(
-            LoggingConnector::new(Default::default(), LONG_TCP_HANDSHAKE_THRESHOLD, "TCP"),
-            // Proxy connectors use LoggingConnector internally
-            Default::default(),
+        let throttle_tls_connections = self.throttle_tls_handshakes(tls_connector);
+        let proxy_or_direct_connector = PhaseTimingConnector::new(
+            TimeoutConnector::new(
+                DirectOrProxy::new(
+                    LoggingConnector::new(
+                        crate::infra::tcp_ssl::StatelessTcp {
+                            tcp_fast_open: *tcp_fast_open,
+                            dscp: *dscp,
+                        },
+                        LONG_TCP_HANDSHAKE_THRESHOLD,
+                        "TCP",
+                    ),
+                    // Proxy connectors use LoggingConnector internally
+                    Default::default(),
+                ),
+                phase_timeouts.tcp_connect,
+                "TCP connect",
+                || TransportConnectError::TcpConnectionFailed,
+            ),
+            ConnectPhase::Tcp,
         );
-        VariableTlsTimeoutConnector::new(
+        EchRetryConnector::new(VariableTlsTimeoutConnector::new(
             throttle_tls_connections,
             proxy_or_direct_connector,
             MIN_TLS_HANDSHAKE_TIMEOUT,
-        )
+        ))
+    }
+}
+
+impl DefaultConnectorFactory {
+    /// A factory with no phase timeouts, TCP Fast Open, DSCP marking, or
+    /// shared TLS permits; the same as [`Default::default`].
+    pub const fn new() -> Self {
+        Self {
+            phase_timeouts: PhaseTimeouts::NONE,
+            tcp_fast_open: false,
+            dscp: None,
+            shared_tls_permits: None,
+        }
+    }
+
+    /// A factory that applies the transport-level settings from `config`.
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            phase_timeouts: config.phase_timeouts,
+            tcp_fast_open: config.tcp_fast_open,
+            dscp: config.dscp,
+            ..Self::new()
+        }
+    }
+
+    /// Wraps a TLS handshake connector so it takes a permit from
+    /// `shared_tls_permits`, or from its own single-permit pool if that isn't
+    /// set.
+    fn throttle_tls_handshakes<C>(&self, tls_connector: C) -> ThrottlingConnector<C> {
+        match &self.shared_tls_permits {
+            Some((pool, priority)) => {
+                ThrottlingConnector::new_shared(tls_connector, pool, *priority)
+            }
+            None => ThrottlingConnector::new(tls_connector, 1),
+        }
     }
 }
 
 impl ConnectState {
     pub fn new(config: Config) -> std::sync::Mutex<Self> {
-        Self::new_with_transport_connector(config, DefaultConnectorFactory)
+        let factory = DefaultConnectorFactory::from_config(&config);
+        Self::new_with_transport_connector(config, factory)
+    }
+
+    /// Makes subsequent connects take a permit from `permits` for each TLS
+    /// handshake, at the given priority.
+    ///
+    /// By default, each connect allows one TLS handshake at a time. Giving
+    /// the same pool to several `ConnectState`s instead limits their combined
+    /// number of handshakes in progress, and lets the higher-priority ones go
+    /// first when they have to wait. See [`SharedConnectPermits`].
+    pub fn set_shared_tls_permits(
+        &mut self,
+        permits: Option<(SharedConnectPermits, ConnectPriority)>,
+    ) {
+        self.make_transport_connector.shared_tls_permits = permits;
     }
 }
 
@@ -174,33 +620,439 @@ impl<ConnectorFactory> ConnectState<ConnectorFactory> {
             connect_timeout,
             network_interface_poll_interval,
             post_route_change_connect_timeout,
+            max_fronting_domains,
+            preconnect_timeout,
+            phase_timeouts,
+            unstable_network,
+            max_concurrent_attestations,
+            // Only used by DefaultConnectorFactory.
+            tcp_fast_open: _,
+            dscp: _,
         } = config;
         Self {
             route_resolver: RouteResolver::default(),
             connect_timeout,
             network_interface_poll_interval,
             post_route_change_connect_timeout,
+            max_fronting_domains,
+            preconnect_timeout,
             make_transport_connector,
             attempts_record: ConnectionOutcomes::new(connect_params),
             route_provider_context: RouteProviderContextImpl::default(),
+            connect_observer: None,
+            connect_progress: None,
+            local_ip_source: LocalIpSource::Os,
+            global_throttle: None,
+            report_resolved_target: false,
+            report_connect_timing: false,
+            phase_timeouts,
+            preferred_route_hint: None,
+            unstable_network,
+            interface_change_aborts: VecDeque::new(),
+            connectivity_stats: Default::default(),
+            attestation_permits: max_concurrent_attestations
+                .map(|limit| Arc::new(tokio::sync::Semaphore::new(limit.get()))),
+            route_delay_override: None,
+            route_ordering: Default::default(),
+            route_bandwidth: Default::default(),
+            connect_attempt_events: None,
         }
         .into()
     }
 
     pub fn network_changed(&mut self, network_change_time: Instant) {
         self.attempts_record.reset(network_change_time);
+        self.connectivity_stats = ConnectivityStats::default();
+        self.route_bandwidth = RouteBandwidth::default();
+    }
+
+    /// The parameters used to decide how long to hold back routes that
+    /// recently failed.
+    pub fn connect_params(&self) -> &ConnectionOutcomeParams {
+        self.attempts_record.params()
+    }
+
+    /// Immediately forgets any connection outcomes that are too old to affect
+    /// future connects, returning how many were removed.
+    ///
+    /// Old outcomes are otherwise only removed when new ones are recorded.
+    pub fn gc_outcomes(&mut self, now: Instant) -> usize {
+        self.attempts_record.remove_aged_out(now)
+    }
+
+    /// Saves the recent connection outcomes so they can be restored with
+    /// [`Self::import_outcomes`], e.g. after an app restart.
+    ///
+    /// Outcome times are saved relative to `now`, so the blob should be
+    /// imported with a reference time that accounts for how long it was
+    /// stored.
+    ///
+    /// The blob lists the IP addresses, SNIs, and proxy hosts of recently
+    /// failed routes in plaintext, so it should be stored as securely as other
+    /// private app data.
+    pub fn export_outcomes(&self, now: Instant) -> Vec<u8> {
+        persisted_outcomes::export(&self.attempts_record, now)
+    }
+
+    /// Restores outcomes saved by [`Self::export_outcomes`], returning how many
+    /// were added.
+    ///
+    /// Outcomes that are too old relative to `now`, or for routes that can't
+    /// be understood, are skipped. Routes with more recent outcomes already
+    /// recorded keep those.
+    pub fn import_outcomes(
+        &mut self,
+        blob: &[u8],
+        now: Instant,
+    ) -> Result<usize, ImportOutcomesError> {
+        persisted_outcomes::import(&mut self.attempts_record, blob, now)
+    }
+
+    /// Counts the websocket connects that have succeeded since the last
+    /// [network change](Self::network_changed), by the kind of route used.
+    pub fn connectivity_stats(&self) -> ConnectivityStats {
+        self.connectivity_stats
+    }
+
+    /// Records that the connection described by `route` turned out not to
+    /// work after all, for example because the server never responded once it
+    /// was established.
+    ///
+    /// This counts against the route in future connects the same way a failed
+    /// attempt would. Does nothing if `route` isn't from a successful connect.
+    pub fn record_failed_connection(&mut self, route: &RouteInfo, now: Instant) {
+        let Some(transport) = &route.connected_transport else {
+            return;
+        };
+        self.attempts_record.apply_outcome_updates(
+            [(
+                transport.clone(),
+                AttemptOutcome {
+                    started: now,
+                    result: Err(UnsuccessfulOutcome),
+                },
+            )],
+            now,
+        );
+    }
+
+    /// Records that a connect was aborted by a network change at `now`.
+    ///
+    /// Returns whether that makes the network unstable, as defined by
+    /// [`Config::unstable_network`].
+    fn record_interface_change_abort(&mut self, now: Instant) -> bool {
+        if self.unstable_network.is_none() {
+            return false;
+        }
+        self.interface_change_aborts.push_back(now);
+        self.is_network_unstable(now)
+    }
+
+    /// Checks whether enough recent connects were aborted by network changes
+    /// that new ones shouldn't be attempted yet.
+    ///
+    /// Aborts too old to count are forgotten along the way.
+    fn is_network_unstable(&mut self, now: Instant) -> bool {
+        let Some(UnstableNetworkParams { max_aborts, window }) = self.unstable_network else {
+            return false;
+        };
+        while self
+            .interface_change_aborts
+            .front()
+            .is_some_and(|aborted_at| now.saturating_duration_since(*aborted_at) >= window)
+        {
+            self.interface_change_aborts.pop_front();
+        }
+        self.interface_change_aborts.len() >= max_aborts.get()
+    }
+
+    /// Sets the observer to notify about subsequent websocket connection
+    /// attempts, replacing any previous one.
+    pub fn set_connect_observer(
+        &mut self,
+        observer: Option<Arc<dyn ConnectObserver<RouteInfo> + Send + Sync>>,
+    ) {
+        self.connect_observer = observer;
+    }
+
+    /// Sets the callback to notify about the progress of subsequent websocket
+    /// connection attempts, replacing any previous one.
+    ///
+    /// Each attempt starts by reporting [`ConnectStage::Resolving`]. After
+    /// that, a stage is only reported the first time any route reaches it, so
+    /// routes that fail partway through don't make progress go backwards while
+    /// other routes are still being tried. An attempt that fails doesn't report
+    /// anything more; the caller finds out from the result.
+    ///
+    /// The callback is invoked synchronously from the connect and so should
+    /// return quickly, e.g. by forwarding the stage to a
+    /// [`tokio::sync::watch`] channel.
+    pub fn set_connect_progress(
+        &mut self,
+        progress: Option<Arc<dyn Fn(ConnectStage) + Send + Sync>>,
+    ) {
+        self.connect_progress = progress;
+    }
+
+    /// Sets where to send a [`ConnectAttemptEvent`] for each route tried by
+    /// subsequent websocket connects, replacing any previous sender.
+    ///
+    /// Events are sent with [`try_send`](tokio::sync::mpsc::Sender::try_send),
+    /// so if the channel is full or closed they're dropped rather than holding
+    /// up the connect.
+    pub fn set_connect_attempt_events(
+        &mut self,
+        events: Option<tokio::sync::mpsc::Sender<ConnectAttemptEvent>>,
+    ) {
+        self.connect_attempt_events = events;
+    }
+
+    /// Overrides how the local IP for reaching a server is determined when
+    /// checking for network changes during a connect.
+    ///
+    /// Connection attempts are abandoned early if this changes while they're
+    /// in progress.
+    pub fn set_local_ip_source(&mut self, source: LocalIpSource) {
+        self.local_ip_source = source;
     }
+
+    /// Makes subsequent websocket connects take a token from `throttle` first.
+    ///
+    /// The same throttle can be given to several `ConnectState`s to limit
+    /// their combined rate of connection attempts.
+    pub fn set_global_throttle(&mut self, throttle: Option<GlobalConnectThrottle>) {
+        self.global_throttle = throttle;
+    }
+
+    /// Makes subsequent websocket connects try routes of type `hint` first.
+    ///
+    /// This is meant to be seeded at startup from a route type the app saved
+    /// after a previous successful connect (see
+    /// [`UnresolvedRouteDescription::route_type`]), so that reconnecting after
+    /// a restart doesn't have to wait for routes that didn't work last time.
+    /// Unlike recorded connection outcomes, it doesn't delay any routes; it
+    /// only changes which go first. A hint that doesn't match any of the
+    /// available routes is ignored.
+    pub fn set_preferred_route_hint(&mut self, hint: Option<RouteType>) {
+        self.preferred_route_hint = hint;
+    }
+
+    /// Replaces the delay-based ordering of routes computed from
+    /// [`Config::connect_params`] with a custom one.
+    ///
+    /// Routes are still scheduled by delay, since they're resolved (and so
+    /// become available to try) a few at a time: a route with a shorter delay
+    /// is tried before one with a longer delay, and routes with equal delays
+    /// keep their original order. The function is given the same outcome
+    /// record the default ordering uses, which is still updated after each
+    /// connect. It must return the same delay for the same inputs so that
+    /// connects stay reproducible. Passing `None` restores the default.
+    pub fn set_route_delay_override(
+        &mut self,
+        compute_delay: Option<
+            impl Fn(&TransportRoute, &ConnectionOutcomes<TransportRoute>, Instant) -> Duration
+                + Send
+                + Sync
+                + 'static,
+        >,
+    ) {
+        self.route_delay_override =
+            compute_delay.map(|compute_delay| Arc::new(compute_delay) as RouteDelayOverride);
+    }
+
+    /// Chooses how routes are reordered before each websocket connect.
+    ///
+    /// [`RouteOrdering::HighestBandwidthFirst`] is meant for connections that
+    /// will carry large transfers, where throughput matters more than how
+    /// quickly the connection is set up. It's applied before the
+    /// [preferred route hint](Self::set_preferred_route_hint), so a hinted
+    /// route type still goes first.
+    pub fn set_route_ordering(&mut self, ordering: RouteOrdering) {
+        self.route_ordering = ordering;
+    }
+
+    /// Records that a connection over `route` transferred `bytes` in
+    /// `elapsed`.
+    ///
+    /// `route` should be the [`RouteInfo::unresolved`] reported for the
+    /// connection. Measurements for the same route are averaged, favoring
+    /// recent ones, and are all forgotten on a
+    /// [network change](Self::network_changed). A measurement over no time at
+    /// all is ignored.
+    pub fn record_bandwidth(
+        &mut self,
+        route: &UnresolvedRouteDescription,
+        bytes: u64,
+        elapsed: Duration,
+    ) {
+        if elapsed.is_zero() {
+            return;
+        }
+        self.route_bandwidth
+            .record(route, bytes as f64 / elapsed.as_secs_f64());
+    }
+
+    /// Controls whether successful websocket connects report the address they
+    /// connected to, as [`RouteInfo::resolved_target`].
+    ///
+    /// This is off by default, since most callers have no use for the IP
+    /// address and shouldn't be tempted to log it.
+    pub fn set_report_resolved_target(&mut self, report: bool) {
+        self.report_resolved_target = report;
+    }
+
+    /// Controls whether successful websocket connects report how long each
+    /// phase of the winning attempt took, as [`RouteInfo::connect_timing`].
+    pub fn set_report_connect_timing(&mut self, report: bool) {
+        self.report_connect_timing = report;
+    }
+
+    /// Estimates how long a connection attempt over `routes` would wait
+    /// before trying the first one, given recent failures.
+    ///
+    /// The result is the time the least-delayed route would be tried if the
+    /// attempt started now. Returns `None` if any of the routes could be tried
+    /// right away (or if there are no routes at all). Since `routes` haven't
+    /// been resolved yet, they're matched against recorded attempts by the
+    /// host they connect to; addresses that have never been tried aren't taken
+    /// into account.
+    pub fn next_attempt_hint<UR>(&self, routes: impl RouteProvider<Route = UR>) -> Option<Instant>
+    where
+        UR: DescribeForLog<Description = UnresolvedRouteDescription>,
+    {
+        let now = Instant::now();
+        let mut min_delay = None;
+        for route in routes.routes(&self.route_provider_context) {
+            let description = route.describe_for_log();
+            let delay = self
+                .attempts_record
+                .min_delay_matching(
+                    |route| route.fragment.sni == *description.target_host(),
+                    now,
+                )
+                .unwrap_or_default();
+            if delay.is_zero() {
+                return None;
+            }
+            min_delay = Some(min_delay.map_or(delay, |min: Duration| min.min(delay)));
+        }
+        min_delay.map(|delay| now + delay)
+    }
+
+    /// Lists the headers that connecting over each of `routes` would send in
+    /// the websocket upgrade request, without connecting.
+    ///
+    /// If `auth` is provided, it's added the same way as for
+    /// [`ConnectionResources::connect_attested_ws`]. The values of headers
+    /// that carry secrets (like `Authorization`) are replaced with
+    /// `[REDACTED]`, so the result is safe to show for auditing. Note that the
+    /// confirmation header given to [`ConnectionResources`] is checked in the
+    /// server's response and isn't sent.
+    pub fn audit_websocket_headers(
+        &self,
+        routes: impl RouteProvider<Route = UnresolvedWebsocketServiceRoute>,
+        auth: Option<&Auth>,
+    ) -> Vec<(UnresolvedRouteDescription, http::HeaderMap)> {
+        routes
+            .routes(&self.route_provider_context)
+            .map(|mut route| {
+                if let Some(auth) = auth {
+                    add_auth_header(&mut route, auth);
+                }
+                let description = route.describe_for_log();
+                let WebSocketRoute {
+                    fragment: WebSocketRouteFragment { headers, .. },
+                    inner:
+                        HttpsTlsRoute {
+                            fragment: HttpRouteFragment { host_header, .. },
+                            inner: _,
+                        },
+                } = route;
+                let headers = websocket_upgrade_headers(headers, &host_header, REDACTED_HEADER)
+                    .map(redact_secret_headers)
+                    .unwrap_or_else(|_| {
+                        log::warn!("route has an invalid host header; it can't connect");
+                        http::HeaderMap::new()
+                    });
+                (description, headers)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+tokio::task_local! {
+    /// Called with the source location of each acquisition of a
+    /// [`ConnectState`] lock by [`lock_connect_state`] on this task.
+    static ON_CONNECT_STATE_LOCK: Box<dyn Fn(&'static std::panic::Location<'static>)>;
+}
+
+/// Locks `connect_state`.
+///
+/// In test builds, each acquisition is logged with the caller's location and
+/// reported to [`ON_CONNECT_STATE_LOCK`], so lock-ordering problems can be
+/// diagnosed without waiting for a test to hang. In other builds this is
+/// just [`Mutex::lock`](std::sync::Mutex::lock).
+#[cfg_attr(test, track_caller)]
+pub(crate) fn lock_connect_state<TC>(
+    connect_state: &std::sync::Mutex<ConnectState<TC>>,
+) -> std::sync::MutexGuard<'_, ConnectState<TC>> {
+    #[cfg(test)]
+    {
+        let location = std::panic::Location::caller();
+        log::trace!("locking ConnectState at {location}");
+        let _not_observed = ON_CONNECT_STATE_LOCK.try_with(|on_lock| on_lock(location));
+    }
+    connect_state.lock().expect("not poisoned")
+}
+
+const REDACTED_HEADER: http::HeaderValue = http::HeaderValue::from_static("[REDACTED]");
+
+/// Replaces the values of headers that could identify or authenticate the
+/// user with [`REDACTED_HEADER`].
+fn redact_secret_headers(mut headers: http::HeaderMap) -> http::HeaderMap {
+    for name in [
+        http::header::AUTHORIZATION,
+        http::header::PROXY_AUTHORIZATION,
+        http::header::COOKIE,
+        http::header::SEC_WEBSOCKET_KEY,
+    ] {
+        if let http::header::Entry::Occupied(mut entry) = headers.entry(name) {
+            entry.insert(REDACTED_HEADER);
+        }
+    }
+    headers
+}
+
+/// Adds the `Authorization` header for `auth` to the websocket upgrade request
+/// for `route`.
+fn add_auth_header(route: &mut UnresolvedWebsocketServiceRoute, auth: &Auth) {
+    route.fragment.headers.extend([auth.as_header()]);
 }
 
 #[derive(Clone, Debug, PartialEq)]
 pub struct RouteInfo {
     unresolved: UnresolvedRouteDescription,
+    correlation: CorrelationContext,
+    resolved_target: Option<ResolvedTarget>,
+    connect_timing: Option<ConnectTiming>,
+    confirmation_header: Option<Arc<str>>,
+    /// The transport route a successful connect used, so that problems found
+    /// afterwards can be recorded against it.
+    connected_transport: Option<TransportRoute>,
 }
 
 impl LogSafeDisplay for RouteInfo {}
 impl std::fmt::Display for RouteInfo {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let Self { unresolved } = self;
+        let Self {
+            unresolved,
+            correlation: _,
+            resolved_target: _,
+            connect_timing: _,
+            confirmation_header: _,
+            connected_transport: _,
+        } = self;
         (unresolved as &dyn LogSafeDisplay).fmt(f)
     }
 }
@@ -209,502 +1061,4741 @@ impl RouteInfo {
     pub fn fake() -> Self {
         Self {
             unresolved: UnresolvedRouteDescription::fake(),
+            correlation: CorrelationContext::default(),
+            resolved_target: None,
+            connect_timing: None,
+            confirmation_header: None,
+            connected_transport: None,
         }
     }
+
+    /// The address the connection was made to.
+    ///
+    /// This is only present for successful connects made with
+    /// [`ConnectState::set_report_resolved_target`] enabled.
+    pub fn resolved_target(&self) -> Option<&ResolvedTarget> {
+        self.resolved_target.as_ref()
+    }
+
+    /// How long each phase of the successful attempt took.
+    ///
+    /// This is only present for successful connects made with
+    /// [`ConnectState::set_report_connect_timing`] enabled.
+    pub fn connect_timing(&self) -> Option<&ConnectTiming> {
+        self.connect_timing.as_ref()
+    }
+
+    /// The value of the server's confirmation header, if the connection was
+    /// made with a [`ConnectionResources::confirmation_header_name`] and the
+    /// server sent a valid one.
+    ///
+    /// This is only recorded for connections that keep the server's response
+    /// headers, like chat connections. Valid values are plain decimal numbers,
+    /// so this is safe to log.
+    pub fn confirmation_header(&self) -> Option<&str> {
+        self.confirmation_header.as_deref()
+    }
+
+    pub(crate) fn set_confirmation_header(&mut self, value: Option<Arc<str>>) {
+        self.confirmation_header = value;
+    }
+
+    /// The route the connection was made over, as it was before name resolution.
+    pub fn unresolved(&self) -> &UnresolvedRouteDescription {
+        &self.unresolved
+    }
+
+    /// The context the connection attempt over this route was made with.
+    pub fn correlation(&self) -> &CorrelationContext {
+        &self.correlation
+    }
 }
 
-/// A snapshot of [`ConnectState`] for a particular connection attempt.
+/// The socket address a connection was made to, and what was there.
 ///
-/// "Like `ConnectState`, but with a single instantiated connector."
-struct ConnectStateSnapshot<C> {
-    route_resolver: RouteResolver,
-    connect_timeout: Duration,
-    network_interface_poll_interval: Duration,
-    post_route_change_connect_timeout: Duration,
-    transport_connector: C,
-    attempts_record: ConnectionOutcomes<TransportRoute>,
-    route_provider_context: RouteProviderContextImpl,
+/// The [`Display`](std::fmt::Display) impl leaves out the address itself, so
+/// it is safe to log.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ResolvedTarget {
+    /// The address of the server, or of the domain front the route goes
+    /// through.
+    Direct(SocketAddr),
+    /// The address of the proxy the connection was made through. The
+    /// server's own address is only known to the proxy.
+    Proxy(SocketAddr),
 }
 
-impl<TC> ConnectState<TC> {
-    fn snapshot<Transport>(&self) -> ConnectStateSnapshot<TC::Connector>
-    where
-        TC: ConnectorFactory<Transport>,
-    {
-        let Self {
-            route_resolver,
-            connect_timeout,
-            network_interface_poll_interval,
-            post_route_change_connect_timeout,
-            make_transport_connector,
-            attempts_record,
-            route_provider_context,
-        } = self;
+impl ResolvedTarget {
+    pub fn addr(&self) -> SocketAddr {
+        match self {
+            Self::Direct(addr) | Self::Proxy(addr) => *addr,
+        }
+    }
 
-        ConnectStateSnapshot {
-            route_resolver: route_resolver.clone(),
-            connect_timeout: *connect_timeout,
-            network_interface_poll_interval: *network_interface_poll_interval,
-            post_route_change_connect_timeout: *post_route_change_connect_timeout,
-            transport_connector: make_transport_connector.make(),
-            attempts_record: attempts_record.clone(),
-            route_provider_context: route_provider_context.clone(),
+    fn from_transport_route(route: &TransportRoute) -> Self {
+        let TlsRoute {
+            fragment: _,
+            inner: direct_or_proxy,
+        } = route;
+        let (TcpRoute { address, port }, make_target): (_, fn(SocketAddr) -> Self) =
+            match direct_or_proxy {
+                DirectOrProxyRoute::Direct(tcp) => (tcp, Self::Direct),
+                DirectOrProxyRoute::Proxy(proxy) => (
+                    match proxy {
+                        ConnectionProxyRoute::Tls {
+                            proxy:
+                                TlsRoute {
+                                    fragment: _,
+                                    inner: tcp,
+                                },
+                        }
+                        | ConnectionProxyRoute::Tcp { proxy: tcp }
+                        | ConnectionProxyRoute::Socks(SocksRoute { proxy: tcp, .. })
+                        | ConnectionProxyRoute::Https(HttpsProxyRoute {
+                            fragment: _,
+                            inner:
+                                Either::Left(TlsRoute {
+                                    fragment: _,
+                                    inner: tcp,
+                                })
+                                | Either::Right(tcp),
+                        }) => tcp,
+                    },
+                    Self::Proxy,
+                ),
+            };
+        make_target(SocketAddr::new(*address, port.get()))
+    }
+}
+
+impl LogSafeDisplay for ResolvedTarget {}
+impl std::fmt::Display for ResolvedTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let addr = self.addr();
+        let ip_type = IpType::from(&addr.ip());
+        match self {
+            Self::Direct(_) => write!(f, "{ip_type}:{} (direct)", addr.port()),
+            Self::Proxy(_) => write!(f, "{ip_type}:{} (proxy)", addr.port()),
         }
     }
 }
 
-impl<TC> ConnectionResources<'_, TC> {
-    pub async fn connect_ws<WC, UR, Transport>(
-        self,
-        routes: impl RouteProvider<Route = UR>,
-        ws_connector: WC,
-        log_tag: Arc<str>,
-    ) -> Result<(WC::Connection, RouteInfo), TimeoutOr<ConnectError<WebSocketServiceConnectError>>>
-    where
-        UR: ResolveHostnames<Resolved = WebSocketServiceRoute<Transport>>
-            + DescribeForLog<Description = UnresolvedRouteDescription>
-            + Clone
-            + 'static,
-        Transport: Clone + Send + UsesTransport + ResolvedRoute,
-        // Note that we're not using WebSocketTransportConnectorFactory here to make `connect_ws`
-        // easier to test; specifically, the output is not guaranteed to be an AsyncDuplexStream.
-        TC: ConnectorFactory<
-            Transport,
-            Connection: Send,
-            Connector: Sync + Connector<Transport, (), Error: Into<WebSocketConnectError>>,
-        >,
-        WC: Connector<
-                (WebSocketRouteFragment, HttpRouteFragment),
-                TC::Connection,
-                Connection: Send,
-                Error = tungstenite::Error,
-            > + Send
-            + Sync,
-    {
-        let Self {
-            connect_state,
-            dns_resolver,
-            network_change_event,
-            confirmation_header_name,
-        } = self;
+/// How long a successful websocket connect spent in each phase.
+///
+/// The phase durations only cover the route that succeeded. `total` is
+/// measured from the start of the whole attempt, so it also includes any time
+/// spent waiting to try the route (or on other routes that were tried first)
+/// and is never less than the sum of the phases.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ConnectTiming {
+    /// The longest lookup for any of the route's hostnames.
+    pub dns: Duration,
+    pub tcp: Duration,
+    pub tls: Duration,
+    pub upgrade: Duration,
+    pub total: Duration,
+    /// Whether the route used a saved preconnection, in which case `tcp` and
+    /// `tls` are zero.
+    pub preconnected: bool,
+}
 
-        let ConnectStateSnapshot {
-            route_resolver,
-            connect_timeout,
-            network_interface_poll_interval,
-            post_route_change_connect_timeout,
-            transport_connector,
-            attempts_record,
-            route_provider_context,
-        } = connect_state.lock().expect("not poisoned").snapshot();
+impl ConnectTiming {
+    fn new(dns: Duration, phases: PhaseDurations, total: Duration) -> Self {
+        let PhaseDurations {
+            tcp,
+            tls,
+            websocket_upgrade,
+            preconnected,
+        } = phases;
+        Self {
+            dns,
+            tcp,
+            tls,
+            upgrade: websocket_upgrade,
+            total,
+            preconnected,
+        }
+    }
+}
 
-        let routes = routes.routes(&route_provider_context).collect_vec();
+/// Caller-provided key-value pairs that identify a connection attempt.
+///
+/// These are included in the log lines for the attempt and attached to the
+/// [`RouteInfo`]s reported to a [`ConnectObserver`], so that e.g. a distributed
+/// trace can follow a connect across all the routes it tries. Since they end
+/// up in logs, neither keys nor values should contain user data.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CorrelationContext(Vec<(Arc<str>, Arc<str>)>);
 
-        log::info!(
-            "[{log_tag}] starting connection attempt with {} routes",
-            routes.len()
-        );
+impl CorrelationContext {
+    /// Adds a key-value pair, keeping any previously added pairs.
+    pub fn with(mut self, key: impl Into<Arc<str>>, value: impl Into<Arc<str>>) -> Self {
+        self.0.push((key.into(), value.into()));
+        self
+    }
 
-        let (network_change_tx, network_change_rx) = tokio::sync::watch::channel(());
-        let _network_change_subscription = network_change_event.subscribe(Box::new(move || {
-            network_change_tx.send_replace(());
-        }));
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
 
-        let route_provider = routes.into_iter().map(ResolveWithSavedDescription);
-        let connector = InterfaceMonitor::new(
-            DescribedRouteConnector(ComposedConnector::new(
-                LoggingConnector::new(ws_connector, Duration::from_secs(3), "websocket"),
-                &transport_connector,
-            )),
-            network_change_rx,
-            network_interface_poll_interval,
-            post_route_change_connect_timeout,
-        );
-        let delay_policy = DelayBasedOnTransport(attempts_record);
+    /// The key-value pairs, in the order they were added.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0.iter().map(|(k, v)| (&**k, &**v))
+    }
+}
 
-        let start = Instant::now();
-        let connect = crate::infra::route::connect(
-            &route_resolver,
-            delay_policy,
-            route_provider,
-            dns_resolver,
-            connector,
-            (),
-            log_tag.clone(),
-            |error| {
-                let error = error.into_inner_or_else(|| {
-                    WebSocketConnectError::Transport(TransportConnectError::ClientAbort)
-                });
-                let error = WebSocketServiceConnectError::from_websocket_error(
-                    error,
-                    confirmation_header_name.as_ref(),
-                    Instant::now(),
-                );
-                log::debug!("[{log_tag}] connection attempt failed with {error}");
-                match error.classify() {
-                    ErrorClass::Intermittent => ControlFlow::Continue(()),
-                    ErrorClass::Fatal | ErrorClass::RetryAt(_) => ControlFlow::Break(error),
-                }
-            },
-        );
+impl LogSafeDisplay for CorrelationContext {}
+impl std::fmt::Display for CorrelationContext {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, (key, value)) in self.iter().enumerate() {
+            if i != 0 {
+                f.write_str(" ")?;
+            }
+            write!(f, "{key}={value}")?;
+        }
+        Ok(())
+    }
+}
 
-        let (result, updates) = tokio::time::timeout(connect_timeout, connect)
-            .await
-            .map_err(|_: tokio::time::error::Elapsed| TimeoutOr::Timeout {
-                attempt_duration: connect_timeout,
-            })?;
+/// A token bucket limiting how often websocket connects can start.
+///
+/// Cloning a `GlobalConnectThrottle` produces a handle to the same bucket, so
+/// it can be given to multiple [`ConnectState`]s with
+/// [`ConnectState::set_global_throttle`]. Each connect takes one token before
+/// starting; tokens are replenished one at a time, up to the bucket's
+/// capacity.
+#[derive(Clone, Debug)]
+pub struct GlobalConnectThrottle {
+    bucket: Arc<std::sync::Mutex<TokenBucket>>,
+    refill_interval: Duration,
+    when_empty: WhenThrottled,
+}
 
-        match &result {
-            Ok((_connection, route)) => log::info!(
-                "[{log_tag}] connection through {route} succeeded after {:.3?}",
-                updates.finished_at - start
-            ),
-            Err(e) => log::info!("[{log_tag}] connection failed with {e}"),
+/// What a connect should do when a [`GlobalConnectThrottle`] has no tokens.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum WhenThrottled {
+    /// Wait for the next token to become available.
+    Wait,
+    /// Fail immediately with [`ConnectError::Throttled`], without trying any
+    /// routes.
+    FailFast,
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    capacity: u32,
+    tokens: u32,
+    last_refill: Instant,
+}
+
+#[derive(Debug)]
+struct GlobalConnectThrottled;
+
+impl GlobalConnectThrottle {
+    /// Creates a full bucket of `capacity` tokens that gains a token every
+    /// `refill_interval`.
+    pub fn new(capacity: NonZeroU32, refill_interval: Duration, when_empty: WhenThrottled) -> Self {
+        Self {
+            bucket: Arc::new(std::sync::Mutex::new(TokenBucket {
+                capacity: capacity.get(),
+                tokens: capacity.get(),
+                last_refill: Instant::now(),
+            })),
+            refill_interval,
+            when_empty,
         }
+    }
 
-        connect_state
-            .lock()
-            .expect("not poisoned")
-            .attempts_record
-            .apply_outcome_updates(
-                updates
-                    .outcomes
-                    .into_iter()
-                    .map(|(route, outcome)| (route.into_transport_part(), outcome)),
-                updates.finished_at,
-            );
+    async fn acquire(&self) -> Result<(), GlobalConnectThrottled> {
+        let Self {
+            bucket,
+            refill_interval,
+            when_empty,
+        } = self;
+        loop {
+            let next_token_at = {
+                let mut bucket = bucket.lock().expect("not poisoned");
+                match bucket.try_take(*refill_interval, Instant::now()) {
+                    Ok(()) => return Ok(()),
+                    Err(next_token_at) => next_token_at,
+                }
+            };
+            match when_empty {
+                WhenThrottled::Wait => tokio::time::sleep_until(next_token_at).await,
+                WhenThrottled::FailFast => return Err(GlobalConnectThrottled),
+            }
+        }
+    }
+}
 
-        let (connection, description) = result?;
-        Ok((
-            connection,
-            RouteInfo {
-                unresolved: description,
-            },
-        ))
+/// Keeps concurrent websocket connects over the same routes from racing each
+/// other.
+///
+/// Connects made through
+/// [`ConnectionResources::connect_ws_single_flight`] with the same routes
+/// while one is already in progress wait for that one to finish instead of
+/// starting their own. A connection can't be shared, so once the attempt
+/// succeeds, each waiting caller makes its own connect, which benefits from
+/// the outcomes the first attempt recorded. If the attempt fails, the waiting
+/// callers get the same error without trying again. If the attempt everyone is
+/// waiting on is cancelled, one of the waiting callers starts a new one.
+///
+/// This is separate from [`ConnectState`] because it's specific to the route
+/// type.
+pub struct ConnectSingleFlight<R> {
+    in_flight: std::sync::Mutex<HashMap<Vec<R>, InFlightConnect>>,
+}
+
+/// The error from a connect shared through a [`ConnectSingleFlight`].
+pub type SharedConnectError = Arc<TimeoutOr<ConnectError<WebSocketServiceConnectError>>>;
+
+type InFlightConnect = tokio::sync::watch::Receiver<Option<Result<(), SharedConnectError>>>;
+
+impl<R> Default for ConnectSingleFlight<R> {
+    fn default() -> Self {
+        Self {
+            in_flight: Default::default(),
+        }
     }
+}
 
-    pub(crate) async fn connect_attested_ws<E, WC>(
-        self,
-        routes: impl RouteProvider<Route = UnresolvedWebsocketServiceRoute>,
-        auth: Auth,
-        (ws_config, ws_connector): (libsignal_net_infra::ws2::Config, WC),
-        log_tag: Arc<str>,
-        params: &EndpointParams<'_, E>,
-    ) -> Result<(AttestedConnection, RouteInfo), crate::enclave::Error>
+impl<R: Clone + Eq + std::hash::Hash> ConnectSingleFlight<R> {
+    /// Runs `connect`, first waiting for any connect over `routes` that is
+    /// already in progress.
+    ///
+    /// If the connect being waited on fails, its error is returned without
+    /// running `connect`.
+    async fn run<C, Fut>(
+        &self,
+        routes: &[R],
+        connect: impl FnOnce() -> Fut,
+    ) -> Result<(C, RouteInfo), SharedConnectError>
     where
-        TC: WebSocketTransportConnectorFactory,
-        WC: Connector<
-                (WebSocketRouteFragment, HttpRouteFragment),
-                TC::Connection,
-                Connection: WebSocketStreamLike + Send + 'static,
-                Error = tungstenite::Error,
-            > + Send
-            + Sync,
-        E: NewHandshake,
+        Fut: Future<
+            Output = Result<(C, RouteInfo), TimeoutOr<ConnectError<WebSocketServiceConnectError>>>,
+        >,
     {
-        let ws_routes = routes.map_routes(|mut route| {
-            route.fragment.headers.extend([auth.as_header()]);
-            route
-        });
-
-        let (ws, route_info) = self
-            .connect_ws(ws_routes, ws_connector, log_tag.clone())
-            .await
-            .map_err(|e| match e {
-                TimeoutOr::Other(
-                    ConnectError::NoResolvedRoutes | ConnectError::AllAttemptsFailed,
-                )
-                | TimeoutOr::Timeout {
-                    attempt_duration: _,
-                } => crate::enclave::Error::ConnectionTimedOut,
-                TimeoutOr::Other(ConnectError::FatalConnect(e)) => {
-                    crate::enclave::Error::WebSocketConnect(e)
+        let tx = loop {
+            let mut existing = {
+                let mut in_flight = self.in_flight.lock().expect("not poisoned");
+                match in_flight.get(routes) {
+                    Some(existing) => existing.clone(),
+                    None => {
+                        let (tx, rx) = tokio::sync::watch::channel(None);
+                        in_flight.insert(routes.to_vec(), rx);
+                        break Some(tx);
+                    }
                 }
-            })?;
+            };
 
-        let connection =
-            AttestedConnection::connect(ws, ws_config, log_tag, move |attestation_message| {
-                E::new_handshake(params, attestation_message)
-            })
-            .await?;
-        Ok((connection, route_info))
+            log::debug!("waiting for in-progress connect");
+            match existing.wait_for(Option::is_some).await {
+                Ok(result) => match &*result {
+                    Some(Ok(())) => break None,
+                    Some(Err(error)) => return Err(error.clone()),
+                    None => unreachable!("waited for a result"),
+                },
+                // The attempt was cancelled before finishing, and has already
+                // been removed.
+                Err(_) => continue,
+            }
+        };
+
+        let Some(tx) = tx else {
+            // The attempt that was in progress succeeded, so the outcomes it
+            // recorded will steer this one toward the route that worked.
+            return connect().await.map_err(Arc::new);
+        };
+
+        // Declared after `tx` so that the entry is gone by the time the sender
+        // is dropped and any waiting callers wake up.
+        let _remove_when_finished = scopeguard::guard((), |()| {
+            self.in_flight.lock().expect("not poisoned").remove(routes);
+        });
+        match connect().await {
+            Ok(connected) => {
+                tx.send_replace(Some(Ok(())));
+                Ok(connected)
+            }
+            Err(error) => {
+                let error = Arc::new(error);
+                tx.send_replace(Some(Err(error.clone())));
+                Err(error)
+            }
+        }
     }
 }
 
-impl<TC> ConnectionResources<'_, PreconnectingFactory<TC>>
-where
-    // Note that we're not using WebSocketTransportConnectorFactory here to make `connect_ws`
-    // easier to test; specifically, the output is not guaranteed to be an AsyncDuplexStream.
-    TC: ConnectorFactory<TransportRoute, Connector: Sync, Connection: Send>,
-{
-    pub async fn preconnect_and_save(
-        self,
-        routes: impl RouteProvider<Route = UnresolvedTransportRoute>,
-        log_tag: Arc<str>,
-    ) -> Result<(), TimeoutOr<ConnectError<TransportConnectError>>> {
+impl TokenBucket {
+    /// Takes a token if one is available, or returns when the next one will be.
+    fn try_take(&mut self, refill_interval: Duration, now: Instant) -> Result<(), Instant> {
         let Self {
-            connect_state,
-            dns_resolver,
-            network_change_event,
-            confirmation_header_name: _,
+            capacity,
+            tokens,
+            last_refill,
         } = self;
 
-        let ConnectStateSnapshot {
-            route_resolver,
-            connect_timeout,
-            network_interface_poll_interval,
-            post_route_change_connect_timeout,
-            transport_connector,
-            attempts_record,
-            route_provider_context,
-        } = connect_state
-            .lock()
-            .expect("not poisoned")
-            .snapshot::<UsePreconnect<_>>();
+        if *tokens == *capacity || refill_interval.is_zero() {
+            // Nothing to refill, so start the clock over.
+            *tokens = *capacity;
+            *last_refill = now;
+        } else {
+            let elapsed = now.saturating_duration_since(*last_refill);
+            let refilled = elapsed.as_nanos() / refill_interval.as_nanos();
+            let refilled = u32::try_from(refilled).unwrap_or(u32::MAX);
+            if refilled >= *capacity - *tokens {
+                *tokens = *capacity;
+                *last_refill = now;
+            } else {
+                *tokens += refilled;
+                *last_refill += refill_interval * refilled;
+            }
+        }
 
-        let routes = routes
-            .map_routes(|r| UsePreconnect {
-                should: true,
-                inner: r,
-            })
-            .routes(&route_provider_context)
-            .collect_vec();
+        if *tokens == 0 {
+            return Err(*last_refill + refill_interval);
+        }
+        *tokens -= 1;
+        Ok(())
+    }
+}
 
-        log::info!(
-            "[{log_tag}] starting connection attempt with {} routes",
-            routes.len()
-        );
+/// A log-safe summary of a single websocket connect attempt.
+///
+/// Meant to be copied into bug reports as-is, so its [`Display`](std::fmt::Display)
+/// representation fits on one line.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConnectDiagnostics {
+    /// How many routes were considered, after any limits were applied.
+    pub route_count: usize,
+    pub outcome: ConnectDiagnosticsOutcome,
+    /// How long the attempt took, from the first route to the final outcome.
+    pub elapsed: Duration,
+    /// Where the successful DNS lookups got their results, in the order they
+    /// finished.
+    pub dns_sources: Vec<DnsSource>,
+}
 
-        struct ConnectWithSavedRoute<C>(C);
+#[derive(Clone, Debug, PartialEq)]
+pub enum ConnectDiagnosticsOutcome {
+    Connected(RouteInfo),
+    /// The attempt failed; holds a log-safe description of the error.
+    Failed(String),
+    TimedOut,
+}
 
-        impl<R, Inner, C> Connector<R, Inner> for ConnectWithSavedRoute<C>
-        where
-            C: Connector<R, Inner>,
-            R: Clone + Send,
-        {
-            type Connection = (R, C::Connection);
+impl LogSafeDisplay for ConnectDiagnostics {}
+impl std::fmt::Display for ConnectDiagnostics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let Self {
+            route_count,
+            outcome,
+            elapsed,
+            dns_sources,
+        } = self;
+        write!(f, "routes={route_count} ")?;
+        match outcome {
+            ConnectDiagnosticsOutcome::Connected(route_info) => {
+                write!(f, "outcome=connected route=\"{route_info}\"")?
+            }
+            ConnectDiagnosticsOutcome::Failed(error) => {
+                write!(f, "outcome=failed error=\"{error}\"")?
+            }
+            ConnectDiagnosticsOutcome::TimedOut => f.write_str("outcome=timed-out")?,
+        }
+        write!(f, " elapsed={elapsed:.3?} dns=")?;
+        if dns_sources.is_empty() {
+            f.write_str("none")
+        } else {
+            write!(f, "{}", dns_sources.iter().join(","))
+        }
+    }
+}
 
-            type Error = C::Error;
+/// A [`Resolver`] that notes the [`DnsSource`] and duration of every
+/// successful lookup.
+struct RecordDnsSources<'a, R> {
+    inner: &'a R,
+    sources: &'a std::sync::Mutex<Vec<DnsSource>>,
+    durations: &'a std::sync::Mutex<HashMap<Arc<str>, Duration>>,
+}
 
-            fn connect_over(
-                &self,
-                over: Inner,
-                route: R,
-                log_tag: Arc<str>,
-            ) -> impl Future<Output = Result<Self::Connection, Self::Error>> + Send {
-                self.0
-                    .connect_over(over, route.clone(), log_tag)
-                    .map_ok(|connection| (route, connection))
-            }
+impl<R: Resolver + Sync> Resolver for RecordDnsSources<'_, R> {
+    async fn lookup_ip(&self, hostname: &str) -> Result<LookupResult, DnsError> {
+        let start = Instant::now();
+        let result = spans::instrument(
+            self.inner.lookup_ip(hostname),
+            spans::phase(spans::Phase::Resolve, hostname),
+        )
+        .await;
+        if let Ok(lookup) = &result {
+            self.sources
+                .lock()
+                .expect("not poisoned")
+                .push(lookup.source());
+            self.durations
+                .lock()
+                .expect("not poisoned")
+                .insert(hostname.into(), start.elapsed());
         }
+        result
+    }
+}
 
-        let (network_change_tx, network_change_rx) = tokio::sync::watch::channel(());
-        let _network_change_subscription = network_change_event.subscribe(Box::new(move || {
-            network_change_tx.send_replace(());
-        }));
+/// A snapshot of [`ConnectState`] for a particular connection attempt.
+///
+/// "Like `ConnectState`, but with a single instantiated connector."
+struct ConnectStateSnapshot<C> {
+    route_resolver: RouteResolver,
+    connect_timeout: Duration,
+    network_interface_poll_interval: Duration,
+    post_route_change_connect_timeout: Duration,
+    max_fronting_domains: Option<NonZeroUsize>,
+    preconnect_timeout: Option<Duration>,
+    transport_connector: C,
+    attempts_record: ConnectionOutcomes<TransportRoute>,
+    route_provider_context: RouteProviderContextImpl,
+    connect_observer: Option<Arc<dyn ConnectObserver<RouteInfo> + Send + Sync>>,
+    connect_progress: Option<Arc<dyn Fn(ConnectStage) + Send + Sync>>,
+    local_ip_source: LocalIpSource,
+    global_throttle: Option<GlobalConnectThrottle>,
+    report_resolved_target: bool,
+    report_connect_timing: bool,
+    phase_timeouts: PhaseTimeouts,
+    preferred_route_hint: Option<RouteType>,
+    route_delay_override: Option<RouteDelayOverride>,
+    /// Set if routes should be ordered by bandwidth.
+    route_bandwidth: Option<RouteBandwidth>,
+    connect_attempt_events: Option<tokio::sync::mpsc::Sender<ConnectAttemptEvent>>,
+}
 
-        let route_provider = routes.into_iter();
-        let connector = InterfaceMonitor::new(
-            ConnectWithSavedRoute(&transport_connector),
-            network_change_rx,
+impl<TC> ConnectState<TC> {
+    fn snapshot<Transport>(&self) -> ConnectStateSnapshot<TC::Connector>
+    where
+        TC: ConnectorFactory<Transport>,
+    {
+        let Self {
+            route_resolver,
+            connect_timeout,
             network_interface_poll_interval,
             post_route_change_connect_timeout,
+            max_fronting_domains,
+            preconnect_timeout,
+            make_transport_connector,
+            attempts_record,
+            route_provider_context,
+            connect_observer,
+            connect_progress,
+            local_ip_source,
+            global_throttle,
+            report_resolved_target,
+            report_connect_timing,
+            phase_timeouts,
+            preferred_route_hint,
+            unstable_network: _,
+            interface_change_aborts: _,
+            connectivity_stats: _,
+            attestation_permits: _,
+            route_delay_override,
+            route_ordering,
+            route_bandwidth,
+            connect_attempt_events,
+        } = self;
+
+        ConnectStateSnapshot {
+            route_resolver: route_resolver.clone(),
+            connect_timeout: *connect_timeout,
+            network_interface_poll_interval: *network_interface_poll_interval,
+            post_route_change_connect_timeout: *post_route_change_connect_timeout,
+            max_fronting_domains: *max_fronting_domains,
+            preconnect_timeout: *preconnect_timeout,
+            transport_connector: make_transport_connector.make(),
+            attempts_record: attempts_record.clone(),
+            route_provider_context: route_provider_context.clone(),
+            connect_observer: connect_observer.clone(),
+            connect_progress: connect_progress.clone(),
+            local_ip_source: local_ip_source.clone(),
+            global_throttle: global_throttle.clone(),
+            report_resolved_target: *report_resolved_target,
+            report_connect_timing: *report_connect_timing,
+            phase_timeouts: *phase_timeouts,
+            preferred_route_hint: *preferred_route_hint,
+            route_delay_override: route_delay_override.clone(),
+            route_bandwidth: match route_ordering {
+                RouteOrdering::AsProvided => None,
+                RouteOrdering::HighestBandwidthFirst => Some(route_bandwidth.clone()),
+            },
+            connect_attempt_events: connect_attempt_events.clone(),
+        }
+    }
+}
+
+impl<TC> ConnectionResources<'_, TC> {
+    /// Connects a websocket over the first of `routes` that works.
+    ///
+    /// If `cancel` is provided and gets cancelled, the connect stops early and
+    /// fails with [`ConnectError::Cancelled`]. Unlike dropping the future, the
+    /// outcomes of attempts that already finished are still recorded. Attempts
+    /// that were still in progress (say, partway through a TLS handshake) are
+    /// abandoned without counting against their routes. If `cancel` is already
+    /// cancelled, no routes are tried.
+    pub async fn connect_ws<WC, UR, Transport>(
+        self,
+        routes: impl RouteProvider<Route = UR>,
+        ws_connector: WC,
+        log_tag: Arc<str>,
+        cancel: Option<&CancellationToken>,
+    ) -> Result<(WC::Connection, RouteInfo), TimeoutOr<ConnectError<WebSocketServiceConnectError>>>
+    where
+        UR: ResolveHostnames<Resolved = WebSocketServiceRoute<Transport>>
+            + DescribeForLog<Description = UnresolvedRouteDescription>
+            + Clone
+            + 'static,
+        Transport: Clone + Send + UsesTransport + ResolvedRoute,
+        TC: ConnectorFactory<
+            Transport,
+            Connection: Send,
+            Connector: Sync + Connector<Transport, (), Error: Into<WebSocketConnectError>>,
+        >,
+        WC: Connector<
+                (WebSocketRouteFragment, HttpRouteFragment),
+                TC::Connection,
+                Connection: Send,
+                Error = tungstenite::Error,
+            > + Send
+            + Sync,
+    {
+        let (result, _diagnostics) = self
+            .connect_ws_inner(
+                routes,
+                ws_connector,
+                log_tag,
+                CorrelationContext::default(),
+                None,
+                cancel,
+            )
+            .await;
+        result
+    }
+
+    /// Like [`Self::connect_ws`], but tags the attempt with `correlation`.
+    ///
+    /// The context is appended to `log_tag` for all logging done while
+    /// connecting, and is available from the [`RouteInfo`]s passed to the
+    /// [`ConnectObserver`] and returned on success.
+    pub async fn connect_ws_with_correlation<WC, UR, Transport>(
+        self,
+        routes: impl RouteProvider<Route = UR>,
+        ws_connector: WC,
+        log_tag: Arc<str>,
+        correlation: CorrelationContext,
+    ) -> Result<(WC::Connection, RouteInfo), TimeoutOr<ConnectError<WebSocketServiceConnectError>>>
+    where
+        UR: ResolveHostnames<Resolved = WebSocketServiceRoute<Transport>>
+            + DescribeForLog<Description = UnresolvedRouteDescription>
+            + Clone
+            + 'static,
+        Transport: Clone + Send + UsesTransport + ResolvedRoute,
+        // Note that we're not using WebSocketTransportConnectorFactory here to make `connect_ws`
+        // easier to test; specifically, the output is not guaranteed to be an AsyncDuplexStream.
+        TC: ConnectorFactory<
+            Transport,
+            Connection: Send,
+            Connector: Sync + Connector<Transport, (), Error: Into<WebSocketConnectError>>,
+        >,
+        WC: Connector<
+                (WebSocketRouteFragment, HttpRouteFragment),
+                TC::Connection,
+                Connection: Send,
+                Error = tungstenite::Error,
+            > + Send
+            + Sync,
+    {
+        let (result, _diagnostics) = self
+            .connect_ws_with_diagnostics(routes, ws_connector, log_tag, correlation)
+            .await;
+        result
+    }
+
+    /// Like [`Self::connect_ws`], but gives up at `deadline` instead of after
+    /// the configured connect timeout.
+    ///
+    /// This lets several operations share one overall deadline. If `deadline`
+    /// has already passed, this returns [`TimeoutOr::Timeout`] without
+    /// resolving or connecting to any routes. On timeout, the reported
+    /// `attempt_duration` is how long the attempt actually ran.
+    pub async fn connect_ws_until<WC, UR, Transport>(
+        self,
+        routes: impl RouteProvider<Route = UR>,
+        ws_connector: WC,
+        log_tag: Arc<str>,
+        deadline: Instant,
+    ) -> Result<(WC::Connection, RouteInfo), TimeoutOr<ConnectError<WebSocketServiceConnectError>>>
+    where
+        UR: ResolveHostnames<Resolved = WebSocketServiceRoute<Transport>>
+            + DescribeForLog<Description = UnresolvedRouteDescription>
+            + Clone
+            + 'static,
+        Transport: Clone + Send + UsesTransport + ResolvedRoute,
+        // Note that we're not using WebSocketTransportConnectorFactory here to make `connect_ws`
+        // easier to test; specifically, the output is not guaranteed to be an AsyncDuplexStream.
+        TC: ConnectorFactory<
+            Transport,
+            Connection: Send,
+            Connector: Sync + Connector<Transport, (), Error: Into<WebSocketConnectError>>,
+        >,
+        WC: Connector<
+                (WebSocketRouteFragment, HttpRouteFragment),
+                TC::Connection,
+                Connection: Send,
+                Error = tungstenite::Error,
+            > + Send
+            + Sync,
+    {
+        let (result, _diagnostics) = self
+            .connect_ws_inner(
+                routes,
+                ws_connector,
+                log_tag,
+                CorrelationContext::default(),
+                Some(deadline),
+                None,
+            )
+            .await;
+        result
+    }
+
+    /// Like [`Self::connect_ws`], but waits for any other connect over the
+    /// same `routes` that is in progress on `single_flight`.
+    ///
+    /// The first caller for a set of routes connects as usual. Callers that
+    /// arrive while it's in progress connect once it has succeeded, using
+    /// what it learned about the routes, or get the same error if it failed.
+    /// Since routes carry their request headers, connects with different
+    /// credentials never wait on each other.
+    pub async fn connect_ws_single_flight<WC, UR, Transport>(
+        self,
+        single_flight: &ConnectSingleFlight<UR>,
+        routes: Vec<UR>,
+        ws_connector: WC,
+        log_tag: Arc<str>,
+    ) -> Result<(WC::Connection, RouteInfo), SharedConnectError>
+    where
+        UR: ResolveHostnames<Resolved = WebSocketServiceRoute<Transport>>
+            + DescribeForLog<Description = UnresolvedRouteDescription>
+            + Clone
+            + Eq
+            + std::hash::Hash
+            + 'static,
+        Transport: Clone + Send + UsesTransport + ResolvedRoute,
+        // Note that we're not using WebSocketTransportConnectorFactory here to make `connect_ws`
+        // easier to test; specifically, the output is not guaranteed to be an AsyncDuplexStream.
+        TC: ConnectorFactory<
+            Transport,
+            Connection: Send,
+            Connector: Sync + Connector<Transport, (), Error: Into<WebSocketConnectError>>,
+        >,
+        WC: Connector<
+                (WebSocketRouteFragment, HttpRouteFragment),
+                TC::Connection,
+                Connection: Send,
+                Error = tungstenite::Error,
+            > + Send
+            + Sync,
+    {
+        let key = routes.clone();
+        single_flight
+            .run(&key, || {
+                self.connect_ws(ListedRoutes(routes), ws_connector, log_tag, None)
+            })
+            .await
+    }
+
+    /// Like [`Self::connect_ws_with_correlation`], but also returns a summary
+    /// of the attempt for diagnostic purposes, whether or not it succeeded.
+    pub async fn connect_ws_with_diagnostics<WC, UR, Transport>(
+        self,
+        routes: impl RouteProvider<Route = UR>,
+        ws_connector: WC,
+        log_tag: Arc<str>,
+        correlation: CorrelationContext,
+    ) -> (
+        Result<(WC::Connection, RouteInfo), TimeoutOr<ConnectError<WebSocketServiceConnectError>>>,
+        ConnectDiagnostics,
+    )
+    where
+        UR: ResolveHostnames<Resolved = WebSocketServiceRoute<Transport>>
+            + DescribeForLog<Description = UnresolvedRouteDescription>
+            + Clone
+            + 'static,
+        Transport: Clone + Send + UsesTransport + ResolvedRoute,
+        // Note that we're not using WebSocketTransportConnectorFactory here to make `connect_ws`
+        // easier to test; specifically, the output is not guaranteed to be an AsyncDuplexStream.
+        TC: ConnectorFactory<
+            Transport,
+            Connection: Send,
+            Connector: Sync + Connector<Transport, (), Error: Into<WebSocketConnectError>>,
+        >,
+        WC: Connector<
+                (WebSocketRouteFragment, HttpRouteFragment),
+                TC::Connection,
+                Connection: Send,
+                Error = tungstenite::Error,
+            > + Send
+            + Sync,
+    {
+        self.connect_ws_inner(routes, ws_connector, log_tag, correlation, None, None)
+            .await
+    }
+
+    /// Shared implementation for the `connect_ws` family.
+    ///
+    /// If `deadline` is `None`, the configured connect timeout is used. If
+    /// `cancel` is provided, cancelling it ends the connect early.
+    async fn connect_ws_inner<WC, UR, Transport>(
+        self,
+        routes: impl RouteProvider<Route = UR>,
+        ws_connector: WC,
+        log_tag: Arc<str>,
+        correlation: CorrelationContext,
+        deadline: Option<Instant>,
+        cancel: Option<&CancellationToken>,
+    ) -> (
+        Result<(WC::Connection, RouteInfo), TimeoutOr<ConnectError<WebSocketServiceConnectError>>>,
+        ConnectDiagnostics,
+    )
+    where
+        UR: ResolveHostnames<Resolved = WebSocketServiceRoute<Transport>>
+            + DescribeForLog<Description = UnresolvedRouteDescription>
+            + Clone
+            + 'static,
+        Transport: Clone + Send + UsesTransport + ResolvedRoute,
+        // Note that we're not using WebSocketTransportConnectorFactory here to make `connect_ws`
+        // easier to test; specifically, the output is not guaranteed to be an AsyncDuplexStream.
+        TC: ConnectorFactory<
+            Transport,
+            Connection: Send,
+            Connector: Sync + Connector<Transport, (), Error: Into<WebSocketConnectError>>,
+        >,
+        WC: Connector<
+                (WebSocketRouteFragment, HttpRouteFragment),
+                TC::Connection,
+                Connection: Send,
+                Error = tungstenite::Error,
+            > + Send
+            + Sync,
+    {
+        let Self {
+            connect_state,
+            dns_resolver,
+            network_change_event,
+            confirmation_header_name,
+        } = self;
+
+        let ConnectStateSnapshot {
+            route_resolver,
+            connect_timeout,
+            network_interface_poll_interval,
+            post_route_change_connect_timeout,
+            max_fronting_domains,
+            preconnect_timeout: _,
+            transport_connector,
+            attempts_record,
+            route_provider_context,
+            connect_observer,
+            connect_progress,
+            local_ip_source,
+            global_throttle,
+            report_resolved_target,
+            report_connect_timing,
+            phase_timeouts,
+            preferred_route_hint,
+            route_delay_override,
+            route_bandwidth,
+            connect_attempt_events,
+        } = lock_connect_state(connect_state).snapshot();
+
+        let log_tag: Arc<str> = if correlation.is_empty() {
+            log_tag
+        } else {
+            format!("{log_tag} {correlation}").into()
+        };
+
+        let span = spans::connect_ws(&log_tag);
+
+        let progress = ConnectProgress::new(connect_progress);
+        progress.advance(ConnectStage::Resolving);
+
+        let mut routes = routes.routes(&route_provider_context).collect_vec();
+        if let Some(max_fronting_domains) = max_fronting_domains {
+            routes = limit_fronting_domains(
+                routes,
+                max_fronting_domains,
+                &attempts_record,
+                Instant::now(),
+            );
+        }
+        if let Some(route_bandwidth) = route_bandwidth {
+            routes = prefer_high_bandwidth(routes, &route_bandwidth);
+        }
+        if let Some(hint) = preferred_route_hint {
+            routes = prefer_route_type(routes, hint, &log_tag);
+        }
+
+        let route_count = routes.len();
+
+        if deadline.is_some_and(|deadline| deadline <= Instant::now()) {
+            log::warn!("[{log_tag}] not connecting; the deadline has already passed");
+            let diagnostics = ConnectDiagnostics {
+                route_count,
+                outcome: ConnectDiagnosticsOutcome::TimedOut,
+                elapsed: Duration::ZERO,
+                dns_sources: vec![],
+            };
+            return (
+                Err(TimeoutOr::Timeout {
+                    attempt_duration: Duration::ZERO,
+                }),
+                diagnostics,
+            );
+        }
+
+        if cancel.is_some_and(CancellationToken::is_cancelled) {
+            log::info!("[{log_tag}] not connecting; already cancelled");
+            let error = ConnectError::Cancelled;
+            let diagnostics = ConnectDiagnostics {
+                route_count,
+                outcome: ConnectDiagnosticsOutcome::Failed(error.to_string()),
+                elapsed: Duration::ZERO,
+                dns_sources: vec![],
+            };
+            return (Err(TimeoutOr::Other(error)), diagnostics);
+        }
+
+        if lock_connect_state(connect_state).is_network_unstable(Instant::now()) {
+            log::warn!("[{log_tag}] not connecting; the network has been changing too often");
+            let error = ConnectError::UnstableNetwork;
+            let diagnostics = ConnectDiagnostics {
+                route_count,
+                outcome: ConnectDiagnosticsOutcome::Failed(error.to_string()),
+                elapsed: Duration::ZERO,
+                dns_sources: vec![],
+            };
+            return (Err(TimeoutOr::Other(error)), diagnostics);
+        }
+
+        if let Some(throttle) = &global_throttle {
+            if let Err(GlobalConnectThrottled) = throttle.acquire().await {
+                log::warn!("[{log_tag}] not connecting; the global connect throttle is empty");
+                let error = ConnectError::Throttled;
+                let diagnostics = ConnectDiagnostics {
+                    route_count,
+                    outcome: ConnectDiagnosticsOutcome::Failed(error.to_string()),
+                    elapsed: Duration::ZERO,
+                    dns_sources: vec![],
+                };
+                return (Err(TimeoutOr::Other(error)), diagnostics);
+            }
+        }
+
+        log::info!("[{log_tag}] starting connection attempt with {route_count} routes");
+
+        let (network_change_tx, network_change_rx) = tokio::sync::watch::channel(());
+        let _network_change_subscription = network_change_event.subscribe(Box::new(move || {
+            network_change_tx.send_replace(());
+        }));
+
+        // Remember which hostnames each route needs, so the DNS time for the
+        // winning route can be found afterwards.
+        let route_hostnames = if report_connect_timing {
+            routes
+                .iter()
+                .map(|route| {
+                    let hostnames = route
+                        .hostnames()
+                        .map(|UnresolvedHost(hostname)| hostname.clone())
+                        .collect_vec();
+                    (route.describe_for_log(), hostnames)
+                })
+                .collect_vec()
+        } else {
+            vec![]
+        };
+
+        let route_provider = routes.into_iter().map(ResolveWithSavedDescription);
+        let connector = InterfaceMonitor::new_with_interface_source(
+            ReportAttemptConnector {
+                inner: DescribedRouteConnector(RecordPhasesConnector(ComposedConnector::new(
+                    ReportStageConnector {
+                        inner: PhaseTimingConnector::new(
+                            TimeoutConnector::new(
+                                LoggingConnector::new(
+                                    ws_connector,
+                                    Duration::from_secs(3),
+                                    "websocket",
+                                ),
+                                phase_timeouts.websocket_upgrade,
+                                "websocket upgrade",
+                                || tungstenite::Error::Io(std::io::ErrorKind::TimedOut.into()),
+                            ),
+                            ConnectPhase::WebSocketUpgrade,
+                        ),
+                        stage: ConnectStage::Upgrading,
+                        span_phase: spans::Phase::WebSocketUpgrade,
+                        progress: &progress,
+                    },
+                    ReportStageConnector {
+                        inner: &transport_connector,
+                        stage: ConnectStage::ConnectingTransport,
+                        span_phase: spans::Phase::ConnectTransport,
+                        progress: &progress,
+                    },
+                ))),
+                events: &connect_attempt_events,
+            },
+            local_ip_source,
+            network_change_rx,
+            network_interface_poll_interval,
+            post_route_change_connect_timeout,
+        );
+        // Remember the order the scheduler ranks routes in, and which it holds
+        // back, so the ones it passes over can be reported as skipped.
+        let scheduled_routes = std::sync::Mutex::new(Vec::new());
+        let delay_policy = RecordScheduledRoutes {
+            inner: DelayBasedOnTransport(OutcomesWithOverride {
+                outcomes: attempts_record,
+                route_delay_override,
+                route_provider_context,
+            }),
+            scheduled: connect_attempt_events
+                .is_some()
+                .then_some(&scheduled_routes),
+        };
+
+        let observer = DescribedRouteObserver {
+            observer: connect_observer,
+            correlation: &correlation,
+        };
+
+        let dns_sources = std::sync::Mutex::new(Vec::new());
+        let dns_durations = std::sync::Mutex::new(HashMap::new());
+        let resolver = TimeoutResolver::new(
+            RecordDnsSources {
+                inner: dns_resolver,
+                sources: &dns_sources,
+                durations: &dns_durations,
+            },
+            phase_timeouts.dns,
+        );
+
+        let mut aborted_by_network_change = false;
+        let start = Instant::now();
+        let connect = crate::infra::route::connect_until_cancelled(
+            &route_resolver,
+            delay_policy,
+            route_provider,
+            &resolver,
+            connector,
+            (),
+            log_tag.clone(),
+            |error| {
+                let (error, attempt) = match error {
+                    InterfaceChangedOr::InterfaceChanged => {
+                        aborted_by_network_change = true;
+                        (
+                            WebSocketConnectError::Transport(TransportConnectError::ClientAbort),
+                            None,
+                        )
+                    }
+                    InterfaceChangedOr::Other(FailedAttempt {
+                        error,
+                        route,
+                        started,
+                    }) => (error, Some((route, started))),
+                };
+                let timed_out = is_timeout(&error);
+                let error = WebSocketServiceConnectError::from_websocket_error(
+                    error,
+                    confirmation_header_name.as_ref(),
+                    Instant::now(),
+                );
+                log::debug!("[{log_tag}] connection attempt failed with {error}");
+                if let Some((route, started)) = attempt {
+                    let outcome = if timed_out {
+                        ConnectAttemptOutcome::TimedOut
+                    } else {
+                        ConnectAttemptOutcome::Failed(error.classify())
+                    };
+                    send_attempt_event(&connect_attempt_events, route, started, outcome);
+                }
+                match error.classify() {
+                    ErrorClass::Intermittent => ControlFlow::Continue(()),
+                    ErrorClass::Fatal | ErrorClass::RetryAt(_) => ControlFlow::Break(error),
+                }
+            },
+            &observer,
+            cancelled_or_pending(cancel),
+        );
+
+        let deadline = deadline.unwrap_or(start + connect_timeout);
+        let connect_result =
+            tokio::time::timeout_at(deadline, spans::instrument(connect, span)).await;
+        let dns_sources = dns_sources.into_inner().expect("not poisoned");
+        let dns_durations = dns_durations.into_inner().expect("not poisoned");
+        let (result, updates) = match connect_result {
+            Ok(finished) => finished,
+            Err(_elapsed) => {
+                let attempt_duration = start.elapsed();
+                let diagnostics = ConnectDiagnostics {
+                    route_count,
+                    outcome: ConnectDiagnosticsOutcome::TimedOut,
+                    elapsed: attempt_duration,
+                    dns_sources,
+                };
+                return (Err(TimeoutOr::Timeout { attempt_duration }), diagnostics);
+            }
+        };
+
+        // A cooling-down route that was never tried was only skipped if the
+        // scheduler moved on to a lower-ranked route while it was waiting;
+        // otherwise it just wasn't needed.
+        let scheduled_routes = scheduled_routes.into_inner().expect("not poisoned");
+        let attempted_before = |route: &UnresolvedRouteDescription, before: Instant| {
+            updates.outcomes.iter().any(|(attempted, outcome)| {
+                attempted.description == *route && outcome.started < before
+            })
+        };
+        let mut skipped_routes = Vec::<UnresolvedRouteDescription>::new();
+        for (i, (route, cooldown_until)) in scheduled_routes.iter().enumerate() {
+            let Some(cooldown_until) = *cooldown_until else {
+                continue;
+            };
+            let passed_over = scheduled_routes[i + 1..]
+                .iter()
+                .any(|(later, _)| attempted_before(later, cooldown_until));
+            if passed_over
+                && !attempted_before(route, updates.finished_at)
+                && !skipped_routes.contains(route)
+            {
+                skipped_routes.push(route.clone());
+            }
+        }
+        for route in skipped_routes {
+            send_attempt_event(
+                &connect_attempt_events,
+                route,
+                start,
+                ConnectAttemptOutcome::SkippedDueToCooldown,
+            );
+        }
+
+        match &result {
+            Ok((_connection, route)) => log::info!(
+                "[{log_tag}] connection through {route} succeeded after {:.3?}",
+                updates.finished_at - start
+            ),
+            Err(e) => log::info!("[{log_tag}] connection failed with {e}"),
+        }
+
+        // The successful attempt, if there was one, is always the last outcome.
+        let connected_transport = match updates.outcomes.last() {
+            Some((route, outcome)) if outcome.result.is_ok() => {
+                Some(route.transport_part().clone())
+            }
+            _ => None,
+        };
+        let resolved_target = connected_transport
+            .as_ref()
+            .filter(|_| report_resolved_target)
+            .map(ResolvedTarget::from_transport_route);
+
+        let network_now_unstable = {
+            let mut connect_state = lock_connect_state(connect_state);
+            connect_state.attempts_record.apply_outcome_updates(
+                updates
+                    .outcomes
+                    .into_iter()
+                    .map(|(route, outcome)| (route.into_transport_part(), outcome)),
+                updates.finished_at,
+            );
+            if let Ok((_connection, description)) = &result {
+                connect_state.connectivity_stats.record_success(description);
+            }
+            result.is_err()
+                && aborted_by_network_change
+                && connect_state.record_interface_change_abort(updates.finished_at)
+        };
+        let result = if network_now_unstable {
+            log::warn!("[{log_tag}] network changes keep aborting connects; backing off");
+            Err(ConnectError::UnstableNetwork)
+        } else {
+            result
+        };
+
+        if result.is_ok() {
+            progress.advance(ConnectStage::Connected);
+        }
+        let result = result.map(|((connection, phases), description)| {
+            let connect_timing = report_connect_timing.then(|| {
+                let dns = route_hostnames
+                    .iter()
+                    .find(|(route, _)| *route == description)
+                    .into_iter()
+                    .flat_map(|(_, hostnames)| hostnames)
+                    .filter_map(|hostname| dns_durations.get(hostname).copied())
+                    .max()
+                    .unwrap_or_default();
+                ConnectTiming::new(dns, phases, updates.finished_at - start)
+            });
+            let route_info = RouteInfo {
+                unresolved: description,
+                correlation,
+                resolved_target,
+                connect_timing,
+                confirmation_header: None,
+                connected_transport,
+            };
+            (connection, route_info)
+        });
+        let diagnostics = ConnectDiagnostics {
+            route_count,
+            outcome: match &result {
+                Ok((_connection, route_info)) => {
+                    ConnectDiagnosticsOutcome::Connected(route_info.clone())
+                }
+                Err(e) => ConnectDiagnosticsOutcome::Failed((e as &dyn LogSafeDisplay).to_string()),
+            },
+            elapsed: updates.finished_at - start,
+            dns_sources,
+        };
+        (result.map_err(TimeoutOr::Other), diagnostics)
+    }
+
+    pub(crate) async fn connect_attested_ws<E, WC>(
+        self,
+        routes: impl RouteProvider<Route = UnresolvedWebsocketServiceRoute>,
+        auth: Auth,
+        (ws_config, ws_connector): (libsignal_net_infra::ws2::Config, WC),
+        log_tag: Arc<str>,
+        params: &EndpointParams<'_, E>,
+        handshake_rng: Option<attest::enclave::HandshakeRng>,
+    ) -> Result<(AttestedConnection, RouteInfo), crate::enclave::Error>
+    where
+        TC: WebSocketTransportConnectorFactory,
+        WC: Connector<
+                (WebSocketRouteFragment, HttpRouteFragment),
+                TC::Connection,
+                Connection: WebSocketStreamLike + Send + 'static,
+                Error = tungstenite::Error,
+            > + Send
+            + Sync,
+        E: NewHandshake,
+    {
+        let span = spans::connect_attested_ws(&log_tag);
+        spans::instrument(
+            async move {
+                let (ws, route_info, _attestation_permit) = self
+                    .connect_enclave_ws(routes, auth, ws_connector, log_tag.clone())
+                    .await?;
+
+                let handshake_span = spans::phase(spans::Phase::AttestationHandshake, &log_tag);
+                let connection = spans::instrument(
+                    AttestedConnection::connect(
+                        ws,
+                        ws_config,
+                        log_tag,
+                        move |attestation_message| {
+                            E::new_handshake(params, attestation_message, handshake_rng)
+                        },
+                    ),
+                    handshake_span,
+                )
+                .await
+                .map_err(crate::enclave::Error::from_handshake)?;
+                Ok::<_, crate::enclave::Error>((connection, route_info))
+            },
+            span,
+        )
+        .await
+    }
+
+    /// Connects to an enclave far enough to validate its attestation, like
+    /// the first step of [`Self::connect_attested_ws`], and then disconnects.
+    pub(crate) async fn check_attested_ws<E, WC>(
+        self,
+        routes: impl RouteProvider<Route = UnresolvedWebsocketServiceRoute>,
+        auth: Auth,
+        (ws_config, ws_connector): (libsignal_net_infra::ws2::Config, WC),
+        log_tag: Arc<str>,
+        params: &EndpointParams<'_, E>,
+    ) -> Result<(AttestationCheck, RouteInfo), crate::enclave::Error>
+    where
+        TC: WebSocketTransportConnectorFactory,
+        WC: Connector<
+                (WebSocketRouteFragment, HttpRouteFragment),
+                TC::Connection,
+                Connection: WebSocketStreamLike + Send + 'static,
+                Error = tungstenite::Error,
+            > + Send
+            + Sync,
+        E: NewHandshake,
+    {
+        let (ws, route_info, _attestation_permit) = self
+            .connect_enclave_ws(routes, auth, ws_connector, log_tag.clone())
+            .await?;
+
+        let check =
+            AttestedConnection::check_attestation(ws, ws_config, log_tag, |attestation_message| {
+                // The handshake is never sent, so there's no point in
+                // controlling its randomness.
+                E::new_handshake(params, attestation_message, None)
+            })
+            .await?;
+        Ok((check, route_info))
+    }
+
+    /// Makes the websocket connection for an attested connection.
+    ///
+    /// If concurrent attestations are limited, a permit is acquired before
+    /// connecting, so that no websocket is left waiting on one, and returned
+    /// to be held while checking the enclave's attestation. Waiting for the
+    /// permit is limited by the connect timeout.
+    async fn connect_enclave_ws<WC>(
+        self,
+        routes: impl RouteProvider<Route = UnresolvedWebsocketServiceRoute>,
+        auth: Auth,
+        ws_connector: WC,
+        log_tag: Arc<str>,
+    ) -> Result<
+        (
+            WC::Connection,
+            RouteInfo,
+            Option<tokio::sync::OwnedSemaphorePermit>,
+        ),
+        crate::enclave::Error,
+    >
+    where
+        TC: WebSocketTransportConnectorFactory,
+        WC: Connector<
+                (WebSocketRouteFragment, HttpRouteFragment),
+                TC::Connection,
+                Connection: WebSocketStreamLike + Send + 'static,
+                Error = tungstenite::Error,
+            > + Send
+            + Sync,
+    {
+        let ws_routes = routes.map_routes(|mut route| {
+            add_auth_header(&mut route, &auth);
+            route
+        });
+        let (attestation_permits, connect_timeout) = {
+            let state = lock_connect_state(self.connect_state);
+            (state.attestation_permits.clone(), state.connect_timeout)
+        };
+        // Held by the caller until the attestation has been checked,
+        // successfully or not.
+        let attestation_permit = match attestation_permits {
+            Some(permits) => {
+                let permit = tokio::time::timeout(connect_timeout, permits.acquire_owned())
+                    .await
+                    .map_err(|_elapsed| {
+                        log::info!("[{log_tag}] timed out waiting to start an attestation");
+                        crate::enclave::Error::ConnectionTimedOut
+                    })?;
+                Some(permit.expect("semaphore is never closed"))
+            }
+            None => None,
+        };
+
+        let (ws, route_info) = self
+            .connect_ws(ws_routes, ws_connector, log_tag, None)
+            .await
+            .map_err(|e| match e {
+                TimeoutOr::Other(
+                    ConnectError::NoResolvedRoutes
+                    | ConnectError::AllAttemptsFailed
+                    | ConnectError::UnstableNetwork
+                    | ConnectError::Cancelled
+                    | ConnectError::Throttled,
+                ) => crate::enclave::Error::Unreachable,
+                TimeoutOr::Timeout {
+                    attempt_duration: _,
+                } => crate::enclave::Error::ConnectionTimedOut,
+                TimeoutOr::Other(ConnectError::FatalConnect(e)) => {
+                    crate::enclave::Error::WebSocketConnect(e)
+                }
+            })?;
+
+        Ok((ws, route_info, attestation_permit))
+    }
+}
+
+/// Error from [`ConnectionResources::preconnect_and_save`].
+#[derive(Debug, thiserror::Error, displaydoc::Display)]
+pub enum PreconnectError {
+    /// timed out after {attempt_duration:?}
+    Timeout { attempt_duration: Duration },
+    /// no routes were available to connect
+    NoResolvedRoutes,
+    /// all connect attempts failed
+    AllAttemptsFailed {
+        /// The error from the last attempt to fail, if any got far enough to
+        /// try connecting.
+        last_error: Option<TransportConnectError>,
+    },
+    /// {0}
+    Fatal(TransportConnectError),
+    /// the network changed too often to connect
+    UnstableNetwork,
+    /// the preconnect was cancelled
+    Cancelled,
+    /// too many connects were started recently
+    Throttled,
+}
+impl LogSafeDisplay for PreconnectError {}
+
+/// The routes tried by a successful
+/// [`ConnectionResources::preconnect_and_save_with_plan`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct PreconnectRoutePlan {
+    /// Routes whose attempts finished, in the order the attempts started,
+    /// including the one that succeeded.
+    ///
+    /// Attempts that were still in progress when the preconnect ended aren't
+    /// included.
+    pub attempted: Vec<RouteInfo>,
+    /// The route that was preconnected.
+    pub succeeded: RouteInfo,
+}
+
+impl<TC> ConnectionResources<'_, PreconnectingFactory<TC>>
+where
+    // Note that we're not using WebSocketTransportConnectorFactory here to make `connect_ws`
+    // easier to test; specifically, the output is not guaranteed to be an AsyncDuplexStream.
+    TC: ConnectorFactory<
+        TransportRoute,
+        Connector: Sync + Connector<TransportRoute, (), Error = TransportConnectError>,
+        Connection: Send + 'static,
+    >,
+{
+    /// Makes a transport connection over the first of `routes` that works, and
+    /// saves it for a later [`ConnectionResources::connect_ws`] to use.
+    ///
+    /// If `cancel` is provided and gets cancelled, the preconnect fails with
+    /// [`PreconnectError::Cancelled`]. As with `connect_ws`, the outcomes of
+    /// attempts that already finished are still recorded, and attempts that
+    /// were still in progress don't count against their routes.
+    pub async fn preconnect_and_save(
+        self,
+        routes: impl RouteProvider<Route = UnresolvedTransportRoute>,
+        log_tag: Arc<str>,
+        cancel: Option<&CancellationToken>,
+    ) -> Result<(), PreconnectError> {
+        self.preconnect_and_save_inner(routes, log_tag, cancel)
+            .await
+            .map(|_plan| ())
+    }
+
+    /// Like [`Self::preconnect_and_save`], but also returns the routes that
+    /// were tried on the way to a successful preconnect.
+    ///
+    /// When the preconnect fails, the routes that were tried are logged
+    /// instead, since a timed-out preconnect can't say which attempts would
+    /// have finished.
+    pub async fn preconnect_and_save_with_plan(
+        self,
+        routes: impl RouteProvider<Route = UnresolvedTransportRoute>,
+        log_tag: Arc<str>,
+    ) -> Result<PreconnectRoutePlan, PreconnectError> {
+        self.preconnect_and_save_inner(routes, log_tag, None).await
+    }
+
+    /// Shared implementation for the `preconnect_and_save` family.
+    async fn preconnect_and_save_inner(
+        self,
+        routes: impl RouteProvider<Route = UnresolvedTransportRoute>,
+        log_tag: Arc<str>,
+        cancel: Option<&CancellationToken>,
+    ) -> Result<PreconnectRoutePlan, PreconnectError> {
+        let Self {
+            connect_state,
+            dns_resolver,
+            network_change_event,
+            confirmation_header_name: _,
+        } = self;
+
+        let ConnectStateSnapshot {
+            route_resolver,
+            connect_timeout,
+            network_interface_poll_interval,
+            post_route_change_connect_timeout,
+            max_fronting_domains: _,
+            preconnect_timeout,
+            transport_connector,
+            attempts_record,
+            route_provider_context,
+            connect_observer: _,
+            connect_progress: _,
+            local_ip_source,
+            global_throttle: _,
+            report_resolved_target: _,
+            report_connect_timing: _,
+            phase_timeouts,
+            preferred_route_hint: _,
+            route_delay_override,
+            route_bandwidth: _,
+            connect_attempt_events: _,
+        } = lock_connect_state(connect_state).snapshot::<UsePreconnect<_>>();
+
+        let routes = routes
+            .map_routes(|r| UsePreconnect {
+                should: true,
+                inner: r,
+            })
+            .routes(&route_provider_context)
+            .collect_vec();
+
+        if cancel.is_some_and(CancellationToken::is_cancelled) {
+            log::info!("[{log_tag}] not connecting; already cancelled");
+            return Err(PreconnectError::Cancelled);
+        }
+
+        log::info!(
+            "[{log_tag}] starting connection attempt with {} routes",
+            routes.len()
+        );
+
+        struct ConnectWithSavedRoute<C>(C);
+
+        impl<R, Inner, C> Connector<R, Inner> for ConnectWithSavedRoute<C>
+        where
+            C: Connector<R, Inner>,
+            R: Clone + Send,
+        {
+            type Connection = (R, C::Connection);
+
+            type Error = C::Error;
+
+            fn connect_over(
+                &self,
+                over: Inner,
+                route: R,
+                log_tag: Arc<str>,
+            ) -> impl Future<Output = Result<Self::Connection, Self::Error>> + Send {
+                self.0
+                    .connect_over(over, route.clone(), log_tag)
+                    .map_ok(|connection| (route, connection))
+            }
+        }
+
+        let (network_change_tx, network_change_rx) = tokio::sync::watch::channel(());
+        let _network_change_subscription = network_change_event.subscribe(Box::new(move || {
+            network_change_tx.send_replace(());
+        }));
+
+        let route_provider = routes.into_iter().map(ResolveWithSavedDescription);
+        let connector = InterfaceMonitor::new_with_interface_source(
+            DescribedRouteConnector(ConnectWithSavedRoute(&transport_connector)),
+            local_ip_source,
+            network_change_rx,
+            network_interface_poll_interval,
+            post_route_change_connect_timeout,
+        );
+        let delay_policy = DelayBasedOnTransport(OutcomesWithOverride {
+            outcomes: attempts_record,
+            route_delay_override,
+            route_provider_context,
+        });
+
+        let start = Instant::now();
+        let mut last_error = None;
+        let connect = crate::infra::route::connect_until_cancelled(
+            &route_resolver,
+            delay_policy,
+            route_provider,
+            &TimeoutResolver::new(dns_resolver, phase_timeouts.dns),
+            connector,
+            (),
+            log_tag.clone(),
+            |error| {
+                match error {
+                    InterfaceChangedOr::InterfaceChanged => {
+                        ControlFlow::Break(TransportConnectError::ClientAbort)
+                    }
+                    InterfaceChangedOr::Other(error) => {
+                        // All normal transport-level errors are considered intermittent; see
+                        // WebSocketServiceConnectError::classify.
+                        last_error = Some(error);
+                        ControlFlow::Continue(())
+                    }
+                }
+            },
+            &(),
+            cancelled_or_pending(cancel),
+        );
+
+        let connect_timeout = preconnect_timeout.unwrap_or(connect_timeout);
+        let Ok((result, updates)) = tokio::time::timeout(connect_timeout, connect).await else {
+            log::info!("[{log_tag}] preconnect timed out after {connect_timeout:.3?}");
+            return Err(PreconnectError::Timeout {
+                attempt_duration: connect_timeout,
+            });
+        };
+
+        let route_info = |description: &UnresolvedRouteDescription| RouteInfo {
+            unresolved: description.clone(),
+            correlation: CorrelationContext::default(),
+            resolved_target: None,
+            connect_timing: None,
+            confirmation_header: None,
+            connected_transport: None,
+        };
+        let attempted = updates
+            .outcomes
+            .iter()
+            .sorted_by_key(|(_route, outcome)| outcome.started)
+            .map(|(route, _outcome)| route_info(&route.description))
+            .collect_vec();
+
+        match &result {
+            Ok((_connection, description)) => {
+                log::info!(
+                    "[{log_tag}] connection through {description} succeeded after {:.3?}",
+                    updates.finished_at - start
+                );
+            }
+            Err(e) => {
+                log::info!(
+                    "[{log_tag}] connection failed with {e} after trying [{}]",
+                    attempted.iter().join(", ")
+                );
+            }
+        }
+
+        // Don't exit yet, we have to save the results!
+        {
+            let mut connect_write = lock_connect_state(connect_state);
+
+            connect_write.attempts_record.apply_outcome_updates(
+                updates
+                    .outcomes
+                    .into_iter()
+                    .map(|(route, outcome)| (route.into_transport_part(), outcome)),
+                updates.finished_at,
+            );
+
+            let (
+                (
+                    UsePreconnect {
+                        inner: route,
+                        should: _,
+                    },
+                    connection,
+                ),
+                description,
+            ) = match result {
+                Ok(connected) => connected,
+                Err(e) => {
+                    let e = match e {
+                        ConnectError::NoResolvedRoutes => PreconnectError::NoResolvedRoutes,
+                        ConnectError::AllAttemptsFailed => {
+                            PreconnectError::AllAttemptsFailed { last_error }
+                        }
+                        ConnectError::FatalConnect(e) => PreconnectError::Fatal(e),
+                        ConnectError::UnstableNetwork => PreconnectError::UnstableNetwork,
+                        ConnectError::Cancelled => PreconnectError::Cancelled,
+                        ConnectError::Throttled => PreconnectError::Throttled,
+                    };
+                    return Err(e);
+                }
+            };
+
+            connect_write.make_transport_connector.save_preconnected(
+                route,
+                connection,
+                updates.finished_at,
+            );
+
+            Ok(PreconnectRoutePlan {
+                attempted,
+                succeeded: route_info(&description),
+            })
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub(crate) struct RouteProviderContextImpl(OsRng);
+
+impl RouteProviderContext for RouteProviderContextImpl {
+    fn random_usize(&self) -> usize {
+        // OsRng is zero-sized, so we're not losing random values by copying it.
+        let mut owned_rng: OsRng = self.0;
+        assert_eq_size_val!(owned_rng, ());
+        owned_rng.gen()
+    }
+}
+
+/// [`RouteProvider`] for routes that have already been listed out.
+struct ListedRoutes<R>(Vec<R>);
+
+impl<R: Clone> RouteProvider for ListedRoutes<R> {
+    type Route = R;
+
+    fn routes<'s>(
+        &'s self,
+        _context: &impl RouteProviderContext,
+    ) -> impl Iterator<Item = Self::Route> + 's {
+        self.0.iter().cloned()
+    }
+}
+
+/// Convenience alias for using `PreconnectingConnector`s with [`ConnectState`].
+pub type PreconnectingFactory<Inner = DefaultConnectorFactory> =
+    libsignal_net_infra::route::PreconnectingFactory<TransportRoute, Inner>;
+
+/// Forwards events for described routes to a [`ConnectObserver`] for [`RouteInfo`].
+struct DescribedRouteObserver<'a> {
+    observer: Option<Arc<dyn ConnectObserver<RouteInfo> + Send + Sync>>,
+    correlation: &'a CorrelationContext,
+}
+
+impl<R> ConnectObserver<WithLoggableDescription<R, UnresolvedRouteDescription>>
+    for DescribedRouteObserver<'_>
+{
+    fn on_route_abandoned(
+        &self,
+        route: &WithLoggableDescription<R, UnresolvedRouteDescription>,
+        reason: RouteAbandonReason,
+    ) {
+        let Self {
+            observer,
+            correlation,
+        } = self;
+        if let Some(observer) = observer {
+            let route_info = RouteInfo {
+                unresolved: route.description.clone(),
+                correlation: (*correlation).clone(),
+                resolved_target: None,
+                connect_timing: None,
+                confirmation_header: None,
+                connected_transport: None,
+            };
+            observer.on_route_abandoned(&route_info, reason);
+        }
+    }
+}
+
+/// [`RouteDelayPolicy`] that uses the [`RouteDelayOverride`] if there is one,
+/// and the recorded outcomes otherwise.
+struct OutcomesWithOverride {
+    outcomes: ConnectionOutcomes<TransportRoute>,
+    route_delay_override: Option<RouteDelayOverride>,
+    /// Source of randomness for [`ConnectionOutcomeParams::jitter_fraction`].
+    route_provider_context: RouteProviderContextImpl,
+}
+
+impl RouteDelayPolicy<TransportRoute> for OutcomesWithOverride {
+    fn compute_delay(&self, route: &TransportRoute, now: Instant) -> Duration {
+        let Self {
+            outcomes,
+            route_delay_override,
+            route_provider_context,
+        } = self;
+        match route_delay_override {
+            Some(compute_delay) => compute_delay(route, outcomes, now),
+            None => outcomes
+                .params()
+                .apply_jitter(outcomes.compute_delay(route, now), route_provider_context),
+        }
+    }
+}
+
+/// [`RouteDelayPolicy`] wrapper that records each route the scheduler ranks,
+/// in order, along with when its cooldown ends if it was held back.
+///
+/// The scheduler asks for delays in the order it would otherwise try routes,
+/// so this is enough to tell which held-back routes it passed over.
+struct RecordScheduledRoutes<'a, P, D> {
+    inner: P,
+    scheduled: Option<&'a std::sync::Mutex<Vec<(D, Option<Instant>)>>>,
+}
+
+impl<P, R, D> RouteDelayPolicy<WithLoggableDescription<R, D>> for RecordScheduledRoutes<'_, P, D>
+where
+    P: RouteDelayPolicy<WithLoggableDescription<R, D>>,
+    D: Clone,
+{
+    fn compute_delay(&self, route: &WithLoggableDescription<R, D>, now: Instant) -> Duration {
+        let Self { inner, scheduled } = self;
+        let delay = inner.compute_delay(route, now);
+        if let Some(scheduled) = scheduled {
+            let cooldown_until = (!delay.is_zero()).then(|| now + delay);
+            scheduled
+                .lock()
+                .expect("not poisoned")
+                .push((route.description.clone(), cooldown_until));
+        }
+        delay
+    }
+}
+
+/// Reports each [`ConnectStage`] the first time a connect reaches it.
+struct ConnectProgress {
+    callback: Option<Arc<dyn Fn(ConnectStage) + Send + Sync>>,
+    reached: std::sync::Mutex<Option<ConnectStage>>,
+}
+
+impl ConnectProgress {
+    fn new(callback: Option<Arc<dyn Fn(ConnectStage) + Send + Sync>>) -> Self {
+        Self {
+            callback,
+            reached: Default::default(),
+        }
+    }
+
+    fn advance(&self, stage: ConnectStage) {
+        let Self { callback, reached } = self;
+        let Some(callback) = callback else {
+            return;
+        };
+        // Hold the lock while calling back so stages are reported in order.
+        let mut reached = reached.lock().expect("not poisoned");
+        if reached.is_some_and(|reached| reached >= stage) {
+            return;
+        }
+        *reached = Some(stage);
+        callback(stage);
+    }
+}
+
+/// A [`Connector`] for described routes that sends a [`ConnectAttemptEvent`]
+/// for each successful attempt.
+///
+/// Failed attempts are returned as a [`FailedAttempt`] so the caller can
+/// report them once the error has been classified.
+struct ReportAttemptConnector<'a, C> {
+    inner: C,
+    events: &'a Option<tokio::sync::mpsc::Sender<ConnectAttemptEvent>>,
+}
+
+/// Error from [`ReportAttemptConnector`].
+struct FailedAttempt<E> {
+    error: E,
+    route: UnresolvedRouteDescription,
+    started: Instant,
+}
+
+impl<C, R, Inner> Connector<WithLoggableDescription<R, UnresolvedRouteDescription>, Inner>
+    for ReportAttemptConnector<'_, C>
+where
+    C: Connector<WithLoggableDescription<R, UnresolvedRouteDescription>, Inner>,
+{
+    type Connection = C::Connection;
+    type Error = FailedAttempt<C::Error>;
+
+    fn connect_over(
+        &self,
+        over: Inner,
+        route: WithLoggableDescription<R, UnresolvedRouteDescription>,
+        log_tag: Arc<str>,
+    ) -> impl Future<Output = Result<Self::Connection, Self::Error>> + Send {
+        let Self { inner, events } = self;
+        let started = Instant::now();
+        let description = route.description.clone();
+        let success_description = description.clone();
+        inner
+            .connect_over(over, route, log_tag)
+            .map_ok(move |connection| {
+                send_attempt_event(
+                    events,
+                    success_description,
+                    started,
+                    ConnectAttemptOutcome::Succeeded,
+                );
+                connection
+            })
+            .map_err(move |error| FailedAttempt {
+                error,
+                route: description,
+                started,
+            })
+    }
+}
+
+/// Sends an event without waiting, dropping it if the channel is full or
+/// closed.
+fn send_attempt_event(
+    events: &Option<tokio::sync::mpsc::Sender<ConnectAttemptEvent>>,
+    route: UnresolvedRouteDescription,
+    started: Instant,
+    outcome: ConnectAttemptOutcome,
+) {
+    if let Some(events) = events {
+        let _ignore_full_or_closed = events.try_send(ConnectAttemptEvent {
+            route,
+            started,
+            outcome,
+        });
+    }
+}
+
+/// Whether `error` means a phase of the attempt ran out of time.
+fn is_timeout(error: &WebSocketConnectError) -> bool {
+    match error {
+        WebSocketConnectError::Timeout => true,
+        WebSocketConnectError::WebSocketError(tungstenite::Error::Io(e)) => {
+            e.kind() == std::io::ErrorKind::TimedOut
+        }
+        WebSocketConnectError::Transport(TransportConnectError::SslFailedHandshake(reason)) => {
+            *reason == FailedHandshakeReason::TIMED_OUT
+        }
+        WebSocketConnectError::Transport(_) | WebSocketConnectError::WebSocketError(_) => false,
+    }
+}
+
+/// Resolves when `cancel` is cancelled, or never if there isn't one.
+async fn cancelled_or_pending(cancel: Option<&CancellationToken>) {
+    match cancel {
+        Some(cancel) => cancel.cancelled().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// A [`Connector`] that reports reaching `stage` whenever it starts a
+/// connection, and runs the connection in a span for `span_phase`.
+struct ReportStageConnector<'a, C> {
+    inner: C,
+    stage: ConnectStage,
+    span_phase: spans::Phase,
+    progress: &'a ConnectProgress,
+}
+
+impl<C, R, Inner> Connector<R, Inner> for ReportStageConnector<'_, C>
+where
+    C: Connector<R, Inner>,
+{
+    type Connection = C::Connection;
+    type Error = C::Error;
+
+    fn connect_over(
+        &self,
+        over: Inner,
+        route: R,
+        log_tag: Arc<str>,
+    ) -> impl Future<Output = Result<Self::Connection, Self::Error>> + Send {
+        let Self {
+            inner,
+            stage,
+            span_phase,
+            progress,
+        } = self;
+        progress.advance(*stage);
+        let span = spans::phase(*span_phase, &log_tag);
+        spans::instrument(inner.connect_over(over, route, log_tag), span)
+    }
+}
+
+/// Moves routes of type `preferred` ahead of all the others.
+///
+/// The relative order of routes is otherwise preserved. If none of `routes`
+/// have the preferred type, they're returned unchanged.
+fn prefer_route_type<R>(routes: Vec<R>, preferred: RouteType, log_tag: &str) -> Vec<R>
+where
+    R: DescribeForLog<Description = UnresolvedRouteDescription>,
+{
+    let (hinted, others): (Vec<_>, Vec<_>) = routes
+        .into_iter()
+        .partition(|route| route.describe_for_log().route_type() == Some(preferred));
+    if hinted.is_empty() {
+        log::info!(
+            "[{log_tag}] no {preferred} routes available; ignoring the preferred route hint"
+        );
+        return others;
+    }
+    log::info!(
+        "[{log_tag}] trying {} {preferred} route(s) first, as hinted",
+        hinted.len()
+    );
+    hinted.into_iter().chain(others).collect()
+}
+
+/// Moves routes with a higher recorded bandwidth ahead of the others.
+///
+/// Routes without a recorded bandwidth go last. Routes with equal bandwidth
+/// keep their relative order.
+fn prefer_high_bandwidth<R>(routes: Vec<R>, bandwidth: &RouteBandwidth) -> Vec<R>
+where
+    R: DescribeForLog<Description = UnresolvedRouteDescription>,
+{
+    let mut ranked = routes
+        .into_iter()
+        .map(|route| (bandwidth.estimate(&route.describe_for_log()), route))
+        .collect_vec();
+    // `None` compares less than any estimate, and the sort is stable.
+    ranked.sort_by(|(a, _), (b, _)| b.partial_cmp(a).unwrap_or(std::cmp::Ordering::Equal));
+    ranked.into_iter().map(|(_, route)| route).collect()
+}
+
+/// Drops routes through all but the `max` best-ranked domain fronts.
+///
+/// Fronts are ranked by the delay `outcomes` assigns to their hosts because of
+/// recent failures, with ties broken by the order the routes were provided in.
+/// Routes that aren't fronted are kept as-is.
+fn limit_fronting_domains<R>(
+    routes: Vec<R>,
+    max: NonZeroUsize,
+    outcomes: &ConnectionOutcomes<TransportRoute>,
+    now: Instant,
+) -> Vec<R>
+where
+    R: DescribeForLog<Description = UnresolvedRouteDescription>,
+{
+    let descriptions = routes.iter().map(R::describe_for_log).collect_vec();
+
+    // Fronts in the order they first appear, with the best delay for any route through them.
+    let mut fronts: Vec<(&'static str, Duration)> = Vec::new();
+    for description in &descriptions {
+        let Some(front) = description.front() else {
+            continue;
+        };
+        let delay = outcomes
+            .min_delay_matching(
+                |route| route.fragment.sni == *description.target_host(),
+                now,
+            )
+            .unwrap_or_default();
+        match fronts.iter_mut().find(|(name, _)| *name == front) {
+            Some((_, best_delay)) => *best_delay = (*best_delay).min(delay),
+            None => fronts.push((front, delay)),
+        }
+    }
+
+    if fronts.len() <= max.get() {
+        return routes;
+    }
+
+    // This is a stable sort, so fronts with equal delays keep their original order.
+    fronts.sort_by_key(|(_, delay)| *delay);
+    let allowed_fronts = fronts[..max.get()]
+        .iter()
+        .map(|(name, _)| *name)
+        .collect_vec();
+    log::info!(
+        "limiting domain fronting to {allowed_fronts:?} out of {} fronts",
+        fronts.len()
+    );
+
+    routes
+        .into_iter()
+        .zip(descriptions)
+        .filter_map(|(route, description)| match description.front() {
+            None => Some(route),
+            Some(front) => allowed_fronts.contains(&front).then_some(route),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::sync::{Arc, LazyLock, Mutex};
+    use std::time::Duration;
+
+    use assert_matches::assert_matches;
+    use const_str::ip_addr;
+    use http::uri::PathAndQuery;
+    use http::HeaderMap;
+    use libsignal_net_infra::certs::RootCertificates;
+    use libsignal_net_infra::dns::lookup_result::LookupResult;
+    use libsignal_net_infra::host::Host;
+    use libsignal_net_infra::route::testutils::ConnectFn;
+    use libsignal_net_infra::route::{
+        AttemptOutcome, DirectOrProxyRoute, HttpsTlsRoute, TcpRoute, TlsRoute, TlsRouteFragment,
+        UnresolvedHost, UnresolvedTransportRoute, UnsuccessfulOutcome, WebSocketRoute,
+    };
+    use libsignal_net_infra::{Alpn, DnsSource, RouteType};
+    use nonzero_ext::nonzero;
+    use test_case::test_case;
+
+    use super::*;
+    use crate::ws::NotRejectedByServer;
+
+    const FAKE_HOST_NAME: &str = "direct-host";
+    static FAKE_TRANSPORT_ROUTE: LazyLock<UnresolvedTransportRoute> = LazyLock::new(|| TlsRoute {
+        fragment: TlsRouteFragment {
+            root_certs: RootCertificates::Native,
+            sni: Host::Domain("fake-sni".into()),
+            alpn: Alpn::Http1_1.into(),
+            ech_config_list: None,
+        },
+        inner: DirectOrProxyRoute::Direct(TcpRoute {
+            address: UnresolvedHost::from(Arc::from(FAKE_HOST_NAME)),
+            port: nonzero!(1234u16),
+        }),
+    });
+    static FAKE_WEBSOCKET_ROUTES: LazyLock<[UnresolvedWebsocketServiceRoute; 2]> =
+        LazyLock::new(|| {
+            [
+                WebSocketRoute {
+                    fragment: WebSocketRouteFragment {
+                        ws_config: Default::default(),
+                        endpoint: PathAndQuery::from_static("/first"),
+                        headers: HeaderMap::new(),
+                    },
+                    inner: HttpsTlsRoute {
+                        fragment: HttpRouteFragment {
+                            host_header: "first-host".into(),
+                            path_prefix: "".into(),
+                            front_name: None,
+                        },
+                        inner: (*FAKE_TRANSPORT_ROUTE).clone(),
+                    },
+                },
+                WebSocketRoute {
+                    fragment: WebSocketRouteFragment {
+                        ws_config: Default::default(),
+                        endpoint: PathAndQuery::from_static("/second"),
+                        headers: HeaderMap::new(),
+                    },
+                    inner: HttpsTlsRoute {
+                        fragment: HttpRouteFragment {
+                            host_header: "second-host".into(),
+                            path_prefix: "".into(),
+                            front_name: Some(RouteType::ProxyF.into()),
+                        },
+                        inner: (*FAKE_TRANSPORT_ROUTE).clone(),
+                    },
+                },
+            ]
+        });
+
+    #[test]
+    fn config_builder_starts_from_suggested_values() {
+        assert_eq!(Config::builder().build(), SUGGESTED_CONNECT_CONFIG);
+
+        let config = Config::builder()
+            .connect_timeout(Duration::from_secs(5))
+            .preconnect_timeout(Some(Duration::from_secs(2)))
+            .build();
+        assert_eq!(
+            config,
+            Config {
+                connect_timeout: Duration::from_secs(5),
+                preconnect_timeout: Some(Duration::from_secs(2)),
+                ..SUGGESTED_CONNECT_CONFIG
+            }
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn connect_ws_successful() {
+        // This doesn't actually matter since we're using a fake connector, but
+        // using the real route type is easier than trying to add yet more
+        // generic parameters.
+        let [failing_route, succeeding_route] = (*FAKE_WEBSOCKET_ROUTES).clone();
+
+        let ws_connector = ConnectFn(|(), route, _log_tag| {
+            let (ws, http) = &route;
+            std::future::ready(
+                if (ws, http) == (&failing_route.fragment, &failing_route.inner.fragment) {
+                    Err(tungstenite::Error::ConnectionClosed)
+                } else {
+                    Ok(route)
+                },
+            )
+        });
+        let resolver = DnsResolver::new_from_static_map(HashMap::from([(
+            FAKE_HOST_NAME,
+            LookupResult::new(DnsSource::Static, vec![ip_addr!(v4, "192.0.2.1")], vec![]),
+        )]));
+
+        let fake_transport_connector =
+            ConnectFn(move |(), _, _| std::future::ready(Ok::<_, WebSocketConnectError>(())));
+
+        let state = ConnectState {
+            connect_timeout: Duration::MAX,
+            network_interface_poll_interval: Duration::MAX,
+            post_route_change_connect_timeout: Duration::MAX,
+            max_fronting_domains: None,
+            preconnect_timeout: None,
+            route_resolver: RouteResolver::default(),
+            attempts_record: ConnectionOutcomes::new(SUGGESTED_CONNECT_PARAMS),
+            make_transport_connector: fake_transport_connector,
+            route_provider_context: Default::default(),
+            connect_observer: None,
+            connect_progress: None,
+            local_ip_source: Default::default(),
+            global_throttle: None,
+            report_resolved_target: false,
+            report_connect_timing: false,
+            phase_timeouts: Default::default(),
+            preferred_route_hint: None,
+            unstable_network: None,
+            interface_change_aborts: Default::default(),
+            connectivity_stats: Default::default(),
+            attestation_permits: None,
+            route_delay_override: None,
+            route_ordering: Default::default(),
+            route_bandwidth: Default::default(),
+            connect_attempt_events: None,
+        }
+        .into();
+
+        let connection_resources = ConnectionResources {
+            connect_state: &state,
+            dns_resolver: &resolver,
+            network_change_event: &ObservableEvent::new(),
+            confirmation_header_name: None,
+        };
+
+        let result = connection_resources
+            .connect_ws(
+                vec![failing_route.clone(), succeeding_route.clone()],
+                ws_connector,
+                "test".into(),
+                None,
+            )
+            // This previously hung forever due to a deadlock bug.
+            .await;
+
+        let (connection, info) = result.expect("succeeded");
+        assert_eq!(
+            connection,
+            (succeeding_route.fragment, succeeding_route.inner.fragment)
+        );
+        let RouteInfo {
+            unresolved,
+            correlation: _,
+            resolved_target,
+            connect_timing,
+            confirmation_header,
+            connected_transport: _,
+        } = info;
+
+        assert_eq!(resolved_target, None, "not requested");
+        assert_eq!(connect_timing, None, "not requested");
+        assert_eq!(confirmation_header, None, "no response headers");
+
+        assert_eq!(unresolved.to_string(), "REDACTED:1234 fronted by proxyf");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn connect_ws_lock_acquisitions() {
+        let ws_connector =
+            ConnectFn(|(), route, _log_tag| std::future::ready(Ok::<_, tungstenite::Error>(route)));
+        let resolver = DnsResolver::new_from_static_map(HashMap::from([(
+            FAKE_HOST_NAME,
+            LookupResult::new(DnsSource::Static, vec![ip_addr!(v4, "192.0.2.1")], vec![]),
+        )]));
+        let fake_transport_connector =
+            ConnectFn(move |(), _, _| std::future::ready(Ok::<_, WebSocketConnectError>(())));
+
+        let state = Arc::new(ConnectState::new_with_transport_connector(
+            SUGGESTED_CONNECT_CONFIG,
+            fake_transport_connector,
+        ));
+
+        // For each acquisition, where it happened and whether the lock was
+        // free beforehand (if not, a single-threaded runtime would deadlock).
+        let acquisitions = Arc::new(Mutex::new(Vec::new()));
+        let network_change_event = ObservableEvent::new();
+        let connect = ConnectionResources {
+            connect_state: &state,
+            dns_resolver: &resolver,
+            network_change_event: &network_change_event,
+            confirmation_header_name: None,
+        }
+        .connect_ws(
+            vec![FAKE_WEBSOCKET_ROUTES[0].clone()],
+            ws_connector,
+            "test".into(),
+            None,
+        );
+        let result = ON_CONNECT_STATE_LOCK
+            .scope(
+                Box::new({
+                    let acquisitions = acquisitions.clone();
+                    let state = state.clone();
+                    move |location| {
+                        let was_free = state.try_lock().is_ok();
+                        acquisitions
+                            .lock()
+                            .expect("not poisoned")
+                            .push((location, was_free));
+                    }
+                }),
+                connect,
+            )
+            .await;
+        let _ = result.expect("succeeded");
+
+        // A connect takes a snapshot of the state, checks for an unstable
+        // network, and then records the outcome, in that order.
+        let acquisitions = std::mem::take(&mut *acquisitions.lock().expect("not poisoned"));
+        let [(snapshot, true), (unstable_check, true), (record_outcome, true)] =
+            acquisitions.as_slice()
+        else {
+            panic!("unexpected lock acquisitions: {acquisitions:?}");
+        };
+        for location in [snapshot, unstable_check, record_outcome] {
+            assert_eq!(location.file(), file!());
+        }
+        assert!(snapshot.line() < unstable_check.line());
+        assert!(unstable_check.line() < record_outcome.line());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn connect_ws_cancelled_mid_handshake() {
+        let [mut failing_route, handshaking_route] = (*FAKE_WEBSOCKET_ROUTES).clone();
+        failing_route.inner.inner.fragment.sni = Host::parse_as_ip_or_domain("fail");
+
+        let ws_connector = ConnectFn(|(), route, _log_tag| std::future::ready(Ok(route)));
+        let resolver = DnsResolver::new_from_static_map(HashMap::from([(
+            FAKE_HOST_NAME,
+            LookupResult::new(DnsSource::Static, vec![ip_addr!(v4, "192.0.2.1")], vec![]),
+        )]));
+
+        let cancel = CancellationToken::new();
+        let handshakes_started = std::sync::atomic::AtomicUsize::new(0);
+        let transport_connector = ConnectFn(|(), route: TransportRoute, _| {
+            let fails = route.fragment.sni == Host::parse_as_ip_or_domain("fail");
+            if !fails {
+                handshakes_started.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                // Cancel while this attempt is still in its "TLS handshake".
+                cancel.cancel();
+            }
+            async move {
+                if fails {
+                    Err(TransportConnectError::TcpConnectionFailed)
+                } else {
+                    std::future::pending::<Result<(), TransportConnectError>>().await
+                }
+            }
+        });
+        let state = ConnectState::new_with_transport_connector(
+            SUGGESTED_CONNECT_CONFIG,
+            transport_connector,
+        );
+
+        let network_change_event = ObservableEvent::new();
+        let connect = || {
+            ConnectionResources {
+                connect_state: &state,
+                dns_resolver: &resolver,
+                network_change_event: &network_change_event,
+                confirmation_header_name: None,
+            }
+            .connect_ws(
+                vec![failing_route.clone(), handshaking_route.clone()],
+                &ws_connector,
+                "test".into(),
+                Some(&cancel),
+            )
+        };
+
+        let result = connect().await;
+        assert_matches!(result, Err(TimeoutOr::Other(ConnectError::Cancelled)));
+        assert_eq!(
+            handshakes_started.load(std::sync::atomic::Ordering::SeqCst),
+            1
+        );
+
+        // The failure was recorded, but the cancelled attempt wasn't.
+        let has_recent_failure = |sni: &str| {
+            lock_connect_state(&state)
+                .attempts_record
+                .min_delay_matching(
+                    |route| route.fragment.sni == Host::parse_as_ip_or_domain(sni),
+                    Instant::now(),
+                )
+                .is_some()
+        };
+        assert!(has_recent_failure("fail"));
+        assert!(!has_recent_failure("fake-sni"));
+
+        // Once cancelled, no further attempts are made.
+        let result = connect().await;
+        assert_matches!(result, Err(TimeoutOr::Other(ConnectError::Cancelled)));
+        assert_eq!(
+            handshakes_started.load(std::sync::atomic::Ordering::SeqCst),
+            1
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn connect_ws_reports_attempt_events() {
+        let [failing_route, succeeding_route] = (*FAKE_WEBSOCKET_ROUTES).clone();
+
+        let ws_connector = ConnectFn(|(), route, _log_tag| {
+            let (_ws, http) = &route;
+            std::future::ready(if http == &failing_route.inner.fragment {
+                Err(tungstenite::Error::ConnectionClosed)
+            } else {
+                Ok(route)
+            })
+        });
+        let resolver = DnsResolver::new_from_static_map(HashMap::from([(
+            FAKE_HOST_NAME,
+            LookupResult::new(DnsSource::Static, vec![ip_addr!(v4, "192.0.2.1")], vec![]),
+        )]));
+        let fake_transport_connector =
+            ConnectFn(move |(), _, _| std::future::ready(Ok::<_, WebSocketConnectError>(())));
+
+        let state = ConnectState::new_with_transport_connector(
+            SUGGESTED_CONNECT_CONFIG,
+            fake_transport_connector,
+        );
+        let (events_tx, mut events_rx) = tokio::sync::mpsc::channel(10);
+        lock_connect_state(&state).set_connect_attempt_events(Some(events_tx));
+
+        let network_change_event = ObservableEvent::new();
+        let connect = || {
+            ConnectionResources {
+                connect_state: &state,
+                dns_resolver: &resolver,
+                network_change_event: &network_change_event,
+                confirmation_header_name: None,
+            }
+            .connect_ws(
+                vec![failing_route.clone(), succeeding_route.clone()],
+                &ws_connector,
+                "test".into(),
+                None,
+            )
+        };
+        let start = Instant::now();
+        let _ = connect().await.expect("succeeded");
+
+        let mut events = vec![];
+        while let Ok(event) = events_rx.try_recv() {
+            events.push(event);
+        }
+        let [failed, succeeded] = events.as_slice() else {
+            panic!("unexpected events: {events:?}");
+        };
+        assert_eq!(failed.route, failing_route.describe_for_log());
+        assert_matches!(
+            failed.outcome,
+            ConnectAttemptOutcome::Failed(ErrorClass::Intermittent)
+        );
+        assert_eq!(succeeded.route, succeeding_route.describe_for_log());
+        assert_matches!(succeeded.outcome, ConnectAttemptOutcome::Succeeded);
+        assert!(start <= failed.started && failed.started <= succeeded.started);
+
+        // Nobody listening doesn't affect the connect.
+        drop(events_rx);
+        let _ = connect().await.expect("succeeded");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn connect_ws_reports_only_routes_passed_over_as_skipped() {
+        let [failing_route, succeeding_route] = (*FAKE_WEBSOCKET_ROUTES).clone();
+
+        let fail_first_attempt = std::sync::atomic::AtomicBool::new(true);
+        let ws_connector = ConnectFn(|(), route, _log_tag| {
+            let (_ws, http) = &route;
+            let fail = http == &failing_route.inner.fragment
+                && fail_first_attempt.swap(false, std::sync::atomic::Ordering::SeqCst);
+            std::future::ready(if fail {
+                Err(tungstenite::Error::ConnectionClosed)
+            } else {
+                Ok(route)
+            })
+        });
+        let resolver = DnsResolver::new_from_static_map(HashMap::from([(
+            FAKE_HOST_NAME,
+            LookupResult::new(DnsSource::Static, vec![ip_addr!(v4, "192.0.2.1")], vec![]),
+        )]));
+        let fake_transport_connector =
+            ConnectFn(move |(), _, _| std::future::ready(Ok::<_, WebSocketConnectError>(())));
+
+        let state = ConnectState::new_with_transport_connector(
+            SUGGESTED_CONNECT_CONFIG,
+            fake_transport_connector,
+        );
+        let (events_tx, mut events_rx) = tokio::sync::mpsc::channel(10);
+        lock_connect_state(&state).set_connect_attempt_events(Some(events_tx));
+
+        let network_change_event = ObservableEvent::new();
+        let connect = |routes| {
+            ConnectionResources {
+                connect_state: &state,
+                dns_resolver: &resolver,
+                network_change_event: &network_change_event,
+                confirmation_header_name: None,
+            }
+            .connect_ws(routes, &ws_connector, "test".into(), None)
+        };
+        let mut take_outcomes = || {
+            let mut outcomes = vec![];
+            while let Ok(event) = events_rx.try_recv() {
+                outcomes.push((event.route, event.outcome));
+            }
+            outcomes
+        };
+
+        // Put the first route into cooldown.
+        let _ = connect(vec![failing_route.clone(), succeeding_route.clone()])
+            .await
+            .expect("succeeded");
+        let _ = take_outcomes();
+
+        // The cooling-down route is ranked last and never needed, so it isn't
+        // reported.
+        let _ = connect(vec![succeeding_route.clone(), failing_route.clone()])
+            .await
+            .expect("succeeded");
+        assert_matches!(
+            take_outcomes().as_slice(),
+            [(route, ConnectAttemptOutcome::Succeeded)]
+                if *route == succeeding_route.describe_for_log()
+        );
+
+        // Here the scheduler moves past it to the next route, so it is.
+        let _ = connect(vec![failing_route.clone(), succeeding_route.clone()])
+            .await
+            .expect("succeeded");
+        assert_matches!(
+            take_outcomes().as_slice(),
+            [
+                (succeeded, ConnectAttemptOutcome::Succeeded),
+                (skipped, ConnectAttemptOutcome::SkippedDueToCooldown),
+            ] if *skipped == failing_route.describe_for_log()
+                && *succeeded == succeeding_route.describe_for_log()
+        );
+    }
+
+    /// A span recorded by [`CapturedSpans`].
+    #[cfg(feature = "tracing")]
+    #[derive(Clone, Debug)]
+    struct CapturedSpan {
+        name: &'static str,
+        parent: Option<&'static str>,
+        log_tag: Option<String>,
+        hostname: Option<String>,
+    }
+
+    /// A [`tracing_subscriber::Layer`] that records every span created.
+    #[cfg(feature = "tracing")]
+    #[derive(Clone, Default)]
+    struct CapturedSpans(Arc<Mutex<Vec<CapturedSpan>>>);
+
+    #[cfg(feature = "tracing")]
+    impl<S> tracing_subscriber::Layer<S> for CapturedSpans
+    where
+        S: tracing::Subscriber + for<'a> tracing_subscriber::registry::LookupSpan<'a>,
+    {
+        fn on_new_span(
+            &self,
+            attrs: &tracing::span::Attributes<'_>,
+            id: &tracing::span::Id,
+            ctx: tracing_subscriber::layer::Context<'_, S>,
+        ) {
+            let mut log_tag = None;
+            let mut hostname = None;
+            attrs.record(
+                &mut |field: &tracing::field::Field, value: &dyn std::fmt::Debug| match field.name()
+                {
+                    "log_tag" => log_tag = Some(format!("{value:?}")),
+                    "hostname" => hostname = Some(format!("{value:?}")),
+                    _ => {}
+                },
+            );
+            let parent = ctx
+                .span(id)
+                .and_then(|span| span.parent())
+                .map(|parent| parent.name());
+            self.0.lock().expect("not poisoned").push(CapturedSpan {
+                name: attrs.metadata().name(),
+                parent,
+                log_tag,
+                hostname,
+            });
+        }
+    }
+
+    #[cfg(feature = "tracing")]
+    #[tokio::test(start_paused = true)]
+    async fn connect_ws_emits_tracing_spans() {
+        use tracing_subscriber::layer::SubscriberExt as _;
+
+        let captured = CapturedSpans::default();
+        let _subscriber =
+            tracing::subscriber::set_default(tracing_subscriber::registry().with(captured.clone()));
+
+        let [route, _] = (*FAKE_WEBSOCKET_ROUTES).clone();
+        let ws_connector = ConnectFn(|(), route, _log_tag| std::future::ready(Ok(route)));
+        let resolver = DnsResolver::new_from_static_map(HashMap::from([(
+            FAKE_HOST_NAME,
+            LookupResult::new(DnsSource::Static, vec![ip_addr!(v4, "192.0.2.1")], vec![]),
+        )]));
+        let fake_transport_connector =
+            ConnectFn(move |(), _, _| std::future::ready(Ok::<_, WebSocketConnectError>(())));
+        let state = ConnectState::new_with_transport_connector(
+            SUGGESTED_CONNECT_CONFIG,
+            fake_transport_connector,
+        );
+        let network_change_event = ObservableEvent::new();
+
+        let _ = ConnectionResources {
+            connect_state: &state,
+            dns_resolver: &resolver,
+            network_change_event: &network_change_event,
+            confirmation_header_name: None,
+        }
+        .connect_ws(vec![route], &ws_connector, "test".into(), None)
+        .await
+        .expect("succeeded");
+
+        let spans = captured.0.lock().expect("not poisoned").clone();
+        let [connect, phases @ ..] = spans.as_slice() else {
+            panic!("no spans were emitted");
+        };
+        assert_eq!(
+            (connect.name, connect.parent, connect.log_tag.as_deref()),
+            ("connect_ws", None, Some("\"test\""))
+        );
+        let phase_names = phases
+            .iter()
+            .map(|span| {
+                assert_eq!(span.parent, Some("connect_ws"), "parent of {}", span.name);
+                span.name
+            })
+            .collect_vec();
+        assert_eq!(
+            phase_names,
+            ["resolve", "connect_transport", "websocket_upgrade"]
+        );
+        // FAKE_HOST_NAME isn't a Signal domain, so it isn't logged.
+        assert_eq!(phases[0].hostname.as_deref(), Some("\"REDACTED\""));
+    }
+
+    #[cfg(feature = "tracing")]
+    #[tokio::test(start_paused = true)]
+    async fn connect_attested_ws_emits_tracing_spans() {
+        use libsignal_net_infra::ws::testutil::fake_websocket;
+        use libsignal_net_infra::ws::NextOrClose;
+        use libsignal_net_infra::ws2::attested::testutil::{
+            run_attested_server, AttestedServerOutput,
+        };
+        use tracing_subscriber::layer::SubscriberExt as _;
+
+        let captured = CapturedSpans::default();
+        let _subscriber =
+            tracing::subscriber::set_default(tracing_subscriber::registry().with(captured.clone()));
+
+        let fake_transport_connector = ConnectFn(|(), _, _| {
+            std::future::ready(Ok::<_, WebSocketConnectError>(tokio::io::duplex(1).0))
+        });
+        let ws_connector = ConnectFn(|_transport, _route, _log_tag| async {
+            let (server, client) = fake_websocket().await;
+            tokio::spawn(run_attested_server(
+                server,
+                attest::sgx_session::testutil::private_key(),
+                |frame| match frame {
+                    NextOrClose::Next(message) => AttestedServerOutput::message(message),
+                    NextOrClose::Close(close) => AttestedServerOutput::close(close),
+                },
+            ));
+            Ok::<_, tungstenite::Error>(client)
+        });
+
+        let _connection = connect_uncounted_fake_enclave(fake_transport_connector, ws_connector)
+            .await
+            .expect("succeeded");
+
+        let spans = captured
+            .0
+            .lock()
+            .expect("not poisoned")
+            .iter()
+            .map(|span| (span.name, span.parent))
+            .collect_vec();
+        assert_eq!(
+            spans,
+            [
+                ("connect_attested_ws", None),
+                ("connect_ws", Some("connect_attested_ws")),
+                ("resolve", Some("connect_ws")),
+                ("connect_transport", Some("connect_ws")),
+                ("websocket_upgrade", Some("connect_ws")),
+                ("attestation_handshake", Some("connect_attested_ws")),
+            ]
+        );
+    }
+
+    /// Returns an [`ON_CONNECT_STATE_LOCK`] hook that makes the `nth`
+    /// acquisition of `state`'s lock (counting from zero) contended.
+    ///
+    /// Another thread takes the lock first and holds it for `hold_for`, like a
+    /// slow [`ConnectState::network_changed`] would, calling `while_held` just
+    /// before releasing it. Since the lock is held on a different thread, this
+    /// works even on a single-threaded runtime.
+    fn contend_connect_state_lock<TC: Send + 'static>(
+        state: Arc<Mutex<ConnectState<TC>>>,
+        nth: usize,
+        hold_for: Duration,
+        while_held: impl FnOnce(&mut ConnectState<TC>) + Send + 'static,
+    ) -> Box<dyn Fn(&'static std::panic::Location<'static>)> {
+        let acquisitions = std::cell::Cell::new(0);
+        let while_held = std::cell::Cell::new(Some(while_held));
+        Box::new(move |_location| {
+            let index = acquisitions.get();
+            acquisitions.set(index + 1);
+            if index != nth {
+                return;
+            }
+            let while_held = while_held.take().expect("only contended once");
+
+            let (locked_tx, locked_rx) = std::sync::mpsc::channel();
+            let state = state.clone();
+            let _detached = std::thread::spawn(move || {
+                let mut state = lock_connect_state(&state);
+                locked_tx.send(()).expect("still waiting");
+                std::thread::sleep(hold_for);
+                while_held(&mut state);
+            });
+            locked_rx.recv().expect("contending thread took the lock");
+        })
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn connect_ws_completes_when_lock_is_contended() {
+        const HOLD_FOR: Duration = Duration::from_millis(50);
+
+        let ws_connector =
+            ConnectFn(|(), route, _log_tag| std::future::ready(Ok::<_, tungstenite::Error>(route)));
+        let resolver = DnsResolver::new_from_static_map(HashMap::from([(
+            FAKE_HOST_NAME,
+            LookupResult::new(DnsSource::Static, vec![ip_addr!(v4, "192.0.2.1")], vec![]),
+        )]));
+        let fake_transport_connector =
+            ConnectFn(move |(), _, _| std::future::ready(Ok::<_, WebSocketConnectError>(())));
+
+        let state = Arc::new(ConnectState::new_with_transport_connector(
+            SUGGESTED_CONNECT_CONFIG,
+            fake_transport_connector,
+        ));
+
+        // Contend the acquisition that records the outcome; see
+        // connect_ws_lock_acquisitions.
+        let stats_while_held = Arc::new(Mutex::new(None));
+        let on_lock = contend_connect_state_lock(state.clone(), 2, HOLD_FOR, {
+            let stats_while_held = stats_while_held.clone();
+            move |state| {
+                *stats_while_held.lock().expect("not poisoned") = Some(state.connectivity_stats())
+            }
+        });
+
+        let network_change_event = ObservableEvent::new();
+        let connect = ConnectionResources {
+            connect_state: &state,
+            dns_resolver: &resolver,
+            network_change_event: &network_change_event,
+            confirmation_header_name: None,
+        }
+        .connect_ws(
+            vec![FAKE_WEBSOCKET_ROUTES[0].clone()],
+            ws_connector,
+            "test".into(),
+            None,
+        );
+        let start = std::time::Instant::now();
+        let result = ON_CONNECT_STATE_LOCK.scope(on_lock, connect).await;
+        let _ = result.expect("succeeded");
+        assert!(start.elapsed() >= HOLD_FOR, "waited for the lock");
+
+        // The outcome wasn't saved while the other thread held the lock, but
+        // was once it was released.
+        assert_eq!(
+            *stats_while_held.lock().expect("not poisoned"),
+            Some(ConnectivityStats::default())
+        );
+        assert_ne!(
+            lock_connect_state(&state).connectivity_stats(),
+            ConnectivityStats::default()
+        );
+    }
+
+    /// Runs connects made by `make_connect`, cancelling the first after one
+    /// poll, the next after two polls, and so on, until one finishes without
+    /// being cancelled; its output is returned.
+    ///
+    /// Whenever a connect is suspended, `state` must not be locked: holding the
+    /// lock across an await point would deadlock any other connect using the
+    /// same state. It must also still be usable after each cancellation.
+    async fn cancel_at_each_await_point<TC, Fut: Future>(
+        state: &std::sync::Mutex<ConnectState<TC>>,
+        make_connect: impl Fn() -> Fut,
+    ) -> Fut::Output {
+        for cancel_after in 1.. {
+            let output = {
+                let mut connect = std::pin::pin!(make_connect());
+                let mut polls = 0;
+                std::future::poll_fn(|cx| {
+                    if polls == cancel_after {
+                        return std::task::Poll::Ready(None);
+                    }
+                    polls += 1;
+                    let poll = connect.as_mut().poll(cx);
+                    assert!(
+                        state.try_lock().is_ok(),
+                        "state was left locked while suspended after {polls} polls"
+                    );
+                    poll.map(Some)
+                })
+                .await
+            };
+            if let Some(output) = output {
+                return output;
+            }
+            assert!(
+                state.try_lock().is_ok(),
+                "state is unusable after cancelling after {cancel_after} polls"
+            );
+        }
+        unreachable!("the connect eventually finishes")
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn connect_ws_can_be_cancelled_at_any_await_point() {
+        const STEP_DELAY: Duration = Duration::from_millis(10);
+
+        let [failing_route, succeeding_route] = (*FAKE_WEBSOCKET_ROUTES).clone();
+
+        let ws_connector = ConnectFn(|(), route, _log_tag| {
+            let (_ws, http) = &route;
+            let fails = http == &failing_route.inner.fragment;
+            async move {
+                tokio::time::sleep(STEP_DELAY).await;
+                if fails {
+                    Err(tungstenite::Error::ConnectionClosed)
+                } else {
+                    Ok(route)
+                }
+            }
+        });
+        let resolver = DnsResolver::new_from_static_map(HashMap::from([(
+            FAKE_HOST_NAME,
+            LookupResult::new(DnsSource::Static, vec![ip_addr!(v4, "192.0.2.1")], vec![]),
+        )]));
+
+        let slow_transport_connector = ConnectFn(|(), _, _| async {
+            tokio::time::sleep(STEP_DELAY).await;
+            Ok::<_, WebSocketConnectError>(())
+        });
+
+        let state = ConnectState {
+            connect_timeout: Duration::MAX,
+            network_interface_poll_interval: Duration::MAX,
+            post_route_change_connect_timeout: Duration::MAX,
+            max_fronting_domains: None,
+            preconnect_timeout: None,
+            route_resolver: RouteResolver::default(),
+            attempts_record: ConnectionOutcomes::new(SUGGESTED_CONNECT_PARAMS),
+            make_transport_connector: slow_transport_connector,
+            route_provider_context: Default::default(),
+            connect_observer: None,
+            connect_progress: None,
+            local_ip_source: Default::default(),
+            global_throttle: None,
+            report_resolved_target: false,
+            report_connect_timing: false,
+            phase_timeouts: Default::default(),
+            preferred_route_hint: None,
+            unstable_network: None,
+            interface_change_aborts: Default::default(),
+            connectivity_stats: Default::default(),
+            attestation_permits: None,
+            route_delay_override: None,
+            route_ordering: Default::default(),
+            route_bandwidth: Default::default(),
+            connect_attempt_events: None,
+        }
+        .into();
+        let network_change_event = ObservableEvent::new();
+
+        let result = cancel_at_each_await_point(&state, || {
+            ConnectionResources {
+                connect_state: &state,
+                dns_resolver: &resolver,
+                network_change_event: &network_change_event,
+                confirmation_header_name: None,
+            }
+            .connect_ws(
+                vec![failing_route.clone(), succeeding_route.clone()],
+                ws_connector.clone(),
+                "test".into(),
+                None,
+            )
+        })
+        .await;
+
+        let (connection, _info) = result.expect("succeeded");
+        assert_eq!(
+            connection,
+            (succeeding_route.fragment, succeeding_route.inner.fragment)
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn connect_ws_single_flight_waits_for_in_progress_attempt() {
+        const CONNECT_DELAY: Duration = Duration::from_millis(100);
+
+        let attempt_starts = std::sync::Mutex::new(Vec::new());
+        let fail = std::sync::atomic::AtomicBool::new(false);
+        let ws_connector = ConnectFn(|(), route, _log_tag| {
+            attempt_starts
+                .lock()
+                .expect("not poisoned")
+                .push(Instant::now());
+            let fails = fail.load(std::sync::atomic::Ordering::Relaxed);
+            async move {
+                tokio::time::sleep(CONNECT_DELAY).await;
+                if fails {
+                    Err(tungstenite::Error::ConnectionClosed)
+                } else {
+                    Ok(route)
+                }
+            }
+        });
+        let resolver = DnsResolver::new_from_static_map(HashMap::from([(
+            FAKE_HOST_NAME,
+            LookupResult::new(DnsSource::Static, vec![ip_addr!(v4, "192.0.2.1")], vec![]),
+        )]));
+        let fake_transport_connector =
+            ConnectFn(move |(), _, _| std::future::ready(Ok::<_, WebSocketConnectError>(())));
+
+        let state = ConnectState::new_with_transport_connector(
+            SUGGESTED_CONNECT_CONFIG,
+            fake_transport_connector,
+        );
+        let network_change_event = ObservableEvent::new();
+        let single_flight = ConnectSingleFlight::default();
+
+        let connect = |route: &UnresolvedWebsocketServiceRoute| {
+            ConnectionResources {
+                connect_state: &state,
+                dns_resolver: &resolver,
+                network_change_event: &network_change_event,
+                confirmation_header_name: None,
+            }
+            .connect_ws_single_flight(
+                &single_flight,
+                vec![route.clone()],
+                ws_connector.clone(),
+                "test".into(),
+            )
+        };
+        let take_attempt_starts =
+            || std::mem::take(&mut *attempt_starts.lock().expect("not poisoned"));
+        let [route, other_route] = &*FAKE_WEBSOCKET_ROUTES;
+
+        // Both callers end up connected, but the second doesn't start its
+        // attempt until the first is done.
+        let (first, second) = tokio::join!(connect(route), connect(route));
+        let _ = first.expect("succeeded");
+        let _ = second.expect("succeeded");
+        assert_matches!(&*take_attempt_starts(), [first_start, second_start] => {
+            assert_eq!(*second_start - *first_start, CONNECT_DELAY);
+        });
+
+        // Connects over different routes don't wait for each other.
+        let (first, second) = tokio::join!(connect(route), connect(other_route));
+        let _ = first.expect("succeeded");
+        let _ = second.expect("succeeded");
+        assert_matches!(&*take_attempt_starts(), [first_start, second_start] => {
+            assert_eq!(first_start, second_start);
+        });
+
+        // If the attempt in progress fails, the waiting caller gets its error
+        // without trying again.
+        fail.store(true, std::sync::atomic::Ordering::Relaxed);
+        let (first, second) = tokio::join!(connect(route), connect(route));
+        let first = first.expect_err("failed");
+        let second = second.expect_err("failed");
+        assert!(Arc::ptr_eq(&first, &second));
+        assert_eq!(take_attempt_starts().len(), 1);
+    }
+
+    #[test]
+    fn audit_websocket_headers_redacts_auth() {
+        let [mut first_route, second_route] = (*FAKE_WEBSOCKET_ROUTES).clone();
+        first_route.fragment.headers.insert(
+            http::header::USER_AGENT,
+            http::HeaderValue::from_static("test-agent"),
+        );
+        let auth = Auth {
+            username: "user".to_owned(),
+            password: "secret".to_owned(),
+        };
+
+        let audit = lock_connect_state(&ConnectState::new(SUGGESTED_CONNECT_CONFIG))
+            .audit_websocket_headers(vec![first_route, second_route], Some(&auth));
+
+        let audit = audit
+            .iter()
+            .map(|(description, headers)| {
+                let headers = headers
+                    .iter()
+                    .map(|(name, value)| format!("{name}: {}", value.to_str().expect("ASCII")))
+                    .collect_vec();
+                (description.to_string(), headers)
+            })
+            .collect_vec();
+        assert_eq!(
+            audit,
+            [
+                (
+                    "REDACTED:1234 (direct)".to_owned(),
+                    vec![
+                        "user-agent: test-agent",
+                        "authorization: [REDACTED]",
+                        "host: first-host",
+                        "connection: Upgrade",
+                        "upgrade: websocket",
+                        "sec-websocket-version: 13",
+                        "sec-websocket-key: [REDACTED]",
+                    ]
+                ),
+                (
+                    "REDACTED:1234 fronted by proxyf".to_owned(),
+                    vec![
+                        "authorization: [REDACTED]",
+                        "host: second-host",
+                        "connection: Upgrade",
+                        "upgrade: websocket",
+                        "sec-websocket-version: 13",
+                        "sec-websocket-key: [REDACTED]",
+                    ]
+                ),
+            ]
+        );
+    }
+
+    #[test_case(None => "first-host"; "no hint")]
+    #[test_case(Some(RouteType::ProxyF) => "second-host"; "hinted front")]
+    #[test_case(Some(RouteType::Direct) => "first-host"; "hinted direct")]
+    #[test_case(Some(RouteType::ProxyG) => "first-host"; "hint not in provider")]
+    #[tokio::test(start_paused = true)]
+    async fn connect_ws_tries_hinted_route_first(hint: Option<RouteType>) -> String {
+        let routes = (*FAKE_WEBSOCKET_ROUTES).clone();
+
+        // Every route works, so whichever is tried first wins.
+        let ws_connector =
+            ConnectFn(|(), route, _log_tag| std::future::ready(Ok::<_, tungstenite::Error>(route)));
+        let resolver = DnsResolver::new_from_static_map(HashMap::from([(
+            FAKE_HOST_NAME,
+            LookupResult::new(DnsSource::Static, vec![ip_addr!(v4, "192.0.2.1")], vec![]),
+        )]));
+
+        let fake_transport_connector =
+            ConnectFn(move |(), _, _| std::future::ready(Ok::<_, WebSocketConnectError>(())));
+
+        let state = ConnectState::new_with_transport_connector(
+            SUGGESTED_CONNECT_CONFIG,
+            fake_transport_connector,
+        );
+        // The outcome history is empty, as it would be right after a restart.
+        lock_connect_state(&state).set_preferred_route_hint(hint);
+
+        let connection_resources = ConnectionResources {
+            connect_state: &state,
+            dns_resolver: &resolver,
+            network_change_event: &ObservableEvent::new(),
+            confirmation_header_name: None,
+        };
+
+        let ((_ws, http), info) = connection_resources
+            .connect_ws(routes.to_vec(), ws_connector, "test".into(), None)
+            .await
+            .expect("succeeded");
+
+        if let Some(hint) = hint.filter(|hint| *hint != RouteType::ProxyG) {
+            assert_eq!(info.unresolved().route_type(), Some(hint));
+        }
+        http.host_header.to_string()
+    }
+
+    #[test_case(RouteOrdering::AsProvided => "first-host"; "as provided")]
+    #[test_case(RouteOrdering::HighestBandwidthFirst => "second-host"; "highest bandwidth first")]
+    #[tokio::test(start_paused = true)]
+    async fn connect_ws_orders_routes_by_bandwidth(ordering: RouteOrdering) -> String {
+        let routes = (*FAKE_WEBSOCKET_ROUTES).clone();
+
+        // Every route works immediately, so whichever is tried first wins.
+        let ws_connector =
+            ConnectFn(|(), route, _log_tag| std::future::ready(Ok::<_, tungstenite::Error>(route)));
+        let resolver = DnsResolver::new_from_static_map(HashMap::from([(
+            FAKE_HOST_NAME,
+            LookupResult::new(DnsSource::Static, vec![ip_addr!(v4, "192.0.2.1")], vec![]),
+        )]));
+
+        let fake_transport_connector =
+            ConnectFn(move |(), _, _| std::future::ready(Ok::<_, WebSocketConnectError>(())));
+
+        let state = ConnectState::new_with_transport_connector(
+            SUGGESTED_CONNECT_CONFIG,
+            fake_transport_connector,
+        );
+        {
+            let mut state = lock_connect_state(&state);
+            state.set_route_ordering(ordering);
+            let [first, second] = &routes;
+            state.record_bandwidth(
+                &first.describe_for_log(),
+                1_000_000,
+                Duration::from_secs(10),
+            );
+            state.record_bandwidth(
+                &second.describe_for_log(),
+                1_000_000,
+                Duration::from_secs(1),
+            );
+        }
+
+        let connection_resources = ConnectionResources {
+            connect_state: &state,
+            dns_resolver: &resolver,
+            network_change_event: &ObservableEvent::new(),
+            confirmation_header_name: None,
+        };
+
+        let ((_ws, http), _info) = connection_resources
+            .connect_ws(routes.to_vec(), ws_connector, "test".into(), None)
+            .await
+            .expect("succeeded");
+        http.host_header.to_string()
+    }
+
+    #[test]
+    fn route_bandwidth_favors_recent_measurements() {
+        let [first, second] = &*FAKE_WEBSOCKET_ROUTES;
+        let (first, second) = (first.describe_for_log(), second.describe_for_log());
+
+        let mut bandwidth = RouteBandwidth::default();
+        bandwidth.record(&first, 100.0);
+        bandwidth.record(&first, 300.0);
+        assert_eq!(bandwidth.estimate(&first), Some(200.0));
+        assert_eq!(bandwidth.estimate(&second), None);
+    }
+
+    #[test_case(false, 2; "all routes")]
+    #[test_case(true, 1; "deduplicated")]
+    #[tokio::test(start_paused = true)]
+    async fn connect_ws_dedupe_targets(dedupe_targets: bool, expected_transport_connects: usize) {
+        // Both routes resolve to the same address and port, but differ in
+        // their HTTP fragments.
+        let routes = (*FAKE_WEBSOCKET_ROUTES).clone();
+
+        let ws_connector = ConnectFn(|(), _route, _log_tag| {
+            std::future::ready(Err::<(), _>(tungstenite::Error::ConnectionClosed))
+        });
+        let resolver = DnsResolver::new_from_static_map(HashMap::from([(
+            FAKE_HOST_NAME,
+            LookupResult::new(DnsSource::Static, vec![ip_addr!(v4, "192.0.2.1")], vec![]),
+        )]));
+
+        let transport_connects = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counting_transport_connector = ConnectFn({
+            let transport_connects = Arc::clone(&transport_connects);
+            move |(), _, _| {
+                transport_connects.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                std::future::ready(Ok::<_, WebSocketConnectError>(()))
+            }
+        });
+
+        let state = ConnectState {
+            connect_timeout: Duration::MAX,
+            network_interface_poll_interval: Duration::MAX,
+            post_route_change_connect_timeout: Duration::MAX,
+            max_fronting_domains: None,
+            preconnect_timeout: None,
+            route_resolver: RouteResolver {
+                dedupe_targets,
+                ..Default::default()
+            },
+            attempts_record: ConnectionOutcomes::new(SUGGESTED_CONNECT_PARAMS),
+            make_transport_connector: counting_transport_connector,
+            route_provider_context: Default::default(),
+            connect_observer: None,
+            connect_progress: None,
+            local_ip_source: Default::default(),
+            global_throttle: None,
+            report_resolved_target: false,
+            report_connect_timing: false,
+            phase_timeouts: Default::default(),
+            preferred_route_hint: None,
+            unstable_network: None,
+            interface_change_aborts: Default::default(),
+            connectivity_stats: Default::default(),
+            attestation_permits: None,
+            route_delay_override: None,
+            route_ordering: Default::default(),
+            route_bandwidth: Default::default(),
+            connect_attempt_events: None,
+        }
+        .into();
+
+        let connection_resources = ConnectionResources {
+            connect_state: &state,
+            dns_resolver: &resolver,
+            network_change_event: &ObservableEvent::new(),
+            confirmation_header_name: None,
+        };
+
+        let result = connection_resources
+            .connect_ws(routes.to_vec(), ws_connector, "test".into(), None)
+            .await;
+
+        assert_matches!(
+            result,
+            Err(TimeoutOr::Other(ConnectError::AllAttemptsFailed))
+        );
+        assert_eq!(
+            transport_connects.load(std::sync::atomic::Ordering::SeqCst),
+            expected_transport_connects
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn connect_ws_reports_resolved_target() {
+        let [_, route] = (*FAKE_WEBSOCKET_ROUTES).clone();
+
+        let ws_connector =
+            ConnectFn(|(), route, _log_tag| std::future::ready(Ok::<_, tungstenite::Error>(route)));
+        let resolver = DnsResolver::new_from_static_map(HashMap::from([(
+            FAKE_HOST_NAME,
+            LookupResult::new(DnsSource::Static, vec![ip_addr!(v4, "192.0.2.1")], vec![]),
+        )]));
+
+        let fake_transport_connector =
+            ConnectFn(move |(), _, _| std::future::ready(Ok::<_, WebSocketConnectError>(())));
+
+        let state = ConnectState {
+            connect_timeout: Duration::MAX,
+            network_interface_poll_interval: Duration::MAX,
+            post_route_change_connect_timeout: Duration::MAX,
+            max_fronting_domains: None,
+            preconnect_timeout: None,
+            route_resolver: RouteResolver::default(),
+            attempts_record: ConnectionOutcomes::new(SUGGESTED_CONNECT_PARAMS),
+            make_transport_connector: fake_transport_connector,
+            route_provider_context: Default::default(),
+            connect_observer: None,
+            connect_progress: None,
+            local_ip_source: Default::default(),
+            global_throttle: None,
+            report_resolved_target: true,
+            report_connect_timing: false,
+            phase_timeouts: Default::default(),
+            preferred_route_hint: None,
+            unstable_network: None,
+            interface_change_aborts: Default::default(),
+            connectivity_stats: Default::default(),
+            attestation_permits: None,
+            route_delay_override: None,
+            route_ordering: Default::default(),
+            route_bandwidth: Default::default(),
+            connect_attempt_events: None,
+        }
+        .into();
+
+        let connection_resources = ConnectionResources {
+            connect_state: &state,
+            dns_resolver: &resolver,
+            network_change_event: &ObservableEvent::new(),
+            confirmation_header_name: None,
+        };
+
+        let (_connection, info) = connection_resources
+            .connect_ws(vec![route], ws_connector, "test".into(), None)
+            .await
+            .expect("succeeded");
+
+        let resolved_target = info.resolved_target().expect("requested");
+        assert_eq!(
+            resolved_target,
+            &ResolvedTarget::Direct(SocketAddr::new(IpAddr::V4(ip_addr!(v4, "192.0.2.1")), 1234))
+        );
+        assert_eq!(resolved_target.to_string(), "V4:1234 (direct)");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn connect_ws_reports_connect_timing() {
+        const TCP_DELAY: Duration = Duration::from_millis(100);
+        const TLS_DELAY: Duration = Duration::from_millis(200);
+        const UPGRADE_DELAY: Duration = Duration::from_millis(50);
+
+        let [_, route] = (*FAKE_WEBSOCKET_ROUTES).clone();
+
+        let ws_connector = ConnectFn(|(), _route, _log_tag| async {
+            tokio::time::sleep(UPGRADE_DELAY).await;
+            Ok::<_, tungstenite::Error>(())
+        });
+        let resolver = DnsResolver::new_from_static_map(HashMap::from([(
+            FAKE_HOST_NAME,
+            LookupResult::new(DnsSource::Static, vec![ip_addr!(v4, "192.0.2.1")], vec![]),
+        )]));
+
+        // Reports its phases the same way the default transport connector does.
+        let sleep_for = |delay| {
+            ConnectFn(move |(), (), _log_tag| async move {
+                tokio::time::sleep(delay).await;
+                Ok::<_, WebSocketConnectError>(())
+            })
+        };
+        let fake_transport_connector = ConnectFn(move |(), _route, log_tag: Arc<str>| async move {
+            PhaseTimingConnector::new(sleep_for(TCP_DELAY), ConnectPhase::Tcp)
+                .connect_over((), (), log_tag.clone())
+                .await?;
+            PhaseTimingConnector::new(sleep_for(TLS_DELAY), ConnectPhase::Tls)
+                .connect_over((), (), log_tag)
+                .await
+        });
+
+        let state = ConnectState {
+            connect_timeout: Duration::MAX,
+            network_interface_poll_interval: Duration::MAX,
+            post_route_change_connect_timeout: Duration::MAX,
+            max_fronting_domains: None,
+            preconnect_timeout: None,
+            route_resolver: RouteResolver::default(),
+            attempts_record: ConnectionOutcomes::new(SUGGESTED_CONNECT_PARAMS),
+            make_transport_connector: fake_transport_connector,
+            route_provider_context: Default::default(),
+            connect_observer: None,
+            connect_progress: None,
+            local_ip_source: Default::default(),
+            global_throttle: None,
+            report_resolved_target: false,
+            report_connect_timing: true,
+            phase_timeouts: Default::default(),
+            preferred_route_hint: None,
+            unstable_network: None,
+            interface_change_aborts: Default::default(),
+            connectivity_stats: Default::default(),
+            attestation_permits: None,
+            route_delay_override: None,
+            route_ordering: Default::default(),
+            route_bandwidth: Default::default(),
+            connect_attempt_events: None,
+        }
+        .into();
+
+        let connection_resources = ConnectionResources {
+            connect_state: &state,
+            dns_resolver: &resolver,
+            network_change_event: &ObservableEvent::new(),
+            confirmation_header_name: None,
+        };
+
+        let (_connection, info) = connection_resources
+            .connect_ws(vec![route], ws_connector, "test".into(), None)
+            .await
+            .expect("succeeded");
+
+        let ConnectTiming {
+            dns,
+            tcp,
+            tls,
+            upgrade,
+            total,
+            preconnected,
+        } = *info.connect_timing().expect("requested");
+        assert_eq!(dns, Duration::ZERO, "static lookups are instant");
+        assert_eq!(tcp, TCP_DELAY);
+        assert_eq!(tls, TLS_DELAY);
+        assert_eq!(upgrade, UPGRADE_DELAY);
+        assert!(!preconnected);
+        // Only one route was tried, so there was no time spent waiting.
+        assert_eq!(total, dns + tcp + tls + upgrade);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn connect_ws_reports_progress_stages() {
+        let [_, route] = (*FAKE_WEBSOCKET_ROUTES).clone();
+
+        let ws_connector = ConnectFn(|(), _route, _log_tag| async {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            Ok::<_, tungstenite::Error>(())
+        });
+        let resolver = DnsResolver::new_from_static_map(HashMap::from([(
+            FAKE_HOST_NAME,
+            LookupResult::new(DnsSource::Static, vec![ip_addr!(v4, "192.0.2.1")], vec![]),
+        )]));
+
+        let fake_transport_connector = ConnectFn(move |(), _, _| async {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            Ok::<_, WebSocketConnectError>(())
+        });
+
+        let stages = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let state = ConnectState {
+            connect_timeout: Duration::MAX,
+            network_interface_poll_interval: Duration::MAX,
+            post_route_change_connect_timeout: Duration::MAX,
+            max_fronting_domains: None,
+            preconnect_timeout: None,
+            route_resolver: RouteResolver::default(),
+            attempts_record: ConnectionOutcomes::new(SUGGESTED_CONNECT_PARAMS),
+            make_transport_connector: fake_transport_connector,
+            route_provider_context: Default::default(),
+            connect_observer: None,
+            connect_progress: Some(Arc::new({
+                let stages = stages.clone();
+                move |stage| stages.lock().expect("not poisoned").push(stage)
+            })),
+            local_ip_source: Default::default(),
+            global_throttle: None,
+            report_resolved_target: false,
+            report_connect_timing: false,
+            phase_timeouts: Default::default(),
+            preferred_route_hint: None,
+            unstable_network: None,
+            interface_change_aborts: Default::default(),
+            connectivity_stats: Default::default(),
+            attestation_permits: None,
+            route_delay_override: None,
+            route_ordering: Default::default(),
+            route_bandwidth: Default::default(),
+            connect_attempt_events: None,
+        }
+        .into();
+
+        let connection_resources = ConnectionResources {
+            connect_state: &state,
+            dns_resolver: &resolver,
+            network_change_event: &ObservableEvent::new(),
+            confirmation_header_name: None,
+        };
+
+        let _connection = connection_resources
+            .connect_ws(vec![route], ws_connector, "test".into(), None)
+            .await
+            .expect("succeeded");
+
+        assert_eq!(
+            *stages.lock().expect("not poisoned"),
+            [
+                ConnectStage::Resolving,
+                ConnectStage::ConnectingTransport,
+                ConnectStage::Upgrading,
+                ConnectStage::Connected,
+            ]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn connectivity_stats_count_fronted_successes() {
+        let [_, fronted_route] = (*FAKE_WEBSOCKET_ROUTES).clone();
+        assert!(fronted_route.describe_for_log().front().is_some());
+
+        let resolver = DnsResolver::new_from_static_map(HashMap::from([(
+            FAKE_HOST_NAME,
+            LookupResult::new(DnsSource::Static, vec![ip_addr!(v4, "192.0.2.1")], vec![]),
+        )]));
+        let network_change_event = ObservableEvent::new();
+
+        let fake_transport_connector =
+            ConnectFn(move |(), _, _| std::future::ready(Ok::<_, WebSocketConnectError>(())));
+
+        let state = ConnectState::new_with_transport_connector(
+            SUGGESTED_CONNECT_CONFIG,
+            fake_transport_connector,
+        );
+
+        const CONNECT_COUNT: u32 = 3;
+        for _ in 0..CONNECT_COUNT {
+            let connection_resources = ConnectionResources {
+                connect_state: &state,
+                dns_resolver: &resolver,
+                network_change_event: &network_change_event,
+                confirmation_header_name: None,
+            };
+            let ws_connector = ConnectFn(|(), _route, _log_tag| {
+                std::future::ready(Ok::<_, tungstenite::Error>(()))
+            });
+            let _connection = connection_resources
+                .connect_ws(
+                    vec![fronted_route.clone()],
+                    ws_connector,
+                    "test".into(),
+                    None,
+                )
+                .await
+                .expect("succeeded");
+        }
+
+        assert_eq!(
+            lock_connect_state(&state).connectivity_stats(),
+            ConnectivityStats {
+                direct_successes: 0,
+                fronted_successes: CONNECT_COUNT,
+                proxied_successes: 0,
+            }
+        );
+
+        lock_connect_state(&state).network_changed(Instant::now());
+        assert_eq!(
+            lock_connect_state(&state).connectivity_stats(),
+            ConnectivityStats::default()
+        );
+    }
+
+    #[test]
+    fn resolved_target_for_proxied_route_is_the_proxy() {
+        let route = TlsRoute {
+            fragment: FAKE_TRANSPORT_ROUTE.fragment.clone(),
+            inner: DirectOrProxyRoute::Proxy(ConnectionProxyRoute::Tcp {
+                proxy: TcpRoute {
+                    address: IpAddr::V4(ip_addr!(v4, "192.0.2.99")),
+                    port: nonzero!(8080u16),
+                },
+            }),
+        };
+        assert_eq!(
+            ResolvedTarget::from_transport_route(&route),
+            ResolvedTarget::Proxy(SocketAddr::new(
+                IpAddr::V4(ip_addr!(v4, "192.0.2.99")),
+                8080
+            ))
+        );
+    }
+
+    #[test_case(true, "outcome=connected route=\"REDACTED:1234 fronted by proxyf\""; "success")]
+    #[test_case(false, "outcome=failed error=\"all connect attempts failed\""; "failure")]
+    #[tokio::test(start_paused = true)]
+    async fn connect_ws_diagnostics_summary(should_succeed: bool, expected_outcome: &str) {
+        let [_, route] = (*FAKE_WEBSOCKET_ROUTES).clone();
+
+        let ws_connector = ConnectFn(move |(), route, _log_tag| {
+            std::future::ready(if should_succeed {
+                Ok(route)
+            } else {
+                Err(tungstenite::Error::ConnectionClosed)
+            })
+        });
+        let resolver = DnsResolver::new_from_static_map(HashMap::from([(
+            FAKE_HOST_NAME,
+            LookupResult::new(DnsSource::Static, vec![ip_addr!(v4, "192.0.2.1")], vec![]),
+        )]));
+
+        let fake_transport_connector =
+            ConnectFn(move |(), _, _| std::future::ready(Ok::<_, WebSocketConnectError>(())));
+
+        let state = ConnectState {
+            connect_timeout: Duration::MAX,
+            network_interface_poll_interval: Duration::MAX,
+            post_route_change_connect_timeout: Duration::MAX,
+            max_fronting_domains: None,
+            preconnect_timeout: None,
+            route_resolver: RouteResolver::default(),
+            attempts_record: ConnectionOutcomes::new(SUGGESTED_CONNECT_PARAMS),
+            make_transport_connector: fake_transport_connector,
+            route_provider_context: Default::default(),
+            connect_observer: None,
+            connect_progress: None,
+            local_ip_source: Default::default(),
+            global_throttle: None,
+            report_resolved_target: false,
+            report_connect_timing: false,
+            phase_timeouts: Default::default(),
+            preferred_route_hint: None,
+            unstable_network: None,
+            interface_change_aborts: Default::default(),
+            connectivity_stats: Default::default(),
+            attestation_permits: None,
+            route_delay_override: None,
+            route_ordering: Default::default(),
+            route_bandwidth: Default::default(),
+            connect_attempt_events: None,
+        }
+        .into();
+
+        let connection_resources = ConnectionResources {
+            connect_state: &state,
+            dns_resolver: &resolver,
+            network_change_event: &ObservableEvent::new(),
+            confirmation_header_name: None,
+        };
+
+        let (result, diagnostics) = connection_resources
+            .connect_ws_with_diagnostics(
+                vec![route],
+                ws_connector,
+                "test".into(),
+                CorrelationContext::default(),
+            )
+            .await;
+        assert_eq!(result.is_ok(), should_succeed);
+
+        assert_eq!(diagnostics.dns_sources, [DnsSource::Static]);
+        assert!(diagnostics.elapsed < Duration::from_secs(1));
+        assert_eq!(
+            diagnostics.to_string(),
+            format!(
+                "routes=1 {expected_outcome} elapsed={:.3?} dns=static",
+                diagnostics.elapsed
+            )
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn shared_tls_permits_are_shared_between_states() {
+        use futures_util::FutureExt as _;
+
+        let permits = SharedConnectPermits::new(1);
+        let states = [(); 2].map(|()| {
+            let state = ConnectState::new(SUGGESTED_CONNECT_CONFIG);
+            lock_connect_state(&state)
+                .set_shared_tls_permits(Some((permits.clone(), ConnectPriority::Normal)));
+            state
+        });
+
+        // Handshakes complete immediately; the resulting connection holds on
+        // to its permit until it is dropped.
+        let [first_connector, second_connector] = states.each_ref().map(|state| {
+            lock_connect_state(state)
+                .make_transport_connector
+                .throttle_tls_handshakes(ConnectFn(|(), route: &'static str, _log_tag| {
+                    std::future::ready(Ok::<_, std::convert::Infallible>(route))
+                }))
+        });
+
+        let first = first_connector
+            .connect("first", "first".into())
+            .await
+            .expect("permit available");
+
+        // The second state's handshake has to wait for the first one's permit,
+        // no matter how long that takes.
+        let mut second = std::pin::pin!(second_connector.connect("second", "second".into()));
+        assert_matches!((&mut second).now_or_never(), None);
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        assert_matches!((&mut second).now_or_never(), None);
+
+        drop(first);
+        let second = second
+            .now_or_never()
+            .expect("permit returned to the pool")
+            .expect("success");
+        assert_eq!(second.as_ref(), &"second");
+    }
+
+    #[test_case(WhenThrottled::Wait; "wait")]
+    #[test_case(WhenThrottled::FailFast; "fail fast")]
+    #[tokio::test(start_paused = true)]
+    async fn global_throttle_is_shared_between_states(when_empty: WhenThrottled) {
+        const REFILL_INTERVAL: Duration = Duration::from_secs(10);
+
+        let [_, route] = (*FAKE_WEBSOCKET_ROUTES).clone();
+        let resolver = DnsResolver::new_from_static_map(HashMap::from([(
+            FAKE_HOST_NAME,
+            LookupResult::new(DnsSource::Static, vec![ip_addr!(v4, "192.0.2.1")], vec![]),
+        )]));
+        let network_change_event = ObservableEvent::new();
+
+        let throttle = GlobalConnectThrottle::new(nonzero!(2u32), REFILL_INTERVAL, when_empty);
+        let make_state = || -> std::sync::Mutex<_> {
+            ConnectState {
+                connect_timeout: Duration::MAX,
+                network_interface_poll_interval: Duration::MAX,
+                post_route_change_connect_timeout: Duration::MAX,
+                max_fronting_domains: None,
+                preconnect_timeout: None,
+                route_resolver: RouteResolver::default(),
+                attempts_record: ConnectionOutcomes::new(SUGGESTED_CONNECT_PARAMS),
+                make_transport_connector: ConnectFn(|(), _, _| {
+                    std::future::ready(Ok::<_, WebSocketConnectError>(()))
+                }),
+                route_provider_context: Default::default(),
+                connect_observer: None,
+                connect_progress: None,
+                local_ip_source: Default::default(),
+                global_throttle: Some(throttle.clone()),
+                report_resolved_target: false,
+                report_connect_timing: false,
+                phase_timeouts: Default::default(),
+                preferred_route_hint: None,
+                unstable_network: None,
+                interface_change_aborts: Default::default(),
+                connectivity_stats: Default::default(),
+                attestation_permits: None,
+                route_delay_override: None,
+                route_ordering: Default::default(),
+                route_bandwidth: Default::default(),
+                connect_attempt_events: None,
+            }
+            .into()
+        };
+        let states = [make_state(), make_state()];
+
+        let start = Instant::now();
+        let mut finished = vec![];
+        // Alternate between the two states; together they should be limited
+        // to the throttle's capacity and then its refill rate.
+        for state in states.iter().cycle().take(4) {
+            let connection_resources = ConnectionResources {
+                connect_state: state,
+                dns_resolver: &resolver,
+                network_change_event: &network_change_event,
+                confirmation_header_name: None,
+            };
+            let result = connection_resources
+                .connect_ws(
+                    vec![route.clone()],
+                    ConnectFn(|(), route, _log_tag| {
+                        std::future::ready(Ok::<_, tungstenite::Error>(route))
+                    }),
+                    "test".into(),
+                    None,
+                )
+                .await;
+            let outcome = match result {
+                Ok(_) => Ok(()),
+                Err(e) => {
+                    assert_matches!(e, TimeoutOr::Other(ConnectError::Throttled));
+                    Err(())
+                }
+            };
+            finished.push((outcome, start.elapsed()));
+        }
+
+        let expected = match when_empty {
+            WhenThrottled::Wait => [
+                (Ok(()), Duration::ZERO),
+                (Ok(()), Duration::ZERO),
+                (Ok(()), REFILL_INTERVAL),
+                (Ok(()), 2 * REFILL_INTERVAL),
+            ],
+            WhenThrottled::FailFast => [
+                (Ok(()), Duration::ZERO),
+                (Ok(()), Duration::ZERO),
+                (Err(()), Duration::ZERO),
+                (Err(()), Duration::ZERO),
+            ],
+        };
+        assert_eq!(finished, expected);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn connect_ws_timeout() {
+        let ws_connector = crate::infra::ws::Stateless;
+        let resolver = DnsResolver::new_from_static_map(HashMap::from([(
+            FAKE_HOST_NAME,
+            LookupResult::new(DnsSource::Static, vec![ip_addr!(v4, "192.0.2.1")], vec![]),
+        )]));
+        let network_change_event = ObservableEvent::new();
+
+        let always_hangs_connector = ConnectFn(|(), _, _| {
+            std::future::pending::<Result<tokio::io::DuplexStream, WebSocketConnectError>>()
+        });
+
+        const CONNECT_TIMEOUT: Duration = Duration::from_secs(31);
+
+        let state = ConnectState {
+            connect_timeout: CONNECT_TIMEOUT,
+            network_interface_poll_interval: Duration::MAX,
+            post_route_change_connect_timeout: Duration::MAX,
+            max_fronting_domains: None,
+            preconnect_timeout: None,
+            route_resolver: RouteResolver::default(),
+            attempts_record: ConnectionOutcomes::new(SUGGESTED_CONNECT_PARAMS),
+            make_transport_connector: always_hangs_connector,
+            route_provider_context: Default::default(),
+            connect_observer: None,
+            connect_progress: None,
+            local_ip_source: Default::default(),
+            global_throttle: None,
+            report_resolved_target: false,
+            report_connect_timing: false,
+            phase_timeouts: Default::default(),
+            preferred_route_hint: None,
+            unstable_network: None,
+            interface_change_aborts: Default::default(),
+            connectivity_stats: Default::default(),
+            attestation_permits: None,
+            route_delay_override: None,
+            route_ordering: Default::default(),
+            route_bandwidth: Default::default(),
+            connect_attempt_events: None,
+        }
+        .into();
+
+        let [failing_route, succeeding_route] = (*FAKE_WEBSOCKET_ROUTES).clone();
+
+        let connection_resources = ConnectionResources {
+            connect_state: &state,
+            dns_resolver: &resolver,
+            network_change_event: &network_change_event,
+            confirmation_header_name: None,
+        };
+
+        let connect = connection_resources.connect_ws(
+            vec![failing_route.clone(), succeeding_route.clone()],
+            ws_connector,
+            "test".into(),
+            None,
+        );
+
+        let start = Instant::now();
+        let result: Result<_, TimeoutOr<ConnectError<_>>> = connect.await;
+
+        assert_matches!(
+            result,
+            Err(TimeoutOr::Timeout {
+                attempt_duration: CONNECT_TIMEOUT
+            })
+        );
+        assert_eq!(start.elapsed(), CONNECT_TIMEOUT);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn connect_ws_websocket_upgrade_timeout() {
+        const CONNECT_TIMEOUT: Duration = Duration::from_secs(60);
+        const UPGRADE_TIMEOUT: Duration = Duration::from_secs(2);
+
+        let resolver = DnsResolver::new_from_static_map(HashMap::from([(
+            FAKE_HOST_NAME,
+            LookupResult::new(DnsSource::Static, vec![ip_addr!(v4, "192.0.2.1")], vec![]),
+        )]));
+
+        // The transport connects right away, but the server never answers the
+        // upgrade request.
+        let fake_transport_connector =
+            ConnectFn(move |(), _, _| std::future::ready(Ok::<_, WebSocketConnectError>(())));
+        let ws_connector = ConnectFn(|(), _route, _log_tag| {
+            std::future::pending::<Result<(), tungstenite::Error>>()
+        });
+
+        let state = ConnectState {
+            connect_timeout: CONNECT_TIMEOUT,
+            network_interface_poll_interval: Duration::MAX,
+            post_route_change_connect_timeout: Duration::MAX,
+            max_fronting_domains: None,
+            preconnect_timeout: None,
+            route_resolver: RouteResolver::default(),
+            attempts_record: ConnectionOutcomes::new(SUGGESTED_CONNECT_PARAMS),
+            make_transport_connector: fake_transport_connector,
+            route_provider_context: Default::default(),
+            connect_observer: None,
+            connect_progress: None,
+            local_ip_source: Default::default(),
+            global_throttle: None,
+            report_resolved_target: false,
+            report_connect_timing: false,
+            phase_timeouts: PhaseTimeouts {
+                websocket_upgrade: Some(UPGRADE_TIMEOUT),
+                ..Default::default()
+            },
+            preferred_route_hint: None,
+            unstable_network: None,
+            interface_change_aborts: Default::default(),
+            connectivity_stats: Default::default(),
+            attestation_permits: None,
+            route_delay_override: None,
+            route_ordering: Default::default(),
+            route_bandwidth: Default::default(),
+            connect_attempt_events: None,
+        }
+        .into();
+
+        let connection_resources = ConnectionResources {
+            connect_state: &state,
+            dns_resolver: &resolver,
+            network_change_event: &ObservableEvent::new(),
+            confirmation_header_name: None,
+        };
+
+        let [route, _] = (*FAKE_WEBSOCKET_ROUTES).clone();
+
+        let start = Instant::now();
+        let result = connection_resources
+            .connect_ws(vec![route], ws_connector, "test".into(), None)
+            .await;
+
+        // The route fails once the upgrade times out, without waiting for the
+        // overall connect timeout.
+        assert_matches!(
+            result,
+            Err(TimeoutOr::Other(ConnectError::AllAttemptsFailed))
+        );
+        assert_eq!(start.elapsed(), UPGRADE_TIMEOUT);
+    }
+
+    #[test_case(Duration::ZERO; "already passed")]
+    #[test_case(Duration::from_secs(5); "in the future")]
+    #[tokio::test(start_paused = true)]
+    async fn connect_ws_until_deadline(time_left: Duration) {
+        let resolver = DnsResolver::new_from_static_map(HashMap::from([(
+            FAKE_HOST_NAME,
+            LookupResult::new(DnsSource::Static, vec![ip_addr!(v4, "192.0.2.1")], vec![]),
+        )]));
+        let network_change_event = ObservableEvent::new();
+
+        let transport_attempts = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let always_hangs_connector = ConnectFn({
+            let transport_attempts = Arc::clone(&transport_attempts);
+            move |(), _, _| {
+                transport_attempts.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                std::future::pending::<Result<tokio::io::DuplexStream, WebSocketConnectError>>()
+            }
+        });
+
+        let state = ConnectState {
+            // Much longer than the deadline, so that only the deadline matters.
+            connect_timeout: Duration::from_secs(60),
+            network_interface_poll_interval: Duration::MAX,
+            post_route_change_connect_timeout: Duration::MAX,
+            max_fronting_domains: None,
+            preconnect_timeout: None,
+            route_resolver: RouteResolver::default(),
+            attempts_record: ConnectionOutcomes::new(SUGGESTED_CONNECT_PARAMS),
+            make_transport_connector: always_hangs_connector,
+            route_provider_context: Default::default(),
+            connect_observer: None,
+            connect_progress: None,
+            local_ip_source: Default::default(),
+            global_throttle: None,
+            report_resolved_target: false,
+            report_connect_timing: false,
+            phase_timeouts: Default::default(),
+            preferred_route_hint: None,
+            unstable_network: None,
+            interface_change_aborts: Default::default(),
+            connectivity_stats: Default::default(),
+            attestation_permits: None,
+            route_delay_override: None,
+            route_ordering: Default::default(),
+            route_bandwidth: Default::default(),
+            connect_attempt_events: None,
+        }
+        .into();
+
+        let connection_resources = ConnectionResources {
+            connect_state: &state,
+            dns_resolver: &resolver,
+            network_change_event: &network_change_event,
+            confirmation_header_name: None,
+        };
+
+        let deadline = Instant::now() + time_left;
+        let start = Instant::now();
+        let result: Result<_, TimeoutOr<ConnectError<_>>> = connection_resources
+            .connect_ws_until(
+                FAKE_WEBSOCKET_ROUTES.to_vec(),
+                crate::infra::ws::Stateless,
+                "test".into(),
+                deadline,
+            )
+            .await;
+
+        let attempt_duration = assert_matches!(
+            result,
+            Err(TimeoutOr::Timeout { attempt_duration }) => attempt_duration
+        );
+        assert_eq!(start.elapsed(), time_left);
+        assert_eq!(attempt_duration, start.elapsed());
+        assert_eq!(
+            transport_attempts.load(std::sync::atomic::Ordering::SeqCst) != 0,
+            !time_left.is_zero()
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn client_abort_transport_error_is_fatal() {
+        // This is the ClientAbort produced by the underlying connector; see
+        // local_ip_change_aborts_connect for the one produced for a network change.
+
+        let ws_connector = crate::infra::ws::Stateless;
+        let resolver = DnsResolver::new_from_static_map(HashMap::from([(
+            FAKE_HOST_NAME,
+            LookupResult::new(DnsSource::Static, vec![ip_addr!(v4, "192.0.2.1")], vec![]),
+        )]));
+        let network_change_event = ObservableEvent::new();
+
+        let client_abort_connector = ConnectFn(|(), _, _| {
+            std::future::ready(Err::<tokio::io::DuplexStream, _>(
+                TransportConnectError::ClientAbort,
+            ))
+        });
+
+        const CONNECT_TIMEOUT: Duration = Duration::from_secs(31);
+
+        let state = ConnectState {
+            connect_timeout: CONNECT_TIMEOUT,
+            network_interface_poll_interval: Duration::MAX,
+            post_route_change_connect_timeout: Duration::MAX,
+            max_fronting_domains: None,
+            preconnect_timeout: None,
+            route_resolver: RouteResolver::default(),
+            attempts_record: ConnectionOutcomes::new(SUGGESTED_CONNECT_PARAMS),
+            make_transport_connector: client_abort_connector,
+            route_provider_context: Default::default(),
+            connect_observer: None,
+            connect_progress: None,
+            local_ip_source: Default::default(),
+            global_throttle: None,
+            report_resolved_target: false,
+            report_connect_timing: false,
+            phase_timeouts: Default::default(),
+            preferred_route_hint: None,
+            unstable_network: None,
+            interface_change_aborts: Default::default(),
+            connectivity_stats: Default::default(),
+            attestation_permits: None,
+            route_delay_override: None,
+            route_ordering: Default::default(),
+            route_bandwidth: Default::default(),
+            connect_attempt_events: None,
+        }
+        .into();
+
+        let [failing_route, succeeding_route] = (*FAKE_WEBSOCKET_ROUTES).clone();
+
+        let connection_resources = ConnectionResources {
+            connect_state: &state,
+            dns_resolver: &resolver,
+            network_change_event: &network_change_event,
+            confirmation_header_name: None,
+        };
+
+        let connect = connection_resources.connect_ws(
+            vec![failing_route.clone(), succeeding_route.clone()],
+            ws_connector,
+            "test".into(),
+            None,
+        );
+
+        let result: Result<_, TimeoutOr<ConnectError<_>>> = connect.await;
+
+        assert_matches!(
+            result,
+            Err(TimeoutOr::Other(ConnectError::FatalConnect(
+                WebSocketServiceConnectError::Connect(
+                    WebSocketConnectError::Transport(TransportConnectError::ClientAbort),
+                    NotRejectedByServer { .. }
+                )
+            )))
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn local_ip_change_aborts_connect() {
+        const ORIGINAL_LOCAL_IP: IpAddr = ip_addr!("192.168.1.2");
+        const NEW_LOCAL_IP: IpAddr = ip_addr!("10.0.0.2");
+        const CHANGE_DELAY: Duration = Duration::from_secs(2);
+        const POST_CHANGE_TIMEOUT: Duration = Duration::from_secs(1);
+
+        let resolver = DnsResolver::new_from_static_map(HashMap::from([(
+            FAKE_HOST_NAME,
+            LookupResult::new(DnsSource::Static, vec![ip_addr!(v4, "192.0.2.1")], vec![]),
+        )]));
+        let network_change_event = ObservableEvent::new();
+
+        let always_hangs_connector = ConnectFn(|(), _, _| {
+            std::future::pending::<Result<tokio::io::DuplexStream, WebSocketConnectError>>()
+        });
+
+        let local_ip = Arc::new(Mutex::new(ORIGINAL_LOCAL_IP));
+        let local_ip_source = LocalIpSource::Explicit(Arc::new({
+            let local_ip = local_ip.clone();
+            move |_target| *local_ip.lock().expect("not poisoned")
+        }));
+
+        let state = ConnectState {
+            connect_timeout: Duration::from_secs(31),
+            network_interface_poll_interval: Duration::MAX,
+            post_route_change_connect_timeout: POST_CHANGE_TIMEOUT,
+            max_fronting_domains: None,
+            preconnect_timeout: None,
+            route_resolver: RouteResolver::default(),
+            attempts_record: ConnectionOutcomes::new(SUGGESTED_CONNECT_PARAMS),
+            make_transport_connector: always_hangs_connector,
+            route_provider_context: Default::default(),
+            connect_observer: None,
+            connect_progress: None,
+            local_ip_source,
+            global_throttle: None,
+            report_resolved_target: false,
+            report_connect_timing: false,
+            phase_timeouts: Default::default(),
+            preferred_route_hint: None,
+            unstable_network: None,
+            interface_change_aborts: Default::default(),
+            connectivity_stats: Default::default(),
+            attestation_permits: None,
+            route_delay_override: None,
+            route_ordering: Default::default(),
+            route_bandwidth: Default::default(),
+            connect_attempt_events: None,
+        }
+        .into();
+
+        let connection_resources = ConnectionResources {
+            connect_state: &state,
+            dns_resolver: &resolver,
+            network_change_event: &network_change_event,
+            confirmation_header_name: None,
+        };
+
+        let connect = connection_resources.connect_ws(
+            Vec::from((*FAKE_WEBSOCKET_ROUTES).clone()),
+            crate::infra::ws::Stateless,
+            "test".into(),
+            None,
         );
-        let delay_policy = DelayBasedOnTransport(attempts_record);
+        let change_network = async {
+            tokio::time::sleep(CHANGE_DELAY).await;
+            *local_ip.lock().expect("not poisoned") = NEW_LOCAL_IP;
+            network_change_event.fire();
+            std::future::pending::<()>().await
+        };
 
         let start = Instant::now();
-        let connect = crate::infra::route::connect(
-            &route_resolver,
-            delay_policy,
-            route_provider,
-            dns_resolver,
-            connector,
-            (),
-            log_tag.clone(),
-            |error| {
-                match error {
-                    InterfaceChangedOr::InterfaceChanged => {
-                        ControlFlow::Break(TransportConnectError::ClientAbort)
-                    }
-                    InterfaceChangedOr::Other(_) => {
-                        // All normal transport-level errors are considered intermittent; see
-                        // WebSocketServiceConnectError::classify.
-                        ControlFlow::Continue(())
-                    }
-                }
-            },
+        let result = tokio::select! {
+            result = connect => result,
+            () = change_network => unreachable!(),
+        };
+
+        assert_matches!(
+            result,
+            Err(TimeoutOr::Other(ConnectError::FatalConnect(
+                WebSocketServiceConnectError::Connect(
+                    WebSocketConnectError::Transport(TransportConnectError::ClientAbort),
+                    NotRejectedByServer { .. }
+                )
+            )))
         );
+        assert_eq!(start.elapsed(), CHANGE_DELAY + POST_CHANGE_TIMEOUT);
+    }
 
-        let (result, updates) = tokio::time::timeout(connect_timeout, connect)
-            .await
-            .map_err(|_: tokio::time::error::Elapsed| TimeoutOr::Timeout {
-                attempt_duration: connect_timeout,
-            })?;
+    #[tokio::test(start_paused = true)]
+    async fn repeated_local_ip_changes_stop_reconnect_loop() {
+        const CHANGE_DELAY: Duration = Duration::from_secs(2);
+        const POST_CHANGE_TIMEOUT: Duration = Duration::from_secs(1);
+        const WINDOW: Duration = Duration::from_secs(60);
 
-        match &result {
-            Ok(_) => {
-                // We can't log the route here because we don't require DescribeForLog.
-                // That's okay, though, it's not critical.
-                log::info!(
-                    "[{log_tag}] connection succeeded after {:.3?}",
-                    updates.finished_at - start
-                );
+        let resolver = DnsResolver::new_from_static_map(HashMap::from([(
+            FAKE_HOST_NAME,
+            LookupResult::new(DnsSource::Static, vec![ip_addr!(v4, "192.0.2.1")], vec![]),
+        )]));
+        let network_change_event = ObservableEvent::new();
+
+        let always_hangs_connector = ConnectFn(|(), _, _| {
+            std::future::pending::<Result<tokio::io::DuplexStream, WebSocketConnectError>>()
+        });
+
+        let local_ip = Arc::new(Mutex::new(ip_addr!("192.168.1.2")));
+        let local_ip_source = LocalIpSource::Explicit(Arc::new({
+            let local_ip = local_ip.clone();
+            move |_target| *local_ip.lock().expect("not poisoned")
+        }));
+
+        let state = ConnectState {
+            connect_timeout: Duration::from_secs(31),
+            network_interface_poll_interval: Duration::MAX,
+            post_route_change_connect_timeout: POST_CHANGE_TIMEOUT,
+            max_fronting_domains: None,
+            preconnect_timeout: None,
+            route_resolver: RouteResolver::default(),
+            attempts_record: ConnectionOutcomes::new(SUGGESTED_CONNECT_PARAMS),
+            make_transport_connector: always_hangs_connector,
+            route_provider_context: Default::default(),
+            connect_observer: None,
+            connect_progress: None,
+            local_ip_source,
+            global_throttle: None,
+            report_resolved_target: false,
+            report_connect_timing: false,
+            phase_timeouts: Default::default(),
+            preferred_route_hint: None,
+            unstable_network: Some(UnstableNetworkParams {
+                max_aborts: nonzero!(3usize),
+                window: WINDOW,
+            }),
+            interface_change_aborts: Default::default(),
+            connectivity_stats: Default::default(),
+            attestation_permits: None,
+            route_delay_override: None,
+            route_ordering: Default::default(),
+            route_bandwidth: Default::default(),
+            connect_attempt_events: None,
+        }
+        .into();
+
+        // Connects once while the network flaps underneath it.
+        let (state, resolver, network_change_event) = (&state, &resolver, &network_change_event);
+        let local_ip = &local_ip;
+        let connect_during_network_change = || async move {
+            let connect = ConnectionResources {
+                connect_state: state,
+                dns_resolver: resolver,
+                network_change_event,
+                confirmation_header_name: None,
             }
-            Err(e) => log::info!("[{log_tag}] connection failed with {e}"),
+            .connect_ws(
+                Vec::from((*FAKE_WEBSOCKET_ROUTES).clone()),
+                crate::infra::ws::Stateless,
+                "test".into(),
+                None,
+            );
+            let change_network = async {
+                tokio::time::sleep(CHANGE_DELAY).await;
+                {
+                    let mut local_ip = local_ip.lock().expect("not poisoned");
+                    *local_ip = match *local_ip {
+                        IpAddr::V4(ip) => IpAddr::V4(Ipv4Addr::from(u32::from(ip) + 1)),
+                        IpAddr::V6(_) => unreachable!("only IPv4 is used"),
+                    };
+                }
+                network_change_event.fire();
+                std::future::pending::<()>().await
+            };
+            tokio::select! {
+                result = connect => result,
+                () = change_network => unreachable!(),
+            }
+        };
+
+        for _ in 0..2 {
+            assert_matches!(
+                connect_during_network_change().await,
+                Err(TimeoutOr::Other(ConnectError::FatalConnect(
+                    WebSocketServiceConnectError::Connect(
+                        WebSocketConnectError::Transport(TransportConnectError::ClientAbort),
+                        NotRejectedByServer { .. }
+                    )
+                )))
+            );
         }
 
-        // Don't exit yet, we have to save the results!
-        {
-            let mut connect_write = connect_state.lock().expect("not poisoned");
+        // The third abort within the window breaks the loop...
+        assert_matches!(
+            connect_during_network_change().await,
+            Err(TimeoutOr::Other(ConnectError::UnstableNetwork))
+        );
 
-            connect_write.attempts_record.apply_outcome_updates(
-                updates
-                    .outcomes
+        // ...and connects after that fail right away, without trying any routes.
+        let start = Instant::now();
+        assert_matches!(
+            connect_during_network_change().await,
+            Err(TimeoutOr::Other(ConnectError::UnstableNetwork))
+        );
+        assert_eq!(start.elapsed(), Duration::ZERO);
+
+        // Once the aborts are old enough, connecting is allowed again.
+        tokio::time::sleep(WINDOW).await;
+        assert_matches!(
+            connect_during_network_change().await,
+            Err(TimeoutOr::Other(ConnectError::FatalConnect(
+                WebSocketServiceConnectError::Connect(
+                    WebSocketConnectError::Transport(TransportConnectError::ClientAbort),
+                    NotRejectedByServer { .. }
+                )
+            )))
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn preconnect_records_outcomes() {
+        let ws_connector = ConnectFn(|(), route, _log_tag| std::future::ready(Ok(route)));
+        let resolver = DnsResolver::new_from_static_map(HashMap::from([(
+            FAKE_HOST_NAME,
+            LookupResult::new(DnsSource::Static, vec![ip_addr!(v4, "192.0.2.1")], vec![]),
+        )]));
+
+        let attempts_by_host = Mutex::new(HashMap::<Host<_>, u32>::new());
+        let make_transport_connector = PreconnectingFactory::new(
+            ConnectFn(|(), route: TransportRoute, _| {
+                let host = route.fragment.sni;
+                let result = if host == Host::parse_as_ip_or_domain("fail") {
+                    Err(TransportConnectError::TcpConnectionFailed)
+                } else {
+                    Ok(())
+                };
+                *attempts_by_host
+                    .lock()
+                    .expect("no panic")
+                    .entry(host)
+                    .or_default() += 1;
+                std::future::ready(result)
+            }),
+            Duration::from_secs(60),
+        );
+
+        const CONNECT_TIMEOUT: Duration = Duration::from_secs(31);
+
+        let state = ConnectState {
+            connect_timeout: CONNECT_TIMEOUT,
+            network_interface_poll_interval: Duration::MAX,
+            post_route_change_connect_timeout: Duration::MAX,
+            max_fronting_domains: None,
+            preconnect_timeout: None,
+            route_resolver: RouteResolver::default(),
+            attempts_record: ConnectionOutcomes::new(SUGGESTED_CONNECT_PARAMS),
+            make_transport_connector,
+            route_provider_context: Default::default(),
+            connect_observer: None,
+            connect_progress: None,
+            local_ip_source: Default::default(),
+            global_throttle: None,
+            report_resolved_target: false,
+            report_connect_timing: false,
+            phase_timeouts: Default::default(),
+            preferred_route_hint: None,
+            unstable_network: None,
+            interface_change_aborts: Default::default(),
+            connectivity_stats: Default::default(),
+            attestation_permits: None,
+            route_delay_override: None,
+            route_ordering: Default::default(),
+            route_bandwidth: Default::default(),
+            connect_attempt_events: None,
+        }
+        .into();
+
+        let good_transport_route = FAKE_TRANSPORT_ROUTE.clone();
+        let mut bad_transport_route = good_transport_route.clone();
+        bad_transport_route.fragment.sni = Host::parse_as_ip_or_domain("fail");
+
+        let connection_resources = ConnectionResources {
+            connect_state: &state,
+            dns_resolver: &resolver,
+            network_change_event: &ObservableEvent::new(),
+            confirmation_header_name: None,
+        };
+
+        connection_resources
+            .preconnect_and_save(
+                vec![bad_transport_route.clone(), good_transport_route.clone()],
+                "preconnect".into(),
+                None,
+            )
+            .await
+            .expect("success");
+
+        assert_eq!(
+            *attempts_by_host.lock().expect("not poisoned"),
+            HashMap::from_iter([
+                (Host::parse_as_ip_or_domain("fake-sni"), 1),
+                (Host::parse_as_ip_or_domain("fail"), 1),
+            ])
+        );
+
+        let connection_resources = ConnectionResources {
+            connect_state: &state,
+            dns_resolver: &resolver,
+            network_change_event: &ObservableEvent::new(),
+            confirmation_header_name: None,
+        };
+
+        _ = connection_resources
+            .connect_ws(
+                [bad_transport_route.clone(), good_transport_route.clone()]
                     .into_iter()
-                    .map(|(route, outcome)| (route.into_transport_part(), outcome)),
-                updates.finished_at,
-            );
+                    .map(|route| WebSocketRoute {
+                        fragment: WebSocketRouteFragment {
+                            ws_config: Default::default(),
+                            endpoint: PathAndQuery::from_static("/"),
+                            headers: HeaderMap::new(),
+                        },
+                        inner: HttpsTlsRoute {
+                            fragment: HttpRouteFragment {
+                                host_header: "host".into(),
+                                path_prefix: "".into(),
+                                front_name: None,
+                            },
+                            inner: route,
+                        },
+                    })
+                    .collect_vec(),
+                ws_connector,
+                "test".into(),
+                None,
+            )
+            .await
+            .expect("succeeded");
 
-            let (
-                UsePreconnect {
-                    inner: route,
-                    should: _,
-                },
-                connection,
-            ) = result?;
+        // Even though the bad transport route was listed first, we should have tried the good
+        // transport route first due to the record of the preconnect attempts.
+        assert_eq!(
+            *attempts_by_host.lock().expect("not poisoned"),
+            HashMap::from_iter([
+                (Host::parse_as_ip_or_domain("fake-sni"), 2),
+                (Host::parse_as_ip_or_domain("fail"), 1),
+            ])
+        );
+    }
+
+    #[test_case(Some(Duration::from_secs(5)) => Duration::from_secs(5); "separate timeout")]
+    #[test_case(None => Duration::from_secs(31); "falls back to connect_timeout")]
+    #[tokio::test(start_paused = true)]
+    async fn preconnect_timeout(preconnect_timeout: Option<Duration>) -> Duration {
+        let resolver = DnsResolver::new_from_static_map(HashMap::from([(
+            FAKE_HOST_NAME,
+            LookupResult::new(DnsSource::Static, vec![ip_addr!(v4, "192.0.2.1")], vec![]),
+        )]));
+
+        // Never finishes connecting.
+        let make_transport_connector = PreconnectingFactory::new(
+            ConnectFn(|(), _route: TransportRoute, _| {
+                std::future::pending::<Result<(), TransportConnectError>>()
+            }),
+            Duration::from_secs(60),
+        );
+
+        let state = ConnectState::new_with_transport_connector(
+            Config {
+                connect_timeout: Duration::from_secs(31),
+                preconnect_timeout,
+                ..SUGGESTED_CONNECT_CONFIG
+            },
+            make_transport_connector,
+        );
 
-            connect_write.make_transport_connector.save_preconnected(
-                route,
-                connection,
-                updates.finished_at,
-            );
+        let start = Instant::now();
+        let result = ConnectionResources {
+            connect_state: &state,
+            dns_resolver: &resolver,
+            network_change_event: &ObservableEvent::new(),
+            confirmation_header_name: None,
         }
+        .preconnect_and_save(
+            vec![FAKE_TRANSPORT_ROUTE.clone()],
+            "preconnect".into(),
+            None,
+        )
+        .await;
 
-        Ok(())
+        let attempt_duration = assert_matches!(
+            result,
+            Err(PreconnectError::Timeout { attempt_duration }) => attempt_duration
+        );
+        assert_eq!(start.elapsed(), attempt_duration);
+        attempt_duration
     }
-}
 
-#[derive(Debug, Default, Clone)]
-struct RouteProviderContextImpl(OsRng);
+    #[tokio::test(start_paused = true)]
+    async fn preconnect_reports_last_transport_error() {
+        let resolver = DnsResolver::new_from_static_map(HashMap::from([(
+            FAKE_HOST_NAME,
+            LookupResult::new(DnsSource::Static, vec![ip_addr!(v4, "192.0.2.1")], vec![]),
+        )]));
 
-impl RouteProviderContext for RouteProviderContextImpl {
-    fn random_usize(&self) -> usize {
-        // OsRng is zero-sized, so we're not losing random values by copying it.
-        let mut owned_rng: OsRng = self.0;
-        assert_eq_size_val!(owned_rng, ());
-        owned_rng.gen()
+        let make_transport_connector = PreconnectingFactory::new(
+            ConnectFn(|(), _route: TransportRoute, _| {
+                std::future::ready(Err::<(), _>(TransportConnectError::CertificateUntrusted))
+            }),
+            Duration::from_secs(60),
+        );
+        let state = ConnectState::new_with_transport_connector(
+            SUGGESTED_CONNECT_CONFIG,
+            make_transport_connector,
+        );
+
+        let start = Instant::now();
+        let result = ConnectionResources {
+            connect_state: &state,
+            dns_resolver: &resolver,
+            network_change_event: &ObservableEvent::new(),
+            confirmation_header_name: None,
+        }
+        .preconnect_and_save(
+            vec![FAKE_TRANSPORT_ROUTE.clone()],
+            "preconnect".into(),
+            None,
+        )
+        .await;
+
+        // The failure isn't mistaken for a timeout, and says why the route
+        // failed.
+        assert_matches!(
+            result,
+            Err(PreconnectError::AllAttemptsFailed {
+                last_error: Some(TransportConnectError::CertificateUntrusted)
+            })
+        );
+        assert!(start.elapsed() < SUGGESTED_CONNECT_CONFIG.connect_timeout);
     }
-}
 
-/// Convenience alias for using `PreconnectingConnector`s with [`ConnectState`].
-pub type PreconnectingFactory<Inner = DefaultConnectorFactory> =
-    libsignal_net_infra::route::PreconnectingFactory<TransportRoute, Inner>;
+    #[tokio::test(start_paused = true)]
+    async fn preconnect_reports_route_plan() {
+        let resolver = DnsResolver::new_from_static_map(HashMap::from([
+            (
+                "failing-host",
+                LookupResult::new(DnsSource::Static, vec![ip_addr!(v4, "192.0.2.1")], vec![]),
+            ),
+            (
+                "working-host",
+                LookupResult::new(DnsSource::Static, vec![ip_addr!(v4, "192.0.2.2")], vec![]),
+            ),
+        ]));
 
-#[cfg(test)]
-mod test {
-    use std::collections::HashMap;
-    use std::sync::{Arc, LazyLock, Mutex};
-    use std::time::Duration;
+        let make_transport_connector = PreconnectingFactory::new(
+            ConnectFn(|(), route: TransportRoute, _| {
+                let result = match route.inner {
+                    DirectOrProxyRoute::Direct(TcpRoute { address, .. })
+                        if address == ip_addr!("192.0.2.2") =>
+                    {
+                        Ok(())
+                    }
+                    _ => Err(TransportConnectError::TcpConnectionFailed),
+                };
+                std::future::ready(result)
+            }),
+            Duration::from_secs(60),
+        );
+        let state = ConnectState::new_with_transport_connector(
+            SUGGESTED_CONNECT_CONFIG,
+            make_transport_connector,
+        );
 
-    use assert_matches::assert_matches;
-    use const_str::ip_addr;
-    use http::uri::PathAndQuery;
-    use http::HeaderMap;
-    use libsignal_net_infra::certs::RootCertificates;
-    use libsignal_net_infra::dns::lookup_result::LookupResult;
-    use libsignal_net_infra::host::Host;
-    use libsignal_net_infra::route::testutils::ConnectFn;
-    use libsignal_net_infra::route::{
-        DirectOrProxyRoute, HttpsTlsRoute, TcpRoute, TlsRoute, TlsRouteFragment, UnresolvedHost,
-        UnresolvedTransportRoute, WebSocketRoute,
-    };
-    use libsignal_net_infra::{Alpn, DnsSource, RouteType};
-    use nonzero_ext::nonzero;
+        let route_to = |host: &str| {
+            let mut route = FAKE_TRANSPORT_ROUTE.clone();
+            route.inner = DirectOrProxyRoute::Direct(TcpRoute {
+                address: UnresolvedHost::from(Arc::from(host)),
+                port: nonzero!(443u16),
+            });
+            route
+        };
+        let failing_route = route_to("failing-host");
+        let working_route = route_to("working-host");
 
-    use super::*;
-    use crate::ws::NotRejectedByServer;
+        let plan = ConnectionResources {
+            connect_state: &state,
+            dns_resolver: &resolver,
+            network_change_event: &ObservableEvent::new(),
+            confirmation_header_name: None,
+        }
+        .preconnect_and_save_with_plan(
+            vec![failing_route.clone(), working_route.clone()],
+            "preconnect".into(),
+        )
+        .await
+        .expect("success");
 
-    const FAKE_HOST_NAME: &str = "direct-host";
-    static FAKE_TRANSPORT_ROUTE: LazyLock<UnresolvedTransportRoute> = LazyLock::new(|| TlsRoute {
-        fragment: TlsRouteFragment {
-            root_certs: RootCertificates::Native,
-            sni: Host::Domain("fake-sni".into()),
-            alpn: Some(Alpn::Http1_1),
-        },
-        inner: DirectOrProxyRoute::Direct(TcpRoute {
-            address: UnresolvedHost::from(Arc::from(FAKE_HOST_NAME)),
-            port: nonzero!(1234u16),
-        }),
-    });
-    static FAKE_WEBSOCKET_ROUTES: LazyLock<[UnresolvedWebsocketServiceRoute; 2]> =
-        LazyLock::new(|| {
+        assert_eq!(
+            plan.attempted
+                .iter()
+                .map(RouteInfo::unresolved)
+                .collect_vec(),
             [
-                WebSocketRoute {
-                    fragment: WebSocketRouteFragment {
-                        ws_config: Default::default(),
-                        endpoint: PathAndQuery::from_static("/first"),
-                        headers: HeaderMap::new(),
+                &failing_route.describe_for_log(),
+                &working_route.describe_for_log()
+            ]
+        );
+        assert_eq!(
+            plan.succeeded.unresolved(),
+            &working_route.describe_for_log()
+        );
+    }
+
+    /// A websocket route whose SNI and TCP address are both `host`.
+    fn fake_route_to_host(
+        host: &'static str,
+        front_name: Option<&'static str>,
+    ) -> UnresolvedWebsocketServiceRoute {
+        WebSocketRoute {
+            fragment: WebSocketRouteFragment {
+                ws_config: Default::default(),
+                endpoint: PathAndQuery::from_static("/"),
+                headers: HeaderMap::new(),
+            },
+            inner: HttpsTlsRoute {
+                fragment: HttpRouteFragment {
+                    host_header: host.into(),
+                    path_prefix: "".into(),
+                    front_name,
+                },
+                inner: TlsRoute {
+                    fragment: TlsRouteFragment {
+                        root_certs: RootCertificates::Native,
+                        sni: Host::Domain(host.into()),
+                        alpn: Alpn::Http1_1.into(),
+                        ech_config_list: None,
                     },
-                    inner: HttpsTlsRoute {
-                        fragment: HttpRouteFragment {
-                            host_header: "first-host".into(),
-                            path_prefix: "".into(),
-                            front_name: None,
+                    inner: DirectOrProxyRoute::Direct(TcpRoute {
+                        address: UnresolvedHost::from(Arc::from(host)),
+                        port: nonzero!(443u16),
+                    }),
+                },
+            },
+        }
+    }
+
+    #[test_case(false => vec!["second", "first"]; "default ordering")]
+    #[test_case(true => vec!["first", "second"]; "inverted ordering")]
+    #[tokio::test(start_paused = true)]
+    async fn connect_ws_route_delay_override(invert: bool) -> Vec<String> {
+        const HOSTS: [&str; 2] = ["first", "second"];
+        const FAKE_IP: Ipv4Addr = ip_addr!(v4, "192.0.2.1");
+        let resolver = DnsResolver::new_from_static_map(HashMap::from_iter(HOSTS.map(|host| {
+            (
+                host,
+                LookupResult::new(DnsSource::Static, vec![FAKE_IP], vec![]),
+            )
+        })));
+
+        let attempted_hosts = Mutex::new(Vec::new());
+        let failing_transport_connector = ConnectFn(|(), route: TransportRoute, _| {
+            attempted_hosts
+                .lock()
+                .expect("not poisoned")
+                .push(route.fragment.sni);
+            std::future::ready(Err::<(), _>(TransportConnectError::TcpConnectionFailed))
+        });
+
+        let state = ConnectState::new_with_transport_connector(
+            SUGGESTED_CONNECT_CONFIG,
+            failing_transport_connector,
+        );
+        {
+            let mut state = lock_connect_state(&state);
+            // The first host failed recently, so by default it's tried last.
+            state.attempts_record.apply_outcome_updates(
+                [(
+                    TlsRoute {
+                        fragment: TlsRouteFragment {
+                            root_certs: RootCertificates::Native,
+                            sni: Host::Domain("first".into()),
+                            alpn: Alpn::Http1_1.into(),
+                            ech_config_list: None,
                         },
-                        inner: (*FAKE_TRANSPORT_ROUTE).clone(),
+                        inner: DirectOrProxyRoute::Direct(TcpRoute {
+                            address: FAKE_IP.into(),
+                            port: nonzero!(443u16),
+                        }),
                     },
-                },
-                WebSocketRoute {
-                    fragment: WebSocketRouteFragment {
-                        ws_config: Default::default(),
-                        endpoint: PathAndQuery::from_static("/second"),
-                        headers: HeaderMap::new(),
+                    AttemptOutcome {
+                        started: Instant::now(),
+                        result: Err(UnsuccessfulOutcome),
                     },
-                    inner: HttpsTlsRoute {
-                        fragment: HttpRouteFragment {
-                            host_header: "second-host".into(),
-                            path_prefix: "".into(),
-                            front_name: Some(RouteType::ProxyF.into()),
-                        },
-                        inner: (*FAKE_TRANSPORT_ROUTE).clone(),
+                )],
+                Instant::now(),
+            );
+            if invert {
+                state.set_route_delay_override(Some(
+                    |route: &TransportRoute, outcomes: &ConnectionOutcomes<TransportRoute>, now| {
+                        SUGGESTED_CONNECT_PARAMS
+                            .max_delay
+                            .saturating_sub(outcomes.compute_delay(route, now))
                     },
-                },
-            ]
-        });
+                ));
+            }
+        }
+
+        let connection_resources = ConnectionResources {
+            connect_state: &state,
+            dns_resolver: &resolver,
+            network_change_event: &ObservableEvent::new(),
+            confirmation_header_name: None,
+        };
+        let result = connection_resources
+            .connect_ws(
+                HOSTS.map(|host| fake_route_to_host(host, None)).to_vec(),
+                ConnectFn(|(), route, _log_tag| std::future::ready(Ok(route))),
+                "test".into(),
+                None,
+            )
+            .await;
+        assert_matches!(
+            result,
+            Err(TimeoutOr::Other(ConnectError::AllAttemptsFailed))
+        );
+
+        attempted_hosts
+            .into_inner()
+            .expect("not poisoned")
+            .into_iter()
+            .map(|host| match host {
+                Host::Domain(domain) => domain.to_string(),
+                Host::Ip(ip) => unreachable!("unexpected IP {ip}"),
+            })
+            .collect()
+    }
 
     #[tokio::test(start_paused = true)]
-    async fn connect_ws_successful() {
-        // This doesn't actually matter since we're using a fake connector, but
-        // using the real route type is easier than trying to add yet more
-        // generic parameters.
-        let [failing_route, succeeding_route] = (*FAKE_WEBSOCKET_ROUTES).clone();
+    async fn connect_ws_limits_fronting_domains() {
+        const HOSTS: [&str; 4] = ["direct", "front-a", "front-b", "front-c"];
+        let routes = vec![
+            fake_route_to_host("direct", None),
+            fake_route_to_host("front-a", Some("a")),
+            fake_route_to_host("front-b", Some("b")),
+            fake_route_to_host("front-c", Some("c")),
+        ];
 
-        let ws_connector = ConnectFn(|(), route, _log_tag| {
-            let (ws, http) = &route;
-            std::future::ready(
-                if (ws, http) == (&failing_route.fragment, &failing_route.inner.fragment) {
-                    Err(tungstenite::Error::ConnectionClosed)
-                } else {
-                    Ok(route)
-                },
+        const FAKE_IP: Ipv4Addr = ip_addr!(v4, "192.0.2.1");
+        let resolver = DnsResolver::new_from_static_map(HashMap::from_iter(HOSTS.map(|host| {
+            (
+                host,
+                LookupResult::new(DnsSource::Static, vec![FAKE_IP], vec![]),
             )
+        })));
+
+        let attempted_hosts = Mutex::new(Vec::new());
+        let failing_transport_connector = ConnectFn(|(), route: TransportRoute, _| {
+            attempted_hosts
+                .lock()
+                .expect("not poisoned")
+                .push(route.fragment.sni);
+            std::future::ready(Err::<(), _>(TransportConnectError::TcpConnectionFailed))
         });
-        let resolver = DnsResolver::new_from_static_map(HashMap::from([(
-            FAKE_HOST_NAME,
-            LookupResult::new(DnsSource::Static, vec![ip_addr!(v4, "192.0.2.1")], vec![]),
-        )]));
 
-        let fake_transport_connector =
-            ConnectFn(move |(), _, _| std::future::ready(Ok::<_, WebSocketConnectError>(())));
+        // The first front failed recently, so it should be ranked below the
+        // others.
+        let mut attempts_record = ConnectionOutcomes::new(SUGGESTED_CONNECT_PARAMS);
+        attempts_record.apply_outcome_updates(
+            [(
+                TlsRoute {
+                    fragment: TlsRouteFragment {
+                        root_certs: RootCertificates::Native,
+                        sni: Host::Domain("front-a".into()),
+                        alpn: Alpn::Http1_1.into(),
+                        ech_config_list: None,
+                    },
+                    inner: DirectOrProxyRoute::Direct(TcpRoute {
+                        address: FAKE_IP.into(),
+                        port: nonzero!(443u16),
+                    }),
+                },
+                AttemptOutcome {
+                    started: Instant::now(),
+                    result: Err(UnsuccessfulOutcome),
+                },
+            )],
+            Instant::now(),
+        );
 
         let state = ConnectState {
             connect_timeout: Duration::MAX,
             network_interface_poll_interval: Duration::MAX,
             post_route_change_connect_timeout: Duration::MAX,
+            max_fronting_domains: Some(nonzero!(2usize)),
+            preconnect_timeout: None,
             route_resolver: RouteResolver::default(),
-            attempts_record: ConnectionOutcomes::new(SUGGESTED_CONNECT_PARAMS),
-            make_transport_connector: fake_transport_connector,
+            attempts_record,
+            make_transport_connector: failing_transport_connector,
             route_provider_context: Default::default(),
+            connect_observer: None,
+            connect_progress: None,
+            local_ip_source: Default::default(),
+            global_throttle: None,
+            report_resolved_target: false,
+            report_connect_timing: false,
+            phase_timeouts: Default::default(),
+            preferred_route_hint: None,
+            unstable_network: None,
+            interface_change_aborts: Default::default(),
+            connectivity_stats: Default::default(),
+            attestation_permits: None,
+            route_delay_override: None,
+            route_ordering: Default::default(),
+            route_bandwidth: Default::default(),
+            connect_attempt_events: None,
         }
         .into();
 
@@ -717,245 +5808,640 @@ mod test {
 
         let result = connection_resources
             .connect_ws(
-                vec![failing_route.clone(), succeeding_route.clone()],
-                ws_connector,
+                routes,
+                ConnectFn(|(), route, _log_tag| std::future::ready(Ok(route))),
                 "test".into(),
+                None,
             )
-            // This previously hung forever due to a deadlock bug.
             .await;
+        assert_matches!(
+            result,
+            Err(TimeoutOr::Other(ConnectError::AllAttemptsFailed))
+        );
 
-        let (connection, info) = result.expect("succeeded");
+        let mut attempted_hosts = attempted_hosts.into_inner().expect("not poisoned");
+        attempted_hosts.sort_by_key(|host| host.to_string());
         assert_eq!(
-            connection,
-            (succeeding_route.fragment, succeeding_route.inner.fragment)
+            attempted_hosts,
+            ["direct", "front-b", "front-c"].map(|host| Host::Domain(Arc::from(host)))
         );
-        let RouteInfo { unresolved } = info;
+    }
 
-        assert_eq!(unresolved.to_string(), "REDACTED:1234 fronted by proxyf");
+    #[tokio::test(start_paused = true)]
+    async fn next_attempt_hint_counts_down_after_failures() {
+        const HOSTS: [&str; 2] = ["host-a", "host-b"];
+        const FAKE_IP: Ipv4Addr = ip_addr!(v4, "192.0.2.1");
+
+        let mut attempts_record = ConnectionOutcomes::new(SUGGESTED_CONNECT_PARAMS);
+        // Fail the second host a bit later so the first one exits cooldown first.
+        for host in HOSTS {
+            let failed_route = TlsRoute {
+                fragment: TlsRouteFragment {
+                    root_certs: RootCertificates::Native,
+                    sni: Host::Domain(host.into()),
+                    alpn: Alpn::Http1_1.into(),
+                    ech_config_list: None,
+                },
+                inner: DirectOrProxyRoute::Direct(TcpRoute {
+                    address: FAKE_IP.into(),
+                    port: nonzero!(443u16),
+                }),
+            };
+            attempts_record.apply_outcome_updates(
+                [(
+                    failed_route,
+                    AttemptOutcome {
+                        started: Instant::now(),
+                        result: Err(UnsuccessfulOutcome),
+                    },
+                )],
+                Instant::now(),
+            );
+            tokio::time::advance(Duration::from_millis(100)).await;
+        }
+
+        let state = ConnectState {
+            connect_timeout: Duration::MAX,
+            network_interface_poll_interval: Duration::MAX,
+            post_route_change_connect_timeout: Duration::MAX,
+            max_fronting_domains: None,
+            preconnect_timeout: None,
+            route_resolver: RouteResolver::default(),
+            attempts_record,
+            make_transport_connector: (),
+            route_provider_context: Default::default(),
+            connect_observer: None,
+            connect_progress: None,
+            local_ip_source: Default::default(),
+            global_throttle: None,
+            report_resolved_target: false,
+            report_connect_timing: false,
+            phase_timeouts: Default::default(),
+            preferred_route_hint: None,
+            unstable_network: None,
+            interface_change_aborts: Default::default(),
+            connectivity_stats: Default::default(),
+            attestation_permits: None,
+            route_delay_override: None,
+            route_ordering: Default::default(),
+            route_bandwidth: Default::default(),
+            connect_attempt_events: None,
+        };
+        let routes = Vec::from(HOSTS.map(|host| fake_route_to_host(host, None)));
+
+        let hint = state
+            .next_attempt_hint(&routes)
+            .expect("all routes failed recently");
+        let first_remaining = hint - Instant::now();
+        assert!(!first_remaining.is_zero());
+
+        tokio::time::advance(first_remaining / 2).await;
+        let later_hint = state
+            .next_attempt_hint(&routes)
+            .expect("still cooling down");
+        assert!(later_hint > Instant::now());
+        assert!(later_hint - Instant::now() < first_remaining);
+
+        // Failures stop counting against a route once they're old enough.
+        tokio::time::advance(SUGGESTED_CONNECT_PARAMS.age_cutoff).await;
+        assert_eq!(state.next_attempt_hint(&routes), None);
+
+        // A route to a host that has never failed is available right away.
+        let fresh_state = ConnectState {
+            attempts_record: ConnectionOutcomes::new(SUGGESTED_CONNECT_PARAMS),
+            ..state
+        };
+        assert_eq!(fresh_state.next_attempt_hint(&routes), None);
     }
 
     #[tokio::test(start_paused = true)]
-    async fn connect_ws_timeout() {
-        let ws_connector = crate::infra::ws::Stateless;
+    async fn gc_outcomes_removes_aged_out_entries() {
+        const FAKE_IP: Ipv4Addr = ip_addr!(v4, "192.0.2.1");
+        let failed_route = |host: &str| TlsRoute {
+            fragment: TlsRouteFragment {
+                root_certs: RootCertificates::Native,
+                sni: Host::Domain(host.into()),
+                alpn: Alpn::Http1_1.into(),
+                ech_config_list: None,
+            },
+            inner: DirectOrProxyRoute::Direct(TcpRoute {
+                address: FAKE_IP.into(),
+                port: nonzero!(443u16),
+            }),
+        };
+        let record_failure = |state: &mut ConnectState<()>, host| {
+            state.attempts_record.apply_outcome_updates(
+                [(
+                    failed_route(host),
+                    AttemptOutcome {
+                        started: Instant::now(),
+                        result: Err(UnsuccessfulOutcome),
+                    },
+                )],
+                Instant::now(),
+            )
+        };
+
+        let mut state = ConnectState::new_with_transport_connector(SUGGESTED_CONNECT_CONFIG, ())
+            .into_inner()
+            .expect("not poisoned");
+        let age_cutoff = SUGGESTED_CONNECT_PARAMS.age_cutoff;
+
+        record_failure(&mut state, "first");
+        record_failure(&mut state, "second");
+        tokio::time::advance(age_cutoff / 2).await;
+        record_failure(&mut state, "third");
+        assert_eq!(state.gc_outcomes(Instant::now()), 0);
+
+        // Only the first two are old enough to be removed.
+        tokio::time::advance(age_cutoff / 2).await;
+        assert_eq!(state.gc_outcomes(Instant::now()), 2);
+        assert_eq!(state.gc_outcomes(Instant::now()), 0);
+
+        tokio::time::advance(age_cutoff / 2).await;
+        assert_eq!(state.gc_outcomes(Instant::now()), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn connect_ws_reports_correlation_to_observer() {
+        #[derive(Default)]
+        struct RecordingObserver(Mutex<Vec<(RouteInfo, RouteAbandonReason)>>);
+        impl ConnectObserver<RouteInfo> for RecordingObserver {
+            fn on_route_abandoned(&self, route: &RouteInfo, reason: RouteAbandonReason) {
+                self.0
+                    .lock()
+                    .expect("not poisoned")
+                    .push((route.clone(), reason));
+            }
+        }
+
         let resolver = DnsResolver::new_from_static_map(HashMap::from([(
             FAKE_HOST_NAME,
             LookupResult::new(DnsSource::Static, vec![ip_addr!(v4, "192.0.2.1")], vec![]),
         )]));
-        let network_change_event = ObservableEvent::new();
-
         let always_hangs_connector = ConnectFn(|(), _, _| {
             std::future::pending::<Result<tokio::io::DuplexStream, WebSocketConnectError>>()
         });
-
-        const CONNECT_TIMEOUT: Duration = Duration::from_secs(31);
+        let observer = Arc::new(RecordingObserver::default());
 
         let state = ConnectState {
-            connect_timeout: CONNECT_TIMEOUT,
+            connect_timeout: Duration::from_secs(10),
             network_interface_poll_interval: Duration::MAX,
             post_route_change_connect_timeout: Duration::MAX,
+            max_fronting_domains: None,
+            preconnect_timeout: None,
             route_resolver: RouteResolver::default(),
             attempts_record: ConnectionOutcomes::new(SUGGESTED_CONNECT_PARAMS),
             make_transport_connector: always_hangs_connector,
             route_provider_context: Default::default(),
+            connect_observer: Some(observer.clone()),
+            connect_progress: None,
+            local_ip_source: Default::default(),
+            global_throttle: None,
+            report_resolved_target: false,
+            report_connect_timing: false,
+            phase_timeouts: Default::default(),
+            preferred_route_hint: None,
+            unstable_network: None,
+            interface_change_aborts: Default::default(),
+            connectivity_stats: Default::default(),
+            attestation_permits: None,
+            route_delay_override: None,
+            route_ordering: Default::default(),
+            route_bandwidth: Default::default(),
+            connect_attempt_events: None,
         }
         .into();
 
-        let [failing_route, succeeding_route] = (*FAKE_WEBSOCKET_ROUTES).clone();
-
         let connection_resources = ConnectionResources {
             connect_state: &state,
             dns_resolver: &resolver,
-            network_change_event: &network_change_event,
+            network_change_event: &ObservableEvent::new(),
             confirmation_header_name: None,
         };
 
-        let connect = connection_resources.connect_ws(
-            vec![failing_route.clone(), succeeding_route.clone()],
-            ws_connector,
-            "test".into(),
-        );
+        let correlation = CorrelationContext::default()
+            .with("trace-id", "abc123")
+            .with("span", "connect");
+        assert_eq!(correlation.to_string(), "trace-id=abc123 span=connect");
 
-        let start = Instant::now();
-        let result: Result<_, TimeoutOr<ConnectError<_>>> = connect.await;
+        let result = connection_resources
+            .connect_ws_with_correlation(
+                Vec::from((*FAKE_WEBSOCKET_ROUTES).clone()),
+                crate::infra::ws::Stateless,
+                "test".into(),
+                correlation.clone(),
+            )
+            .await;
+        assert_matches!(result, Err(TimeoutOr::Timeout { .. }));
 
-        assert_matches!(
-            result,
-            Err(TimeoutOr::Timeout {
-                attempt_duration: CONNECT_TIMEOUT
-            })
-        );
-        assert_eq!(start.elapsed(), CONNECT_TIMEOUT);
+        let abandoned = std::mem::take(&mut *observer.0.lock().expect("not poisoned"));
+        assert_matches!(&*abandoned, [_, ..]);
+        for (route_info, reason) in abandoned {
+            assert_eq!(reason, RouteAbandonReason::Cancelled);
+            assert_eq!(route_info.correlation(), &correlation);
+        }
     }
 
-    #[tokio::test(start_paused = true)]
-    async fn client_abort_transport_error_is_fatal() {
-        // We can't directly test the ClientAbort produced for a network change without *more*
-        // custom dependency injection for connect_ws---we can fire the network change event, but we
-        // can't actually change the local IP detection logic. But we can test a ClientAbort
-        // produced by the underlying connector.
+    /// An enclave that accepts the attestation from the test data.
+    ///
+    /// Counts how many of its handshakes are in progress, since
+    /// [`NewHandshake`] doesn't get any other state to use.
+    enum FakeEnclave {}
 
-        let ws_connector = crate::infra::ws::Stateless;
-        let resolver = DnsResolver::new_from_static_map(HashMap::from([(
-            FAKE_HOST_NAME,
-            LookupResult::new(DnsSource::Static, vec![ip_addr!(v4, "192.0.2.1")], vec![]),
-        )]));
-        let network_change_event = ObservableEvent::new();
+    static FAKE_ENCLAVE_HANDSHAKES_IN_PROGRESS: std::sync::atomic::AtomicUsize =
+        std::sync::atomic::AtomicUsize::new(0);
+    static FAKE_ENCLAVE_MAX_HANDSHAKES_IN_PROGRESS: std::sync::atomic::AtomicUsize =
+        std::sync::atomic::AtomicUsize::new(0);
 
-        let client_abort_connector = ConnectFn(|(), _, _| {
-            std::future::ready(Err::<tokio::io::DuplexStream, _>(
-                TransportConnectError::ClientAbort,
-            ))
-        });
+    impl crate::enclave::EnclaveKind for FakeEnclave {
+        type RaftConfigType = ();
+        fn url_path(_enclave: &[u8]) -> PathAndQuery {
+            PathAndQuery::from_static("/")
+        }
+    }
 
-        const CONNECT_TIMEOUT: Duration = Duration::from_secs(31);
+    impl NewHandshake for FakeEnclave {
+        fn new_handshake(
+            _params: &EndpointParams<Self>,
+            attestation_message: &[u8],
+            handshake_rng: Option<attest::enclave::HandshakeRng>,
+        ) -> attest::enclave::Result<attest::enclave::Handshake> {
+            use std::sync::atomic::Ordering;
 
-        let state = ConnectState {
-            connect_timeout: CONNECT_TIMEOUT,
-            network_interface_poll_interval: Duration::MAX,
-            post_route_change_connect_timeout: Duration::MAX,
-            route_resolver: RouteResolver::default(),
-            attempts_record: ConnectionOutcomes::new(SUGGESTED_CONNECT_PARAMS),
-            make_transport_connector: client_abort_connector,
-            route_provider_context: Default::default(),
+            assert_eq!(
+                attestation_message,
+                libsignal_net_infra::ws2::attested::testutil::FAKE_ATTESTATION
+            );
+            let in_progress =
+                FAKE_ENCLAVE_HANDSHAKES_IN_PROGRESS.fetch_add(1, Ordering::SeqCst) + 1;
+            FAKE_ENCLAVE_MAX_HANDSHAKES_IN_PROGRESS.fetch_max(in_progress, Ordering::SeqCst);
+            attest::sgx_session::testutil::handshake_from_tests_data_with_rng(handshake_rng)
         }
-        .into();
+    }
 
-        let [failing_route, succeeding_route] = (*FAKE_WEBSOCKET_ROUTES).clone();
+    /// An enclave that accepts the attestation from the test data, without
+    /// counting its handshakes like [`FakeEnclave`].
+    enum UncountedFakeEnclave {}
 
-        let connection_resources = ConnectionResources {
+    impl crate::enclave::EnclaveKind for UncountedFakeEnclave {
+        type RaftConfigType = ();
+        fn url_path(_enclave: &[u8]) -> PathAndQuery {
+            PathAndQuery::from_static("/")
+        }
+    }
+
+    impl NewHandshake for UncountedFakeEnclave {
+        fn new_handshake(
+            _params: &EndpointParams<Self>,
+            _attestation_message: &[u8],
+            handshake_rng: Option<attest::enclave::HandshakeRng>,
+        ) -> attest::enclave::Result<attest::enclave::Handshake> {
+            attest::sgx_session::testutil::handshake_from_tests_data_with_rng(handshake_rng)
+        }
+    }
+
+    async fn connect_uncounted_fake_enclave<TC, WC>(
+        transport_connector: TC,
+        ws_connector: WC,
+        handshake_rng: Option<attest::enclave::HandshakeRng>,
+    ) -> Result<(AttestedConnection, RouteInfo), crate::enclave::Error>
+    where
+        TC: WebSocketTransportConnectorFactory,
+        WC: Connector<
+                (WebSocketRouteFragment, HttpRouteFragment),
+                TC::Connection,
+                Connection: WebSocketStreamLike + Send + 'static,
+                Error = tungstenite::Error,
+            > + Send
+            + Sync,
+    {
+        const FAKE_WS_CONFIG: libsignal_net_infra::ws2::Config = libsignal_net_infra::ws2::Config {
+            local_idle_timeout: Duration::from_secs(5),
+            remote_idle_ping_timeout: Duration::from_secs(100),
+            remote_idle_disconnect_timeout: Duration::from_secs(100),
+        };
+
+        let resolver = DnsResolver::new_from_static_map(HashMap::from([(
+            FAKE_HOST_NAME,
+            LookupResult::new(DnsSource::Static, vec![ip_addr!(v4, "192.0.2.1")], vec![]),
+        )]));
+        let state = ConnectState::new_with_transport_connector(
+            SUGGESTED_CONNECT_CONFIG,
+            transport_connector,
+        );
+        let params = EndpointParams::<UncountedFakeEnclave> {
+            mr_enclave: crate::enclave::MrEnclave::new(&[]),
+            raft_config: (),
+        };
+
+        ConnectionResources {
             connect_state: &state,
             dns_resolver: &resolver,
-            network_change_event: &network_change_event,
+            network_change_event: &ObservableEvent::new(),
             confirmation_header_name: None,
-        };
-
-        let connect = connection_resources.connect_ws(
-            vec![failing_route.clone(), succeeding_route.clone()],
-            ws_connector,
+        }
+        .connect_attested_ws(
+            vec![FAKE_WEBSOCKET_ROUTES[0].clone()],
+            Auth {
+                username: "user".into(),
+                password: "pass".into(),
+            },
+            (FAKE_WS_CONFIG, ws_connector),
             "test".into(),
-        );
+            &params,
+            handshake_rng,
+        )
+        .await
+    }
 
-        let result: Result<_, TimeoutOr<ConnectError<_>>> = connect.await;
+    #[tokio::test(start_paused = true)]
+    async fn connect_attested_ws_handshake_rejected() {
+        use futures_util::{SinkExt as _, StreamExt as _};
+        use libsignal_net_infra::ws::testutil::fake_websocket;
+        use libsignal_net_infra::ws2::attested::testutil::FAKE_ATTESTATION;
+
+        let fake_transport_connector = ConnectFn(|(), _, _| {
+            std::future::ready(Ok::<_, WebSocketConnectError>(tokio::io::duplex(1).0))
+        });
+        let ws_connector = ConnectFn(|_transport, _route, _log_tag| async {
+            let (mut server, client) = fake_websocket().await;
+            tokio::spawn(async move {
+                // Present an acceptable attestation, then hang up on the
+                // client's handshake instead of completing it.
+                server
+                    .send(tungstenite::Message::Binary(FAKE_ATTESTATION.into()))
+                    .await
+                    .expect("can send");
+                let _handshake = server.next().await;
+                server.close(None).await.expect("can close");
+            });
+            Ok::<_, tungstenite::Error>(client)
+        });
 
+        let result =
+            connect_uncounted_fake_enclave(fake_transport_connector, ws_connector, None).await;
+        let error = assert_matches!(result, Err(e) => e);
         assert_matches!(
-            result,
-            Err(TimeoutOr::Other(ConnectError::FatalConnect(
-                WebSocketServiceConnectError::Connect(
-                    WebSocketConnectError::Transport(TransportConnectError::ClientAbort),
-                    NotRejectedByServer { .. }
-                )
-            )))
+            error,
+            crate::enclave::Error::HandshakeRejected(
+                libsignal_net_infra::ws2::attested::AttestedProtocolError::UnexpectedClose(_)
+            )
         );
+        assert!(!error.is_resumable());
     }
 
     #[tokio::test(start_paused = true)]
-    async fn preconnect_records_outcomes() {
-        let ws_connector = ConnectFn(|(), route, _log_tag| std::future::ready(Ok(route)));
+    async fn connect_attested_ws_uses_provided_handshake_rng() {
+        use futures_util::{SinkExt as _, StreamExt as _};
+        use libsignal_net_infra::ws::testutil::fake_websocket;
+        use libsignal_net_infra::ws2::attested::testutil::FAKE_ATTESTATION;
+        use rand::SeedableRng as _;
+
+        async fn client_handshake_with_seed(seed: u64) -> Vec<u8> {
+            let (handshake_tx, handshake_rx) = tokio::sync::oneshot::channel();
+            let handshake_tx = std::sync::Mutex::new(Some(handshake_tx));
+
+            let fake_transport_connector = ConnectFn(|(), _, _| {
+                std::future::ready(Ok::<_, WebSocketConnectError>(tokio::io::duplex(1).0))
+            });
+            let ws_connector = ConnectFn(|_transport, _route, _log_tag| {
+                let handshake_tx = handshake_tx
+                    .lock()
+                    .expect("not poisoned")
+                    .take()
+                    .expect("only one connection");
+                async move {
+                    let (mut server, client) = fake_websocket().await;
+                    tokio::spawn(async move {
+                        // Record the client's handshake, then hang up instead
+                        // of completing it.
+                        server
+                            .send(tungstenite::Message::Binary(FAKE_ATTESTATION.into()))
+                            .await
+                            .expect("can send");
+                        let handshake = assert_matches!(
+                            server.next().await,
+                            Some(Ok(tungstenite::Message::Binary(handshake))) => handshake
+                        );
+                        handshake_tx
+                            .send(handshake.to_vec())
+                            .expect("receiver alive");
+                        server.close(None).await.expect("can close");
+                    });
+                    Ok::<_, tungstenite::Error>(client)
+                }
+            });
+
+            let handshake_rng =
+                attest::enclave::HandshakeRng::new(rand_chacha::ChaCha20Rng::seed_from_u64(seed));
+            let result = connect_uncounted_fake_enclave(
+                fake_transport_connector,
+                ws_connector,
+                Some(handshake_rng),
+            )
+            .await;
+            assert_matches!(result, Err(crate::enclave::Error::HandshakeRejected(_)));
+
+            handshake_rx.await.expect("server saw a handshake")
+        }
+
+        let first = client_handshake_with_seed(1).await;
+        let same_seed = client_handshake_with_seed(1).await;
+        let other_seed = client_handshake_with_seed(2).await;
+        assert_eq!(first, same_seed);
+        assert_ne!(first, other_seed);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn connect_attested_ws_unreachable() {
+        use libsignal_net_infra::ws::testutil::fake_websocket;
+
+        let fake_transport_connector = ConnectFn(|(), _, _| {
+            std::future::ready(Err::<tokio::io::DuplexStream, _>(
+                WebSocketConnectError::Transport(TransportConnectError::TcpConnectionFailed),
+            ))
+        });
+        // Never reached, since no transport connection succeeds.
+        let ws_connector = ConnectFn(|_transport, _route, _log_tag| async {
+            Ok::<_, tungstenite::Error>(fake_websocket().await.1)
+        });
+
+        let result =
+            connect_uncounted_fake_enclave(fake_transport_connector, ws_connector, None).await;
+        let error = assert_matches!(result, Err(e) => e);
+        assert_matches!(error, crate::enclave::Error::Unreachable);
+        assert!(error.is_resumable());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn connect_attested_ws_limits_concurrent_attestations() {
+        use std::sync::atomic::Ordering;
+
+        use libsignal_net_infra::ws::testutil::fake_websocket;
+        use libsignal_net_infra::ws::NextOrClose;
+        use libsignal_net_infra::ws2::attested::testutil::{
+            run_attested_server, AttestedServerOutput,
+        };
+
+        const MAX_ATTESTATIONS: NonZeroUsize = nonzero!(2usize);
+        const CONNECT_COUNT: usize = 5;
+        const FAKE_WS_CONFIG: libsignal_net_infra::ws2::Config = libsignal_net_infra::ws2::Config {
+            local_idle_timeout: Duration::from_secs(5),
+            remote_idle_ping_timeout: Duration::from_secs(100),
+            remote_idle_disconnect_timeout: Duration::from_secs(100),
+        };
+
         let resolver = DnsResolver::new_from_static_map(HashMap::from([(
             FAKE_HOST_NAME,
             LookupResult::new(DnsSource::Static, vec![ip_addr!(v4, "192.0.2.1")], vec![]),
         )]));
+        let network_change_event = ObservableEvent::new();
 
-        let attempts_by_host = Mutex::new(HashMap::<Host<_>, u32>::new());
-        let make_transport_connector = PreconnectingFactory::new(
-            ConnectFn(|(), route: TransportRoute, _| {
-                let host = route.fragment.sni;
-                let result = if host == Host::parse_as_ip_or_domain("fail") {
-                    Err(TransportConnectError::TcpConnectionFailed)
-                } else {
-                    Ok(())
-                };
-                *attempts_by_host
-                    .lock()
-                    .expect("no panic")
-                    .entry(host)
-                    .or_default() += 1;
-                std::future::ready(result)
-            }),
-            Duration::from_secs(60),
+        let fake_transport_connector = ConnectFn(|(), _, _| {
+            std::future::ready(Ok::<_, WebSocketConnectError>(tokio::io::duplex(1).0))
+        });
+        // Websockets that have been connected but whose connect hasn't
+        // finished yet.
+        let open_websockets = std::sync::atomic::AtomicUsize::new(0);
+        let max_open_websockets = std::sync::atomic::AtomicUsize::new(0);
+        let ws_connector = ConnectFn(|_transport, _route, _log_tag| {
+            let open = open_websockets.fetch_add(1, Ordering::SeqCst) + 1;
+            max_open_websockets.fetch_max(open, Ordering::SeqCst);
+            async {
+                let (server, client) = fake_websocket().await;
+                tokio::spawn(async move {
+                    // Hold back the attestation so that every connect that is
+                    // allowed to start its handshake gets the chance to.
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                    run_attested_server(
+                        server,
+                        attest::sgx_session::testutil::private_key(),
+                        |frame| match frame {
+                            NextOrClose::Next(message) => AttestedServerOutput::message(message),
+                            NextOrClose::Close(close) => AttestedServerOutput::close(close),
+                        },
+                    )
+                    .await
+                });
+                Ok::<_, tungstenite::Error>(client)
+            }
+        });
+
+        let state = ConnectState::new_with_transport_connector(
+            Config {
+                max_concurrent_attestations: Some(MAX_ATTESTATIONS),
+                ..SUGGESTED_CONNECT_CONFIG
+            },
+            fake_transport_connector,
         );
+        let params = EndpointParams::<FakeEnclave> {
+            mr_enclave: crate::enclave::MrEnclave::new(&[]),
+            raft_config: (),
+        };
 
-        const CONNECT_TIMEOUT: Duration = Duration::from_secs(31);
+        let connects = (0..CONNECT_COUNT).map(|_| async {
+            let result = ConnectionResources {
+                connect_state: &state,
+                dns_resolver: &resolver,
+                network_change_event: &network_change_event,
+                confirmation_header_name: None,
+            }
+            .connect_attested_ws(
+                vec![FAKE_WEBSOCKET_ROUTES[0].clone()],
+                Auth {
+                    username: "user".into(),
+                    password: "pass".into(),
+                },
+                (FAKE_WS_CONFIG, ws_connector.clone()),
+                "test".into(),
+                &params,
+                None,
+            )
+            .await;
+            FAKE_ENCLAVE_HANDSHAKES_IN_PROGRESS.fetch_sub(1, Ordering::SeqCst);
+            open_websockets.fetch_sub(1, Ordering::SeqCst);
+            result
+        });
+        let results = futures_util::future::join_all(connects).await;
 
-        let state = ConnectState {
-            connect_timeout: CONNECT_TIMEOUT,
-            network_interface_poll_interval: Duration::MAX,
-            post_route_change_connect_timeout: Duration::MAX,
-            route_resolver: RouteResolver::default(),
-            attempts_record: ConnectionOutcomes::new(SUGGESTED_CONNECT_PARAMS),
-            make_transport_connector,
-            route_provider_context: Default::default(),
+        for result in &results {
+            assert_matches!(result, Ok(_));
         }
-        .into();
+        assert_eq!(
+            FAKE_ENCLAVE_MAX_HANDSHAKES_IN_PROGRESS.load(Ordering::SeqCst),
+            MAX_ATTESTATIONS.get()
+        );
+        // Connects waiting for a permit don't hold a websocket open meanwhile.
+        assert_eq!(
+            max_open_websockets.load(Ordering::SeqCst),
+            MAX_ATTESTATIONS.get()
+        );
+    }
 
-        let good_transport_route = FAKE_TRANSPORT_ROUTE.clone();
-        let mut bad_transport_route = good_transport_route.clone();
-        bad_transport_route.fragment.sni = Host::parse_as_ip_or_domain("fail");
+    #[tokio::test(start_paused = true)]
+    async fn connect_attested_ws_times_out_waiting_for_attestation_permit() {
+        use libsignal_net_infra::ws::testutil::fake_websocket;
 
-        let connection_resources = ConnectionResources {
-            connect_state: &state,
-            dns_resolver: &resolver,
-            network_change_event: &ObservableEvent::new(),
-            confirmation_header_name: None,
+        let fake_transport_connector = ConnectFn(|(), _, _| {
+            std::future::ready(Ok::<_, WebSocketConnectError>(tokio::io::duplex(1).0))
+        });
+        let websocket_connected = std::sync::atomic::AtomicBool::new(false);
+        let ws_connector = ConnectFn(|_transport, _route, _log_tag| {
+            websocket_connected.store(true, std::sync::atomic::Ordering::SeqCst);
+            async { Ok::<_, tungstenite::Error>(fake_websocket().await.1) }
+        });
+        const FAKE_WS_CONFIG: libsignal_net_infra::ws2::Config = libsignal_net_infra::ws2::Config {
+            local_idle_timeout: Duration::from_secs(5),
+            remote_idle_ping_timeout: Duration::from_secs(100),
+            remote_idle_disconnect_timeout: Duration::from_secs(100),
         };
 
-        connection_resources
-            .preconnect_and_save(
-                vec![bad_transport_route.clone(), good_transport_route.clone()],
-                "preconnect".into(),
-            )
-            .await
-            .expect("success");
-
-        assert_eq!(
-            *attempts_by_host.lock().expect("not poisoned"),
-            HashMap::from_iter([
-                (Host::parse_as_ip_or_domain("fake-sni"), 1),
-                (Host::parse_as_ip_or_domain("fail"), 1),
-            ])
+        let resolver = DnsResolver::new_from_static_map(HashMap::from([(
+            FAKE_HOST_NAME,
+            LookupResult::new(DnsSource::Static, vec![ip_addr!(v4, "192.0.2.1")], vec![]),
+        )]));
+        let state = ConnectState::new_with_transport_connector(
+            Config {
+                max_concurrent_attestations: Some(nonzero!(1usize)),
+                ..SUGGESTED_CONNECT_CONFIG
+            },
+            fake_transport_connector,
         );
+        // Some other attestation is taking a very long time.
+        let _held_permit = lock_connect_state(&state)
+            .attestation_permits
+            .clone()
+            .expect("limited")
+            .try_acquire_owned()
+            .expect("available");
+        let params = EndpointParams::<UncountedFakeEnclave> {
+            mr_enclave: crate::enclave::MrEnclave::new(&[]),
+            raft_config: (),
+        };
 
-        let connection_resources = ConnectionResources {
+        let start = Instant::now();
+        let result = ConnectionResources {
             connect_state: &state,
             dns_resolver: &resolver,
             network_change_event: &ObservableEvent::new(),
             confirmation_header_name: None,
-        };
-
-        _ = connection_resources
-            .connect_ws(
-                [bad_transport_route.clone(), good_transport_route.clone()]
-                    .into_iter()
-                    .map(|route| WebSocketRoute {
-                        fragment: WebSocketRouteFragment {
-                            ws_config: Default::default(),
-                            endpoint: PathAndQuery::from_static("/"),
-                            headers: HeaderMap::new(),
-                        },
-                        inner: HttpsTlsRoute {
-                            fragment: HttpRouteFragment {
-                                host_header: "host".into(),
-                                path_prefix: "".into(),
-                                front_name: None,
-                            },
-                            inner: route,
-                        },
-                    })
-                    .collect_vec(),
-                ws_connector,
-                "test".into(),
-            )
-            .await
-            .expect("succeeded");
+        }
+        .connect_attested_ws(
+            vec![FAKE_WEBSOCKET_ROUTES[0].clone()],
+            Auth {
+                username: "user".into(),
+                password: "pass".into(),
+            },
+            (FAKE_WS_CONFIG, ws_connector),
+            "test".into(),
+            &params,
+            None,
+        )
+        .await;
 
-        // Even though the bad transport route was listed first, we should have tried the good
-        // transport route first due to the record of the preconnect attempts.
-        assert_eq!(
-            *attempts_by_host.lock().expect("not poisoned"),
-            HashMap::from_iter([
-                (Host::parse_as_ip_or_domain("fake-sni"), 2),
-                (Host::parse_as_ip_or_domain("fail"), 1),
-            ])
-        );
+        assert_matches!(result, Err(crate::enclave::Error::ConnectionTimedOut));
+        assert_eq!(start.elapsed(), SUGGESTED_CONNECT_CONFIG.connect_timeout);
+        assert!(!websocket_connected.load(std::sync::atomic::Ordering::SeqCst));
     }
 }
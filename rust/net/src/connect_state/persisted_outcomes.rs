@@ -0,0 +1,506 @@
+//
+// Copyright 2025 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+//! Versioned serialized form of the connection outcomes kept by
+//! [`ConnectState`](super::ConnectState).
+//!
+//! Only routes that can be described without credentials are saved: direct
+//! routes and routes through a Signal TLS proxy. Everything else is left out
+//! on export, and on import any route that can't be understood (including
+//! routes of a type written by a newer version) is skipped rather than treated
+//! as an error.
+//!
+//! The saved blob is sensitive, and should be stored like other private app
+//! data. Routes are written out in plaintext, so it records which IP
+//! addresses, SNIs, and proxy hosts the device has been connecting through.
+
+use std::borrow::Cow;
+use std::net::IpAddr;
+use std::num::NonZeroU16;
+use std::sync::Arc;
+use std::time::Duration;
+
+use libsignal_net_infra::certs::RootCertificates;
+use libsignal_net_infra::host::Host;
+use libsignal_net_infra::route::{
+    ConnectionOutcomes, ConnectionProxyRoute, DirectOrProxyRoute, SerializableOutcome, TcpRoute,
+    TlsRoute, TlsRouteFragment, TransportRoute,
+};
+use libsignal_net_infra::{Alpn, AlpnList};
+use serde_with::{serde_as, DurationMilliSeconds};
+use tokio::time::Instant;
+
+use crate::certs::{PROXY_G_ROOT_CERTIFICATES, SIGNAL_ROOT_CERTIFICATES};
+
+/// The version written by [`export`].
+///
+/// Bump this if the format changes in a way older readers would misinterpret;
+/// new route types can be added without bumping it.
+const CURRENT_VERSION: u32 = 1;
+
+#[derive(Debug, thiserror::Error, displaydoc::Display)]
+pub enum ImportOutcomesError {
+    /// saved connection outcomes were malformed
+    Malformed,
+    /// saved connection outcomes had unsupported version {0}
+    UnsupportedVersion(u32),
+}
+
+pub(super) fn export(outcomes: &ConnectionOutcomes<TransportRoute>, now: Instant) -> Vec<u8> {
+    let outcomes = outcomes
+        .to_serializable(now)
+        .into_iter()
+        .filter_map(
+            |SerializableOutcome {
+                 route,
+                 age,
+                 consecutive_failures,
+             }| {
+                Some(PersistedOutcome {
+                    route: PersistedRoute::from_route(&route)?,
+                    age,
+                    consecutive_failures,
+                })
+            },
+        )
+        .collect();
+
+    serde_json::to_vec(&PersistedOutcomes {
+        version: CURRENT_VERSION,
+        outcomes,
+    })
+    .expect("can serialize")
+}
+
+/// Adds the outcomes in `blob` to `outcomes`, returning how many were
+/// restored.
+pub(super) fn import(
+    outcomes: &mut ConnectionOutcomes<TransportRoute>,
+    blob: &[u8],
+    now: Instant,
+) -> Result<usize, ImportOutcomesError> {
+    // Check the version before trying to interpret anything else.
+    let VersionOnly { version } =
+        serde_json::from_slice(blob).map_err(|_| ImportOutcomesError::Malformed)?;
+    if version != CURRENT_VERSION {
+        return Err(ImportOutcomesError::UnsupportedVersion(version));
+    }
+
+    let PersistedOutcomes {
+        version: _,
+        outcomes: saved,
+    }: PersistedOutcomes<serde_json::Value> =
+        serde_json::from_slice(blob).map_err(|_| ImportOutcomesError::Malformed)?;
+
+    let restored = saved
+        .into_iter()
+        .filter_map(
+            |PersistedOutcome {
+                 route,
+                 age,
+                 consecutive_failures,
+             }| {
+                let route = serde_json::from_value::<PersistedRoute>(route)
+                    .ok()?
+                    .into_route()?;
+                Some(SerializableOutcome {
+                    route,
+                    age,
+                    consecutive_failures,
+                })
+            },
+        )
+        .collect::<Vec<_>>();
+
+    Ok(outcomes.extend_from_serializable(restored, now))
+}
+
+#[derive(serde::Deserialize)]
+struct VersionOnly {
+    version: u32,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct PersistedOutcomes<Route = PersistedRoute> {
+    version: u32,
+    outcomes: Vec<PersistedOutcome<Route>>,
+}
+
+#[serde_as]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct PersistedOutcome<Route> {
+    route: Route,
+    #[serde_as(as = "DurationMilliSeconds<u64>")]
+    #[serde(rename = "age_ms")]
+    age: Duration,
+    consecutive_failures: u8,
+}
+
+type Base64Padded =
+    serde_with::base64::Base64<serde_with::base64::Standard, serde_with::formats::Padded>;
+
+#[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum PersistedRoute {
+    Direct {
+        tls: PersistedTls,
+        tcp: PersistedTcp,
+    },
+    TlsProxy {
+        tls: PersistedTls,
+        proxy_tls: PersistedTls,
+        proxy_tcp: PersistedTcp,
+    },
+}
+
+#[serde_as]
+#[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+struct PersistedTls {
+    root_certs: PersistedCerts,
+    sni: PersistedHost,
+    alpn: Vec<String>,
+    #[serde_as(as = "Option<Base64Padded>")]
+    ech_config_list: Option<Vec<u8>>,
+}
+
+#[serde_as]
+#[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum PersistedCerts {
+    Native,
+    Signal,
+    ProxyG,
+    Der(#[serde_as(as = "Base64Padded")] Vec<u8>),
+}
+
+#[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum PersistedHost {
+    Ip(IpAddr),
+    Domain(String),
+}
+
+#[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+struct PersistedTcp {
+    address: IpAddr,
+    port: NonZeroU16,
+}
+
+impl PersistedRoute {
+    fn from_route(route: &TransportRoute) -> Option<Self> {
+        let TlsRoute { fragment, inner } = route;
+        let tls = PersistedTls::from_fragment(fragment)?;
+        Some(match inner {
+            DirectOrProxyRoute::Direct(tcp) => Self::Direct {
+                tls,
+                tcp: PersistedTcp::from(tcp),
+            },
+            DirectOrProxyRoute::Proxy(ConnectionProxyRoute::Tls {
+                proxy:
+                    TlsRoute {
+                        fragment: proxy_fragment,
+                        inner: proxy_tcp,
+                    },
+            }) => Self::TlsProxy {
+                tls,
+                proxy_tls: PersistedTls::from_fragment(proxy_fragment)?,
+                proxy_tcp: PersistedTcp::from(proxy_tcp),
+            },
+            // Other proxies can carry credentials, and the unencrypted TCP
+            // proxy is only used for testing.
+            DirectOrProxyRoute::Proxy(
+                ConnectionProxyRoute::Tcp { .. }
+                | ConnectionProxyRoute::Socks(_)
+                | ConnectionProxyRoute::Https(_),
+            ) => return None,
+        })
+    }
+
+    fn into_route(self) -> Option<TransportRoute> {
+        Some(match self {
+            Self::Direct { tls, tcp } => TlsRoute {
+                fragment: tls.into_fragment()?,
+                inner: DirectOrProxyRoute::Direct(tcp.into()),
+            },
+            Self::TlsProxy {
+                tls,
+                proxy_tls,
+                proxy_tcp,
+            } => TlsRoute {
+                fragment: tls.into_fragment()?,
+                inner: DirectOrProxyRoute::Proxy(ConnectionProxyRoute::Tls {
+                    proxy: TlsRoute {
+                        fragment: proxy_tls.into_fragment()?,
+                        inner: proxy_tcp.into(),
+                    },
+                }),
+            },
+        })
+    }
+}
+
+impl PersistedTls {
+    fn from_fragment(fragment: &TlsRouteFragment) -> Option<Self> {
+        let TlsRouteFragment {
+            root_certs,
+            sni,
+            alpn,
+            ech_config_list,
+        } = fragment;
+        Some(Self {
+            root_certs: PersistedCerts::from_certs(root_certs)?,
+            sni: match sni {
+                Host::Ip(ip) => PersistedHost::Ip(*ip),
+                Host::Domain(domain) => PersistedHost::Domain(domain.to_string()),
+            },
+            alpn: alpn
+                .iter()
+                .map(|alpn| String::from_utf8_lossy(&alpn.as_ref()[1..]).into_owned())
+                .collect(),
+            ech_config_list: ech_config_list.as_deref().map(Vec::from),
+        })
+    }
+
+    fn into_fragment(self) -> Option<TlsRouteFragment> {
+        let Self {
+            root_certs,
+            sni,
+            alpn,
+            ech_config_list,
+        } = self;
+        let alpn = alpn
+            .iter()
+            .map(|name| Alpn::from_protocol_name(name.as_bytes()))
+            .collect::<Option<Vec<_>>>()?;
+        Some(TlsRouteFragment {
+            root_certs: root_certs.into_certs(),
+            sni: match sni {
+                PersistedHost::Ip(ip) => Host::Ip(ip),
+                PersistedHost::Domain(domain) => Host::Domain(Arc::from(domain)),
+            },
+            alpn: AlpnList::new(alpn),
+            ech_config_list: ech_config_list.map(Arc::from),
+        })
+    }
+}
+
+impl PersistedCerts {
+    fn from_certs(certs: &RootCertificates) -> Option<Self> {
+        Some(match certs {
+            RootCertificates::Native => Self::Native,
+            RootCertificates::FromDer(der) => Self::Der(der.to_vec()),
+            certs if *certs == SIGNAL_ROOT_CERTIFICATES => Self::Signal,
+            certs if *certs == PROXY_G_ROOT_CERTIFICATES => Self::ProxyG,
+            // Any other static certificates can't be identified on import.
+            RootCertificates::FromStaticDers(_) => return None,
+        })
+    }
+
+    fn into_certs(self) -> RootCertificates {
+        match self {
+            Self::Native => RootCertificates::Native,
+            Self::Signal => SIGNAL_ROOT_CERTIFICATES,
+            Self::ProxyG => PROXY_G_ROOT_CERTIFICATES,
+            Self::Der(der) => RootCertificates::FromDer(Cow::Owned(der)),
+        }
+    }
+}
+
+impl From<&TcpRoute<IpAddr>> for PersistedTcp {
+    fn from(value: &TcpRoute<IpAddr>) -> Self {
+        let TcpRoute { address, port } = value;
+        Self {
+            address: *address,
+            port: *port,
+        }
+    }
+}
+
+impl From<PersistedTcp> for TcpRoute<IpAddr> {
+    fn from(value: PersistedTcp) -> Self {
+        let PersistedTcp { address, port } = value;
+        Self { address, port }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use assert_matches::assert_matches;
+    use const_str::ip_addr;
+    use libsignal_net_infra::route::{
+        AgedOutSuccessOrdering, AttemptOutcome, ConnectionOutcomeParams, RouteDelayPolicy as _,
+        UnsuccessfulOutcome,
+    };
+    use nonzero_ext::nonzero;
+
+    use super::*;
+
+    const AGE_CUTOFF: Duration = Duration::from_secs(300);
+    const PARAMS: ConnectionOutcomeParams = ConnectionOutcomeParams {
+        age_cutoff: AGE_CUTOFF,
+        cooldown_growth_factor: 10.0,
+        count_growth_factor: 10.0,
+        max_count: 5,
+        max_delay: Duration::from_secs(30),
+        max_entries: None,
+        aged_out_successes: AgedOutSuccessOrdering::Unranked,
+        jitter_fraction: 0.0,
+    };
+
+    fn direct_route() -> TransportRoute {
+        TlsRoute {
+            fragment: TlsRouteFragment {
+                root_certs: SIGNAL_ROOT_CERTIFICATES,
+                sni: Host::Domain("chat.example".into()),
+                alpn: Alpn::Http1_1.into(),
+                ech_config_list: Some(Arc::from(&b"ech"[..])),
+            },
+            inner: DirectOrProxyRoute::Direct(TcpRoute {
+                address: ip_addr!("192.0.2.1"),
+                port: nonzero!(443u16),
+            }),
+        }
+    }
+
+    fn proxy_route() -> TransportRoute {
+        TlsRoute {
+            fragment: TlsRouteFragment {
+                root_certs: RootCertificates::Native,
+                sni: Host::Ip(ip_addr!("192.0.2.2")),
+                alpn: AlpnList::new([Alpn::Http2, Alpn::Http1_1]),
+                ech_config_list: None,
+            },
+            inner: DirectOrProxyRoute::Proxy(ConnectionProxyRoute::Tls {
+                proxy: TlsRoute {
+                    fragment: TlsRouteFragment {
+                        root_certs: RootCertificates::FromDer(Cow::Borrowed(b"der")),
+                        sni: Host::Domain("proxy.example".into()),
+                        alpn: AlpnList::new([]),
+                        ech_config_list: None,
+                    },
+                    inner: TcpRoute {
+                        address: ip_addr!("2001:db8::1"),
+                        port: nonzero!(8443u16),
+                    },
+                },
+            }),
+        }
+    }
+
+    fn record_failures(
+        outcomes: &mut ConnectionOutcomes<TransportRoute>,
+        route: TransportRoute,
+        count: usize,
+        started: Instant,
+    ) {
+        for _ in 0..count {
+            outcomes.apply_outcome_updates(
+                [(
+                    route.clone(),
+                    AttemptOutcome {
+                        started,
+                        result: Err(UnsuccessfulOutcome),
+                    },
+                )],
+                started,
+            );
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let start = Instant::now();
+        let mut outcomes = ConnectionOutcomes::new(PARAMS);
+        record_failures(&mut outcomes, direct_route(), 2, start);
+        record_failures(&mut outcomes, proxy_route(), 1, start);
+
+        let now = start + Duration::from_secs(10);
+        let blob = export(&outcomes, now);
+
+        // Restore at some other point in time, as after a restart.
+        let restored_now = now + Duration::from_secs(1000);
+        let mut restored = ConnectionOutcomes::new(PARAMS);
+        assert_matches!(import(&mut restored, &blob, restored_now), Ok(2));
+
+        for route in [direct_route(), proxy_route()] {
+            assert_eq!(
+                restored.compute_delay(&route, restored_now),
+                outcomes.compute_delay(&route, now),
+                "{route:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn aged_out_outcomes_are_dropped() {
+        let saved = |age: Duration| {
+            serde_json::json!({
+                "route": PersistedRoute::from_route(&direct_route()).expect("can persist"),
+                "age_ms": age.as_millis() as u64,
+                "consecutive_failures": 1,
+            })
+        };
+        let blob = serde_json::json!({
+            "version": CURRENT_VERSION,
+            "outcomes": [saved(AGE_CUTOFF), saved(AGE_CUTOFF + Duration::from_secs(1))],
+        });
+
+        let mut restored = ConnectionOutcomes::new(PARAMS);
+        assert_matches!(
+            import(
+                &mut restored,
+                &serde_json::to_vec(&blob).expect("can serialize"),
+                Instant::now() + AGE_CUTOFF * 2,
+            ),
+            Ok(0)
+        );
+    }
+
+    #[test]
+    fn unknown_routes_are_ignored() {
+        let blob = serde_json::json!({
+            "version": CURRENT_VERSION,
+            "outcomes": [
+                {
+                    "route": {"type": "some_future_route", "whatever": 1},
+                    "age_ms": 1000,
+                    "consecutive_failures": 1,
+                },
+                {
+                    "route": PersistedRoute::from_route(&direct_route()).expect("can persist"),
+                    "age_ms": 1000,
+                    "consecutive_failures": 1,
+                },
+            ],
+        });
+
+        let mut restored = ConnectionOutcomes::new(PARAMS);
+        assert_matches!(
+            import(
+                &mut restored,
+                &serde_json::to_vec(&blob).expect("can serialize"),
+                Instant::now() + AGE_CUTOFF,
+            ),
+            Ok(1)
+        );
+    }
+
+    #[test]
+    fn rejects_unsupported_version() {
+        let mut restored = ConnectionOutcomes::new(PARAMS);
+        assert_matches!(
+            import(
+                &mut restored,
+                br#"{"version": 2, "outcomes": []}"#,
+                Instant::now()
+            ),
+            Err(ImportOutcomesError::UnsupportedVersion(2))
+        );
+        assert_matches!(
+            import(&mut restored, b"not json", Instant::now()),
+            Err(ImportOutcomesError::Malformed)
+        );
+    }
+}
@@ -0,0 +1,100 @@
+//
+// Copyright 2025 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+//! [`tracing`] spans for connection attempts.
+//!
+//! With the `tracing` feature disabled, the spans are zero-sized and
+//! instrumenting a future is a no-op, so callers don't need their own
+//! `cfg`s.
+
+/// A phase of a connection attempt that gets its own span.
+#[derive(Copy, Clone, Debug)]
+pub(super) enum Phase {
+    /// Looking up a hostname.
+    Resolve,
+    /// Establishing a transport connection (TCP, TLS, proxies).
+    ConnectTransport,
+    /// Upgrading a transport connection to a websocket.
+    WebSocketUpgrade,
+    /// Checking an enclave's attestation and completing the Noise handshake.
+    AttestationHandshake,
+}
+
+pub(super) use imp::*;
+
+#[cfg(feature = "tracing")]
+mod imp {
+    use std::future::Future;
+
+    use libsignal_net_infra::dns::log_safe_domain;
+
+    use super::Phase;
+
+    pub(in crate::connect_state) type Span = tracing::Span;
+
+    /// The span for a whole websocket connect, from resolving to upgrading.
+    pub(in crate::connect_state) fn connect_ws(log_tag: &str) -> Span {
+        tracing::info_span!("connect_ws", log_tag)
+    }
+
+    /// The span for an attested connect, including the websocket connect.
+    pub(in crate::connect_state) fn connect_attested_ws(log_tag: &str) -> Span {
+        tracing::info_span!("connect_attested_ws", log_tag)
+    }
+
+    /// The span for a single `phase` within a connect.
+    ///
+    /// `target` is the hostname for [`Phase::Resolve`], which is redacted
+    /// unless it's safe to log, and the log tag for the others.
+    pub(in crate::connect_state) fn phase(phase: Phase, target: &str) -> Span {
+        match phase {
+            Phase::Resolve => {
+                tracing::debug_span!("resolve", hostname = log_safe_domain(target))
+            }
+            Phase::ConnectTransport => tracing::debug_span!("connect_transport", log_tag = target),
+            Phase::WebSocketUpgrade => tracing::debug_span!("websocket_upgrade", log_tag = target),
+            Phase::AttestationHandshake => {
+                tracing::debug_span!("attestation_handshake", log_tag = target)
+            }
+        }
+    }
+
+    /// Runs `future` inside `span`.
+    pub(in crate::connect_state) fn instrument<F: Future>(
+        future: F,
+        span: Span,
+    ) -> impl Future<Output = F::Output> {
+        tracing::Instrument::instrument(future, span)
+    }
+}
+
+#[cfg(not(feature = "tracing"))]
+mod imp {
+    use std::future::Future;
+
+    use super::Phase;
+
+    #[derive(Clone, Debug)]
+    pub(in crate::connect_state) struct Span;
+
+    pub(in crate::connect_state) fn connect_ws(_log_tag: &str) -> Span {
+        Span
+    }
+
+    pub(in crate::connect_state) fn connect_attested_ws(_log_tag: &str) -> Span {
+        Span
+    }
+
+    pub(in crate::connect_state) fn phase(_phase: Phase, _target: &str) -> Span {
+        Span
+    }
+
+    pub(in crate::connect_state) fn instrument<F: Future>(
+        future: F,
+        _span: Span,
+    ) -> impl Future<Output = F::Output> {
+        future
+    }
+}
@@ -191,9 +191,14 @@ pub struct EnclaveEndpoint<'a, E: EnclaveKind> {
 }
 
 pub trait NewHandshake: EnclaveKind + Sized {
+    /// Starts a handshake with the enclave that sent `attestation_message`.
+    ///
+    /// If `handshake_rng` is provided, it's used instead of the operating
+    /// system's RNG; see [`enclave::HandshakeRng`] for the risks.
     fn new_handshake(
         params: &EndpointParams<Self>,
         attestation_message: &[u8],
+        handshake_rng: Option<enclave::HandshakeRng>,
     ) -> enclave::Result<enclave::Handshake>;
 }
 
@@ -213,12 +218,53 @@ pub enum Error {
     Protocol(AttestedProtocolError),
     /// Enclave attestation failed: {0}
     AttestationError(attest::enclave::Error),
+    /// Enclave rejected the handshake: {0}
+    HandshakeRejected(AttestedProtocolError),
     /// Connection timeout
     ConnectionTimedOut,
+    /// Could not reach any enclave
+    Unreachable,
 }
 
 impl LogSafeDisplay for Error {}
 
+impl Error {
+    /// Whether this error means the connection was lost or couldn't be made,
+    /// rather than the enclave or its messages being at fault.
+    ///
+    /// Only a lost or timed-out connection qualifies; attestation failures,
+    /// rejected handshakes, and malformed messages are not resumable. Whether
+    /// an SVR operation can be continued also depends on what it had sent;
+    /// see [`crate::svr::OperationCheckpoint::is_resumable`].
+    pub fn is_resumable(&self) -> bool {
+        match self {
+            Error::WebSocket(_)
+            | Error::Protocol(AttestedProtocolError::UnexpectedClose(_))
+            | Error::ConnectionTimedOut
+            | Error::Unreachable => true,
+            Error::WebSocketConnect(_)
+            | Error::Protocol(
+                AttestedProtocolError::ProtobufDecode | AttestedProtocolError::TextFrame,
+            )
+            | Error::AttestationError(_)
+            | Error::HandshakeRejected(_) => false,
+        }
+    }
+
+    /// Converts an error from the handshake step of an attested connection.
+    ///
+    /// A protocol error at this point means the enclave was reached but
+    /// didn't accept the handshake, which could be a version mismatch or a
+    /// client bug, so it's reported as [`Error::HandshakeRejected`] rather
+    /// than as a failure of an established connection.
+    pub(crate) fn from_handshake(error: AttestedConnectionError) -> Self {
+        match error {
+            AttestedConnectionError::Protocol(error) => Self::HandshakeRejected(error),
+            error => error.into(),
+        }
+    }
+}
+
 impl From<AttestedConnectionError> for Error {
     fn from(value: AttestedConnectionError) -> Self {
         match value {
@@ -307,6 +353,7 @@ impl NewHandshake for SgxPreQuantum {
     fn new_handshake(
         params: &EndpointParams<Self>,
         attestation_message: &[u8],
+        handshake_rng: Option<enclave::HandshakeRng>,
     ) -> enclave::Result<enclave::Handshake> {
         attest::svr2::new_handshake(
             params.mr_enclave.as_ref(),
@@ -317,6 +364,7 @@ impl NewHandshake for SgxPreQuantum {
                 .as_raft_config()
                 .expect("Raft config must be present for SGX"),
             enclave::HandshakeType::PreQuantum,
+            handshake_rng,
         )
     }
 }
@@ -325,11 +373,13 @@ impl NewHandshake for Cdsi {
     fn new_handshake(
         params: &EndpointParams<Self>,
         attestation_message: &[u8],
+        handshake_rng: Option<enclave::HandshakeRng>,
     ) -> enclave::Result<enclave::Handshake> {
         cds2::new_handshake(
             params.mr_enclave.as_ref(),
             attestation_message,
             SystemTime::now(),
+            handshake_rng,
         )
     }
 }
@@ -485,7 +535,8 @@ mod test {
             log_tag,
             do_handshake,
         )
-        .await?;
+        .await
+        .map_err(Error::from_handshake)?;
         Ok((attested, connection_info))
     }
 
@@ -49,6 +49,7 @@ const DOMAIN_CONFIG_CHAT: DomainConfig = DomainConfig {
         hostname: "chat.signal.org",
         port: DEFAULT_HTTPS_PORT,
         cert: SIGNAL_ROOT_CERTIFICATES,
+        ech_config_list: None,
         confirmation_header_name: Some(TIMESTAMP_HEADER_NAME),
         proxy: Some(ConnectionProxyConfig {
             path_prefix: "/service",
@@ -70,6 +71,7 @@ const DOMAIN_CONFIG_CHAT_STAGING: DomainConfig = DomainConfig {
         hostname: "chat.staging.signal.org",
         port: DEFAULT_HTTPS_PORT,
         cert: SIGNAL_ROOT_CERTIFICATES,
+        ech_config_list: None,
         confirmation_header_name: Some(TIMESTAMP_HEADER_NAME),
         proxy: Some(ConnectionProxyConfig {
             path_prefix: "/service-staging",
@@ -83,6 +85,7 @@ const DOMAIN_CONFIG_CDSI: DomainConfig = DomainConfig {
         hostname: "cdsi.signal.org",
         port: DEFAULT_HTTPS_PORT,
         cert: SIGNAL_ROOT_CERTIFICATES,
+        ech_config_list: None,
         confirmation_header_name: None,
         proxy: Some(ConnectionProxyConfig {
             path_prefix: "/cdsi",
@@ -98,6 +101,7 @@ const DOMAIN_CONFIG_CDSI_STAGING: DomainConfig = DomainConfig {
         hostname: "cdsi.staging.signal.org",
         port: DEFAULT_HTTPS_PORT,
         cert: SIGNAL_ROOT_CERTIFICATES,
+        ech_config_list: None,
         confirmation_header_name: None,
         proxy: Some(ConnectionProxyConfig {
             path_prefix: "/cdsi-staging",
@@ -113,6 +117,7 @@ const DOMAIN_CONFIG_SVR2: DomainConfig = DomainConfig {
         hostname: "svr2.signal.org",
         port: DEFAULT_HTTPS_PORT,
         cert: SIGNAL_ROOT_CERTIFICATES,
+        ech_config_list: None,
         confirmation_header_name: None,
         proxy: Some(ConnectionProxyConfig {
             path_prefix: "/svr2",
@@ -128,6 +133,7 @@ const DOMAIN_CONFIG_SVR2_STAGING: DomainConfig = DomainConfig {
         hostname: "svr2.staging.signal.org",
         port: DEFAULT_HTTPS_PORT,
         cert: SIGNAL_ROOT_CERTIFICATES,
+        ech_config_list: None,
         confirmation_header_name: None,
         proxy: Some(ConnectionProxyConfig {
             path_prefix: "/svr2-staging",
@@ -226,6 +232,11 @@ pub struct ConnectionConfig {
     pub port: NonZeroU16,
     /// Which certificates to use when connecting to the resource.
     pub cert: RootCertificates,
+    /// The resource's ECHConfigList, if direct connections should use
+    /// Encrypted Client Hello.
+    ///
+    /// See [`TlsRouteFragment::ech_config_list`](crate::infra::route::TlsRouteFragment::ech_config_list).
+    pub ech_config_list: Option<&'static [u8]>,
     /// A header to look for that indicates that the resource was reached.
     ///
     /// If this is `Some()`, then the presence of the header in an HTTP response
@@ -253,6 +264,21 @@ pub struct KeyTransConfig {
     pub auditor_key_material: &'static [u8; 32],
 }
 
+/// A problem with a [`DomainConfig`] found by [`DomainConfig::validate`].
+#[derive(Debug, PartialEq, Eq, thiserror::Error, displaydoc::Display)]
+pub enum ConfigError {
+    /// hostname is empty
+    EmptyHostname,
+    /// {0:?} is not a valid hostname
+    InvalidHostname(&'static str),
+    /// {0:?} is not a valid header name
+    InvalidConfirmationHeaderName(&'static str),
+    /// proxy path prefix {0:?} must start with '/'
+    InvalidProxyPathPrefix(&'static str),
+    /// {0} proxy has no domain names to connect to
+    NoProxyHostnames(RouteType),
+}
+
 impl DomainConfig {
     pub fn static_fallback(&self) -> (&'static str, LookupResult) {
         (
@@ -260,6 +286,83 @@ impl DomainConfig {
             LookupResult::new(DnsSource::Static, self.ip_v4.into(), self.ip_v6.into()),
         )
     }
+
+    /// Checks for mistakes in the config that would otherwise only show up
+    /// when connecting, often as a failure to produce any routes at all.
+    ///
+    /// This doesn't check that the configured names actually resolve.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let Self {
+            connect:
+                ConnectionConfig {
+                    hostname,
+                    port: _,
+                    cert: _,
+                    ech_config_list: _,
+                    confirmation_header_name,
+                    proxy,
+                },
+            ip_v4: _,
+            ip_v6: _,
+        } = self;
+
+        if hostname.is_empty() {
+            return Err(ConfigError::EmptyHostname);
+        }
+        validate_hostname(hostname)?;
+
+        if let Some(header_name) = confirmation_header_name {
+            // HeaderName::from_static, used when connecting, panics on names
+            // that aren't already lowercase.
+            if !matches!(
+                http::HeaderName::from_bytes(header_name.as_bytes()),
+                Ok(parsed) if parsed.as_str() == *header_name
+            ) {
+                return Err(ConfigError::InvalidConfirmationHeaderName(header_name));
+            }
+        }
+
+        if let Some(ConnectionProxyConfig {
+            path_prefix,
+            configs,
+        }) = proxy
+        {
+            if !path_prefix.starts_with('/') {
+                return Err(ConfigError::InvalidProxyPathPrefix(path_prefix));
+            }
+            for ProxyConfig {
+                route_type,
+                http_host,
+                sni_list,
+                certs: _,
+            } in configs
+            {
+                validate_hostname(http_host)?;
+                if sni_list.is_empty() {
+                    return Err(ConfigError::NoProxyHostnames(*route_type));
+                }
+                sni_list.iter().try_for_each(|sni| validate_hostname(sni))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn validate_hostname(hostname: &'static str) -> Result<(), ConfigError> {
+    let is_valid_label = |label: &str| {
+        !label.is_empty()
+            && label.len() <= 63
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+            && label
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'-')
+    };
+    if hostname.len() > 253 || !hostname.split('.').all(is_valid_label) {
+        return Err(ConfigError::InvalidHostname(hostname));
+    }
+    Ok(())
 }
 
 impl ConnectionConfig {
@@ -312,6 +415,7 @@ impl ConnectionConfig {
             hostname,
             port,
             cert,
+            ech_config_list,
             confirmation_header_name: _,
             proxy,
         } = self;
@@ -352,15 +456,20 @@ impl ConnectionConfig {
 
         let hostname = Arc::<str>::from(*hostname);
 
+        let mut direct_provider = TlsRouteProvider::new(
+            cert.clone(),
+            Host::Domain(Arc::clone(&hostname)),
+            DirectTcpRouteProvider::new(Arc::clone(&hostname), *port),
+        );
+        if let Some(ech_config_list) = ech_config_list {
+            direct_provider = direct_provider.with_ech_config_list(Arc::from(*ech_config_list));
+        }
+
         HttpsProvider::new(
-            Arc::clone(&hostname),
+            hostname,
             HttpVersion::Http1_1,
             DomainFrontRouteProvider::new(HttpVersion::Http1_1, domain_front_configs),
-            TlsRouteProvider::new(
-                cert.clone(),
-                Host::Domain(Arc::clone(&hostname)),
-                DirectTcpRouteProvider::new(hostname, *port),
-            ),
+            direct_provider,
         )
     }
 }
@@ -540,7 +649,7 @@ mod test {
         UnresolvedHost,
     };
     use libsignal_net_infra::Alpn;
-    use test_case::test_matrix;
+    use test_case::{test_case, test_matrix};
 
     use super::*;
 
@@ -592,6 +701,33 @@ mod test {
         }
     }
 
+    #[test_matrix([
+        &DOMAIN_CONFIG_CHAT,
+        &DOMAIN_CONFIG_CHAT_STAGING,
+        &DOMAIN_CONFIG_CDSI,
+        &DOMAIN_CONFIG_CDSI_STAGING,
+        &DOMAIN_CONFIG_SVR2,
+        &DOMAIN_CONFIG_SVR2_STAGING
+    ])]
+    fn builtin_configs_are_valid(config: &DomainConfig) {
+        assert_eq!(config.validate(), Ok(()));
+    }
+
+    #[test_case(|c| c.connect.hostname = "" => Err(ConfigError::EmptyHostname); "empty hostname")]
+    #[test_case(|c| c.connect.hostname = "chat..signal.org" => Err(ConfigError::InvalidHostname("chat..signal.org")); "empty label")]
+    #[test_case(|c| c.connect.hostname = "https://chat.signal.org" => Err(ConfigError::InvalidHostname("https://chat.signal.org")); "url instead of hostname")]
+    #[test_case(|c| c.connect.confirmation_header_name = Some("X-Signal-Timestamp") => Err(ConfigError::InvalidConfirmationHeaderName("X-Signal-Timestamp")); "uppercase header name")]
+    #[test_case(|c| c.connect.confirmation_header_name = Some("bad header") => Err(ConfigError::InvalidConfirmationHeaderName("bad header")); "header name with space")]
+    #[test_case(|c| c.connect.proxy.as_mut().unwrap().path_prefix = "service" => Err(ConfigError::InvalidProxyPathPrefix("service")); "relative path prefix")]
+    #[test_case(|c| c.connect.proxy.as_mut().unwrap().configs[1].sni_list = &[] => Err(ConfigError::NoProxyHostnames(RouteType::ProxyG)); "proxy without snis")]
+    #[test_case(|c| c.connect.proxy.as_mut().unwrap().configs[0].http_host = "-proxy.example" => Err(ConfigError::InvalidHostname("-proxy.example")); "invalid proxy host")]
+    #[test_case(|c| c.connect.proxy = None => Ok(()); "no proxy")]
+    fn validate_config(modify: fn(&mut DomainConfig)) -> Result<(), ConfigError> {
+        let mut config = DOMAIN_CONFIG_CHAT;
+        modify(&mut config);
+        config.validate()
+    }
+
     #[test_matrix([true, false])]
     fn connect_config_routes_enable_domain_fronting(enable_domain_fronting: bool) {
         const PORT: NonZeroU16 = nonzero!(123u16);
@@ -599,6 +735,7 @@ mod test {
             hostname: "host",
             port: PORT,
             cert: RootCertificates::Native,
+            ech_config_list: None,
             confirmation_header_name: None,
             proxy: Some(ConnectionProxyConfig {
                 path_prefix: "proxy-prefix",
@@ -635,7 +772,8 @@ mod test {
                 fragment: TlsRouteFragment {
                     root_certs: RootCertificates::Native,
                     sni: Host::Domain("host".into()),
-                    alpn: Some(Alpn::Http1_1),
+                    alpn: Alpn::Http1_1.into(),
+                    ech_config_list: None,
                 },
                 inner: TcpRoute {
                     address: UnresolvedHost::from(Arc::from("host")),
@@ -652,6 +790,31 @@ mod test {
         };
     }
 
+    #[test]
+    fn connect_config_routes_offer_ech_on_direct_routes() {
+        const ECH_CONFIG_LIST: &[u8] = b"ech config list";
+        let config = ConnectionConfig {
+            ech_config_list: Some(ECH_CONFIG_LIST),
+            ..DOMAIN_CONFIG_CHAT.connect
+        };
+        let routes = config
+            .route_provider(EnableDomainFronting::OneDomainPerProxy)
+            .routes(&FakeContext::new())
+            .collect_vec();
+
+        let (direct, proxied) = routes
+            .split_first()
+            .expect("has a direct route and proxy routes");
+        assert_eq!(
+            direct.inner.fragment.ech_config_list.as_deref(),
+            Some(ECH_CONFIG_LIST)
+        );
+        assert!(!proxied.is_empty());
+        for route in proxied {
+            assert_eq!(route.inner.fragment.ech_config_list, None, "{route:?}");
+        }
+    }
+
     #[tokio::test]
     #[test_matrix([&DOMAIN_CONFIG_CHAT, &DOMAIN_CONFIG_CHAT_STAGING, &DOMAIN_CONFIG_CDSI, &DOMAIN_CONFIG_CDSI_STAGING])]
     async fn live_resolve_eq_static_resolution(config: &DomainConfig) {
@@ -5,7 +5,10 @@
 
 use std::panic::UnwindSafe;
 
+use futures_util::stream::BoxStream;
+use futures_util::{Stream, StreamExt as _};
 use static_assertions::assert_impl_all;
+use tokio::time::{Duration, Instant};
 
 mod error;
 pub use error::*;
@@ -27,8 +30,11 @@ pub use session_id::*;
 #[derive(Debug)]
 pub struct RegistrationService<'c> {
     session: RegistrationSession,
+    /// When `session` was received from the server.
+    session_received_at: Instant,
     connection: RegistrationConnection<'c>,
     session_id: SessionId,
+    body_compression: BodyCompression,
 }
 
 assert_impl_all!(RegistrationService<'static>: UnwindSafe);
@@ -47,6 +53,8 @@ impl<'c> RegistrationService<'c> {
 
         let (connection, response) =
             RegistrationConnection::connect_and_send(connect_chat, create_session.into()).await?;
+        let mut body_compression = BodyCompression::default();
+        body_compression.update_from_response(&response.headers);
 
         let RegistrationResponse {
             session_id,
@@ -60,6 +68,8 @@ impl<'c> RegistrationService<'c> {
             session_id,
             connection,
             session,
+            session_received_at: Instant::now(),
+            body_compression,
         })
     }
 
@@ -82,6 +92,8 @@ impl<'c> RegistrationService<'c> {
             .into(),
         )
         .await?;
+        let mut body_compression = BodyCompression::default();
+        body_compression.update_from_response(&response.headers);
 
         let RegistrationResponse {
             session_id: _,
@@ -93,19 +105,97 @@ impl<'c> RegistrationService<'c> {
             session_id,
             connection,
             session,
+            session_received_at: Instant::now(),
+            body_compression,
         })
     }
 
+    /// Sets the encoding to compress request bodies with, or `None` to send
+    /// them uncompressed.
+    ///
+    /// Bodies are only compressed once the server has advertised support for
+    /// `compression` in its `accept-encoding` response header; until then, or
+    /// if it stops advertising it, requests are sent uncompressed.
+    pub fn set_request_compression(&mut self, compression: Option<RequestCompression>) {
+        self.body_compression.set_preferred(compression);
+    }
+
     /// Returns the server identifier for the bound session.
     pub fn session_id(&self) -> &SessionId {
         &self.session_id
     }
 
+    /// Sets how long to give a dropped connection to recover before
+    /// reconnecting.
+    ///
+    /// Momentary drops (e.g. during a cell handoff) can resolve on their own;
+    /// with a non-zero grace period, requests are retried on the existing
+    /// connection if it's still up once the period has passed. Defaults to
+    /// zero, which reconnects immediately.
+    pub fn set_reconnect_grace_period(&mut self, grace_period: std::time::Duration) {
+        self.connection.set_reconnect_grace_period(grace_period);
+    }
+
+    /// Limits how many times in a row a lost connection is replaced.
+    ///
+    /// Once `max_reconnects` new connections have been made without a
+    /// successful request in between, the next loss fails the request with
+    /// [`RequestError::Unknown`] instead of reconnecting again. A successful
+    /// request resets the count. Defaults to `None`, which never gives up.
+    pub fn set_max_reconnects(&mut self, max_reconnects: Option<u32>) {
+        self.connection.set_max_reconnects(max_reconnects);
+    }
+
+    /// Hints whether another request is expected soon, to decide how long to
+    /// keep the idle connection open.
+    ///
+    /// Pass `true` when the next request is known to be coming (e.g. the user
+    /// is about to enter the code that was just requested) to keep the
+    /// connection warm for longer than usual, or `false` to close it as soon
+    /// as any in-progress request finishes. The hint is cleared by the next
+    /// request.
+    pub fn hint_next_request_imminent(&mut self, imminent: bool) {
+        self.connection.hint_next_request_imminent(imminent);
+    }
+
+    /// Replaces the [`ConnectChat`] used to reach the server.
+    ///
+    /// The session itself lives on the server, so it can be continued over a
+    /// different connection, for example after the device switches networks.
+    /// The next request connects using `connect_chat`, once any requests
+    /// still in flight on the current connection have finished.
+    pub fn set_connect_chat(
+        &mut self,
+        connect_chat: Box<dyn ConnectChat + Send + Sync + UnwindSafe + 'c>,
+    ) {
+        self.connection.set_connect_chat(connect_chat);
+    }
+
     /// Returns the last known server-reported state of the session.
     pub fn session_state(&self) -> &RegistrationSession {
         &self.session
     }
 
+    /// Returns how long until each rate-limited action can be taken, based on
+    /// the last known state of the session.
+    ///
+    /// The server's timers are counted down from when the session state was
+    /// received, so this can be polled (for example, to decide when to
+    /// re-enable a "resend code" button) without contacting the server.
+    pub fn rate_limit_state(&self) -> RateLimitState {
+        self.session
+            .rate_limit_state(self.session_received_at.elapsed())
+    }
+
+    /// Re-fetches the state of the session from the server.
+    ///
+    /// On success, the updated state is accessible via
+    /// [`Self::session_state`]. This method will retry internally if transient
+    /// errors are encountered.
+    pub async fn refresh_session(&mut self) -> Result<(), RequestError<ResumeSessionError>> {
+        self.submit_request(GetSession {}).await.map_err(Into::into)
+    }
+
     pub async fn submit_captcha(
         &mut self,
         captcha_value: &str,
@@ -154,6 +244,56 @@ impl<'c> RegistrationService<'c> {
         .map_err(Into::into)
     }
 
+    /// Submits several updates to the session, in order.
+    ///
+    /// The server doesn't accept batched updates, so these are sent one after
+    /// another over the same connection, stopping at the first failure. The
+    /// saved session state is replaced once, after the last request that
+    /// succeeded, so on partial failure it reflects the updates that were
+    /// applied. This method will retry internally if transient errors are
+    /// encountered.
+    pub async fn submit_batch<'a>(
+        &mut self,
+        updates: impl IntoIterator<Item = SessionUpdate<'a>>,
+    ) -> Result<(), BatchRequestError<UpdateSessionError>> {
+        let Self {
+            connection,
+            session,
+            session_received_at,
+            session_id,
+            body_compression,
+        } = self;
+
+        let mut latest_session = None;
+        let mut result = Ok(());
+        for (index, update) in updates.into_iter().enumerate() {
+            match send_session_request(
+                connection,
+                session_id,
+                body_compression,
+                UpdateRegistrationSession::from(update),
+            )
+            .await
+            {
+                Ok(response_session) => latest_session = Some(response_session),
+                Err(error) => {
+                    log::info!("batched session update {index} failed");
+                    result = Err(BatchRequestError {
+                        index,
+                        error: error.into(),
+                    });
+                    break;
+                }
+            }
+        }
+
+        if let Some(latest_session) = latest_session {
+            *session = latest_session;
+            *session_received_at = Instant::now();
+        }
+        result
+    }
+
     pub async fn submit_verification_code(
         &mut self,
         code: &str,
@@ -163,11 +303,99 @@ impl<'c> RegistrationService<'c> {
             .map_err(Into::into)
     }
 
+    /// Sends each of `steps` in turn, yielding the session state from each
+    /// response.
+    ///
+    /// All the requests share one connection. If the server says to retry a
+    /// request later, the stream waits and resends it, as long as the total
+    /// time spent waiting stays within `max_total_backoff`; the budget is
+    /// shared by every request in the stream. Any other error, or one that
+    /// would exceed the budget, is yielded as the last item of the stream and
+    /// no further steps are sent.
+    ///
+    /// As with the single-request methods, the saved session state is
+    /// updated after each successful request.
+    pub fn request_stream<'s, 'a: 's>(
+        &'s mut self,
+        steps: impl Stream<Item = SessionStep<'a>> + Send + 's,
+        max_total_backoff: Duration,
+    ) -> BoxStream<'s, Result<RegistrationSession, SessionStepError>> {
+        futures_util::stream::unfold(
+            Some((self, Box::pin(steps), max_total_backoff)),
+            |state| async move {
+                let (service, mut steps, mut remaining_backoff) = state?;
+                let step = steps.next().await?;
+                match service.submit_step(step, &mut remaining_backoff).await {
+                    Ok(session) => Some((Ok(session), Some((service, steps, remaining_backoff)))),
+                    Err(e) => {
+                        log::info!("ending registration request stream after a failure");
+                        Some((Err(e), None))
+                    }
+                }
+            },
+        )
+        .boxed()
+    }
+
+    /// Sends a single [`SessionStep`], waiting and retrying when asked to by
+    /// the server for as long as `remaining_backoff` allows.
+    async fn submit_step(
+        &mut self,
+        step: SessionStep<'_>,
+        remaining_backoff: &mut Duration,
+    ) -> Result<RegistrationSession, SessionStepError> {
+        loop {
+            let result = match step {
+                SessionStep::RefreshSession => self.submit_request(GetSession {}).await,
+                SessionStep::Update(update) => {
+                    self.submit_request(UpdateRegistrationSession::from(update))
+                        .await
+                }
+                SessionStep::RequestVerificationCode { transport, client } => {
+                    self.submit_request(RequestVerificationCode { transport, client })
+                        .await
+                }
+                SessionStep::SubmitVerificationCode(code) => {
+                    self.submit_request(SubmitVerificationCode { code }).await
+                }
+            };
+
+            let error = match result {
+                Ok(()) => return Ok(self.session.clone()),
+                Err(RequestError::Other(SessionRequestError::RetryLater(retry_later))) => {
+                    let delay = Duration::from_secs(retry_later.retry_after_seconds.into());
+                    if let Some(remaining) = remaining_backoff.checked_sub(delay) {
+                        log::info!("server asked to retry later; waiting {delay:?}");
+                        *remaining_backoff = remaining;
+                        tokio::time::sleep(delay).await;
+                        continue;
+                    }
+                    RequestError::Other(SessionRequestError::RetryLater(retry_later))
+                }
+                Err(e) => e,
+            };
+
+            return Err(match step {
+                SessionStep::RefreshSession => SessionStepError::RefreshSession(error.into()),
+                SessionStep::Update(_) => SessionStepError::Update(error.into()),
+                SessionStep::RequestVerificationCode { .. } => {
+                    SessionStepError::RequestVerificationCode(error.into())
+                }
+                SessionStep::SubmitVerificationCode(_) => {
+                    SessionStepError::SubmitVerificationCode(error.into())
+                }
+            });
+        }
+    }
+
     /// Sends a request for an established session.
     ///
     /// On success, the state of the session as reported by the server is saved
     /// (and accessible via [`Self::session_state`]). This method will retry
-    /// internally if transient errors are encountered.
+    /// internally if transient errors are encountered. If the connection is lost
+    /// while a non-idempotent request is in flight, it is not resent and
+    /// [`RequestError::OutcomeUnknown`] is returned; callers can fetch the
+    /// session to find out whether the server applied it.
     async fn submit_request<R: Request>(
         &mut self,
         request: R,
@@ -175,41 +403,59 @@ impl<'c> RegistrationService<'c> {
         let Self {
             connection,
             session,
+            session_received_at,
             session_id,
+            body_compression,
         } = self;
-        log::info!(
-            "sending {request_type} on registration session {session_id}",
-            request_type = std::any::type_name::<R>()
-        );
-
-        let response = connection
-            .submit_chat_request(
-                RegistrationRequest {
-                    session_id,
-                    request,
-                }
-                .into(),
-            )
-            .await?;
-
-        log::info!(
-            "{request_type} succeeded",
-            request_type = std::any::type_name::<R>()
-        );
-        let RegistrationResponse {
-            session_id: _,
-            session: response_session,
-        } = response.try_into_response()?;
 
-        *session = response_session;
+        *session = send_session_request(connection, session_id, body_compression, request).await?;
+        *session_received_at = Instant::now();
         Ok(())
     }
 }
 
-#[cfg(test)]
-mod testutil {
+/// Sends a request for an established session and returns the session state
+/// from the response.
+async fn send_session_request<R: Request>(
+    connection: &mut RegistrationConnection<'_>,
+    session_id: &SessionId,
+    body_compression: &mut BodyCompression,
+    request: R,
+) -> Result<RegistrationSession, RequestError<SessionRequestError>> {
+    log::info!(
+        "sending {request_type} on registration session {session_id}",
+        request_type = std::any::type_name::<R>()
+    );
+
+    let response = connection
+        .submit_chat_request(
+            RegistrationRequest {
+                session_id,
+                request,
+            }
+            .into_chat_request(body_compression.negotiated()),
+        )
+        .await?;
+    body_compression.update_from_response(&response.headers);
+
+    log::info!(
+        "{request_type} succeeded",
+        request_type = std::any::type_name::<R>()
+    );
+    let RegistrationResponse {
+        session_id: _,
+        session: response_session,
+    } = response.try_into_response()?;
+
+    Ok(response_session)
+}
+
+#[cfg(any(test, feature = "test-util"))]
+pub mod testutil {
     use std::convert::Infallible;
+    #[cfg(test)]
     use std::future::Future;
+    #[cfg(test)]
     use std::marker::PhantomData;
 
     use futures_util::future::BoxFuture;
@@ -218,12 +464,118 @@ mod testutil {
 
     use crate::chat::fake::FakeChatRemote;
     use crate::chat::ws2::ListenerEvent;
-    use crate::chat::{ChatConnection, ConnectError as ChatConnectError};
-    use crate::registration::ConnectChat;
+    use crate::chat::{ChatConnection, ConnectError as ChatConnectError, ResponseProto};
+    use crate::registration::{ConnectChat, RegistrationResponse, RegistrationSession};
 
     /// Fake [`ConnectChat`] impl that writes the remote end to a channel.
-    pub(super) struct FakeChatConnect {
-        pub(super) remote: mpsc::UnboundedSender<FakeChatRemote>,
+    pub struct FakeChatConnect {
+        pub remote: mpsc::UnboundedSender<FakeChatRemote>,
+    }
+
+    /// A stand-in for the registration endpoints of the chat server that
+    /// answers a fixed script of requests.
+    ///
+    /// Connections are made with the [`FakeChatConnect`] returned from
+    /// [`FakeRegistrationServer::new`]. If the client reconnects partway
+    /// through, the script picks up where it left off on the new connection.
+    pub struct FakeRegistrationServer {
+        remotes: mpsc::UnboundedReceiver<FakeChatRemote>,
+        current: Option<FakeChatRemote>,
+    }
+
+    /// A request the [`FakeRegistrationServer`] expects next, and what to
+    /// answer it with.
+    #[derive(Debug)]
+    pub struct ScriptedExchange {
+        pub method: http::Method,
+        pub path: String,
+        response: ResponseProto,
+    }
+
+    impl ScriptedExchange {
+        /// Answers with the state of a session, like a successful request
+        /// does.
+        pub fn session(
+            method: http::Method,
+            path: impl Into<String>,
+            session_id: &str,
+            session: RegistrationSession,
+        ) -> Self {
+            Self {
+                method,
+                path: path.into(),
+                response: RegistrationResponse {
+                    session_id: session_id.to_owned(),
+                    session,
+                }
+                .into_websocket_response(0),
+            }
+        }
+
+        /// Answers with an empty response with the given status.
+        pub fn status(method: http::Method, path: impl Into<String>, status: u16) -> Self {
+            Self {
+                method,
+                path: path.into(),
+                response: ResponseProto {
+                    id: None,
+                    status: Some(status.into()),
+                    message: None,
+                    headers: vec![],
+                    body: None,
+                },
+            }
+        }
+    }
+
+    impl FakeRegistrationServer {
+        pub fn new() -> (Self, FakeChatConnect) {
+            let (remote, remotes) = mpsc::unbounded_channel();
+            (
+                Self {
+                    remotes,
+                    current: None,
+                },
+                FakeChatConnect { remote },
+            )
+        }
+
+        /// Answers each request in turn according to `script`.
+        ///
+        /// Panics if a request doesn't match the next [`ScriptedExchange`].
+        /// Returns once the script is finished, leaving the connection open
+        /// until the server is dropped, so `serve` can be called again to
+        /// continue the flow.
+        pub async fn serve(&mut self, script: impl IntoIterator<Item = ScriptedExchange>) {
+            let Self { remotes, current } = self;
+            for exchange in script {
+                let ScriptedExchange {
+                    method,
+                    path,
+                    mut response,
+                } = exchange;
+                let (remote, request) = loop {
+                    let remote = match current.take() {
+                        Some(remote) => remote,
+                        None => remotes.recv().await.expect("client connects"),
+                    };
+                    // If the client disconnected, it'll reconnect to retry.
+                    if let Some(request) = remote.receive_request().await.expect("valid request") {
+                        break (remote, request);
+                    }
+                };
+                assert_eq!(
+                    (request.verb(), request.path()),
+                    (method.as_str(), path.as_str()),
+                    "unexpected request"
+                );
+                response.id = request.id;
+                remote
+                    .send_response(response)
+                    .expect("client still connected");
+                *current = Some(remote);
+            }
+        }
     }
 
     pub(super) struct DropOnDisconnect<T>(Option<T>);
@@ -268,14 +620,17 @@ mod testutil {
     }
 
     /// [`ConnectChat`] impl that wraps a [`Fn`].
+    #[cfg(test)]
     pub(super) struct ConnectChatFn<'a, F>(F, PhantomData<&'a ()>);
 
+    #[cfg(test)]
     impl<F> ConnectChatFn<'_, F> {
         pub(super) fn new(f: F) -> Self {
             Self(f, PhantomData)
         }
     }
 
+    #[cfg(test)]
     impl<'a, F, Fut> ConnectChat for ConnectChatFn<'a, F>
     where
         F: Fn(oneshot::Sender<Infallible>) -> Fut + Send,
@@ -295,10 +650,12 @@ mod test {
     use std::str::FromStr as _;
 
     use assert_matches::assert_matches;
+    use test_case::test_case;
     use tokio::sync::mpsc;
 
     use super::*;
-    use crate::proto::chat_websocket::WebSocketRequestMessage;
+    use crate::chat::fake::FakeChatRemote;
+    use crate::proto::chat_websocket::{WebSocketRequestMessage, WebSocketResponseMessage};
     use crate::registration::testutil::FakeChatConnect;
 
     #[test_log::test(tokio::test(start_paused = true))]
@@ -360,6 +717,259 @@ mod test {
         assert_eq!(service.session_state(), &make_session())
     }
 
+    // Uses a real local socket, so time can't be paused.
+    #[test_log::test(tokio::test)]
+    async fn create_session_with_connect_state() {
+        use futures_util::{SinkExt as _, StreamExt as _};
+        use libsignal_net_infra::certs::RootCertificates;
+        use libsignal_net_infra::dns::lookup_result::LookupResult;
+        use libsignal_net_infra::dns::DnsResolver;
+        use libsignal_net_infra::errors::TransportConnectError;
+        use libsignal_net_infra::host::Host;
+        use libsignal_net_infra::route::testutils::ConnectFn;
+        use libsignal_net_infra::route::{
+            DirectOrProxyRoute, HttpRouteFragment, HttpsTlsRoute, TcpRoute, TlsRoute,
+            TlsRouteFragment, UnresolvedHost, DEFAULT_HTTPS_PORT,
+        };
+        use libsignal_net_infra::utils::ObservableEvent;
+        use libsignal_net_infra::ws::WebSocketConnectError;
+        use libsignal_net_infra::Alpn;
+        use prost::Message as _;
+
+        use crate::chat::MessageProto;
+        use crate::connect_state::{ConnectState, SUGGESTED_CONNECT_CONFIG};
+        use crate::env::UserAgent;
+        use crate::proto::chat_websocket::web_socket_message::Type as MessageType;
+
+        const CHAT_DOMAIN: &str = "test.signal.org";
+        const SESSION_ID: &str = "sessionId";
+        let make_session = || RegistrationSession {
+            allowed_to_request_code: true,
+            ..Default::default()
+        };
+
+        // Stands in for the chat server, answering the first request.
+        let listener = tokio::net::TcpListener::bind((std::net::Ipv4Addr::LOCALHOST, 0))
+            .await
+            .expect("can bind");
+        let server_addr = listener.local_addr().expect("bound");
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.expect("can accept");
+            let mut ws = tokio_tungstenite::accept_async(stream)
+                .await
+                .expect("can upgrade");
+            let request = loop {
+                let message = ws.next().await.expect("open").expect("can read");
+                if let tungstenite::Message::Binary(message) = message {
+                    break MessageProto::decode(&*message)
+                        .expect("valid proto")
+                        .request
+                        .expect("is a request");
+                }
+            };
+            let response = MessageProto {
+                r#type: Some(MessageType::Response.into()),
+                request: None,
+                response: Some(
+                    RegistrationResponse {
+                        session_id: SESSION_ID.to_owned(),
+                        session: make_session(),
+                    }
+                    .into_websocket_response(request.id()),
+                ),
+            };
+            ws.send(tungstenite::Message::Binary(response.encode_to_vec()))
+                .await
+                .expect("can send");
+            // Keep the connection open until the client is done with it.
+            while let Some(Ok(_)) = ws.next().await {}
+        });
+
+        // Skip TLS by connecting to the local server directly.
+        let connect_state = ConnectState::new_with_transport_connector(
+            SUGGESTED_CONNECT_CONFIG,
+            ConnectFn(move |_inner, _route, _log_tag| async move {
+                tokio::net::TcpStream::connect(server_addr)
+                    .await
+                    .map_err(|_| {
+                        WebSocketConnectError::Transport(TransportConnectError::TcpConnectionFailed)
+                    })
+            }),
+        );
+        let dns_resolver = DnsResolver::new_from_static_map(std::collections::HashMap::from([(
+            CHAT_DOMAIN,
+            LookupResult::localhost(),
+        )]));
+        let network_change_event = ObservableEvent::new();
+
+        let connect_chat = ConnectChatWithState {
+            connect_state: &connect_state,
+            dns_resolver: &dns_resolver,
+            network_change_event: &network_change_event,
+            confirmation_header_name: None,
+            route_provider: vec![HttpsTlsRoute {
+                fragment: HttpRouteFragment {
+                    host_header: CHAT_DOMAIN.into(),
+                    path_prefix: "".into(),
+                    front_name: None,
+                },
+                inner: TlsRoute {
+                    fragment: TlsRouteFragment {
+                        root_certs: RootCertificates::Native,
+                        sni: Host::Domain(CHAT_DOMAIN.into()),
+                        alpn: Alpn::Http1_1.into(),
+                        ech_config_list: None,
+                    },
+                    inner: DirectOrProxyRoute::Direct(TcpRoute {
+                        address: UnresolvedHost(CHAT_DOMAIN.into()),
+                        port: DEFAULT_HTTPS_PORT,
+                    }),
+                },
+            }],
+            user_agent: UserAgent::with_libsignal_version("test"),
+            ws_config: crate::chat::ws2::Config {
+                local_idle_timeout: Duration::from_secs(60),
+                remote_idle_timeout: Duration::from_secs(60),
+                initial_request_id: 0,
+                subprotocols: &[],
+            },
+        };
+
+        let service = RegistrationService::create_session(
+            CreateSession {
+                number: "+18005550101".to_owned(),
+                ..Default::default()
+            },
+            Box::new(connect_chat),
+        )
+        .await
+        .expect("can create session");
+
+        assert_eq!(**service.session_id(), SESSION_ID);
+        assert_eq!(service.session_state(), &make_session());
+    }
+
+    #[test_log::test(tokio::test(start_paused = true))]
+    async fn full_registration_flow() {
+        use crate::registration::testutil::{FakeRegistrationServer, ScriptedExchange};
+
+        const SESSION_ID: &str = "sessionId";
+        const SESSION_PATH: &str = "/v1/verification/session/sessionId";
+        const CODE_PATH: &str = "/v1/verification/session/sessionId/code";
+
+        let (mut server, fake_connect) = FakeRegistrationServer::new();
+        let server = tokio::spawn(async move {
+            server
+                .serve([
+                    ScriptedExchange::session(
+                        http::Method::POST,
+                        "/v1/verification/session",
+                        SESSION_ID,
+                        RegistrationSession::default(),
+                    ),
+                    ScriptedExchange::session(
+                        http::Method::PATCH,
+                        SESSION_PATH,
+                        SESSION_ID,
+                        RegistrationSession {
+                            allowed_to_request_code: true,
+                            ..Default::default()
+                        },
+                    ),
+                    ScriptedExchange::session(
+                        http::Method::POST,
+                        CODE_PATH,
+                        SESSION_ID,
+                        RegistrationSession {
+                            allowed_to_request_code: true,
+                            next_verification_attempt: Some(Duration::ZERO),
+                            ..Default::default()
+                        },
+                    ),
+                    ScriptedExchange::session(
+                        http::Method::PUT,
+                        CODE_PATH,
+                        SESSION_ID,
+                        RegistrationSession {
+                            verified: true,
+                            ..Default::default()
+                        },
+                    ),
+                ])
+                .await;
+            server
+        });
+
+        let mut service = RegistrationService::create_session(
+            CreateSession {
+                number: "+18005550101".to_owned(),
+                ..Default::default()
+            },
+            Box::new(fake_connect),
+        )
+        .await
+        .expect("can create session");
+        assert_eq!(**service.session_id(), SESSION_ID);
+        assert!(!service.session_state().allowed_to_request_code);
+
+        service
+            .submit_captcha("captcha")
+            .await
+            .expect("can submit captcha");
+        assert!(service.session_state().allowed_to_request_code);
+
+        service
+            .request_verification_code(VerificationTransport::Sms, "client")
+            .await
+            .expect("can request code");
+        assert_eq!(
+            service.session_state().next_verification_attempt,
+            Some(Duration::ZERO)
+        );
+
+        service
+            .submit_verification_code("123456")
+            .await
+            .expect("can submit code");
+        assert!(service.session_state().verified);
+
+        // Keep the server alive until the client is done with it.
+        let _server = server.await.expect("followed the script");
+    }
+
+    #[test_log::test(tokio::test(start_paused = true))]
+    async fn create_session_in_unexpected_environment() {
+        let (fake_chat_remote_tx, mut fake_chat_remote_rx) = mpsc::unbounded_channel();
+        // The fake connection reports a route to a test host.
+        let fake_connect = RequireEnvironment::new(
+            FakeChatConnect {
+                remote: fake_chat_remote_tx,
+            },
+            "chat.signal.org",
+        );
+
+        let result = RegistrationService::create_session(
+            CreateSession {
+                number: "+18005550101".to_owned(),
+                ..Default::default()
+            },
+            Box::new(fake_connect),
+        )
+        .await;
+
+        assert_matches!(
+            result,
+            Err(RequestError::Unknown(message)) if message == "invalid chat client configuration"
+        );
+
+        // The connection is refused without retrying.
+        let _fake_chat_remote = fake_chat_remote_rx.recv().await.expect("connected once");
+        assert_matches!(
+            fake_chat_remote_rx.try_recv(),
+            Err(mpsc::error::TryRecvError::Disconnected)
+        );
+    }
+
     #[test_log::test(tokio::test(start_paused = true))]
     async fn resume_session() {
         let (fake_chat_remote_tx, mut fake_chat_remote_rx) = mpsc::unbounded_channel();
@@ -421,6 +1031,41 @@ mod test {
         );
     }
 
+    #[test_log::test(tokio::test(start_paused = true))]
+    async fn dropping_service_disconnects_promptly() {
+        let (fake_chat_remote_tx, mut fake_chat_remote_rx) = mpsc::unbounded_channel();
+        let fake_connect = FakeChatConnect {
+            remote: fake_chat_remote_tx,
+        };
+
+        let resume_session = RegistrationService::resume_session(
+            SessionId::from_str("abcabc").unwrap(),
+            Box::new(fake_connect),
+        );
+        let remote_respond = async {
+            let fake_chat_remote = fake_chat_remote_rx.recv().await.expect("sender not closed");
+            let incoming_request = fake_chat_remote
+                .receive_request()
+                .await
+                .expect("still receiving")
+                .expect("received request");
+            fake_chat_remote
+                .send_response(
+                    RegistrationResponse::default().into_websocket_response(incoming_request.id()),
+                )
+                .expect("not disconnected");
+            fake_chat_remote
+        };
+        let (session_client, fake_chat_remote) = tokio::join!(resume_session, remote_respond);
+        let session_client = session_client.expect("resumed session");
+
+        let dropped_at = Instant::now();
+        drop(session_client);
+
+        assert_matches!(fake_chat_remote.receive_request().await, Ok(None));
+        assert_eq!(dropped_at.elapsed(), Duration::ZERO);
+    }
+
     #[test_log::test(tokio::test(start_paused = true))]
     async fn resume_session_and_make_requests() {
         let (fake_chat_remote_tx, mut fake_chat_remote_rx) = mpsc::unbounded_channel();
@@ -516,4 +1161,444 @@ mod test {
             tokio::join!(submit_captcha, answer_submit_captcha);
         assert_matches!(submit_result, Ok(()));
     }
+
+    #[test_log::test(tokio::test(start_paused = true))]
+    async fn refresh_session_updates_state() {
+        let (fake_chat_remote_tx, mut fake_chat_remote_rx) = mpsc::unbounded_channel();
+        let fake_connect = FakeChatConnect {
+            remote: fake_chat_remote_tx,
+        };
+        const SESSION_ID: &str = "abcabc";
+
+        let resume_session = RegistrationService::resume_session(
+            SessionId::from_str(SESSION_ID).unwrap(),
+            Box::new(fake_connect),
+        );
+
+        async fn answer_get_session(
+            fake_chat_remote: FakeChatRemote,
+            id: u64,
+            session: RegistrationSession,
+        ) -> FakeChatRemote {
+            let incoming_request = fake_chat_remote
+                .receive_request()
+                .await
+                .expect("still receiving")
+                .expect("received request");
+
+            assert_eq!(
+                incoming_request,
+                WebSocketRequestMessage {
+                    verb: Some("GET".to_string()),
+                    path: Some("/v1/verification/session/abcabc".to_string()),
+                    body: None,
+                    headers: vec![],
+                    id: Some(id),
+                }
+            );
+
+            fake_chat_remote
+                .send_response(
+                    RegistrationResponse {
+                        session_id: SESSION_ID.to_owned(),
+                        session,
+                    }
+                    .into_websocket_response(id),
+                )
+                .expect("not disconnected");
+            fake_chat_remote
+        }
+
+        let initial_session = RegistrationSession {
+            allowed_to_request_code: true,
+            verified: false,
+            ..Default::default()
+        };
+        let (session_client, fake_chat_remote) = tokio::join!(resume_session, async {
+            let fake_chat_remote = fake_chat_remote_rx.recv().await.expect("sender not closed");
+            answer_get_session(fake_chat_remote, 0, initial_session.clone()).await
+        });
+
+        let mut session_client = session_client.expect("resumed session");
+        assert_eq!(session_client.session_state(), &initial_session);
+
+        let updated_session = RegistrationSession {
+            allowed_to_request_code: true,
+            verified: true,
+            ..Default::default()
+        };
+        let (refresh_result, _fake_chat_remote) = tokio::join!(
+            session_client.refresh_session(),
+            answer_get_session(fake_chat_remote, 1, updated_session.clone())
+        );
+        assert_matches!(refresh_result, Ok(()));
+        assert_eq!(session_client.session_state(), &updated_session);
+    }
+
+    #[test_log::test(tokio::test(start_paused = true))]
+    async fn rate_limit_state_counts_down_session_timers() {
+        let (fake_chat_remote_tx, mut fake_chat_remote_rx) = mpsc::unbounded_channel();
+        let fake_connect = FakeChatConnect {
+            remote: fake_chat_remote_tx,
+        };
+        const SESSION_ID: &str = "abcabc";
+
+        let resume_session = RegistrationService::resume_session(
+            SessionId::from_str(SESSION_ID).unwrap(),
+            Box::new(fake_connect),
+        );
+
+        let throttled_session = RegistrationSession {
+            allowed_to_request_code: true,
+            next_sms: Some(Duration::from_secs(30)),
+            next_call: Some(Duration::from_secs(60)),
+            next_verification_attempt: None,
+            ..Default::default()
+        };
+        let (session_client, _fake_chat_remote) = tokio::join!(resume_session, async {
+            let fake_chat_remote = fake_chat_remote_rx.recv().await.expect("sender not closed");
+            let incoming_request = fake_chat_remote
+                .receive_request()
+                .await
+                .expect("still receiving")
+                .expect("received request");
+            fake_chat_remote
+                .send_response(
+                    RegistrationResponse {
+                        session_id: SESSION_ID.to_owned(),
+                        session: throttled_session,
+                    }
+                    .into_websocket_response(incoming_request.id()),
+                )
+                .expect("not disconnected");
+            fake_chat_remote
+        });
+        let session_client = session_client.expect("resumed session");
+
+        assert_eq!(
+            session_client.rate_limit_state(),
+            RateLimitState {
+                next_sms: Some(Duration::from_secs(30)),
+                next_call: Some(Duration::from_secs(60)),
+                next_verification_attempt: None,
+            }
+        );
+
+        tokio::time::sleep(Duration::from_secs(40)).await;
+
+        assert_eq!(
+            session_client.rate_limit_state(),
+            RateLimitState {
+                next_sms: Some(Duration::ZERO),
+                next_call: Some(Duration::from_secs(20)),
+                next_verification_attempt: None,
+            }
+        );
+    }
+
+    #[test_log::test(tokio::test(start_paused = true))]
+    async fn continue_session_with_new_connect_chat() {
+        let (old_remote_tx, mut old_remote_rx) = mpsc::unbounded_channel();
+        let (new_remote_tx, mut new_remote_rx) = mpsc::unbounded_channel();
+        const SESSION_ID: &str = "abcabc";
+
+        async fn answer_get_session(
+            fake_chat_remote: FakeChatRemote,
+            session: RegistrationSession,
+        ) -> FakeChatRemote {
+            let incoming_request = fake_chat_remote
+                .receive_request()
+                .await
+                .expect("still receiving")
+                .expect("received request");
+            assert_eq!(incoming_request.path(), "/v1/verification/session/abcabc");
+
+            fake_chat_remote
+                .send_response(
+                    RegistrationResponse {
+                        session_id: SESSION_ID.to_owned(),
+                        session,
+                    }
+                    .into_websocket_response(incoming_request.id()),
+                )
+                .expect("not disconnected");
+            fake_chat_remote
+        }
+
+        let resume_session = RegistrationService::resume_session(
+            SessionId::from_str(SESSION_ID).unwrap(),
+            Box::new(FakeChatConnect {
+                remote: old_remote_tx,
+            }),
+        );
+        let (session_client, _old_remote) = tokio::join!(resume_session, async {
+            let fake_chat_remote = old_remote_rx.recv().await.expect("sender not closed");
+            answer_get_session(fake_chat_remote, RegistrationSession::default()).await
+        });
+        let mut session_client = session_client.expect("resumed session");
+
+        // Pretend the device switched networks.
+        session_client.set_connect_chat(Box::new(FakeChatConnect {
+            remote: new_remote_tx,
+        }));
+
+        let updated_session = RegistrationSession {
+            verified: true,
+            ..Default::default()
+        };
+        let (refresh_result, _new_remote) = tokio::join!(session_client.refresh_session(), async {
+            let fake_chat_remote = new_remote_rx.recv().await.expect("sender not closed");
+            answer_get_session(fake_chat_remote, updated_session.clone()).await
+        });
+        assert_matches!(refresh_result, Ok(()));
+        assert_eq!(session_client.session_state(), &updated_session);
+        assert_eq!(**session_client.session_id(), SESSION_ID);
+
+        // The old connector was not used again.
+        assert_matches!(
+            old_remote_rx.try_recv(),
+            Err(mpsc::error::TryRecvError::Disconnected)
+        );
+    }
+
+    #[test_case(false; "all succeed")]
+    #[test_case(true; "second rejected")]
+    #[test_log::test(tokio::test(start_paused = true))]
+    async fn submit_batch_sends_updates_in_order(reject_second: bool) {
+        let (fake_chat_remote_tx, mut fake_chat_remote_rx) = mpsc::unbounded_channel();
+        let fake_connect = FakeChatConnect {
+            remote: fake_chat_remote_tx,
+        };
+        const SESSION_ID: &str = "abcabc";
+
+        let resume_session = RegistrationService::resume_session(
+            SessionId::from_str(SESSION_ID).unwrap(),
+            Box::new(fake_connect),
+        );
+
+        let make_response = |id, session| {
+            RegistrationResponse {
+                session_id: SESSION_ID.to_owned(),
+                session,
+            }
+            .into_websocket_response(id)
+        };
+
+        let (session_client, fake_chat_remote) = tokio::join!(resume_session, async {
+            let fake_chat_remote = fake_chat_remote_rx.recv().await.expect("sender not closed");
+            let incoming_request = fake_chat_remote
+                .receive_request()
+                .await
+                .expect("still receiving")
+                .expect("received request");
+            fake_chat_remote
+                .send_response(make_response(incoming_request.id(), Default::default()))
+                .expect("not disconnected");
+            fake_chat_remote
+        });
+        let mut session_client = session_client.expect("resumed session");
+
+        let after_captcha = RegistrationSession {
+            allowed_to_request_code: false,
+            ..Default::default()
+        };
+        let after_push_challenge = RegistrationSession {
+            allowed_to_request_code: true,
+            ..Default::default()
+        };
+
+        let answer_updates = async {
+            let mut bodies = Vec::new();
+            for session in [after_captcha.clone(), after_push_challenge.clone()] {
+                let incoming_request = fake_chat_remote
+                    .receive_request()
+                    .await
+                    .expect("still receiving")
+                    .expect("received request");
+                assert_eq!(incoming_request.verb(), "PATCH");
+                bodies.push(String::from_utf8(incoming_request.body().to_vec()).unwrap());
+
+                let response = if reject_second && bodies.len() == 2 {
+                    WebSocketResponseMessage {
+                        id: incoming_request.id,
+                        status: Some(403),
+                        ..Default::default()
+                    }
+                } else {
+                    make_response(incoming_request.id(), session)
+                };
+                fake_chat_remote
+                    .send_response(response)
+                    .expect("not disconnected");
+            }
+            bodies
+        };
+
+        let (result, bodies) = tokio::join!(
+            session_client.submit_batch([
+                SessionUpdate::Captcha("captcha value"),
+                SessionUpdate::PushChallenge("challenge value"),
+            ]),
+            answer_updates
+        );
+
+        assert_eq!(
+            bodies,
+            [
+                r#"{"captcha":"captcha value"}"#,
+                r#"{"pushChallenge":"challenge value"}"#
+            ]
+        );
+        if reject_second {
+            assert_matches!(
+                result,
+                Err(BatchRequestError {
+                    index: 1,
+                    error: RequestError::Other(UpdateSessionError::Rejected)
+                })
+            );
+            assert_eq!(session_client.session_state(), &after_captcha);
+        } else {
+            assert_matches!(result, Ok(()));
+            assert_eq!(session_client.session_state(), &after_push_challenge);
+        }
+    }
+
+    #[test_case(false; "all succeed")]
+    #[test_case(true; "second fails")]
+    #[test_log::test(tokio::test(start_paused = true))]
+    async fn request_stream_yields_responses_in_order(fail_second: bool) {
+        let (fake_chat_remote_tx, mut fake_chat_remote_rx) = mpsc::unbounded_channel();
+        let fake_connect = FakeChatConnect {
+            remote: fake_chat_remote_tx,
+        };
+        const SESSION_ID: &str = "abcabc";
+
+        let resume_session = RegistrationService::resume_session(
+            SessionId::from_str(SESSION_ID).unwrap(),
+            Box::new(fake_connect),
+        );
+
+        let make_response = |id, session| {
+            RegistrationResponse {
+                session_id: SESSION_ID.to_owned(),
+                session,
+            }
+            .into_websocket_response(id)
+        };
+
+        let (session_client, fake_chat_remote) = tokio::join!(resume_session, async {
+            let fake_chat_remote = fake_chat_remote_rx.recv().await.expect("sender not closed");
+            let incoming_request = fake_chat_remote
+                .receive_request()
+                .await
+                .expect("still receiving")
+                .expect("received request");
+            fake_chat_remote
+                .send_response(make_response(incoming_request.id(), Default::default()))
+                .expect("not disconnected");
+            fake_chat_remote
+        });
+        let mut session_client = session_client.expect("resumed session");
+
+        let sessions = [1, 2, 3].map(|n| RegistrationSession {
+            next_sms: Some(Duration::from_secs(n)),
+            ..Default::default()
+        });
+
+        let answer_requests = async {
+            let mut paths = Vec::new();
+            let mut rate_limited_once = false;
+            let mut sessions = sessions.iter();
+            while let Ok(Some(incoming_request)) = fake_chat_remote.receive_request().await {
+                paths.push(format!(
+                    "{} {}",
+                    incoming_request.verb(),
+                    incoming_request.path()
+                ));
+                let response = if !rate_limited_once {
+                    // The first attempt is rate limited and should be retried.
+                    rate_limited_once = true;
+                    WebSocketResponseMessage {
+                        id: incoming_request.id,
+                        status: Some(429),
+                        headers: vec!["retry-after: 5".to_owned()],
+                        ..Default::default()
+                    }
+                } else if fail_second && paths.len() == 3 {
+                    WebSocketResponseMessage {
+                        id: incoming_request.id,
+                        status: Some(422),
+                        ..Default::default()
+                    }
+                } else {
+                    make_response(
+                        incoming_request.id(),
+                        sessions.next().expect("not too many requests").clone(),
+                    )
+                };
+                fake_chat_remote
+                    .send_response(response)
+                    .expect("not disconnected");
+            }
+            paths
+        };
+
+        let steps = futures_util::stream::iter([
+            SessionStep::Update(SessionUpdate::Captcha("captcha value")),
+            SessionStep::RequestVerificationCode {
+                transport: VerificationTransport::Sms,
+                client: "client",
+            },
+            SessionStep::SubmitVerificationCode("123456"),
+        ]);
+        let start = tokio::time::Instant::now();
+        let results = async {
+            let results = session_client
+                .request_stream(steps, Duration::from_secs(10))
+                .collect::<Vec<_>>()
+                .await;
+            // Only the rate-limited request should have been delayed.
+            assert_eq!(start.elapsed(), Duration::from_secs(5));
+            // Let the fake server finish.
+            drop(session_client);
+            results
+        };
+        let (results, paths) = tokio::join!(results, answer_requests);
+
+        if fail_second {
+            assert_eq!(
+                paths,
+                [
+                    "PATCH /v1/verification/session/abcabc",
+                    "PATCH /v1/verification/session/abcabc",
+                    "POST /v1/verification/session/abcabc/code",
+                ]
+            );
+            assert_matches!(
+                &results[..],
+                [
+                    Ok(first),
+                    Err(SessionStepError::RequestVerificationCode(
+                        RequestError::RequestWasNotValid
+                    ))
+                ] if first == &sessions[0]
+            );
+        } else {
+            assert_eq!(
+                paths,
+                [
+                    "PATCH /v1/verification/session/abcabc",
+                    "PATCH /v1/verification/session/abcabc",
+                    "POST /v1/verification/session/abcabc/code",
+                    "PUT /v1/verification/session/abcabc/code",
+                ]
+            );
+            let results = results
+                .into_iter()
+                .collect::<Result<Vec<_>, _>>()
+                .expect("all succeeded");
+            assert_eq!(results, sessions);
+        }
+    }
 }
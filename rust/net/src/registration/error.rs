@@ -6,7 +6,10 @@
 use http::{HeaderMap, StatusCode};
 use libsignal_net_infra::errors::{LogSafeDisplay, RetryLater};
 
-use crate::registration::{InvalidSessionId, ResponseError, VerificationCodeNotDeliverable};
+use crate::registration::{
+    IncorrectVerificationCode, InvalidSessionId, ResponseError, SessionConflict,
+    VerificationCodeNotDeliverable,
+};
 
 #[derive(Debug, thiserror::Error, displaydoc::Display, strum::EnumString)]
 pub enum RequestError<E> {
@@ -14,6 +17,8 @@ pub enum RequestError<E> {
     Timeout,
     /// the request did not pass server validation
     RequestWasNotValid,
+    /// the connection was lost after the request was sent; it may or may not have been applied
+    OutcomeUnknown,
     /// unknown error: {0}
     Unknown(String),
     /// {0}
@@ -51,6 +56,8 @@ pub enum ResumeSessionError {
 pub(super) enum SessionRequestError {
     /// {0}
     RetryLater(#[from] RetryLater),
+    /// the session is already verified
+    AlreadyVerified,
     /// unknown HTTP response status: {status}
     UnrecognizedStatus {
         status: StatusCode,
@@ -77,7 +84,9 @@ pub enum RequestVerificationCodeError {
     InvalidSessionId,
     /// session not found
     SessionNotFound,
-    /// the session is already verified or not ready for a code request
+    /// the session is already verified
+    AlreadyVerified,
+    /// the session is not ready for a code request
     NotReadyForVerification,
     /// the request to send a verification code with the requested transport could not be fulfilled
     SendFailed,
@@ -95,12 +104,47 @@ pub enum SubmitVerificationError {
     InvalidSessionId,
     /// session not found
     SessionNotFound,
-    /// the session is already verified or no code was requested
+    /// the session is already verified
+    AlreadyVerified,
+    /// no code was requested for the session
     NotReadyForVerification,
+    /// the verification code was incorrect
+    IncorrectCode {
+        /// How many more codes can be submitted, if the server said.
+        remaining_attempts: Option<u32>,
+    },
     /// {0}
     RetryLater(#[from] RetryLater),
 }
 
+/// One of the requests submitted as a batch failed.
+///
+/// The requests before `index` were applied by the server; the ones after it
+/// weren't sent.
+#[derive(Debug, thiserror::Error, displaydoc::Display)]
+/// request {index} of the batch failed: {error}
+pub struct BatchRequestError<E> {
+    pub index: usize,
+    pub error: RequestError<E>,
+}
+
+/// A request from a stream of [`SessionStep`](crate::registration::SessionStep)s
+/// failed.
+///
+/// The variant says which kind of step failed, and holds the error that the
+/// corresponding single-request method would have returned.
+#[derive(Debug, thiserror::Error, displaydoc::Display)]
+pub enum SessionStepError {
+    /// refreshing the session failed: {0}
+    RefreshSession(RequestError<ResumeSessionError>),
+    /// updating the session failed: {0}
+    Update(RequestError<UpdateSessionError>),
+    /// requesting a verification code failed: {0}
+    RequestVerificationCode(RequestError<RequestVerificationCodeError>),
+    /// submitting the verification code failed: {0}
+    SubmitVerificationCode(RequestError<SubmitVerificationError>),
+}
+
 /// Convert [`RequestError<SessionRequestError>`] into a typed version.
 ///
 /// This boilerplate implementation delegates conversion to the specific
@@ -114,6 +158,7 @@ where
             RequestError::Other(e) => e.into(),
             RequestError::Timeout => RequestError::Timeout,
             RequestError::RequestWasNotValid => RequestError::RequestWasNotValid,
+            RequestError::OutcomeUnknown => RequestError::OutcomeUnknown,
             RequestError::Unknown(message) => RequestError::Unknown(message),
         }
     }
@@ -173,6 +218,16 @@ impl From<ResponseError> for RequestError<SessionRequestError> {
             | ResponseError::UnexpectedData) => {
                 RequestError::Unknown((&error as &dyn LogSafeDisplay).to_string())
             }
+            ResponseError::UnrecognizedStatus {
+                status,
+                response_headers,
+                response_body: Some(response_body),
+            } if status == StatusCode::CONFLICT
+                && SessionConflict::from_response(&response_headers, &response_body)
+                    .is_some_and(|conflict| conflict.verified) =>
+            {
+                Self::Other(SessionRequestError::AlreadyVerified)
+            }
             ResponseError::UnrecognizedStatus {
                 status,
                 response_headers,
@@ -190,6 +245,10 @@ impl From<SessionRequestError> for RequestError<CreateSessionError> {
     fn from(value: SessionRequestError) -> Self {
         match value {
             SessionRequestError::RetryLater(retry_later) => RequestError::Other(retry_later.into()),
+            SessionRequestError::AlreadyVerified => {
+                log::error!("got unexpected already-verified response when creating a session");
+                RequestError::Unknown("unexpected already-verified response".to_owned())
+            }
             SessionRequestError::UnrecognizedStatus { status, .. } => {
                 log::error!("got unexpected HTTP status {status} when creating a session");
                 RequestError::Unknown(format!("unexpected HTTP status {status}"))
@@ -202,6 +261,10 @@ impl From<SessionRequestError> for RequestError<ResumeSessionError> {
     fn from(value: SessionRequestError) -> Self {
         match value {
             SessionRequestError::RetryLater(retry_later) => retry_later.into(),
+            SessionRequestError::AlreadyVerified => {
+                log::error!("got unexpected already-verified response when reading a session");
+                RequestError::Unknown("unexpected already-verified response".to_owned())
+            }
             SessionRequestError::UnrecognizedStatus { status, .. } => match status.as_u16() {
                 404 => RequestError::Other(ResumeSessionError::SessionNotFound),
                 400 => RequestError::Other(ResumeSessionError::InvalidSessionId),
@@ -218,6 +281,10 @@ impl From<SessionRequestError> for RequestError<UpdateSessionError> {
     fn from(value: SessionRequestError) -> Self {
         match value {
             SessionRequestError::RetryLater(retry_later) => RequestError::Other(retry_later.into()),
+            SessionRequestError::AlreadyVerified => {
+                log::error!("got unexpected already-verified response updating the session");
+                RequestError::Unknown("unexpected already-verified response".to_owned())
+            }
             SessionRequestError::UnrecognizedStatus { status, .. } => match status.as_u16() {
                 403 => RequestError::Other(UpdateSessionError::Rejected),
                 code => {
@@ -233,6 +300,7 @@ impl From<SessionRequestError> for RequestError<RequestVerificationCodeError> {
     fn from(value: SessionRequestError) -> Self {
         RequestError::Other(match value {
             SessionRequestError::RetryLater(retry_later) => retry_later.into(),
+            SessionRequestError::AlreadyVerified => RequestVerificationCodeError::AlreadyVerified,
             SessionRequestError::UnrecognizedStatus {
                 status,
                 response_headers,
@@ -259,9 +327,24 @@ impl From<SessionRequestError> for RequestError<RequestVerificationCodeError> {
 impl From<SessionRequestError> for RequestError<SubmitVerificationError> {
     fn from(value: SessionRequestError) -> Self {
         RequestError::Other(match value {
+            // For this request, the server only asks the client to back off
+            // after too many wrong codes.
             SessionRequestError::RetryLater(retry_later) => retry_later.into(),
-            SessionRequestError::UnrecognizedStatus { status, .. } => match status.as_u16() {
+            SessionRequestError::AlreadyVerified => SubmitVerificationError::AlreadyVerified,
+            SessionRequestError::UnrecognizedStatus {
+                status,
+                response_headers,
+                response_body,
+            } => match status.as_u16() {
                 400 => SubmitVerificationError::InvalidSessionId,
+                403 => SubmitVerificationError::IncorrectCode {
+                    remaining_attempts: response_body
+                        .as_deref()
+                        .and_then(|body| {
+                            IncorrectVerificationCode::from_response(&response_headers, body)
+                        })
+                        .and_then(|response| response.remaining_attempts),
+                },
                 404 => SubmitVerificationError::SessionNotFound,
                 409 => SubmitVerificationError::NotReadyForVerification,
                 _ => return RequestError::Unknown(format!("unexpected HTTP status {status}")),
@@ -281,6 +364,7 @@ impl From<RetryLater> for RequestError<RetryLater> {
 mod test {
     use std::fmt::Debug;
 
+    use assert_matches::assert_matches;
     use itertools::Itertools;
     use strum::{IntoDiscriminant, IntoEnumIterator};
     use test_case::test_case;
@@ -316,6 +400,7 @@ mod test {
             match self {
                 RequestError::Timeout => None,
                 RequestError::RequestWasNotValid => Some(422),
+                RequestError::OutcomeUnknown => None,
                 RequestError::Unknown(_) => None,
                 RequestError::Other(inner) => inner.as_status(),
             }
@@ -357,6 +442,10 @@ mod test {
             Some(match self {
                 Self::InvalidSessionId => 400,
                 Self::SessionNotFound => 404,
+                Self::AlreadyVerified => {
+                    // A 409 like NotReadyForVerification, but told apart by the response body.
+                    return None;
+                }
                 Self::NotReadyForVerification => 409,
                 Self::SendFailed => 418, // 🫖
                 Self::CodeNotDeliverable => 440,
@@ -370,7 +459,12 @@ mod test {
             Some(match self {
                 Self::InvalidSessionId => 400,
                 Self::SessionNotFound => 404,
+                Self::AlreadyVerified => {
+                    // A 409 like NotReadyForVerification, but told apart by the response body.
+                    return None;
+                }
                 Self::NotReadyForVerification => 409,
+                Self::IncorrectCode => 403,
                 Self::RetryLater => 429,
             })
         }
@@ -390,7 +484,7 @@ mod test {
         );
         assert_eq!(
             SubmitVerificationError::sorted_statuses(),
-            vec![400, 404, 409, 422, 429]
+            vec![400, 403, 404, 409, 422, 429]
         )
     }
 
@@ -436,7 +530,9 @@ mod test {
             let inner = match request_error.into() {
                 RequestError::RequestWasNotValid => continue,
                 RequestError::Other(inner) => inner,
-                RequestError::Timeout | RequestError::Unknown(_) => unreachable!(),
+                RequestError::Timeout | RequestError::OutcomeUnknown | RequestError::Unknown(_) => {
+                    unreachable!()
+                }
             };
             assert_eq!(inner.discriminant().as_status(), Some(status));
         }
@@ -457,4 +553,73 @@ mod test {
     {
         round_trip_all_variants::<T>();
     }
+
+    #[test_case(None, None; "no body")]
+    #[test_case(Some(serde_json::json!({})), None; "no count")]
+    #[test_case(Some(serde_json::json!({"remainingAttempts": 2})), Some(2); "with count")]
+    fn submit_verification_incorrect_code(
+        body: Option<serde_json::Value>,
+        expected_remaining: Option<u32>,
+    ) {
+        let mut response_headers = HeaderMap::new();
+        response_headers.append(CONTENT_TYPE_JSON.0, CONTENT_TYPE_JSON.1);
+        let error = ResponseError::UnrecognizedStatus {
+            status: StatusCode::FORBIDDEN,
+            response_headers,
+            response_body: body.map(|body| serde_json::to_vec(&body).unwrap().into_boxed_slice()),
+        };
+
+        let error: RequestError<SubmitVerificationError> =
+            RequestError::<SessionRequestError>::from(error).into();
+        assert_matches!(
+            error,
+            RequestError::Other(SubmitVerificationError::IncorrectCode { remaining_attempts })
+                if remaining_attempts == expected_remaining
+        );
+    }
+
+    #[test]
+    fn submit_verification_too_many_attempts() {
+        let error = ResponseError::RetryLater(RetryLater {
+            retry_after_seconds: 300,
+        });
+
+        let error: RequestError<SubmitVerificationError> =
+            RequestError::<SessionRequestError>::from(error).into();
+        assert_matches!(
+            error,
+            RequestError::Other(SubmitVerificationError::RetryLater(RetryLater {
+                retry_after_seconds: 300
+            }))
+        );
+    }
+
+    #[test_case(serde_json::json!({"verified": true}) => true; "verified")]
+    #[test_case(serde_json::json!({"verified": false}) => false; "not verified")]
+    #[test_case(serde_json::json!({}) => false; "no flag")]
+    fn already_verified_rejection(body: serde_json::Value) -> bool {
+        let conflict = || {
+            let mut response_headers = HeaderMap::new();
+            response_headers.append(CONTENT_TYPE_JSON.0, CONTENT_TYPE_JSON.1);
+            RequestError::<SessionRequestError>::from(ResponseError::UnrecognizedStatus {
+                status: StatusCode::CONFLICT,
+                response_headers,
+                response_body: Some(serde_json::to_vec(&body).unwrap().into_boxed_slice()),
+            })
+        };
+
+        let request_code_error: RequestError<RequestVerificationCodeError> = conflict().into();
+        let submit_error: RequestError<SubmitVerificationError> = conflict().into();
+        match (request_code_error, submit_error) {
+            (
+                RequestError::Other(RequestVerificationCodeError::AlreadyVerified),
+                RequestError::Other(SubmitVerificationError::AlreadyVerified),
+            ) => true,
+            (
+                RequestError::Other(RequestVerificationCodeError::NotReadyForVerification),
+                RequestError::Other(SubmitVerificationError::NotReadyForVerification),
+            ) => false,
+            errors => panic!("unexpected errors: {errors:?}"),
+        }
+    }
 }
@@ -54,6 +54,102 @@ pub struct RegistrationSession {
     pub requested_information: HashSet<RequestedInformation>,
 }
 
+/// How long until each rate-limited registration action will be allowed.
+///
+/// For each action, `None` means the server didn't offer it at all, and zero
+/// means it's allowed now. See [`RegistrationService::rate_limit_state`].
+///
+/// [`RegistrationService::rate_limit_state`]: crate::registration::RegistrationService::rate_limit_state
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct RateLimitState {
+    pub next_sms: Option<Duration>,
+    pub next_call: Option<Duration>,
+    pub next_verification_attempt: Option<Duration>,
+}
+
+impl RegistrationSession {
+    /// Computes the [`RateLimitState`] for this session, `elapsed` after the
+    /// server reported it.
+    ///
+    /// The server's timers are relative to when it sent the session, so each
+    /// one is reduced by `elapsed` (but not below zero).
+    pub fn rate_limit_state(&self, elapsed: Duration) -> RateLimitState {
+        let Self {
+            allowed_to_request_code: _,
+            verified: _,
+            next_sms,
+            next_call,
+            next_verification_attempt,
+            requested_information: _,
+        } = self;
+        let remaining = |timer: &Option<Duration>| timer.map(|t| t.saturating_sub(elapsed));
+        RateLimitState {
+            next_sms: remaining(next_sms),
+            next_call: remaining(next_call),
+            next_verification_attempt: remaining(next_verification_attempt),
+        }
+    }
+
+    /// Produces a JSON summary of the session that's safe to include in logs
+    /// and bug reports.
+    ///
+    /// The output has exactly these fields, named as in the server's
+    /// response:
+    /// - `allowedToRequestCode` and `verified`, as booleans
+    /// - `nextSms`, `nextCall`, and `nextVerificationAttempt`, as whole
+    ///   seconds, or `null` if the action isn't available
+    /// - `requestedInformation`, as a sorted list of the requested items
+    ///
+    /// Nothing else is included, even if fields are added to the session
+    /// later. In particular, the session ID, the phone number, and any
+    /// verification codes never appear. The timers are as the server reported
+    /// them; see [`Self::rate_limit_state`] for how much is left.
+    pub fn to_redacted_json(&self) -> RedactedSessionJson {
+        let Self {
+            allowed_to_request_code,
+            verified,
+            next_sms,
+            next_call,
+            next_verification_attempt,
+            requested_information,
+        } = self;
+        let seconds = |timer: &Option<Duration>| timer.map(|t| t.as_secs());
+        let mut requested_information: Vec<&str> = requested_information
+            .iter()
+            .map(|info| info.as_ref())
+            .collect();
+        requested_information.sort_unstable();
+        RedactedSessionJson(serde_json::json!({
+            "allowedToRequestCode": allowed_to_request_code,
+            "verified": verified,
+            "nextSms": seconds(next_sms),
+            "nextCall": seconds(next_call),
+            "nextVerificationAttempt": seconds(next_verification_attempt),
+            "requestedInformation": requested_information,
+        }))
+    }
+}
+
+/// The output of [`RegistrationSession::to_redacted_json`].
+///
+/// Displays as compact JSON.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RedactedSessionJson(serde_json::Value);
+
+impl RedactedSessionJson {
+    pub fn as_value(&self) -> &serde_json::Value {
+        &self.0
+    }
+}
+
+impl std::fmt::Display for RedactedSessionJson {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Display::fmt(&self.0, f)
+    }
+}
+
+impl LogSafeDisplay for RedactedSessionJson {}
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, serde::Deserialize, strum::AsRefStr)]
 #[strum(serialize_all = "camelCase")]
 #[serde(rename_all = "camelCase")]
@@ -165,6 +261,54 @@ pub(super) struct UpdateRegistrationSession<'a> {
     pub(crate) push_challenge: Option<&'a str>,
 }
 
+/// A single update to a session that can be submitted with
+/// [`RegistrationService::submit_batch`](crate::registration::RegistrationService::submit_batch).
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum SessionUpdate<'a> {
+    Captcha(&'a str),
+    PushToken {
+        push_token: &'a str,
+        push_token_type: PushTokenType,
+    },
+    PushChallenge(&'a str),
+}
+
+impl<'a> From<SessionUpdate<'a>> for UpdateRegistrationSession<'a> {
+    fn from(value: SessionUpdate<'a>) -> Self {
+        match value {
+            SessionUpdate::Captcha(captcha) => Self {
+                captcha: Some(captcha),
+                ..Default::default()
+            },
+            SessionUpdate::PushToken {
+                push_token,
+                push_token_type,
+            } => Self {
+                push_token: Some(push_token),
+                push_token_type: Some(push_token_type),
+                ..Default::default()
+            },
+            SessionUpdate::PushChallenge(push_challenge) => Self {
+                push_challenge: Some(push_challenge),
+                ..Default::default()
+            },
+        }
+    }
+}
+
+/// A request on an established session that can be submitted with
+/// [`RegistrationService::request_stream`](crate::registration::RegistrationService::request_stream).
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum SessionStep<'a> {
+    RefreshSession,
+    Update(SessionUpdate<'a>),
+    RequestVerificationCode {
+        transport: VerificationTransport,
+        client: &'a str,
+    },
+    SubmitVerificationCode(&'a str),
+}
+
 #[derive(Clone, Debug, Eq, PartialEq, serde::Serialize)]
 #[serde(rename_all = "camelCase")]
 pub(super) struct RequestVerificationCode<'a> {
@@ -183,6 +327,120 @@ pub(super) struct RegistrationRequest<'s, R> {
     pub(super) request: R,
 }
 
+/// A `content-encoding` that registration request bodies can be compressed
+/// with.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, strum::EnumString, strum::IntoStaticStr)]
+#[strum(serialize_all = "lowercase")]
+pub enum RequestCompression {
+    Gzip,
+    Deflate,
+}
+
+impl RequestCompression {
+    fn compress(self, bytes: &[u8]) -> Box<[u8]> {
+        fn finish<W: std::io::Write>(mut encoder: W, bytes: &[u8]) -> std::io::Result<W> {
+            encoder.write_all(bytes)?;
+            Ok(encoder)
+        }
+
+        let compressed = match self {
+            Self::Gzip => finish(
+                flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default()),
+                bytes,
+            )
+            .and_then(flate2::write::GzEncoder::finish),
+            // HTTP's "deflate" is the zlib format, not raw deflate.
+            Self::Deflate => finish(
+                flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default()),
+                bytes,
+            )
+            .and_then(flate2::write::ZlibEncoder::finish),
+        };
+        compressed
+            .expect("writing to a Vec doesn't fail")
+            .into_boxed_slice()
+    }
+
+    /// Whether `headers` from a server response list `self` in
+    /// `accept-encoding`.
+    ///
+    /// A coding listed with a weight of `q=0` is explicitly not acceptable.
+    fn is_accepted_by(self, headers: &HeaderMap) -> bool {
+        let name: &'static str = self.into();
+        headers
+            .get_all(http::header::ACCEPT_ENCODING)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .any(|coding| {
+                let mut parts = coding.split(';').map(str::trim);
+                let coding = parts.next().unwrap_or_default();
+                coding.eq_ignore_ascii_case(name) && weight(parts).is_some_and(|q| q > 0.0)
+            })
+    }
+}
+
+/// Finds the `q` weight in the parameters of an `accept-encoding` entry.
+///
+/// The weight defaults to 1 if it isn't given. Returns `None` if it can't be
+/// parsed.
+fn weight<'a>(mut params: impl Iterator<Item = &'a str>) -> Option<f32> {
+    let Some(q) = params.find_map(|param| {
+        let (key, value) = param.split_once('=')?;
+        key.trim().eq_ignore_ascii_case("q").then_some(value.trim())
+    }) else {
+        return Some(1.0);
+    };
+    q.parse().ok()
+}
+
+/// Decides whether to compress request bodies for a registration session.
+///
+/// Compression is only used once the server has advertised support for the
+/// preferred encoding in the `accept-encoding` header of its latest response.
+#[derive(Debug, Default)]
+pub(super) struct BodyCompression {
+    preferred: Option<RequestCompression>,
+    accepted_by_server: bool,
+}
+
+impl BodyCompression {
+    /// Changes the preferred encoding.
+    ///
+    /// If it's different from before, compression isn't used again until a
+    /// response shows that the server accepts the new one.
+    pub(super) fn set_preferred(&mut self, preferred: Option<RequestCompression>) {
+        let Self {
+            preferred: current,
+            accepted_by_server,
+        } = self;
+        if *current != preferred {
+            *current = preferred;
+            *accepted_by_server = false;
+        }
+    }
+
+    /// The encoding to use for the next request body, if any.
+    pub(super) fn negotiated(&self) -> Option<RequestCompression> {
+        let Self {
+            preferred,
+            accepted_by_server,
+        } = self;
+        preferred.filter(|_| *accepted_by_server)
+    }
+
+    /// Updates what the server accepts from the headers of one of its
+    /// responses.
+    pub(super) fn update_from_response(&mut self, response_headers: &HeaderMap) {
+        let Self {
+            preferred,
+            accepted_by_server,
+        } = self;
+        *accepted_by_server =
+            preferred.is_some_and(|preferred| preferred.is_accepted_by(response_headers));
+    }
+}
+
 pub(super) struct AccountKeys<'a> {
     identity_key: &'a PublicKey,
     signed_pre_key: &'a SignedPreKeyRecord,
@@ -236,29 +494,117 @@ pub(super) struct RegistrationResponse {
     pub(super) session: RegistrationSession,
 }
 
+/// Response body sent along with a rejected verification code.
+#[derive(Clone, Debug, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub(super) struct IncorrectVerificationCode {
+    pub(super) remaining_attempts: Option<u32>,
+}
+
+/// Response body sent along with a request rejected because of the session's
+/// state.
+///
+/// The server includes the current session, but only whether it has been
+/// verified is needed to tell the rejections apart.
+#[derive(Clone, Debug, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub(super) struct SessionConflict {
+    pub(super) verified: bool,
+}
+
 impl VerificationCodeNotDeliverable {
     pub(crate) fn from_response(
         response_headers: &HeaderMap,
         response_body: &[u8],
     ) -> Option<Self> {
-        if response_headers.get(CONTENT_TYPE_JSON.0) != Some(&CONTENT_TYPE_JSON.1) {
-            return None;
-        }
+        from_json_response(response_headers, response_body)
+    }
+}
 
-        serde_json::from_slice(response_body).ok()
+impl IncorrectVerificationCode {
+    pub(super) fn from_response(
+        response_headers: &HeaderMap,
+        response_body: &[u8],
+    ) -> Option<Self> {
+        from_json_response(response_headers, response_body)
     }
 }
 
+impl SessionConflict {
+    pub(super) fn from_response(
+        response_headers: &HeaderMap,
+        response_body: &[u8],
+    ) -> Option<Self> {
+        from_json_response(response_headers, response_body)
+    }
+}
+
+fn from_json_response<T: serde::de::DeserializeOwned>(
+    response_headers: &HeaderMap,
+    response_body: &[u8],
+) -> Option<T> {
+    if response_headers.get(CONTENT_TYPE_JSON.0) != Some(&CONTENT_TYPE_JSON.1) {
+        return None;
+    }
+
+    serde_json::from_slice(response_body).ok()
+}
+
 /// A value that can be sent to the server as part of a REST request.
 pub(super) trait Request {
     /// The HTTP [`Method`] to send the request with
     const METHOD: Method;
 
+    /// The value for the `accept` header, if the request should have one.
+    const ACCEPT: Option<HeaderValue> = None;
+
     /// The HTTP path to use when sending the request.
     fn request_path(session_id: &SessionId) -> PathAndQuery;
 
-    /// The serialized JSON for the request body, if any.
-    fn into_json_body(self) -> Option<Box<[u8]>>;
+    /// The serialized request body, if any.
+    fn into_body(self) -> Option<RequestBody>;
+}
+
+/// A serialized request body and the `content-type` it is encoded as.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(super) struct RequestBody {
+    content_type: HeaderValue,
+    bytes: Box<[u8]>,
+}
+
+impl RequestBody {
+    /// Serializes `value` as JSON.
+    pub(super) fn json(value: &impl serde::Serialize) -> Self {
+        Self {
+            content_type: CONTENT_TYPE_JSON.1,
+            bytes: serde_json::to_vec(value)
+                .expect("no maps")
+                .into_boxed_slice(),
+        }
+    }
+
+    /// Uses `bytes`, which are already encoded as `content_type`.
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub(super) fn encoded(content_type: HeaderValue, bytes: Box<[u8]>) -> Self {
+        Self {
+            content_type,
+            bytes,
+        }
+    }
+
+    /// Checks that the body is well-formed for its declared `content-type`.
+    ///
+    /// Only JSON is checked; other content types are passed through as-is.
+    fn matches_content_type(&self) -> bool {
+        let Self {
+            content_type,
+            bytes,
+        } = self;
+        if *content_type != CONTENT_TYPE_JSON.1 {
+            return true;
+        }
+        serde_json::from_slice::<serde::de::IgnoredAny>(bytes).is_ok()
+    }
 }
 
 impl Request for GetSession {
@@ -271,7 +617,7 @@ impl Request for GetSession {
         .parse()
         .unwrap()
     }
-    fn into_json_body(self) -> Option<Box<[u8]>> {
+    fn into_body(self) -> Option<RequestBody> {
         None
     }
 }
@@ -281,12 +627,8 @@ impl Request for UpdateRegistrationSession<'_> {
     fn request_path(session_id: &SessionId) -> PathAndQuery {
         GetSession::request_path(session_id)
     }
-    fn into_json_body(self) -> Option<Box<[u8]>> {
-        Some(
-            serde_json::to_vec(&self)
-                .expect("no maps")
-                .into_boxed_slice(),
-        )
+    fn into_body(self) -> Option<RequestBody> {
+        Some(RequestBody::json(&self))
     }
 }
 
@@ -300,12 +642,8 @@ impl Request for RequestVerificationCode<'_> {
         .parse()
         .unwrap()
     }
-    fn into_json_body(self) -> Option<Box<[u8]>> {
-        Some(
-            serde_json::to_vec(&self)
-                .expect("no maps")
-                .into_boxed_slice(),
-        )
+    fn into_body(self) -> Option<RequestBody> {
+        Some(RequestBody::json(&self))
     }
 }
 
@@ -314,12 +652,8 @@ impl Request for SubmitVerificationCode<'_> {
     fn request_path(session_id: &SessionId) -> PathAndQuery {
         RequestVerificationCode::request_path(session_id)
     }
-    fn into_json_body(self) -> Option<Box<[u8]>> {
-        Some(
-            serde_json::to_vec(&self)
-                .expect("no maps")
-                .into_boxed_slice(),
-        )
+    fn into_body(self) -> Option<RequestBody> {
+        Some(RequestBody::json(&self))
     }
 }
 
@@ -505,16 +839,51 @@ impl From<CreateSession> for crate::chat::Request {
 
 impl<'s, R: Request> From<RegistrationRequest<'s, R>> for crate::chat::Request {
     fn from(value: RegistrationRequest<'s, R>) -> Self {
-        let RegistrationRequest {
+        value.into_chat_request(None)
+    }
+}
+
+impl<R: Request> RegistrationRequest<'_, R> {
+    /// Encodes `self` as a chat request, compressing the body with
+    /// `compression` if one is provided.
+    pub(super) fn into_chat_request(
+        self,
+        compression: Option<RequestCompression>,
+    ) -> crate::chat::Request {
+        let Self {
             session_id,
             request,
-        } = value;
+        } = self;
 
         let path = R::request_path(session_id);
-        let body = request.into_json_body();
-        let headers = HeaderMap::from_iter(body.is_some().then_some(CONTENT_TYPE_JSON));
+        let mut headers = HeaderMap::new();
+        if let Some(accept) = R::ACCEPT {
+            headers.insert(http::header::ACCEPT, accept);
+        }
+        let body = request.into_body().map(|body| {
+            debug_assert!(
+                body.matches_content_type(),
+                "body is not valid {:?}",
+                body.content_type
+            );
+            let RequestBody {
+                content_type,
+                bytes,
+            } = body;
+            headers.insert(http::header::CONTENT_TYPE, content_type);
+            match compression {
+                None => bytes,
+                Some(compression) => {
+                    headers.insert(
+                        http::header::CONTENT_ENCODING,
+                        HeaderValue::from_static(compression.into()),
+                    );
+                    compression.compress(&bytes)
+                }
+            }
+        });
 
-        Self {
+        crate::chat::Request {
             method: R::METHOD,
             headers,
             path,
@@ -581,7 +950,7 @@ impl TryFrom<String> for VerificationTransport {
     }
 }
 
-#[cfg(test)]
+#[cfg(any(test, feature = "test-util"))]
 impl RegistrationResponse {
     pub(super) fn into_websocket_response(
         self,
@@ -605,6 +974,7 @@ mod test {
     use libsignal_protocol::KeyPair;
     use rand::SeedableRng as _;
     use serde_json::json;
+    use test_case::test_case;
 
     use super::*;
     use crate::chat::{Request as ChatRequest, Response as ChatResponse};
@@ -695,6 +1065,174 @@ mod test {
         );
     }
 
+    #[test]
+    fn registration_submit_verification_code_as_chat_request() {
+        let request: ChatRequest = RegistrationRequest {
+            session_id: &SessionId::from_str("aaabbbcccdddeee").unwrap(),
+            request: SubmitVerificationCode { code: "123456" },
+        }
+        .into();
+
+        assert_eq!(
+            request,
+            ChatRequest {
+                method: Method::PUT,
+                path: PathAndQuery::from_static("/v1/verification/session/aaabbbcccdddeee/code"),
+                headers: HeaderMap::from_iter([CONTENT_TYPE_JSON]),
+                body: Some(b"{\"code\":\"123456\"}".as_slice().into()),
+            }
+        );
+    }
+
+    #[test_case(RequestCompression::Gzip, "gzip")]
+    #[test_case(RequestCompression::Deflate, "deflate")]
+    fn registration_request_with_compressed_body(
+        compression: RequestCompression,
+        expected_encoding: &'static str,
+    ) {
+        use std::io::Read as _;
+
+        let ChatRequest {
+            method,
+            path,
+            headers,
+            body,
+        } = RegistrationRequest {
+            session_id: &SessionId::from_str("aaabbbcccdddeee").unwrap(),
+            request: SubmitVerificationCode { code: "123456" },
+        }
+        .into_chat_request(Some(compression));
+
+        assert_eq!(method, Method::PUT);
+        assert_eq!(path, "/v1/verification/session/aaabbbcccdddeee/code");
+        assert_eq!(
+            headers,
+            HeaderMap::from_iter([
+                CONTENT_TYPE_JSON,
+                (
+                    http::header::CONTENT_ENCODING,
+                    HeaderValue::from_static(expected_encoding)
+                ),
+            ])
+        );
+
+        let body = body.expect("has body");
+        let mut decompressed = Vec::new();
+        match compression {
+            RequestCompression::Gzip => flate2::read::GzDecoder::new(&*body)
+                .read_to_end(&mut decompressed)
+                .expect("valid gzip"),
+            RequestCompression::Deflate => flate2::read::ZlibDecoder::new(&*body)
+                .read_to_end(&mut decompressed)
+                .expect("valid zlib"),
+        };
+        assert_eq!(decompressed, b"{\"code\":\"123456\"}");
+    }
+
+    #[test_case(None, None => None; "not preferred")]
+    #[test_case(Some(RequestCompression::Gzip), None => None; "not advertised")]
+    #[test_case(Some(RequestCompression::Gzip), Some("deflate") => None; "other encoding advertised")]
+    #[test_case(Some(RequestCompression::Gzip), Some("deflate, GZIP;q=0.5") => Some(RequestCompression::Gzip); "advertised")]
+    #[test_case(None, Some("gzip") => None; "advertised but not preferred")]
+    #[test_case(Some(RequestCompression::Gzip), Some("deflate, gzip;q=0") => None; "advertised as not acceptable")]
+    #[test_case(Some(RequestCompression::Gzip), Some("gzip; Q=0.000") => None; "advertised as not acceptable with spaces")]
+    #[test_case(Some(RequestCompression::Gzip), Some("gzip;q=0.001") => Some(RequestCompression::Gzip); "advertised with a low weight")]
+    #[test_case(Some(RequestCompression::Gzip), Some("gzip;q=high") => None; "advertised with an invalid weight")]
+    fn body_compression_negotiation(
+        preferred: Option<RequestCompression>,
+        accept_encoding: Option<&'static str>,
+    ) -> Option<RequestCompression> {
+        let mut compression = BodyCompression::default();
+        compression.set_preferred(preferred);
+        assert_eq!(compression.negotiated(), None, "not until a response");
+
+        compression.update_from_response(&HeaderMap::from_iter(accept_encoding.map(|value| {
+            (
+                http::header::ACCEPT_ENCODING,
+                HeaderValue::from_static(value),
+            )
+        })));
+        compression.negotiated()
+    }
+
+    #[test]
+    fn body_compression_changing_preference_waits_for_server() {
+        let mut compression = BodyCompression::default();
+        compression.set_preferred(Some(RequestCompression::Gzip));
+        compression.update_from_response(&HeaderMap::from_iter([(
+            http::header::ACCEPT_ENCODING,
+            HeaderValue::from_static("gzip, deflate"),
+        )]));
+        assert_eq!(compression.negotiated(), Some(RequestCompression::Gzip));
+
+        // Setting the same preference again doesn't lose the negotiation.
+        compression.set_preferred(Some(RequestCompression::Gzip));
+        assert_eq!(compression.negotiated(), Some(RequestCompression::Gzip));
+
+        // The server hasn't said anything about deflate in particular yet.
+        compression.set_preferred(Some(RequestCompression::Deflate));
+        assert_eq!(compression.negotiated(), None);
+
+        compression.update_from_response(&HeaderMap::from_iter([(
+            http::header::ACCEPT_ENCODING,
+            HeaderValue::from_static("gzip, deflate"),
+        )]));
+        assert_eq!(compression.negotiated(), Some(RequestCompression::Deflate));
+    }
+
+    /// Stands in for a request type with a non-JSON encoding.
+    struct BinaryRequest(&'static [u8]);
+
+    const CONTENT_TYPE_BINARY: HeaderValue = HeaderValue::from_static("application/x-protobuf");
+
+    impl Request for BinaryRequest {
+        const METHOD: Method = Method::PUT;
+        const ACCEPT: Option<HeaderValue> = Some(CONTENT_TYPE_BINARY);
+
+        fn request_path(session_id: &SessionId) -> PathAndQuery {
+            GetSession::request_path(session_id)
+        }
+
+        fn into_body(self) -> Option<RequestBody> {
+            Some(RequestBody::encoded(CONTENT_TYPE_BINARY, self.0.into()))
+        }
+    }
+
+    #[test]
+    fn registration_request_with_alternate_content_type() {
+        let request: ChatRequest = RegistrationRequest {
+            session_id: &SessionId::from_str("aaabbbcccdddeee").unwrap(),
+            request: BinaryRequest(b"\x0a\x02hi"),
+        }
+        .into();
+
+        assert_eq!(
+            request,
+            ChatRequest {
+                method: Method::PUT,
+                path: PathAndQuery::from_static("/v1/verification/session/aaabbbcccdddeee"),
+                headers: HeaderMap::from_iter([
+                    (http::header::ACCEPT, CONTENT_TYPE_BINARY),
+                    (http::header::CONTENT_TYPE, CONTENT_TYPE_BINARY),
+                ]),
+                body: Some(b"\x0a\x02hi".as_slice().into()),
+            }
+        );
+    }
+
+    #[test]
+    fn request_body_content_type_validation() {
+        assert!(RequestBody::json(&json!({"captcha": "captcha"})).matches_content_type());
+        assert!(
+            RequestBody::encoded(CONTENT_TYPE_BINARY, b"\x0a\x02hi".as_slice().into())
+                .matches_content_type()
+        );
+        assert!(
+            !RequestBody::encoded(CONTENT_TYPE_JSON.1, b"\x0a\x02hi".as_slice().into())
+                .matches_content_type()
+        );
+    }
+
     #[test]
     fn registration_response_deserialize() {
         const RESPONSE_JSON: &str = r#"{
@@ -731,6 +1269,44 @@ mod test {
         );
     }
 
+    #[test]
+    fn registration_session_redacted_json() {
+        const RESPONSE_JSON: &str = r#"{
+                "id": "fivesixseven",
+                "number": "+18005550101",
+                "allowedToRequestCode": true,
+                "verified": false,
+                "nextSms": 30,
+                "nextVerificationAttempt": 0,
+                "requestedInformation": ["pushChallenge", "captcha"]
+            }"#;
+        let response: RegistrationResponse = ChatResponse {
+            status: StatusCode::OK,
+            message: Some("OK".to_owned()),
+            headers: HeaderMap::from_iter([CONTENT_TYPE_JSON]),
+            body: Some(RESPONSE_JSON.as_bytes().into()),
+        }
+        .try_into_response()
+        .unwrap();
+
+        let redacted = response.session.to_redacted_json();
+        assert_eq!(
+            redacted.as_value(),
+            &json!({
+                "allowedToRequestCode": true,
+                "verified": false,
+                "nextSms": 30,
+                "nextCall": null,
+                "nextVerificationAttempt": 0,
+                "requestedInformation": ["captcha", "pushChallenge"],
+            })
+        );
+
+        let logged = (&redacted as &dyn LogSafeDisplay).to_string();
+        assert!(!logged.contains("fivesixseven"), "{logged}");
+        assert!(!logged.contains("8005550101"), "{logged}");
+    }
+
     static ACCOUNT_ATTRIBUTES: LazyLock<ProvidedAccountAttributes<'static>> =
         LazyLock::new(|| ProvidedAccountAttributes {
             recovery_password: b"recovery",
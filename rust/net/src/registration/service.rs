@@ -7,19 +7,34 @@ use std::convert::Infallible;
 use std::fmt::Debug;
 use std::future::Future;
 use std::panic::UnwindSafe;
+use std::sync::Arc;
 
 use either::Either;
 use futures_util::future::BoxFuture;
 use futures_util::{FutureExt as _, Stream, StreamExt as _};
+use http::HeaderName;
+use libsignal_net_infra::dns::DnsResolver;
 use libsignal_net_infra::errors::{LogSafeDisplay, RetryLater};
-use tokio::sync::{mpsc, oneshot};
+use libsignal_net_infra::host::Host;
+use libsignal_net_infra::route::{
+    ConnectionOutcomeParams, RouteProvider, TransportRoute, UnresolvedHttpsServiceRoute,
+    UsePreconnect,
+};
+use libsignal_net_infra::utils::{EventSubscription, ObservableEvent};
+use tokio::sync::{mpsc, oneshot, watch};
 use tokio::time::{Duration, Instant};
 use tokio_stream::wrappers::ReceiverStream;
 
+use crate::chat::ws2::ListenerEvent;
 use crate::chat::{
-    ChatConnection, ConnectError as ChatConnectError, Request as ChatRequest,
+    ChatConnection, ConnectError as ChatConnectError, PreconnectPolicy, Request as ChatRequest,
     Response as ChatResponse, SendError as ChatSendError,
 };
+use crate::connect_state::{
+    lock_connect_state, ConnectState, ConnectionResources, RouteProviderContextImpl,
+    WebSocketTransportConnectorFactory,
+};
+use crate::env::UserAgent;
 use crate::registration::{RequestError, SessionRequestError};
 
 /// Internal connection implementation for the registration client.
@@ -32,7 +47,52 @@ use crate::registration::{RequestError, SessionRequestError};
 pub(super) struct RegistrationConnection<'c> {
     #[debug("_")]
     connect_chat: Box<dyn ConnectChat + Send + Sync + UnwindSafe + 'c>,
-    sender: tokio::sync::mpsc::Sender<IncomingRequest>,
+    /// The connection task for the current connection, if there is one.
+    sender: Option<tokio::sync::mpsc::Sender<IncomingRequest>>,
+    /// Used to stop the task behind `sender` when this is dropped.
+    task: Option<tokio::task::JoinHandle<()>>,
+    /// The task for a connection replaced by [`Self::set_connect_chat`], which
+    /// is finishing the requests already handed to it.
+    draining: Option<tokio::task::JoinHandle<()>>,
+    /// How long to wait for a dropped connection to recover before reconnecting.
+    reconnect_grace_period: Duration,
+    /// How long connection tasks wait for another request before closing.
+    inactivity_timeout: watch::Sender<Duration>,
+    /// Reconnects made since the last successful request.
+    reconnects: ReconnectBudget,
+}
+
+/// Counts reconnects made after losing a connection, up to an optional limit.
+///
+/// The count is reset whenever a request succeeds, so the limit only applies
+/// to consecutive losses.
+#[derive(Debug, Default)]
+struct ReconnectBudget {
+    reconnects: u32,
+    max_reconnects: Option<u32>,
+}
+
+impl ReconnectBudget {
+    /// Records that a lost connection is about to be replaced.
+    ///
+    /// Returns an error instead if that would exceed the limit.
+    fn record_reconnect<E>(&mut self) -> Result<(), RequestError<E>> {
+        if self
+            .max_reconnects
+            .is_some_and(|max| self.reconnects >= max)
+        {
+            return Err(RequestError::Unknown(format!(
+                "gave up after reconnecting to the chat server {} times",
+                self.reconnects
+            )));
+        }
+        self.reconnects += 1;
+        Ok(())
+    }
+
+    fn reset(&mut self) {
+        self.reconnects = 0;
+    }
 }
 
 /// Describes how to make a [`ChatConnection`].
@@ -48,73 +108,589 @@ pub trait ConnectChat: Send {
         &self,
         on_disconnect: oneshot::Sender<Infallible>,
     ) -> BoxFuture<'_, Result<ChatConnection, ChatConnectError>>;
+
+    /// The pool to reuse idle connections from, if any.
+    ///
+    /// Connections from the pool are used before making new ones with
+    /// [`Self::connect_chat`], and connections that go idle are returned to
+    /// it instead of being closed.
+    fn connection_pool(&self) -> Option<&ChatConnectionPool> {
+        None
+    }
+
+    /// How long to wait before retrying after [`Self::connect_chat`] fails.
+    ///
+    /// The default backs off without any jitter.
+    fn retry_delay_params(&self) -> ConnectionOutcomeParams {
+        CHAT_CONNECT_DELAY_PARAMS
+    }
+}
+
+/// Idle registration chat connections, shared by every [`ConnectChat`] that
+/// uses the pool.
+///
+/// This lets several [`RegistrationService`](crate::registration::RegistrationService)s
+/// (say, for different accounts) reuse one connection instead of each making
+/// its own. Clones refer to the same pool. Use [`PooledConnectChat`] to make
+/// connections with one.
+///
+/// Connections are closed once they've sat in the pool for two minutes, and
+/// all of them are closed when the network changes, since connections made on
+/// a previous network might not work on the new one.
+#[derive(Clone)]
+pub struct ChatConnectionPool {
+    idle: Arc<std::sync::Mutex<Vec<IdleChat>>>,
+    _network_change_subscription: Arc<EventSubscription>,
+}
+
+/// A connection in a [`ChatConnectionPool`], with the receiver that's closed
+/// when it disconnects.
+struct IdleChat {
+    chat: ChatConnection,
+    on_disconnect: oneshot::Receiver<Infallible>,
+    idle_since: Instant,
+}
+
+impl IdleChat {
+    fn is_expired(&self, now: Instant) -> bool {
+        now.saturating_duration_since(self.idle_since) >= POOLED_CONNECTION_IDLE_TIMEOUT
+    }
+}
+
+impl ChatConnectionPool {
+    /// Makes an empty pool that's emptied whenever `network_change_event`
+    /// fires.
+    pub fn new(network_change_event: &ObservableEvent) -> Self {
+        let idle = Arc::<std::sync::Mutex<Vec<IdleChat>>>::default();
+        let network_change_subscription = network_change_event.subscribe(Box::new({
+            let idle = Arc::downgrade(&idle);
+            move || {
+                if let Some(idle) = idle.upgrade() {
+                    Self::network_changed(&idle)
+                }
+            }
+        }));
+        Self {
+            idle,
+            _network_change_subscription: Arc::new(network_change_subscription),
+        }
+    }
+
+    /// The number of idle connections in the pool.
+    ///
+    /// Connections that were lost while idle are included until the next time
+    /// one is taken.
+    pub fn idle_count(&self) -> usize {
+        self.idle.lock().expect("not poisoned").len()
+    }
+
+    fn network_changed(idle: &std::sync::Mutex<Vec<IdleChat>>) {
+        let stale = std::mem::take(&mut *idle.lock().expect("not poisoned"));
+        if !stale.is_empty() {
+            log::info!(
+                "closing {} pooled registration chat connection(s) after a network change",
+                stale.len()
+            );
+        }
+        // Dropping a ChatConnection closes it.
+        drop(stale);
+    }
+
+    /// Takes the most recently used idle connection that's still connected.
+    fn take(&self) -> Option<IdleChat> {
+        let now = Instant::now();
+        let mut idle = self.idle.lock().expect("not poisoned");
+        while let Some(mut candidate) = idle.pop() {
+            if candidate.is_expired(now) {
+                // The timer to close it hasn't fired yet, but it would have
+                // been closed by the time it was used.
+                log::debug!("closing a pooled registration chat connection that was idle too long");
+                // Everything before this one has been idle even longer.
+                idle.clear();
+                return None;
+            }
+            match candidate.on_disconnect.try_recv() {
+                Ok(infallible) => match infallible {},
+                Err(oneshot::error::TryRecvError::Empty) => return Some(candidate),
+                Err(oneshot::error::TryRecvError::Closed) => {
+                    log::debug!("discarding a pooled registration chat connection that was lost");
+                }
+            }
+        }
+        None
+    }
+
+    /// Adds `chat` to the pool, and starts a timer to close it if it's still
+    /// there after [`POOLED_CONNECTION_IDLE_TIMEOUT`].
+    ///
+    /// Must be called from within a tokio runtime.
+    fn put(&self, chat: IdleChat) {
+        let expires_at = chat.idle_since + POOLED_CONNECTION_IDLE_TIMEOUT;
+        self.idle.lock().expect("not poisoned").push(chat);
+
+        let idle = Arc::downgrade(&self.idle);
+        tokio::spawn(async move {
+            tokio::time::sleep_until(expires_at).await;
+            let Some(idle) = idle.upgrade() else {
+                return;
+            };
+            let now = Instant::now();
+            let mut idle = idle.lock().expect("not poisoned");
+            let before = idle.len();
+            // Dropping a ChatConnection closes it.
+            idle.retain(|pooled| !pooled.is_expired(now));
+            if idle.len() != before {
+                log::debug!("closed a pooled registration chat connection that was idle too long");
+            }
+        });
+    }
+}
+
+/// A [`ConnectChat`] that reuses idle connections from a
+/// [`ChatConnectionPool`], making new ones with the wrapped `ConnectChat`.
+pub struct PooledConnectChat<C> {
+    connect_chat: C,
+    pool: ChatConnectionPool,
+}
+
+impl<C> PooledConnectChat<C> {
+    pub fn new(connect_chat: C, pool: ChatConnectionPool) -> Self {
+        Self { connect_chat, pool }
+    }
+}
+
+impl<C: ConnectChat> ConnectChat for PooledConnectChat<C> {
+    fn connect_chat(
+        &self,
+        on_disconnect: oneshot::Sender<Infallible>,
+    ) -> BoxFuture<'_, Result<ChatConnection, ChatConnectError>> {
+        self.connect_chat.connect_chat(on_disconnect)
+    }
+
+    fn connection_pool(&self) -> Option<&ChatConnectionPool> {
+        Some(&self.pool)
+    }
+
+    fn retry_delay_params(&self) -> ConnectionOutcomeParams {
+        self.connect_chat.retry_delay_params()
+    }
+}
+
+/// A [`ConnectChat`] wrapper that refuses connections to the wrong
+/// environment.
+///
+/// Registering against the wrong server (say, staging instead of production)
+/// is easy to miss and hard to undo. Each connection made by the wrapped
+/// `ConnectChat` is checked against the chat host expected for the
+/// environment, and a mismatch is reported as
+/// [`ChatConnectError::InvalidConnectionConfiguration`] so that registration
+/// stops instead of retrying.
+pub struct RequireEnvironment<C> {
+    connect_chat: C,
+    expected_chat_host: Arc<str>,
+}
+
+impl<C> RequireEnvironment<C> {
+    pub fn new(connect_chat: C, expected_chat_host: impl Into<Arc<str>>) -> Self {
+        Self {
+            connect_chat,
+            expected_chat_host: expected_chat_host.into(),
+        }
+    }
+}
+
+impl<C: ConnectChat + Sync> ConnectChat for RequireEnvironment<C> {
+    fn connect_chat(
+        &self,
+        on_disconnect: oneshot::Sender<Infallible>,
+    ) -> BoxFuture<'_, Result<ChatConnection, ChatConnectError>> {
+        let Self {
+            connect_chat,
+            expected_chat_host,
+        } = self;
+        async move {
+            let chat = connect_chat.connect_chat(on_disconnect).await?;
+            let connected_host = chat
+                .connection_info()
+                .route_info
+                .unresolved()
+                .target_host()
+                .clone();
+            if connected_host.as_deref() != Host::Domain(&**expected_chat_host) {
+                log::error!(
+                    "registration chat connected to {connected_host}, \
+                     but {expected_chat_host} was expected"
+                );
+                chat.disconnect().await;
+                return Err(ChatConnectError::InvalidConnectionConfiguration);
+            }
+            Ok(chat)
+        }
+        .boxed()
+    }
+
+    fn connection_pool(&self) -> Option<&ChatConnectionPool> {
+        // The pool might be shared with a ConnectChat that doesn't check the
+        // environment, so its connections can't be trusted.
+        None
+    }
+
+    fn retry_delay_params(&self) -> ConnectionOutcomeParams {
+        self.connect_chat.retry_delay_params()
+    }
+}
+
+/// A [`ConnectChat`] that makes unauthenticated chat connections with a
+/// [`ConnectState`].
+///
+/// Connections are made like [`ChatConnection::start_connect_with`] does, over
+/// the routes from `route_provider`. The connect state (and so its record of
+/// which routes have been working) is shared with anything else using it.
+pub struct ConnectChatWithState<'a, TC, R> {
+    pub connect_state: &'a std::sync::Mutex<ConnectState<TC>>,
+    pub dns_resolver: &'a DnsResolver,
+    pub network_change_event: &'a ObservableEvent,
+    pub confirmation_header_name: Option<HeaderName>,
+    pub route_provider: R,
+    pub user_agent: UserAgent,
+    pub ws_config: crate::chat::ws2::Config,
+}
+
+// Everything borrowed here is either immutable or behind a mutex that would be
+// poisoned by a panic, so observing it after an unwind is fine.
+impl<TC, R: UnwindSafe> UnwindSafe for ConnectChatWithState<'_, TC, R> {}
+
+impl<TC, R> ConnectChat for ConnectChatWithState<'_, TC, R>
+where
+    TC: WebSocketTransportConnectorFactory<
+            UsePreconnect<TransportRoute>,
+            Connector: Send,
+            Connection: libsignal_net_infra::Connection,
+        > + Send,
+    R: RouteProvider<Route = UnresolvedHttpsServiceRoute> + Sync,
+{
+    fn connect_chat(
+        &self,
+        on_disconnect: oneshot::Sender<Infallible>,
+    ) -> BoxFuture<'_, Result<ChatConnection, ChatConnectError>> {
+        let Self {
+            connect_state,
+            dns_resolver,
+            network_change_event,
+            confirmation_header_name,
+            route_provider,
+            user_agent,
+            ws_config,
+        } = self;
+        async move {
+            let connection_resources = ConnectionResources {
+                connect_state,
+                dns_resolver,
+                network_change_event,
+                confirmation_header_name: confirmation_header_name.clone(),
+            };
+            let pending = ChatConnection::start_connect_with_transport(
+                connection_resources,
+                route_provider,
+                user_agent,
+                *ws_config,
+                None,
+                PreconnectPolicy::UseIfAvailable,
+                "registration",
+            )
+            .await?;
+
+            let mut on_disconnect = Some(on_disconnect);
+            let listener = move |event| match event {
+                ListenerEvent::Finished(_) => drop(on_disconnect.take()),
+                ListenerEvent::ReceivedAlerts(_) | ListenerEvent::ReceivedMessage(_, _) => (),
+            };
+            Ok(ChatConnection::finish_connect(
+                tokio::runtime::Handle::current(),
+                pending,
+                Box::new(listener),
+            ))
+        }
+        .boxed()
+    }
+
+    /// Jitters retries by the [`ConnectState`]'s configured
+    /// [`ConnectionOutcomeParams::jitter_fraction`].
+    fn retry_delay_params(&self) -> ConnectionOutcomeParams {
+        let jitter_fraction = lock_connect_state(self.connect_state)
+            .connect_params()
+            .jitter_fraction;
+        ConnectionOutcomeParams {
+            jitter_fraction,
+            ..CHAT_CONNECT_DELAY_PARAMS
+        }
+    }
 }
 
 impl<'c> RegistrationConnection<'c> {
     /// Attempts to connect to the chat service and send a request.
     ///
-    /// This method will retry internally if transient errors are encountered.
+    /// The request is handed to the new connection as soon as it's
+    /// established, so the connection can't be closed for inactivity before
+    /// the request is sent. This method will retry internally if transient
+    /// errors are encountered.
     pub(super) async fn connect_and_send(
         connect_chat: Box<dyn ConnectChat + Send + Sync + UnwindSafe + 'c>,
         request: ChatRequest,
     ) -> Result<(Self, ChatResponse), RequestError<SessionRequestError>> {
-        let (response, sender) = send_request(request, &*connect_chat, None).await?;
+        let inactivity_timeout = watch::Sender::new(INACTIVITY_TIMEOUT);
+        let mut reconnects = ReconnectBudget::default();
+        let (response, sender, task) = send_request(
+            request,
+            &*connect_chat,
+            None,
+            Duration::ZERO,
+            &inactivity_timeout.subscribe(),
+            &mut reconnects,
+        )
+        .await?;
 
         Ok((
             Self {
                 connect_chat,
-                sender,
+                sender: Some(sender),
+                task,
+                draining: None,
+                reconnect_grace_period: Duration::ZERO,
+                inactivity_timeout,
+                reconnects,
             },
             response,
         ))
     }
 
+    /// Sets how long to wait, after a request fails because the connection
+    /// was lost, for the existing connection to prove still usable before
+    /// establishing a new one.
+    ///
+    /// Zero (the default) means reconnecting right away.
+    pub(super) fn set_reconnect_grace_period(&mut self, grace_period: Duration) {
+        self.reconnect_grace_period = grace_period;
+    }
+
+    /// Sets how many times in a row a lost connection may be replaced before
+    /// requests fail instead.
+    ///
+    /// The count covers every request made since the last one that
+    /// succeeded. `None` (the default) means reconnecting indefinitely.
+    pub(super) fn set_max_reconnects(&mut self, max_reconnects: Option<u32>) {
+        self.reconnects.max_reconnects = max_reconnects;
+    }
+
+    /// Adjusts how long the connection stays open once it's idle, based on
+    /// whether another request is expected soon.
+    ///
+    /// If `imminent`, the connection is kept open for
+    /// [`IMMINENT_REQUEST_INACTIVITY_TIMEOUT`] instead of the usual
+    /// [`INACTIVITY_TIMEOUT`]; otherwise it's closed as soon as it's idle,
+    /// rather than returned to a [`ChatConnectionPool`]. The hint applies to
+    /// the current idle period, counted from when the last request finished,
+    /// and is cleared by the next request.
+    pub(super) fn hint_next_request_imminent(&mut self, imminent: bool) {
+        self.inactivity_timeout.send_replace(if imminent {
+            IMMINENT_REQUEST_INACTIVITY_TIMEOUT
+        } else {
+            Duration::ZERO
+        });
+    }
+
+    /// Replaces the [`ConnectChat`] used to establish new connections.
+    ///
+    /// The next request is sent over a connection made with `connect_chat`.
+    /// The current connection, if any, is drained first: requests already
+    /// sent on it are allowed to finish (up to [`DRAIN_TIMEOUT`]) before
+    /// anything is sent on the new one, so they can't reach the server out of
+    /// order.
+    pub(super) fn set_connect_chat(
+        &mut self,
+        connect_chat: Box<dyn ConnectChat + Send + Sync + UnwindSafe + 'c>,
+    ) {
+        self.connect_chat = connect_chat;
+        // Dropping the sender lets the connection task shut down once it has
+        // finished any requests already handed to it.
+        self.sender = None;
+        if let Some(task) = self.task.take() {
+            if let Some(previous) = self.draining.replace(task) {
+                // That connection was replaced before anything else was sent,
+                // so it had nothing left to wait for.
+                previous.abort();
+            }
+        }
+    }
+
     /// Sends a request on an established connection.
     ///
     /// This method will retry internally if transient errors are encountered.
+    /// If the connection is lost after a non-idempotent request might have
+    /// reached the server, [`RequestError::OutcomeUnknown`] is returned
+    /// instead of resubmitting it.
     pub(super) async fn submit_chat_request(
         &mut self,
         request: ChatRequest,
     ) -> Result<ChatResponse, RequestError<SessionRequestError>> {
         let Self {
             sender,
+            task,
+            draining,
             connect_chat,
+            reconnect_grace_period,
+            inactivity_timeout,
+            reconnects,
         } = self;
 
-        let (response, request_sender) =
-            send_request(request, &**connect_chat, Some(sender)).await?;
-        *sender = request_sender;
+        if let Some(draining) = draining.take() {
+            drain_connection_task(draining).await;
+        }
+
+        // Any hint was about this request; the next idle period starts over.
+        inactivity_timeout.send_replace(INACTIVITY_TIMEOUT);
+        let (response, request_sender, new_task) = send_request(
+            request,
+            &**connect_chat,
+            sender.as_ref(),
+            *reconnect_grace_period,
+            &inactivity_timeout.subscribe(),
+            reconnects,
+        )
+        .await?;
+        *sender = Some(request_sender);
+        if let Some(new_task) = new_task {
+            if let Some(old_task) = task.replace(new_task) {
+                old_task.abort();
+            }
+        }
 
         Ok(response)
     }
 }
 
+impl Drop for RegistrationConnection<'_> {
+    fn drop(&mut self) {
+        // Stop the connection task right away instead of leaving it to notice
+        // that no more requests are coming, so the socket is closed promptly.
+        if let Some(task) = self.task.take() {
+            task.abort();
+        }
+        if let Some(draining) = self.draining.take() {
+            draining.abort();
+        }
+    }
+}
+
+/// Waits for the task of a replaced connection to finish the requests it was
+/// handed and exit.
+///
+/// The task is stopped if that takes longer than [`DRAIN_TIMEOUT`].
+async fn drain_connection_task(mut task: tokio::task::JoinHandle<()>) {
+    log::info!("waiting for the previous registration connection to finish its requests");
+    match tokio::time::timeout(DRAIN_TIMEOUT, &mut task).await {
+        Ok(Ok(())) => (),
+        Ok(Err(join_error)) => {
+            log::warn!("the previous registration connection task failed: {join_error}")
+        }
+        Err(_elapsed) => {
+            log::warn!("the previous registration connection didn't finish in time; stopping it");
+            task.abort();
+        }
+    }
+}
+
 /// Sends a request to the chat service.
 ///
 /// Uses the provided sender if there is one, otherwise establishes a new
 /// connection to the service. Non-fatal connect errors are retried.
+///
+/// If the connection is lost before the request was handed to the server, it
+/// is resent on a new connection. If it was lost while the request was in
+/// flight, the server might have already processed it. Idempotent requests
+/// are resent anyway, but for others [`RequestError::OutcomeUnknown`] is
+/// returned so that the request isn't applied twice.
+///
+/// A lost connection might only be a momentary blip. If the task for the
+/// connection is still running once `reconnect_grace_period` has passed, the
+/// request is retried on it instead of connecting again.
+///
+/// Each new connection made to replace a lost one is counted against
+/// `reconnects`; once its limit is reached the request fails instead. The
+/// count is reset when the request succeeds.
+///
+/// If a new connection had to be made, the request is the first one sent on
+/// it, and the handle for its task is returned along with the sender
+/// for it. The task closes the connection after `inactivity_timeout` without
+/// requests.
 async fn send_request<E>(
     request: ChatRequest,
     connect_chat: &(impl ConnectChat + Sync + ?Sized),
-    mut sender: Option<&mpsc::Sender<IncomingRequest>>,
-) -> Result<(ChatResponse, mpsc::Sender<IncomingRequest>), RequestError<E>>
+    sender: Option<&mpsc::Sender<IncomingRequest>>,
+    reconnect_grace_period: Duration,
+    inactivity_timeout: &watch::Receiver<Duration>,
+    reconnects: &mut ReconnectBudget,
+) -> Result<
+    (
+        ChatResponse,
+        mpsc::Sender<IncomingRequest>,
+        Option<tokio::task::JoinHandle<()>>,
+    ),
+    RequestError<E>,
+>
 where
     RequestError<E>: From<FatalConnectError>,
 {
+    let mut sender = sender.cloned();
+    let mut new_task = None;
     loop {
-        let sender = match sender.take() {
-            Some(sender) => sender.clone(),
+        let (sender, result) = match sender.take() {
+            Some(sender) => {
+                let result = send_request_to_connected_chat(request.clone(), &sender).await;
+                (sender, result)
+            }
             None => {
-                let (sender, _join_handle) = spawn_connected_chat(connect_chat)
-                    .await
-                    .map_err(RequestError::from)?;
-                sender
+                let (incoming_request, response) = request_for_task(request.clone());
+                let (sender, join_handle) = spawn_connected_chat(
+                    connect_chat,
+                    Some(incoming_request),
+                    inactivity_timeout.clone(),
+                )
+                .await
+                .map_err(RequestError::from)?;
+                if let Some(replaced) = new_task.replace(join_handle) {
+                    // That connection was lost, but make sure its task is gone.
+                    replaced.abort();
+                }
+                (sender, response.await)
             }
         };
-        let result = match send_request_to_connected_chat(request.clone(), &sender).await {
-            Ok(response) => Ok((response, sender)),
-            Err(SendRequestError::ConnectionLost) => {
+        let result = match result {
+            Ok(response) => {
+                reconnects.reset();
+                Ok((response, sender, new_task))
+            }
+            Err(SendRequestError::ConnectionLostBeforeSend) => {
                 log::info!("the connection to the chat server was lost, will retry");
+                sender = reusable_after_grace_period(sender, reconnect_grace_period).await;
+                if sender.is_none() {
+                    reconnects.record_reconnect()?;
+                }
+                continue;
+            }
+            Err(SendRequestError::ConnectionLostInFlight) => {
+                if !request.method.is_idempotent() {
+                    log::warn!(
+                        "the connection to the chat server was lost after sending a \
+                         non-idempotent request; not resending it"
+                    );
+                    return Err(RequestError::OutcomeUnknown);
+                }
+                log::info!("the connection to the chat server was lost, will retry");
+                sender = reusable_after_grace_period(sender, reconnect_grace_period).await;
+                if sender.is_none() {
+                    reconnects.record_reconnect()?;
+                }
                 continue;
             }
             Err(SendRequestError::RequestTimedOut) => Err(RequestError::Timeout),
@@ -124,6 +700,26 @@ where
     }
 }
 
+/// Waits up to `grace_period` to see whether the task behind `sender` exits.
+///
+/// Returns the sender if the task is still running afterwards, meaning it can
+/// be used to retry, or `None` if a new connection is needed.
+async fn reusable_after_grace_period(
+    sender: mpsc::Sender<IncomingRequest>,
+    grace_period: Duration,
+) -> Option<mpsc::Sender<IncomingRequest>> {
+    if grace_period.is_zero() {
+        return None;
+    }
+    match tokio::time::timeout(grace_period, sender.closed()).await {
+        Ok(()) => None,
+        Err(_elapsed) => {
+            log::info!("the chat connection is still usable after {grace_period:?}; reusing it");
+            Some(sender)
+        }
+    }
+}
+
 #[derive(Debug)]
 enum FatalConnectError {
     InvalidConfiguration,
@@ -148,21 +744,59 @@ where
     }
 }
 
-const CHAT_CONNECT_DELAY_PARAMS: libsignal_net_infra::route::ConnectionOutcomeParams =
-    crate::infra::route::ConnectionOutcomeParams {
-        age_cutoff: Duration::from_secs(60),
-        cooldown_growth_factor: 1.5,
-        count_growth_factor: 10.0,
-        max_count: 5,
-        max_delay: Duration::from_secs(30),
-    };
+const CHAT_CONNECT_DELAY_PARAMS: ConnectionOutcomeParams = ConnectionOutcomeParams {
+    age_cutoff: Duration::from_secs(60),
+    cooldown_growth_factor: 1.5,
+    count_growth_factor: 10.0,
+    max_count: 5,
+    max_delay: Duration::from_secs(30),
+    max_entries: None,
+    aged_out_successes: crate::infra::route::AgedOutSuccessOrdering::Unranked,
+    jitter_fraction: 0.0,
+};
+
+#[cfg(test)]
+tokio::task_local! {
+    /// Called with the number of failures so far and the computed delay before
+    /// each connect retry made by [`spawn_connected_chat`] on this task.
+    static ON_RETRY_DELAY: Box<dyn Fn(u32, Duration)>;
+}
 
 /// Connects to the chat service and spawns a task to manage it.
 ///
-/// Returns a channel for sending requests to it.
+/// An idle connection from the [`ConnectChat::connection_pool`] is used if
+/// there is one; the task will return the connection to the pool once it goes
+/// idle again. If `first_request` is provided, the task starts out sending it, so the
+/// connection's inactivity timeout doesn't start until it's finished. Returns
+/// a channel for sending further requests to the task.
+///
+/// The task goes idle once no request has come in for the current value of
+/// `inactivity_timeout`.
 async fn spawn_connected_chat(
     connect_chat: &(impl ConnectChat + ?Sized),
+    first_request: Option<IncomingRequest>,
+    inactivity_timeout: watch::Receiver<Duration>,
 ) -> Result<(mpsc::Sender<IncomingRequest>, tokio::task::JoinHandle<()>), FatalConnectError> {
+    let pool = connect_chat.connection_pool();
+    if let Some(IdleChat {
+        chat,
+        on_disconnect,
+        idle_since: _,
+    }) = pool.and_then(ChatConnectionPool::take)
+    {
+        log::debug!("reusing a pooled chat connection for registration");
+        let (sender, receiver) = mpsc::channel(MAX_PENDING_REQUESTS);
+        let handle = tokio::spawn(spawned_task_body(
+            chat,
+            first_request,
+            ReceiverStream::new(receiver),
+            on_disconnect,
+            inactivity_timeout,
+            pool.cloned(),
+        ));
+        return Ok((sender, handle));
+    }
+
     let mut failure_count = 0;
     let mut last_failure_at = None;
 
@@ -180,19 +814,32 @@ async fn spawn_connected_chat(
                     ChatConnectError::InvalidConnectionConfiguration => {
                         return Err(FatalConnectError::InvalidConfiguration)
                     }
-                    ChatConnectError::RetryLater(retry_later) => {
+                    ChatConnectError::RetryLater(retry_later)
+                    | ChatConnectError::ServerMaintenance {
+                        retry_after: Some(retry_later),
+                    } => {
                         return Err(FatalConnectError::RetryLater(retry_later));
                     }
                     err @ (ChatConnectError::Timeout
                     | ChatConnectError::AllAttemptsFailed
+                    | ChatConnectError::NoServerResponse
+                    | ChatConnectError::ServerMaintenance { retry_after: None }
+                    | ChatConnectError::UnstableNetwork
+                    | ChatConnectError::Throttled
                     | ChatConnectError::WebSocket(_)) => {
                         log::warn!("retryable error: {}", (&err as &dyn LogSafeDisplay));
                         let now = Instant::now();
                         let since_last_failure = last_failure_at
                             .replace(now)
                             .map_or(Duration::MAX, |previous_failure| now - previous_failure);
-                        let delay = CHAT_CONNECT_DELAY_PARAMS
-                            .compute_delay(since_last_failure, failure_count);
+                        let delay_params = connect_chat.retry_delay_params();
+                        let delay = delay_params.apply_jitter(
+                            delay_params.compute_delay(since_last_failure, failure_count),
+                            &RouteProviderContextImpl::default(),
+                        );
+                        #[cfg(test)]
+                        let _not_observed = ON_RETRY_DELAY
+                            .try_with(|on_retry_delay| on_retry_delay(failure_count.into(), delay));
                         tokio::time::sleep(delay).await;
                         failure_count += 1;
                         continue;
@@ -207,6 +854,16 @@ async fn spawn_connected_chat(
                             "unauthenticated socket signaled deregistration",
                         ));
                     }
+                    ChatConnectError::UnsupportedSubprotocol => {
+                        return Err(FatalConnectError::Unexpected(
+                            "server selected an unsupported websocket subprotocol",
+                        ));
+                    }
+                    ChatConnectError::Cancelled => {
+                        return Err(FatalConnectError::Unexpected(
+                            "registration chat connect was cancelled",
+                        ));
+                    }
                 }
             }
         };
@@ -214,22 +871,24 @@ async fn spawn_connected_chat(
         break (chat, on_disconnect_rx);
     };
     let (sender, receiver) = mpsc::channel(MAX_PENDING_REQUESTS);
-    let on_disconnect = on_disconnect_rx.map(|r| match r {
-        Ok(infallible) => match infallible {},
-        Err(_recv_error) => (),
-    });
     log::info!("successfully connecting chat for registration");
     let handle = tokio::spawn(spawned_task_body(
         chat,
+        first_request,
         ReceiverStream::new(receiver),
-        on_disconnect,
+        on_disconnect_rx,
+        inactivity_timeout,
+        pool.cloned(),
     ));
     Ok((sender, handle))
 }
 
 #[derive(Debug, derive_more::From)]
 enum SendRequestError {
-    ConnectionLost,
+    /// The connection was lost before the request was handed to the server.
+    ConnectionLostBeforeSend,
+    /// The connection was lost after the request might have reached the server.
+    ConnectionLostInFlight,
     Unknown(String),
     RequestTimedOut,
 }
@@ -242,17 +901,46 @@ async fn send_request_to_connected_chat(
     request: ChatRequest,
     sender: &mpsc::Sender<IncomingRequest>,
 ) -> Result<ChatResponse, SendRequestError> {
-    let (responder, receiver) = oneshot::channel();
-    match sender.send((request.clone(), responder)).await {
+    let (incoming_request, response) = request_for_task(request);
+    match sender.send(incoming_request).await {
         Ok(()) => (),
         Err(_channel_closed) => {
-            return Err(SendRequestError::ConnectionLost);
+            return Err(SendRequestError::ConnectionLostBeforeSend);
         }
     };
 
-    let result = receiver
-        .await
-        .map_err(|_: oneshot::error::RecvError| SendRequestError::ConnectionLost)?;
+    response.await
+}
+
+/// Packages `request` to be handed to a connection task.
+///
+/// The returned future resolves once the task has finished with the request,
+/// or has been dropped without doing so.
+fn request_for_task(
+    request: ChatRequest,
+) -> (
+    IncomingRequest,
+    impl Future<Output = Result<ChatResponse, SendRequestError>>,
+) {
+    let (responder, receiver) = oneshot::channel();
+    let (on_dispatch, dispatched) = oneshot::channel();
+    (
+        (request, on_dispatch, responder),
+        receive_response(receiver, dispatched),
+    )
+}
+
+async fn receive_response(
+    receiver: oneshot::Receiver<Result<ChatResponse, ChatSendError>>,
+    mut dispatched: oneshot::Receiver<()>,
+) -> Result<ChatResponse, SendRequestError> {
+    let result =
+        receiver
+            .await
+            .map_err(|_: oneshot::error::RecvError| match dispatched.try_recv() {
+                Ok(()) => SendRequestError::ConnectionLostInFlight,
+                Err(_) => SendRequestError::ConnectionLostBeforeSend,
+            })?;
 
     let response = result.map_err(|err| {
         log::warn!(
@@ -261,7 +949,7 @@ async fn send_request_to_connected_chat(
         );
         match err {
             ChatSendError::RequestTimedOut => SendRequestError::RequestTimedOut,
-            ChatSendError::Disconnected => SendRequestError::ConnectionLost,
+            ChatSendError::Disconnected => SendRequestError::ConnectionLostInFlight,
             ChatSendError::ConnectionInvalidated | ChatSendError::ConnectedElsewhere => {
                 SendRequestError::Unknown(
                     "registration connection unexpectedly closed by server".into(),
@@ -277,6 +965,9 @@ async fn send_request_to_connected_chat(
             ChatSendError::RequestHasInvalidHeader => {
                 SendRequestError::Unknown("request had invalid header".into())
             }
+            ChatSendError::ServerMaintenance { retry_after: _ } => {
+                SendRequestError::Unknown("server is down for maintenance".into())
+            }
         }
     })?;
 
@@ -287,27 +978,87 @@ async fn send_request_to_connected_chat(
 /// The body of a spawned [`tokio::task`] that handles the given
 /// [`ChatConnection`].
 ///
-/// Sends received incoming requests to the provided `ChatConnection` as long as
-/// it remains connected. The task handles a single request at a time in the
-/// order that they are received. If the `ChatConnection` stops working, or if
-/// the `on_disconnect` future resolves, the stream of incoming requests will be
-/// dropped. Callers can use that to determine whether the task is still active.
+/// Handles requests as described for [`serve_requests`]. Once the connection
+/// goes idle, it's returned to `pool` if there is one, and closed otherwise.
 async fn spawned_task_body(
     chat: ChatConnection,
+    first_request: Option<IncomingRequest>,
     incoming_requests: impl Stream<Item = IncomingRequest> + Send,
-    mut on_disconnect: impl Future<Output = ()>,
+    mut on_disconnect: oneshot::Receiver<Infallible>,
+    inactivity_timeout: watch::Receiver<Duration>,
+    pool: Option<ChatConnectionPool>,
 ) {
+    let wait_for_disconnect = (&mut on_disconnect).map(|r| match r {
+        Ok(infallible) => match infallible {},
+        Err(_recv_error) => (),
+    });
+    match serve_requests(
+        &chat,
+        first_request,
+        incoming_requests,
+        wait_for_disconnect,
+        inactivity_timeout,
+    )
+    .await
+    {
+        ServeEnd::Disconnected => {}
+        ServeEnd::Finished => chat.disconnect().await,
+        ServeEnd::Idle => match pool {
+            Some(pool) => {
+                log::debug!("returning the idle registration chat connection to the pool");
+                pool.put(IdleChat {
+                    chat,
+                    on_disconnect,
+                    idle_since: Instant::now(),
+                });
+            }
+            None => chat.disconnect().await,
+        },
+    }
+}
+
+/// Why [`serve_requests`] returned.
+enum ServeEnd {
+    /// The connection was lost.
+    Disconnected,
+    /// The connection is still up, but there are no more requests to send.
+    Idle,
+    /// The connection is still up, but no more requests are expected (see
+    /// [`RegistrationConnection::hint_next_request_imminent`]), so it should
+    /// be closed instead of kept around.
+    Finished,
+}
+
+/// Sends `first_request`, if there is one, and then received incoming requests
+/// to the provided `ChatConnection` as long as it remains connected.
+///
+/// Requests are handled one at a time in the order that they are received.
+/// Returns when the connection is lost (signalled by `on_disconnect`
+/// resolving), when no request comes in for `inactivity_timeout`, or when
+/// the stream of incoming requests ends. The stream is dropped on return, so
+/// senders can use that to determine whether the task is still active.
+///
+/// If `inactivity_timeout` changes while waiting for a request, the new value
+/// is counted from when the wait started.
+async fn serve_requests(
+    chat: &ChatConnection,
+    first_request: Option<IncomingRequest>,
+    incoming_requests: impl Stream<Item = IncomingRequest> + Send,
+    on_disconnect: impl Future<Output = ()>,
+    mut inactivity_timeout: watch::Receiver<Duration>,
+) -> ServeEnd {
     let mut on_disconnect = std::pin::pin!(on_disconnect);
 
     let incoming_requests = Some(incoming_requests);
-    let request_in_progress = None;
+    let request_in_progress = first_request.map(|request| start_request(chat, request));
     let mut request_in_progress = std::pin::pin!(request_in_progress);
     let mut incoming_requests = std::pin::pin!(incoming_requests);
 
     loop {
         enum Event {
             RequestFinished,
-            Incoming(Result<Option<IncomingRequest>, tokio::time::error::Elapsed>),
+            Incoming(Option<IncomingRequest>),
+            Inactive,
             Disconnected,
         }
 
@@ -324,12 +1075,23 @@ async fn spawned_task_body(
                     // There's no request in progress and none are coming in.
                     break;
                 }
-                Some(mut incoming_requests) => Either::Right(
-                    tokio::time::timeout(INACTIVITY_TIMEOUT, async move {
-                        incoming_requests.next().await
+                Some(mut incoming_requests) => {
+                    let inactivity_timeout = &mut inactivity_timeout;
+                    Either::Right(async move {
+                        let idle_since = Instant::now();
+                        loop {
+                            let deadline = idle_since + *inactivity_timeout.borrow_and_update();
+                            tokio::select! {
+                                request = incoming_requests.next() => {
+                                    break Event::Incoming(request);
+                                }
+                                () = tokio::time::sleep_until(deadline) => break Event::Inactive,
+                                // Recompute the deadline with the new timeout.
+                                Ok(()) = inactivity_timeout.changed() => {}
+                            }
+                        }
                     })
-                    .map(Event::Incoming),
-                ),
+                }
             },
         };
 
@@ -344,20 +1106,24 @@ async fn spawned_task_body(
                 // If that was the last request we'll discover that at the top of the loop.
                 continue;
             }
-            Event::Incoming(Err(_)) => {
+            Event::Inactive => {
                 // This only happens when there are no requests in flight.
-                log::warn!("registration chat inactivity timeout was reached; disconnecting");
+                if inactivity_timeout.borrow().is_zero() {
+                    log::debug!("no more registration requests are expected");
+                    incoming_requests.set(None);
+                    return ServeEnd::Finished;
+                }
+                log::warn!("registration chat inactivity timeout was reached");
                 break;
             }
             Event::Disconnected => {
-                // Nothing to do.
-                return;
+                return ServeEnd::Disconnected;
             }
-            Event::Incoming(Ok(Some(request))) => {
-                let request_fut = start_request(&chat, request);
+            Event::Incoming(Some(request)) => {
+                let request_fut = start_request(chat, request);
                 request_in_progress.set(Some(request_fut));
             }
-            Event::Incoming(Ok(None)) => {
+            Event::Incoming(None) => {
                 // Indicate that we won't be getting any more requests.
                 incoming_requests.set(None);
             }
@@ -367,37 +1133,61 @@ async fn spawned_task_body(
     // gets feedback sooner.
     incoming_requests.set(None);
 
-    chat.disconnect().await;
+    ServeEnd::Idle
 }
 
 /// How long to wait after the last request before disconnecting from Chat.
 const INACTIVITY_TIMEOUT: Duration = Duration::from_secs(90);
 
+/// How long a connection can sit unused in a [`ChatConnectionPool`] before it's
+/// closed instead of being reused.
+///
+/// This is on top of [`INACTIVITY_TIMEOUT`], which has to pass before a
+/// connection is pooled in the first place.
+const POOLED_CONNECTION_IDLE_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// How long to wait after the last request before disconnecting from Chat,
+/// when another request is expected soon.
+///
+/// See [`RegistrationConnection::hint_next_request_imminent`].
+const IMMINENT_REQUEST_INACTIVITY_TIMEOUT: Duration = Duration::from_secs(300);
+
 /// How long each request to the Chat server should be allowed to take.
 ///
 /// This doesn't include the amount of time spent connecting to the service in
 /// the first place.
 const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
 
+/// How long to wait for a replaced connection to finish its requests and
+/// close before sending anything on its replacement.
+///
+/// This leaves time for a request that was just sent to run into
+/// [`REQUEST_TIMEOUT`], plus a little for the connection to close.
+const DRAIN_TIMEOUT: Duration = REQUEST_TIMEOUT.saturating_add(Duration::from_secs(5));
+
 /// The maximum number of requests that can be pending but not sent off yet.
 ///
 /// This can be extremely small since the registration process is serialized;
 /// there is no need to have multiple requests in flight at a time.
 const MAX_PENDING_REQUESTS: usize = 1;
 
+/// A request for the spawned task, along with a sender that is signalled when
+/// the request is handed to the [`ChatConnection`] and one for the response.
 type IncomingRequest = (
     ChatRequest,
+    oneshot::Sender<()>,
     oneshot::Sender<Result<ChatResponse, ChatSendError>>,
 );
 
-async fn start_request(chat: &ChatConnection, (request, mut responder): IncomingRequest) {
+async fn start_request(chat: &ChatConnection, (request, on_dispatch, responder): IncomingRequest) {
     if responder.is_closed() {
         return;
     }
-    let result = tokio::select! {
-        result = chat.send(request, REQUEST_TIMEOUT) => result,
-        () = responder.closed() => return,
-    };
+    let _ignore_closed = on_dispatch.send(());
+    // Once the request is on its way to the server, see it through even if
+    // nobody is waiting for the response anymore, so that whatever is sent
+    // next (on this connection or a replacement) can't overtake it.
+    let result = chat.send(request, REQUEST_TIMEOUT).await;
 
     match responder.send(result) {
         Ok(()) => (),
@@ -408,7 +1198,7 @@ async fn start_request(chat: &ChatConnection, (request, mut responder): Incoming
 #[cfg(test)]
 mod test {
     use std::sync::atomic::AtomicUsize;
-    use std::sync::LazyLock;
+    use std::sync::{LazyLock, Mutex};
 
     use assert_matches::assert_matches;
     use http::uri::PathAndQuery;
@@ -421,6 +1211,11 @@ mod test {
     use crate::registration::testutil::{ConnectChatFn, DropOnDisconnect, FakeChatConnect};
     use crate::registration::{RegistrationResponse, RegistrationSession};
 
+    /// An inactivity timeout for a connection task that stays at the default.
+    fn default_inactivity_timeout() -> watch::Receiver<Duration> {
+        watch::channel(INACTIVITY_TIMEOUT).1
+    }
+
     /// A value to use when we don't care about the contents of the request.
     static SOME_REQUEST: LazyLock<ChatRequest> = LazyLock::new(|| ChatRequest {
         method: http::Method::GET,
@@ -436,9 +1231,10 @@ mod test {
             remote: fake_chat_remote_tx,
         };
 
-        let (sender, join_handle) = spawn_connected_chat(&fake_connect)
-            .await
-            .expect("can connect");
+        let (sender, join_handle) =
+            spawn_connected_chat(&fake_connect, None, default_inactivity_timeout())
+                .await
+                .expect("can connect");
 
         // With no requests sent to it, the task will hang up after the allowed inactivity period.
         let start = Instant::now();
@@ -447,12 +1243,324 @@ mod test {
 
         // Trying to send to it now is futile!
         let (tx, _rx) = oneshot::channel();
+        let (dispatch_tx, _dispatch_rx) = oneshot::channel();
         sender
-            .send((SOME_REQUEST.clone(), tx))
+            .send((SOME_REQUEST.clone(), dispatch_tx, tx))
             .await
             .expect_err("remote should have hung up");
     }
 
+    #[tokio::test(start_paused = true)]
+    async fn first_request_is_sent_on_new_connection() {
+        let (fake_chat_remote_tx, mut fake_chat_remote_rx) = mpsc::unbounded_channel();
+        let fake_connect = FakeChatConnect {
+            remote: fake_chat_remote_tx,
+        };
+
+        let (incoming_request, response) = request_for_task(SOME_REQUEST.clone());
+        let (_sender, join_handle) = spawn_connected_chat(
+            &fake_connect,
+            Some(incoming_request),
+            default_inactivity_timeout(),
+        )
+        .await
+        .expect("can connect");
+        let fake_remote = fake_chat_remote_rx
+            .recv()
+            .await
+            .expect("connection started");
+
+        // The server takes a while to respond, but the request was already
+        // handed to the connection, so it isn't closed out from under it.
+        tokio::time::sleep(REQUEST_TIMEOUT / 2).await;
+        let request = fake_remote
+            .receive_request()
+            .await
+            .expect("still connected")
+            .expect("request received");
+        assert_eq!(request.path(), SOME_REQUEST.path.as_str());
+        fake_remote
+            .send_response(
+                RegistrationResponse {
+                    session_id: "abcdef".to_string(),
+                    session: RegistrationSession::default(),
+                }
+                .into_websocket_response(request.id()),
+            )
+            .expect("still connected");
+        let _response = response.await.expect("request succeeded");
+
+        // The inactivity timeout only starts once the first request is done.
+        let start = Instant::now();
+        let () = join_handle.await.expect("finished gracefully");
+        assert_eq!(start.elapsed(), INACTIVITY_TIMEOUT);
+    }
+
+    /// How long after a request finishes to give a hint about the next one.
+    const HINT_DELAY: Duration = Duration::from_secs(1);
+
+    #[test_case(None => INACTIVITY_TIMEOUT; "no hint")]
+    #[test_case(Some(true) => IMMINENT_REQUEST_INACTIVITY_TIMEOUT; "imminent")]
+    #[test_case(Some(false) => HINT_DELAY; "done")]
+    #[tokio::test(start_paused = true)]
+    async fn next_request_hint_adjusts_inactivity_timeout(hint: Option<bool>) -> Duration {
+        let (fake_chat_remote_tx, mut fake_chat_remote_rx) = mpsc::unbounded_channel();
+        let fake_connect = FakeChatConnect {
+            remote: fake_chat_remote_tx,
+        };
+
+        let server = async {
+            let fake_remote = fake_chat_remote_rx.recv().await.expect("connected");
+            let request = fake_remote
+                .receive_request()
+                .await
+                .expect("still connected")
+                .expect("request received");
+            fake_remote
+                .send_response(
+                    RegistrationResponse {
+                        session_id: "abcdef".to_string(),
+                        session: RegistrationSession::default(),
+                    }
+                    .into_websocket_response(request.id()),
+                )
+                .expect("still connected");
+            fake_remote
+        };
+        let client =
+            RegistrationConnection::connect_and_send(Box::new(fake_connect), SOME_REQUEST.clone());
+        let (fake_remote, result) = tokio::join!(server, client);
+        let (mut connection, _response) = result.expect("request succeeded");
+
+        // Give the hint partway into the idle period; the timeout is still
+        // counted from when the request finished, so with no time left the
+        // connection closes right away.
+        let start = Instant::now();
+        tokio::time::sleep(HINT_DELAY).await;
+        if let Some(imminent) = hint {
+            connection.hint_next_request_imminent(imminent);
+        }
+
+        assert_matches!(fake_remote.receive_request().await, Ok(None));
+        start.elapsed()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn done_hint_closes_pooled_connection() {
+        let (fake_chat_remote_tx, mut fake_chat_remote_rx) = mpsc::unbounded_channel();
+        let pool = ChatConnectionPool::new(&ObservableEvent::new());
+        let connect_chat = PooledConnectChat::new(
+            FakeChatConnect {
+                remote: fake_chat_remote_tx,
+            },
+            pool.clone(),
+        );
+
+        let server = async {
+            let fake_remote = fake_chat_remote_rx.recv().await.expect("connected");
+            let request = fake_remote
+                .receive_request()
+                .await
+                .expect("still connected")
+                .expect("request received");
+            fake_remote
+                .send_response(
+                    RegistrationResponse {
+                        session_id: "abcdef".to_string(),
+                        session: RegistrationSession::default(),
+                    }
+                    .into_websocket_response(request.id()),
+                )
+                .expect("still connected");
+            fake_remote
+        };
+        let client =
+            RegistrationConnection::connect_and_send(Box::new(connect_chat), SOME_REQUEST.clone());
+        let (fake_remote, result) = tokio::join!(server, client);
+        let (mut connection, _response) = result.expect("request succeeded");
+
+        connection.hint_next_request_imminent(false);
+
+        // The connection is closed rather than returned to the pool.
+        assert_matches!(
+            tokio::time::timeout(INACTIVITY_TIMEOUT, fake_remote.receive_request()).await,
+            Ok(Ok(None))
+        );
+        assert_eq!(pool.idle_count(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn services_share_pooled_connection() {
+        use crate::registration::{CreateSession, RegistrationService};
+
+        let (fake_chat_remote_tx, mut fake_chat_remote_rx) = mpsc::unbounded_channel();
+        let pool = ChatConnectionPool::new(&ObservableEvent::new());
+        let make_connect_chat = || {
+            Box::new(PooledConnectChat::new(
+                FakeChatConnect {
+                    remote: fake_chat_remote_tx.clone(),
+                },
+                pool.clone(),
+            ))
+        };
+        let create_session = |number: &str| CreateSession {
+            number: number.to_owned(),
+            ..Default::default()
+        };
+
+        // Answers every request that comes in on the first connection.
+        let server = async {
+            let fake_remote = fake_chat_remote_rx.recv().await.expect("connected");
+            for session_id in ["first", "second"] {
+                let request = fake_remote
+                    .receive_request()
+                    .await
+                    .expect("still connected")
+                    .expect("request received");
+                fake_remote
+                    .send_response(
+                        RegistrationResponse {
+                            session_id: session_id.to_owned(),
+                            session: RegistrationSession::default(),
+                        }
+                        .into_websocket_response(request.id()),
+                    )
+                    .expect("still connected");
+            }
+            fake_remote
+        };
+
+        let client = async {
+            let first = RegistrationService::create_session(
+                create_session("+18005550101"),
+                make_connect_chat(),
+            )
+            .await
+            .expect("can create first session");
+            assert_eq!(pool.idle_count(), 0);
+
+            // The first service's connection goes idle and is pooled.
+            tokio::time::sleep(INACTIVITY_TIMEOUT + Duration::from_secs(1)).await;
+            assert_eq!(pool.idle_count(), 1);
+
+            let second = RegistrationService::create_session(
+                create_session("+18005550102"),
+                make_connect_chat(),
+            )
+            .await
+            .expect("can create second session");
+            assert_eq!(pool.idle_count(), 0);
+            (first, second)
+        };
+
+        let (_fake_remote, (first, second)) = tokio::join!(server, client);
+        assert_eq!(**first.session_id(), "first");
+        assert_eq!(**second.session_id(), "second");
+        fake_chat_remote_rx
+            .try_recv()
+            .expect_err("only one connection was made");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn pooled_connections_are_dropped_on_network_change() {
+        let (fake_chat_remote_tx, mut fake_chat_remote_rx) = mpsc::unbounded_channel();
+        let network_change_event = ObservableEvent::new();
+        let pool = ChatConnectionPool::new(&network_change_event);
+        let connect_chat = PooledConnectChat::new(
+            FakeChatConnect {
+                remote: fake_chat_remote_tx,
+            },
+            pool.clone(),
+        );
+
+        let (_sender, join_handle) =
+            spawn_connected_chat(&connect_chat, None, default_inactivity_timeout())
+                .await
+                .expect("can connect");
+        let fake_remote = fake_chat_remote_rx.recv().await.expect("connected");
+        join_handle.await.expect("went idle");
+        assert_eq!(pool.idle_count(), 1);
+
+        network_change_event.fire();
+        assert_eq!(pool.idle_count(), 0);
+        assert_matches!(fake_remote.receive_request().await, Ok(None));
+
+        // The next connect has to make a new connection.
+        let (_sender, _join_handle) =
+            spawn_connected_chat(&connect_chat, None, default_inactivity_timeout())
+                .await
+                .expect("can connect");
+        let _new_remote = fake_chat_remote_rx.recv().await.expect("connected again");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn pooled_connections_expire_after_idle_timeout() {
+        let (fake_chat_remote_tx, mut fake_chat_remote_rx) = mpsc::unbounded_channel();
+        let pool = ChatConnectionPool::new(&ObservableEvent::new());
+        let connect_chat = PooledConnectChat::new(
+            FakeChatConnect {
+                remote: fake_chat_remote_tx,
+            },
+            pool.clone(),
+        );
+
+        let (_sender, join_handle) =
+            spawn_connected_chat(&connect_chat, None, default_inactivity_timeout())
+                .await
+                .expect("can connect");
+        let fake_remote = fake_chat_remote_rx.recv().await.expect("connected");
+        join_handle.await.expect("went idle");
+        assert_eq!(pool.idle_count(), 1);
+
+        // The expired connection is closed even if the pool isn't used again.
+        let start = Instant::now();
+        assert_matches!(fake_remote.receive_request().await, Ok(None));
+        assert_eq!(start.elapsed(), POOLED_CONNECTION_IDLE_TIMEOUT);
+        assert_eq!(pool.idle_count(), 0);
+
+        // A new one is made instead.
+        let (_sender, _join_handle) =
+            spawn_connected_chat(&connect_chat, None, default_inactivity_timeout())
+                .await
+                .expect("can connect");
+        let _new_remote = fake_chat_remote_rx.recv().await.expect("connected again");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn require_environment_skips_pooled_connections() {
+        let (fake_chat_remote_tx, mut fake_chat_remote_rx) = mpsc::unbounded_channel();
+        let pool = ChatConnectionPool::new(&ObservableEvent::new());
+        let make_connect_chat = || {
+            PooledConnectChat::new(
+                FakeChatConnect {
+                    remote: fake_chat_remote_tx.clone(),
+                },
+                pool.clone(),
+            )
+        };
+
+        // Pool a connection that was made without checking the environment.
+        let (_sender, join_handle) =
+            spawn_connected_chat(&make_connect_chat(), None, default_inactivity_timeout())
+                .await
+                .expect("can connect");
+        let _pooled_remote = fake_chat_remote_rx.recv().await.expect("connected");
+        join_handle.await.expect("went idle");
+        assert_eq!(pool.idle_count(), 1);
+
+        // The fake connection reports a route to a test host, so a new
+        // connection is made and refused instead of the pooled one being used.
+        let result = spawn_connected_chat(
+            &RequireEnvironment::new(make_connect_chat(), "chat.signal.org"),
+            None,
+            default_inactivity_timeout(),
+        )
+        .await;
+        assert_matches!(result, Err(_));
+        let _refused_remote = fake_chat_remote_rx.recv().await.expect("connected again");
+        assert_eq!(pool.idle_count(), 1);
+    }
+
     enum DisconnectTime {
         AfterConnectionSpawned,
         AfterRequestSent,
@@ -473,18 +1581,20 @@ mod test {
 
         let (to_send, receive_response) = {
             let (tx, rx) = oneshot::channel();
+            let (dispatch_tx, _dispatch_rx) = oneshot::channel();
             let request = ChatRequest {
                 method: http::Method::GET,
                 body: None,
                 headers: HeaderMap::new(),
                 path: PathAndQuery::from_static("/"),
             };
-            ((request, tx), rx)
+            ((request, dispatch_tx, tx), rx)
         };
 
-        let (sender, _join_handle) = spawn_connected_chat(&fake_connect)
-            .await
-            .expect("can connect");
+        let (sender, _join_handle) =
+            spawn_connected_chat(&fake_connect, None, default_inactivity_timeout())
+                .await
+                .expect("can connect");
         let fake_remote = fake_chat_remote_rx
             .recv()
             .await
@@ -527,7 +1637,25 @@ mod test {
             })
         });
 
-        let send_request = send_request::<RetryLater>(SOME_REQUEST.clone(), &connect_chat, None);
+        let retry_delays = Arc::new(Mutex::new(Vec::new()));
+        let inactivity_timeout = default_inactivity_timeout();
+        let mut reconnects = ReconnectBudget::default();
+        let send_request = ON_RETRY_DELAY.scope(
+            Box::new({
+                let retry_delays = retry_delays.clone();
+                move |failure_count, delay| {
+                    retry_delays.lock().unwrap().push((failure_count, delay))
+                }
+            }),
+            send_request::<RetryLater>(
+                SOME_REQUEST.clone(),
+                &connect_chat,
+                None,
+                Duration::ZERO,
+                &inactivity_timeout,
+                &mut reconnects,
+            ),
+        );
         let mut send_request = std::pin::pin!(send_request);
 
         // Get the remote end for the connected fake chat. We need to poll both
@@ -553,13 +1681,110 @@ mod test {
             .send_response(response)
             .expect("still connected");
 
-        let (_response, connected_sender) = send_request.await.expect("connects after retry");
+        let (_response, connected_sender, task) = send_request.await.expect("connects after retry");
+        assert!(task.is_some(), "new connection");
 
         assert!(!connected_sender.is_closed());
         assert_eq!(
             connect_count.load(std::sync::atomic::Ordering::SeqCst),
             RETRY_COUNT
         );
+
+        // The first failure has no previous one to cool down from; the second
+        // comes right after the first delay, since connecting takes no time.
+        let first_delay = CHAT_CONNECT_DELAY_PARAMS.compute_delay(Duration::MAX, 0);
+        assert_eq!(
+            *retry_delays.lock().unwrap(),
+            [
+                (0, first_delay),
+                (1, CHAT_CONNECT_DELAY_PARAMS.compute_delay(first_delay, 1)),
+            ]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn connect_retry_delays_increase() {
+        const FAILURE_COUNT: usize = 4;
+        let connect_count = AtomicUsize::new(0);
+        let (fake_chat_tx, mut fake_chat_rx) = mpsc::unbounded_channel();
+        let connect_chat = ConnectChatFn::new(|on_disconnect| {
+            let count = connect_count.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            std::future::ready(if count == FAILURE_COUNT {
+                let (fake_chat, fake_remote) = ChatConnection::new_fake(
+                    tokio::runtime::Handle::current(),
+                    DropOnDisconnect::new(on_disconnect).into_listener(),
+                    [],
+                );
+                fake_chat_tx.send(fake_remote).unwrap();
+                Ok(fake_chat)
+            } else {
+                Err(ChatConnectError::Timeout)
+            })
+        });
+
+        let retry_delays = Arc::new(Mutex::new(Vec::new()));
+        let start = Instant::now();
+        let (_sender, _join_handle) = ON_RETRY_DELAY
+            .scope(
+                Box::new({
+                    let retry_delays = retry_delays.clone();
+                    move |_failure_count, delay| retry_delays.lock().unwrap().push(delay)
+                }),
+                spawn_connected_chat(&connect_chat, None, default_inactivity_timeout()),
+            )
+            .await
+            .expect("connects after retries");
+        let _fake_remote = fake_chat_rx.recv().await.expect("connected");
+
+        let retry_delays = retry_delays.lock().unwrap();
+        assert_eq!(retry_delays.len(), FAILURE_COUNT);
+        assert!(
+            retry_delays.windows(2).all(|pair| pair[0] < pair[1]),
+            "{retry_delays:?}"
+        );
+        assert_eq!(start.elapsed(), retry_delays.iter().sum());
+    }
+
+    #[test]
+    fn connect_chat_with_state_uses_configured_jitter() {
+        use libsignal_net_infra::errors::TransportConnectError;
+        use libsignal_net_infra::route::testutils::ConnectFn;
+        use libsignal_net_infra::ws::WebSocketConnectError;
+
+        use crate::connect_state::{Config, SUGGESTED_CONNECT_PARAMS};
+
+        let connect_state = ConnectState::new_with_transport_connector(
+            Config::builder()
+                .connect_params(ConnectionOutcomeParams {
+                    jitter_fraction: 0.25,
+                    ..SUGGESTED_CONNECT_PARAMS
+                })
+                .build(),
+            ConnectFn(|_inner, _route, _log_tag| {
+                std::future::ready(Err::<tokio::net::TcpStream, _>(
+                    WebSocketConnectError::Transport(TransportConnectError::TcpConnectionFailed),
+                ))
+            }),
+        );
+        let connect_chat = ConnectChatWithState {
+            connect_state: &connect_state,
+            dns_resolver: &DnsResolver::new_from_static_map(Default::default()),
+            network_change_event: &ObservableEvent::new(),
+            confirmation_header_name: None,
+            route_provider: Vec::<UnresolvedHttpsServiceRoute>::new(),
+            user_agent: UserAgent::with_libsignal_version("test"),
+            ws_config: crate::chat::ws2::Config {
+                local_idle_timeout: Duration::from_secs(60),
+                remote_idle_timeout: Duration::from_secs(60),
+                initial_request_id: 0,
+                subprotocols: &[],
+            },
+        };
+
+        let params = connect_chat.retry_delay_params();
+        assert_eq!(params.jitter_fraction, 0.25);
+        assert_eq!(params.max_delay, CHAT_CONNECT_DELAY_PARAMS.max_delay);
+        assert_eq!(params.age_cutoff, CHAT_CONNECT_DELAY_PARAMS.age_cutoff);
     }
 
     #[tokio::test(start_paused = true)]
@@ -569,7 +1794,16 @@ mod test {
             remote: fake_chat_remote_tx,
         };
 
-        let send_request = send_request::<RetryLater>(SOME_REQUEST.clone(), &fake_connect, None);
+        let inactivity_timeout = default_inactivity_timeout();
+        let mut reconnects = ReconnectBudget::default();
+        let send_request = send_request::<RetryLater>(
+            SOME_REQUEST.clone(),
+            &fake_connect,
+            None,
+            Duration::ZERO,
+            &inactivity_timeout,
+            &mut reconnects,
+        );
         let mut send_request = std::pin::pin!(send_request);
 
         // Get the remote end for the connected fake chat. We need to poll both
@@ -592,6 +1826,248 @@ mod test {
         assert_matches!(result, Err(RequestError::Timeout));
     }
 
+    #[tokio::test(start_paused = true)]
+    async fn send_request_resends_if_dropped_before_send() {
+        let (fake_chat_remote_tx, mut fake_chat_remote_rx) = mpsc::unbounded_channel();
+        let fake_connect = FakeChatConnect {
+            remote: fake_chat_remote_tx,
+        };
+
+        // The task for the previous connection has already exited, so the
+        // request can't have been sent.
+        let (stale_sender, _) = mpsc::channel(MAX_PENDING_REQUESTS);
+
+        let inactivity_timeout = default_inactivity_timeout();
+        let mut reconnects = ReconnectBudget::default();
+        let send_request = send_request::<RetryLater>(
+            ChatRequest {
+                method: http::Method::POST,
+                ..SOME_REQUEST.clone()
+            },
+            &fake_connect,
+            Some(&stale_sender),
+            Duration::ZERO,
+            &inactivity_timeout,
+            &mut reconnects,
+        );
+
+        let answer_request = async {
+            let fake_remote = fake_chat_remote_rx.recv().await.expect("reconnected");
+            let request = fake_remote
+                .receive_request()
+                .await
+                .expect("still connected")
+                .expect("request received");
+            assert_eq!(request.verb.as_deref(), Some("POST"));
+            assert_eq!(request.path.as_deref(), Some("/"));
+
+            fake_remote
+                .send_response(
+                    RegistrationResponse::default().into_websocket_response(request.id.unwrap()),
+                )
+                .expect("still connected");
+            fake_remote
+        };
+
+        let (result, _fake_remote) = tokio::join!(send_request, answer_request);
+        assert_matches!(result, Ok(_));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn send_request_resends_idempotent_after_dropped_in_flight() {
+        let (fake_chat_remote_tx, mut fake_chat_remote_rx) = mpsc::unbounded_channel();
+        let fake_connect = FakeChatConnect {
+            remote: fake_chat_remote_tx,
+        };
+
+        let inactivity_timeout = default_inactivity_timeout();
+        let mut reconnects = ReconnectBudget::default();
+        let send_request = send_request::<RetryLater>(
+            ChatRequest {
+                method: http::Method::PUT,
+                ..SOME_REQUEST.clone()
+            },
+            &fake_connect,
+            None,
+            Duration::ZERO,
+            &inactivity_timeout,
+            &mut reconnects,
+        );
+
+        let answer_request = async {
+            // The server receives and processes the request, but the
+            // connection drops before the response makes it back.
+            let fake_remote = fake_chat_remote_rx.recv().await.expect("connected");
+            let request = fake_remote
+                .receive_request()
+                .await
+                .expect("still connected")
+                .expect("request received");
+            assert_eq!(request.verb.as_deref(), Some("PUT"));
+            fake_remote.send_close(None).expect("still connected");
+
+            let fake_remote = fake_chat_remote_rx.recv().await.expect("reconnected");
+            let request = fake_remote
+                .receive_request()
+                .await
+                .expect("still connected")
+                .expect("request received");
+            assert_eq!(request.verb.as_deref(), Some("PUT"));
+            assert_eq!(request.path.as_deref(), Some("/"));
+            fake_remote
+                .send_response(
+                    RegistrationResponse::default().into_websocket_response(request.id.unwrap()),
+                )
+                .expect("still connected");
+            fake_remote
+        };
+
+        let (result, fake_remote) = tokio::join!(send_request, answer_request);
+        assert_matches!(result, Ok(_));
+        assert_matches!(fake_remote.receive_request().now_or_never(), None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn send_request_non_idempotent_dropped_in_flight_has_unknown_outcome() {
+        let (fake_chat_remote_tx, mut fake_chat_remote_rx) = mpsc::unbounded_channel();
+        let fake_connect = FakeChatConnect {
+            remote: fake_chat_remote_tx,
+        };
+
+        let inactivity_timeout = default_inactivity_timeout();
+        let mut reconnects = ReconnectBudget::default();
+        let send_request = send_request::<RetryLater>(
+            ChatRequest {
+                method: http::Method::POST,
+                ..SOME_REQUEST.clone()
+            },
+            &fake_connect,
+            None,
+            Duration::ZERO,
+            &inactivity_timeout,
+            &mut reconnects,
+        );
+
+        let drop_request = async {
+            // The server receives the request, but the connection drops
+            // before the response makes it back.
+            let fake_remote = fake_chat_remote_rx.recv().await.expect("connected");
+            let request = fake_remote
+                .receive_request()
+                .await
+                .expect("still connected")
+                .expect("request received");
+            assert_eq!(request.verb.as_deref(), Some("POST"));
+            fake_remote.send_close(None).expect("still connected");
+        };
+
+        let (result, ()) = tokio::join!(send_request, drop_request);
+        assert_matches!(result, Err(RequestError::OutcomeUnknown));
+        // The request wasn't resent on a new connection.
+        assert!(fake_chat_remote_rx.try_recv().is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn send_request_gives_up_after_max_reconnects() {
+        const MAX_RECONNECTS: u32 = 2;
+
+        let (fake_chat_remote_tx, mut fake_chat_remote_rx) = mpsc::unbounded_channel();
+        let fake_connect = FakeChatConnect {
+            remote: fake_chat_remote_tx,
+        };
+
+        let inactivity_timeout = default_inactivity_timeout();
+        let mut reconnects = ReconnectBudget {
+            reconnects: 0,
+            max_reconnects: Some(MAX_RECONNECTS),
+        };
+        let send_request = send_request::<RetryLater>(
+            SOME_REQUEST.clone(),
+            &fake_connect,
+            None,
+            Duration::ZERO,
+            &inactivity_timeout,
+            &mut reconnects,
+        );
+
+        let drop_every_request = async {
+            // The first connection plus each allowed reconnect is dropped as
+            // soon as the request arrives.
+            let mut fake_remotes = vec![];
+            for _ in 0..=MAX_RECONNECTS {
+                let fake_remote = fake_chat_remote_rx.recv().await.expect("connected");
+                let _request = fake_remote
+                    .receive_request()
+                    .await
+                    .expect("still connected")
+                    .expect("request received");
+                fake_remote.send_close(None).expect("still connected");
+                fake_remotes.push(fake_remote);
+            }
+            fake_remotes
+        };
+
+        let (result, _fake_remotes) = tokio::join!(send_request, drop_every_request);
+        assert_matches!(result, Err(RequestError::Unknown(_)));
+        assert_eq!(reconnects.reconnects, MAX_RECONNECTS);
+
+        // No further connection was attempted after giving up.
+        assert!(fake_chat_remote_rx.try_recv().is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn send_request_reuses_connection_that_recovers_within_grace_period() {
+        const GRACE_PERIOD: Duration = Duration::from_secs(1);
+
+        let connect_chat = ConnectChatFn::new(
+            |_on_disconnect| -> std::future::Ready<Result<ChatConnection, ChatConnectError>> {
+                unreachable!("should reuse the existing connection")
+            },
+        );
+
+        // Stand in for the connection task so the test controls what happens
+        // to each request.
+        let (sender, mut task_requests) = mpsc::channel(MAX_PENDING_REQUESTS);
+
+        let inactivity_timeout = default_inactivity_timeout();
+        let mut reconnects = ReconnectBudget::default();
+        let send_request = send_request::<RetryLater>(
+            SOME_REQUEST.clone(),
+            &connect_chat,
+            Some(&sender),
+            GRACE_PERIOD,
+            &inactivity_timeout,
+            &mut reconnects,
+        );
+
+        let handle_requests = async {
+            // The first attempt is dropped before it's sent, as if the
+            // connection hiccuped...
+            let (_request, _on_dispatch, responder) =
+                task_requests.recv().await.expect("first attempt");
+            drop(responder);
+            let dropped_at = Instant::now();
+
+            // ...but the connection is still around for the retry.
+            let (_request, on_dispatch, responder) = task_requests.recv().await.expect("retried");
+            assert_eq!(dropped_at.elapsed(), GRACE_PERIOD);
+            let _ignore_closed = on_dispatch.send(());
+            let _ignore_closed = responder.send(Ok(ChatResponse {
+                status: http::StatusCode::OK,
+                message: None,
+                body: None,
+                headers: HeaderMap::new(),
+            }));
+            task_requests
+        };
+
+        let (result, _task_requests) = tokio::join!(send_request, handle_requests);
+        let (response, reused_sender, task) = result.expect("succeeded");
+        assert_eq!(response.status, http::StatusCode::OK);
+        assert!(reused_sender.same_channel(&sender));
+        assert!(task.is_none(), "no new connection");
+    }
+
     #[tokio::test(start_paused = true)]
     async fn request_sent_to_task_cancelled_before_send() {
         let (fake_chat_remote_tx, mut fake_chat_remote_rx) = mpsc::unbounded_channel();
@@ -599,9 +2075,10 @@ mod test {
             remote: fake_chat_remote_tx,
         };
 
-        let (request_sender, _join_handle) = spawn_connected_chat(&fake_connect)
-            .await
-            .expect("can connect");
+        let (request_sender, _join_handle) =
+            spawn_connected_chat(&fake_connect, None, default_inactivity_timeout())
+                .await
+                .expect("can connect");
         let fake_chat_remote = fake_chat_remote_rx.recv().await.unwrap();
 
         let mut first_send_fut = std::pin::pin!(send_request_to_connected_chat(
@@ -651,4 +2128,93 @@ mod test {
         // The task should reach its inactivity timeout and disconnect.
         assert_matches!(fake_chat_remote.receive_request().await, Ok(None));
     }
+
+    #[tokio::test(start_paused = true)]
+    async fn replaced_connection_is_drained_before_reconnecting() {
+        let (old_remote_tx, mut old_remote_rx) = mpsc::unbounded_channel();
+        let (new_remote_tx, mut new_remote_rx) = mpsc::unbounded_channel();
+
+        let (connected, old_remote) = tokio::join!(
+            RegistrationConnection::connect_and_send(
+                Box::new(FakeChatConnect {
+                    remote: old_remote_tx,
+                }),
+                SOME_REQUEST.clone(),
+            ),
+            async {
+                let fake_remote = old_remote_rx.recv().await.expect("connected");
+                let request = fake_remote
+                    .receive_request()
+                    .await
+                    .expect("still connected")
+                    .expect("request received");
+                fake_remote
+                    .send_response(
+                        RegistrationResponse::default()
+                            .into_websocket_response(request.id.unwrap()),
+                    )
+                    .expect("still connected");
+                fake_remote
+            }
+        );
+        let (mut connection, _response) = connected.expect("connected");
+
+        // Stop waiting for a request once the server has received it, leaving
+        // it in flight on the old connection.
+        let in_flight = {
+            let mut submit = std::pin::pin!(connection.submit_chat_request(ChatRequest {
+                method: http::Method::POST,
+                path: PathAndQuery::from_static("/1"),
+                ..SOME_REQUEST.clone()
+            }));
+            tokio::select! {
+                request = old_remote.receive_request() => request,
+                _ = submit.as_mut() => unreachable!("can't finish without response"),
+            }
+            .expect("still connected")
+            .expect("request received")
+        };
+
+        connection.set_connect_chat(Box::new(FakeChatConnect {
+            remote: new_remote_tx,
+        }));
+
+        let mut submit = std::pin::pin!(connection.submit_chat_request(ChatRequest {
+            path: PathAndQuery::from_static("/2"),
+            ..SOME_REQUEST.clone()
+        }));
+
+        // Nothing goes to a new connection while the old request is pending.
+        tokio::select! {
+            _ = submit.as_mut() => unreachable!("can't finish without response"),
+            () = tokio::time::sleep(REQUEST_TIMEOUT / 2) => {}
+        }
+        assert_matches!(
+            new_remote_rx.try_recv(),
+            Err(mpsc::error::TryRecvError::Empty)
+        );
+
+        old_remote
+            .send_response(
+                RegistrationResponse::default().into_websocket_response(in_flight.id.unwrap()),
+            )
+            .expect("still connected");
+
+        let (result, _new_remote) = tokio::join!(submit, async {
+            let fake_remote = new_remote_rx.recv().await.expect("reconnected");
+            let request = fake_remote
+                .receive_request()
+                .await
+                .expect("still connected")
+                .expect("request received");
+            assert_eq!(request.path.as_deref(), Some("/2"));
+            fake_remote
+                .send_response(
+                    RegistrationResponse::default().into_websocket_response(request.id.unwrap()),
+                )
+                .expect("still connected");
+            fake_remote
+        });
+        assert_matches!(result, Ok(_));
+    }
 }
@@ -5,20 +5,26 @@
 
 use std::marker::PhantomData;
 
+use attest::svr2::RaftConfig;
 use libsignal_net_infra::route::{RouteProvider, UnresolvedWebsocketServiceRoute};
-use libsignal_net_infra::ws2::attested::AttestedConnection;
+use libsignal_net_infra::ws::NextOrClose;
+use libsignal_net_infra::ws2::attested::{
+    AttestationCheck, AttestedConnection, AttestedProtocolError,
+};
 
 use crate::auth::Auth;
 use crate::connect_state::{ConnectionResources, RouteInfo, WebSocketTransportConnectorFactory};
 pub use crate::enclave::Error;
 use crate::enclave::{
-    ConnectionLabel, EnclaveKind, EndpointParams, IntoAttestedConnection, LabeledConnection,
-    NewHandshake,
+    AsRaftConfig as _, ConnectionLabel, EnclaveKind, EndpointParams, IntoAttestedConnection,
+    LabeledConnection, NewHandshake,
 };
 
 pub struct SvrConnection<Kind: EnclaveKind> {
     inner: AttestedConnection,
     remote_address: RouteInfo,
+    /// The config the enclave's attestation was checked against.
+    raft_config: Kind::RaftConfigType,
     witness: PhantomData<Kind>,
 }
 
@@ -30,6 +36,188 @@ impl<Kind: EnclaveKind> IntoAttestedConnection for SvrConnection<Kind> {
     }
 }
 
+/// Progress through a multi-step operation against an SVR enclave.
+///
+/// An operation is a fixed sequence of request messages, each of which is
+/// answered by exactly one response message. The checkpoint owns the requests
+/// and records the response to each step as it is acknowledged, so that if the
+/// connection drops partway through, the operation can be continued on a new
+/// [`SvrConnection`] with [`SvrConnection::continue_operation`].
+///
+/// Resuming relies on the following invariants:
+///
+/// - A step is acknowledged only once its response has been received and
+///   decrypted over an attested connection. Acknowledged steps are never sent
+///   again.
+/// - A step whose request was sent but not acknowledged may or may not have
+///   been processed by the enclave. It is only sent again if it was created
+///   with [`OperationStep::idempotent`]; otherwise the operation can't be
+///   resumed past it.
+/// - Only a lost connection makes an operation resumable. Errors from the
+///   enclave or its messages are not retried.
+/// - No session state is carried over between connections. Every connection
+///   performs its own attestation and Noise handshake, since the transport
+///   keys are bound to that handshake; reusing them on a new connection would
+///   let a different server continue the session unattested.
+/// - The requests are fixed when the checkpoint is created, so a resumed
+///   operation can't diverge from the one that was started.
+#[derive(Clone, Debug)]
+pub struct OperationCheckpoint {
+    steps: Vec<OperationStep>,
+    responses: Vec<Vec<u8>>,
+    /// Whether the request for the first unacknowledged step has been sent.
+    next_step_sent: bool,
+    resumable: bool,
+}
+
+/// A single request in an [`OperationCheckpoint`].
+#[derive(Clone, Debug)]
+pub struct OperationStep {
+    request: Vec<u8>,
+    idempotent: bool,
+}
+
+/// Failure of [`SvrConnection::continue_operation`].
+#[derive(Debug, thiserror::Error, displaydoc::Display)]
+pub enum OperationError {
+    /// {0}
+    Enclave(#[from] Error),
+    /// the operation was interrupted in a way that can't be resumed
+    NotResumable,
+}
+
+impl OperationStep {
+    /// A step that the enclave can safely process more than once.
+    pub fn idempotent(request: Vec<u8>) -> Self {
+        Self {
+            request,
+            idempotent: true,
+        }
+    }
+
+    /// A step that must not be sent again once it might have reached the
+    /// enclave.
+    pub fn not_idempotent(request: Vec<u8>) -> Self {
+        Self {
+            request,
+            idempotent: false,
+        }
+    }
+}
+
+impl OperationCheckpoint {
+    pub fn new(steps: Vec<OperationStep>) -> Self {
+        Self {
+            steps,
+            responses: Vec::new(),
+            next_step_sent: false,
+            resumable: true,
+        }
+    }
+
+    /// The number of steps whose responses have been received.
+    pub fn acknowledged_steps(&self) -> usize {
+        self.responses.len()
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.responses.len() == self.steps.len()
+    }
+
+    /// Whether the operation can be continued on a new connection.
+    ///
+    /// This becomes false once [`SvrConnection::continue_operation`] fails
+    /// with anything but a lost connection, or loses the connection after
+    /// sending a step that isn't idempotent.
+    pub fn is_resumable(&self) -> bool {
+        self.resumable
+    }
+
+    /// The responses received so far, in step order.
+    pub fn responses(&self) -> &[Vec<u8>] {
+        &self.responses
+    }
+
+    pub fn into_responses(self) -> Vec<Vec<u8>> {
+        self.responses
+    }
+
+    fn record_failure(&mut self, error: &Error) {
+        let next_step_repeatable = !self.next_step_sent
+            || self
+                .steps
+                .get(self.responses.len())
+                .is_some_and(|step| step.idempotent);
+        self.resumable = error.is_resumable() && next_step_repeatable;
+    }
+}
+
+impl<Kind: EnclaveKind> SvrConnection<Kind> {
+    /// The Raft group configuration the enclave was required to match when
+    /// this connection was attested, if the enclave kind has one.
+    ///
+    /// This describes the expected size of the group behind the enclave
+    /// (how many replicas vote, and how many must agree), which is useful
+    /// when diagnosing availability problems.
+    pub fn raft_config(&self) -> Option<&'static RaftConfig> {
+        self.raft_config.as_raft_config()
+    }
+
+    /// Runs the unacknowledged steps of an operation over this connection.
+    ///
+    /// Starts from the first step without a recorded response and records each
+    /// response in `checkpoint` as it arrives. If this fails and
+    /// [`OperationCheckpoint::is_resumable`] is still true, the same checkpoint
+    /// can be passed to a newly established connection to continue the
+    /// operation; see [`OperationCheckpoint`] for the invariants this relies
+    /// on. Otherwise, this returns [`OperationError::NotResumable`] without
+    /// sending anything.
+    pub async fn continue_operation(
+        &mut self,
+        checkpoint: &mut OperationCheckpoint,
+    ) -> Result<(), OperationError> {
+        if !checkpoint.is_resumable() {
+            return Err(OperationError::NotResumable);
+        }
+        let result = self.run_unacknowledged_steps(checkpoint).await;
+        if let Err(error) = &result {
+            checkpoint.record_failure(error);
+        }
+        Ok(result?)
+    }
+
+    async fn run_unacknowledged_steps(
+        &mut self,
+        checkpoint: &mut OperationCheckpoint,
+    ) -> Result<(), Error> {
+        let OperationCheckpoint {
+            steps,
+            responses,
+            next_step_sent,
+            resumable: _,
+        } = checkpoint;
+
+        for step in &steps[responses.len()..] {
+            // Once the request starts going out, the enclave may act on it
+            // even if the connection is lost before the response arrives.
+            *next_step_sent = true;
+            self.inner.send_bytes(&step.request).await?;
+            match self.inner.receive_bytes().await? {
+                NextOrClose::Next(response) => {
+                    responses.push(response);
+                    *next_step_sent = false;
+                }
+                NextOrClose::Close(frame) => {
+                    return Err(Error::Protocol(AttestedProtocolError::UnexpectedClose(
+                        frame.into(),
+                    )))
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
 impl<E> SvrConnection<E>
 where
     E: EnclaveKind + NewHandshake + Sized,
@@ -48,12 +236,194 @@ where
                 (ws_config, crate::infra::ws::WithoutResponseHeaders::new()),
                 format!("svr3:{}", std::any::type_name::<E>()).into(),
                 params,
+                None,
             )
             .await
             .map(|(connection, info)| Self {
                 inner: connection,
                 remote_address: info,
+                raft_config: params.raft_config.clone(),
                 witness: PhantomData,
             })
     }
+
+    /// Fetches the enclave's attestation and checks it against `params`
+    /// without completing a connection.
+    ///
+    /// Connection failures are returned as errors, but a failed attestation
+    /// is reported in the returned [`AttestationCheck`] along with the
+    /// evidence the enclave sent. The connection is closed either way.
+    pub async fn check_attestation(
+        connection_resources: ConnectionResources<'_, impl WebSocketTransportConnectorFactory>,
+        route_provider: impl RouteProvider<Route = UnresolvedWebsocketServiceRoute>,
+        ws_config: crate::infra::ws2::Config,
+        params: &EndpointParams<'_, E>,
+        auth: Auth,
+    ) -> Result<AttestationCheck, Error> {
+        connection_resources
+            .check_attested_ws(
+                route_provider,
+                auth,
+                (ws_config, crate::infra::ws::WithoutResponseHeaders::new()),
+                format!("svr3:{}:check", std::any::type_name::<E>()).into(),
+                params,
+            )
+            .await
+            .map(|(check, _route_info)| check)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use assert_matches::assert_matches;
+    use libsignal_net_infra::ws::testutil::fake_websocket;
+    use libsignal_net_infra::ws2::attested::testutil::{run_attested_server, AttestedServerOutput};
+
+    use super::*;
+    use crate::enclave::SgxPreQuantum;
+
+    const FAKE_WS_CONFIG: libsignal_net_infra::ws2::Config = libsignal_net_infra::ws2::Config {
+        local_idle_timeout: Duration::from_secs(5),
+        remote_idle_ping_timeout: Duration::from_secs(100),
+        remote_idle_disconnect_timeout: Duration::from_secs(100),
+    };
+
+    /// Starts a fake enclave that answers each request by echoing it back with
+    /// a prefix, closing the connection instead of answering request number
+    /// `close_on`.
+    async fn fake_svr_connection(
+        received: Arc<Mutex<Vec<Vec<u8>>>>,
+        close_on: Option<usize>,
+    ) -> SvrConnection<SgxPreQuantum> {
+        let (server, client) = fake_websocket().await;
+
+        let mut count = 0;
+        tokio::spawn(run_attested_server(
+            server,
+            attest::sgx_session::testutil::private_key(),
+            move |frame| {
+                let frame = frame.next_or(()).expect("no client close");
+                received.lock().expect("not poisoned").push(frame.clone());
+                count += 1;
+                if close_on == Some(count) {
+                    return AttestedServerOutput::close(None);
+                }
+                AttestedServerOutput::message([b"response to ".as_slice(), &frame].concat())
+            },
+        ));
+
+        let inner = AttestedConnection::connect(client, FAKE_WS_CONFIG, "test".into(), |_| {
+            attest::sgx_session::testutil::handshake_from_tests_data()
+        })
+        .await
+        .expect("handshake failed");
+
+        SvrConnection {
+            inner,
+            remote_address: RouteInfo::fake(),
+            raft_config: attest::constants::RAFT_CONFIG_SVR2_STAGING,
+            witness: PhantomData,
+        }
+    }
+
+    #[tokio::test]
+    async fn raft_config_is_readable() {
+        let connection = fake_svr_connection(Arc::default(), None).await;
+
+        let RaftConfig {
+            min_voting_replicas,
+            max_voting_replicas,
+            super_majority,
+            group_id,
+        } = connection.raft_config().expect("SVR2 has a raft config");
+        let expected = attest::constants::RAFT_CONFIG_SVR2_STAGING;
+        assert_eq!(*min_voting_replicas, expected.min_voting_replicas);
+        assert_eq!(*max_voting_replicas, expected.max_voting_replicas);
+        assert_eq!(*super_majority, expected.super_majority);
+        assert_eq!(*group_id, expected.group_id);
+    }
+
+    #[tokio::test]
+    async fn continue_operation_after_dropped_connection() {
+        let mut checkpoint = OperationCheckpoint::new(vec![
+            OperationStep::idempotent(b"step 1".to_vec()),
+            OperationStep::idempotent(b"step 2".to_vec()),
+            OperationStep::idempotent(b"step 3".to_vec()),
+        ]);
+
+        // The first server answers the first step and then drops the
+        // connection while the second is in flight.
+        let first_received = Arc::default();
+        let mut connection = fake_svr_connection(Arc::clone(&first_received), Some(2)).await;
+        let err = connection
+            .continue_operation(&mut checkpoint)
+            .await
+            .expect_err("connection dropped");
+        assert_matches!(
+            err,
+            OperationError::Enclave(Error::Protocol(AttestedProtocolError::UnexpectedClose(_)))
+        );
+        assert!(checkpoint.is_resumable());
+        assert_eq!(checkpoint.acknowledged_steps(), 1);
+        assert!(!checkpoint.is_complete());
+        assert_eq!(
+            *first_received.lock().unwrap(),
+            [b"step 1".to_vec(), b"step 2".to_vec()]
+        );
+
+        // The second server should see only the unacknowledged steps.
+        let second_received = Arc::default();
+        let mut connection = fake_svr_connection(Arc::clone(&second_received), None).await;
+        connection
+            .continue_operation(&mut checkpoint)
+            .await
+            .expect("operation completes");
+        assert!(checkpoint.is_complete());
+        assert_eq!(
+            *second_received.lock().unwrap(),
+            [b"step 2".to_vec(), b"step 3".to_vec()]
+        );
+
+        assert_eq!(
+            checkpoint.into_responses(),
+            [
+                b"response to step 1".to_vec(),
+                b"response to step 2".to_vec(),
+                b"response to step 3".to_vec(),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn non_idempotent_step_is_not_resent_after_dropped_connection() {
+        let mut checkpoint = OperationCheckpoint::new(vec![
+            OperationStep::idempotent(b"step 1".to_vec()),
+            OperationStep::not_idempotent(b"step 2".to_vec()),
+        ]);
+
+        // The connection drops while the non-idempotent step is in flight, so
+        // the enclave might have processed it.
+        let mut connection = fake_svr_connection(Arc::default(), Some(2)).await;
+        let err = connection
+            .continue_operation(&mut checkpoint)
+            .await
+            .expect_err("connection dropped");
+        assert_matches!(
+            err,
+            OperationError::Enclave(Error::Protocol(AttestedProtocolError::UnexpectedClose(_)))
+        );
+        assert!(!checkpoint.is_resumable());
+        assert_eq!(checkpoint.acknowledged_steps(), 1);
+
+        let second_received = Arc::default();
+        let mut connection = fake_svr_connection(Arc::clone(&second_received), None).await;
+        assert_matches!(
+            connection.continue_operation(&mut checkpoint).await,
+            Err(OperationError::NotResumable)
+        );
+        assert_eq!(*second_received.lock().unwrap(), Vec::<Vec<u8>>::new());
+    }
 }
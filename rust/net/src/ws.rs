@@ -13,6 +13,7 @@ use libsignal_net_infra::service::{CancellationToken, ServiceConnector};
 use libsignal_net_infra::ws::WebSocketConnectError;
 use libsignal_net_infra::{extract_retry_later, ConnectionParams};
 use tokio::time::Instant;
+use tungstenite::error::ProtocolError;
 
 #[derive(Debug, thiserror::Error)]
 pub enum WebSocketServiceConnectError {
@@ -30,6 +31,73 @@ pub enum WebSocketServiceConnectError {
     /// [`WebSocketServiceConnectError::from_websocket_error`] to process a
     /// [`WebSocketConnectError`] and check for server-originating rejection.
     Connect(WebSocketConnectError, NotRejectedByServer),
+    /// An HTTP error response that had the confirmation header, but with a
+    /// value the Signal servers would never send.
+    ///
+    /// This suggests something between the client and the server, like a
+    /// captive portal, is imitating the server's responses.
+    InvalidConfirmationHeader {
+        response: http::Response<Option<Vec<u8>>>,
+    },
+}
+
+/// Whether a response with `status` and `headers` reports that the service is
+/// down for maintenance, as opposed to an overloaded or failing server.
+///
+/// Per [RFC 9110 §15.6.4], a 503 Service Unavailable response may carry a
+/// Retry-After header ([§10.2.3]) when the server knows how long it will be
+/// unavailable; that's what a planned outage looks like. A 503 without one is
+/// treated as an ordinary (retryable) server error. The Retry-After value
+/// isn't required to parse, since it may be an HTTP-date rather than a number
+/// of seconds.
+///
+/// [RFC 9110 §15.6.4]: https://www.rfc-editor.org/rfc/rfc9110#section-15.6.4
+/// [§10.2.3]: https://www.rfc-editor.org/rfc/rfc9110#section-10.2.3
+pub(crate) fn is_server_maintenance(status: http::StatusCode, headers: &http::HeaderMap) -> bool {
+    status == http::StatusCode::SERVICE_UNAVAILABLE
+        && headers.contains_key(http::header::RETRY_AFTER)
+}
+
+/// What an HTTP response says about having come from a Signal server.
+enum ConfirmationHeader {
+    Valid,
+    Missing,
+    Invalid,
+}
+
+impl ConfirmationHeader {
+    /// Checks the confirmation header in `headers`.
+    ///
+    /// Signal servers send the header exactly once, with the server's current
+    /// time in milliseconds as its value. Anything else is treated as an
+    /// imitation.
+    fn check(headers: &http::HeaderMap, name: &HeaderName) -> Self {
+        if valid_confirmation_header(headers, name).is_some() {
+            Self::Valid
+        } else if headers.contains_key(name) {
+            Self::Invalid
+        } else {
+            Self::Missing
+        }
+    }
+}
+
+/// Returns the value of the confirmation header in `headers`, if it's one a
+/// Signal server would send.
+///
+/// See [`ConfirmationHeader::check`].
+pub(crate) fn valid_confirmation_header<'h>(
+    headers: &'h http::HeaderMap,
+    name: &HeaderName,
+) -> Option<&'h str> {
+    let mut values = headers.get_all(name).into_iter();
+    let (Some(value), None) = (values.next(), values.next()) else {
+        return None;
+    };
+    value
+        .to_str()
+        .ok()
+        .filter(|value| value.bytes().all(|b| b.is_ascii_digit()) && value.parse::<u64>().is_ok())
 }
 
 impl WebSocketServiceConnectError {
@@ -39,17 +107,26 @@ impl WebSocketServiceConnectError {
         received_at: Instant,
     ) -> Self {
         match error {
-            WebSocketConnectError::WebSocketError(tungstenite::Error::Http(response))
-                if confirmation_header
-                    .map(|header| response.headers().contains_key(header))
-                    .unwrap_or(true) =>
-            {
-                // Promote any HTTP error to an explicit rejection if
-                // - the confirmation header is present in the response, or
-                // - there's no header to check
-                Self::RejectedByServer {
-                    response,
-                    received_at,
+            WebSocketConnectError::WebSocketError(tungstenite::Error::Http(response)) => {
+                match confirmation_header
+                    .map(|header| ConfirmationHeader::check(response.headers(), header))
+                {
+                    // Promote any HTTP error to an explicit rejection if
+                    // - the confirmation header is present and valid, or
+                    // - there's no header to check
+                    None | Some(ConfirmationHeader::Valid) => Self::RejectedByServer {
+                        response,
+                        received_at,
+                    },
+                    Some(ConfirmationHeader::Invalid) => {
+                        Self::InvalidConfirmationHeader { response }
+                    }
+                    Some(ConfirmationHeader::Missing) => Self::Connect(
+                        WebSocketConnectError::WebSocketError(tungstenite::Error::Http(response)),
+                        NotRejectedByServer {
+                            _limit_construction: (),
+                        },
+                    ),
                 }
             }
             e => Self::Connect(
@@ -78,6 +155,69 @@ impl WebSocketServiceConnectError {
             },
         )
     }
+
+    /// Sorts the error into a coarse category suitable for presenting to users.
+    ///
+    /// This is independent of the retry decision made by
+    /// [`ErrorClassifier::classify`], which is also available to callers.
+    pub fn category(&self) -> ConnectErrorCategory {
+        match self {
+            Self::RejectedByServer {
+                response: _,
+                received_at: _,
+            } => ConnectErrorCategory::ServerRejected,
+            // Whatever produced the response, it wasn't the server.
+            Self::InvalidConfirmationHeader { response: _ } => {
+                ConnectErrorCategory::TransientNetwork
+            }
+            Self::Connect(error, NotRejectedByServer { .. }) => match error {
+                // The server picked a protocol this client can't speak, which
+                // is as much a refusal as an error status.
+                WebSocketConnectError::WebSocketError(tungstenite::Error::Protocol(
+                    ProtocolError::SecWebSocketSubProtocolError(_),
+                )) => ConnectErrorCategory::ServerRejected,
+                WebSocketConnectError::Transport(
+                    TransportConnectError::ClientAbort
+                    | TransportConnectError::InvalidConfiguration
+                    | TransportConnectError::CertError
+                    // These are most often caused by a wrong local clock.
+                    | TransportConnectError::CertificateExpired(_)
+                    | TransportConnectError::CertificateNotYetValid(_),
+                ) => ConnectErrorCategory::AppOrDevice,
+                WebSocketConnectError::Transport(
+                    TransportConnectError::TcpConnectionFailed
+                    | TransportConnectError::DnsError
+                    | TransportConnectError::SslError(_)
+                    | TransportConnectError::SslFailedHandshake(_)
+                    | TransportConnectError::CertificateUntrusted
+                    | TransportConnectError::EchRejected { .. }
+                    | TransportConnectError::ProxyProtocol,
+                )
+                | WebSocketConnectError::Timeout
+                | WebSocketConnectError::WebSocketError(_) => {
+                    ConnectErrorCategory::TransientNetwork
+                }
+            },
+        }
+    }
+}
+
+/// User-facing category for a [`WebSocketServiceConnectError`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ConnectErrorCategory {
+    /// The server couldn't be reached.
+    ///
+    /// Trying again later, or on a different network, might help.
+    TransientNetwork,
+    /// A Signal server responded and refused the connection.
+    ///
+    /// This includes rate limiting as well as rejections of the request itself.
+    ServerRejected,
+    /// Something on this device prevented the connection.
+    ///
+    /// This covers invalid configuration (like a bad proxy) and attempts that
+    /// were aborted locally.
+    AppOrDevice,
 }
 
 impl Display for WebSocketServiceConnectError {
@@ -97,6 +237,13 @@ impl Display for WebSocketServiceConnectError {
                 web_socket_connect_error,
                 _not_rejected_by_server,
             ) => web_socket_connect_error.fmt(f),
+            WebSocketServiceConnectError::InvalidConfirmationHeader { response } => {
+                write!(
+                    f,
+                    "response with error code {} had an invalid confirmation header",
+                    response.status()
+                )
+            }
         }
     }
 }
@@ -173,13 +320,22 @@ impl ErrorClassifier for WebSocketServiceConnectError {
                 }
 
                 // If we're rejected based on the request (4xx), there's no point in retrying.
-                if response.status().is_client_error() {
+                // The same goes for a maintenance window, which applies to the
+                // whole service rather than to a particular route.
+                if response.status().is_client_error()
+                    || is_server_maintenance(response.status(), response.headers())
+                {
                     return ErrorClass::Fatal;
                 }
 
                 // Otherwise, assume we have a server problem (5xx), and retry.
                 ErrorClass::Intermittent
             }
+            WebSocketServiceConnectError::InvalidConfirmationHeader { response: _ } => {
+                // Someone is impersonating the server. Don't keep feeding
+                // them requests; the network needs to change first.
+                ErrorClass::Fatal
+            }
             WebSocketServiceConnectError::Connect(
                 WebSocketConnectError::Transport(TransportConnectError::ClientAbort),
                 NotRejectedByServer { .. },
@@ -187,6 +343,29 @@ impl ErrorClassifier for WebSocketServiceConnectError {
                 // If we *locally* chose to abort, that isn't route-specific; treat it as fatal.
                 ErrorClass::Fatal
             }
+            WebSocketServiceConnectError::Connect(
+                WebSocketConnectError::Transport(
+                    TransportConnectError::CertificateExpired(_)
+                    | TransportConnectError::CertificateNotYetValid(_),
+                ),
+                NotRejectedByServer { .. },
+            ) => {
+                // If the local clock is wrong, every route will fail the same
+                // way. An untrusted certificate, on the other hand, is
+                // usually something on the path to one route (like a proxy or
+                // an intercepting middlebox), so it falls through below.
+                ErrorClass::Fatal
+            }
+            WebSocketServiceConnectError::Connect(
+                WebSocketConnectError::WebSocketError(tungstenite::Error::Protocol(
+                    ProtocolError::SecWebSocketSubProtocolError(_),
+                )),
+                NotRejectedByServer { .. },
+            ) => {
+                // Every route leads to the same server, which will make the
+                // same subprotocol choice.
+                ErrorClass::Fatal
+            }
             WebSocketServiceConnectError::Connect(_, NotRejectedByServer { .. }) => {
                 // In any other case, if we didn't make it to the server, we should retry.
                 ErrorClass::Intermittent
@@ -199,11 +378,136 @@ impl ErrorClassifier for WebSocketServiceConnectError {
 mod test {
     use assert_matches::assert_matches;
     use http::HeaderName;
-    use test_case::test_matrix;
+    use itertools::Itertools as _;
+    use test_case::{test_case, test_matrix};
     use tokio::time::Instant;
 
     use super::*;
 
+    fn rejected_with(
+        status: u16,
+        headers: &[(&'static str, &'static str)],
+    ) -> http::Response<Option<Vec<u8>>> {
+        let mut response = http::Response::new(None);
+        *response.status_mut() = http::StatusCode::from_u16(status).expect("valid");
+        for &(name, value) in headers {
+            response
+                .headers_mut()
+                .append(name, http::HeaderValue::from_static(value));
+        }
+        response
+    }
+
+    #[test_case(
+        WebSocketConnectError::Transport(TransportConnectError::ClientAbort)
+        => matches (ConnectErrorCategory::AppOrDevice, ErrorClass::Fatal);
+        "client abort"
+    )]
+    #[test_case(
+        WebSocketConnectError::Transport(TransportConnectError::InvalidConfiguration)
+        => matches (ConnectErrorCategory::AppOrDevice, ErrorClass::Intermittent);
+        "invalid configuration"
+    )]
+    #[test_case(
+        WebSocketConnectError::Transport(TransportConnectError::CertificateUntrusted)
+        => matches (ConnectErrorCategory::TransientNetwork, ErrorClass::Intermittent);
+        "untrusted certificate"
+    )]
+    #[test_case(
+        WebSocketConnectError::Transport(TransportConnectError::CertificateExpired(
+            libsignal_net_infra::errors::CertificateValidity {
+                not_before: std::time::UNIX_EPOCH,
+                not_after: std::time::UNIX_EPOCH,
+            }
+        ))
+        => matches (ConnectErrorCategory::AppOrDevice, ErrorClass::Fatal);
+        "expired certificate"
+    )]
+    #[test_case(
+        WebSocketConnectError::Transport(TransportConnectError::TcpConnectionFailed)
+        => matches (ConnectErrorCategory::TransientNetwork, ErrorClass::Intermittent);
+        "transport failure"
+    )]
+    #[test_case(
+        WebSocketConnectError::Timeout
+        => matches (ConnectErrorCategory::TransientNetwork, ErrorClass::Intermittent);
+        "timeout"
+    )]
+    #[test_case(
+        tungstenite::Error::Http(rejected_with(429, &[("retry-after", "20")])).into()
+        => matches (ConnectErrorCategory::ServerRejected, ErrorClass::RetryAt(_));
+        "retry later"
+    )]
+    #[test_case(
+        tungstenite::Error::Http(rejected_with(403, &[])).into()
+        => matches (ConnectErrorCategory::ServerRejected, ErrorClass::Fatal);
+        "client error"
+    )]
+    #[test_case(
+        tungstenite::Error::Http(rejected_with(500, &[])).into()
+        => matches (ConnectErrorCategory::ServerRejected, ErrorClass::Intermittent);
+        "server error"
+    )]
+    #[test_case(
+        tungstenite::Error::Http(rejected_with(503, &[])).into()
+        => matches (ConnectErrorCategory::ServerRejected, ErrorClass::Intermittent);
+        "unavailable"
+    )]
+    #[test_case(
+        tungstenite::Error::Http(rejected_with(503, &[("retry-after", "Fri, 16 Oct 2026 18:00:00 GMT")])).into()
+        => matches (ConnectErrorCategory::ServerRejected, ErrorClass::Fatal);
+        "maintenance"
+    )]
+    #[test_case(
+        tungstenite::Error::Http(rejected_with(503, &[("retry-after", "600")])).into()
+        => matches (ConnectErrorCategory::ServerRejected, ErrorClass::RetryAt(_));
+        "maintenance with retry hint"
+    )]
+    fn category_and_classification(
+        error: WebSocketConnectError,
+    ) -> (ConnectErrorCategory, ErrorClass) {
+        let error = WebSocketServiceConnectError::from_websocket_error(error, None, Instant::now());
+        (error.category(), error.classify())
+    }
+
+    #[test_case(
+        &["1700000000000"] => matches WebSocketServiceConnectError::RejectedByServer { .. };
+        "valid"
+    )]
+    #[test_case(
+        &["<html>"] => matches WebSocketServiceConnectError::InvalidConfirmationHeader { .. };
+        "not a timestamp"
+    )]
+    #[test_case(
+        &[""] => matches WebSocketServiceConnectError::InvalidConfirmationHeader { .. };
+        "empty"
+    )]
+    #[test_case(
+        &["+1700000000000"] => matches WebSocketServiceConnectError::InvalidConfirmationHeader { .. };
+        "signed"
+    )]
+    #[test_case(
+        &["1700000000000", "1700000000000"]
+            => matches WebSocketServiceConnectError::InvalidConfirmationHeader { .. };
+        "repeated"
+    )]
+    fn confirmation_header_value_is_checked(
+        values: &[&'static str],
+    ) -> WebSocketServiceConnectError {
+        const HEADER: &str = "x-signal-timestamp";
+        let headers = values.iter().map(|value| (HEADER, *value)).collect_vec();
+        let error = WebSocketServiceConnectError::from_websocket_error(
+            tungstenite::Error::Http(rejected_with(403, &headers)).into(),
+            Some(&HeaderName::from_static(HEADER)),
+            Instant::now(),
+        );
+        if let WebSocketServiceConnectError::InvalidConfirmationHeader { .. } = &error {
+            assert_matches!(error.classify(), ErrorClass::Fatal);
+            assert_eq!(error.category(), ConnectErrorCategory::TransientNetwork);
+        }
+        error
+    }
+
     #[test_matrix([None, Some("x-pinky-promise")])]
     fn classify_errors(confirmation_header: Option<&'static str>) {
         let now = Instant::now();
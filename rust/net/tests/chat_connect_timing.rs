@@ -4,27 +4,32 @@
 //
 
 use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
 
 use assert_matches::assert_matches;
 use async_trait::async_trait;
 use futures_util::{StreamExt as _, TryFutureExt as _};
 use itertools::Itertools as _;
 use libsignal_net::chat;
+use libsignal_net::chat::ws2::ListenerEvent;
 use libsignal_net::env::{DomainConfig, STAGING};
-use libsignal_net::infra::errors::TransportConnectError;
+use libsignal_net::infra::errors::{RetryLater, TransportConnectError};
 use libsignal_net_infra::dns::dns_lookup::{DnsLookup, DnsLookupRequest};
 use libsignal_net_infra::dns::lookup_result::LookupResult;
 use libsignal_net_infra::dns::{self, DnsResolver};
 use libsignal_net_infra::host::Host;
-use libsignal_net_infra::timeouts::MIN_TLS_HANDSHAKE_TIMEOUT;
+use libsignal_net_infra::timeouts::{
+    MIN_TLS_HANDSHAKE_TIMEOUT, WS_KEEP_ALIVE_INTERVAL, WS_MAX_IDLE_INTERVAL,
+};
 use libsignal_net_infra::utils::timed;
 use test_case::test_case;
 use tokio::time::{Duration, Instant};
 
 mod fake_transport;
 use fake_transport::{
-    allow_domain_fronting, connect_websockets_on_incoming, error_all_hosts_after,
-    only_direct_routes, FakeDeps,
+    allow_domain_fronting, assert_events_match_recording, connect_websockets_on_incoming,
+    connect_websockets_with_subprotocol_on_incoming, error_all_hosts_after, only_direct_routes,
+    reject_websockets_on_incoming, serve_websockets_on_incoming, FakeDeps,
 };
 
 use crate::fake_transport::{
@@ -113,6 +118,83 @@ async fn transport_connects_but_websocket_never_responds(expected_duration: Dura
     );
 }
 
+#[test_case(true => matches Ok(()); "server responds")]
+#[test_case(false => matches Err(chat::ConnectError::NoServerResponse); "server never heard from")]
+#[test_log::test(tokio::test(start_paused = true))]
+async fn check_server_responds_after_websocket_upgrade(
+    respond: bool,
+) -> Result<(), chat::ConnectError> {
+    const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+    let chat_domain_config = STAGING.chat_domain_config;
+    let (deps, incoming_streams) = FakeDeps::new(&chat_domain_config);
+    deps.transport_connector
+        .set_behaviors(allow_all_routes(&chat_domain_config, deps.static_ip_map()));
+    tokio::spawn(serve_websockets_on_incoming(incoming_streams, respond));
+
+    // The websocket upgrade succeeds either way.
+    let mut pending = deps.connect_chat().await.expect("connected");
+    assert_eq!(deps.routes_with_recent_failures(), 0);
+
+    let (elapsed, outcome) = timed(deps.check_server_responds(&mut pending, CHECK_TIMEOUT)).await;
+    if outcome.is_err() {
+        assert_eq!(elapsed, CHECK_TIMEOUT);
+        // The route that connected counts as failed.
+        assert_eq!(deps.routes_with_recent_failures(), 1);
+    } else {
+        assert_eq!(deps.routes_with_recent_failures(), 0);
+    }
+    outcome
+}
+
+#[test_case(426, &[] => matches Err(chat::ConnectError::AppExpired); "upgrade required")]
+#[test_case(499, &[] => matches Err(chat::ConnectError::AppExpired); "signal-specific status")]
+#[test_case(
+    503, &[("retry-after", "600")]
+    => matches Err(chat::ConnectError::ServerMaintenance {
+        retry_after: Some(RetryLater { retry_after_seconds: 600 }),
+    });
+    "maintenance"
+)]
+#[test_log::test(tokio::test(start_paused = true))]
+async fn server_rejects_websocket_upgrade(
+    status: u16,
+    headers: &'static [(&'static str, &'static str)],
+) -> Result<(), chat::ConnectError> {
+    let chat_domain_config = STAGING.chat_domain_config;
+    let (deps, incoming_streams) = FakeDeps::new(&chat_domain_config);
+    deps.transport_connector
+        .set_behaviors(allow_all_routes(&chat_domain_config, deps.static_ip_map()));
+    tokio::spawn(reject_websockets_on_incoming(
+        incoming_streams,
+        status,
+        headers,
+    ));
+
+    deps.connect_chat().await.map(|_pending| ())
+}
+
+#[test_case("chat.v2" => matches Ok(Some(subprotocol)) if subprotocol == "chat.v2"; "offered")]
+#[test_case("chat.v3" => matches Err(chat::ConnectError::UnsupportedSubprotocol); "not offered")]
+#[test_log::test(tokio::test(start_paused = true))]
+async fn server_selects_subprotocol(
+    selected: &'static str,
+) -> Result<Option<String>, chat::ConnectError> {
+    let chat_domain_config = STAGING.chat_domain_config;
+    let (deps, incoming_streams) = FakeDeps::new(&chat_domain_config);
+    deps.transport_connector
+        .set_behaviors(allow_all_routes(&chat_domain_config, deps.static_ip_map()));
+    tokio::spawn(connect_websockets_with_subprotocol_on_incoming(
+        incoming_streams,
+        selected,
+    ));
+
+    let pending = deps
+        .connect_chat_with_subprotocols(&["chat.v2", "chat.v1"])
+        .await?;
+    Ok(pending.subprotocol().map(ToOwned::to_owned))
+}
+
 #[test_case(Duration::from_millis(500), Duration::from_millis(500))]
 #[test_log::test(tokio::test(start_paused = true))]
 async fn connect_again_skips_timed_out_routes(
@@ -207,6 +289,48 @@ async fn runs_one_tls_handshake_at_a_time() {
     assert_eq!(timing, TLS_HANDSHAKE_DELAY);
 }
 
+#[test_log::test(tokio::test(start_paused = true))]
+async fn staging_direct_connect_matches_recording() {
+    let domain_config = STAGING.chat_domain_config;
+    let (deps, incoming_streams) = FakeDeps::new(&domain_config);
+
+    tokio::spawn(connect_websockets_on_incoming(incoming_streams));
+    deps.transport_connector.set_behaviors(
+        only_direct_routes(&domain_config, deps.static_ip_map()).map(|(target, behavior)| {
+            let delay = match &target {
+                FakeTransportTarget::Tcp { .. } | FakeTransportTarget::TcpThroughProxy { .. } => {
+                    Duration::from_millis(100)
+                }
+                FakeTransportTarget::Tls { .. } => Duration::from_millis(200),
+            };
+            let new_behavior = Behavior::Delay {
+                delay,
+                then: behavior.into(),
+            };
+            (target, new_behavior)
+        }),
+    );
+
+    let start = Instant::now();
+    let outcome = deps.connect_chat().map_ok(|_| ()).await;
+    assert_matches!(outcome, Ok(_));
+
+    let events = deps
+        .transport_connector
+        .recorded_events
+        .lock()
+        .unwrap()
+        .drain(..)
+        .map(|(event, when)| (event, when.duration_since(start)))
+        .collect_vec();
+
+    assert_events_match_recording(
+        &events,
+        include_str!("data/staging_direct_connect_events.json"),
+        Duration::from_millis(10),
+    );
+}
+
 #[test_case(MIN_TLS_HANDSHAKE_TIMEOUT)]
 #[test_log::test(tokio::test(start_paused = true))]
 async fn first_tls_hangs_then_fallback_succeeds(expected_duration: Duration) {
@@ -276,6 +400,115 @@ async fn first_tls_hangs_then_fallback_succeeds(expected_duration: Duration) {
     );
 }
 
+#[test_case(0.1, true; "moderate loss")]
+#[test_case(0.99, false; "heavy loss")]
+#[test_log::test(tokio::test(start_paused = true))]
+async fn connect_over_lossy_transport(loss_rate: f64, should_connect: bool) {
+    const CHAT_DOMAIN_CONFIG: DomainConfig = STAGING.chat_domain_config;
+    let (deps, incoming_streams) = FakeDeps::new(&CHAT_DOMAIN_CONFIG);
+
+    deps.transport_connector.set_behaviors(
+        allow_all_routes(&CHAT_DOMAIN_CONFIG, deps.static_ip_map()).map(|(target, behavior)| {
+            let modified = match &target {
+                FakeTransportTarget::Tls { .. } => Behavior::Lossy {
+                    loss_rate,
+                    latency: Duration::from_millis(50),
+                    seed: 0,
+                    reliable_for: Duration::ZERO,
+                },
+                FakeTransportTarget::TcpThroughProxy { .. } | FakeTransportTarget::Tcp { .. } => {
+                    behavior
+                }
+            };
+            (target, modified)
+        }),
+    );
+
+    tokio::spawn(connect_websockets_on_incoming(incoming_streams));
+
+    let outcome = deps.connect_chat().map_ok(|_| ()).await;
+    assert_eq!(
+        outcome.is_ok(),
+        should_connect,
+        "unexpected outcome {outcome:?}"
+    );
+}
+
+#[test_case(0.1, true; "moderate loss stays connected")]
+#[test_case(0.99, false; "heavy loss disconnects")]
+#[test_log::test(tokio::test(start_paused = true))]
+async fn keepalive_over_lossy_transport(loss_rate: f64, should_stay_connected: bool) {
+    const CHAT_DOMAIN_CONFIG: DomainConfig = STAGING.chat_domain_config;
+    /// Long enough to connect before the link starts losing data.
+    const RELIABLE_FOR: Duration = Duration::from_secs(10);
+    /// Enough time for several rounds of keepalive pings.
+    const OBSERVE_FOR: Duration = Duration::from_secs(5 * 60);
+
+    let (deps, incoming_streams) = FakeDeps::new(&CHAT_DOMAIN_CONFIG);
+
+    deps.transport_connector.set_behaviors(
+        allow_all_routes(&CHAT_DOMAIN_CONFIG, deps.static_ip_map()).map(|(target, behavior)| {
+            let modified = match &target {
+                FakeTransportTarget::Tls { .. } => Behavior::Lossy {
+                    loss_rate,
+                    latency: Duration::from_millis(50),
+                    seed: 0,
+                    reliable_for: RELIABLE_FOR,
+                },
+                FakeTransportTarget::TcpThroughProxy { .. } | FakeTransportTarget::Tcp { .. } => {
+                    behavior
+                }
+            };
+            (target, modified)
+        }),
+    );
+
+    // The server answers the client's keepalive pings, as long as they (and
+    // the answers) make it through.
+    tokio::spawn(serve_websockets_on_incoming(incoming_streams, true));
+
+    // Give the server a chance to answer a ping before the connection is
+    // considered dead, like the app does.
+    let pending = deps
+        .connect_chat_with_config(chat::ws2::Config {
+            local_idle_timeout: WS_KEEP_ALIVE_INTERVAL,
+            remote_idle_timeout: WS_MAX_IDLE_INTERVAL,
+            initial_request_id: 0,
+            subprotocols: &[],
+        })
+        .await
+        .expect("can connect");
+    let (events_tx, mut events_rx) = tokio::sync::mpsc::unbounded_channel();
+    let _chat = chat::ChatConnection::finish_connect(
+        tokio::runtime::Handle::current(),
+        pending,
+        Box::new(move |event| {
+            let _ignore_if_closed = events_tx.send(event);
+        }),
+    );
+
+    let finished = tokio::time::timeout(OBSERVE_FOR, async {
+        loop {
+            if let ListenerEvent::Finished(finish) = events_rx.recv().await.expect("not dropped") {
+                break finish;
+            }
+        }
+    })
+    .await;
+
+    if should_stay_connected {
+        assert_matches!(finished, Err(_elapsed), "connection should stay up");
+        return;
+    }
+
+    let finish = finished.expect("connection should be lost");
+    assert_matches!(finish, Err(_));
+
+    // A fresh connection works again, at least until it also starts losing data.
+    let reconnect = deps.connect_chat().map_ok(|_| ()).await;
+    assert_matches!(reconnect, Ok(()));
+}
+
 #[derive(Debug)]
 struct DnsLookupThatNeverCompletes;
 #[async_trait]
@@ -369,3 +602,69 @@ async fn slow_dns(should_accept_connection: bool, expected_duration: Duration) {
         assert_matches!(outcome, Err(chat::ConnectError::Timeout));
     }
 }
+
+#[derive(Debug)]
+struct DnsLookupThatRecordsStarts {
+    started_at: Arc<Mutex<Vec<Instant>>>,
+    results: HashMap<&'static str, LookupResult>,
+}
+#[async_trait]
+impl DnsLookup for DnsLookupThatRecordsStarts {
+    async fn dns_lookup(
+        &self,
+        request: DnsLookupRequest,
+    ) -> dns::Result<dns::lookup_result::LookupResult> {
+        self.started_at
+            .lock()
+            .expect("not poisoned")
+            .push(Instant::now());
+        self.results
+            .get(&*request.hostname)
+            .cloned()
+            .ok_or(dns::DnsError::LookupFailed)
+    }
+}
+
+#[test_case(DnsLookupThatNeverCompletes, DNS_STRATEGY_TIMEOUT)]
+#[test_case(
+    DnsLookupThatFailsSlowly(Duration::from_secs(3)),
+    Duration::from_secs(3)
+)]
+#[test_log::test(tokio::test(start_paused = true))]
+async fn sequential_fallback_dns_waits_for_primary_failure(
+    primary: impl DnsLookup + 'static,
+    expected_duration: Duration,
+) {
+    let chat_domain_config = STAGING.chat_domain_config;
+    let (mut deps, incoming_streams) = FakeDeps::new(&chat_domain_config);
+    let backup_started_at = Arc::new(Mutex::new(Vec::new()));
+    deps.dns_resolver = DnsResolver::new_sequential_fallback(
+        (Box::new(primary), DNS_STRATEGY_TIMEOUT),
+        (
+            Box::new(DnsLookupThatRecordsStarts {
+                started_at: backup_started_at.clone(),
+                results: deps.static_ip_map().clone(),
+            }),
+            DNS_STRATEGY_TIMEOUT,
+        ),
+    );
+    deps.transport_connector
+        .set_behaviors(allow_all_routes(&chat_domain_config, deps.static_ip_map()));
+
+    tokio::spawn(connect_websockets_on_incoming(incoming_streams));
+    let start = Instant::now();
+    let (elapsed, outcome) = timed(deps.connect_chat().map_ok(|_| ())).await;
+
+    outcome.expect("accepted");
+    assert_eq!(elapsed, expected_duration);
+
+    // The backup shouldn't have been asked anything before the primary gave up.
+    let backup_started_at = backup_started_at.lock().expect("not poisoned");
+    assert!(!backup_started_at.is_empty());
+    assert!(
+        backup_started_at
+            .iter()
+            .all(|started_at| *started_at == start + expected_duration),
+        "{backup_started_at:?}"
+    );
+}
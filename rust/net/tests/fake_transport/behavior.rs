@@ -4,7 +4,10 @@
 //
 
 use libsignal_net::infra::errors::TransportConnectError;
-use tokio::time::Duration;
+use rand::{Rng as _, SeedableRng as _};
+use rand_chacha::ChaCha8Rng;
+use tokio::io::{AsyncRead, AsyncReadExt as _, AsyncWrite, AsyncWriteExt as _};
+use tokio::time::{Duration, Instant};
 
 use super::FakeStream;
 
@@ -24,12 +27,31 @@ pub enum Behavior {
     },
     /// Connect the transport, applying the given modifier to the returned stream.
     ReturnStream(Option<fn(FakeStream) -> FakeStream>),
+    /// Connect the transport, but degrade the established connection.
+    ///
+    /// Each chunk of data written in either direction is "lost" with
+    /// probability `loss_rate` (which must be in `[0, 1]`) and retransmitted
+    /// after a backoff, like TCP would. Delivered chunks are delayed by
+    /// `latency`. If a chunk is lost too many times in a row, the connection is
+    /// reset. Nothing is lost for the first `reliable_for` after the transport
+    /// connects, so a link can be made to degrade only once it's in use.
+    ///
+    /// The losses are decided by an RNG seeded with `seed`, so the same
+    /// sequence of reads and writes always sees the same losses.
+    Lossy {
+        loss_rate: f64,
+        latency: Duration,
+        seed: u64,
+        reliable_for: Duration,
+    },
     /// Panic if invoked.
     Unreachable,
 }
 
+pub(super) type StreamModifier = Box<dyn FnOnce(FakeStream) -> FakeStream + Send>;
+
 impl Behavior {
-    pub(super) async fn apply(self) -> Result<fn(FakeStream) -> FakeStream, TransportConnectError> {
+    pub(super) async fn apply(self) -> Result<StreamModifier, TransportConnectError> {
         let mut next = self;
 
         loop {
@@ -41,10 +63,117 @@ impl Behavior {
                 }
                 Behavior::Fail(make_error) => return Err(make_error()),
                 Behavior::ReturnStream(stream) => {
-                    return Ok(stream.unwrap_or(std::convert::identity))
+                    return Ok(Box::new(stream.unwrap_or(std::convert::identity)))
+                }
+                Behavior::Lossy {
+                    loss_rate,
+                    latency,
+                    seed,
+                    reliable_for,
+                } => {
+                    return Ok(Box::new(move |stream| {
+                        lossy_stream(stream, loss_rate, latency, seed, reliable_for)
+                    }))
                 }
                 Behavior::Unreachable => unreachable!("this test should not attempt to connect"),
             }
         }
     }
 }
+
+/// The wait before the first retransmission of a lost chunk; doubles after
+/// each subsequent loss.
+const INITIAL_RETRANSMIT_TIMEOUT: Duration = Duration::from_millis(200);
+/// How many times a chunk can be lost in a row before the connection is reset.
+const MAX_CONSECUTIVE_LOSSES: u32 = 5;
+const RELAY_BUF_SIZE: usize = 64 * 1024;
+
+/// Returns a stream that relays to and from `stream` over a simulated lossy
+/// link.
+///
+/// The relaying is done by a spawned task, so this must be called from within
+/// a tokio runtime.
+fn lossy_stream(
+    stream: FakeStream,
+    loss_rate: f64,
+    latency: Duration,
+    seed: u64,
+    reliable_for: Duration,
+) -> FakeStream {
+    let (local, relayed) = tokio::io::duplex(RELAY_BUF_SIZE);
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    let lossy_from = Instant::now() + reliable_for;
+    let outgoing = LossyLink {
+        loss_rate,
+        latency,
+        lossy_from,
+        rng: ChaCha8Rng::seed_from_u64(rng.gen()),
+    };
+    let incoming = LossyLink {
+        loss_rate,
+        latency,
+        lossy_from,
+        rng,
+    };
+
+    tokio::spawn(async move {
+        let (relayed_read, relayed_write) = tokio::io::split(relayed);
+        let (stream_read, stream_write) = tokio::io::split(stream);
+        // If either direction fails, both halves of both streams are dropped,
+        // which closes the connection from both sides.
+        let result = tokio::try_join!(
+            outgoing.relay(relayed_read, stream_write),
+            incoming.relay(stream_read, relayed_write),
+        );
+        if let Err(e) = result {
+            log::info!("lossy connection closed: {e}");
+        }
+    });
+
+    Box::new(local)
+}
+
+struct LossyLink {
+    loss_rate: f64,
+    latency: Duration,
+    /// When chunks start getting lost.
+    lossy_from: Instant,
+    rng: ChaCha8Rng,
+}
+
+impl LossyLink {
+    async fn relay(
+        mut self,
+        mut from: impl AsyncRead + Unpin,
+        mut to: impl AsyncWrite + Unpin,
+    ) -> std::io::Result<()> {
+        let mut buf = vec![0; RELAY_BUF_SIZE];
+        loop {
+            let read = from.read(&mut buf).await?;
+            if read == 0 {
+                return to.shutdown().await;
+            }
+            self.transmit().await?;
+            to.write_all(&buf[..read]).await?;
+        }
+    }
+
+    /// Waits until a chunk would have been delivered, or returns an error if
+    /// it never would be.
+    async fn transmit(&mut self) -> std::io::Result<()> {
+        if Instant::now() < self.lossy_from {
+            tokio::time::sleep(self.latency).await;
+            return Ok(());
+        }
+        let mut retransmit_timeout = INITIAL_RETRANSMIT_TIMEOUT;
+        for _ in 0..MAX_CONSECUTIVE_LOSSES {
+            if !self.rng.gen_bool(self.loss_rate) {
+                tokio::time::sleep(self.latency).await;
+                return Ok(());
+            }
+            tokio::time::sleep(retransmit_timeout).await;
+            retransmit_timeout *= 2;
+        }
+        Err(std::io::ErrorKind::ConnectionReset.into())
+    }
+}
@@ -7,16 +7,20 @@ use std::collections::HashMap;
 use std::fmt::Debug;
 use std::future::Future;
 use std::net::IpAddr;
+use std::pin::Pin;
 use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
 
 use futures_util::TryFutureExt as _;
 use libsignal_net::infra::errors::TransportConnectError;
 use libsignal_net_infra::host::Host;
 use libsignal_net_infra::route::{
-    ConnectionProxyRoute, Connector, TcpRoute, TlsRouteFragment, TransportRoute, UsePreconnect,
+    ConnectionProxyRoute, Connector, ResolvedRoute as _, TcpRoute, TlsRouteFragment,
+    TransportRoute, UsePreconnect,
 };
-use libsignal_net_infra::AsyncDuplexStream;
-use tokio::io::DuplexStream;
+use libsignal_net_infra::{AsyncDuplexStream, Connection, IpType, TransportInfo};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncWrite, DuplexStream, ReadBuf};
 use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
 use tokio::time::Instant;
 
@@ -38,13 +42,13 @@ pub struct FakeTransportConnector {
     connect_behavior: Arc<Mutex<HashMap<FakeTransportTarget, Behavior>>>,
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum TransportConnectEvent {
-    TcpConnect(Option<Host<Arc<str>>>),
-    TlsHandshake(Host<Arc<str>>),
+    TcpConnect(#[serde(with = "super::recording::serde_optional_host")] Option<Host<Arc<str>>>),
+    TlsHandshake(#[serde(with = "super::recording::serde_host")] Host<Arc<str>>),
 }
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum TransportConnectEventStage {
     Start,
     End,
@@ -128,11 +132,58 @@ impl FakeTransportConnector {
     }
 }
 
+/// The client end of a connection made by a [`FakeConnector`].
+///
+/// Unlike the bare stream, this can be used where an established
+/// [`Connection`] is expected, e.g. to finish connecting a chat.
+pub struct FakeConnection<S> {
+    stream: S,
+    ip_version: IpType,
+}
+
+impl<S> Connection for FakeConnection<S> {
+    fn transport_info(&self) -> TransportInfo {
+        TransportInfo {
+            ip_version: self.ip_version,
+            local_port: 0,
+            negotiated_alpn: None,
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for FakeConnection<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_read(cx, buf)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for FakeConnection<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.get_mut().stream).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_shutdown(cx)
+    }
+}
+
 impl<C> Connector<UsePreconnect<TransportRoute>, ()> for FakeConnector<C>
 where
     C: Connector<TransportRoute, FakeStream> + Send,
 {
-    type Connection = C::Connection;
+    type Connection = FakeConnection<C::Connection>;
 
     type Error = C::Error;
 
@@ -148,9 +199,11 @@ where
         } = self;
         let (local, remote) = tokio::io::duplex(MAX_BUF_SIZE);
         let sni = route.inner.fragment.sni.clone();
+        let ip_version = IpType::from(route.immediate_target());
 
         replaced
             .connect_over(Box::new(local), route.inner, log_tag)
+            .map_ok(move |stream| FakeConnection { stream, ip_version })
             .inspect_ok(|_| {
                 server_stream_sender.send((sni, remote)).unwrap();
             })
@@ -4,8 +4,8 @@
 //
 
 use libsignal_net_infra::route::{
-    ComposedConnector, DirectOrProxy, LoggingConnector, ThrottlingConnector,
-    VariableTlsTimeoutConnector,
+    ComposedConnector, DirectOrProxy, EchRetryConnector, LoggingConnector, PhaseTimingConnector,
+    ThrottlingConnector, TimeoutConnector, VariableTlsTimeoutConnector,
 };
 
 use super::FakeTransportConnector;
@@ -54,6 +54,16 @@ where
     }
 }
 
+impl<C: ReplaceStatelessConnectorsWithFake> ReplaceStatelessConnectorsWithFake
+    for EchRetryConnector<C>
+{
+    type Replacement = EchRetryConnector<C::Replacement>;
+
+    fn replace_with_fake(self, fake: FakeTransportConnector) -> Self::Replacement {
+        EchRetryConnector::new(self.into_inner().replace_with_fake(fake))
+    }
+}
+
 impl<D, P, E> ReplaceStatelessConnectorsWithFake for DirectOrProxy<D, P, E>
 where
     D: ReplaceStatelessConnectorsWithFake,
@@ -114,3 +124,24 @@ impl<C: ReplaceStatelessConnectorsWithFake> ReplaceStatelessConnectorsWithFake
         self.into_inner().replace_with_fake(fake)
     }
 }
+
+impl<C: ReplaceStatelessConnectorsWithFake, E> ReplaceStatelessConnectorsWithFake
+    for TimeoutConnector<C, E>
+{
+    type Replacement = C::Replacement;
+
+    fn replace_with_fake(self, fake: FakeTransportConnector) -> Self::Replacement {
+        // Fake connections have their own timing, so skip the phase timeouts.
+        self.into_inner().replace_with_fake(fake)
+    }
+}
+
+impl<C: ReplaceStatelessConnectorsWithFake> ReplaceStatelessConnectorsWithFake
+    for PhaseTimingConnector<C>
+{
+    type Replacement = C::Replacement;
+
+    fn replace_with_fake(self, fake: FakeTransportConnector) -> Self::Replacement {
+        self.into_inner().replace_with_fake(fake)
+    }
+}
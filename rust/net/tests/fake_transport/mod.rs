@@ -25,7 +25,7 @@ use libsignal_net::infra::host::Host;
 use libsignal_net::infra::route::{ConnectorFactory, DirectOrProxyProvider, DEFAULT_HTTPS_PORT};
 use libsignal_net::infra::utils::ObservableEvent;
 use libsignal_net::infra::{
-    AsyncDuplexStream, DnsSource, EnableDomainFronting, EndpointConnection,
+    AsyncDuplexStream, Connection, DnsSource, EnableDomainFronting, EndpointConnection,
 };
 use libsignal_net_infra::route::{Connector, TransportRoute, UsePreconnect};
 use tokio::time::Duration;
@@ -42,6 +42,9 @@ pub use behavior::Behavior;
 mod connector;
 pub use connector::{FakeTransportConnector, TransportConnectEvent, TransportConnectEventStage};
 
+mod recording;
+pub use recording::assert_events_match_recording;
+
 mod target;
 pub use target::FakeTransportTarget;
 
@@ -61,6 +64,7 @@ pub fn only_direct_routes(
                 port,
                 hostname,
                 cert: _,
+                ech_config_list: _,
                 confirmation_header_name: _,
                 proxy: _,
             },
@@ -105,6 +109,7 @@ pub fn allow_domain_fronting(
                 port: _,
                 hostname: _,
                 cert: _,
+                ech_config_list: _,
                 confirmation_header_name: _,
                 proxy,
             },
@@ -179,7 +184,7 @@ impl FakeDeps {
         );
 
         let connector_factory =
-            ReplacingConnectorFactory(transport_connector.clone(), DefaultConnectorFactory);
+            ReplacingConnectorFactory(transport_connector.clone(), Default::default());
         let connect_state =
             ConnectState::new_with_transport_connector(SUGGESTED_CONNECT_CONFIG, connector_factory);
         let resolved_names = fake_ips_for_names(chat_domain_config);
@@ -201,22 +206,83 @@ impl FakeDeps {
         &self.resolved_names
     }
 
+    /// Runs [`PendingChatConnection::check_server_responds`] against this
+    /// connect state.
+    pub async fn check_server_responds(
+        &self,
+        pending: &mut PendingChatConnection<impl AsyncDuplexStream>,
+        timeout: Duration,
+    ) -> Result<(), chat::ConnectError> {
+        pending
+            .check_server_responds(&self.connect_state, timeout)
+            .await
+    }
+
+    /// Counts the routes that have recent failures recorded.
+    pub fn routes_with_recent_failures(&self) -> usize {
+        // Round-trip through an export, since the outcomes aren't otherwise
+        // visible.
+        let now = tokio::time::Instant::now();
+        let exported = self
+            .connect_state
+            .lock()
+            .expect("not poisoned")
+            .export_outcomes(now);
+        ConnectState::new(SUGGESTED_CONNECT_CONFIG)
+            .lock()
+            .expect("not poisoned")
+            .import_outcomes(&exported, now)
+            .expect("valid export")
+    }
+
     pub async fn connect_chat(
         &self,
-    ) -> Result<PendingChatConnection<impl AsyncDuplexStream>, chat::ConnectError> {
+    ) -> Result<
+        PendingChatConnection<impl AsyncDuplexStream + Connection + 'static>,
+        chat::ConnectError,
+    > {
+        self.connect_chat_with_subprotocols(&[]).await
+    }
+
+    /// Like [`Self::connect_chat`], but offers `subprotocols` in the websocket
+    /// upgrade request.
+    pub async fn connect_chat_with_subprotocols(
+        &self,
+        subprotocols: &'static [&'static str],
+    ) -> Result<
+        PendingChatConnection<impl AsyncDuplexStream + Connection + 'static>,
+        chat::ConnectError,
+    > {
+        let libsignal_net::infra::ws2::Config {
+            local_idle_timeout,
+            remote_idle_ping_timeout,
+            remote_idle_disconnect_timeout: _,
+        } = self.endpoint_connection.config.ws2_config();
+        self.connect_chat_with_config(chat::ws2::Config {
+            local_idle_timeout,
+            remote_idle_timeout: remote_idle_ping_timeout,
+            initial_request_id: 0,
+            subprotocols,
+        })
+        .await
+    }
+
+    /// Like [`Self::connect_chat`], but with the given websocket configuration.
+    pub async fn connect_chat_with_config(
+        &self,
+        ws_config: chat::ws2::Config,
+    ) -> Result<
+        PendingChatConnection<impl AsyncDuplexStream + Connection + 'static>,
+        chat::ConnectError,
+    > {
         let Self {
-            endpoint_connection,
+            endpoint_connection: _,
             connect_state,
             dns_resolver,
             transport_connector: _,
             resolved_names: _,
             chat_domain_config,
         } = self;
-        let libsignal_net::infra::ws2::Config {
-            local_idle_timeout,
-            remote_idle_ping_timeout,
-            remote_idle_disconnect_timeout: _,
-        } = endpoint_connection.config.ws2_config();
         let connection_resources = ConnectionResources {
             connect_state,
             dns_resolver,
@@ -233,12 +299,9 @@ impl FakeDeps {
                 None,
             ),
             &UserAgent::with_libsignal_version("test"),
-            chat::ws2::Config {
-                local_idle_timeout,
-                remote_idle_timeout: remote_idle_ping_timeout,
-                initial_request_id: 0,
-            },
+            ws_config,
             None,
+            chat::PreconnectPolicy::UseIfAvailable,
             "fake chat",
         )
         .await
@@ -304,3 +367,84 @@ pub async fn connect_websockets_on_incoming<S: AsyncDuplexStream + 'static, T: D
         }))
         .await
 }
+
+/// Like [`connect_websockets_on_incoming`], but selects `subprotocol` in each
+/// upgrade response regardless of what the client offered.
+pub async fn connect_websockets_with_subprotocol_on_incoming<
+    S: AsyncDuplexStream + 'static,
+    T: Display,
+>(
+    incoming_streams: impl Stream<Item = (T, S)> + Send,
+    subprotocol: &'static str,
+) {
+    let filter = warp::any().and(warp::ws()).map(move |ws: warp::ws::Ws| {
+        warp::reply::with_header(
+            ws.on_upgrade(|_ws| {
+                log::info!("serving websocket");
+                std::future::pending()
+            }),
+            "sec-websocket-protocol",
+            subprotocol,
+        )
+    });
+    warp::serve(filter)
+        .run_incoming(incoming_streams.map(|(host, stream)| {
+            log::info!("serving websocket to {host}");
+            Ok::<_, std::io::Error>(stream)
+        }))
+        .await
+}
+
+/// Like [`connect_websockets_on_incoming`], but rejects every websocket
+/// upgrade request with `status` and `headers`.
+pub async fn reject_websockets_on_incoming<S: AsyncDuplexStream + 'static, T: Display>(
+    incoming_streams: impl Stream<Item = (T, S)> + Send,
+    status: u16,
+    headers: &'static [(&'static str, &'static str)],
+) {
+    let status = warp::http::StatusCode::from_u16(status).expect("valid status");
+    let filter = warp::any().map(move || {
+        log::info!("rejecting websocket with {status}");
+        let mut response = warp::http::Response::builder().status(status);
+        for &(name, value) in headers {
+            response = response.header(name, value);
+        }
+        response.body("").expect("valid response")
+    });
+    warp::serve(filter)
+        .run_incoming(incoming_streams.map(|(host, stream)| {
+            log::info!("serving websocket to {host}");
+            Ok::<_, std::io::Error>(stream)
+        }))
+        .await
+}
+
+/// Like [`connect_websockets_on_incoming`], but keeps each websocket open once
+/// it's established.
+///
+/// If `respond` is set, the server reads (and so answers pings on) each
+/// websocket until the client goes away. Otherwise the server never touches
+/// the websocket again, as if everything it sends after the upgrade were being
+/// dropped on the way to the client.
+pub async fn serve_websockets_on_incoming<S: AsyncDuplexStream + 'static, T: Display>(
+    incoming_streams: impl Stream<Item = (T, S)> + Send,
+    respond: bool,
+) {
+    let filter = warp::any().and(warp::ws()).map(move |ws: warp::ws::Ws| {
+        ws.on_upgrade(move |ws| async move {
+            log::info!("serving websocket");
+            if respond {
+                ws.for_each(|_message| std::future::ready(())).await
+            } else {
+                let _ws = ws;
+                std::future::pending().await
+            }
+        })
+    });
+    warp::serve(filter)
+        .run_incoming(incoming_streams.map(|(host, stream)| {
+            log::info!("serving websocket to {host}");
+            Ok::<_, std::io::Error>(stream)
+        }))
+        .await
+}
@@ -0,0 +1,139 @@
+//
+// Copyright 2025 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+//! Comparing recorded [`TransportConnectEvent`]s against "golden" recordings.
+//!
+//! A golden recording is a JSON array of [`RecordedEvent`]s. When a change in
+//! connection behavior is intended, the recording can be regenerated by
+//! running the test and copying the "actual" JSON from the failure message.
+
+use std::sync::Arc;
+
+use itertools::Itertools as _;
+use libsignal_net_infra::host::Host;
+use serde::{Deserialize, Serialize};
+use tokio::time::Duration;
+
+use super::{TransportConnectEvent, TransportConnectEventStage};
+
+/// A single entry in a golden recording.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct RecordedEvent {
+    event: TransportConnectEvent,
+    stage: TransportConnectEventStage,
+    /// Time since the start of the connection attempt, in milliseconds.
+    at_millis: u64,
+}
+
+impl RecordedEvent {
+    fn new(
+        ((event, stage), at): &(
+            (TransportConnectEvent, TransportConnectEventStage),
+            Duration,
+        ),
+    ) -> Self {
+        Self {
+            event: event.clone(),
+            stage: *stage,
+            at_millis: at
+                .as_millis()
+                .try_into()
+                .expect("test runs for a sane amount of time"),
+        }
+    }
+}
+
+/// Asserts that `actual` matches the golden recording in `golden_json`.
+///
+/// The events must match exactly and in order; their times may differ from the
+/// recorded ones by at most `tolerance`.
+pub fn assert_events_match_recording(
+    actual: &[(
+        (TransportConnectEvent, TransportConnectEventStage),
+        Duration,
+    )],
+    golden_json: &str,
+    tolerance: Duration,
+) {
+    let expected: Vec<RecordedEvent> =
+        serde_json::from_str(golden_json).expect("golden recording is valid");
+    let actual = actual.iter().map(RecordedEvent::new).collect_vec();
+
+    let mismatch = if actual.len() != expected.len() {
+        Some(format!(
+            "expected {} events but got {}",
+            expected.len(),
+            actual.len()
+        ))
+    } else {
+        actual
+            .iter()
+            .zip(&expected)
+            .enumerate()
+            .find_map(|(i, (actual, expected))| {
+                if (&actual.event, actual.stage) != (&expected.event, expected.stage) {
+                    return Some(format!(
+                        "event {i} differs: expected {:?} {:?}, got {:?} {:?}",
+                        expected.stage, expected.event, actual.stage, actual.event
+                    ));
+                }
+                let difference = actual.at_millis.abs_diff(expected.at_millis);
+                (Duration::from_millis(difference) > tolerance).then(|| {
+                    format!(
+                        "event {i} happened at {}ms, expected {}ms (tolerance {tolerance:?})",
+                        actual.at_millis, expected.at_millis
+                    )
+                })
+            })
+    };
+
+    if let Some(mismatch) = mismatch {
+        panic!(
+            "{mismatch}\nactual recording:\n{}",
+            serde_json::to_string_pretty(&actual).expect("can serialize")
+        );
+    }
+}
+
+/// Serializes a [`Host`] as its string representation.
+pub(super) mod serde_host {
+    use super::*;
+
+    pub fn serialize<S: serde::Serializer>(
+        host: &Host<Arc<str>>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(host)
+    }
+
+    pub fn deserialize<'de, D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Host<Arc<str>>, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        Ok(Host::parse_as_ip_or_domain(&s))
+    }
+}
+
+/// Like [`serde_host`], but with `null` for `None`.
+pub(super) mod serde_optional_host {
+    use super::*;
+
+    pub fn serialize<S: serde::Serializer>(
+        host: &Option<Host<Arc<str>>>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match host {
+            Some(host) => serializer.collect_str(host),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Host<Arc<str>>>, D::Error> {
+        let s = Option::<String>::deserialize(deserializer)?;
+        Ok(s.as_deref().map(Host::parse_as_ip_or_domain))
+    }
+}
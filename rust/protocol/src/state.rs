@@ -11,7 +11,7 @@ mod signed_prekey;
 
 pub use bundle::{PreKeyBundle, PreKeyBundleContent};
 pub use kyber_prekey::{KyberPreKeyId, KyberPreKeyRecord};
-pub use prekey::{PreKeyId, PreKeyRecord};
+pub use prekey::{PreKeyId, PreKeyRecord, PreKeyRecordStructure};
 pub use session::SessionRecord;
 pub(crate) use session::{InvalidSessionError, SessionState};
 pub use signed_prekey::{GenericSignedPreKey, SignedPreKeyId, SignedPreKeyRecord};
@@ -7,7 +7,7 @@ use std::fmt;
 
 use prost::Message;
 
-use crate::proto::storage::PreKeyRecordStructure;
+pub use crate::proto::storage::PreKeyRecordStructure;
 use crate::{KeyPair, PrivateKey, PublicKey, Result, SignalProtocolError};
 
 /// A unique identifier selecting among this client's known pre-keys.
@@ -40,6 +40,21 @@ impl PreKeyRecord {
         }
     }
 
+    /// Creates a record from already-serialized public and private keys, such
+    /// as ones migrated from another store.
+    ///
+    /// Fails if either key isn't a well-formed curve key, or if the public key
+    /// isn't the one that goes with the private key.
+    pub fn from_key_bytes(id: PreKeyId, public_key: &[u8], private_key: &[u8]) -> Result<Self> {
+        let key_pair = KeyPair::from_public_and_private(public_key, private_key)?;
+        if key_pair.private_key.public_key()? != key_pair.public_key {
+            return Err(SignalProtocolError::InvalidArgument(
+                "public key does not match private key".to_string(),
+            ));
+        }
+        Ok(Self::new(id, &key_pair))
+    }
+
     pub fn deserialize(data: &[u8]) -> Result<Self> {
         Ok(Self {
             pre_key: PreKeyRecordStructure::decode(data)
@@ -69,4 +84,93 @@ impl PreKeyRecord {
     pub fn serialize(&self) -> Result<Vec<u8>> {
         Ok(self.pre_key.encode_to_vec())
     }
+
+    /// Provides the underlying protobuf structure, for callers that store it
+    /// directly rather than via [`Self::serialize`].
+    ///
+    /// **Hazard:** the structure includes the serialized private key. Treat it
+    /// as carefully as the private key itself. The key bytes are also not
+    /// validated; use [`Self::key_pair`] to get checked keys.
+    pub fn as_structure(&self) -> &PreKeyRecordStructure {
+        &self.pre_key
+    }
+
+    /// Unwraps the underlying protobuf structure.
+    ///
+    /// The same hazard applies as for [`Self::as_structure`].
+    pub fn into_structure(self) -> PreKeyRecordStructure {
+        self.pre_key
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::OsRng;
+
+    use super::*;
+
+    #[test]
+    fn test_from_key_bytes() -> Result<()> {
+        let key_pair = KeyPair::generate(&mut OsRng);
+        let record = PreKeyRecord::from_key_bytes(
+            PreKeyId::from(7),
+            &key_pair.public_key.serialize(),
+            &key_pair.private_key.serialize(),
+        )?;
+
+        assert_eq!(record.id()?, PreKeyId::from(7));
+        let round_tripped = record.key_pair()?;
+        assert_eq!(round_tripped.public_key, key_pair.public_key);
+        assert_eq!(
+            round_tripped.private_key.serialize(),
+            key_pair.private_key.serialize()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_as_structure() {
+        let key_pair = KeyPair::generate(&mut OsRng);
+        let record = PreKeyRecord::new(PreKeyId::from(42), &key_pair);
+
+        let structure = record.as_structure();
+        assert_eq!(structure.id, 42);
+        assert_eq!(*structure.public_key, *key_pair.public_key.serialize());
+        assert_eq!(*structure.private_key, *key_pair.private_key.serialize());
+
+        assert_eq!(record.clone().into_structure(), *record.as_structure());
+    }
+
+    #[test]
+    fn test_from_key_bytes_rejects_malformed_keys() {
+        let key_pair = KeyPair::generate(&mut OsRng);
+        let public_key = key_pair.public_key.serialize();
+        let private_key = key_pair.private_key.serialize();
+
+        assert!(PreKeyRecord::from_key_bytes(
+            PreKeyId::from(1),
+            &public_key[..public_key.len() - 1],
+            &private_key
+        )
+        .is_err());
+        assert!(
+            PreKeyRecord::from_key_bytes(PreKeyId::from(1), &public_key, &private_key[1..])
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_from_key_bytes_rejects_mismatched_keys() {
+        let key_pair = KeyPair::generate(&mut OsRng);
+        let other_key_pair = KeyPair::generate(&mut OsRng);
+
+        assert!(matches!(
+            PreKeyRecord::from_key_bytes(
+                PreKeyId::from(1),
+                &other_key_pair.public_key.serialize(),
+                &key_pair.private_key.serialize(),
+            ),
+            Err(SignalProtocolError::InvalidArgument(_))
+        ));
+    }
 }
@@ -65,6 +65,7 @@ public enum SignalError: Error {
     case deviceDeregistered(String)
     case connectionInvalidated(String)
     case connectedElsewhere(String)
+    case serverMaintenance(retryAfter: TimeInterval?, message: String)
 
     case unknown(UInt32, String)
 }
@@ -226,6 +227,12 @@ internal func checkError(_ error: SignalFfiErrorRef?) throws {
         throw SignalError.connectionInvalidated(errStr)
     case SignalErrorCodeConnectedElsewhere:
         throw SignalError.connectedElsewhere(errStr)
+    case SignalErrorCodeServerMaintenance:
+        // The server doesn't always say when to come back.
+        let retryAfterSeconds = try? invokeFnReturningInteger {
+            signal_error_get_retry_after_seconds(error, $0)
+        }
+        throw SignalError.serverMaintenance(retryAfter: retryAfterSeconds.map { TimeInterval($0) }, message: errStr)
     case SignalErrorCodeBackupValidation:
         let unknownFields = try invokeFnReturningStringArray {
             signal_error_get_unknown_fields(error, $0)
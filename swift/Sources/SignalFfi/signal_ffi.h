@@ -212,6 +212,7 @@ typedef enum {
   SignalErrorCodeDeviceDeregistered = 171,
   SignalErrorCodeConnectionInvalidated = 172,
   SignalErrorCodeConnectedElsewhere = 173,
+  SignalErrorCodeServerMaintenance = 174,
   SignalErrorCodeBackupValidation = 180,
 } SignalErrorCode;
 
@@ -91,6 +91,14 @@ final class ChatServiceTests: TestCaseBase {
         } catch SignalError.rateLimitedError(retryAfter: 42, let message) {
             XCTAssertEqual(message, "Rate limited; try again after 42s")
         }
+        do {
+            try failWithError("ServerMaintenance")
+        } catch SignalError.serverMaintenance(retryAfter: nil, _) {}
+        do {
+            try failWithError("ServerMaintenanceRetryAfter42Seconds")
+        } catch SignalError.serverMaintenance(retryAfter: 42, let message) {
+            XCTAssertEqual(message, "Server is down for maintenance; try again after 42s")
+        }
     }
 
     func testConvertSendError() throws {
@@ -121,6 +129,15 @@ final class ChatServiceTests: TestCaseBase {
         do {
             try failWithError("RequestHasInvalidHeader")
         } catch SignalError.internalError(_) {}
+
+        do {
+            try failWithError("ServerMaintenance")
+        } catch SignalError.serverMaintenance(retryAfter: nil, _) {}
+        do {
+            try failWithError("ServerMaintenanceRetryAfter42Seconds")
+        } catch SignalError.serverMaintenance(retryAfter: 42, let message) {
+            XCTAssertEqual(message, "Server is down for maintenance; try again after 42s")
+        }
     }
 
     func testConstructRequest() throws {